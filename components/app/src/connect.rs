@@ -17,6 +17,9 @@ use timer::create_timer;
 
 use node::connect::{node_connect, NodeConnection};
 
+/// Amount of ticks between two saved snapshots of the node report
+const REPORT_SNAPSHOT_INTERVAL_TICKS: usize = 0x100;
+
 #[derive(Debug)]
 pub struct ConnectError;
 
@@ -32,8 +35,10 @@ where
 {
     let resolve_thread_pool = ThreadPool::new().map_err(|_| ConnectError)?;
 
-    // A tcp connector, Used to connect to remote servers:
-    let net_connector = NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, spawner.clone());
+    // A tcp connector, Used to connect to remote servers.
+    // Apps usually connect to a local node, so we don't use a proxy here:
+    let net_connector =
+        NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, spawner.clone(), None);
 
     // Get a timer client:
     let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
@@ -48,6 +53,8 @@ where
         node_net_address,
         timer_client,
         app_identity_client,
+        None,
+        REPORT_SNAPSHOT_INTERVAL_TICKS,
         rng,
         spawner
     ))
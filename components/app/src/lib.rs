@@ -31,7 +31,12 @@ pub use proto::funder::signature_buff::verify_receipt;
 pub use proto::index_server::messages::NamedIndexServerAddress;
 pub use proto::report::signature_buff::verify_move_token_hashed_report;
 
-pub use node::connect::{AppBuyer, AppConfig, AppReport, AppRoutes, AppSeller, NodeConnection};
+pub use node::connect::{
+    AppBuyer, AppConfig, AppReport, AppRoutes, AppSeller, BuyerNodeConnection, CancelPaymentError,
+    ConfigNodeConnection, NodeConnection, NodeConnectionBuilder, PaymentProgressEvent,
+    PaymentProgressStream, ReconnectError, ReconnectingNodeConnection, RoutesNodeConnection,
+    SellerNodeConnection,
+};
 
 pub use self::connect::{connect, ConnectError};
 pub use self::identity::{identity_from_file, IdentityFromFileError};
@@ -39,13 +44,14 @@ pub use self::identity::{identity_from_file, IdentityFromFileError};
 // TODO: Possibly reduce what we export from report in the future?
 pub mod report {
     pub use proto::report::messages::{
-        AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, DirectionReport,
-        FriendLivenessReport, FriendReport, FriendReportMutation, FriendStatusReport, FunderReport,
-        FunderReportMutateError, FunderReportMutation, FunderReportMutations, McBalanceReport,
-        McRequestsStatusReport, MoveTokenHashedReport, RequestsStatusReport, ResetTermsReport,
-        SentLocalRelaysReport, TcReport,
+        AddFriendReport, ChannelInconsistentReport, ChannelStatsReport, ChannelStatusReport,
+        DirectionReport, FriendLivenessReport, FriendReport, FriendReportMutation,
+        FriendStatusReport, FunderReport, FunderReportMutateError, FunderReportMutation,
+        FunderReportMutations, McBalanceReport, McRequestsStatusReport, MoveTokenHashedReport,
+        RequestsStatusReport, ResetTermsReport, SentLocalRelaysReport, TcReport,
     };
 
+    pub use node::connect::ReportDiff;
     pub use proto::app_server::messages::{NodeReport, NodeReportMutation};
     pub use proto::index_client::messages::{
         AddIndexServer, IndexClientReport, IndexClientReportMutation,
@@ -23,4 +23,4 @@ mod server;
 #[cfg(test)]
 mod tests;
 
-pub use self::server::{app_server_loop, AppServerError, IncomingAppConnection};
+pub use self::server::{app_server_loop, AppServerError, AppServerStats, IncomingAppConnection};
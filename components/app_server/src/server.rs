@@ -1,33 +1,70 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::marker::Unpin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+use futures::{future, select, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
 
 use common::conn::ConnPair;
-use common::select_streams::{select_streams, BoxStream};
+use common::int_convert::usize_to_u64;
+use common::priority_stream::priority_stream;
+use common::select_streams::BoxStream;
 // use common::mutable_state::MutableState;
+use crypto::crypto_rand::CryptoRandom;
+use crypto::identity::PublicKey;
 use crypto::payment_id::PaymentId;
 use crypto::uid::Uid;
 
 use proto::funder::messages::{
     FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl, RemoveFriend,
-    RequestsStatus, SetFriendStatus, SetRequestsStatus,
+    RequestsStatus, SetFriendStatus, SetRequestsStatus, TransactionResult,
 };
 use proto::report::convert::funder_report_mutation_to_index_mutation;
+use proto::report::messages::FunderReportMutation;
+
+use proto::consts::TICK_MS;
 
 use proto::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport, NodeReportMutation,
-    ReportMutations,
+    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, FriendPermissions, NodeReport,
+    NodeReportMutation, ReportMutations,
 };
 use proto::index_client::messages::{
     AppServerToIndexClient, IndexClientRequest, IndexClientToAppServer,
 };
 
+use timer::TimerTick;
+
+/// Maximum amount of undeliverable transaction results we keep.
+const MAX_DEAD_LETTER_QUEUE_LEN: usize = 1000;
+
+/// Amount of handled events between two consecutive logs of the app server stats.
+const STATS_LOG_INTERVAL: usize = 1000;
+
+/// Amount of priority tiers for requests coming from apps.
+const NUM_APP_REQUEST_PRIORITIES: usize = 3;
+
+/// Length of the queue of every app request priority tier (Shared by all apps).
+/// When a queue is full, an app sending a request of this priority is not read from until the
+/// queue has room again. Requests are read from an app in order, so this also delays its
+/// requests of higher priorities.
+const APP_REQUEST_QUEUE_LEN: usize = 0x100;
+
+/// Length of each of the two queues of messages waiting to be sent to an app.
+/// An app that does not read its messages fast enough to keep a queue from filling up is
+/// disconnected.
+const APP_SEND_QUEUE_LEN: usize = 0x400;
+
+type FromAppSender<B> = mpsc::Sender<(u128, Option<AppToAppServer<B>>)>;
+
+/// An app connection: The public key of the app, its permissions, and the session token the
+/// app received on a previous connection (If the app is reconnecting).
 pub type IncomingAppConnection<B> = (
+    PublicKey,
     AppPermissions,
+    Option<Uid>,
     ConnPair<AppServerToApp<B>, AppToAppServer<B>>,
 );
 
@@ -50,37 +87,313 @@ pub enum AppServerEvent<B: Clone> {
     FromIndexClient(IndexClientToAppServer<B>),
     IndexClientClosed,
     FromApp((u128, Option<AppToAppServer<B>>)), // None means that app was closed
+    TimerTick,
+}
+
+/// Monitoring counters of a running app server loop.
+/// Updated by the loop after every handled event, and may be read from anywhere.
+#[derive(Debug, Clone, Default)]
+pub struct AppServerStats {
+    app_count: Arc<AtomicUsize>,
+    pending_request_count: Arc<AtomicUsize>,
+}
+
+impl AppServerStats {
+    pub fn new() -> Self {
+        AppServerStats::default()
+    }
+
+    /// Amount of currently connected apps
+    pub fn app_count(&self) -> usize {
+        self.app_count.load(Ordering::SeqCst)
+    }
+
+    /// Amount of app requests still waiting for a response
+    pub fn pending_request_count(&self) -> usize {
+        self.pending_request_count.load(Ordering::SeqCst)
+    }
+
+    fn update(&self, app_count: usize, pending_request_count: usize) {
+        self.app_count.store(app_count, Ordering::SeqCst);
+        self.pending_request_count
+            .store(pending_request_count, Ordering::SeqCst);
+    }
+}
+
+/// Amount of tokens a single request costs.
+/// A request is worth many tokens, so that a fraction of a request may be refilled on every tick.
+const TOKENS_PER_REQUEST: u64 = 1000;
+
+/// A token bucket limiting the rate of requests sent by an app.
+/// The bucket can hold up to one second worth of requests.
+struct RequestTokenBucket {
+    capacity: u64,
+    refill_per_tick: u64,
+    tokens: u64,
+}
+
+/// Amount of ticks in one second. Discarded requests are logged at most once per this amount of
+/// ticks.
+fn ticks_per_second() -> usize {
+    (1000 / TICK_MS).max(1)
+}
+
+impl RequestTokenBucket {
+    fn new(max_requests_per_second: u32) -> Self {
+        let capacity = u64::from(max_requests_per_second).saturating_mul(TOKENS_PER_REQUEST);
+        // TOKENS_PER_REQUEST tokens are refilled every second for every allowed request:
+        let refill_per_tick = u64::from(max_requests_per_second)
+            .saturating_mul(usize_to_u64(TICK_MS).unwrap())
+            .saturating_mul(TOKENS_PER_REQUEST)
+            / 1000;
+        RequestTokenBucket {
+            capacity,
+            refill_per_tick,
+            tokens: capacity,
+        }
+    }
+
+    /// Refill the bucket after `num_ticks` time ticks have passed.
+    fn refill(&mut self, num_ticks: usize) {
+        let refill = self
+            .refill_per_tick
+            .saturating_mul(usize_to_u64(num_ticks).unwrap());
+        self.tokens = self.tokens.saturating_add(refill).min(self.capacity);
+    }
+
+    /// Take the tokens of one request from the bucket.
+    /// Returns false if there are not enough tokens.
+    fn try_take(&mut self) -> bool {
+        match self.tokens.checked_sub(TOKENS_PER_REQUEST) {
+            Some(new_tokens) => {
+                self.tokens = new_tokens;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Limits the rate of requests read from an app connection.
+/// Requests are checked before they are queued, so that requests over the limit never take
+/// space in the app request queues.
+struct RequestRateLimiter {
+    request_bucket: RequestTokenBucket,
+    /// Counts the time ticks received by the app server.
+    ticks: Arc<AtomicUsize>,
+    /// Value of `ticks` during the last refill of the bucket
+    last_refill_ticks: usize,
+    /// Amount of discarded requests that were not logged yet
+    num_discarded: usize,
+    /// Value of `ticks` during the last log of discarded requests
+    opt_last_log_ticks: Option<usize>,
+}
+
+impl RequestRateLimiter {
+    fn new(max_requests_per_second: u32, ticks: Arc<AtomicUsize>) -> Self {
+        let last_refill_ticks = ticks.load(Ordering::SeqCst);
+        RequestRateLimiter {
+            request_bucket: RequestTokenBucket::new(max_requests_per_second),
+            ticks,
+            last_refill_ticks,
+            num_discarded: 0,
+            opt_last_log_ticks: None,
+        }
+    }
+
+    /// Check if another request may be sent by the app.
+    /// Discarded requests are logged at most once a second, to avoid flooding the log.
+    fn check_request(&mut self, app_public_key: &PublicKey) -> bool {
+        let cur_ticks = self.ticks.load(Ordering::SeqCst);
+        self.request_bucket
+            .refill(cur_ticks.wrapping_sub(self.last_refill_ticks));
+        self.last_refill_ticks = cur_ticks;
+
+        if self.request_bucket.try_take() {
+            return true;
+        }
+
+        self.num_discarded = self.num_discarded.saturating_add(1);
+        let should_log = match self.opt_last_log_ticks {
+            Some(last_log_ticks) => cur_ticks.wrapping_sub(last_log_ticks) >= ticks_per_second(),
+            None => true,
+        };
+        if should_log {
+            warn!(
+                "App {} exceeded its requests rate. Discarded {} requests",
+                app_public_key.to_hex(),
+                self.num_discarded
+            );
+            self.num_discarded = 0;
+            self.opt_last_log_ticks = Some(cur_ticks);
+        }
+        false
+    }
+}
+
+/// The sending side of the queues of an app.
+struct AppSenders<B: Clone> {
+    /// Messages that should be delivered to the app as soon as possible
+    /// (Responses to requests issued by the app).
+    high_priority_sender: mpsc::Sender<AppServerToApp<B>>,
+    /// Messages that may be delayed in favour of high priority messages (Report mutations).
+    normal_priority_sender: mpsc::Sender<AppServerToApp<B>>,
+    /// Dropping this sender closes the connection to the app.
+    _close_sender: oneshot::Sender<()>,
 }
 
 pub struct App<B: Clone> {
     permissions: AppPermissions,
-    opt_sender: Option<mpsc::Sender<AppServerToApp<B>>>,
+    /// None if the app was disconnected because one of its queues was full.
+    opt_senders: Option<AppSenders<B>>,
+}
+
+/// Is this message a response to a request issued by the app?
+/// Such messages preempt report mutations queued for the app.
+fn is_high_priority<B>(message: &AppServerToApp<B>) -> bool
+where
+    B: Clone,
+{
+    match message {
+        AppServerToApp::TransactionResult(_)
+        | AppServerToApp::ResponseClosePayment(_)
+        | AppServerToApp::ResponsePaymentTimeline(_)
+        | AppServerToApp::ResponseRoutes(_)
+        | AppServerToApp::ResponseDeadLetterQueue(_) => true,
+        // The initial report must always arrive before any report mutation:
+        AppServerToApp::Report(_) => true,
+        AppServerToApp::ReportMutations(_) => false,
+    }
+}
+
+/// Priority of a request from an app. Lower value means higher priority.
+/// Config mutations are handled first, then payment operations and finally route requests and
+/// seller operations.
+fn app_request_priority<B>(app_request: &AppRequest<B>) -> usize {
+    match app_request {
+        AppRequest::AddRelay(_)
+        | AppRequest::RemoveRelay(_)
+        | AppRequest::AddFriend(_)
+        | AppRequest::SetFriendRelays(_)
+        | AppRequest::SetFriendName(_)
+        | AppRequest::SetFriendNote(_)
+        | AppRequest::RemoveFriend(_)
+        | AppRequest::EnableFriend(_)
+        | AppRequest::DisableFriend(_)
+        | AppRequest::OpenFriend(_)
+        | AppRequest::CloseFriend(_)
+        | AppRequest::SetFriendRemoteMaxDebt(_)
+        | AppRequest::SetFriendRate(_)
+        | AppRequest::ResetFriendChannel(_)
+        | AppRequest::FreezeFriend(_)
+        | AppRequest::UnfreezeFriend(_)
+        | AppRequest::AddIndexServer(_)
+        | AppRequest::RemoveIndexServer(_)
+        | AppRequest::RotateIndexServer(_)
+        | AppRequest::GetDeadLetterQueue => 0,
+        AppRequest::CreatePayment(_)
+        | AppRequest::CreateTransaction(_)
+        | AppRequest::CreateMultiRoutePayment(_)
+        | AppRequest::RequestClosePayment(_)
+        | AppRequest::AckClosePayment(_)
+        | AppRequest::GetPaymentTimeline(_) => 1,
+        AppRequest::AddInvoice(_)
+        | AppRequest::CancelInvoice(_)
+        | AppRequest::CommitInvoice(_)
+        | AppRequest::RequestRoutes(_)
+        | AppRequest::ReportRouteOutcome(_) => 2,
+    }
+}
+
+/// Forward messages queued for an app to the app's connection.
+/// High priority messages are always sent before pending normal priority messages.
+/// Returns (closing the connection to the app) once the app is removed or disconnected.
+async fn app_sender_loop<B>(
+    mut high_priority_receiver: mpsc::Receiver<AppServerToApp<B>>,
+    mut normal_priority_receiver: mpsc::Receiver<AppServerToApp<B>>,
+    mut close_receiver: oneshot::Receiver<()>,
+    mut sender: mpsc::Sender<AppServerToApp<B>>,
+) where
+    B: Clone,
+{
+    loop {
+        let opt_message = if let Ok(opt_message) = high_priority_receiver.try_next() {
+            opt_message
+        } else if let Ok(opt_message) = normal_priority_receiver.try_next() {
+            opt_message
+        } else {
+            // Both queues are empty. Wait for the next message:
+            select! {
+                opt_message = high_priority_receiver.next().fuse() => opt_message,
+                opt_message = normal_priority_receiver.next().fuse() => opt_message,
+                _ = (&mut close_receiver).fuse() => None,
+            }
+        };
+
+        let message = match opt_message {
+            Some(message) => message,
+            None => return, // The app was removed
+        };
+
+        // Don't wait on a slow app after it was disconnected:
+        let send_res = select! {
+            send_res = sender.send(message).fuse() => send_res,
+            _ = (&mut close_receiver).fuse() => return,
+        };
+        if send_res.is_err() {
+            return; // The app connection was closed
+        }
+    }
 }
 
 impl<B> App<B>
 where
     B: Clone,
 {
-    pub fn new(permissions: AppPermissions, sender: mpsc::Sender<AppServerToApp<B>>) -> Self {
+    pub fn new(
+        permissions: AppPermissions,
+        high_priority_sender: mpsc::Sender<AppServerToApp<B>>,
+        normal_priority_sender: mpsc::Sender<AppServerToApp<B>>,
+        close_sender: oneshot::Sender<()>,
+    ) -> Self {
         App {
             permissions,
-            opt_sender: Some(sender),
+            opt_senders: Some(AppSenders {
+                high_priority_sender,
+                normal_priority_sender,
+                _close_sender: close_sender,
+            }),
         }
     }
 
-    pub async fn send(&mut self, message: AppServerToApp<B>) {
-        if let Some(mut sender) = self.opt_sender.take() {
-            if let Ok(()) = await!(sender.send(message)) {
-                self.opt_sender = Some(sender);
+    /// Queue a message to be sent to the app.
+    /// If the queue is full the app is disconnected: Silently dropping a report mutation would
+    /// leave the app with a wrong view of the node report.
+    pub fn send(&mut self, message: AppServerToApp<B>) {
+        let senders = match &mut self.opt_senders {
+            Some(senders) => senders,
+            None => return, // The app was already disconnected
+        };
+        let sender = if is_high_priority(&message) {
+            &mut senders.high_priority_sender
+        } else {
+            &mut senders.normal_priority_sender
+        };
+        if let Err(e) = sender.try_send(message) {
+            if e.is_full() {
+                warn!("App::send(): Queue is full. Disconnecting app.");
+                self.opt_senders = None;
             }
+            // Otherwise the app connection was closed, and the message is discarded.
         }
     }
 }
 
-pub struct AppServer<B: Clone, TF, TIC, S> {
+pub struct AppServer<B: Clone, TF, TIC, R, S> {
     to_funder: TF,
     to_index_client: TIC,
-    from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
+    /// A sender for every app request priority (Highest priority first):
+    from_app_senders: Vec<FromAppSender<B>>,
     node_report: NodeReport<B>,
     incoming_connections_closed: bool,
     /// A long cyclic incrementing counter,
@@ -88,14 +401,116 @@ pub struct AppServer<B: Clone, TF, TIC, S> {
     /// Required because an app (with one public key) might have multiple connections.
     app_counter: u128,
     apps: HashMap<u128, App<B>>,
+    /// Counts the time ticks received. Used to refill the requests rate limit of apps.
+    ticks: Arc<AtomicUsize>,
     /// Data structures to track ongoing requests.
     /// This allows us to multiplex requests/responses to multiple apps:
     route_requests: HashMap<Uid, u128>,
     close_payment_requests: HashMap<PaymentId, u128>,
+    payment_timeline_requests: HashMap<PaymentId, u128>,
     transactions: HashMap<Uid, u128>,
+    /// Maps the session token of every app to the public key of the app and its app id.
+    /// A session of a closed app is kept as long as the app has pending requests,
+    /// so that the app could reconnect and receive the responses:
+    sessions: HashMap<Uid, (PublicKey, u128)>,
+    /// Transaction results whose originating app was closed before the result arrived.
+    /// Oldest results are discarded first.
+    dead_letter_queue: VecDeque<(Uid, TransactionResult)>,
+    rng: R,
     spawner: S,
 }
 
+/// Check a permission of an app with respect to a specific friend.
+/// Falls back to the `config` permission if the app has no permissions entry for the friend.
+fn check_friend_permission<F>(
+    app_permissions: &AppPermissions,
+    friend_public_key: &PublicKey,
+    get_permission: F,
+) -> bool
+where
+    F: Fn(&FriendPermissions) -> bool,
+{
+    match app_permissions
+        .friend_permissions
+        .as_ref()
+        .and_then(|friend_permissions| friend_permissions.get(friend_public_key))
+    {
+        Some(friend_permissions) => get_permission(friend_permissions),
+        None => app_permissions.config,
+    }
+}
+
+/// Check if an app may read the state of a specific friend.
+/// Unlike the other friend permissions, there is no fallback to the `config` permission: Every
+/// app may read the state of friends it has no permissions entry for.
+fn can_read_friend(app_permissions: &AppPermissions, friend_public_key: &PublicKey) -> bool {
+    app_permissions
+        .friend_permissions
+        .as_ref()
+        .and_then(|friend_permissions| friend_permissions.get(friend_public_key))
+        .map(|friend_permissions| friend_permissions.read)
+        .unwrap_or(true)
+}
+
+/// Remove from a node report all the friends the app may not read.
+fn filter_node_report<B>(
+    app_permissions: &AppPermissions,
+    node_report: &NodeReport<B>,
+) -> NodeReport<B>
+where
+    B: Clone,
+{
+    let mut node_report = node_report.clone();
+    node_report.funder_report.friends = node_report
+        .funder_report
+        .friends
+        .iter()
+        .filter(|(friend_public_key, _)| can_read_friend(app_permissions, friend_public_key))
+        .map(|(friend_public_key, friend_report)| {
+            (friend_public_key.clone(), friend_report.clone())
+        })
+        .collect();
+    node_report
+}
+
+/// Remove from report mutations all the mutations of friends the app may not read.
+fn filter_report_mutations<B>(
+    app_permissions: &AppPermissions,
+    report_mutations: &ReportMutations<B>,
+) -> ReportMutations<B>
+where
+    B: Clone,
+{
+    let mutations = report_mutations
+        .mutations
+        .iter()
+        .filter(|mutation| {
+            let opt_friend_public_key = match mutation {
+                NodeReportMutation::Funder(FunderReportMutation::AddFriend(add_friend_report)) => {
+                    Some(&add_friend_report.friend_public_key)
+                }
+                NodeReportMutation::Funder(FunderReportMutation::RemoveFriend(
+                    friend_public_key,
+                ))
+                | NodeReportMutation::Funder(FunderReportMutation::FriendReportMutation((
+                    friend_public_key,
+                    _,
+                ))) => Some(friend_public_key),
+                _ => None,
+            };
+            opt_friend_public_key
+                .map(|friend_public_key| can_read_friend(app_permissions, friend_public_key))
+                .unwrap_or(true)
+        })
+        .cloned()
+        .collect();
+
+    ReportMutations {
+        opt_app_request_id: report_mutations.opt_app_request_id.clone(),
+        mutations,
+    }
+}
+
 /// Check if we should process an app_message from an app with certain permissions
 fn check_permissions<B>(app_permissions: &AppPermissions, app_request: &AppRequest<B>) -> bool {
     match app_request {
@@ -103,8 +518,10 @@ fn check_permissions<B>(app_permissions: &AppPermissions, app_request: &AppReque
         AppRequest::RemoveRelay(_) => app_permissions.config,
         AppRequest::CreatePayment(_) => app_permissions.buyer,
         AppRequest::CreateTransaction(_) => app_permissions.buyer,
+        AppRequest::CreateMultiRoutePayment(_) => app_permissions.buyer,
         AppRequest::RequestClosePayment(_) => app_permissions.buyer,
         AppRequest::AckClosePayment(_) => app_permissions.buyer,
+        AppRequest::GetPaymentTimeline(_) => app_permissions.buyer,
 
         AppRequest::AddInvoice(_) => app_permissions.seller,
         AppRequest::CancelInvoice(_) => app_permissions.seller,
@@ -113,75 +530,215 @@ fn check_permissions<B>(app_permissions: &AppPermissions, app_request: &AppReque
         AppRequest::AddFriend(_) => app_permissions.config,
         AppRequest::SetFriendRelays(_) => app_permissions.config,
         AppRequest::SetFriendName(_) => app_permissions.config,
+        AppRequest::SetFriendNote(_) => app_permissions.config,
         AppRequest::RemoveFriend(_) => app_permissions.config,
         AppRequest::EnableFriend(_) => app_permissions.config,
         AppRequest::DisableFriend(_) => app_permissions.config,
-        AppRequest::OpenFriend(_) => app_permissions.config,
-        AppRequest::CloseFriend(_) => app_permissions.config,
-        AppRequest::SetFriendRemoteMaxDebt(_) => app_permissions.config,
+        AppRequest::OpenFriend(friend_public_key) => {
+            check_friend_permission(app_permissions, friend_public_key, |fp| fp.open_close)
+        }
+        AppRequest::CloseFriend(friend_public_key) => {
+            check_friend_permission(app_permissions, friend_public_key, |fp| fp.open_close)
+        }
+        AppRequest::SetFriendRemoteMaxDebt(set_friend_remote_max_debt) => check_friend_permission(
+            app_permissions,
+            &set_friend_remote_max_debt.friend_public_key,
+            |fp| fp.set_max_debt,
+        ),
         AppRequest::SetFriendRate(_) => app_permissions.config,
         AppRequest::ResetFriendChannel(_) => app_permissions.config,
+        AppRequest::FreezeFriend(_) => app_permissions.config,
+        AppRequest::UnfreezeFriend(_) => app_permissions.config,
         AppRequest::RequestRoutes(_) => app_permissions.routes,
+        AppRequest::ReportRouteOutcome(_) => app_permissions.routes,
         AppRequest::AddIndexServer(_) => app_permissions.config,
         AppRequest::RemoveIndexServer(_) => app_permissions.config,
+        AppRequest::RotateIndexServer(_) => app_permissions.config,
+        AppRequest::GetDeadLetterQueue => app_permissions.config,
     }
 }
 
-impl<B, TF, TIC, S> AppServer<B, TF, TIC, S>
+impl<B, TF, TIC, R, S> AppServer<B, TF, TIC, R, S>
 where
     B: Clone + PartialEq + Eq + Debug + Send + Sync + 'static,
     TF: Sink<FunderIncomingControl<B>> + Unpin + Sync + Send,
     TIC: Sink<AppServerToIndexClient<B>> + Unpin,
+    R: CryptoRandom,
     S: Spawn,
 {
     pub fn new(
         to_funder: TF,
         to_index_client: TIC,
-        from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
+        from_app_senders: Vec<FromAppSender<B>>,
         node_report: NodeReport<B>,
+        rng: R,
         spawner: S,
     ) -> Self {
         AppServer {
             to_funder,
             to_index_client,
-            from_app_sender,
+            from_app_senders,
             node_report,
             incoming_connections_closed: false,
             app_counter: 0,
             apps: HashMap::new(),
+            ticks: Arc::new(AtomicUsize::new(0)),
             route_requests: HashMap::new(),
             close_payment_requests: HashMap::new(),
+            payment_timeline_requests: HashMap::new(),
             transactions: HashMap::new(),
+            sessions: HashMap::new(),
+            dead_letter_queue: VecDeque::new(),
+            rng,
             spawner,
         }
     }
 
+    /// Amount of currently connected apps
+    pub fn get_app_count(&self) -> usize {
+        self.apps.len()
+    }
+
+    /// Amount of app requests we still wait to get a response for
+    pub fn get_pending_request_count(&self) -> usize {
+        self.route_requests.len()
+            + self.close_payment_requests.len()
+            + self.payment_timeline_requests.len()
+            + self.transactions.len()
+    }
+
+    /// Does the app `app_id` still wait for responses to any of its requests?
+    fn has_pending_requests(&self, app_id: u128) -> bool {
+        self.route_requests
+            .values()
+            .chain(self.close_payment_requests.values())
+            .chain(self.payment_timeline_requests.values())
+            .chain(self.transactions.values())
+            .any(|&request_app_id| request_app_id == app_id)
+    }
+
+    /// Hand over all the pending requests of app `old_app_id` to app `new_app_id`.
+    fn move_pending_requests(&mut self, old_app_id: u128, new_app_id: u128) {
+        let request_app_ids = self
+            .route_requests
+            .values_mut()
+            .chain(self.close_payment_requests.values_mut())
+            .chain(self.payment_timeline_requests.values_mut())
+            .chain(self.transactions.values_mut());
+
+        for request_app_id in request_app_ids {
+            if *request_app_id == old_app_id {
+                *request_app_id = new_app_id;
+            }
+        }
+    }
+
+    /// Forget the session of a closed app once it has no more pending requests.
+    fn forget_idle_session(&mut self, app_id: u128) {
+        if self.apps.contains_key(&app_id) || self.has_pending_requests(app_id) {
+            return;
+        }
+        self.sessions
+            .retain(|_app_session, (_app_public_key, session_app_id)| *session_app_id != app_id);
+    }
+
     /// Add an application connection
     pub async fn handle_incoming_connection(
         &mut self,
         incoming_app_connection: IncomingAppConnection<B>,
     ) -> Result<(), AppServerError> {
-        let (permissions, (sender, receiver)) = incoming_app_connection;
+        let (app_public_key, permissions, opt_app_session, (sender, receiver)) =
+            incoming_app_connection;
 
         let app_counter = self.app_counter;
-        let mut receiver =
-            receiver.map(move |app_to_app_server| (app_counter, Some(app_to_app_server)));
 
-        let mut from_app_sender = self.from_app_sender.clone();
+        // An app presenting the token of a known session inherits all the pending requests of
+        // that session. Otherwise a new session is created.
+        // A session may only be resumed by the app that created it:
+        let opt_old_app_id = match opt_app_session
+            .as_ref()
+            .and_then(|app_session| self.sessions.get(app_session))
+        {
+            Some((session_public_key, old_app_id)) => {
+                if session_public_key == &app_public_key {
+                    Some(*old_app_id)
+                } else {
+                    warn!(
+                        "App {} presented a session token of another app",
+                        app_public_key.to_hex()
+                    );
+                    None
+                }
+            }
+            None => None,
+        };
+        let app_session = match (opt_app_session, opt_old_app_id) {
+            (Some(app_session), Some(old_app_id)) => {
+                self.move_pending_requests(old_app_id, app_counter);
+                app_session
+            }
+            _ => Uid::new(&self.rng),
+        };
+        self.sessions
+            .insert(app_session.clone(), (app_public_key.clone(), app_counter));
+        let mut receiver = receiver;
+
+        // Make sure the app does not exceed its requests rate:
+        let mut opt_rate_limiter =
+            permissions
+                .max_requests_per_second
+                .map(|max_requests_per_second| {
+                    RequestRateLimiter::new(max_requests_per_second, self.ticks.clone())
+                });
+
+        let mut from_app_senders = self.from_app_senders.clone();
         let send_all_fut = async move {
-            // Forward all messages:
-            let _ = await!(from_app_sender.send_all(&mut receiver));
-            // Notify that the connection to the app was closed:
-            let _ = await!(from_app_sender.send((app_counter, None)));
+            // Forward all messages, each according to its priority:
+            while let Some(app_to_app_server) = await!(receiver.next()) {
+                if let Some(rate_limiter) = &mut opt_rate_limiter {
+                    if !rate_limiter.check_request(&app_public_key) {
+                        continue;
+                    }
+                }
+                let priority = app_request_priority(&app_to_app_server.app_request);
+                if await!(from_app_senders[priority].send((app_counter, Some(app_to_app_server))))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            // Notify that the connection to the app was closed.
+            // This is sent with the lowest priority, so that it is handled only after all the
+            // requests sent by the app:
+            let _ =
+                await!(from_app_senders[NUM_APP_REQUEST_PRIORITIES - 1].send((app_counter, None)));
         };
 
         self.spawner
             .spawn(send_all_fut)
             .map_err(|_| AppServerError::SpawnError)?;
 
-        let mut app = App::new(permissions, sender);
+        let (high_priority_sender, high_priority_receiver) = mpsc::channel(APP_SEND_QUEUE_LEN);
+        let (normal_priority_sender, normal_priority_receiver) = mpsc::channel(APP_SEND_QUEUE_LEN);
+        let (close_sender, close_receiver) = oneshot::channel();
+        self.spawner
+            .spawn(app_sender_loop(
+                high_priority_receiver,
+                normal_priority_receiver,
+                close_receiver,
+                sender,
+            ))
+            .map_err(|_| AppServerError::SpawnError)?;
+
+        let mut app = App::new(
+            permissions,
+            high_priority_sender,
+            normal_priority_sender,
+            close_sender,
+        );
         // Send the initial node report:
-        await!(app.send(AppServerToApp::Report(self.node_report.clone())));
+        let node_report = filter_node_report(&app.permissions, &self.node_report);
+        app.send(AppServerToApp::Report((app_session, node_report)));
 
         self.apps.insert(self.app_counter, app);
         self.app_counter = self.app_counter.wrapping_add(1);
@@ -189,6 +746,11 @@ where
         Ok(())
     }
 
+    /// Refill the requests rate limit of all apps.
+    pub fn handle_timer_tick(&mut self) {
+        let _ = self.ticks.fetch_add(1, Ordering::SeqCst);
+    }
+
     /// The channel carrying new connections was closed.
     /// This means we will not receive any new connections
     pub async fn handle_incoming_connections_closed(&mut self) -> Result<(), AppServerError> {
@@ -200,10 +762,17 @@ where
     }
 
     /// Send node report mutations to all connected apps
-    pub async fn broadcast_node_report_mutations(&mut self, report_mutations: ReportMutations<B>) {
+    pub fn broadcast_node_report_mutations(&mut self, report_mutations: ReportMutations<B>) {
         // Send node report mutations to all connected apps
         for app in &mut self.apps.values_mut() {
-            await!(app.send(AppServerToApp::ReportMutations(report_mutations.clone())));
+            let app_report_mutations = filter_report_mutations(&app.permissions, &report_mutations);
+            // Nothing left to tell this app:
+            if app_report_mutations.mutations.is_empty()
+                && app_report_mutations.opt_app_request_id.is_none()
+            {
+                continue;
+            }
+            app.send(AppServerToApp::ReportMutations(app_report_mutations));
         }
     }
 
@@ -223,9 +792,15 @@ where
                     return Ok(());
                 };
                 if let Some(app) = self.apps.get_mut(&app_id) {
-                    await!(app.send(AppServerToApp::TransactionResult(
-                        transaction_result.clone()
-                    )));
+                    app.send(AppServerToApp::TransactionResult(transaction_result));
+                } else {
+                    // The app was closed. Keep the result, so that it could be retrieved later:
+                    if self.dead_letter_queue.len() >= MAX_DEAD_LETTER_QUEUE_LEN {
+                        let _ = self.dead_letter_queue.pop_front();
+                    }
+                    self.dead_letter_queue
+                        .push_back((transaction_result.request_id.clone(), transaction_result));
+                    self.forget_idle_session(app_id);
                 }
             }
             FunderOutgoingControl::ResponseClosePayment(response_close_payment) => {
@@ -240,11 +815,34 @@ where
                     return Ok(());
                 };
                 if let Some(app) = self.apps.get_mut(&app_id) {
-                    await!(app.send(AppServerToApp::ResponseClosePayment(
-                        response_close_payment.clone()
-                    )));
+                    app.send(AppServerToApp::ResponseClosePayment(response_close_payment));
+                } else {
+                    self.forget_idle_session(app_id);
+                }
+            }
+            FunderOutgoingControl::ResponsePaymentTimeline(response_payment_timeline) => {
+                // Find the app that issued the request, and forward the response to this app:
+                let app_id = if let Some(app_id) = self
+                    .payment_timeline_requests
+                    .remove(&response_payment_timeline.payment_id)
+                {
+                    app_id
+                } else {
+                    warn!("ResponsePaymentTimeline: Could not find app that initiated GetPaymentTimeline");
+                    return Ok(());
+                };
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponsePaymentTimeline(
+                        response_payment_timeline,
+                    ));
+                } else {
+                    self.forget_idle_session(app_id);
                 }
             }
+            FunderOutgoingControl::PaymentHistoryResponse(_) => {
+                // The app server never sends QueryPaymentHistory to the funder:
+                warn!("PaymentHistoryResponse: Payment history was not requested");
+            }
             FunderOutgoingControl::ReportMutations(funder_report_mutations) => {
                 let mut index_mutations = Vec::new();
                 for funder_report_mutation in &funder_report_mutations.mutations {
@@ -279,7 +877,7 @@ where
                     report_mutations.mutations.push(mutation);
                 }
 
-                await!(self.broadcast_node_report_mutations(report_mutations));
+                self.broadcast_node_report_mutations(report_mutations);
             }
         }
         Ok(())
@@ -302,7 +900,7 @@ where
                     report_mutations.mutations.push(mutation);
                 }
 
-                await!(self.broadcast_node_report_mutations(report_mutations));
+                self.broadcast_node_report_mutations(report_mutations);
             }
             IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
                 // We search for the app that issued the request, and send it the response.
@@ -319,9 +917,9 @@ where
                 };
 
                 if let Some(app) = self.apps.get_mut(&app_id) {
-                    await!(app.send(AppServerToApp::ResponseRoutes(
-                        client_response_routes.clone()
-                    )));
+                    app.send(AppServerToApp::ResponseRoutes(client_response_routes));
+                } else {
+                    self.forget_idle_session(app_id);
                 }
             }
         };
@@ -336,7 +934,7 @@ where
         // Get the relevant application:
 
         {
-            let app = match self.apps.get(&app_id) {
+            let app = match self.apps.get_mut(&app_id) {
                 Some(app) => app,
                 None => {
                     warn!("App {:?} does not exist!", app_id);
@@ -385,6 +983,21 @@ where
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::CreateMultiRoutePayment(create_multi_route_payment) => {
+                // Keep track of which application issued the requests.
+                // Request ids that are already in flight keep their original app, as the funder
+                // will not report a failure for them if this payment is rejected:
+                for multi_route_transaction in &create_multi_route_payment.transactions {
+                    self.transactions
+                        .entry(multi_route_transaction.request_id.clone())
+                        .or_insert(app_id);
+                }
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::CreateMultiRoutePayment(create_multi_route_payment)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::RequestClosePayment(request_close_payment) => {
                 await!(self.to_funder.send(FunderIncomingControl::new(
                     app_request_id,
@@ -399,13 +1012,20 @@ where
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
-            AppRequest::AddInvoice(add_invoice) => {
+            AppRequest::GetPaymentTimeline(payment_id) => {
+                // Keep track of which application issued this request:
+                self.payment_timeline_requests
+                    .insert(payment_id.clone(), app_id);
                 await!(self.to_funder.send(FunderIncomingControl::new(
                     app_request_id,
-                    FunderControl::AddInvoice(add_invoice)
+                    FunderControl::GetPaymentTimeline(payment_id)
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::AddInvoice(add_invoice) => await!(self.to_funder.send(
+                FunderIncomingControl::new(app_request_id, FunderControl::AddInvoice(add_invoice))
+            ))
+            .map_err(|_| AppServerError::SendToFunderError),
             AppRequest::CancelInvoice(invoice_id) => {
                 await!(self.to_funder.send(FunderIncomingControl::new(
                     app_request_id,
@@ -438,6 +1058,13 @@ where
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::SetFriendNote(set_friend_note) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetFriendNote(set_friend_note)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::RemoveFriend(friend_public_key) => {
                 let remove_friend = RemoveFriend { friend_public_key };
                 await!(self.to_funder.send(FunderIncomingControl::new(
@@ -511,11 +1138,26 @@ where
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::FreezeFriend(friend_public_key) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::FreezeFriend(friend_public_key)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::UnfreezeFriend(friend_public_key) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::UnfreezeFriend(friend_public_key)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::RequestRoutes(request_routes) => {
                 // Keep track of which application issued this request:
                 if self
                     .route_requests
-                    .insert(request_routes.request_id, app_id).is_some()
+                    .insert(request_routes.request_id, app_id)
+                    .is_some()
                 {
                     warn!("RequestRoutes: request_id clash.");
                 }
@@ -527,6 +1169,15 @@ where
                     ))))
                 .map_err(|_| AppServerError::SendToIndexClientError)
             }
+            AppRequest::ReportRouteOutcome(route_outcome) => {
+                await!(self
+                    .to_index_client
+                    .send(AppServerToIndexClient::AppRequest((
+                        app_request_id,
+                        IndexClientRequest::ReportRouteOutcome(route_outcome)
+                    ))))
+                .map_err(|_| AppServerError::SendToIndexClientError)
+            }
             AppRequest::AddIndexServer(named_index_server_address) => await!(self
                 .to_index_client
                 .send(AppServerToIndexClient::AppRequest((
@@ -541,6 +1192,21 @@ where
                     IndexClientRequest::RemoveIndexServer(index_server_address)
                 ))))
             .map_err(|_| AppServerError::SendToIndexClientError),
+            AppRequest::RotateIndexServer(rotate_index_server) => await!(self
+                .to_index_client
+                .send(AppServerToIndexClient::AppRequest((
+                    app_request_id,
+                    IndexClientRequest::RotateIndexServer(rotate_index_server)
+                ))))
+            .map_err(|_| AppServerError::SendToIndexClientError),
+            AppRequest::GetDeadLetterQueue => {
+                // Results are handed out only once:
+                let dead_letters = self.dead_letter_queue.drain(..).collect();
+                if let Some(app) = self.apps.get_mut(&app_id) {
+                    app.send(AppServerToApp::ResponseDeadLetterQueue(dead_letters));
+                }
+                Ok(())
+            }
         }
     }
 
@@ -554,6 +1220,7 @@ where
                 // Remove the application. We assert that this application exists
                 // in our apps map:
                 self.apps.remove(&app_id).unwrap();
+                self.forget_idle_session(app_id);
                 if self.apps.is_empty() && self.incoming_connections_closed {
                     return Err(AppServerError::AllAppsClosed);
                 }
@@ -565,13 +1232,16 @@ where
 }
 
 #[allow(unused)]
-pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, S>(
+pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, TS, R, S>(
     from_funder: FF,
     to_funder: TF,
     from_index_client: FIC,
     to_index_client: TIC,
     incoming_connections: IC,
+    timer_stream: TS,
     initial_node_report: NodeReport<B>,
+    stats: AppServerStats,
+    rng: R,
     mut spawner: S,
 ) -> Result<(), AppServerError>
 where
@@ -581,14 +1251,24 @@ where
     FIC: Stream<Item = IndexClientToAppServer<B>> + Unpin + Send,
     TIC: Sink<AppServerToIndexClient<B>> + Unpin,
     IC: Stream<Item = IncomingAppConnection<B>> + Unpin + Send,
+    TS: Stream<Item = TimerTick> + Unpin + Send,
+    R: CryptoRandom,
     S: Spawn,
 {
-    let (from_app_sender, from_app_receiver) = mpsc::channel(0);
+    let mut from_app_senders = Vec::new();
+    let mut from_app_receivers = Vec::new();
+    for _ in 0..NUM_APP_REQUEST_PRIORITIES {
+        let (from_app_sender, from_app_receiver) = mpsc::channel(APP_REQUEST_QUEUE_LEN);
+        from_app_senders.push(from_app_sender);
+        from_app_receivers.push(from_app_receiver);
+    }
+
     let mut app_server = AppServer::new(
         to_funder,
         to_index_client,
-        from_app_sender,
+        from_app_senders,
         initial_node_report,
+        rng,
         spawner,
     );
 
@@ -602,21 +1282,28 @@ where
             AppServerEvent::IndexClientClosed,
         )));
 
-    let from_app_receiver = from_app_receiver.map(AppServerEvent::FromApp);
-
     let incoming_connections = incoming_connections
         .map(AppServerEvent::IncomingConnection)
         .chain(stream::once(future::ready(
             AppServerEvent::IncomingConnectionsClosed,
         )));
 
-    let mut events = select_streams![
-        from_funder,
-        from_index_client,
-        from_app_receiver,
-        incoming_connections
-    ];
+    let timer_stream = timer_stream.map(|_| AppServerEvent::TimerTick);
+
+    // Time ticks, events from the funder, the index client and new connections are handled
+    // first.
+    // Requests from apps are handled according to their priority:
+    let mut streams: Vec<BoxStream<'_, _>> = Vec::new();
+    streams.push(Box::pin(timer_stream));
+    streams.push(Box::pin(from_funder));
+    streams.push(Box::pin(from_index_client));
+    streams.push(Box::pin(incoming_connections));
+    for from_app_receiver in from_app_receivers {
+        streams.push(Box::pin(from_app_receiver.map(AppServerEvent::FromApp)));
+    }
+    let mut events = priority_stream(streams);
 
+    let mut num_events: usize = 0;
     while let Some(event) = await!(events.next()) {
         match event {
             AppServerEvent::IncomingConnection(incoming_app_connection) => {
@@ -636,6 +1323,19 @@ where
             AppServerEvent::FromApp((app_id, opt_app_message)) => {
                 await!(app_server.handle_from_app(app_id, opt_app_message))?
             }
+            AppServerEvent::TimerTick => app_server.handle_timer_tick(),
+        }
+
+        let app_count = app_server.get_app_count();
+        let pending_request_count = app_server.get_pending_request_count();
+        stats.update(app_count, pending_request_count);
+
+        num_events = num_events.wrapping_add(1);
+        if num_events % STATS_LOG_INTERVAL == 0 {
+            debug!(
+                "app_server_loop: apps: {}, pending requests: {}",
+                app_count, pending_request_count
+            );
         }
     }
     Ok(())
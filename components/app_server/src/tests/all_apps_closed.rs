@@ -11,7 +11,7 @@ use proto::index_client::messages::{
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
 
 async fn task_app_server_loop_all_apps_closed<S>(spawner: S)
 where
@@ -35,14 +35,22 @@ where
         buyer: true,
         seller: true,
         config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
     };
 
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The app should receive the current node report as the first message:
     let to_app_message = await!(app_receiver.next()).unwrap();
     match to_app_message {
-        AppServerToApp::Report(report) => assert_eq!(report, initial_node_report),
+        AppServerToApp::Report((_app_session, report)) => assert_eq!(report, initial_node_report),
         _ => unreachable!(),
     };
 
@@ -0,0 +1,169 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::payment_id::{PaymentId, PAYMENT_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    CreateTransaction, FriendsRoute, FunderControl, FunderOutgoingControl, RequestResult,
+    TransactionResult,
+};
+
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
+
+fn request_id_from_index(index: u16) -> Uid {
+    let mut uid_bytes = [0u8; UID_LEN];
+    uid_bytes[0] = 1;
+    uid_bytes[1] = (index >> 8) as u8;
+    uid_bytes[2] = index as u8;
+    Uid::from(&uid_bytes)
+}
+
+/// A TransactionResult that does not match any open request.
+/// Once sending it is done, all the previously sent funder messages were handled.
+fn unrelated_transaction_result() -> FunderOutgoingControl<u32> {
+    FunderOutgoingControl::TransactionResult(TransactionResult {
+        request_id: Uid::from(&[0xff; UID_LEN]),
+        result: RequestResult::Failure,
+    })
+}
+
+async fn task_app_server_loop_dead_letter_queue<S>(spawner: S, num_transactions: u16)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: true,
+        seller: false,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+
+    // Connect the buyer app:
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver0) = mpsc::channel(0);
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions.clone(),
+        None,
+        (app_server_sender, app_server_receiver)
+    )))
+    .unwrap();
+
+    // The app should receive the current node report as the first message:
+    let _to_app_message = await!(app_receiver0.next()).unwrap();
+
+    let pk_e = PublicKey::from(&[0xee; PUBLIC_KEY_LEN]);
+    let pk_f = PublicKey::from(&[0xff; PUBLIC_KEY_LEN]);
+
+    for index in 0..num_transactions {
+        let create_transaction = CreateTransaction {
+            payment_id: PaymentId::from(&[1; PAYMENT_ID_LEN]),
+            request_id: request_id_from_index(index),
+            route: FriendsRoute {
+                public_keys: vec![pk_e.clone(), pk_f.clone()],
+            },
+            dest_payment: 20,
+            fees: 4,
+        };
+        let to_app_server = AppToAppServer::new(
+            Uid::from(&[22; UID_LEN]),
+            AppRequest::CreateTransaction(create_transaction),
+        );
+        await!(app_sender0.send(to_app_server)).unwrap();
+
+        let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+        match funder_incoming_control.funder_control {
+            FunderControl::CreateTransaction(_) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    // The buyer app disconnects before receiving any results.
+    // The app server drops its side of the connection once the app is removed:
+    drop(app_sender0);
+    assert!(await!(app_receiver0.next()).is_none());
+
+    for index in 0..num_transactions {
+        let transaction_result = TransactionResult {
+            request_id: request_id_from_index(index),
+            result: RequestResult::Failure,
+        };
+        await!(funder_sender.send(FunderOutgoingControl::TransactionResult(transaction_result)))
+            .unwrap();
+    }
+    await!(funder_sender.send(unrelated_transaction_result())).unwrap();
+
+    // Connect another app and retrieve the undelivered results:
+    let (mut app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    await!(connections_sender.send((
+        dummy_app_public_key(1),
+        app_permissions,
+        None,
+        (app_server_sender, app_server_receiver)
+    )))
+    .unwrap();
+    let _to_app_message = await!(app_receiver1.next()).unwrap();
+
+    let to_app_server =
+        AppToAppServer::new(Uid::from(&[23; UID_LEN]), AppRequest::GetDeadLetterQueue);
+    await!(app_sender1.send(to_app_server)).unwrap();
+
+    let dead_letters = match await!(app_receiver1.next()).unwrap() {
+        AppServerToApp::ResponseDeadLetterQueue(dead_letters) => dead_letters,
+        _ => unreachable!(),
+    };
+
+    // Only the most recent results are kept:
+    let num_kept = num_transactions.min(1000);
+    assert_eq!(dead_letters.len(), usize::from(num_kept));
+    let first_kept = num_transactions - num_kept;
+    for (dead_letter, index) in dead_letters.iter().zip(first_kept..num_transactions) {
+        let (request_id, transaction_result) = dead_letter;
+        assert_eq!(request_id, &request_id_from_index(index));
+        assert_eq!(&transaction_result.request_id, request_id);
+    }
+
+    // Results are handed out only once:
+    let to_app_server =
+        AppToAppServer::new(Uid::from(&[24; UID_LEN]), AppRequest::GetDeadLetterQueue);
+    await!(app_sender1.send(to_app_server)).unwrap();
+    match await!(app_receiver1.next()).unwrap() {
+        AppServerToApp::ResponseDeadLetterQueue(dead_letters) => assert!(dead_letters.is_empty()),
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_dead_letter_queue() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_dead_letter_queue(
+        thread_pool.clone(),
+        3,
+    ));
+}
+
+#[test]
+fn test_app_server_loop_dead_letter_queue_bounded() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_dead_letter_queue(
+        thread_pool.clone(),
+        1005,
+    ));
+}
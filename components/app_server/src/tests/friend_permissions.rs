@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, FriendPermissions,
+    NodeReportMutation,
+};
+use proto::funder::messages::{FunderControl, FunderOutgoingControl, SetFriendRemoteMaxDebt};
+use proto::report::messages::{
+    AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, FunderReportMutation,
+    FunderReportMutations,
+};
+
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
+
+fn add_friend_mutation(friend_public_key: &PublicKey) -> FunderReportMutation<u32> {
+    FunderReportMutation::AddFriend(AddFriendReport {
+        friend_public_key: friend_public_key.clone(),
+        name: "friend".to_owned(),
+        note: String::new(),
+        relays: Vec::new(),
+        balance: 0,
+        opt_last_incoming_move_token: None,
+        channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+            local_reset_terms_balance: 0,
+            opt_remote_reset_terms: None,
+        }),
+    })
+}
+
+fn set_max_debt_message(index: u8, friend_public_key: &PublicKey) -> AppToAppServer<u32> {
+    AppToAppServer::new(
+        Uid::from(&[index; UID_LEN]),
+        AppRequest::SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt {
+            friend_public_key: friend_public_key.clone(),
+            remote_max_debt: 100,
+        }),
+    )
+}
+
+async fn task_app_server_loop_friend_permissions<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+    let pk_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+
+    // An app without config permission, that may only set the max debt of pk_b:
+    let mut friend_permissions = HashMap::new();
+    friend_permissions.insert(
+        pk_b.clone(),
+        FriendPermissions {
+            read: true,
+            set_max_debt: true,
+            open_close: false,
+        },
+    );
+    // The app may not read the state of pk_d:
+    friend_permissions.insert(
+        pk_d.clone(),
+        FriendPermissions {
+            read: false,
+            set_max_debt: false,
+            open_close: false,
+        },
+    );
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: false,
+        config: false,
+        max_requests_per_second: None,
+        friend_permissions: Some(friend_permissions),
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app should receive the current node report as the first message:
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+
+    // Not allowed: No permissions entry for pk_c, and no config permission:
+    await!(app_sender.send(set_max_debt_message(0, &pk_c))).unwrap();
+
+    // Not allowed: open_close is not set for pk_b:
+    await!(app_sender.send(AppToAppServer::new(
+        Uid::from(&[1; UID_LEN]),
+        AppRequest::OpenFriend(pk_b.clone()),
+    )))
+    .unwrap();
+
+    // Allowed:
+    await!(app_sender.send(set_max_debt_message(2, &pk_b))).unwrap();
+
+    // Only the allowed request reaches the funder:
+    let to_funder_message = await!(funder_receiver.next()).unwrap();
+    assert_eq!(to_funder_message.app_request_id, Uid::from(&[2; UID_LEN]));
+    match to_funder_message.funder_control {
+        FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt) => {
+            assert_eq!(set_friend_remote_max_debt.friend_public_key, pk_b)
+        }
+        _ => unreachable!(),
+    };
+
+    // The app only sees mutations of friends it may read:
+    let funder_report_mutations = FunderReportMutations {
+        opt_app_request_id: None,
+        mutations: vec![add_friend_mutation(&pk_c), add_friend_mutation(&pk_d)],
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ReportMutations(
+        funder_report_mutations
+    )))
+    .unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => {
+            assert_eq!(
+                report_mutations.mutations,
+                vec![NodeReportMutation::Funder(add_friend_mutation(&pk_c))]
+            );
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_friend_permissions() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_friend_permissions(thread_pool.clone()));
+}
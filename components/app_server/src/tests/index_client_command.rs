@@ -14,7 +14,7 @@ use proto::index_client::messages::{
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
 
 async fn task_app_server_loop_index_client_command<S>(spawner: S)
 where
@@ -38,14 +38,22 @@ where
         buyer: true,
         seller: true,
         config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
     };
 
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The app should receive the current node report as the first message:
     let to_app_message = await!(app_receiver.next()).unwrap();
     match to_app_message {
-        AppServerToApp::Report(report) => assert_eq!(report, initial_node_report),
+        AppServerToApp::Report((_app_session, report)) => assert_eq!(report, initial_node_report),
         _ => unreachable!(),
     };
 
@@ -107,6 +115,34 @@ where
         }
         _ => unreachable!(),
     }
+
+    // Replace the index server with a new one:
+    let new_named_index_server_address = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+        address: 301u32,
+        name: "IndexServer301".to_string(),
+    };
+    let to_app_server = AppToAppServer {
+        app_request_id: Uid::from(&[12; UID_LEN]),
+        app_request: AppRequest::RotateIndexServer((
+            PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            new_named_index_server_address.clone(),
+        )),
+    };
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    // RotateIndexServer command should be forwarded to IndexClient:
+    let to_index_client_message = await!(index_client_receiver.next()).unwrap();
+    match to_index_client_message {
+        AppServerToIndexClient::AppRequest((
+            _app_request_id,
+            IndexClientRequest::RotateIndexServer((old_public_key, named_index_server_address0)),
+        )) => {
+            assert_eq!(old_public_key, PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]));
+            assert_eq!(named_index_server_address0, new_named_index_server_address);
+        }
+        _ => unreachable!(),
+    };
 }
 
 #[test]
@@ -1,7 +1,15 @@
 mod all_apps_closed;
+mod dead_letter_queue;
+mod friend_permissions;
 mod funder_command;
 mod index_client_command;
+mod priority;
+mod rate_limit;
+mod reconnect;
+mod request_priority;
 mod request_routes;
 mod request_send_funds;
+mod slow_app;
+mod stats;
 mod two_apps;
 mod utils;
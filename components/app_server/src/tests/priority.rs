@@ -0,0 +1,140 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::payment_id::{PaymentId, PAYMENT_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    CreateTransaction, FriendsRoute, FunderControl, FunderOutgoingControl, RequestResult,
+    TransactionResult,
+};
+use proto::report::messages::FunderReportMutations;
+
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
+
+/// Create an empty report mutations message, as sent by the Funder.
+fn dummy_report_mutations() -> FunderOutgoingControl<u32> {
+    FunderOutgoingControl::ReportMutations(FunderReportMutations {
+        opt_app_request_id: None,
+        mutations: Vec::new(),
+    })
+}
+
+async fn task_app_server_loop_priority<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app should receive the current node report as the first message:
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+
+    let pk_e = PublicKey::from(&[0xee; PUBLIC_KEY_LEN]);
+    let pk_f = PublicKey::from(&[0xff; PUBLIC_KEY_LEN]);
+
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[1; PAYMENT_ID_LEN]),
+        request_id: Uid::from(&[3; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![pk_e.clone(), pk_f.clone()],
+        },
+        dest_payment: 20,
+        fees: 4,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[23; UID_LEN]),
+        AppRequest::CreateTransaction(create_transaction.clone()),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    // CreateTransaction command should be forwarded to the Funder:
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    match funder_incoming_control.funder_control {
+        FunderControl::CreateTransaction(received_create_transaction) => {
+            assert_eq!(received_create_transaction, create_transaction)
+        }
+        _ => unreachable!(),
+    };
+
+    // The app is not reading its messages. Many report mutations pile up:
+    for _ in 0..100 {
+        await!(funder_sender.send(dummy_report_mutations())).unwrap();
+    }
+
+    let transaction_result = TransactionResult {
+        request_id: Uid::from(&[3; UID_LEN]),
+        result: RequestResult::Failure,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::TransactionResult(
+        transaction_result.clone()
+    )))
+    .unwrap();
+
+    // Make sure that the TransactionResult was processed by the app server:
+    // Once the second message is accepted, the first one (and the TransactionResult before it)
+    // must have been fully handled.
+    await!(funder_sender.send(dummy_report_mutations())).unwrap();
+    await!(funder_sender.send(dummy_report_mutations())).unwrap();
+
+    // The TransactionResult should preempt the queued report mutations.
+    // Only report mutations that were already handed to the app connection may arrive first:
+    let mut num_report_mutations = 0;
+    loop {
+        match await!(app_receiver.next()).unwrap() {
+            AppServerToApp::ReportMutations(_) => num_report_mutations += 1,
+            AppServerToApp::TransactionResult(received_transaction_result) => {
+                assert_eq!(received_transaction_result, transaction_result);
+                break;
+            }
+            _ => unreachable!(),
+        }
+    }
+    assert!(num_report_mutations <= 2);
+
+    // All the remaining report mutations are delivered afterwards:
+    for _ in num_report_mutations..102 {
+        match await!(app_receiver.next()).unwrap() {
+            AppServerToApp::ReportMutations(_) => {}
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_app_server_loop_priority() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_priority(thread_pool.clone()));
+}
@@ -0,0 +1,139 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::index_client::messages::RequestRoutes;
+use timer::TimerTick;
+
+use crate::server::AppServerStats;
+
+use super::utils::{
+    dummy_app_public_key, dummy_named_relay_address, spawn_dummy_app_server_with_timer,
+};
+
+/// Create a config request sent by an app.
+fn add_relay_message(index: u8) -> AppToAppServer<u32> {
+    AppToAppServer::new(
+        Uid::from(&[index; UID_LEN]),
+        AppRequest::AddRelay(dummy_named_relay_address(index)),
+    )
+}
+
+async fn task_app_server_loop_rate_limit<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (mut tick_sender, timer_stream) = mpsc::channel(0);
+    let (
+        _funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server_with_timer(spawner.clone(), AppServerStats::new(), timer_stream);
+
+    // Connect a rate limited app:
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver0) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: Some(2),
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // Connect an app without a rate limit:
+    let (mut app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(1),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The apps should receive the current node report as the first message:
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+    match await!(app_receiver1.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+
+    // app0 may send two requests at once:
+    for index in 0..2u8 {
+        await!(app_sender0.send(add_relay_message(index))).unwrap();
+        let to_funder_message = await!(funder_receiver.next()).unwrap();
+        assert_eq!(
+            to_funder_message.app_request_id,
+            Uid::from(&[index; UID_LEN])
+        );
+    }
+
+    // Further requests from app0 are discarded before they are queued:
+    await!(app_sender0.send(add_relay_message(2))).unwrap();
+    let request_routes = RequestRoutes {
+        request_id: Uid::from(&[3; UID_LEN]),
+        capacity: 250,
+        source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+        destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+        opt_exclude: None,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[3; UID_LEN]),
+        AppRequest::RequestRoutes(request_routes),
+    );
+    await!(app_sender0.send(to_app_server)).unwrap();
+
+    // app1 is not limited:
+    await!(app_sender1.send(add_relay_message(4))).unwrap();
+    let to_funder_message = await!(funder_receiver.next()).unwrap();
+    assert_eq!(to_funder_message.app_request_id, Uid::from(&[4; UID_LEN]));
+
+    // Send a time tick. Time ticks are handled before requests from apps, so once a request
+    // from app1 is handled we know that the time tick was handled too:
+    await!(tick_sender.send(TimerTick)).unwrap();
+    await!(app_sender1.send(add_relay_message(5))).unwrap();
+    let to_funder_message = await!(funder_receiver.next()).unwrap();
+    assert_eq!(to_funder_message.app_request_id, Uid::from(&[5; UID_LEN]));
+
+    // After the time tick, app0 may send requests again.
+    // Its discarded requests were never queued, so the next request we see is the new one:
+    await!(app_sender0.send(add_relay_message(6))).unwrap();
+    let to_funder_message = await!(funder_receiver.next()).unwrap();
+    assert_eq!(to_funder_message.app_request_id, Uid::from(&[6; UID_LEN]));
+}
+
+#[test]
+fn test_app_server_loop_rate_limit() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_rate_limit(thread_pool.clone()));
+}
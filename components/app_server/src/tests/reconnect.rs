@@ -0,0 +1,168 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::index_client::messages::{
+    AppServerToIndexClient, ClientResponseRoutes, IndexClientRequest, IndexClientToAppServer,
+    RequestRoutes, ResponseRoutesResult,
+};
+
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
+
+async fn task_app_server_loop_reconnect<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        mut index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions.clone(),
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app receives its session token together with the initial node report:
+    let app_session = match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report((app_session, _node_report)) => app_session,
+        _ => unreachable!(),
+    };
+
+    let request_routes = RequestRoutes {
+        request_id: Uid::from(&[3; UID_LEN]),
+        capacity: 250,
+        source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+        destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+        opt_exclude: None,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[22; UID_LEN]),
+        AppRequest::RequestRoutes(request_routes.clone()),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    let to_index_client_message = await!(index_client_receiver.next()).unwrap();
+    match to_index_client_message {
+        AppServerToIndexClient::AppRequest((
+            _app_request_id,
+            IndexClientRequest::RequestRoutes(received_request_routes),
+        )) => assert_eq!(received_request_routes, request_routes),
+        _ => unreachable!(),
+    };
+
+    // The connection to the app is lost before the response arrives:
+    drop(app_sender);
+    drop(app_receiver);
+
+    // Another app presents the session token. It gets a new session:
+    let (_app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_public_key(1),
+        app_permissions.clone(),
+        Some(app_session.clone()),
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report((new_app_session, _node_report)) => {
+            assert_ne!(new_app_session, app_session)
+        }
+        _ => unreachable!(),
+    };
+
+    // The app reconnects, presenting its session token:
+    let (_app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions.clone(),
+        Some(app_session.clone()),
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The session is preserved:
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report((new_app_session, _node_report)) => {
+            assert_eq!(new_app_session, app_session)
+        }
+        _ => unreachable!(),
+    };
+
+    // The response to the request sent over the previous connection
+    // is delivered to the new connection:
+    let client_response_routes = ClientResponseRoutes {
+        request_id: Uid::from(&[3; UID_LEN]),
+        result: ResponseRoutesResult::Failure,
+    };
+    await!(
+        index_client_sender.send(IndexClientToAppServer::ResponseRoutes(
+            client_response_routes
+        ))
+    )
+    .unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseRoutes(response_routes) => {
+            assert_eq!(response_routes.request_id, Uid::from(&[3; UID_LEN]));
+            assert_eq!(response_routes.result, ResponseRoutesResult::Failure);
+        }
+        _ => unreachable!(),
+    };
+
+    // An app presenting an unknown session token gets a new session:
+    let unknown_app_session = Uid::from(&[0x55; UID_LEN]);
+    let (_app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_public_key(2),
+        app_permissions,
+        Some(unknown_app_session.clone()),
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report((new_app_session, _node_report)) => {
+            assert_ne!(new_app_session, unknown_app_session);
+            assert_ne!(new_app_session, app_session);
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_reconnect() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_reconnect(thread_pool.clone()));
+}
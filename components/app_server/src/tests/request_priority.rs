@@ -0,0 +1,131 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, Stream, StreamExt};
+
+use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{FunderControl, ResetFriendChannel};
+use proto::index_client::messages::{AppServerToIndexClient, IndexClientRequest, RequestRoutes};
+
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
+
+/// Create a request routes message sent by an app.
+fn request_routes_message(index: u8) -> AppToAppServer<u32> {
+    let request_routes = RequestRoutes {
+        request_id: Uid::from(&[index; UID_LEN]),
+        capacity: 250,
+        source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+        destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+        opt_exclude: None,
+    };
+    AppToAppServer::new(
+        Uid::from(&[index; UID_LEN]),
+        AppRequest::RequestRoutes(request_routes),
+    )
+}
+
+/// Read the next request routes message forwarded to the index client,
+/// and return its request id.
+async fn next_request_routes_id<S>(index_client_receiver: &mut S) -> Uid
+where
+    S: Stream<Item = AppServerToIndexClient<u32>> + Unpin,
+{
+    match await!(index_client_receiver.next()).unwrap() {
+        AppServerToIndexClient::AppRequest((
+            _app_request_id,
+            IndexClientRequest::RequestRoutes(request_routes),
+        )) => request_routes.request_id,
+        _ => unreachable!(),
+    }
+}
+
+async fn task_app_server_loop_request_priority<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app should receive the current node report as the first message:
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+
+    // We don't read from the index client yet, so the app server gets stuck forwarding the
+    // route requests, and the rest of the requests are queued:
+    for index in 0..100u8 {
+        await!(app_sender.send(request_routes_message(index))).unwrap();
+    }
+
+    let reset_friend_channel = ResetFriendChannel {
+        friend_public_key: PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+        reset_token: Signature::from(&[0x11; SIGNATURE_LEN]),
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[0xcc; UID_LEN]),
+        AppRequest::ResetFriendChannel(reset_friend_channel.clone()),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    // Once another message is accepted, we know that the config mutation was queued by the
+    // app server:
+    await!(app_sender.send(request_routes_message(100))).unwrap();
+
+    // Let the app server continue:
+    assert_eq!(
+        await!(next_request_routes_id(&mut index_client_receiver)),
+        Uid::from(&[0; UID_LEN])
+    );
+
+    // The config mutation is handled before the rest of the route requests:
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    match funder_incoming_control.funder_control {
+        FunderControl::ResetFriendChannel(received_reset_friend_channel) => {
+            assert_eq!(received_reset_friend_channel, reset_friend_channel)
+        }
+        _ => unreachable!(),
+    };
+
+    // The route requests are handled afterwards, in order:
+    for index in 1..=100u8 {
+        assert_eq!(
+            await!(next_request_routes_id(&mut index_client_receiver)),
+            Uid::from(&[index; UID_LEN])
+        );
+    }
+}
+
+#[test]
+fn test_app_server_loop_request_priority() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_request_priority(thread_pool.clone()));
+}
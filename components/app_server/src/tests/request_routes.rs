@@ -13,7 +13,7 @@ use proto::index_client::messages::{
     RequestRoutes, ResponseRoutesResult,
 };
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
 
 async fn task_app_server_loop_request_routes<S>(spawner: S)
 where
@@ -37,8 +37,16 @@ where
         buyer: true,
         seller: true,
         config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     let (_app_sender1, app_server_receiver) = mpsc::channel(0);
     let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
@@ -48,8 +56,16 @@ where
         buyer: true,
         seller: true,
         config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_public_key(1),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The apps should receive the current node report as the first message:
     let _to_app_message = await!(app_receiver0.next()).unwrap();
@@ -0,0 +1,78 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use proto::app_server::messages::{AppPermissions, AppServerToApp};
+use proto::funder::messages::FunderOutgoingControl;
+use proto::report::messages::FunderReportMutations;
+
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
+
+/// Amount of report mutations sent to the app. Larger than the app's send queue.
+const NUM_REPORT_MUTATIONS: usize = 0x1000;
+
+async fn task_app_server_loop_slow_app<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (_app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app is not reading its messages, until its queue overflows:
+    for _ in 0..NUM_REPORT_MUTATIONS {
+        await!(funder_sender.send(FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: None,
+                mutations: Vec::new(),
+            }
+        )))
+        .unwrap();
+    }
+
+    // The app server should have disconnected the app.
+    // Only messages that were already handed to the app connection may still arrive:
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+    let mut num_report_mutations = 0;
+    while let Some(message) = await!(app_receiver.next()) {
+        match message {
+            AppServerToApp::ReportMutations(_) => num_report_mutations += 1,
+            _ => unreachable!(),
+        }
+    }
+    assert!(num_report_mutations < NUM_REPORT_MUTATIONS);
+}
+
+#[test]
+fn test_app_server_loop_slow_app() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_slow_app(thread_pool.clone()));
+}
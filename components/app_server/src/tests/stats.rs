@@ -0,0 +1,209 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::payment_id::{PaymentId, PAYMENT_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    CreateTransaction, FriendsRoute, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+    RequestResult, TransactionResult,
+};
+use proto::index_client::messages::{
+    AppServerToIndexClient, ClientResponseRoutes, IndexClientToAppServer, RequestRoutes,
+    ResponseRoutesResult,
+};
+
+use crate::server::AppServerStats;
+
+use super::utils::{
+    dummy_app_public_key, dummy_named_relay_address, spawn_dummy_app_server_with_stats,
+};
+
+/// Make sure that the app server loop is done handling all the events
+/// that were already observed by the test.
+///
+/// The app server loop handles events one by one, and updates its stats after every event.
+/// Therefore once a new AddRelay request is forwarded to the Funder, the stats
+/// reflect all previously handled events.
+async fn sync_app_server(
+    app_sender: &mut mpsc::Sender<AppToAppServer<u32>>,
+    funder_receiver: &mut mpsc::Receiver<FunderIncomingControl<u32>>,
+) {
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[0xff; UID_LEN]),
+        AppRequest::AddRelay(dummy_named_relay_address(0)),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    match funder_incoming_control.funder_control {
+        FunderControl::AddRelay(_) => {}
+        _ => unreachable!(),
+    };
+}
+
+async fn task_app_server_loop_stats<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let stats = AppServerStats::new();
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        mut index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server_with_stats(spawner.clone(), stats.clone());
+
+    assert_eq!(stats.app_count(), 0);
+    assert_eq!(stats.pending_request_count(), 0);
+
+    // Connect two apps:
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver0) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    let (app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        buyer: true,
+        seller: true,
+        config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+    await!(connections_sender.send((
+        dummy_app_public_key(1),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The apps should receive the current node report as the first message:
+    let _to_app_message = await!(app_receiver0.next()).unwrap();
+    let _to_app_message = await!(app_receiver1.next()).unwrap();
+
+    await!(sync_app_server(&mut app_sender0, &mut funder_receiver));
+    assert_eq!(stats.app_count(), 2);
+    assert_eq!(stats.pending_request_count(), 0);
+
+    // Close app1:
+    drop(app_sender1);
+    // Once app1 is removed, the app server stops sending it messages:
+    assert!(await!(app_receiver1.next()).is_none());
+
+    await!(sync_app_server(&mut app_sender0, &mut funder_receiver));
+    assert_eq!(stats.app_count(), 1);
+    assert_eq!(stats.pending_request_count(), 0);
+
+    // Create a transaction through app0:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[1; PAYMENT_ID_LEN]),
+        request_id: Uid::from(&[3; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+            ],
+        },
+        dest_payment: 20,
+        fees: 4,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[22; UID_LEN]),
+        AppRequest::CreateTransaction(create_transaction),
+    );
+    await!(app_sender0.send(to_app_server)).unwrap();
+    let _funder_incoming_control = await!(funder_receiver.next()).unwrap();
+
+    await!(sync_app_server(&mut app_sender0, &mut funder_receiver));
+    assert_eq!(stats.app_count(), 1);
+    assert_eq!(stats.pending_request_count(), 1);
+
+    // Request routes through app0:
+    let request_routes = RequestRoutes {
+        request_id: Uid::from(&[4; UID_LEN]),
+        capacity: 250,
+        source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+        destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+        opt_exclude: None,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[23; UID_LEN]),
+        AppRequest::RequestRoutes(request_routes),
+    );
+    await!(app_sender0.send(to_app_server)).unwrap();
+    match await!(index_client_receiver.next()).unwrap() {
+        AppServerToIndexClient::AppRequest(_) => {}
+        _ => unreachable!(),
+    };
+
+    await!(sync_app_server(&mut app_sender0, &mut funder_receiver));
+    assert_eq!(stats.app_count(), 1);
+    assert_eq!(stats.pending_request_count(), 2);
+
+    // Funder returns the result of the transaction:
+    let transaction_result = TransactionResult {
+        request_id: Uid::from(&[3; UID_LEN]),
+        result: RequestResult::Failure,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::TransactionResult(transaction_result)))
+        .unwrap();
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::TransactionResult(_) => {}
+        _ => unreachable!(),
+    };
+
+    await!(sync_app_server(&mut app_sender0, &mut funder_receiver));
+    assert_eq!(stats.app_count(), 1);
+    assert_eq!(stats.pending_request_count(), 1);
+
+    // IndexClient returns the requested routes:
+    let client_response_routes = ClientResponseRoutes {
+        request_id: Uid::from(&[4; UID_LEN]),
+        result: ResponseRoutesResult::Failure,
+    };
+    await!(
+        index_client_sender.send(IndexClientToAppServer::ResponseRoutes(
+            client_response_routes
+        ))
+    )
+    .unwrap();
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::ResponseRoutes(_) => {}
+        _ => unreachable!(),
+    };
+
+    await!(sync_app_server(&mut app_sender0, &mut funder_receiver));
+    assert_eq!(stats.app_count(), 1);
+    assert_eq!(stats.pending_request_count(), 0);
+}
+
+#[test]
+fn test_app_server_loop_stats() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_stats(thread_pool.clone()));
+}
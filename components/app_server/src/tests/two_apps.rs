@@ -11,7 +11,7 @@ use proto::index_client::messages::{
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_public_key, spawn_dummy_app_server};
 
 async fn task_app_server_loop_two_apps<S>(spawner: S)
 where
@@ -34,8 +34,16 @@ where
         buyer: true,
         seller: true,
         config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_public_key(0),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     let (_app_sender1, app_server_receiver) = mpsc::channel(0);
     let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
@@ -45,19 +53,27 @@ where
         buyer: true,
         seller: true,
         config: true,
+        max_requests_per_second: None,
+        friend_permissions: None,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_public_key(1),
+        app_permissions,
+        None,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The apps should receive the current node report as the first message:
     // Send a report
     let to_app_message = await!(app_receiver0.next()).unwrap();
     match to_app_message {
-        AppServerToApp::Report(report) => assert_eq!(report, initial_node_report),
+        AppServerToApp::Report((_app_session, report)) => assert_eq!(report, initial_node_report),
         _ => unreachable!(),
     };
     let to_app_message = await!(app_receiver1.next()).unwrap();
     match to_app_message {
-        AppServerToApp::Report(report) => assert_eq!(report, initial_node_report),
+        AppServerToApp::Report((_app_session, report)) => assert_eq!(report, initial_node_report),
         _ => unreachable!(),
     };
 
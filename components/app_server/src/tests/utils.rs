@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{FutureExt, TryFutureExt};
@@ -5,6 +7,7 @@ use futures::{FutureExt, TryFutureExt};
 use im::hashmap::HashMap as ImHashMap;
 
 use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
 
 use proto::app_server::messages::{NamedRelayAddress, NodeReport};
 use proto::funder::messages::{FunderIncomingControl, FunderOutgoingControl};
@@ -14,7 +17,9 @@ use proto::index_client::messages::{
 use proto::index_server::messages::NamedIndexServerAddress;
 use proto::report::messages::FunderReport;
 
-use crate::server::{app_server_loop, IncomingAppConnection};
+use timer::TimerTick;
+
+use crate::server::{app_server_loop, AppServerStats, IncomingAppConnection};
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
@@ -25,6 +30,11 @@ pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
     }
 }
 
+/// A helper function to quickly create the public key of a dummy app.
+pub fn dummy_app_public_key(index: u8) -> PublicKey {
+    PublicKey::from(&[0x80 | index; PUBLIC_KEY_LEN])
+}
+
 /*
 /// A helper function to quickly create a dummy RelayAddress.
 pub fn dummy_relay_address(index: u8) -> RelayAddress<u32> {
@@ -36,7 +46,48 @@ pub fn dummy_relay_address(index: u8) -> RelayAddress<u32> {
 /// Spawns an app server loop and returns all relevant channels
 /// used for control or communication.
 pub fn spawn_dummy_app_server<S>(
+    spawner: S,
+) -> (
+    mpsc::Sender<FunderOutgoingControl<u32>>,
+    mpsc::Receiver<FunderIncomingControl<u32>>,
+    mpsc::Sender<IndexClientToAppServer<u32>>,
+    mpsc::Receiver<AppServerToIndexClient<u32>>,
+    mpsc::Sender<IncomingAppConnection<u32>>,
+    NodeReport<u32>,
+)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    spawn_dummy_app_server_with_stats(spawner, AppServerStats::new())
+}
+
+/// Same as `spawn_dummy_app_server`, but allows the caller to observe
+/// the stats of the spawned app server loop.
+pub fn spawn_dummy_app_server_with_stats<S>(
+    spawner: S,
+    stats: AppServerStats,
+) -> (
+    mpsc::Sender<FunderOutgoingControl<u32>>,
+    mpsc::Receiver<FunderIncomingControl<u32>>,
+    mpsc::Sender<IndexClientToAppServer<u32>>,
+    mpsc::Receiver<AppServerToIndexClient<u32>>,
+    mpsc::Sender<IncomingAppConnection<u32>>,
+    NodeReport<u32>,
+)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    // The app server will never receive time ticks:
+    let (_tick_sender, timer_stream) = mpsc::channel(0);
+    spawn_dummy_app_server_with_timer(spawner, stats, timer_stream)
+}
+
+/// Same as `spawn_dummy_app_server_with_stats`, but time ticks are delivered to the spawned
+/// app server loop through `timer_stream`.
+pub fn spawn_dummy_app_server_with_timer<S>(
     mut spawner: S,
+    stats: AppServerStats,
+    timer_stream: mpsc::Receiver<TimerTick>,
 ) -> (
     mpsc::Sender<FunderOutgoingControl<u32>>,
     mpsc::Receiver<FunderIncomingControl<u32>>,
@@ -64,8 +115,12 @@ where
             .collect(),
         friends: ImHashMap::new(),
         num_open_invoices: 0,
+        invoices_progress: ImHashMap::new(),
         num_payments: 0,
         num_open_transactions: 0,
+        total_frozen_credits: (0, 0),
+        max_friends: 0x100,
+        max_node_relays: 0x10,
     };
 
     let server100 = NamedIndexServerAddress {
@@ -83,6 +138,7 @@ where
     let index_client_report = IndexClientReport {
         index_servers: vec![server100, server101],
         opt_connected_server: Some(PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])),
+        circuit_states: HashMap::new(),
     };
 
     let initial_node_report = NodeReport {
@@ -96,7 +152,10 @@ where
         from_index_client,
         to_index_client,
         incoming_connections,
+        timer_stream,
         initial_node_report.clone(),
+        stats,
+        DummyRandom::new(&[0xaa]),
         spawner.clone(),
     )
     .map_err(|e| error!("app_server_loop() error: {:?}", e))
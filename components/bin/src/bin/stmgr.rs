@@ -14,6 +14,8 @@
 #[macro_use]
 extern crate log;
 
+use std::process;
+
 use structopt::StructOpt;
 
 use bin::stmgrlib::{stmgr, StMgrCmd, StmError};
@@ -28,5 +30,6 @@ fn run() -> Result<(), StmError> {
 fn main() {
     if let Err(e) = run() {
         error!("run() error: {:?}", e);
+        process::exit(1);
     }
 }
@@ -12,6 +12,12 @@
     clippy::new_without_default
 )]
 
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate serde_derive;
+
 pub mod stindexlib;
 pub mod stmgrlib;
 pub mod stnodelib;
@@ -20,7 +20,7 @@ use index_server::{net_index_server, NetIndexServerError};
 use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
 use timer::create_timer;
 
-use net::{NetConnector, TcpListener};
+use net::{socks5_proxy_from_env, NetConnector, TcpListener};
 
 use proto::file::identity::load_identity_from_file;
 use proto::file::index_server::{load_trusted_servers, IndexServerDirectoryError};
@@ -118,8 +118,12 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let (_config_sender, incoming_server_raw_conns) = server_tcp_listener.listen(lserver);
 
     // A tcp connector, Used to connect to remote servers:
-    let raw_server_net_connector =
-        NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, thread_pool.clone());
+    let raw_server_net_connector = NetConnector::new(
+        MAX_FRAME_LENGTH,
+        resolve_thread_pool,
+        thread_pool.clone(),
+        socks5_proxy_from_env(),
+    );
 
     let rng = system_random();
 
@@ -1,20 +1,34 @@
 use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 use structopt::StructOpt;
 
 use crypto::crypto_rand::system_random;
-use crypto::identity::{generate_pkcs8_key_pair, Identity};
+use crypto::identity::{generate_pkcs8_key_pair, Identity, PublicKey, PUBLIC_KEY_BECH32_HRP};
+use crypto::invoice_id::INVOICE_ID_BECH32_HRP;
 
 use proto::app_server::messages::{AppPermissions, RelayAddress};
+use proto::consts::{MAX_FRIENDS, MAX_NODE_RELAYS};
+use proto::funder::messages::Receipt;
+use proto::funder::receipt_url::ParseError;
 use proto::index_server::messages::IndexServerAddress;
+use proto::json_serialize::{JsonDeserializer, JsonSerializeError, JsonSerializer};
 use proto::net::messages::{NetAddress, NetAddressError};
 use proto::node::types::NodeAddress;
+use proto::report::messages::ChannelStatusReport;
 
-use database::file_db::FileDb;
-use node::NodeState;
+use database::file_db::{FileDb, FileDbError};
+use database::AtomicDb;
+use funder::graph::friend_graph_edges;
+use funder::report::create_initial_report;
+use funder::{FriendMutation, FunderMutation, FunderState, FunderStateSnapshot};
+use node::{NodeMutation, NodeState};
 
-use proto::file::app::{store_trusted_app_to_file, TrustedApp};
+use proto::file::app::{load_trusted_app_from_file, store_trusted_app_to_file, TrustedApp};
 use proto::file::identity::{load_identity_from_file, store_raw_identity_to_file};
 use proto::file::index_server::store_index_server_to_file;
 use proto::file::node::store_node_to_file;
@@ -64,6 +78,22 @@ pub struct AppTicketCmd {
     /// Permission to change configuration
     #[structopt(long = "pconfig")]
     pub pconfig: bool,
+    /// Maximum amount of requests per second the application may send
+    #[structopt(long = "max-rps")]
+    pub max_requests_per_second: Option<u32>,
+    /// Amount of seconds until the ticket expires (Never expires by default)
+    #[structopt(long = "expires-in")]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RenewTicketCmd {
+    /// Application ticket file path
+    #[structopt(parse(from_os_str), short = "t", long = "ticket")]
+    pub ticket: PathBuf,
+    /// Amount of seconds (from now) until the ticket expires
+    #[structopt(long = "expires-in")]
+    pub expires_in: u64,
 }
 
 #[derive(Debug, StructOpt)]
@@ -74,7 +104,7 @@ pub struct RelayTicketCmd {
     /// Relay ticket output file path
     #[structopt(parse(from_os_str), short = "o", long = "output")]
     pub output: PathBuf,
-    /// Public address of the relay
+    /// Public address of the relay (May be a Tor hidden service address: `<name>.onion:<port>`)
     #[structopt(short = "a", long = "address")]
     pub address: String,
 }
@@ -105,6 +135,109 @@ pub struct NodeTicketCmd {
     pub address: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct ExportFriendGraphCmd {
+    /// Node database file path
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Graph output file path (Graphviz dot format)
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SetFriendMaxDebtCmd {
+    /// Node database file path
+    #[structopt(parse(from_os_str), long = "db")]
+    pub db_path: PathBuf,
+    /// Friend's public key (bech32 or hex)
+    #[structopt(long = "friend-key")]
+    pub friend_public_key: String,
+    /// Maximum debt the friend is allowed to have
+    #[structopt(long = "max-debt")]
+    pub max_debt: u128,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SendFunderSnapshotCmd {
+    /// Node database file path
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Unix domain socket path of the receiving process
+    #[structopt(parse(from_os_str), short = "s", long = "socket")]
+    pub socket: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RecvFunderSnapshotCmd {
+    /// Node database file path (Everything except the funder state is taken from this database)
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Unix domain socket path to listen on
+    #[structopt(parse(from_os_str), short = "s", long = "socket")]
+    pub socket: PathBuf,
+    /// Database output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportDbCmd {
+    /// Node database file path (The node may be running)
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Output file path (JSON)
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportFunderStateCmd {
+    /// Node database file path (The node may be running)
+    #[structopt(parse(from_os_str), long = "db")]
+    pub db_path: PathBuf,
+    /// Output file path (JSON)
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportDbCmd {
+    /// Input file path (JSON, as created by export-db)
+    #[structopt(parse(from_os_str), short = "i", long = "input")]
+    pub input: PathBuf,
+    /// Database output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyNodeDbCmd {
+    /// Node database file path (The node may be running)
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ListNodeDbCmd {
+    /// Node database file path (The node may be running)
+    #[structopt(parse(from_os_str), long = "db")]
+    pub db_path: PathBuf,
+    /// Print the output as JSON
+    #[structopt(long = "json")]
+    pub json: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyReceiptCmd {
+    /// Receipt URL as shared by the seller, or a path to a file containing it
+    #[structopt(short = "r", long = "receipt")]
+    pub receipt: String,
+    /// Seller's public key (bech32 or hex)
+    #[structopt(long = "seller-key")]
+    pub seller_key: String,
+}
+
 /// stmgr: offST ManaGeR
 /// A util for managing Offst entities and files
 #[derive(Debug, StructOpt)]
@@ -119,6 +252,9 @@ pub enum StMgrCmd {
     /// Create an application ticket
     #[structopt(name = "app-ticket")]
     AppTicket(AppTicketCmd),
+    /// Extend the expiry of an application ticket
+    #[structopt(name = "renew-ticket")]
+    RenewTicket(RenewTicketCmd),
     /// Create a relay ticket
     #[structopt(name = "relay-ticket")]
     RelayTicket(RelayTicketCmd),
@@ -128,6 +264,36 @@ pub enum StMgrCmd {
     /// Create a node server ticket
     #[structopt(name = "node-ticket")]
     NodeTicket(NodeTicketCmd),
+    /// Export the friends graph of a node database
+    #[structopt(name = "export-friend-graph")]
+    ExportFriendGraph(ExportFriendGraphCmd),
+    /// Set the max debt of a friend in a node database (Node must not be running)
+    #[structopt(name = "set-friend-max-debt")]
+    SetFriendMaxDebt(SetFriendMaxDebtCmd),
+    /// Send the funder state of a node database to another process
+    #[structopt(name = "send-funder-snapshot")]
+    SendFunderSnapshot(SendFunderSnapshotCmd),
+    /// Receive a funder state from another process, and save it into a new node database
+    #[structopt(name = "recv-funder-snapshot")]
+    RecvFunderSnapshot(RecvFunderSnapshotCmd),
+    /// Export the state of a node database into a JSON file (Node may be running)
+    #[structopt(name = "export-db")]
+    ExportDb(ExportDbCmd),
+    /// Export the funder state of a node database into a JSON file (Node may be running)
+    #[structopt(name = "export-funder-state")]
+    ExportFunderState(ExportFunderStateCmd),
+    /// Create a new node database from a JSON file created by export-db
+    #[structopt(name = "import-db")]
+    ImportDb(ImportDbCmd),
+    /// Verify the integrity of a node database, and print statistics about it
+    #[structopt(name = "verify-node-db")]
+    VerifyNodeDb(VerifyNodeDbCmd),
+    /// Print the contents of a node database
+    #[structopt(name = "list-node-db")]
+    ListNodeDb(ListNodeDbCmd),
+    /// Verify a receipt URL against the seller's public key
+    #[structopt(name = "verify-receipt")]
+    VerifyReceipt(VerifyReceiptCmd),
 }
 
 fn init_node_db(InitNodeDbCmd { idfile, output }: InitNodeDbCmd) -> Result<(), InitNodeDbError> {
@@ -192,6 +358,8 @@ fn app_ticket(
         pbuyer,
         pseller,
         pconfig,
+        max_requests_per_second,
+        expires_in,
     }: AppTicketCmd,
 ) -> Result<(), AppTicketError> {
     // Obtain app's public key:
@@ -210,16 +378,36 @@ fn app_ticket(
         buyer: pbuyer,
         seller: pseller,
         config: pconfig,
+        max_requests_per_second,
     };
 
     // Store app ticket to file:
     let trusted_app = TrustedApp {
         public_key,
         permissions,
+        expires_at: expires_in.map(|secs| SystemTime::now() + Duration::from_secs(secs)),
     };
     store_trusted_app_to_file(&trusted_app, &output).map_err(|_| AppTicketError::StoreAppFileError)
 }
 
+#[derive(Debug)]
+pub enum RenewTicketError {
+    LoadAppFileError,
+    StoreAppFileError,
+}
+
+/// Set the expiry of an existing app ticket to `expires_in` seconds from now.
+/// Note that the ticket file is overwritten.
+fn renew_ticket(
+    RenewTicketCmd { ticket, expires_in }: RenewTicketCmd,
+) -> Result<(), RenewTicketError> {
+    let mut trusted_app =
+        load_trusted_app_from_file(&ticket).map_err(|_| RenewTicketError::LoadAppFileError)?;
+    trusted_app.expires_at = Some(SystemTime::now() + Duration::from_secs(expires_in));
+    store_trusted_app_to_file(&trusted_app, &ticket)
+        .map_err(|_| RenewTicketError::StoreAppFileError)
+}
+
 #[derive(Debug)]
 pub enum RelayTicketError {
     OutputAlreadyExists,
@@ -344,15 +532,495 @@ fn node_ticket(
     store_node_to_file(&node_address, &output).map_err(|_| NodeTicketError::StoreNodeFileError)
 }
 
+#[derive(Debug)]
+pub enum ExportFriendGraphError {
+    OutputAlreadyExists,
+    LoadDbError,
+    WriteError(io::Error),
+}
+
+impl From<io::Error> for ExportFriendGraphError {
+    fn from(e: io::Error) -> Self {
+        ExportFriendGraphError::WriteError(e)
+    }
+}
+
+/// Export the graph of friend channels of a node database.
+/// The output is written in Graphviz dot format. Each edge points in the direction of the last
+/// sent move token. Inconsistent channels are drawn as dashed edges.
+fn export_friend_graph(
+    ExportFriendGraphCmd { database, output }: ExportFriendGraphCmd,
+) -> Result<(), ExportFriendGraphError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(ExportFriendGraphError::OutputAlreadyExists);
+    }
+
+    let file_db = FileDb::<NodeState<NetAddress>>::load(database)
+        .map_err(|_| ExportFriendGraphError::LoadDbError)?;
+    let node_state = file_db.get_state();
+
+    let mut file = File::create(output)?;
+    writeln!(file, "digraph friends {{")?;
+    for edge in friend_graph_edges(&node_state.funder_state) {
+        let style = if edge.is_consistent {
+            "solid"
+        } else {
+            "dashed"
+        };
+        writeln!(
+            file,
+            "    \"{}\" -> \"{}\" [label=\"{}\", style={}];",
+            edge.from_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            edge.to_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            edge.balance,
+            style
+        )?;
+    }
+    writeln!(file, "}}")?;
+
+    Ok(())
+}
+
+/// Parse a public key given on the command line.
+/// Both the bech32 and the (older) hex representations are accepted.
+fn parse_public_key(public_key_str: &str) -> Option<PublicKey> {
+    PublicKey::from_bech32(public_key_str, PUBLIC_KEY_BECH32_HRP)
+        .ok()
+        .or_else(|| PublicKey::from_hex(public_key_str).ok())
+}
+
+#[derive(Debug)]
+pub enum SetFriendMaxDebtError {
+    InvalidFriendPublicKey,
+    LoadDbError,
+    FriendDoesNotExist,
+    MutateDbError,
+}
+
+/// Set the maximum debt of a friend, directly in the node database.
+/// This should only be done while the node is not running.
+///
+/// We only update the wanted remote max debt. The remote side is notified of the change
+/// (through the token channel) the next time the node is started. Changing the token channel's
+/// remote max debt directly would make it disagree with the remote side.
+fn set_friend_max_debt(
+    SetFriendMaxDebtCmd {
+        db_path,
+        friend_public_key,
+        max_debt,
+    }: SetFriendMaxDebtCmd,
+) -> Result<(), SetFriendMaxDebtError> {
+    let friend_public_key = parse_public_key(&friend_public_key)
+        .ok_or(SetFriendMaxDebtError::InvalidFriendPublicKey)?;
+
+    let mut file_db = FileDb::<NodeState<NetAddress>>::load(db_path)
+        .map_err(|_| SetFriendMaxDebtError::LoadDbError)?;
+
+    if !file_db
+        .get_state()
+        .funder_state
+        .friends
+        .contains_key(&friend_public_key)
+    {
+        return Err(SetFriendMaxDebtError::FriendDoesNotExist);
+    }
+
+    let friend_mutation = FriendMutation::SetWantedRemoteMaxDebt(max_debt);
+    let funder_mutation = FunderMutation::FriendMutation((friend_public_key, friend_mutation));
+    file_db
+        .mutate_db(&[NodeMutation::Funder(funder_mutation)])
+        .map_err(|_| SetFriendMaxDebtError::MutateDbError)
+}
+
+#[derive(Debug)]
+pub enum SendFunderSnapshotError {
+    LoadDbError,
+    ConnectError(io::Error),
+    SerializeError(bincode::Error),
+}
+
+/// Send the funder state of a node database, as a snapshot, over a unix domain socket.
+/// Used to hand off the state to a new process during an upgrade.
+fn send_funder_snapshot(
+    SendFunderSnapshotCmd { database, socket }: SendFunderSnapshotCmd,
+) -> Result<(), SendFunderSnapshotError> {
+    let file_db = FileDb::<NodeState<NetAddress>>::load(database)
+        .map_err(|_| SendFunderSnapshotError::LoadDbError)?;
+    let snapshot = file_db.get_state().funder_state.clone().into_snapshot();
+
+    let mut stream = UnixStream::connect(socket).map_err(SendFunderSnapshotError::ConnectError)?;
+    bincode::serialize_into(&mut stream, &snapshot).map_err(SendFunderSnapshotError::SerializeError)
+}
+
+#[derive(Debug)]
+pub enum RecvFunderSnapshotError {
+    OutputAlreadyExists,
+    LoadDbError,
+    ListenError(io::Error),
+    AcceptError(io::Error),
+    DeserializeError(bincode::Error),
+    InvalidSnapshot,
+    LocalPublicKeyMismatch,
+    FileDbError,
+}
+
+/// Accept a single funder state snapshot on `listener`, and save it into a new node database at
+/// `output`. All the other parts of the node state are taken from `database`.
+fn recv_funder_snapshot_from(
+    listener: &UnixListener,
+    database: PathBuf,
+    output: PathBuf,
+) -> Result<(), RecvFunderSnapshotError> {
+    let file_db = FileDb::<NodeState<NetAddress>>::load(database)
+        .map_err(|_| RecvFunderSnapshotError::LoadDbError)?;
+    let mut node_state = file_db.get_state().clone();
+
+    let (stream, _) = listener
+        .accept()
+        .map_err(RecvFunderSnapshotError::AcceptError)?;
+    let snapshot: FunderStateSnapshot<NetAddress> =
+        bincode::deserialize_from(stream).map_err(RecvFunderSnapshotError::DeserializeError)?;
+    let funder_state = FunderState::from_snapshot(snapshot)
+        .map_err(|_| RecvFunderSnapshotError::InvalidSnapshot)?;
+
+    // Make sure that we don't mix the states of two different nodes:
+    if funder_state.local_public_key != node_state.funder_state.local_public_key {
+        return Err(RecvFunderSnapshotError::LocalPublicKeyMismatch);
+    }
+    node_state.funder_state = funder_state;
+
+    let _ = FileDb::create(output, node_state).map_err(|_| RecvFunderSnapshotError::FileDbError)?;
+    Ok(())
+}
+
+/// Receive a funder state snapshot sent by `send-funder-snapshot`.
+fn recv_funder_snapshot(
+    RecvFunderSnapshotCmd {
+        database,
+        socket,
+        output,
+    }: RecvFunderSnapshotCmd,
+) -> Result<(), RecvFunderSnapshotError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(RecvFunderSnapshotError::OutputAlreadyExists);
+    }
+
+    let listener = UnixListener::bind(&socket).map_err(RecvFunderSnapshotError::ListenError)?;
+    let res = recv_funder_snapshot_from(&listener, database, output);
+    let _ = fs::remove_file(&socket);
+    res
+}
+
+#[derive(Debug)]
+pub enum ExportDbError {
+    OutputAlreadyExists,
+    LoadDbError,
+    WriteError(io::Error),
+}
+
+/// Export a snapshot of the state of a node database as human readable JSON.
+/// The database is not modified, so this is safe to use while the node is running.
+fn export_db(ExportDbCmd { database, output }: ExportDbCmd) -> Result<(), ExportDbError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(ExportDbError::OutputAlreadyExists);
+    }
+
+    let node_state = FileDb::<NodeState<NetAddress>>::snapshot(&database)
+        .map_err(|_| ExportDbError::LoadDbError)?;
+
+    fs::write(&output, node_state.serialize_json_pretty()).map_err(ExportDbError::WriteError)
+}
+
+#[derive(Debug)]
+pub enum ExportFunderStateError {
+    OutputAlreadyExists,
+    LoadDbError,
+    WriteError(io::Error),
+}
+
+/// Export a snapshot of the funder state of a node database as human readable JSON.
+/// Public keys are encoded using bech32.
+fn export_funder_state(
+    ExportFunderStateCmd { db_path, output }: ExportFunderStateCmd,
+) -> Result<(), ExportFunderStateError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(ExportFunderStateError::OutputAlreadyExists);
+    }
+
+    let node_state = FileDb::<NodeState<NetAddress>>::snapshot(&db_path)
+        .map_err(|_| ExportFunderStateError::LoadDbError)?;
+
+    let json = node_state.funder_state.to_json();
+    fs::write(&output, json.serialize_json_pretty()).map_err(ExportFunderStateError::WriteError)
+}
+
+#[derive(Debug)]
+pub enum ImportDbError {
+    OutputAlreadyExists,
+    ReadError(io::Error),
+    DeserializeError(JsonSerializeError),
+    FileDbError,
+}
+
+/// Create a new node database from a JSON file created by `export-db`
+fn import_db(ImportDbCmd { input, output }: ImportDbCmd) -> Result<(), ImportDbError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(ImportDbError::OutputAlreadyExists);
+    }
+
+    let data = fs::read(&input).map_err(ImportDbError::ReadError)?;
+    let node_state = NodeState::<NetAddress>::deserialize_json(&data)
+        .map_err(ImportDbError::DeserializeError)?;
+
+    FileDb::restore(&output, &node_state).map_err(|_| ImportDbError::FileDbError)
+}
+
+#[derive(Debug)]
+pub enum VerifyNodeDbError {
+    Corrupted,
+    /// The database was written by an incompatible version of offst
+    StateVersionMismatch(u32),
+    LoadDbError,
+    WriteError,
+}
+
+/// Verify the integrity of a node database (Including its write ahead log), and print a few
+/// statistics about its contents. The database is not modified.
+fn verify_node_db(
+    VerifyNodeDbCmd { database }: VerifyNodeDbCmd,
+    writer: &mut impl Write,
+) -> Result<(), VerifyNodeDbError> {
+    let node_state = FileDb::<NodeState<NetAddress>>::snapshot(&database).map_err(|e| match e {
+        FileDbError::Corrupted => VerifyNodeDbError::Corrupted,
+        FileDbError::StateVersionMismatch(version) => {
+            VerifyNodeDbError::StateVersionMismatch(version)
+        }
+        _ => VerifyNodeDbError::LoadDbError,
+    })?;
+
+    let funder_state = &node_state.funder_state;
+    writeln!(
+        writer,
+        "Database is valid! friends: {}, open invoices: {}, open transactions: {}",
+        funder_state.friends.len(),
+        funder_state.open_invoices.len(),
+        funder_state.open_transactions.len()
+    )
+    .map_err(|_| VerifyNodeDbError::WriteError)
+}
+
+#[derive(Debug)]
+pub enum ListNodeDbError {
+    LoadDbError,
+    WriteError,
+}
+
+/// A relay or an index server, as printed by `list-node-db`
+#[derive(Debug, Serialize)]
+struct NamedServerListing {
+    name: String,
+    public_key: String,
+    address: String,
+}
+
+/// A friend, as printed by `list-node-db`
+#[derive(Debug, Serialize)]
+struct FriendListing {
+    name: String,
+    public_key: String,
+    /// Balance, from the point of view of the local node
+    balance: i128,
+    note: String,
+}
+
+/// Contents of a node database, as printed by `list-node-db`.
+/// All public keys are encoded using bech32.
+#[derive(Debug, Serialize)]
+struct NodeDbListing {
+    local_public_key: String,
+    relays: Vec<NamedServerListing>,
+    friends: Vec<FriendListing>,
+    index_servers: Vec<NamedServerListing>,
+}
+
+fn node_db_listing(node_state: &NodeState<NetAddress>) -> NodeDbListing {
+    let funder_report =
+        create_initial_report(&node_state.funder_state, MAX_FRIENDS, MAX_NODE_RELAYS);
+
+    let relays = funder_report
+        .relays
+        .iter()
+        .map(|relay| NamedServerListing {
+            name: relay.name.clone(),
+            public_key: relay.public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            address: relay.address.as_str().to_owned(),
+        })
+        .collect();
+
+    let mut friends = funder_report
+        .friends
+        .iter()
+        .map(|(friend_public_key, friend_report)| FriendListing {
+            name: friend_report.name.clone(),
+            public_key: friend_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            balance: match &friend_report.channel_status {
+                ChannelStatusReport::Consistent(tc_report) => tc_report.balance.balance,
+                ChannelStatusReport::Inconsistent(channel_inconsistent_report) => {
+                    channel_inconsistent_report.local_reset_terms_balance
+                }
+            },
+            note: friend_report.note.clone(),
+        })
+        .collect::<Vec<_>>();
+    // Friends are kept in a map, sort them to get a stable output:
+    friends.sort_by(|a, b| (&a.name, &a.public_key).cmp(&(&b.name, &b.public_key)));
+
+    let index_servers = node_state
+        .index_client_config
+        .index_servers
+        .iter()
+        .map(|index_server| NamedServerListing {
+            name: index_server.name.clone(),
+            public_key: index_server.public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            address: index_server.address.as_str().to_owned(),
+        })
+        .collect();
+
+    NodeDbListing {
+        local_public_key: funder_report
+            .local_public_key
+            .to_bech32(PUBLIC_KEY_BECH32_HRP),
+        relays,
+        friends,
+        index_servers,
+    }
+}
+
+fn write_node_db_listing(
+    listing: &NodeDbListing,
+    writer: &mut impl Write,
+) -> Result<(), io::Error> {
+    writeln!(writer, "Local public key: {}", listing.local_public_key)?;
+    writeln!(writer, "Relays:")?;
+    for relay in &listing.relays {
+        writeln!(
+            writer,
+            "  {} {} {}",
+            relay.name, relay.public_key, relay.address
+        )?;
+    }
+    writeln!(writer, "Friends:")?;
+    for friend in &listing.friends {
+        write!(
+            writer,
+            "  {} {} balance: {}",
+            friend.name, friend.public_key, friend.balance
+        )?;
+        if !friend.note.is_empty() {
+            write!(writer, " note: {}", friend.note)?;
+        }
+        writeln!(writer)?;
+    }
+    writeln!(writer, "Index servers:")?;
+    for index_server in &listing.index_servers {
+        writeln!(
+            writer,
+            "  {} {} {}",
+            index_server.name, index_server.public_key, index_server.address
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the contents of a node database, without starting the node.
+/// The database is not modified.
+fn list_node_db(
+    ListNodeDbCmd { db_path, json }: ListNodeDbCmd,
+    writer: &mut impl Write,
+) -> Result<(), ListNodeDbError> {
+    let node_state = FileDb::<NodeState<NetAddress>>::snapshot(&db_path)
+        .map_err(|_| ListNodeDbError::LoadDbError)?;
+    let listing = node_db_listing(&node_state);
+
+    if json {
+        writer
+            .write_all(&listing.serialize_json_pretty())
+            .and_then(|_| writeln!(writer))
+            .map_err(|_| ListNodeDbError::WriteError)
+    } else {
+        write_node_db_listing(&listing, writer).map_err(|_| ListNodeDbError::WriteError)
+    }
+}
+
+#[derive(Debug)]
+pub enum VerifyReceiptError {
+    ReadReceiptFileError,
+    InvalidSellerPublicKey,
+    ParseReceiptUrlError(ParseError),
+    InvalidReceipt,
+    WriteError,
+}
+
+/// Verify the signature of a receipt shared as a URL (See `Receipt::to_url`).
+/// A valid signature proves that the seller has received the payment.
+fn verify_receipt(
+    VerifyReceiptCmd {
+        receipt,
+        seller_key,
+    }: VerifyReceiptCmd,
+    writer: &mut impl Write,
+) -> Result<(), VerifyReceiptError> {
+    let seller_public_key =
+        parse_public_key(&seller_key).ok_or(VerifyReceiptError::InvalidSellerPublicKey)?;
+
+    let receipt_path = Path::new(&receipt);
+    let receipt_url = if receipt_path.is_file() {
+        fs::read_to_string(receipt_path).map_err(|_| VerifyReceiptError::ReadReceiptFileError)?
+    } else {
+        receipt
+    };
+    let receipt =
+        Receipt::from_url(receipt_url.trim()).map_err(VerifyReceiptError::ParseReceiptUrlError)?;
+
+    if !receipt.verify(&seller_public_key) {
+        return Err(VerifyReceiptError::InvalidReceipt);
+    }
+
+    writeln!(
+        writer,
+        "Receipt is valid! invoice_id: {}, dest_payment: {}, total_dest_payment: {}",
+        receipt.invoice_id.to_bech32(INVOICE_ID_BECH32_HRP),
+        receipt.dest_payment,
+        receipt.total_dest_payment
+    )
+    .map_err(|_| VerifyReceiptError::WriteError)
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum StmError {
     InitNodeDbError(InitNodeDbError),
     GenIdentityError(GenIdentityError),
     AppTicketError(AppTicketError),
+    RenewTicketError(RenewTicketError),
     RelayTicketError(RelayTicketError),
     IndexTicketError(IndexTicketError),
     NodeTicketError(NodeTicketError),
+    ExportFriendGraphError(ExportFriendGraphError),
+    SetFriendMaxDebtError(SetFriendMaxDebtError),
+    SendFunderSnapshotError(SendFunderSnapshotError),
+    RecvFunderSnapshotError(RecvFunderSnapshotError),
+    ExportDbError(ExportDbError),
+    ExportFunderStateError(ExportFunderStateError),
+    ImportDbError(ImportDbError),
+    VerifyNodeDbError(VerifyNodeDbError),
+    ListNodeDbError(ListNodeDbError),
+    VerifyReceiptError(VerifyReceiptError),
 }
 
 impl From<InitNodeDbError> for StmError {
@@ -373,6 +1041,12 @@ impl From<AppTicketError> for StmError {
     }
 }
 
+impl From<RenewTicketError> for StmError {
+    fn from(e: RenewTicketError) -> Self {
+        StmError::RenewTicketError(e)
+    }
+}
+
 impl From<RelayTicketError> for StmError {
     fn from(e: RelayTicketError) -> Self {
         StmError::RelayTicketError(e)
@@ -391,15 +1065,584 @@ impl From<NodeTicketError> for StmError {
     }
 }
 
+impl From<ExportFriendGraphError> for StmError {
+    fn from(e: ExportFriendGraphError) -> Self {
+        StmError::ExportFriendGraphError(e)
+    }
+}
+
+impl From<SetFriendMaxDebtError> for StmError {
+    fn from(e: SetFriendMaxDebtError) -> Self {
+        StmError::SetFriendMaxDebtError(e)
+    }
+}
+
+impl From<SendFunderSnapshotError> for StmError {
+    fn from(e: SendFunderSnapshotError) -> Self {
+        StmError::SendFunderSnapshotError(e)
+    }
+}
+
+impl From<RecvFunderSnapshotError> for StmError {
+    fn from(e: RecvFunderSnapshotError) -> Self {
+        StmError::RecvFunderSnapshotError(e)
+    }
+}
+
+impl From<ExportDbError> for StmError {
+    fn from(e: ExportDbError) -> Self {
+        StmError::ExportDbError(e)
+    }
+}
+
+impl From<ExportFunderStateError> for StmError {
+    fn from(e: ExportFunderStateError) -> Self {
+        StmError::ExportFunderStateError(e)
+    }
+}
+
+impl From<ImportDbError> for StmError {
+    fn from(e: ImportDbError) -> Self {
+        StmError::ImportDbError(e)
+    }
+}
+
+impl From<VerifyNodeDbError> for StmError {
+    fn from(e: VerifyNodeDbError) -> Self {
+        StmError::VerifyNodeDbError(e)
+    }
+}
+
+impl From<ListNodeDbError> for StmError {
+    fn from(e: ListNodeDbError) -> Self {
+        StmError::ListNodeDbError(e)
+    }
+}
+
+impl From<VerifyReceiptError> for StmError {
+    fn from(e: VerifyReceiptError) -> Self {
+        StmError::VerifyReceiptError(e)
+    }
+}
+
 pub fn stmgr(st_mgr_cmd: StMgrCmd) -> Result<(), StmError> {
     match st_mgr_cmd {
         StMgrCmd::InitNodeDb(i) => init_node_db(i)?,
         StMgrCmd::GenIdent(i) => gen_identity(i)?,
         StMgrCmd::AppTicket(i) => app_ticket(i)?,
+        StMgrCmd::RenewTicket(i) => renew_ticket(i)?,
         StMgrCmd::RelayTicket(i) => relay_ticket(i)?,
         StMgrCmd::IndexTicket(i) => index_ticket(i)?,
         StMgrCmd::NodeTicket(i) => node_ticket(i)?,
+        StMgrCmd::ExportFriendGraph(i) => export_friend_graph(i)?,
+        StMgrCmd::SetFriendMaxDebt(i) => set_friend_max_debt(i)?,
+        StMgrCmd::SendFunderSnapshot(i) => send_funder_snapshot(i)?,
+        StMgrCmd::RecvFunderSnapshot(i) => recv_funder_snapshot(i)?,
+        StMgrCmd::ExportDb(i) => export_db(i)?,
+        StMgrCmd::ExportFunderState(i) => export_funder_state(i)?,
+        StMgrCmd::ImportDb(i) => import_db(i)?,
+        StMgrCmd::VerifyNodeDb(i) => verify_node_db(i, &mut io::stdout())?,
+        StMgrCmd::ListNodeDb(i) => list_node_db(i, &mut io::stdout())?,
+        StMgrCmd::VerifyReceipt(i) => verify_receipt(i, &mut io::stdout())?,
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tempfile::tempdir;
+
+    use crypto::hash::{sha_512_256, HashResult, HASH_RESULT_LEN};
+    use crypto::hash_lock::{PlainLock, PLAIN_LOCK_LEN};
+    use crypto::identity::{Signature, SoftwareEd25519Identity, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::test_utils::DummyRandom;
+    use funder::report::create_initial_report;
+    use proto::file::relay::load_relay_from_file;
+    use proto::funder::messages::AddFriend;
+    use proto::funder::signature_buff::FUNDS_RESPONSE_PREFIX;
+
+    /// Create a node database with a single friend
+    fn create_fixture_db(db_path: &Path, friend_public_key: &PublicKey) {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let initial_state = NodeState::<NetAddress>::new(local_public_key);
+        let mut file_db = FileDb::create(db_path.to_path_buf(), initial_state).unwrap();
+
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: Vec::new(),
+            name: "friend".into(),
+            note: "Met at a conference".into(),
+            balance: 0,
+        };
+        file_db
+            .mutate_db(&[NodeMutation::Funder(FunderMutation::AddFriend(add_friend))])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_app_ticket_expiry() {
+        let dir = tempdir().unwrap();
+        let idfile = dir.path().join("app.ident");
+        let ticket = dir.path().join("app.ticket");
+
+        gen_identity(GenIdentCmd {
+            output: idfile.clone(),
+        })
+        .unwrap();
+        app_ticket(AppTicketCmd {
+            idfile,
+            output: ticket.clone(),
+            proutes: true,
+            pbuyer: false,
+            pseller: false,
+            pconfig: false,
+            max_requests_per_second: None,
+            expires_in: Some(0),
+        })
+        .unwrap();
+
+        let trusted_app = load_trusted_app_from_file(&ticket).unwrap();
+        assert!(trusted_app.is_expired(SystemTime::now()));
+
+        renew_ticket(RenewTicketCmd {
+            ticket: ticket.clone(),
+            expires_in: 3600,
+        })
+        .unwrap();
+
+        let renewed_app = load_trusted_app_from_file(&ticket).unwrap();
+        assert!(!renewed_app.is_expired(SystemTime::now()));
+        assert_eq!(renewed_app.public_key, trusted_app.public_key);
+        assert_eq!(renewed_app.permissions, trusted_app.permissions);
+    }
+
+    #[test]
+    fn test_relay_ticket_onion() {
+        let dir = tempdir().unwrap();
+        let idfile = dir.path().join("relay.ident");
+        let output = dir.path().join("relay.ticket");
+
+        gen_identity(GenIdentCmd {
+            output: idfile.clone(),
+        })
+        .unwrap();
+
+        let address = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion:9735";
+        relay_ticket(RelayTicketCmd {
+            idfile: idfile.clone(),
+            output: output.clone(),
+            address: address.to_owned(),
+        })
+        .unwrap();
+
+        let relay_address = load_relay_from_file(&output).unwrap();
+        assert_eq!(relay_address.address.as_str(), address);
+        assert!(relay_address.address.is_onion());
+
+        // An onion address of invalid length:
+        match relay_ticket(RelayTicketCmd {
+            idfile,
+            output: dir.path().join("relay2.ticket"),
+            address: "abcdefgh.onion:9735".to_owned(),
+        }) {
+            Err(RelayTicketError::NetAddressError(NetAddressError::InvalidOnionAddress)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_set_friend_max_debt() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        set_friend_max_debt(SetFriendMaxDebtCmd {
+            db_path: db_path.clone(),
+            friend_public_key: friend_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            max_debt: 100,
+        })
+        .unwrap();
+
+        // The change should be saved to the database file:
+        let file_db = FileDb::<NodeState<NetAddress>>::load(db_path).unwrap();
+        let friend = file_db
+            .get_state()
+            .funder_state
+            .friends
+            .get(&friend_public_key)
+            .unwrap();
+        assert_eq!(friend.wanted_remote_max_debt, 100);
+    }
+
+    #[test]
+    fn test_set_friend_max_debt_friend_does_not_exist() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        let other_public_key = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let res = set_friend_max_debt(SetFriendMaxDebtCmd {
+            db_path: db_path.clone(),
+            friend_public_key: other_public_key.to_hex(),
+            max_debt: 100,
+        });
+        match res {
+            Err(SetFriendMaxDebtError::FriendDoesNotExist) => {}
+            _ => unreachable!(),
+        }
+
+        let res = set_friend_max_debt(SetFriendMaxDebtCmd {
+            db_path,
+            friend_public_key: "abab".into(),
+            max_debt: 100,
+        });
+        match res {
+            Err(SetFriendMaxDebtError::InvalidFriendPublicKey) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_export_import_db() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        let json_path = dir.path().join("db.json");
+        export_db(ExportDbCmd {
+            database: db_path.clone(),
+            output: json_path.clone(),
+        })
+        .unwrap();
+
+        let imported_db_path = dir.path().join("imported_db");
+        import_db(ImportDbCmd {
+            input: json_path.clone(),
+            output: imported_db_path.clone(),
+        })
+        .unwrap();
+
+        let original_state = FileDb::<NodeState<NetAddress>>::load(db_path)
+            .unwrap()
+            .get_state()
+            .clone();
+        let imported_state = FileDb::<NodeState<NetAddress>>::load(imported_db_path.clone())
+            .unwrap()
+            .get_state()
+            .clone();
+        assert_eq!(
+            imported_state.funder_state.local_public_key,
+            original_state.funder_state.local_public_key
+        );
+        let imported_friend = imported_state
+            .funder_state
+            .friends
+            .get(&friend_public_key)
+            .unwrap();
+        let original_friend = original_state
+            .funder_state
+            .friends
+            .get(&friend_public_key)
+            .unwrap();
+        assert_eq!(imported_friend.name, original_friend.name);
+        assert_eq!(
+            imported_friend.wanted_remote_max_debt,
+            original_friend.wanted_remote_max_debt
+        );
+
+        // Existing files are never overwritten:
+        assert!(import_db(ImportDbCmd {
+            input: json_path,
+            output: imported_db_path,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_export_funder_state() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        let json_path = dir.path().join("funder_state.json");
+        export_funder_state(ExportFunderStateCmd {
+            db_path: db_path.clone(),
+            output: json_path.clone(),
+        })
+        .unwrap();
+
+        let data = fs::read(&json_path).unwrap();
+        assert!(String::from_utf8(data.clone())
+            .unwrap()
+            .contains(&friend_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP)));
+
+        let json = JsonDeserializer::deserialize_json(&data).unwrap();
+        let funder_state = FunderState::<NetAddress>::from_json(json).unwrap();
+        let original_state = FileDb::<NodeState<NetAddress>>::load(db_path.clone())
+            .unwrap()
+            .get_state()
+            .clone();
+        assert_eq!(
+            funder_state.local_public_key,
+            original_state.funder_state.local_public_key
+        );
+        let friend = funder_state.friends.get(&friend_public_key).unwrap();
+        assert_eq!(friend.note, "Met at a conference");
+
+        // Existing files are never overwritten:
+        match export_funder_state(ExportFunderStateCmd {
+            db_path,
+            output: json_path,
+        }) {
+            Err(ExportFunderStateError::OutputAlreadyExists) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_verify_node_db() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        let mut output = Vec::new();
+        verify_node_db(
+            VerifyNodeDbCmd {
+                database: db_path.clone(),
+            },
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Database is valid! friends: 1, open invoices: 0, open transactions: 0\n"
+        );
+
+        // Flip a byte in the database file:
+        let mut file_buff = fs::read(&db_path).unwrap();
+        file_buff[0] ^= 0x01;
+        fs::write(&db_path, &file_buff).unwrap();
+
+        match verify_node_db(VerifyNodeDbCmd { database: db_path }, &mut Vec::new()) {
+            Err(VerifyNodeDbError::Corrupted) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_list_node_db() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        let mut output = Vec::new();
+        list_node_db(
+            ListNodeDbCmd {
+                db_path: db_path.clone(),
+                json: false,
+            },
+            &mut output,
+        )
+        .unwrap();
+        let expected = format!(
+            "Local public key: {}\nRelays:\nFriends:\n  friend {} balance: 0 note: Met at a conference\nIndex servers:\n",
+            local_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            friend_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP)
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        let mut output = Vec::new();
+        list_node_db(
+            ListNodeDbCmd {
+                db_path,
+                json: true,
+            },
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains(&format!(
+            "\"public_key\": \"{}\"",
+            friend_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP)
+        )));
+        assert!(output.contains("\"name\": \"friend\""));
+        assert!(output.contains("\"note\": \"Met at a conference\""));
+    }
+
+    #[test]
+    fn test_parse_public_key() {
+        let public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let bech32_str = public_key.to_bech32(PUBLIC_KEY_BECH32_HRP);
+        assert_eq!(parse_public_key(&bech32_str), Some(public_key.clone()));
+        assert_eq!(parse_public_key(&public_key.to_hex()), Some(public_key));
+
+        // Invalid checksum:
+        let mut corrupted = bech32_str.into_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] = if corrupted[last] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            parse_public_key(&String::from_utf8(corrupted).unwrap()),
+            None
+        );
+    }
+
+    /// Create a receipt signed by the given seller identity
+    fn create_signed_receipt(seller_identity: &impl Identity) -> Receipt {
+        let mut receipt = Receipt {
+            response_hash: HashResult::from(&[0; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[2; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[3; PLAIN_LOCK_LEN]),
+            dest_payment: 100,
+            total_dest_payment: 200,
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        };
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&sha_512_256(FUNDS_RESPONSE_PREFIX));
+        data.extend_from_slice(&receipt.response_hash);
+        data.extend_from_slice(&receipt.src_plain_lock.hash());
+        data.extend_from_slice(&receipt.dest_plain_lock.hash());
+        data.extend_from_slice(&receipt.dest_payment.to_be_bytes());
+        data.extend_from_slice(&receipt.total_dest_payment.to_be_bytes());
+        data.extend_from_slice(&receipt.invoice_id);
+        receipt.signature = seller_identity.sign(&data);
+
+        receipt
+    }
+
+    #[test]
+    fn test_verify_receipt_url() {
+        let rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        let seller_identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+        let seller_public_key = seller_identity.get_public_key();
+
+        let receipt = create_signed_receipt(&seller_identity);
+        let url = receipt.to_url("https://shop.example.com/receipt");
+
+        let mut output = Vec::new();
+        verify_receipt(
+            VerifyReceiptCmd {
+                receipt: url.clone(),
+                seller_key: seller_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP),
+            },
+            &mut output,
+        )
+        .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("Receipt is valid!"));
+
+        // A receipt is not valid for a different seller:
+        let other_public_key = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let res = verify_receipt(
+            VerifyReceiptCmd {
+                receipt: url,
+                seller_key: other_public_key.to_hex(),
+            },
+            &mut Vec::new(),
+        );
+        match res {
+            Err(VerifyReceiptError::InvalidReceipt) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_verify_receipt_files() {
+        let dir = tempdir().unwrap();
+
+        let rng = DummyRandom::new(&[2u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        let seller_identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+        let seller_public_key = seller_identity.get_public_key();
+
+        let receipt = create_signed_receipt(&seller_identity);
+        assert!(receipt.verify(&seller_public_key));
+        let receipt_path = dir.path().join("receipt.url");
+        fs::write(
+            &receipt_path,
+            format!("{}\n", receipt.to_url("https://shop.example.com/receipt")),
+        )
+        .unwrap();
+
+        let mut output = Vec::new();
+        verify_receipt(
+            VerifyReceiptCmd {
+                receipt: receipt_path.to_str().unwrap().to_owned(),
+                seller_key: seller_public_key.to_hex(),
+            },
+            &mut output,
+        )
+        .unwrap();
+        let expected = format!(
+            "Receipt is valid! invoice_id: {}, dest_payment: 100, total_dest_payment: 200\n",
+            receipt.invoice_id.to_bech32(INVOICE_ID_BECH32_HRP),
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+        // A corrupted receipt file:
+        fs::write(
+            &receipt_path,
+            "https://shop.example.com/receipt?receipt=AAAA",
+        )
+        .unwrap();
+        let res = verify_receipt(
+            VerifyReceiptCmd {
+                receipt: receipt_path.to_str().unwrap().to_owned(),
+                seller_key: seller_public_key.to_hex(),
+            },
+            &mut Vec::new(),
+        );
+        match res {
+            Err(VerifyReceiptError::ParseReceiptUrlError(ParseError::InvalidLength)) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_funder_snapshot_send_recv() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("db");
+        let socket_path = dir.path().join("socket");
+        let output_path = dir.path().join("output_db");
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        create_fixture_db(&db_path, &friend_public_key);
+
+        // Listen before starting the sending side, so that it can connect right away:
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let send_funder_snapshot_cmd = SendFunderSnapshotCmd {
+            database: db_path.clone(),
+            socket: socket_path,
+        };
+        let sender_thread = thread::spawn(move || send_funder_snapshot(send_funder_snapshot_cmd));
+
+        recv_funder_snapshot_from(&listener, db_path.clone(), output_path.clone()).unwrap();
+        sender_thread.join().unwrap().unwrap();
+
+        let orig_db = FileDb::<NodeState<NetAddress>>::load(db_path).unwrap();
+        let restored_db = FileDb::<NodeState<NetAddress>>::load(output_path).unwrap();
+        let orig_state = &orig_db.get_state().funder_state;
+        let restored_state = &restored_db.get_state().funder_state;
+
+        assert_eq!(
+            create_initial_report(restored_state, 0x100, 0x10),
+            create_initial_report(orig_state, 0x100, 0x10)
+        );
+        assert!(restored_state.friends.contains_key(&friend_public_key));
+    }
+}
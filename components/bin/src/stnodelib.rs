@@ -1,11 +1,14 @@
 use std::collections::HashMap;
 
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use futures::channel::oneshot;
 use futures::executor::ThreadPool;
 use futures::task::SpawnExt;
+use futures::{FutureExt, TryFutureExt};
 
 use structopt::StructOpt;
 
@@ -13,17 +16,20 @@ use common::conn::Listener;
 use common::int_convert::usize_to_u64;
 
 use crypto::crypto_rand::system_random;
+use crypto::sym_encrypt::SymEncryptAlgorithm;
 
 use identity::{create_identity, IdentityClient};
 use timer::create_timer;
 
-use node::{net_node, NetNodeError, NodeConfig, NodeState};
+use node::{metrics_handler, net_node, NetNodeError, NodeConfig, NodeMetrics, NodeState};
 
 use database::file_db::FileDb;
 
-use net::{NetConnector, TcpListener};
+use net::{socks5_proxy_from_env, NetConnector, RawTcpListener, TcpListener};
 use proto::consts::{
-    KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY,
+    INCOMING_CONN_WAIT_TICKS, KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_FRIENDS, MAX_NODE_RELAYS,
+    MAX_OPERATIONS_IN_BATCH, MAX_PENDING_TICKS, METRICS_MAX_CONCURRENT_CONNS,
+    METRICS_REQUEST_TIMEOUT_TICKS, NODE_DRAIN_TIMEOUT_TICKS, REKEY_MIN_TICKS, TICKS_TO_REKEY,
     TICK_MS,
 };
 use proto::net::messages::NetAddress;
@@ -40,6 +46,10 @@ const BACKOFF_TICKS: usize = 0x8;
 const MAX_CONCURRENT_ENCRYPT: usize = 0x8;
 /// The size we allocate for the user send funds requests queue.
 const MAX_PENDING_USER_REQUESTS: usize = 0x20;
+/// Total amount of frozen credits (local or remote) above which the funder emits a warning.
+const MAX_FROZEN_CREDITS_THRESHOLD: u128 = 1 << 64;
+/// Amount of payment events a completed payment is kept in the payment history.
+const HISTORY_RETENTION_EVENTS: u64 = 1 << 20;
 /// Maximum amount of concurrent index client requests:
 const MAX_OPEN_INDEX_CLIENT_REQUESTS: usize = 0x8;
 /// The amount of ticks we are willing to wait until a connection is established (Through
@@ -49,6 +59,13 @@ const CONN_TIMEOUT_TICKS: usize = 0x8;
 /// going through the incoming connection transform at the same time
 const MAX_CONCURRENT_INCOMING_APPS: usize = 0x8;
 
+/// Path of the relays access control list file, kept next to the database in `database`
+fn access_control_path(database: &Path) -> PathBuf {
+    let mut access_control_os_string = database.as_os_str().to_owned();
+    access_control_os_string.push(".acl");
+    PathBuf::from(access_control_os_string)
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum NodeBinError {
@@ -57,6 +74,7 @@ pub enum NodeBinError {
     CreateTimerError,
     LoadDbError,
     SpawnError,
+    SetSignalHandlerError,
     NetNodeError(NetNodeError),
 }
 
@@ -80,6 +98,9 @@ pub struct StNodeCmd {
     /// Directory path of trusted applications
     #[structopt(parse(from_os_str), short = "t", long = "trusted")]
     pub trusted: PathBuf,
+    /// Metrics listening address (Serves Prometheus metrics over HTTP)
+    #[structopt(short = "m", long = "metrics")]
+    pub opt_metrics_addr: Option<SocketAddr>,
 }
 
 pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
@@ -88,6 +109,7 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         laddr,
         database,
         trusted,
+        opt_metrics_addr,
     } = st_node_cmd;
 
     // Parse identity file:
@@ -125,6 +147,9 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         keepalive_ticks: KEEPALIVE_TICKS,
         /// Amount of ticks to wait until the next rekeying (Channel encryption)
         ticks_to_rekey: TICKS_TO_REKEY,
+        rekey_min_ticks: REKEY_MIN_TICKS,
+        /// Preferred symmetric encryption algorithm (Channel encryption)
+        sym_encrypt_algorithm: SymEncryptAlgorithm::default(),
         /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
         /// time.
         max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
@@ -135,17 +160,36 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
         /// The size we allocate for the user send funds requests queue.
         max_pending_user_requests: MAX_PENDING_USER_REQUESTS,
+        /// The amount of ticks we wait for a pending transaction to be collected.
+        max_pending_ticks: MAX_PENDING_TICKS,
+        /// Total amount of frozen credits (local or remote) above which we emit a warning.
+        max_frozen_credits_threshold: MAX_FROZEN_CREDITS_THRESHOLD,
+        /// Amount of payment events a completed payment is kept in the payment history.
+        history_retention_events: HISTORY_RETENTION_EVENTS,
         /// Maximum amount of concurrent index client requests:
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        max_friends: MAX_FRIENDS,
         /// Maximum amount of incoming app connections we set up at the same time
         max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
+        /// The amount of ticks an incoming connection waits for a free slot
+        incoming_conn_wait_ticks: INCOMING_CONN_WAIT_TICKS,
+        /// The amount of ticks we wait for every component to finish during a shutdown
+        drain_timeout_ticks: NODE_DRAIN_TIMEOUT_TICKS,
+        /// Address of a SOCKS5 proxy for outgoing connections (Taken from the environment)
+        opt_socks5_proxy: socks5_proxy_from_env(),
+        /// The relays access control list is kept next to the database file
+        opt_access_control_path: Some(access_control_path(&database)),
     };
 
     // A tcp connector, Used to connect to remote servers:
-    let net_connector =
-        NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, thread_pool.clone());
+    let net_connector = NetConnector::new(
+        MAX_FRAME_LENGTH,
+        resolve_thread_pool,
+        thread_pool.clone(),
+        node_config.opt_socks5_proxy.clone(),
+    );
 
     // Obtain secure cryptographic random:
     let rng = system_random();
@@ -164,11 +208,40 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
             load_trusted_apps(&trusted)
                 .ok()?
                 .into_iter()
-                .map(|trusted_app| (trusted_app.public_key, trusted_app.permissions))
+                .map(|trusted_app| (trusted_app.public_key.clone(), trusted_app))
                 .collect::<HashMap<_, _>>(),
         )
     };
 
+    let metrics = Arc::new(NodeMetrics::new());
+    if let Some(metrics_addr) = opt_metrics_addr {
+        let metrics_tcp_listener = RawTcpListener::new(thread_pool.clone());
+        let (_config_sender, incoming_metrics_conns) = metrics_tcp_listener.listen(metrics_addr);
+        let metrics_fut = metrics_handler(
+            metrics.clone(),
+            incoming_metrics_conns,
+            timer_client.clone(),
+            METRICS_REQUEST_TIMEOUT_TICKS,
+            METRICS_MAX_CONCURRENT_CONNS,
+            thread_pool.clone(),
+        )
+        .map_err(|e| error!("metrics_handler() error: {:?}", e))
+        .map(|_| ());
+        thread_pool
+            .spawn(metrics_fut)
+            .map_err(|_| NodeBinError::SpawnError)?;
+    }
+
+    // Shut down gracefully on SIGINT or SIGTERM:
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+    let opt_shutdown_sender = Mutex::new(Some(shutdown_sender));
+    ctrlc::set_handler(move || {
+        if let Some(shutdown_sender) = opt_shutdown_sender.lock().unwrap().take() {
+            let _ = shutdown_sender.send(());
+        }
+    })
+    .map_err(|_| NodeBinError::SetSignalHandlerError)?;
+
     let node_fut = net_node(
         incoming_app_raw_conns,
         net_connector,
@@ -178,6 +251,8 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         node_config,
         get_trusted_apps,
         atomic_db,
+        shutdown_receiver,
+        metrics,
         file_system_thread_pool.clone(),
         file_system_thread_pool.clone(),
         thread_pool.clone(),
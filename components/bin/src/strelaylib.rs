@@ -1,7 +1,9 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Mutex;
 use std::time::Duration;
 
+use futures::channel::{mpsc, oneshot};
 use futures::executor::ThreadPool;
 use futures::task::SpawnExt;
 
@@ -12,12 +14,17 @@ use common::conn::Listener;
 use crypto::crypto_rand::system_random;
 use identity::{create_identity, IdentityClient};
 
-use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
+use proto::consts::{
+    MAX_FRAME_LENGTH, RELAY_DRAIN_TIMEOUT_TICKS, RELAY_RATE_LIMIT_CAPACITY,
+    RELAY_RATE_LIMIT_REFILL_PER_TICK, TICK_MS,
+};
 
 use common::int_convert::usize_to_u64;
 
 use net::TcpListener;
-use relay::{net_relay_server, NetRelayServerError};
+use relay::{
+    net_relay_server, NetRelayServerError, RateLimitConfig, RelayServerHandle, ShutdownHandle,
+};
 use timer::create_timer;
 
 use proto::file::identity::load_identity_from_file;
@@ -34,6 +41,8 @@ pub enum RelayServerBinError {
     LoadIdentityError,
     CreateIdentityError,
     CreateTimerError,
+    SpawnError,
+    SetSignalHandlerError,
     NetRelayServerError(NetRelayServerError),
 }
 
@@ -50,6 +59,30 @@ pub struct StRelayCmd {
     pub laddr: SocketAddr,
 }
 
+/// Wait for a termination signal. Then log the statistics of the recently closed connections
+/// and shut down the relay server.
+async fn on_signal(
+    signal_receiver: oneshot::Receiver<()>,
+    mut relay_server_handle: RelayServerHandle,
+    shutdown_handle: ShutdownHandle,
+) {
+    if await!(signal_receiver).is_err() {
+        return;
+    }
+
+    match await!(relay_server_handle.recent_connection_stats()) {
+        Ok(recent_connection_stats) => {
+            for (public_key, connection_stats) in recent_connection_stats {
+                info!("{}: {:?}", public_key.to_hex(), connection_stats);
+            }
+        }
+        Err(e) => warn!("recent_connection_stats() error: {:?}", e),
+    }
+
+    info!("Shutting down");
+    shutdown_handle.shutdown();
+}
+
 pub fn strelay(st_relay_cmd: StRelayCmd) -> Result<(), RelayServerBinError> {
     let StRelayCmd { idfile, laddr } = st_relay_cmd;
 
@@ -77,15 +110,44 @@ pub fn strelay(st_relay_cmd: StRelayCmd) -> Result<(), RelayServerBinError> {
     let tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
     let (_config_sender, incoming_raw_conns) = tcp_listener.listen(laddr);
 
-    let relay_server_fut = net_relay_server(
+    let (requests_sender, incoming_requests) = mpsc::channel(0);
+    let relay_server_handle = RelayServerHandle::new(requests_sender);
+
+    let rate_limit = RateLimitConfig {
+        capacity: RELAY_RATE_LIMIT_CAPACITY,
+        refill_per_tick: RELAY_RATE_LIMIT_REFILL_PER_TICK,
+    };
+
+    let (shutdown_handle, relay_server_fut) = net_relay_server(
         incoming_raw_conns,
+        incoming_requests,
         identity_client,
         timer_client,
         rng,
         MAX_CONCURRENT_ENCRYPT,
+        RELAY_DRAIN_TIMEOUT_TICKS,
+        rate_limit,
         thread_pool.clone(),
     );
 
+    // Shut down gracefully on SIGINT or SIGTERM:
+    let (signal_sender, signal_receiver) = oneshot::channel();
+    let opt_signal_sender = Mutex::new(Some(signal_sender));
+    ctrlc::set_handler(move || {
+        if let Some(signal_sender) = opt_signal_sender.lock().unwrap().take() {
+            let _ = signal_sender.send(());
+        }
+    })
+    .map_err(|_| RelayServerBinError::SetSignalHandlerError)?;
+
+    thread_pool
+        .spawn(on_signal(
+            signal_receiver,
+            relay_server_handle,
+            shutdown_handle,
+        ))
+        .map_err(|_| RelayServerBinError::SpawnError)?;
+
     thread_pool
         .run(relay_server_fut)
         .map_err(RelayServerBinError::NetRelayServerError)
@@ -0,0 +1,198 @@
+#![deny(trivial_numeric_casts, warnings)]
+#![allow(intra_doc_link_resolution_failure)]
+
+//! `#[derive(CanonicalSerialize)]`
+//!
+//! Structs are serialized by serializing all of their fields in declaration order.
+//! Enums are serialized as a `u8` discriminant (The index of the variant, in declaration order),
+//! followed by the fields of the variant.
+//!
+//! A field can be left out of the serialization using `#[canonical_serialize(skip)]`.
+//!
+//! The generated code refers to the trait as `::common::canonical_serialize::CanonicalSerialize`,
+//! so the deriving crate must depend on `offst-common` under the name `common`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Data, DataEnum, DeriveInput, Error, Field, Fields,
+    GenericParam, Generics, Ident, Index, Meta, NestedMeta,
+};
+
+#[proc_macro_derive(CanonicalSerialize, attributes(canonical_serialize))]
+pub fn derive_canonical_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match impl_canonical_serialize(input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn impl_canonical_serialize(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => serialize_struct(&data.fields)?,
+        Data::Enum(data) => serialize_enum(name, data)?,
+        Data::Union(_) => {
+            return Err(Error::new(
+                Span::call_site(),
+                "CanonicalSerialize can not be derived for unions",
+            ));
+        }
+    };
+
+    let generics = add_trait_bounds(input.generics.clone());
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::common::canonical_serialize::CanonicalSerialize
+            for #name #ty_generics #where_clause
+        {
+            fn canonical_serialize(&self) -> ::std::vec::Vec<u8> {
+                let mut res_bytes = ::std::vec::Vec::new();
+                ::common::canonical_serialize::CanonicalSerialize::canonical_serialize_into(
+                    self,
+                    &mut res_bytes,
+                )
+                .unwrap();
+                res_bytes
+            }
+
+            // `writer` is unused if there is nothing to serialize:
+            #[allow(unused_variables)]
+            fn canonical_serialize_into<__W>(&self, writer: &mut __W) -> ::std::io::Result<()>
+            where
+                __W: ::std::io::Write,
+            {
+                #body
+                Ok(())
+            }
+        }
+    })
+}
+
+/// Require `CanonicalSerialize` for every type parameter.
+fn add_trait_bounds(mut generics: Generics) -> Generics {
+    for param in &mut generics.params {
+        if let GenericParam::Type(type_param) = param {
+            type_param.bounds.push(parse_quote!(
+                ::common::canonical_serialize::CanonicalSerialize
+            ));
+        }
+    }
+    generics
+}
+
+/// Check if a field is marked with `#[canonical_serialize(skip)]`
+fn is_skipped(field: &Field) -> Result<bool, Error> {
+    let mut skipped = false;
+    for attr in &field.attrs {
+        let is_canonical_serialize =
+            attr.path.segments.len() == 1 && attr.path.segments[0].ident == "canonical_serialize";
+        if !is_canonical_serialize {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(meta_list) => {
+                for nested in &meta_list.nested {
+                    match nested {
+                        NestedMeta::Meta(Meta::Word(ident)) if ident == "skip" => skipped = true,
+                        _ => {
+                            return Err(Error::new(
+                                nested.span(),
+                                "unknown canonical_serialize attribute",
+                            ));
+                        }
+                    }
+                }
+            }
+            meta => {
+                return Err(Error::new(
+                    meta.span(),
+                    "expected #[canonical_serialize(skip)]",
+                ));
+            }
+        }
+    }
+    Ok(skipped)
+}
+
+fn serialize_field(value: TokenStream2) -> TokenStream2 {
+    quote! {
+        ::common::canonical_serialize::CanonicalSerialize::canonical_serialize_into(
+            #value,
+            writer,
+        )?;
+    }
+}
+
+fn serialize_struct(fields: &Fields) -> Result<TokenStream2, Error> {
+    let mut body = TokenStream2::new();
+    for (i, field) in fields.iter().enumerate() {
+        if is_skipped(field)? {
+            continue;
+        }
+        let value = match &field.ident {
+            Some(ident) => quote!(&self.#ident),
+            None => {
+                let index = Index::from(i);
+                quote!(&self.#index)
+            }
+        };
+        body.extend(serialize_field(value));
+    }
+    Ok(body)
+}
+
+fn serialize_enum(name: &Ident, data: &DataEnum) -> Result<TokenStream2, Error> {
+    if data.variants.len() > usize::from(u8::max_value()) + 1 {
+        return Err(Error::new(
+            Span::call_site(),
+            "CanonicalSerialize supports at most 256 enum variants",
+        ));
+    }
+
+    let mut arms = TokenStream2::new();
+    for (discriminant, variant) in data.variants.iter().enumerate() {
+        let discriminant = discriminant as u8;
+        let variant_ident = &variant.ident;
+
+        let mut bindings = Vec::new();
+        let mut body = TokenStream2::new();
+        for (i, field) in variant.fields.iter().enumerate() {
+            if is_skipped(field)? {
+                bindings.push(quote!(_));
+                continue;
+            }
+            let binding = Ident::new(&format!("__field{}", i), Span::call_site());
+            body.extend(serialize_field(quote!(#binding)));
+            bindings.push(quote!(#binding));
+        }
+
+        let pattern = match &variant.fields {
+            Fields::Named(fields_named) => {
+                let field_idents = fields_named.named.iter().map(|field| &field.ident);
+                quote!(#name::#variant_ident { #(#field_idents: #bindings),* })
+            }
+            Fields::Unnamed(_) => quote!(#name::#variant_ident(#(#bindings),*)),
+            Fields::Unit => quote!(#name::#variant_ident),
+        };
+
+        arms.extend(quote! {
+            #pattern => {
+                ::std::io::Write::write_all(writer, &[#discriminant])?;
+                #body
+            }
+        });
+    }
+
+    Ok(quote! {
+        match self {
+            #arms
+        }
+    })
+}
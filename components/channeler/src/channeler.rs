@@ -1,5 +1,5 @@
-use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::marker::Unpin;
 
@@ -22,6 +22,8 @@ pub enum ChannelerEvent<RA> {
     FromFunder(FunderToChanneler<RA>),
     Connection((PublicKey, RawConn)),
     FriendEvent(FriendEvent),
+    /// A connection attempt to an out friend has failed:
+    ConnectFailed(PublicKey),
     ListenerClosed,
     FunderClosed,
 }
@@ -96,6 +98,7 @@ struct OutFriend<RA> {
     config_client: CpConfigClient<RA>,
     connect_client: CpConnectClient,
     status: OutFriendStatus,
+    connection_priority: u8,
 }
 
 struct Friends<RA> {
@@ -143,6 +146,14 @@ struct Channeler<RA, C, S, TF> {
     spawner: S,
     to_funder: TF,
     event_sender: mpsc::Sender<ChannelerEvent<RA>>,
+    /// Maximum amount of simultaneous connection attempts to out friends:
+    max_concurrent_connect: usize,
+    /// Out friends we are currently attempting to connect to:
+    connecting: HashSet<PublicKey>,
+    /// Out friends waiting for a free connection slot: (connection_priority, counter, public_key).
+    /// The counter keeps friends of equal priority in the order they were queued.
+    connect_queue: BinaryHeap<(u8, Reverse<u64>, PublicKey)>,
+    connect_queue_counter: u64,
 }
 
 impl<RA, C, S, TF> Channeler<RA, C, S, TF>
@@ -163,6 +174,7 @@ where
         spawner: S,
         to_funder: TF,
         event_sender: mpsc::Sender<ChannelerEvent<RA>>,
+        max_concurrent_connect: usize,
     ) -> Self {
         Channeler {
             local_public_key,
@@ -172,6 +184,10 @@ where
             spawner,
             to_funder,
             event_sender,
+            max_concurrent_connect,
+            connecting: HashSet::new(),
+            connect_queue: BinaryHeap::new(),
+            connect_queue_counter: 0,
         }
     }
 
@@ -197,9 +213,11 @@ where
                     let _ = await!(c_event_sender.send(event));
                 }
                 Err(e) => {
-                    // This probably happened because the friend was removed
-                    // during connection attempt.
-                    warn!("connect_out_friend(): connect() error: {:?}", e);
+                    // The connection attempt has failed, or the friend was removed
+                    // during the connection attempt.
+                    info!("connect_out_friend(): connect() error: {:?}", e);
+                    let event = ChannelerEvent::ConnectFailed(c_friend_public_key);
+                    let _ = await!(c_event_sender.send(event));
                 }
             };
         };
@@ -212,10 +230,98 @@ where
         Ok(())
     }
 
+    /// Attempt to connect to an out friend if a connection slot is available.
+    /// Otherwise, queue the out friend according to its connection priority.
+    fn schedule_connect(&mut self, friend_public_key: &PublicKey) -> Result<(), ChannelerError> {
+        if self.connecting.len() < self.max_concurrent_connect {
+            self.connecting.insert(friend_public_key.clone());
+            return self.connect_out_friend(friend_public_key);
+        }
+
+        self.queue_connect(friend_public_key);
+        Ok(())
+    }
+
+    /// Queue an out friend until a connection slot is available.
+    fn queue_connect(&mut self, friend_public_key: &PublicKey) {
+        let connection_priority = match self.friends.out_friends.get(friend_public_key) {
+            Some(out_friend) => out_friend.connection_priority,
+            None => unreachable!(), // We assert that the out_friend exists.
+        };
+        self.connect_queue.push((
+            connection_priority,
+            Reverse(self.connect_queue_counter),
+            friend_public_key.clone(),
+        ));
+        self.connect_queue_counter = self.connect_queue_counter.wrapping_add(1);
+    }
+
+    /// A connection attempt to `friend_public_key` is over (Successfully or not).
+    /// Free its connection slot and connect to the queued out friends with the highest priority.
+    fn connect_done(&mut self, friend_public_key: &PublicKey) -> Result<(), ChannelerError> {
+        if !self.connecting.remove(friend_public_key) {
+            return Ok(());
+        }
+
+        while self.connecting.len() < self.max_concurrent_connect {
+            let (connection_priority, _, public_key) = match self.connect_queue.pop() {
+                Some(queued) => queued,
+                None => break,
+            };
+
+            // Skip stale entries: The friend was removed, is already handled,
+            // or was queued again with a different priority.
+            let is_waiting = match self.friends.out_friends.get(&public_key) {
+                Some(out_friend) => match out_friend.status {
+                    OutFriendStatus::Connecting => {
+                        out_friend.connection_priority == connection_priority
+                            && !self.connecting.contains(&public_key)
+                    }
+                    OutFriendStatus::Connected(_) => false,
+                },
+                None => false,
+            };
+            if !is_waiting {
+                continue;
+            }
+
+            self.connecting.insert(public_key.clone());
+            self.connect_out_friend(&public_key)?;
+        }
+        Ok(())
+    }
+
+    /// A connection attempt to an out friend has failed.
+    /// The connection slot is given to the next queued out friend, and the failed friend is
+    /// queued again. This makes sure that offline friends don't hold the connection slots forever.
+    fn handle_connect_failed(
+        &mut self,
+        friend_public_key: PublicKey,
+    ) -> Result<(), ChannelerError> {
+        if !self.connecting.contains(&friend_public_key) {
+            return Ok(());
+        }
+
+        // Queue the friend before freeing the slot, so that it will compete with the other
+        // waiting friends according to its connection priority:
+        let is_connecting = match self.friends.out_friends.get(&friend_public_key) {
+            Some(out_friend) => match out_friend.status {
+                OutFriendStatus::Connecting => true,
+                OutFriendStatus::Connected(_) => false,
+            },
+            None => false,
+        };
+        if is_connecting {
+            self.queue_connect(&friend_public_key);
+        }
+        self.connect_done(&friend_public_key)
+    }
+
     /// Add friend if does not yet exist
     async fn try_create_friend<'a>(
         &'a mut self,
         friend_public_key: &'a PublicKey,
+        connection_priority: u8,
     ) -> Result<(), ChannelerError> {
         if self.friends.in_friends.contains_key(friend_public_key)
             || self.friends.out_friends.contains_key(friend_public_key)
@@ -236,11 +342,12 @@ where
                 config_client,
                 connect_client,
                 status: OutFriendStatus::Connecting,
+                connection_priority,
             };
             self.friends
                 .out_friends
                 .insert(friend_public_key.clone(), out_friend);
-            self.connect_out_friend(friend_public_key)?;
+            self.schedule_connect(friend_public_key)?;
         }
         Ok(())
     }
@@ -255,8 +362,8 @@ where
                     Some(friend_connected) => friend_connected,
                     None => {
                         error!(
-                            "Attempt to send a message to unavailable friend: {:?}",
-                            public_key
+                            "Attempt to send a message to unavailable friend: {}",
+                            public_key.to_hex()
                         );
                         return Ok(());
                     }
@@ -281,9 +388,10 @@ where
                     friend_public_key,
                     friend_relays,
                     local_relays,
+                    connection_priority,
                 } = channeler_update_friend;
 
-                await!(self.try_create_friend(&friend_public_key))?;
+                await!(self.try_create_friend(&friend_public_key, connection_priority))?;
 
                 if let Some(_in_friend) = self.friends.in_friends.get(&friend_public_key) {
                     let lp_config =
@@ -295,6 +403,21 @@ where
                 {
                     await!(out_friend.config_client.config(friend_relays))
                         .map_err(|_| ChannelerError::ConnectorConfigError)?;
+
+                    if out_friend.connection_priority != connection_priority {
+                        out_friend.connection_priority = connection_priority;
+                        // If the friend is waiting for a connection slot,
+                        // queue it again with the new priority:
+                        let is_queued = match out_friend.status {
+                            OutFriendStatus::Connecting => {
+                                !self.connecting.contains(&friend_public_key)
+                            }
+                            OutFriendStatus::Connected(_) => false,
+                        };
+                        if is_queued {
+                            self.schedule_connect(&friend_public_key)?;
+                        }
+                    }
                 }
 
                 Ok(())
@@ -308,6 +431,9 @@ where
                 }
 
                 self.friends.out_friends.remove(&friend_public_key);
+                // The connection attempt (If any) will fail now that the friend is removed.
+                // We don't wait for the failure to free its connection slot:
+                self.connect_done(&friend_public_key)?;
 
                 Ok(())
             }
@@ -322,6 +448,9 @@ where
     ) -> Result<(), ChannelerError> {
         let (sender, receiver) = raw_conn;
 
+        // If this is a connection to an out friend, the connection attempt is over:
+        self.connect_done(&friend_public_key)?;
+
         // Close fut_recv whenever closer is closed.
         let (closer, close_receiver) = oneshot::channel::<()>();
 
@@ -341,8 +470,8 @@ where
             match in_friend {
                 InFriend::Connected(_) => {
                     warn!(
-                        "Already connected to in_friend: {:?}. Aborting.",
-                        friend_public_key
+                        "Already connected to in_friend: {}. Aborting.",
+                        friend_public_key.to_hex()
                     );
                     return Ok(());
                 }
@@ -354,8 +483,8 @@ where
             match out_friend.status {
                 OutFriendStatus::Connected(_) => {
                     warn!(
-                        "Already connected to out_friend: {:?}. Aborting.",
-                        friend_public_key
+                        "Already connected to out_friend: {}. Aborting.",
+                        friend_public_key.to_hex()
                     );
                     return Ok(());
                 }
@@ -439,7 +568,7 @@ where
                 {
                     // Request a new connection
                     out_friend.status = OutFriendStatus::Connecting;
-                    self.connect_out_friend(&friend_public_key)?;
+                    self.schedule_connect(&friend_public_key)?;
                 }
             }
         }
@@ -453,6 +582,7 @@ pub async fn channeler_loop<FF, TF, RA, C, L, S>(
     to_funder: TF,
     connector: C,
     listener: L,
+    max_concurrent_connect: usize,
     spawner: S,
 ) -> Result<(), ChannelerError>
 where
@@ -478,6 +608,7 @@ where
         spawner,
         to_funder,
         event_sender,
+        max_concurrent_connect,
     );
 
     // Forward incoming listen connections:
@@ -510,6 +641,9 @@ where
             ChannelerEvent::FriendEvent(friend_event) => {
                 await!(channeler.handle_friend_event(friend_event))?
             }
+            ChannelerEvent::ConnectFailed(public_key) => {
+                channeler.handle_connect_failed(public_key)?
+            }
             ChannelerEvent::ListenerClosed => return Err(ChannelerError::ListenerClosed),
             ChannelerEvent::FunderClosed => return Err(ChannelerError::FunderClosed),
         };
@@ -522,10 +656,14 @@ mod tests {
     use super::*;
     use futures::executor::ThreadPool;
 
-    use common::dummy_connector::DummyConnector;
+    use common::dummy_connector::{ConnRequest, DummyConnector};
     use common::dummy_listener::DummyListener;
     use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
 
+    use crate::connect_pool::CpConnectRequest;
+
+    const MAX_CONCURRENT_CONNECT: usize = 2;
+
     /// Test the case of a friend the channeler initiates connection to.
     async fn task_channeler_loop_connect_friend<S>(mut spawner: S)
     where
@@ -558,6 +696,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    MAX_CONCURRENT_CONNECT,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -599,6 +738,7 @@ mod tests {
             friend_public_key: pks[0].clone(),
             friend_relays: vec![0x0u32],
             local_relays: vec![0x2u32, 0x3u32],
+            connection_priority: 0,
         };
 
         await!(funder_sender.send(FunderToChanneler::UpdateFriend(channeler_update_friend)))
@@ -748,6 +888,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    MAX_CONCURRENT_CONNECT,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -767,6 +908,7 @@ mod tests {
             friend_public_key: pks[2].clone(),
             friend_relays: vec![0x0u32],
             local_relays: vec![0x2u32, 0x3u32],
+            connection_priority: 0,
         };
         await!(funder_sender.send(FunderToChanneler::UpdateFriend(channeler_update_friend)))
             .unwrap();
@@ -872,6 +1014,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    MAX_CONCURRENT_CONNECT,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -892,6 +1035,7 @@ mod tests {
                 friend_public_key: pks[2].clone(),
                 friend_relays: vec![0x0u32],
                 local_relays: vec![0x2u32, 0x3u32],
+                connection_priority: 0,
             };
             await!(funder_sender.send(FunderToChanneler::UpdateFriend(channeler_update_friend)))
                 .unwrap();
@@ -973,6 +1117,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    MAX_CONCURRENT_CONNECT,
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -996,6 +1141,7 @@ mod tests {
             friend_public_key: pks[0].clone(),
             friend_relays: vec![0x0u32],
             local_relays: vec![0x2u32, 0x3u32],
+            connection_priority: 0,
         };
 
         await!(funder_sender.send(FunderToChanneler::UpdateFriend(
@@ -1040,6 +1186,159 @@ mod tests {
         ));
     }
 
+    /// Add an out friend with the given connection priority, and reply to the connector request.
+    /// Returns the receivers of the configuration and connection requests for this friend.
+    async fn add_out_friend<'a>(
+        funder_sender: &'a mut mpsc::Sender<FunderToChanneler<u32>>,
+        conn_request_receiver: &'a mut mpsc::Receiver<
+            ConnRequest<PublicKey, ConnectPoolControl<u32>>,
+        >,
+        friend_public_key: &'a PublicKey,
+        connection_priority: u8,
+    ) -> (mpsc::Receiver<Vec<u32>>, mpsc::Receiver<CpConnectRequest>) {
+        let channeler_update_friend = ChannelerUpdateFriend {
+            friend_public_key: friend_public_key.clone(),
+            friend_relays: vec![0x0u32],
+            local_relays: vec![],
+            connection_priority,
+        };
+        await!(funder_sender.send(FunderToChanneler::UpdateFriend(channeler_update_friend)))
+            .unwrap();
+
+        let conn_request = await!(conn_request_receiver.next()).unwrap();
+        assert_eq!(&conn_request.address, friend_public_key);
+        let (connect_sender, connect_receiver) = mpsc::channel(0);
+        let (config_sender, mut config_receiver) = mpsc::channel(0);
+        conn_request.reply((
+            CpConfigClient::new(config_sender),
+            CpConnectClient::new(connect_sender),
+        ));
+
+        assert_eq!(await!(config_receiver.next()).unwrap(), vec![0x0u32]);
+        (config_receiver, connect_receiver)
+    }
+
+    /// Test the order of connection attempts when there are more out friends than
+    /// available connection slots.
+    async fn task_channeler_loop_connect_priority<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + Sync + 'static,
+    {
+        let (mut funder_sender, from_funder) = mpsc::channel(0);
+        let (to_funder, mut funder_receiver) = mpsc::channel(0);
+
+        // Our local public key will be pks[4], so all the other friends are out friends
+        // (We initiate connection):
+        let mut pks = (0..5)
+            .map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+            .collect::<Vec<PublicKey>>();
+        pks.sort_by(compare_public_key);
+
+        let (conn_request_sender, mut conn_request_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(conn_request_sender);
+
+        let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
+        let listener = DummyListener::new(listener_req_sender, spawner.clone());
+
+        spawner
+            .spawn(
+                channeler_loop(
+                    pks[4].clone(),
+                    from_funder,
+                    to_funder,
+                    connector,
+                    listener,
+                    MAX_CONCURRENT_CONNECT,
+                    spawner.clone(),
+                )
+                .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
+                .map(|_| ()),
+            )
+            .unwrap();
+
+        let _listener_request = await!(listener_req_receiver.next()).unwrap();
+
+        // Two idle friends take both connection slots:
+        let (_config_receiver0, mut connect_receiver0) = await!(add_out_friend(
+            &mut funder_sender,
+            &mut conn_request_receiver,
+            &pks[0],
+            0
+        ));
+        let (_config_receiver1, mut connect_receiver1) = await!(add_out_friend(
+            &mut funder_sender,
+            &mut conn_request_receiver,
+            &pks[1],
+            0
+        ));
+
+        // Friends with higher priorities have to wait for a free slot:
+        let (_config_receiver2, mut connect_receiver2) = await!(add_out_friend(
+            &mut funder_sender,
+            &mut conn_request_receiver,
+            &pks[2],
+            1
+        ));
+        let (_config_receiver3, mut connect_receiver3) = await!(add_out_friend(
+            &mut funder_sender,
+            &mut conn_request_receiver,
+            &pks[3],
+            2
+        ));
+
+        let connect_req0 = await!(connect_receiver0.next()).unwrap();
+        let connect_req1 = await!(connect_receiver1.next()).unwrap();
+        assert!(connect_receiver2.try_next().is_err());
+        assert!(connect_receiver3.try_next().is_err());
+
+        // pks[0] connects successfully:
+        let (_pk0_sender, local_receiver) = mpsc::channel(0);
+        let (local_sender, _pk0_receiver) = mpsc::channel(0);
+        connect_req0
+            .response_sender
+            .send((local_sender, local_receiver))
+            .unwrap();
+
+        let channeler_to_funder = await!(funder_receiver.next()).unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::Online(public_key) => assert_eq!(public_key, pks[0]),
+            _ => unreachable!(),
+        };
+
+        // The free slot is given to pks[3], which has the highest priority:
+        let _connect_req3 = await!(connect_receiver3.next()).unwrap();
+        assert!(connect_receiver2.try_next().is_err());
+
+        // Connection attempt to pks[1] fails (The pool drops the request after a failed attempt).
+        // The free slot is given to pks[2]:
+        drop(connect_req1);
+        let connect_req2 = await!(connect_receiver2.next()).unwrap();
+        assert!(connect_receiver1.try_next().is_err());
+
+        // pks[2] connects successfully:
+        let (_pk2_sender, local_receiver) = mpsc::channel(0);
+        let (local_sender, _pk2_receiver) = mpsc::channel(0);
+        connect_req2
+            .response_sender
+            .send((local_sender, local_receiver))
+            .unwrap();
+
+        let channeler_to_funder = await!(funder_receiver.next()).unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::Online(public_key) => assert_eq!(public_key, pks[2]),
+            _ => unreachable!(),
+        };
+
+        // pks[1] was queued again after its failed attempt, and now gets the free slot:
+        let _connect_req1 = await!(connect_receiver1.next()).unwrap();
+    }
+
+    #[test]
+    fn test_channeler_loop_connect_priority() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_channeler_loop_connect_priority(thread_pool.clone()));
+    }
+
     // TODO: Add tests to make sure access control works properly?
     // If a friend with a strange public key tries to connect, he should not be able to succeed?
 }
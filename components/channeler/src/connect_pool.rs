@@ -48,6 +48,9 @@ impl CpConnectClient {
         CpConnectClient { request_sender }
     }
 
+    /// Request a connection to the friend.
+    /// The pool makes at most one connection attempt (After waiting for its backoff) for every
+    /// request. An error is returned if the attempt has failed or the pool was closed.
     pub async fn connect(&mut self) -> Result<RawConn, ConnectPoolClientError> {
         let (response_sender, response_receiver) = oneshot::channel();
         let connect_request = CpConnectRequest { response_sender };
@@ -76,6 +79,8 @@ enum CpEvent<RA> {
 
 enum CpStatus<RA> {
     NoRequest,
+    /// No pending request. The next request will wait the remaining ticks before connecting.
+    Backoff(usize),
     Waiting((usize, oneshot::Sender<RawConn>)),
     Connecting((RA, oneshot::Sender<()>, oneshot::Sender<RawConn>)),
 }
@@ -180,15 +185,24 @@ where
         &mut self,
         connect_request: CpConnectRequest,
     ) -> Result<(), ConnectPoolError> {
-        if let CpStatus::NoRequest = self.status {
-        } else {
-            return Err(ConnectPoolError::MultipleConnectRequests);
+        match self.status {
+            CpStatus::NoRequest => {}
+            CpStatus::Backoff(backoff_ticks) => {
+                // A previous attempt has failed recently. We wait before trying again:
+                self.status = CpStatus::Waiting((backoff_ticks, connect_request.response_sender));
+                return Ok(());
+            }
+            CpStatus::Waiting(_) | CpStatus::Connecting(_) => {
+                return Err(ConnectPoolError::MultipleConnectRequests);
+            }
         }
 
         let address = match self.addresses.pop_front() {
             None => {
                 // We can't connect yet, because we don't know of any address.
-                self.status = CpStatus::Waiting((0, connect_request.response_sender));
+                // If no address shows up during the backoff period, the request fails.
+                self.status =
+                    CpStatus::Waiting((self.backoff_ticks, connect_request.response_sender));
                 return Ok(());
             }
             Some(address) => address,
@@ -221,6 +235,9 @@ where
         self.addresses.retain(|cur_address| cur_address != &address);
         match mem::replace(&mut self.status, CpStatus::NoRequest) {
             CpStatus::NoRequest => {}
+            CpStatus::Backoff(backoff_ticks) => {
+                self.status = CpStatus::Backoff(backoff_ticks);
+            }
             CpStatus::Waiting(waiting) => {
                 self.status = CpStatus::Waiting(waiting);
             }
@@ -262,6 +279,13 @@ where
     pub fn handle_timer_tick(&mut self) -> Result<(), ConnectPoolError> {
         let waiting = match mem::replace(&mut self.status, CpStatus::NoRequest) {
             CpStatus::Waiting(waiting) => waiting,
+            CpStatus::Backoff(backoff_ticks) => {
+                let backoff_ticks = backoff_ticks.saturating_sub(1);
+                if backoff_ticks > 0 {
+                    self.status = CpStatus::Backoff(backoff_ticks);
+                }
+                return Ok(());
+            }
             other_status => {
                 self.status = other_status;
                 return Ok(());
@@ -275,7 +299,10 @@ where
                 let canceler = self.create_conn_attempt(address.clone())?;
                 self.status = CpStatus::Connecting((address, canceler, response_sender));
             } else {
-                self.status = CpStatus::Waiting((self.backoff_ticks, response_sender));
+                // We still don't know of any address. The request fails (By dropping
+                // response_sender), so that the caller may use its connection slot for
+                // another friend in the meanwhile.
+                drop(response_sender);
             }
         } else {
             self.status = CpStatus::Waiting((backoff_ticks, response_sender));
@@ -285,7 +312,7 @@ where
 
     pub fn handle_connect_attempt_done(&mut self, opt_conn: Option<RawConn>) {
         let connecting = match mem::replace(&mut self.status, CpStatus::NoRequest) {
-            CpStatus::NoRequest | CpStatus::Waiting(_) => unreachable!(),
+            CpStatus::NoRequest | CpStatus::Backoff(_) | CpStatus::Waiting(_) => unreachable!(),
             CpStatus::Connecting(connecting) => connecting,
         };
 
@@ -301,7 +328,10 @@ where
             }
             self.status = CpStatus::NoRequest;
         } else {
-            self.status = CpStatus::Waiting((self.backoff_ticks, response_sender));
+            // The attempt has failed. We fail the request (By dropping response_sender)
+            // and make the next request wait backoff_ticks:
+            drop(response_sender);
+            self.status = CpStatus::Backoff(self.backoff_ticks);
         }
     }
 }
@@ -707,11 +737,22 @@ mod tests {
         // Addresses that we have seen an attempt to connect to:
         let mut observed_addresses = Vec::new();
 
-        // Connect and handle the connection request at the same time
-        let connect_fut = connect_client.connect();
-        let handle_connect_fut = async {
-            await!(event_receiver.next()).unwrap(); // Connection request event
-            for _ in 0..addresses.len() {
+        // Every connection attempt fails. The pool should fail the request after each attempt,
+        // and wait backoff_ticks before attempting again on the next request:
+        for i in 0..addresses.len() {
+            let connect_fut = connect_client.connect();
+            let handle_connect_fut = async {
+                await!(event_receiver.next()).unwrap(); // Connection request event
+
+                if i > 0 {
+                    // Wait backoff_ticks:
+                    for _ in 0..backoff_ticks {
+                        assert!(conn_request_receiver.try_next().is_err());
+                        await!(tick_sender.send(TimerTick)).unwrap();
+                        await!(event_receiver.next()).unwrap(); // timer tick event
+                    }
+                }
+
                 let conn_request = await!(conn_request_receiver.next()).unwrap();
 
                 let (address, pk) = &conn_request.address;
@@ -721,15 +762,20 @@ mod tests {
                 // Connection attempt failed:
                 conn_request.reply(None);
                 await!(event_receiver.next()).unwrap(); // connection attempt done event
+            };
+            let (res, ()) = await!(join(connect_fut, handle_connect_fut));
+            assert!(res.is_err());
+        }
 
-                // Wait backoff_ticks:
-                for _ in 0..backoff_ticks {
-                    await!(tick_sender.send(TimerTick)).unwrap();
-                    await!(event_receiver.next()).unwrap(); // timer tick event
-                }
+        // Finally, we let the connection request succeed:
+        let connect_fut = connect_client.connect();
+        let handle_connect_fut = async {
+            await!(event_receiver.next()).unwrap(); // Connection request event
+            for _ in 0..backoff_ticks {
+                await!(tick_sender.send(TimerTick)).unwrap();
+                await!(event_receiver.next()).unwrap(); // timer tick event
             }
 
-            // Finally, we let the connection request succeed:
             let conn_request = await!(conn_request_receiver.next()).unwrap();
 
             let (local_sender, remote_receiver) = mpsc::channel(0);
@@ -743,14 +789,12 @@ mod tests {
 
             conn_request.reply(Some((local_sender, local_receiver)));
             await!(event_receiver.next()).unwrap(); // connection attempt done event
-            (conn_request_receiver, (remote_sender, remote_receiver))
+            (remote_sender, remote_receiver)
         };
-        let (local_conn, (_remote_conn, new_conn_request_receiver)) =
-            await!(join(connect_fut, handle_connect_fut));
-        let _conn_request_receiver = new_conn_request_receiver;
+        let (local_conn, _remote_conn) = await!(join(connect_fut, handle_connect_fut));
 
         // Drop the connection:
-        drop(local_conn);
+        drop(local_conn.unwrap());
     }
 
     #[test]
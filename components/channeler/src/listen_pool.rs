@@ -2,13 +2,16 @@ use std::collections::HashSet;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
 
 use common::access_control::AccessControlOp;
 use common::conn::{FutTransform, Listener};
+use common::int_convert::usize_to_u64;
 use common::select_streams::{select_streams, BoxStream};
 use common::transform_pool::transform_pool_loop;
 
@@ -209,6 +212,19 @@ where
         Ok(())
     }
 
+    /// Amount of relays we currently listen on.
+    /// Relays that are waiting to reconnect after their connection was closed are not counted.
+    fn num_listening_relays(&self) -> usize {
+        self.state
+            .relays
+            .values()
+            .filter(|relay| match relay.status {
+                RelayStatus::Connected(_) => true,
+                RelayStatus::Waiting(_) => false,
+            })
+            .count()
+    }
+
     pub fn handle_relay_closed(&mut self, address: RA) -> Result<(), ListenPoolError> {
         let relay = match self.state.relays.get_mut(&address) {
             Some(relay) => relay,
@@ -252,6 +268,7 @@ async fn listen_pool_loop<RA, L, TS, S>(
     listener: L,
     backoff_ticks: usize,
     timer_stream: TS,
+    listening_relays: Arc<AtomicU64>,
     spawner: S,
     mut opt_event_sender: Option<mpsc::Sender<()>>,
 ) -> Result<(), ListenPoolError>
@@ -297,11 +314,17 @@ where
             LpEvent::TimerClosed => break,
         };
 
+        listening_relays.store(
+            usize_to_u64(listen_pool.num_listening_relays()).unwrap(),
+            Ordering::Relaxed,
+        );
+
         // Used for debugging:
         if let Some(ref mut event_sender) = opt_event_sender {
             let _ = await!(event_sender.send(()));
         }
     }
+    listening_relays.store(0, Ordering::Relaxed);
     Ok(())
 }
 
@@ -310,8 +333,11 @@ pub struct PoolListener<RA, L, ET, S> {
     listener: L,
     encrypt_transform: ET,
     max_concurrent_encrypt: usize,
+    incoming_conn_wait_ticks: usize,
     backoff_ticks: usize,
     timer_client: TimerClient,
+    /// Amount of relays we currently listen on. Updated by the listen pool.
+    listening_relays: Arc<AtomicU64>,
     spawner: S,
     phantom_b: PhantomData<RA>,
 }
@@ -321,16 +347,20 @@ impl<RA, L, ET, S> PoolListener<RA, L, ET, S> {
         listener: L,
         encrypt_transform: ET,
         max_concurrent_encrypt: usize,
+        incoming_conn_wait_ticks: usize,
         backoff_ticks: usize,
         timer_client: TimerClient,
+        listening_relays: Arc<AtomicU64>,
         spawner: S,
     ) -> Self {
         PoolListener {
             listener,
             encrypt_transform,
             max_concurrent_encrypt,
+            incoming_conn_wait_ticks,
             backoff_ticks,
             timer_client,
+            listening_relays,
             spawner,
             phantom_b: PhantomData,
         }
@@ -368,20 +398,36 @@ where
         let c_listener = self.listener.clone();
         let c_encrypt_transform = self.encrypt_transform.clone();
         let c_max_concurrent_encrypt = self.max_concurrent_encrypt;
+        let c_incoming_conn_wait_ticks = self.incoming_conn_wait_ticks;
         let c_backoff_ticks = self.backoff_ticks;
+        let c_listening_relays = self.listening_relays.clone();
         let mut c_spawner = self.spawner.clone();
 
         // Connections encryptor:
         let (plain_conn_sender, incoming_plain_conn) = mpsc::channel(0);
-        let enc_loop_fut = transform_pool_loop(
-            incoming_plain_conn,
-            outgoing_conns,
-            c_encrypt_transform,
-            c_max_concurrent_encrypt,
-            c_spawner.clone(),
-        )
-        .map_err(|e| error!("transform_pool_loop: {:?}", e))
-        .map(|_| ());
+        let mut enc_timer_client = self.timer_client.clone();
+        let enc_spawner = c_spawner.clone();
+        let enc_loop_fut = async move {
+            let pool_timer_stream = match await!(enc_timer_client.request_timer_stream()) {
+                Ok(pool_timer_stream) => pool_timer_stream,
+                Err(_) => {
+                    error!("PoolListener::listen(): Failed to obtain timer stream!");
+                    return;
+                }
+            };
+            let res = await!(transform_pool_loop(
+                incoming_plain_conn,
+                outgoing_conns,
+                c_encrypt_transform,
+                c_max_concurrent_encrypt,
+                pool_timer_stream,
+                c_incoming_conn_wait_ticks,
+                enc_spawner
+            ));
+            if let Err(e) = res {
+                error!("transform_pool_loop: {:?}", e);
+            }
+        };
 
         if c_spawner.spawn(enc_loop_fut).is_err() {
             return (config_sender, incoming_conns);
@@ -403,6 +449,7 @@ where
                 c_listener,
                 c_backoff_ticks,
                 timer_stream,
+                c_listening_relays,
                 c_spawner,
                 None
             ));
@@ -425,6 +472,7 @@ mod tests {
     use super::*;
     use futures::channel::mpsc;
     use futures::executor::ThreadPool;
+    use futures::{FutureExt, TryFutureExt};
 
     use crypto::identity::PUBLIC_KEY_LEN;
 
@@ -449,6 +497,7 @@ mod tests {
         let (listen_req_sender, mut listen_req_receiver) = mpsc::channel(0);
         let listener = DummyListener::new(listen_req_sender, spawner.clone());
 
+        let listening_relays = Arc::new(AtomicU64::new(0));
         let (event_sender, mut event_receiver) = mpsc::channel(0);
         let fut_loop = listen_pool_loop::<u32, _, _, _>(
             incoming_config,
@@ -456,6 +505,7 @@ mod tests {
             listener,
             backoff_ticks,
             timer_stream,
+            listening_relays.clone(),
             spawner.clone(),
             Some(event_sender),
         )
@@ -538,6 +588,7 @@ mod tests {
         let (listen_req_sender, mut listen_req_receiver) = mpsc::channel(0);
         let listener = DummyListener::new(listen_req_sender, spawner.clone());
 
+        let listening_relays = Arc::new(AtomicU64::new(0));
         let (event_sender, mut event_receiver) = mpsc::channel(0);
         let fut_loop = listen_pool_loop::<u32, _, _, _>(
             incoming_config,
@@ -545,6 +596,7 @@ mod tests {
             listener,
             backoff_ticks,
             timer_stream,
+            listening_relays.clone(),
             spawner.clone(),
             Some(event_sender),
         )
@@ -560,10 +612,12 @@ mod tests {
             let listen_req = await!(listen_req_receiver.next()).unwrap();
             let (ref relay_address, _) = listen_req.arg;
             assert_eq!(*relay_address, 0);
+            assert_eq!(listening_relays.load(Ordering::Relaxed), 1);
 
             // Simulate closing of the listener:
             drop(listen_req);
             await!(event_receiver.next()).unwrap();
+            assert_eq!(listening_relays.load(Ordering::Relaxed), 0);
 
             // Wait until backoff_ticks time passes:
             for _ in 0..backoff_ticks {
@@ -604,6 +658,7 @@ mod tests {
         let (listen_req_sender, mut listen_req_receiver) = mpsc::channel(0);
         let listener = DummyListener::new(listen_req_sender, spawner.clone());
 
+        let listening_relays = Arc::new(AtomicU64::new(0));
         let (event_sender, mut event_receiver) = mpsc::channel(0);
         let fut_loop = listen_pool_loop::<u32, _, _, _>(
             incoming_config,
@@ -611,6 +666,7 @@ mod tests {
             listener,
             backoff_ticks,
             timer_stream,
+            listening_relays.clone(),
             spawner.clone(),
             Some(event_sender),
         )
@@ -1,5 +1,8 @@
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 
 use futures::channel::mpsc;
 use futures::task::Spawn;
@@ -9,11 +12,12 @@ use timer::TimerClient;
 
 use crypto::identity::PublicKey;
 
-use relay::{ClientConnector, ClientListener};
+use relay::{ClientConnector, ClientListener, PingConfig};
 
 use crate::channeler::{channeler_loop, ChannelerError};
 use crate::connect_pool::PoolConnector;
 use crate::listen_pool::PoolListener;
+use proto::consts::{RELAY_PING_INTERVAL_TICKS, RELAY_PONG_TIMEOUT_TICKS};
 use proto::funder::messages::{ChannelerToFunder, FunderToChanneler};
 
 /// A connection style encrypt transform.
@@ -98,9 +102,12 @@ pub async fn spawn_channeler<RA, C, ET, KT, S>(
     backoff_ticks: usize,
     conn_timeout_ticks: usize,
     max_concurrent_encrypt: usize,
+    incoming_conn_wait_ticks: usize,
     enc_relay_connector: C,
     encrypt_transform: ET,
     keepalive_transform: KT,
+    opt_access_control_path: Option<PathBuf>,
+    listening_relays: Arc<AtomicU64>,
     from_funder: mpsc::Receiver<FunderToChanneler<RA>>,
     to_funder: mpsc::Sender<ChannelerToFunder>,
     spawner: S,
@@ -131,11 +138,18 @@ where
         spawner.clone(),
     );
 
+    let ping_config = PingConfig {
+        ping_interval_ticks: RELAY_PING_INTERVAL_TICKS,
+        pong_timeout_ticks: RELAY_PONG_TIMEOUT_TICKS,
+    };
+
     let client_listener = ClientListener::new(
         enc_relay_connector,
         keepalive_transform.clone(),
         conn_timeout_ticks,
+        Some(ping_config),
         timer_client.clone(),
+        opt_access_control_path,
         spawner.clone(),
     );
 
@@ -145,8 +159,10 @@ where
         client_listener,
         listen_encrypt_transform,
         max_concurrent_encrypt,
+        incoming_conn_wait_ticks,
         backoff_ticks,
         timer_client.clone(),
+        listening_relays,
         spawner.clone(),
     );
 
@@ -157,6 +173,7 @@ where
         to_funder,
         pool_connector,
         pool_listener,
+        max_concurrent_encrypt,
         spawner.clone()
     ))
 }
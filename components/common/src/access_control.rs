@@ -36,6 +36,22 @@ where
     pub fn is_allowed(&self, item: &T) -> bool {
         self.allowed.contains(item)
     }
+
+    /// Reconstruct an AccessControl from a list of allowed items.
+    pub fn from_snapshot(items: Vec<T>) -> AccessControl<T> {
+        AccessControl {
+            allowed: items.into_iter().collect(),
+        }
+    }
+
+    /// Get a list of all allowed items (In no particular order).
+    /// Can be used to persist the AccessControl, and later restore it using `from_snapshot()`.
+    pub fn snapshot(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.allowed.iter().cloned().collect()
+    }
 }
 
 #[cfg(test)]
@@ -76,4 +92,26 @@ mod tests {
         assert!(!ac.is_allowed(&a_public_key));
         assert!(!ac.is_allowed(&b_public_key));
     }
+
+    #[test]
+    fn test_access_control_snapshot() {
+        let mut ac = AccessControl::new();
+        ac.apply_op(AccessControlOp::Add(0xaa));
+        ac.apply_op(AccessControlOp::Add(0xbb));
+        ac.apply_op(AccessControlOp::Add(0xcc));
+        ac.apply_op(AccessControlOp::Remove(0xbb));
+
+        let mut snapshot = ac.snapshot();
+        snapshot.sort();
+        assert_eq!(snapshot, vec![0xaa, 0xcc]);
+
+        let restored_ac = AccessControl::from_snapshot(snapshot);
+        assert!(restored_ac.is_allowed(&0xaa));
+        assert!(!restored_ac.is_allowed(&0xbb));
+        assert!(restored_ac.is_allowed(&0xcc));
+
+        // Restoring an empty snapshot results in an empty AccessControl:
+        let empty_ac = AccessControl::<u32>::from_snapshot(Vec::new());
+        assert!(empty_ac.snapshot().is_empty());
+    }
 }
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use bech32::{Bech32, FromBase32, ToBase32};
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The string is not valid bech32 (For example: bad checksum or invalid character)
+    Bech32(bech32::Error),
+    /// The decoded data does not have the expected amount of bytes
+    InvalidLength,
+    /// The human readable part is not the expected one (For example: A payment id was given
+    /// where a public key was expected)
+    InvalidHrp,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Bech32(e) => write!(f, "invalid bech32: {}", e),
+            DecodeError::InvalidLength => write!(f, "invalid bech32 data length"),
+            DecodeError::InvalidHrp => write!(f, "unexpected bech32 human readable part"),
+        }
+    }
+}
+
+impl From<bech32::Error> for DecodeError {
+    fn from(e: bech32::Error) -> Self {
+        DecodeError::Bech32(e)
+    }
+}
+
+/// Encode bytes as a bech32 string with the given human readable part.
+///
+/// # Panics
+///
+/// Panics if `hrp` is not a valid bech32 human readable part.
+pub fn to_bech32(hrp: &str, bytes: &[u8]) -> String {
+    Bech32::new(hrp.to_owned(), bytes.to_base32())
+        .expect("Invalid bech32 human readable part")
+        .to_string()
+}
+
+/// Decode a bech32 string into exactly `out.len()` bytes.
+/// Returns the human readable part of the string.
+pub fn from_bech32(s: &str, out: &mut [u8]) -> Result<String, DecodeError> {
+    let bech32: Bech32 = s.parse()?;
+    let data = Vec::<u8>::from_base32(bech32.data())?;
+    if data.len() != out.len() {
+        return Err(DecodeError::InvalidLength);
+    }
+    out.copy_from_slice(&data);
+    Ok(bech32.hrp().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bech32_round_trip() {
+        let bytes = [0x00u8, 0x01, 0x7f, 0x80, 0xab, 0xff];
+        let bech32_str = to_bech32("test", &bytes);
+        assert!(bech32_str.starts_with("test1"));
+
+        let mut out = [0u8; 6];
+        assert_eq!(from_bech32(&bech32_str, &mut out).unwrap(), "test");
+        assert_eq!(out, bytes);
+    }
+
+    #[test]
+    fn test_from_bech32_errors() {
+        let bech32_str = to_bech32("test", &[1, 2, 3]);
+
+        // Wrong length:
+        let mut out = [0u8; 2];
+        assert_eq!(
+            from_bech32(&bech32_str, &mut out),
+            Err(DecodeError::InvalidLength)
+        );
+
+        // Invalid checksum:
+        let mut corrupted = bech32_str.clone().into_bytes();
+        let last = corrupted.len() - 1;
+        corrupted[last] = if corrupted[last] == b'q' { b'p' } else { b'q' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        let mut out = [0u8; 3];
+        match from_bech32(&corrupted, &mut out) {
+            Err(DecodeError::Bech32(_)) => {}
+            _ => unreachable!(),
+        };
+    }
+}
@@ -1,11 +1,29 @@
+use std::io::{self, Write};
+
 use crate::int_convert::usize_to_u64;
 use byteorder::{BigEndian, WriteBytesExt};
 
+/// `#[derive(CanonicalSerialize)]`: Serializes all fields in declaration order.
+/// Enums are serialized as a `u8` variant index, followed by the fields of the variant.
+/// Fields marked with `#[canonical_serialize(skip)]` are not serialized.
+pub use canonical_serialize_derive::CanonicalSerialize;
+
 /// Canonically serialize an object
 /// This serialization is used for security related applications (For example, signatures and
 /// hashing), therefore the serialization result must be the same on any system.
 pub trait CanonicalSerialize {
     fn canonical_serialize(&self) -> Vec<u8>;
+
+    /// Write the canonical serialization into a writer.
+    /// Useful for hashing, where the serialization result does not have to be kept in memory.
+    /// The default implementation allocates the serialization result. Types that contain other
+    /// serializable types should override this to avoid intermediate allocations.
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(&self.canonical_serialize())
+    }
 }
 
 impl<T> CanonicalSerialize for Option<T>
@@ -14,16 +32,21 @@ where
 {
     fn canonical_serialize(&self) -> Vec<u8> {
         let mut res_data = Vec::new();
+        self.canonical_serialize_into(&mut res_data).unwrap();
+        res_data
+    }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
         match &self {
-            None => {
-                res_data.push(0);
-            }
+            None => writer.write_u8(0),
             Some(t) => {
-                res_data.push(1);
-                res_data.extend_from_slice(&t.canonical_serialize());
+                writer.write_u8(1)?;
+                t.canonical_serialize_into(writer)
             }
-        };
-        res_data
+        }
     }
 }
 
@@ -33,15 +56,21 @@ where
 {
     fn canonical_serialize(&self) -> Vec<u8> {
         let mut res_data = Vec::new();
-        // Write length:
+        self.canonical_serialize_into(&mut res_data).unwrap();
         res_data
-            .write_u64::<BigEndian>(usize_to_u64(self.len()).unwrap())
-            .unwrap();
+    }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        // Write length:
+        writer.write_u64::<BigEndian>(usize_to_u64(self.len()).unwrap())?;
         // Write all items:
         for t in self.iter() {
-            res_data.extend_from_slice(&t.canonical_serialize());
+            t.canonical_serialize_into(writer)?;
         }
-        res_data
+        Ok(())
     }
 }
 
@@ -49,6 +78,26 @@ impl CanonicalSerialize for String {
     fn canonical_serialize(&self) -> Vec<u8> {
         self.as_bytes().to_vec()
     }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(self.as_bytes())
+    }
+}
+
+impl CanonicalSerialize for bool {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        vec![u8::from(*self)]
+    }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_u8(u8::from(*self))
+    }
 }
 
 // Used mostly for testing:
@@ -58,6 +107,43 @@ impl CanonicalSerialize for u32 {
         res_data.write_u32::<BigEndian>(*self).unwrap();
         res_data
     }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_u32::<BigEndian>(*self)
+    }
+}
+
+impl CanonicalSerialize for u64 {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_data = Vec::new();
+        res_data.write_u64::<BigEndian>(*self).unwrap();
+        res_data
+    }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_u64::<BigEndian>(*self)
+    }
+}
+
+impl CanonicalSerialize for u128 {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_data = Vec::new();
+        res_data.write_u128::<BigEndian>(*self).unwrap();
+        res_data
+    }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_u128::<BigEndian>(*self)
+    }
 }
 
 impl<T, W> CanonicalSerialize for (T, W)
@@ -66,10 +152,54 @@ where
     W: CanonicalSerialize,
 {
     fn canonical_serialize(&self) -> Vec<u8> {
-        let (t, w) = self;
         let mut res_data = Vec::new();
-        res_data.extend_from_slice(&t.canonical_serialize());
-        res_data.extend_from_slice(&w.canonical_serialize());
+        self.canonical_serialize_into(&mut res_data).unwrap();
         res_data
     }
+
+    fn canonical_serialize_into<WR>(&self, writer: &mut WR) -> io::Result<()>
+    where
+        WR: Write,
+    {
+        let (t, w) = self;
+        t.canonical_serialize_into(writer)?;
+        w.canonical_serialize_into(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_serialize_into_matches() {
+        let value: Vec<(Option<u32>, String)> = vec![
+            (None, "first".to_owned()),
+            (Some(0x01020304), "second".to_owned()),
+        ];
+
+        let mut written = Vec::new();
+        value.canonical_serialize_into(&mut written).unwrap();
+        assert_eq!(written, value.canonical_serialize());
+
+        let mut expected = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        expected.push(0);
+        expected.extend_from_slice(b"first");
+        expected.extend_from_slice(&[1, 1, 2, 3, 4]);
+        expected.extend_from_slice(b"second");
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn test_canonical_serialize_primitives() {
+        assert_eq!(true.canonical_serialize(), vec![1]);
+        assert_eq!(false.canonical_serialize(), vec![0]);
+        assert_eq!(
+            0x0102_0304_0506_0708u64.canonical_serialize(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        let mut expected = vec![0; 15];
+        expected.push(0xab);
+        assert_eq!(0xabu128.canonical_serialize(), expected);
+    }
 }
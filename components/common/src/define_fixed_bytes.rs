@@ -6,12 +6,160 @@ macro_rules! define_fixed_bytes {
         )]
         pub struct $name([u8; $len]);
 
+        $crate::define_fixed_bytes!(@impls $name, $len);
+    };
+    // Like the `eq = $eq_fn` variant, but human readable formats (For example: JSON)
+    // serialize the bytes as bech32, using the given human readable part.
+    // The binary serialization is the same as the derived one.
+    ($name:ident, $len:expr, eq = $eq_fn:path, bech32_hrp = $hrp:expr) => {
+        #[allow(clippy::derive_hash_xor_eq)]
+        #[derive(Default, Debug, Clone, Hash, PartialOrd, Ord)]
+        pub struct $name([u8; $len]);
+
+        $crate::define_fixed_bytes!(@eq $name, $eq_fn);
+        $crate::define_fixed_bytes!(@bech32_serde $name, $len, $hrp);
+        $crate::define_fixed_bytes!(@impls $name, $len);
+    };
+    // Compare using the given `fn(&[u8], &[u8]) -> bool` instead of the derived `PartialEq`.
+    // (For example, a constant time comparison)
+    // Only equality goes through `$eq_fn`. `PartialOrd` and `Ord` are still derived, so `cmp()`
+    // and `<` stop at the first differing byte. They agree with `$eq_fn` as long as it compares
+    // the bytes for equality.
+    ($name:ident, $len:expr, eq = $eq_fn:path) => {
+        #[allow(clippy::derive_hash_xor_eq)]
+        #[derive(Default, Debug, Clone, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        pub struct $name([u8; $len]);
+
+        $crate::define_fixed_bytes!(@eq $name, $eq_fn);
+        $crate::define_fixed_bytes!(@impls $name, $len);
+    };
+    (@eq $name:ident, $eq_fn:path) => {
+        impl PartialEq for $name {
+            #[inline]
+            fn eq(&self, other: &$name) -> bool {
+                $eq_fn(&self.0, &other.0)
+            }
+        }
+
+        impl Eq for $name {}
+    };
+    (@bech32_serde $name:ident, $len:expr, $hrp:expr) => {
+        impl ::serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                if serializer.is_human_readable() {
+                    serializer.serialize_str(&self.to_bech32($hrp))
+                } else {
+                    serializer.serialize_newtype_struct(stringify!($name), &self.0)
+                }
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<$name, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                struct HumanReadableVisitor;
+
+                impl<'de> ::serde::de::Visitor<'de> for HumanReadableVisitor {
+                    type Value = $name;
+
+                    fn expecting(
+                        &self,
+                        formatter: &mut ::std::fmt::Formatter,
+                    ) -> ::std::fmt::Result {
+                        formatter.write_str("a bech32 string")
+                    }
+
+                    fn visit_str<E>(self, s: &str) -> Result<$name, E>
+                    where
+                        E: ::serde::de::Error,
+                    {
+                        $name::from_bech32(s, $hrp).map_err(E::custom)
+                    }
+
+                    // Array of bytes, as serialized before bech32 was used:
+                    fn visit_seq<A>(self, mut seq: A) -> Result<$name, A::Error>
+                    where
+                        A: ::serde::de::SeqAccess<'de>,
+                    {
+                        let mut inner = [0x00u8; $len];
+                        for (i, byte) in inner.iter_mut().enumerate() {
+                            *byte = seq
+                                .next_element()?
+                                .ok_or_else(|| ::serde::de::Error::invalid_length(i, &self))?;
+                        }
+                        if seq.next_element::<u8>()?.is_some() {
+                            return Err(::serde::de::Error::invalid_length($len + 1, &self));
+                        }
+                        Ok($name(inner))
+                    }
+                }
+
+                if deserializer.is_human_readable() {
+                    deserializer.deserialize_any(HumanReadableVisitor)
+                } else {
+                    let inner = <[u8; $len] as ::serde::Deserialize>::deserialize(deserializer)?;
+                    Ok($name(inner))
+                }
+            }
+        }
+    };
+    (@impls $name:ident, $len:expr) => {
         impl $name {
             #[allow(unused)]
             #[inline]
             pub fn as_array_ref(&self) -> &[u8; $len] {
                 &self.0
             }
+
+            /// Lowercase hex representation
+            #[allow(unused)]
+            pub fn to_hex(&self) -> String {
+                $crate::hex::to_hex(&self.0)
+            }
+
+            #[allow(unused)]
+            pub fn from_hex(s: &str) -> Result<$name, $crate::hex::HexError> {
+                let mut inner = [0x00u8; $len];
+                $crate::hex::from_hex(s, &mut inner)?;
+                Ok($name(inner))
+            }
+
+            /// Bech32 representation, using the given human readable part
+            #[allow(unused)]
+            pub fn to_bech32(&self, hrp: &str) -> String {
+                $crate::bech32_codec::to_bech32(hrp, &self.0)
+            }
+
+            /// Parse a bech32 representation, expecting the given human readable part
+            #[allow(unused)]
+            pub fn from_bech32(
+                s: &str,
+                hrp: &str,
+            ) -> Result<$name, $crate::bech32_codec::DecodeError> {
+                let mut inner = [0x00u8; $len];
+                let s_hrp = $crate::bech32_codec::from_bech32(s, &mut inner)?;
+                if !s_hrp.eq_ignore_ascii_case(hrp) {
+                    return Err($crate::bech32_codec::DecodeError::InvalidHrp);
+                }
+                Ok($name(inner))
+            }
+        }
+        impl $crate::canonical_serialize::CanonicalSerialize for $name {
+            fn canonical_serialize(&self) -> Vec<u8> {
+                self.0.to_vec()
+            }
+
+            fn canonical_serialize_into<W>(&self, writer: &mut W) -> ::std::io::Result<()>
+            where
+                W: ::std::io::Write,
+            {
+                writer.write_all(&self.0)
+            }
         }
         impl AsRef<[u8]> for $name {
             #[inline]
@@ -0,0 +1,77 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexError {
+    /// The hex string does not represent the expected amount of bytes
+    InvalidLength,
+    /// The hex string contains a character that is not a hex digit
+    InvalidChar,
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HexError::InvalidLength => write!(f, "invalid hex length"),
+            HexError::InvalidChar => write!(f, "invalid hex character"),
+        }
+    }
+}
+
+/// Encode bytes as a lowercase hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+fn hex_digit(c: u8) -> Result<u8, HexError> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(HexError::InvalidChar),
+    }
+}
+
+/// Decode a hex string (Lowercase or uppercase) into exactly `out.len()` bytes.
+pub fn from_hex(s: &str, out: &mut [u8]) -> Result<(), HexError> {
+    let s_bytes = s.as_bytes();
+    if s_bytes.len() != out.len() * 2 {
+        return Err(HexError::InvalidLength);
+    }
+    for (i, pair) in s_bytes.chunks(2).enumerate() {
+        out[i] = (hex_digit(pair[0])? << 4) | hex_digit(pair[1])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = [0x00u8, 0x01, 0x7f, 0x80, 0xab, 0xff];
+        let hex_str = to_hex(&bytes);
+        assert_eq!(hex_str, "00017f80abff");
+
+        let mut out = [0u8; 6];
+        from_hex(&hex_str, &mut out).unwrap();
+        assert_eq!(out, bytes);
+
+        // Uppercase is accepted too:
+        let mut out = [0u8; 6];
+        from_hex("00017F80ABFF", &mut out).unwrap();
+        assert_eq!(out, bytes);
+    }
+
+    #[test]
+    fn test_from_hex_errors() {
+        let mut out = [0u8; 2];
+        assert_eq!(from_hex("abc", &mut out), Err(HexError::InvalidLength));
+        assert_eq!(from_hex("abcdef", &mut out), Err(HexError::InvalidLength));
+        assert_eq!(from_hex("abcg", &mut out), Err(HexError::InvalidChar));
+    }
+}
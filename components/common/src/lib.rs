@@ -24,14 +24,17 @@ pub mod define_fixed_bytes;
 // pub mod frame_codec;
 pub mod access_control;
 pub mod async_test_utils;
+pub mod bech32_codec;
 pub mod caller_info;
 pub mod canonical_serialize;
 pub mod conn;
 pub mod dummy_connector;
 pub mod dummy_listener;
 pub mod futures_compat;
+pub mod hex;
 pub mod multi_consumer;
 pub mod mutable_state;
+pub mod priority_stream;
 pub mod select_streams;
 pub mod state_service;
 pub mod transform_pool;
@@ -0,0 +1,90 @@
+use futures::task::Context;
+use futures::{Poll, Stream, StreamExt};
+use std::pin::Pin;
+
+use crate::select_streams::BoxStream;
+
+/// Merges a few streams, ordered by priority.
+/// The first stream has the highest priority. An item is returned from a stream only if all the
+/// streams of higher priority have no ready items.
+pub struct PriorityStream<'a, T> {
+    /// None marks a stream that was exhausted.
+    streams: Vec<Option<BoxStream<'a, T>>>,
+}
+
+impl<'a, T> Stream for PriorityStream<'a, T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Self::Item>> {
+        for opt_stream in &mut self.streams {
+            if let Some(stream) = opt_stream {
+                match stream.poll_next_unpin(context) {
+                    Poll::Ready(Some(t)) => return Poll::Ready(Some(t)),
+                    Poll::Ready(None) => *opt_stream = None,
+                    Poll::Pending => {}
+                }
+            }
+        }
+
+        if self.streams.iter().all(Option::is_none) {
+            // No more streams to poll:
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+pub fn priority_stream<'a, T>(streams: Vec<BoxStream<'a, T>>) -> PriorityStream<'a, T> {
+    PriorityStream {
+        streams: streams.into_iter().map(Some).collect(),
+    }
+}
+
+/// Merge the given streams into a PriorityStream.
+/// Streams are given in descending order of priority.
+#[macro_export]
+macro_rules! priority_stream {
+    ( $( $x:expr ),* ) => {
+        {
+            let mut streams_vec: Vec<BoxStream<'_,_>> = Vec::new();
+            $(
+                streams_vec.push(Box::pin($x));
+            )*
+            priority_stream(streams_vec)
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+    use futures::stream;
+
+    #[test]
+    fn test_priority_stream_basic() {
+        let s1 = stream::iter(vec![1, 2, 3u8]);
+        let s2 = stream::iter(vec![4, 5u8]);
+        let s3 = stream::iter(vec![6, 7, 8u8]);
+
+        let prioritized = priority_stream![s1, s2, s3];
+
+        let result = block_on(prioritized.collect::<Vec<u8>>());
+        assert_eq!(result, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_priority_stream_pending() {
+        // A pending high priority stream does not block lower priority streams:
+        let (sender, s1) = mpsc::unbounded();
+        sender.unbounded_send(1u8).unwrap();
+        let s2 = stream::iter(vec![2, 3u8]);
+
+        let prioritized = priority_stream![s1, s2];
+
+        let result = block_on(prioritized.take(3).collect::<Vec<u8>>());
+        assert_eq!(result, vec![1, 2, 3]);
+    }
+}
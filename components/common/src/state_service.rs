@@ -12,8 +12,12 @@ pub enum StateServiceError<E> {
     MutateError(E),
 }
 
+/// A predicate deciding which mutations are delivered to a subscriber.
+pub type MutationFilter<MU> = Box<dyn Fn(&MU) -> bool + Send>;
+
 pub struct StateRequest<ST, MU> {
     response_sender: oneshot::Sender<StateResponse<ST, MU>>,
+    opt_filter: Option<MutationFilter<MU>>,
 }
 
 #[derive(Debug)]
@@ -33,8 +37,31 @@ impl<ST, MU> StateClient<ST, MU> {
     }
 
     pub async fn request_state(&mut self) -> Result<StateResponse<ST, MU>, StateClientError> {
+        await!(self.send_request(None))
+    }
+
+    /// Subscribe to future mutations, receiving only mutations for which `predicate` returns
+    /// true. Other mutations are dropped by the service and never reach the subscriber.
+    pub async fn subscribe_filtered<P>(
+        &mut self,
+        predicate: P,
+    ) -> Result<mpsc::Receiver<MU>, StateClientError>
+    where
+        P: Fn(&MU) -> bool + Send + 'static,
+    {
+        let (_state, receiver) = await!(self.send_request(Some(Box::new(predicate))))?;
+        Ok(receiver)
+    }
+
+    async fn send_request(
+        &mut self,
+        opt_filter: Option<MutationFilter<MU>>,
+    ) -> Result<StateResponse<ST, MU>, StateClientError> {
         let (response_sender, response_receiver) = oneshot::channel();
-        let state_request = StateRequest { response_sender };
+        let state_request = StateRequest {
+            response_sender,
+            opt_filter,
+        };
 
         await!(self.request_sender.send(state_request))
             .map_err(|_| StateClientError::SendRequestError)?;
@@ -72,7 +99,7 @@ where
 
     let mut incoming = select(incoming_requests, incoming_mutations);
 
-    let mut senders: Vec<mpsc::Sender<MU>> = Vec::new();
+    let mut senders: Vec<(mpsc::Sender<MU>, Option<MutationFilter<MU>>)> = Vec::new();
     let mut incoming_requests_closed: bool = false;
 
     while let Some(event) = await!(incoming.next()) {
@@ -84,7 +111,7 @@ where
                     .send((state.clone(), receiver))
                     .is_ok()
                 {
-                    senders.push(sender);
+                    senders.push((sender, request.opt_filter));
                 }
             }
             Event::IncomingRequestsClosed => incoming_requests_closed = true,
@@ -96,10 +123,17 @@ where
 
                 // Update all clients about state change:
                 let mut new_senders = Vec::new();
-                for mut sender in senders {
+                for (mut sender, opt_filter) in senders {
+                    if let Some(filter) = &opt_filter {
+                        if !filter(&mutation) {
+                            // This client is not interested in this mutation:
+                            new_senders.push((sender, opt_filter));
+                            continue;
+                        }
+                    }
                     if await!(sender.send(mutation.clone())).is_ok() {
                         // We only retain the sender if no error have occurred:
-                        new_senders.push(sender)
+                        new_senders.push((sender, opt_filter))
                     }
                 }
                 senders = new_senders;
@@ -115,4 +149,71 @@ where
     Ok(())
 }
 
-// TODO: Add tests
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::FutureExt;
+
+    /// A state that sums all the incoming mutations.
+    #[derive(Debug, Clone)]
+    struct SumState(u64);
+
+    impl MutableState for SumState {
+        type Mutation = u64;
+        type MutateError = ();
+
+        fn mutate(&mut self, mutation: &u64) -> Result<(), ()> {
+            self.0 += *mutation;
+            Ok(())
+        }
+    }
+
+    async fn task_state_service_subscribe_filtered<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let (mut mutations_sender, incoming_mutations) = mpsc::channel(0);
+        spawner
+            .spawn(
+                state_service(incoming_requests, SumState(0), incoming_mutations)
+                    .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        let mut state_client = StateClient::new(requests_sender);
+        let (state, all_mutations) = await!(state_client.request_state()).unwrap();
+        assert_eq!(state.0, 0);
+        let even_mutations =
+            await!(state_client.subscribe_filtered(|mutation: &u64| mutation % 2 == 0)).unwrap();
+        drop(state_client);
+
+        spawner
+            .spawn(async move {
+                for mutation in 1..=6u64 {
+                    await!(mutations_sender.send(mutation)).unwrap();
+                }
+            })
+            .unwrap();
+
+        // Both subscribers must be read concurrently, as the service waits for every delivery:
+        let (received_all, received_even) = await!(future::join(
+            all_mutations.collect::<Vec<_>>(),
+            even_mutations.collect::<Vec<_>>()
+        ));
+
+        // The unfiltered subscriber gets all mutations:
+        assert_eq!(received_all, vec![1, 2, 3, 4, 5, 6]);
+        // The filtered subscriber only gets the mutations it is interested in:
+        assert_eq!(received_even, vec![2, 4, 6]);
+    }
+
+    #[test]
+    fn test_state_service_subscribe_filtered() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_state_service_subscribe_filtered(thread_pool.clone()));
+    }
+}
@@ -1,7 +1,10 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
 use futures::channel::mpsc;
 use futures::stream::select;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+use futures::{future, stream, Future, Sink, SinkExt, Stream, StreamExt};
 
 use crate::conn::FutTransform;
 
@@ -14,18 +17,59 @@ enum TransformPoolEvent<I> {
     Incoming(I),
     IncomingClosed,
     TransformDone,
+    TimerTick,
+    TimerClosed,
 }
 
-/// Transform a stream of incoming items to outgoing items.
-/// The transformation is asynchronous, therefore outgoing items
-/// might not be in the same order in which the incoming items entered.
-///
-/// max_concurrent is the maximum amount of concurrent transformations.
-pub async fn transform_pool_loop<IN, OUT, I, O, T, S>(
+/// Allows monitoring a running transform pool.
+#[derive(Debug, Clone, Default)]
+pub struct TransformPoolHandle {
+    pool_size: Arc<AtomicUsize>,
+}
+
+impl TransformPoolHandle {
+    /// Amount of transformations currently running in the pool.
+    pub fn pool_size(&self) -> usize {
+        self.pool_size.load(Ordering::Relaxed)
+    }
+}
+
+fn spawn_transform<IN, OUT, O, T, S>(
+    input_value: IN,
+    outgoing: &O,
+    transform: &T,
+    close_sender: &mpsc::Sender<()>,
+    spawner: &mut S,
+) -> Result<(), TransformPoolLoopError>
+where
+    IN: Send + 'static,
+    OUT: Send,
+    T: FutTransform<Input = IN, Output = Option<OUT>> + Clone + Send + 'static,
+    O: Sink<OUT> + Clone + Send + Unpin + 'static,
+    S: Spawn,
+{
+    let mut c_outgoing = outgoing.clone();
+    let mut c_transform = transform.clone();
+    let mut c_close_sender = close_sender.clone();
+    let fut = async move {
+        if let Some(output_value) = await!(c_transform.transform(input_value)) {
+            let _ = await!(c_outgoing.send(output_value));
+        }
+        let _ = await!(c_close_sender.send(()));
+    };
+    spawner
+        .spawn(fut)
+        .map_err(|_| TransformPoolLoopError::SpawnError)
+}
+
+async fn inner_transform_pool_loop<IN, OUT, I, O, T, TS, S>(
     incoming: I,
     outgoing: O,
     transform: T,
     max_concurrent: usize,
+    timer_stream: TS,
+    wait_ticks: usize,
+    pool_size: Arc<AtomicUsize>,
     mut spawner: S,
 ) -> Result<(), TransformPoolLoopError>
 where
@@ -34,9 +78,10 @@ where
     T: FutTransform<Input = IN, Output = Option<OUT>> + Clone + Send + 'static,
     I: Stream<Item = IN> + Unpin,
     O: Sink<OUT> + Clone + Send + Unpin + 'static,
+    TS: Stream + Unpin,
     S: Spawn,
 {
-    let incoming = incoming
+    let mut incoming = incoming
         .map(TransformPoolEvent::Incoming)
         .chain(stream::once(future::ready(
             TransformPoolEvent::IncomingClosed,
@@ -45,38 +90,79 @@ where
     let (close_sender, close_receiver) = mpsc::channel::<()>(0);
     let close_receiver = close_receiver.map(|()| TransformPoolEvent::TransformDone);
 
-    let mut incoming_events = select(incoming, close_receiver);
+    let timer_stream = timer_stream
+        .map(|_| TransformPoolEvent::TimerTick)
+        .chain(stream::once(future::ready(TransformPoolEvent::TimerClosed)));
+
+    let mut other_events = select(close_receiver, timer_stream);
     let mut num_concurrent: usize = 0;
     let mut incoming_closed = false;
-    while let Some(event) = await!(incoming_events.next()) {
+    // An incoming value waiting for a free slot, together with the amount of ticks left to wait:
+    let mut opt_waiting: Option<(IN, usize)> = None;
+
+    loop {
+        // While a value is waiting for a free slot we do not read incoming values,
+        // so that the pressure is propagated back to the sender:
+        let opt_event = if opt_waiting.is_some() {
+            await!(other_events.next())
+        } else {
+            await!(select(&mut incoming, &mut other_events).next())
+        };
+        let event = match opt_event {
+            Some(event) => event,
+            None => break,
+        };
+
         match event {
             TransformPoolEvent::Incoming(input_value) => {
-                if num_concurrent >= max_concurrent {
+                if num_concurrent < max_concurrent {
+                    spawn_transform(
+                        input_value,
+                        &outgoing,
+                        &transform,
+                        &close_sender,
+                        &mut spawner,
+                    )?;
+                    num_concurrent = num_concurrent.checked_add(1).unwrap();
+                } else if wait_ticks > 0 {
+                    opt_waiting = Some((input_value, wait_ticks));
+                } else {
                     warn!("transform_pool_loop: Dropping connection: max_concurrent exceeded");
                     // We drop the input value because we don't have any room to process it.
-                    continue;
                 }
-                num_concurrent = num_concurrent.checked_add(1).unwrap();
-                let mut c_outgoing = outgoing.clone();
-                let mut c_transform = transform.clone();
-                let mut c_close_sender = close_sender.clone();
-                let fut = async move {
-                    if let Some(output_value) = await!(c_transform.transform(input_value)) {
-                        let _ = await!(c_outgoing.send(output_value));
-                    }
-                    let _ = await!(c_close_sender.send(()));
-                };
-                spawner
-                    .spawn(fut)
-                    .map_err(|_| TransformPoolLoopError::SpawnError)?;
             }
             TransformPoolEvent::IncomingClosed => {
                 incoming_closed = true;
             }
             TransformPoolEvent::TransformDone => {
                 num_concurrent = num_concurrent.checked_sub(1).unwrap();
+                if let Some((input_value, _ticks_left)) = opt_waiting.take() {
+                    spawn_transform(
+                        input_value,
+                        &outgoing,
+                        &transform,
+                        &close_sender,
+                        &mut spawner,
+                    )?;
+                    num_concurrent = num_concurrent.checked_add(1).unwrap();
+                }
+            }
+            TransformPoolEvent::TimerTick => {
+                if let Some((_input_value, ticks_left)) = &mut opt_waiting {
+                    *ticks_left = ticks_left.saturating_sub(1);
+                    if *ticks_left == 0 {
+                        warn!("transform_pool_loop: Dropping connection: No free slot in time");
+                        opt_waiting = None;
+                    }
+                }
+            }
+            TransformPoolEvent::TimerClosed => {
+                warn!("transform_pool_loop: Timer closed");
+                break;
             }
         }
+        pool_size.store(num_concurrent, Ordering::Relaxed);
+
         if incoming_closed && num_concurrent == 0 {
             break;
         }
@@ -84,7 +170,80 @@ where
     Ok(())
 }
 
-// TODO: Add tests!
+/// Transform a stream of incoming items to outgoing items.
+/// The transformation is asynchronous, therefore outgoing items
+/// might not be in the same order in which the incoming items entered.
+///
+/// max_concurrent is the maximum amount of concurrent transformations.
+/// When max_concurrent transformations are running, the next incoming item waits (up to
+/// `wait_ticks` ticks of `timer_stream`) for a running transformation to finish. No more incoming
+/// items are read while an item is waiting. If the wait times out, the waiting item is dropped.
+pub async fn transform_pool_loop<IN, OUT, I, O, T, TS, S>(
+    incoming: I,
+    outgoing: O,
+    transform: T,
+    max_concurrent: usize,
+    timer_stream: TS,
+    wait_ticks: usize,
+    spawner: S,
+) -> Result<(), TransformPoolLoopError>
+where
+    IN: Send + 'static,
+    OUT: Send,
+    T: FutTransform<Input = IN, Output = Option<OUT>> + Clone + Send + 'static,
+    I: Stream<Item = IN> + Unpin,
+    O: Sink<OUT> + Clone + Send + Unpin + 'static,
+    TS: Stream + Unpin,
+    S: Spawn,
+{
+    await!(inner_transform_pool_loop(
+        incoming,
+        outgoing,
+        transform,
+        max_concurrent,
+        timer_stream,
+        wait_ticks,
+        Arc::new(AtomicUsize::new(0)),
+        spawner
+    ))
+}
+
+/// Like `transform_pool_loop`, but also returns a `TransformPoolHandle` that can be used to
+/// monitor the pool.
+pub fn transform_pool<IN, OUT, I, O, T, TS, S>(
+    incoming: I,
+    outgoing: O,
+    transform: T,
+    max_concurrent: usize,
+    timer_stream: TS,
+    wait_ticks: usize,
+    spawner: S,
+) -> (
+    TransformPoolHandle,
+    impl Future<Output = Result<(), TransformPoolLoopError>>,
+)
+where
+    IN: Send + 'static,
+    OUT: Send,
+    T: FutTransform<Input = IN, Output = Option<OUT>> + Clone + Send + 'static,
+    I: Stream<Item = IN> + Unpin,
+    O: Sink<OUT> + Clone + Send + Unpin + 'static,
+    TS: Stream + Unpin,
+    S: Spawn,
+{
+    let pool_handle = TransformPoolHandle::default();
+    let pool_fut = inner_transform_pool_loop(
+        incoming,
+        outgoing,
+        transform,
+        max_concurrent,
+        timer_stream,
+        wait_ticks,
+        pool_handle.pool_size.clone(),
+        spawner,
+    );
+    (pool_handle, pool_fut)
+}
 
 /*
 
@@ -121,3 +280,126 @@ where
     Ok((input_sender, output_receiver))
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::oneshot;
+    use futures::executor::ThreadPool;
+
+    use crate::conn::BoxFuture;
+
+    /// Outputs the input value once the release channel is triggered.
+    #[derive(Clone)]
+    struct WaitTransform;
+
+    impl FutTransform for WaitTransform {
+        type Input = (u32, oneshot::Receiver<()>);
+        type Output = Option<u32>;
+
+        fn transform(&mut self, input: Self::Input) -> BoxFuture<'_, Self::Output> {
+            let (value, release) = input;
+            Box::pin(async move {
+                await!(release).ok()?;
+                Some(value)
+            })
+        }
+    }
+
+    async fn task_transform_pool_wait<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_sender, incoming) = mpsc::channel(0);
+        let (outgoing, mut outgoing_receiver) = mpsc::channel(0);
+        let (_tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let pool_fut = transform_pool_loop(
+            incoming,
+            outgoing,
+            WaitTransform,
+            1,
+            timer_stream,
+            2,
+            spawner.clone(),
+        );
+        let pool_handle = spawner.spawn_with_handle(pool_fut).unwrap();
+
+        let (release_sender1, release1) = oneshot::channel();
+        let (release_sender2, release2) = oneshot::channel();
+        await!(incoming_sender.send((1, release1))).unwrap();
+        // The pool is full. This value waits until the first transformation is done:
+        await!(incoming_sender.send((2, release2))).unwrap();
+
+        release_sender1.send(()).unwrap();
+        assert_eq!(await!(outgoing_receiver.next()).unwrap(), 1);
+        release_sender2.send(()).unwrap();
+        assert_eq!(await!(outgoing_receiver.next()).unwrap(), 2);
+
+        drop(incoming_sender);
+        await!(pool_handle).unwrap();
+    }
+
+    #[test]
+    fn test_transform_pool_wait() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_transform_pool_wait(thread_pool.clone()));
+    }
+
+    async fn task_transform_pool_wait_timeout<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_sender, incoming) = mpsc::channel(0);
+        let (outgoing, mut outgoing_receiver) = mpsc::channel(0);
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (pool_handle, pool_fut) = transform_pool(
+            incoming,
+            outgoing,
+            WaitTransform,
+            1,
+            timer_stream,
+            2,
+            spawner.clone(),
+        );
+        let pool_fut_handle = spawner.spawn_with_handle(pool_fut).unwrap();
+
+        let (release_sender1, release1) = oneshot::channel();
+        let (release_sender2, release2) = oneshot::channel();
+        let (release_sender3, release3) = oneshot::channel();
+        let (release_sender4, release4) = oneshot::channel();
+        await!(incoming_sender.send((1, release1))).unwrap();
+        await!(incoming_sender.send((2, release2))).unwrap();
+
+        // The second value waits for 2 ticks, and is then dropped:
+        await!(tick_sender.send(())).unwrap();
+        await!(tick_sender.send(())).unwrap();
+
+        // No incoming values are read while the second value waits.
+        // Sending the fourth value completes only after the third value was read, which happens
+        // only after the second value was dropped:
+        await!(incoming_sender.send((3, release3))).unwrap();
+        await!(incoming_sender.send((4, release4))).unwrap();
+        assert!(release_sender2.send(()).is_err());
+        assert_eq!(pool_handle.pool_size(), 1);
+
+        release_sender1.send(()).unwrap();
+        assert_eq!(await!(outgoing_receiver.next()).unwrap(), 1);
+        release_sender3.send(()).unwrap();
+        assert_eq!(await!(outgoing_receiver.next()).unwrap(), 3);
+
+        // The fourth value is transformed after the third one is done. It is never released:
+        drop(release_sender4);
+        drop(incoming_sender);
+        await!(pool_fut_handle).unwrap();
+        assert_eq!(pool_handle.pool_size(), 0);
+    }
+
+    #[test]
+    fn test_transform_pool_wait_timeout() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_transform_pool_wait_timeout(thread_pool.clone()));
+    }
+}
@@ -9,7 +9,46 @@ pub const RAND_VALUE_LEN: usize = 16;
 
 define_fixed_bytes!(RandValue, RAND_VALUE_LEN);
 
-pub trait CryptoRandom: SecureRandom + Sync + Send {}
+pub trait CryptoRandom: SecureRandom + Sync + Send {
+    /// Erase the concrete type of this random generator.
+    fn into_dyn(self) -> DynRandom
+    where
+        Self: Sized + 'static,
+    {
+        DynRandom::new(self)
+    }
+}
+
+/// A type erased cryptographic random generator.
+/// Useful where threading the random generator as a type parameter is inconvenient.
+/// Clones share the same underlying generator.
+#[derive(Clone)]
+pub struct DynRandom {
+    inner: Arc<dyn CryptoRandom>,
+}
+
+impl DynRandom {
+    pub fn new<R>(rng: R) -> DynRandom
+    where
+        R: CryptoRandom + 'static,
+    {
+        DynRandom {
+            inner: Arc::new(rng),
+        }
+    }
+}
+
+impl SecureRandom for DynRandom {
+    fn fill(&self, dest: &mut [u8]) -> Result<(), Unspecified> {
+        self.inner.fill(dest)
+    }
+}
+
+impl CryptoRandom for DynRandom {
+    fn into_dyn(self) -> DynRandom {
+        self
+    }
+}
 
 pub struct RngContainer<R> {
     arc_rng: Arc<R>,
@@ -47,11 +86,11 @@ impl<R> Deref for RngContainer<R> {
     }
 }
 
-pub type OffstSystemRandom = RngContainer<SystemRandom>;
+pub type OffstSystemRandom = DynRandom;
 
 /// Returns a secure cryptographic random generator
 pub fn system_random() -> OffstSystemRandom {
-    RngContainer::new(SystemRandom::new())
+    RngContainer::new(SystemRandom::new()).into_dyn()
 }
 
 impl RandValue {
@@ -149,4 +188,23 @@ mod tests {
         assert!(!rand_values_store.contains(&rand_value));
         assert!(!rand_values_store.contains(&rand_value0));
     }
+
+    #[test]
+    fn test_dyn_random() {
+        let rng = DummyRandom::new(&[1, 2, 3, 4, 6]);
+        let dyn_rng = rng.clone().into_dyn();
+
+        // The type erased generator produces the same values as the original one:
+        assert_eq!(RandValue::new(&dyn_rng), RandValue::new(&rng));
+
+        // Clones share the same underlying generator:
+        let dyn_rng_clone = dyn_rng.clone();
+        assert_ne!(RandValue::new(&dyn_rng), RandValue::new(&dyn_rng_clone));
+
+        // Erasing the type again keeps using the same generator:
+        let dyn_rng = dyn_rng.into_dyn();
+        let _ = RandValue::new(&rng);
+        let _ = RandValue::new(&rng);
+        assert_eq!(RandValue::new(&dyn_rng), RandValue::new(&rng));
+    }
 }
@@ -1,31 +1,60 @@
 use ring::agreement::{self, EphemeralPrivateKey};
 use ring::digest;
-use ring::hkdf::extract_and_expand;
-use ring::hmac::SigningKey;
+use ring::hkdf;
+use ring::hmac::{self, SigningKey};
 use ring::rand::SecureRandom;
+use zeroize::{Zeroize, Zeroizing};
 
 use super::sym_encrypt::{SymmetricKey, SYMMETRIC_KEY_LEN};
+use super::utils::constant_time_eq;
 use super::CryptoError;
 
 pub const SALT_LEN: usize = 32;
 pub const DH_PUBLIC_KEY_LEN: usize = 32;
 pub const SHARED_SECRET_LEN: usize = 32;
+/// Length of the output of the hash function used by HKDF (SHA-512/256)
+pub const HKDF_HASH_LEN: usize = 32;
 
-define_fixed_bytes!(Salt, SALT_LEN);
-define_fixed_bytes!(DhPublicKey, DH_PUBLIC_KEY_LEN);
+define_fixed_bytes!(Salt, SALT_LEN, eq = constant_time_eq);
+define_fixed_bytes!(DhPublicKey, DH_PUBLIC_KEY_LEN, eq = constant_time_eq);
 
 impl Salt {
     pub fn new<R: SecureRandom>(crypt_rng: &R) -> Result<Salt, CryptoError> {
         let mut salt = Salt::default();
 
-        if crypt_rng.fill(&mut salt).is_ok() {
-            Ok(salt)
-        } else {
-            Err(CryptoError)
-        }
+        crypt_rng.fill(&mut salt)?;
+        Ok(salt)
     }
 }
 
+/// HKDF extract step (RFC 5869), using HMAC-SHA-512/256.
+/// Turns input keying material (For example, a DH shared secret) into a pseudorandom key.
+/// `ring::hkdf::extract()` does not expose the bytes of the pseudorandom key, so we compute the
+/// HMAC directly (This is the definition of the extract step).
+/// The caller should zeroize the returned key after use.
+pub fn hkdf_extract(salt: &Salt, ikm: &[u8]) -> [u8; HKDF_HASH_LEN] {
+    let salt_key = SigningKey::new(&digest::SHA512_256, salt);
+    let mut prk = [0u8; HKDF_HASH_LEN];
+    prk.copy_from_slice(hmac::sign(&salt_key, ikm).as_ref());
+    prk
+}
+
+/// HKDF expand step (RFC 5869), using HMAC-SHA-512/256.
+/// Derives `len` bytes of output keying material from a pseudorandom key.
+/// The output is zeroized when dropped.
+///
+/// # Panics
+///
+/// Panics if `len` is larger than `255 * HKDF_HASH_LEN`.
+pub fn hkdf_expand(prk: &[u8; HKDF_HASH_LEN], info: &[u8], len: usize) -> Zeroizing<Vec<u8>> {
+    assert!(len <= 255 * HKDF_HASH_LEN, "hkdf_expand: len is too large");
+
+    let prk_key = SigningKey::new(&digest::SHA512_256, prk);
+    let mut okm = Zeroizing::new(vec![0u8; len]);
+    hkdf::expand(&prk_key, info, &mut okm);
+    okm
+}
+
 pub struct DhPrivateKey(EphemeralPrivateKey);
 
 impl DhPrivateKey {
@@ -42,35 +71,28 @@ impl DhPrivateKey {
     pub fn compute_public_key(&self) -> Result<DhPublicKey, CryptoError> {
         let mut public_key = DhPublicKey([0_u8; DH_PUBLIC_KEY_LEN]);
 
-        if self.0.compute_public_key(&mut public_key).is_ok() {
-            Ok(public_key)
-        } else {
-            Err(CryptoError)
-        }
+        self.0.compute_public_key(&mut public_key)?;
+        Ok(public_key)
     }
 
-    /// Derive a symmetric key from our private key and remote's public key.
-    pub fn derive_symmetric_key(
+    /// Compute the raw DH shared secret.
+    /// The shared secret should never be used directly as a key. See `derive_symmetric_key`.
+    fn derive_shared_secret(
         self,
         remote_public_key: DhPublicKey,
-        sent_salt: Salt,
-        recv_salt: Salt,
-    ) -> Result<(SymmetricKey, SymmetricKey), CryptoError> {
+    ) -> Result<[u8; SHARED_SECRET_LEN], CryptoError> {
         let u_remote_public_key = untrusted::Input::from(&remote_public_key);
 
-        let kdf = |shared_key: &[u8]| -> Result<(SymmetricKey, SymmetricKey), CryptoError> {
+        let copy_secret = |shared_key: &[u8]| -> Result<[u8; SHARED_SECRET_LEN], CryptoError> {
             if shared_key.len() != SHARED_SECRET_LEN {
-                Err(CryptoError)
+                Err(CryptoError::InvalidLength {
+                    expected: SHARED_SECRET_LEN,
+                    actual: shared_key.len(),
+                })
             } else {
-                let sent_sk = SigningKey::new(&digest::SHA512_256, &sent_salt);
-                let recv_sk = SigningKey::new(&digest::SHA512_256, &recv_salt);
-
-                let mut send_key = [0x00u8; SYMMETRIC_KEY_LEN];
-                let mut recv_key = [0x00u8; SYMMETRIC_KEY_LEN];
-                extract_and_expand(&sent_sk, shared_key, &[], &mut send_key);
-                extract_and_expand(&recv_sk, shared_key, &[], &mut recv_key);
-
-                Ok((SymmetricKey::from(&send_key), SymmetricKey::from(&recv_key)))
+                let mut shared_secret = [0x00u8; SHARED_SECRET_LEN];
+                shared_secret.copy_from_slice(shared_key);
+                Ok(shared_secret)
             }
         };
 
@@ -78,10 +100,33 @@ impl DhPrivateKey {
             self.0,
             &agreement::X25519,
             u_remote_public_key,
-            CryptoError,
-            kdf,
+            CryptoError::Unspecified(ring::error::Unspecified),
+            copy_secret,
         )
     }
+
+    /// Derive a symmetric key from our private key and remote's public key.
+    pub fn derive_symmetric_key(
+        self,
+        remote_public_key: DhPublicKey,
+        sent_salt: Salt,
+        recv_salt: Salt,
+    ) -> Result<(SymmetricKey, SymmetricKey), CryptoError> {
+        let mut shared_secret = self.derive_shared_secret(remote_public_key)?;
+
+        let derive_key = |salt: &Salt| {
+            let mut prk = hkdf_extract(salt, &shared_secret);
+            let okm = hkdf_expand(&prk, &[], SYMMETRIC_KEY_LEN);
+            prk[..].zeroize();
+            let mut key = SymmetricKey::default();
+            key.copy_from_slice(&okm);
+            key
+        };
+
+        let keys = (derive_key(&sent_salt), derive_key(&recv_salt));
+        shared_secret[..].zeroize();
+        Ok(keys)
+    }
 }
 
 #[cfg(test)]
@@ -124,4 +169,54 @@ mod tests {
         assert_eq!(send_key_a, recv_key_b);
         assert_eq!(send_key_b, recv_key_a)
     }
+
+    #[test]
+    fn test_hkdf_expand_dh_shared_secret() {
+        let rng = DummyRandom::new(&[1, 2, 3, 4, 7]);
+        let dh_private_a = DhPrivateKey::new(&rng).unwrap();
+        let dh_private_b = DhPrivateKey::new(&rng).unwrap();
+
+        let public_key_a = dh_private_a.compute_public_key().unwrap();
+        let public_key_b = dh_private_b.compute_public_key().unwrap();
+        let salt = Salt::new(&rng).unwrap();
+
+        let prk_a = hkdf_extract(
+            &salt,
+            &dh_private_a.derive_shared_secret(public_key_b).unwrap(),
+        );
+        let prk_b = hkdf_extract(
+            &salt,
+            &dh_private_b.derive_shared_secret(public_key_a).unwrap(),
+        );
+
+        // Same info results in the same key on both sides:
+        let key_a = hkdf_expand(&prk_a, b"info", 32);
+        let key_b = hkdf_expand(&prk_b, b"info", 32);
+        assert_eq!(*key_a, *key_b);
+
+        // Different info results in a different key:
+        let other_key_a = hkdf_expand(&prk_a, b"other info", 32);
+        assert_ne!(*key_a, *other_key_a);
+    }
+
+    #[test]
+    fn test_hkdf_matches_ring() {
+        let salt = Salt::from(&[3u8; SALT_LEN]);
+        let ikm = [5u8; SHARED_SECRET_LEN];
+        let info = b"some info";
+
+        // Lengths that are not multiples of the hash length require truncating the last block:
+        for &len in &[1, HKDF_HASH_LEN, HKDF_HASH_LEN + 1, 3 * HKDF_HASH_LEN + 5] {
+            let mut expected = vec![0u8; len];
+            ring::hkdf::extract_and_expand(
+                &SigningKey::new(&digest::SHA512_256, &salt),
+                &ikm,
+                info,
+                &mut expected,
+            );
+
+            let prk = hkdf_extract(&salt, &ikm);
+            assert_eq!(*hkdf_expand(&prk, info, len), expected);
+        }
+    }
 }
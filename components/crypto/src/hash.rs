@@ -1,8 +1,11 @@
-use ring::digest::{digest, SHA512_256};
+use std::io::{self, Write};
+
+use crate::utils::constant_time_eq;
+use ring::digest::{digest, Context, SHA512_256};
 
 pub const HASH_RESULT_LEN: usize = 32;
 
-define_fixed_bytes!(HashResult, HASH_RESULT_LEN);
+define_fixed_bytes!(HashResult, HASH_RESULT_LEN, eq = constant_time_eq);
 
 /// Calculate SHA512/256 over the given data.
 pub fn sha_512_256(data: &[u8]) -> HashResult {
@@ -14,6 +17,66 @@ pub fn sha_512_256(data: &[u8]) -> HashResult {
     HashResult(inner)
 }
 
+/// Incremental SHA512/256 calculation.
+/// Allows hashing a message that is given in parts, without buffering the whole message.
+#[derive(Clone)]
+pub struct HashState {
+    context: Context,
+}
+
+impl HashState {
+    pub fn new() -> Self {
+        HashState {
+            context: Context::new(&SHA512_256),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.context.update(data);
+    }
+
+    pub fn finalize(self) -> HashResult {
+        let mut inner = [0x00; HASH_RESULT_LEN];
+        inner.copy_from_slice(self.context.finish().as_ref());
+        HashResult(inner)
+    }
+}
+
+impl Default for HashState {
+    fn default() -> Self {
+        HashState::new()
+    }
+}
+
+/// A `Write` adapter over `HashState`. Everything written is fed into the hash.
+#[derive(Clone, Default)]
+pub struct HashWriter {
+    hash_state: HashState,
+}
+
+impl HashWriter {
+    pub fn finalize(self) -> HashResult {
+        self.hash_state.finalize()
+    }
+}
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hash_state.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Create a writer that calculates SHA512/256 over everything written into it.
+/// Call `finalize()` to obtain the result.
+pub fn hash_writer() -> HashWriter {
+    HashWriter::default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -32,4 +95,38 @@ mod tests {
 
         assert_eq!(hash_res.as_ref(), expected);
     }
+
+    #[test]
+    fn hash_result_hex_round_trip() {
+        let hash_res = sha_512_256(b"This is a test!");
+        let hex_str = hash_res.to_hex();
+        assert_eq!(
+            hex_str,
+            "349c7ea7498d0432dcb0604a9ed37a8b65a90bfa166c91475f072a29e42da1fb"
+        );
+        assert_eq!(HashResult::from_hex(&hex_str).unwrap(), hash_res);
+    }
+
+    #[test]
+    fn hash_state_matches_sha_512_256() {
+        let data = b"This is a test!";
+
+        let mut hash_state = HashState::new();
+        hash_state.update(&data[..4]);
+        hash_state.update(&[]);
+        hash_state.update(&data[4..]);
+        assert_eq!(hash_state.finalize(), sha_512_256(&data[..]));
+
+        assert_eq!(HashState::new().finalize(), sha_512_256(&[]));
+    }
+
+    #[test]
+    fn hash_writer_matches_sha_512_256() {
+        let data = b"This is a test!";
+
+        let mut writer = hash_writer();
+        writer.write_all(&data[..7]).unwrap();
+        writer.write_all(&data[7..]).unwrap();
+        assert_eq!(writer.finalize(), sha_512_256(&data[..]));
+    }
 }
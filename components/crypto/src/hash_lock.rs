@@ -1,5 +1,6 @@
 use ring::digest::{digest, SHA512_256};
 use ring::rand::SecureRandom;
+use zeroize::Zeroize;
 
 // TODO: Use bcrypt instead here
 
@@ -9,6 +10,19 @@ pub const HASHED_LOCK_LEN: usize = 32;
 define_fixed_bytes!(PlainLock, PLAIN_LOCK_LEN);
 define_fixed_bytes!(HashedLock, HASHED_LOCK_LEN);
 
+impl Zeroize for PlainLock {
+    fn zeroize(&mut self) {
+        self.0[..].zeroize();
+    }
+}
+
+/// Clear the secret lock from memory once it is not needed anymore
+impl Drop for PlainLock {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl PlainLock {
     /// Randomly generate a new PlainLock
     pub fn new<R: SecureRandom>(rng: &R) -> Self {
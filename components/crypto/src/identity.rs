@@ -1,16 +1,28 @@
 use derive_more::*;
 use ring::signature;
 use std::cmp::Ordering;
+use std::io::{self, Write};
+use zeroize::{Zeroize, Zeroizing};
 
 use super::CryptoError;
 use crate::crypto_rand::CryptoRandom;
 use crate::hash::sha_512_256;
+use crate::utils::constant_time_eq;
 use common::big_array::BigArray;
+use common::canonical_serialize::CanonicalSerialize;
+use common::hex::{self, HexError};
 
 pub const PUBLIC_KEY_LEN: usize = 32;
 pub const SIGNATURE_LEN: usize = 64;
+/// Human readable part used for the bech32 representation of public keys
+pub const PUBLIC_KEY_BECH32_HRP: &str = "offstpk";
 
-define_fixed_bytes!(PublicKey, PUBLIC_KEY_LEN);
+define_fixed_bytes!(
+    PublicKey,
+    PUBLIC_KEY_LEN,
+    eq = constant_time_eq,
+    bech32_hrp = PUBLIC_KEY_BECH32_HRP
+);
 
 #[derive(Clone, Serialize, Deserialize, From)]
 pub struct Signature(#[serde(with = "BigArray")] [u8; SIGNATURE_LEN]);
@@ -25,11 +37,40 @@ impl Signature {
     pub fn zero() -> Signature {
         Signature([0x00u8; SIGNATURE_LEN])
     }
+
+    /// Lowercase hex representation
+    pub fn to_hex(&self) -> String {
+        hex::to_hex(&self.0)
+    }
+
+    pub fn from_hex(s: &str) -> Result<Signature, HexError> {
+        let mut inner = [0x00u8; SIGNATURE_LEN];
+        hex::from_hex(s, &mut inner)?;
+        Ok(Signature(inner))
+    }
 }
 
-/// Generate a pkcs8 key pair
-pub fn generate_pkcs8_key_pair<R: CryptoRandom>(rng: &R) -> [u8; 85] {
-    ring::signature::Ed25519KeyPair::generate_pkcs8(rng).unwrap()
+impl CanonicalSerialize for Signature {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+
+    fn canonical_serialize_into<W>(&self, writer: &mut W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writer.write_all(&self.0)
+    }
+}
+
+/// Generate a pkcs8 key pair.
+/// The returned bytes are cleared from memory when dropped.
+pub fn generate_pkcs8_key_pair<R: CryptoRandom>(rng: &R) -> Zeroizing<Vec<u8>> {
+    let mut pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(rng).unwrap();
+    let pkcs8_vec = Zeroizing::new(pkcs8.to_vec());
+    // Don't leave a copy of the private key on the stack:
+    pkcs8[..].zeroize();
+    pkcs8_vec
 }
 
 /// A generic interface for signing and verifying messages.
@@ -49,7 +90,8 @@ pub struct SoftwareEd25519Identity {
 
 impl SoftwareEd25519Identity {
     pub fn from_pkcs8(pkcs8_bytes: &[u8]) -> Result<Self, CryptoError> {
-        let key_pair = signature::Ed25519KeyPair::from_pkcs8(untrusted::Input::from(pkcs8_bytes))?;
+        let key_pair = signature::Ed25519KeyPair::from_pkcs8(untrusted::Input::from(pkcs8_bytes))
+            .map_err(CryptoError::KeyRejected)?;
 
         Ok(SoftwareEd25519Identity { key_pair })
     }
@@ -63,6 +105,20 @@ pub fn verify_signature(message: &[u8], public_key: &PublicKey, signature: &Sign
     signature::verify(&signature::ED25519, public_key, message, signature).is_ok()
 }
 
+/// Verify a batch of (message, public_key, signature) entries.
+/// Returns true only if all the signatures are valid. An empty batch is valid.
+///
+/// ring does not expose batch verification yet, so every entry is verified separately.
+/// All the entries are verified even after a failure, to avoid revealing through timing
+/// which entry failed.
+pub fn batch_verify_signatures(messages: &[(&[u8], &PublicKey, &Signature)]) -> bool {
+    messages
+        .iter()
+        .fold(true, |all_valid, (message, public_key, signature)| {
+            verify_signature(message, public_key, signature) & all_valid
+        })
+}
+
 impl Identity for SoftwareEd25519Identity {
     fn sign(&self, message: &[u8]) -> Signature {
         let mut sig_array = [0; SIGNATURE_LEN];
@@ -150,12 +206,7 @@ impl<'a> ::std::convert::TryFrom<&'a ::bytes::Bytes> for Signature {
 impl PartialEq for Signature {
     #[inline]
     fn eq(&self, other: &Signature) -> bool {
-        for i in 0..SIGNATURE_LEN {
-            if self.0[i] != other.0[i] {
-                return false;
-            }
-        }
-        true
+        constant_time_eq(&self.0, &other.0)
     }
 }
 
@@ -170,7 +221,10 @@ impl ::std::fmt::Debug for Signature {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::CONSTANT_TIME_EQ_CALLS;
+    use common::bech32_codec::DecodeError;
     use ring::test::rand::FixedByteRandom;
+    use std::cell::Cell;
 
     #[test]
     fn test_get_public_key_sanity() {
@@ -214,4 +268,105 @@ mod tests {
 
         assert!(!verify_signature(message, &public_key2, &signature1));
     }
+
+    #[test]
+    fn test_batch_verify_signatures() {
+        let ids = (1..=3u8)
+            .map(|i| {
+                let secure_rand = FixedByteRandom { byte: i };
+                let pkcs8 = signature::Ed25519KeyPair::generate_pkcs8(&secure_rand).unwrap();
+                SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let messages: Vec<Vec<u8>> = (0..3u8).map(|i| vec![i; 10]).collect();
+        let public_keys = ids.iter().map(|id| id.get_public_key()).collect::<Vec<_>>();
+        let signatures = ids
+            .iter()
+            .zip(messages.iter())
+            .map(|(id, message)| id.sign(message))
+            .collect::<Vec<_>>();
+
+        let batch = (0..3)
+            .map(|i| (&messages[i][..], &public_keys[i], &signatures[i]))
+            .collect::<Vec<_>>();
+        assert!(batch_verify_signatures(&batch));
+        assert!(batch_verify_signatures(&[]));
+
+        // A single invalid entry fails the whole batch, regardless of its position:
+        for i in 0..3 {
+            let mut bad_batch = batch.clone();
+            bad_batch[i].1 = &public_keys[(i + 1) % 3];
+            assert!(!batch_verify_signatures(&bad_batch));
+        }
+    }
+
+    #[test]
+    fn test_public_key_hex_round_trip() {
+        let public_key = PublicKey::from(&[0xab; PUBLIC_KEY_LEN]);
+        let hex_str = public_key.to_hex();
+        assert_eq!(hex_str, "ab".repeat(PUBLIC_KEY_LEN));
+        assert_eq!(PublicKey::from_hex(&hex_str).unwrap(), public_key);
+        assert_eq!(PublicKey::from_hex("abab"), Err(HexError::InvalidLength));
+    }
+
+    #[test]
+    fn test_public_key_bech32_round_trip() {
+        let public_key = PublicKey::from(&[0xab; PUBLIC_KEY_LEN]);
+        let bech32_str = public_key.to_bech32(PUBLIC_KEY_BECH32_HRP);
+        assert!(bech32_str.starts_with("offstpk1"));
+        assert_eq!(
+            PublicKey::from_bech32(&bech32_str, PUBLIC_KEY_BECH32_HRP).unwrap(),
+            public_key
+        );
+
+        // A different human readable part is rejected:
+        let other_bech32_str = public_key.to_bech32("offstpid");
+        assert_eq!(
+            PublicKey::from_bech32(&other_bech32_str, PUBLIC_KEY_BECH32_HRP),
+            Err(DecodeError::InvalidHrp)
+        );
+
+        // Corrupt a single character. The checksum should catch it:
+        let mut corrupted = bech32_str.into_bytes();
+        let index = corrupted.len() / 2;
+        corrupted[index] = if corrupted[index] == b'q' { b'p' } else { b'q' };
+        let corrupted = String::from_utf8(corrupted).unwrap();
+        assert!(PublicKey::from_bech32(&corrupted, PUBLIC_KEY_BECH32_HRP).is_err());
+    }
+
+    #[test]
+    fn test_signature_hex_round_trip() {
+        let mut sig_array = [0u8; SIGNATURE_LEN];
+        for (i, byte) in sig_array.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let signature = Signature::from(&sig_array);
+        let hex_str = signature.to_hex();
+        assert_eq!(&hex_str[..8], "00010203");
+        assert_eq!(Signature::from_hex(&hex_str).unwrap(), signature);
+        assert!(Signature::from_hex(&hex_str[1..]).is_err());
+    }
+
+    #[test]
+    fn test_public_key_eq_is_constant_time() {
+        let public_key_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let public_key_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let calls_before = CONSTANT_TIME_EQ_CALLS.with(Cell::get);
+        assert!(public_key_a == public_key_a.clone());
+        assert!(public_key_a != public_key_b);
+        assert_eq!(CONSTANT_TIME_EQ_CALLS.with(Cell::get), calls_before + 2);
+    }
+
+    #[test]
+    fn test_signature_eq_is_constant_time() {
+        let signature_a = Signature::from(&[0xaa; SIGNATURE_LEN]);
+        let signature_b = Signature::from(&[0xbb; SIGNATURE_LEN]);
+
+        let calls_before = CONSTANT_TIME_EQ_CALLS.with(Cell::get);
+        assert!(signature_a == signature_a.clone());
+        assert!(signature_a != signature_b);
+        assert_eq!(CONSTANT_TIME_EQ_CALLS.with(Cell::get), calls_before + 2);
+    }
 }
@@ -2,6 +2,8 @@ use ring::rand::SecureRandom;
 use std::fmt;
 
 pub const INVOICE_ID_LEN: usize = 32;
+/// Human readable part used for the bech32 representation of invoice ids
+pub const INVOICE_ID_BECH32_HRP: &str = "offstinv";
 
 // An invoice identifier
 define_fixed_bytes!(InvoiceId, INVOICE_ID_LEN);
@@ -31,3 +33,19 @@ impl fmt::Display for InvoiceId {
         write!(f, "{}", self.format())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoice_id_bech32_round_trip() {
+        let invoice_id = InvoiceId::from(&[0x12; INVOICE_ID_LEN]);
+        let bech32_str = invoice_id.to_bech32(INVOICE_ID_BECH32_HRP);
+        assert!(bech32_str.starts_with(INVOICE_ID_BECH32_HRP));
+        assert_eq!(
+            InvoiceId::from_bech32(&bech32_str, INVOICE_ID_BECH32_HRP).unwrap(),
+            invoice_id
+        );
+    }
+}
@@ -23,16 +23,51 @@ pub mod payment_id;
 pub mod sym_encrypt;
 pub mod test_utils;
 pub mod uid;
+pub mod utils;
 
-use derive_more::*;
+use std::error::Error;
+use std::fmt;
 
-#[derive(Clone, Copy, Debug, PartialEq, Display)]
-#[display(fmt = "crypto error")]
-pub struct CryptoError;
+#[derive(Clone, Debug, PartialEq)]
+pub enum CryptoError {
+    /// An operation inside ring failed, without further details
+    Unspecified(::ring::error::Unspecified),
+    /// A key was rejected while being parsed
+    /// (ring 0.13 does not report why a key was rejected)
+    KeyRejected(::ring::error::Unspecified),
+    /// A buffer does not have the expected length
+    InvalidLength { expected: usize, actual: usize },
+    /// The nonce of an incoming message is not the one we expect
+    NonceMismatch,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptoError::Unspecified(_) => write!(f, "unspecified crypto error"),
+            CryptoError::KeyRejected(_) => write!(f, "key rejected"),
+            CryptoError::InvalidLength { expected, actual } => write!(
+                f,
+                "invalid length: expected {} bytes, got {} bytes",
+                expected, actual
+            ),
+            CryptoError::NonceMismatch => write!(f, "nonce mismatch"),
+        }
+    }
+}
+
+impl Error for CryptoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CryptoError::Unspecified(e) | CryptoError::KeyRejected(e) => Some(e),
+            CryptoError::InvalidLength { .. } | CryptoError::NonceMismatch => None,
+        }
+    }
+}
 
 impl From<::ring::error::Unspecified> for CryptoError {
-    fn from(_: ::ring::error::Unspecified) -> CryptoError {
-        CryptoError
+    fn from(e: ::ring::error::Unspecified) -> CryptoError {
+        CryptoError::Unspecified(e)
     }
 }
 
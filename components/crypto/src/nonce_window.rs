@@ -14,9 +14,14 @@
 /// ```
 pub struct NonceWindow {
     nonce: u128,
+    /// Amount of nonces (up to and including the current nonce) tracked by the window.
+    capacity: usize,
     blocks: Vec<u64>,
 }
 
+/// Capacity of a `NonceWindow` created using `NonceWindow::default()`
+pub const DEFAULT_NONCE_WINDOW_CAPACITY: usize = 256;
+
 pub trait WindowNonce: Into<u128> {}
 
 impl NonceWindow {
@@ -27,13 +32,31 @@ impl NonceWindow {
     /// Panics if the width not divisible by `64`.
     pub fn new(width: usize) -> NonceWindow {
         assert_eq!(width % 64, 0, "width should divisible by 64");
+        NonceWindow::with_capacity(width)
+    }
+
+    /// Constructs a new `NonceWindow` that tracks `capacity` nonces.
+    /// Unlike `new()`, any positive capacity is allowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the capacity is `0`.
+    pub fn with_capacity(capacity: usize) -> NonceWindow {
+        assert!(capacity > 0, "capacity should be positive");
 
         NonceWindow {
             nonce: 0,
-            blocks: vec![0; width / 64],
+            capacity,
+            // Round up to a whole amount of blocks:
+            blocks: vec![0; (capacity + 63) / 64],
         }
     }
 
+    /// The amount of nonces tracked by the window
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     /// Try to accept a nonce, returns `true` if the `nonce` was accepted.
     ///
     /// This function determine whether we should accept a nonce, we accept
@@ -45,12 +68,13 @@ impl NonceWindow {
     pub fn try_accept<T: WindowNonce>(&mut self, nonce: T) -> bool {
         let nonce = nonce.into();
 
+        let capacity = self.capacity as u128;
         let width = self.blocks.len() as u128 * 64;
 
         if nonce < self.nonce {
             let dif = self.nonce - nonce;
 
-            if dif >= width {
+            if dif >= capacity {
                 false
             } else {
                 !self.set(dif as usize)
@@ -82,6 +106,12 @@ impl NonceWindow {
     }
 }
 
+impl Default for NonceWindow {
+    fn default() -> Self {
+        NonceWindow::with_capacity(DEFAULT_NONCE_WINDOW_CAPACITY)
+    }
+}
+
 /// Logical left shift of the bits.
 #[inline]
 fn shift_left(blocks: &mut Vec<u64>, nbits: usize) {
@@ -131,6 +161,8 @@ mod tests {
 
     impl<'a> WindowNonce for &'a Nonce {}
 
+    impl WindowNonce for u128 {}
+
     #[test]
     fn test_shift_left() {
         let mut blocks = vec![0xf0f0_f0f0_f0f0_f0f0, 0xf0f0_f0f0_f0f0_f0f0];
@@ -179,4 +211,53 @@ mod tests {
 
         assert!(!window.try_accept(&out_of_range));
     }
+
+    /// Check the acceptance boundary of a window with the given capacity
+    fn check_capacity_boundary(capacity: usize) {
+        let mut window = NonceWindow::with_capacity(capacity);
+        assert_eq!(window.capacity(), capacity);
+
+        let top = capacity as u128 + 10;
+        assert!(window.try_accept(top));
+        assert!(!window.try_accept(top));
+
+        // All the nonces inside the window are accepted exactly once:
+        for dif in 1..capacity as u128 {
+            assert!(window.try_accept(top - dif));
+            assert!(!window.try_accept(top - dif));
+        }
+
+        // The first nonce outside the window is rejected:
+        assert!(!window.try_accept(top - capacity as u128));
+        assert!(!window.try_accept(0u128));
+    }
+
+    #[test]
+    fn test_nonce_window_with_capacity() {
+        check_capacity_boundary(1);
+        check_capacity_boundary(63);
+        check_capacity_boundary(65);
+        check_capacity_boundary(DEFAULT_NONCE_WINDOW_CAPACITY);
+        check_capacity_boundary(65536);
+
+        assert_eq!(
+            NonceWindow::default().capacity(),
+            DEFAULT_NONCE_WINDOW_CAPACITY
+        );
+        assert_eq!(NonceWindow::new(WINDOW_WIDTH).capacity(), WINDOW_WIDTH);
+    }
+
+    #[test]
+    fn test_nonce_window_capacity_after_shift() {
+        let capacity = 3;
+        let mut window = NonceWindow::with_capacity(capacity);
+        assert!(window.try_accept(5u128));
+        assert!(window.try_accept(3u128));
+
+        // Moving the window forward drops nonces that fell out of it:
+        assert!(window.try_accept(6u128));
+        assert!(!window.try_accept(3u128));
+        assert!(window.try_accept(4u128));
+        assert!(!window.try_accept(5u128));
+    }
 }
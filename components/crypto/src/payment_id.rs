@@ -2,6 +2,8 @@ use ring::rand::SecureRandom;
 use std::fmt;
 
 pub const PAYMENT_ID_LEN: usize = 16;
+/// Human readable part used for the bech32 representation of payment ids
+pub const PAYMENT_ID_BECH32_HRP: &str = "offstpay";
 
 define_fixed_bytes!(PaymentId, PAYMENT_ID_LEN);
 
@@ -31,3 +33,19 @@ impl fmt::Display for PaymentId {
         write!(f, "{}", self.format())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payment_id_bech32_round_trip() {
+        let payment_id = PaymentId::from(&[0x12; PAYMENT_ID_LEN]);
+        let bech32_str = payment_id.to_bech32(PAYMENT_ID_BECH32_HRP);
+        assert!(bech32_str.starts_with(PAYMENT_ID_BECH32_HRP));
+        assert_eq!(
+            PaymentId::from_bech32(&bech32_str, PAYMENT_ID_BECH32_HRP).unwrap(),
+            payment_id
+        );
+    }
+}
@@ -1,21 +1,119 @@
 use std::iter;
 
 use ring;
-use ring::aead::{open_in_place, seal_in_place, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::aead::{
+    self, open_in_place, seal_in_place, OpeningKey, SealingKey, AES_128_GCM, CHACHA20_POLY1305,
+};
+use zeroize::Zeroize;
 
 use super::{increase_nonce, CryptoError};
 
 pub const SYMMETRIC_KEY_LEN: usize = 32;
-// Length of tag for CHACHA20_POLY1305
+// Length of tag (The same for all the supported algorithms)
 const TAG_LEN: usize = 16;
-// Length of nonce for CHACHA20_POLY1305
+// Length of nonce (The same for all the supported algorithms)
 const ENC_NONCE_LEN: usize = 12;
 
+/// Authenticated encryption algorithm used with a symmetric key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymEncryptAlgorithm {
+    Aes128Gcm,
+    ChaCha20Poly1305,
+}
+
+impl SymEncryptAlgorithm {
+    fn aead_algorithm(self) -> &'static aead::Algorithm {
+        match self {
+            SymEncryptAlgorithm::Aes128Gcm => &AES_128_GCM,
+            SymEncryptAlgorithm::ChaCha20Poly1305 => &CHACHA20_POLY1305,
+        }
+    }
+}
+
+/// ChaCha20-Poly1305 was the only algorithm used before other algorithms were supported.
+impl Default for SymEncryptAlgorithm {
+    fn default() -> Self {
+        SymEncryptAlgorithm::ChaCha20Poly1305
+    }
+}
+
 define_fixed_bytes!(SymmetricKey, SYMMETRIC_KEY_LEN);
 
+impl Zeroize for SymmetricKey {
+    fn zeroize(&mut self) {
+        self.0[..].zeroize();
+    }
+}
+
+/// Clear the key bytes from memory once the key is not needed anymore
+impl Drop for SymmetricKey {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 #[derive(Clone)]
 pub struct EncryptNonce(pub [u8; ENC_NONCE_LEN]);
 
+/// A symmetric key, together with the algorithm it is used with.
+pub struct SymKey {
+    symmetric_key: SymmetricKey,
+    algorithm: SymEncryptAlgorithm,
+}
+
+impl SymKey {
+    pub fn new(symmetric_key: SymmetricKey, algorithm: SymEncryptAlgorithm) -> Self {
+        SymKey {
+            symmetric_key,
+            algorithm,
+        }
+    }
+
+    pub fn algorithm(&self) -> SymEncryptAlgorithm {
+        self.algorithm
+    }
+
+    /// The key bytes used by the algorithm.
+    /// Algorithms with shorter keys (AES-128-GCM) use a prefix of the symmetric key.
+    fn key_bytes(&self) -> &[u8] {
+        &self.symmetric_key[..self.algorithm.aead_algorithm().key_len()]
+    }
+
+    /// Encrypt `plain_msg`, and authenticate both `plain_msg` and `ad`.
+    /// Returns the encrypted message, followed by the authentication tag.
+    /// A nonce must never be used twice with the same key.
+    pub fn encrypt(
+        &self,
+        nonce: &EncryptNonce,
+        plain_msg: &[u8],
+        ad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let sealing_key = SealingKey::new(self.algorithm.aead_algorithm(), self.key_bytes())?;
+        let mut msg_buffer = plain_msg.to_vec();
+        // Extend the message with TAG_LEN zeroes. This leaves space for the tag:
+        msg_buffer.extend(iter::repeat(0).take(TAG_LEN));
+
+        let length = seal_in_place(&sealing_key, &nonce.0, ad, &mut msg_buffer, TAG_LEN)?;
+        msg_buffer.truncate(length);
+        Ok(msg_buffer)
+    }
+
+    /// Decrypt and authenticate a message created by `encrypt()`.
+    pub fn decrypt(
+        &self,
+        nonce: &EncryptNonce,
+        cipher_msg: &[u8],
+        ad: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let opening_key = OpeningKey::new(self.algorithm.aead_algorithm(), self.key_bytes())?;
+        let mut msg_buffer = cipher_msg.to_vec();
+
+        let length = open_in_place(&opening_key, &nonce.0, ad, 0, &mut msg_buffer)?.len();
+        msg_buffer.truncate(length);
+        Ok(msg_buffer)
+    }
+}
+
 pub struct EncryptNonceCounter {
     inner: EncryptNonce,
 }
@@ -50,17 +148,17 @@ impl Default for EncryptNonceCounter {
 /// A structure used for encrypting messages with a given symmetric key.
 /// Maintains internal state of an increasing nonce counter.
 pub struct Encryptor {
-    sealing_key: SealingKey,
+    sym_key: SymKey,
     nonce_counter: EncryptNonceCounter,
 }
 
 impl Encryptor {
     /// Create a new encryptor object. This object can encrypt messages.
-    pub fn new(symmetric_key: &SymmetricKey) -> Result<Self, CryptoError> {
-        Ok(Encryptor {
-            sealing_key: SealingKey::new(&CHACHA20_POLY1305, symmetric_key)?,
+    pub fn new(sym_key: SymKey) -> Self {
+        Encryptor {
+            sym_key,
             nonce_counter: EncryptNonceCounter::new(),
-        })
+        }
     }
 
     /// Encrypt a message. The nonce must be unique.
@@ -68,63 +166,53 @@ impl Encryptor {
         // Put the nonce in the beginning of the resulting buffer:
         let enc_nonce = self.nonce_counter.next_nonce();
         let mut msg_buffer = enc_nonce.0.to_vec();
-        msg_buffer.extend(plain_msg);
-        // Extend the message with TAG_LEN zeroes. This leaves space for the tag:
-        msg_buffer.extend(iter::repeat(0).take(TAG_LEN).collect::<Vec<u8>>());
-        let ad: [u8; 0] = [];
-
-        match seal_in_place(
-            &self.sealing_key,
-            &enc_nonce.0,
-            &ad,
-            &mut msg_buffer[ENC_NONCE_LEN..],
-            TAG_LEN,
-        ) {
-            Err(ring::error::Unspecified) => Err(CryptoError),
-            Ok(length) => Ok(msg_buffer[..ENC_NONCE_LEN + length].to_vec()),
-        }
+        msg_buffer.extend(self.sym_key.encrypt(&enc_nonce, plain_msg, &[])?);
+        Ok(msg_buffer)
     }
 }
 
 /// A structure used for decrypting messages with a given symmetric key.
 pub struct Decryptor {
-    opening_key: OpeningKey,
+    sym_key: SymKey,
     nonce_counter: EncryptNonceCounter,
 }
 
 impl Decryptor {
     /// Create a new decryptor object. This object can decrypt messages.
-    pub fn new(symmetric_key: &SymmetricKey) -> Result<Self, CryptoError> {
-        Ok(Decryptor {
-            opening_key: OpeningKey::new(&CHACHA20_POLY1305, symmetric_key)?,
+    pub fn new(sym_key: SymKey) -> Self {
+        Decryptor {
+            sym_key,
             nonce_counter: EncryptNonceCounter::new(),
-        })
+        }
     }
 
     /// Decrypt and authenticate a message.
     pub fn decrypt(&mut self, cipher_msg: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if cipher_msg.len() < ENC_NONCE_LEN {
+            return Err(CryptoError::InvalidLength {
+                expected: ENC_NONCE_LEN,
+                actual: cipher_msg.len(),
+            });
+        }
         let enc_nonce = &cipher_msg[..ENC_NONCE_LEN];
         if enc_nonce != self.nonce_counter.as_ref() {
             // Nonce doesn't match!
-            return Err(CryptoError);
+            return Err(CryptoError::NonceMismatch);
         }
 
-        let mut msg_buffer = cipher_msg[ENC_NONCE_LEN..].to_vec();
-        let ad: [u8; 0] = [];
-
-        match open_in_place(&self.opening_key, enc_nonce, &ad, 0, &mut msg_buffer) {
-            Ok(slice) => {
-                let _ = self.nonce_counter.next_nonce();
-                Ok(slice.to_vec())
-            }
-            Err(ring::error::Unspecified) => Err(CryptoError),
-        }
+        let enc_nonce = EncryptNonce(*self.nonce_counter.as_ref());
+        let plain_msg = self
+            .sym_key
+            .decrypt(&enc_nonce, &cipher_msg[ENC_NONCE_LEN..], &[])?;
+        let _ = self.nonce_counter.next_nonce();
+        Ok(plain_msg)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::error::Error;
 
     #[test]
     fn increase_nonce_basic() {
@@ -145,19 +233,78 @@ mod tests {
         assert_eq!(array_num, [0, 0, 0, 0]);
     }
 
+    fn sym_key(algorithm: SymEncryptAlgorithm) -> SymKey {
+        SymKey::new(SymmetricKey::from(&[1; SYMMETRIC_KEY_LEN]), algorithm)
+    }
+
     #[test]
     fn test_encryptor_decryptor() {
-        let symmetric_key = SymmetricKey::from(&[1; SYMMETRIC_KEY_LEN]);
+        for &algorithm in &[
+            SymEncryptAlgorithm::Aes128Gcm,
+            SymEncryptAlgorithm::ChaCha20Poly1305,
+        ] {
+            // let rng_seed: &[_] = &[1,2,3,4,5,6];
+            // let mut rng: StdRng = rand::SeedableRng::from_seed(rng_seed);
+            let mut encryptor = Encryptor::new(sym_key(algorithm));
+            let mut decryptor = Decryptor::new(sym_key(algorithm));
+
+            let plain_msg = b"Hello world!";
+            let cipher_msg = encryptor.encrypt(plain_msg).unwrap();
+            let decrypted_msg = decryptor.decrypt(&cipher_msg).unwrap();
+
+            assert_eq!(plain_msg, &decrypted_msg[..]);
+        }
+    }
+
+    #[test]
+    fn test_sym_key_algorithms() {
+        let nonce = EncryptNonce([2; ENC_NONCE_LEN]);
+        let aes_key = sym_key(SymEncryptAlgorithm::Aes128Gcm);
+        let chacha_key = sym_key(SymEncryptAlgorithm::ChaCha20Poly1305);
+
+        let cipher_msg = aes_key.encrypt(&nonce, b"hello", b"ad").unwrap();
+        assert_eq!(cipher_msg.len(), 5 + TAG_LEN);
+        assert_eq!(
+            aes_key.decrypt(&nonce, &cipher_msg, b"ad").unwrap(),
+            b"hello"
+        );
+
+        // The additional data must match:
+        assert!(aes_key.decrypt(&nonce, &cipher_msg, b"other").is_err());
+        // Both sides must use the same algorithm:
+        assert!(chacha_key.decrypt(&nonce, &cipher_msg, b"ad").is_err());
+    }
+
+    #[test]
+    fn test_decryptor_errors() {
+        let algorithm = SymEncryptAlgorithm::default();
+        let mut encryptor = Encryptor::new(sym_key(algorithm));
+        let mut decryptor = Decryptor::new(sym_key(algorithm));
 
-        // let rng_seed: &[_] = &[1,2,3,4,5,6];
-        // let mut rng: StdRng = rand::SeedableRng::from_seed(rng_seed);
-        let mut encryptor = Encryptor::new(&symmetric_key).unwrap();
-        let mut decryptor = Decryptor::new(&symmetric_key).unwrap();
+        // Message is too short to contain a nonce:
+        assert_eq!(
+            decryptor.decrypt(&[0u8; 3]),
+            Err(CryptoError::InvalidLength {
+                expected: ENC_NONCE_LEN,
+                actual: 3,
+            })
+        );
 
-        let plain_msg = b"Hello world!";
-        let cipher_msg = encryptor.encrypt(plain_msg).unwrap();
-        let decrypted_msg = decryptor.decrypt(&cipher_msg).unwrap();
+        // Skip the first nonce:
+        let _ = encryptor.encrypt(b"first").unwrap();
+        let cipher_msg = encryptor.encrypt(b"second").unwrap();
+        assert_eq!(
+            decryptor.decrypt(&cipher_msg),
+            Err(CryptoError::NonceMismatch)
+        );
 
-        assert_eq!(plain_msg, &decrypted_msg[..]);
+        // Tampered message fails authentication:
+        let mut encryptor = Encryptor::new(sym_key(algorithm));
+        let mut cipher_msg = encryptor.encrypt(b"hello").unwrap();
+        let last = cipher_msg.len() - 1;
+        cipher_msg[last] ^= 1;
+        let err = decryptor.decrypt(&cipher_msg).unwrap_err();
+        assert_eq!(err, CryptoError::Unspecified(ring::error::Unspecified));
+        assert!(err.source().is_some());
     }
 }
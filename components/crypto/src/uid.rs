@@ -33,3 +33,17 @@ impl fmt::Display for Uid {
         write!(f, "{}", self.format())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uid_hex_round_trip() {
+        let uid = Uid::from(&[0x5a; UID_LEN]);
+        let hex_str = uid.to_hex();
+        assert_eq!(hex_str, "5a".repeat(UID_LEN));
+        assert_eq!(Uid::from_hex(&hex_str).unwrap(), uid);
+        assert!(Uid::from_hex("zz").is_err());
+    }
+}
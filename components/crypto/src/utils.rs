@@ -0,0 +1,61 @@
+use ring::constant_time::verify_slices_are_equal;
+
+#[cfg(test)]
+use std::cell::Cell;
+
+#[cfg(test)]
+thread_local! {
+    /// Amount of calls to `constant_time_eq()` made by the current thread.
+    /// Allows tests to check that a type is compared using `constant_time_eq()`.
+    pub static CONSTANT_TIME_EQ_CALLS: Cell<usize> = Cell::new(0);
+}
+
+/// Compare two byte slices in constant time.
+/// The running time depends only on the lengths of the slices, and not on their contents.
+/// Slices of different lengths are never equal.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    #[cfg(test)]
+    CONSTANT_TIME_EQ_CALLS.with(|calls| calls.set(calls.get() + 1));
+
+    verify_slices_are_equal(a, b).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{self, Rng, RngCore, StdRng};
+
+    #[test]
+    fn test_constant_time_eq_basic() {
+        assert!(constant_time_eq(&[], &[]));
+        assert!(constant_time_eq(&[1, 2, 3], &[1, 2, 3]));
+        assert!(!constant_time_eq(&[1, 2, 3], &[1, 2, 4]));
+        assert!(!constant_time_eq(&[1, 2, 3], &[1, 2]));
+        assert!(!constant_time_eq(&[], &[0]));
+    }
+
+    #[test]
+    fn test_constant_time_eq_matches_eq() {
+        let mut rng: StdRng = rand::SeedableRng::from_seed([7u8; 32]);
+
+        for _ in 0..0x1000 {
+            let len_a = rng.gen_range(0, 8);
+            let len_b = if rng.gen() {
+                len_a
+            } else {
+                rng.gen_range(0, 8)
+            };
+            let mut a = vec![0u8; len_a];
+            let mut b = vec![0u8; len_b];
+            // Use a small alphabet, so that equal slices are common:
+            for x in a.iter_mut().chain(b.iter_mut()) {
+                *x = rng.gen_range(0, 2);
+            }
+            assert_eq!(constant_time_eq(&a, &b), a == b);
+
+            let mut c = vec![0u8; len_a];
+            rng.fill_bytes(&mut c);
+            assert!(constant_time_eq(&c, &c.clone()));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Checks that secret key material is cleared before its memory is freed.
+//! Uses a global allocator shim that inspects a watched allocation right before freeing it.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use offst_crypto::hash_lock::{PlainLock, PLAIN_LOCK_LEN};
+use offst_crypto::identity::generate_pkcs8_key_pair;
+use offst_crypto::sym_encrypt::{SymmetricKey, SYMMETRIC_KEY_LEN};
+use offst_crypto::test_utils::DummyRandom;
+
+/// Address of the allocation we are watching (0 if none)
+static WATCHED_PTR: AtomicUsize = AtomicUsize::new(0);
+/// Set once the watched allocation was freed
+static WATCHED_FREED: AtomicBool = AtomicBool::new(false);
+/// Was the watched allocation all zeroes when it was freed?
+static WATCHED_ZEROED: AtomicBool = AtomicBool::new(false);
+
+struct CheckingAllocator;
+
+unsafe impl GlobalAlloc for CheckingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if ptr as usize == WATCHED_PTR.load(Ordering::SeqCst) {
+            let bytes = std::slice::from_raw_parts(ptr, layout.size());
+            WATCHED_ZEROED.store(bytes.iter().all(|&byte| byte == 0), Ordering::SeqCst);
+            WATCHED_FREED.store(true, Ordering::SeqCst);
+            WATCHED_PTR.store(0, Ordering::SeqCst);
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CheckingAllocator = CheckingAllocator;
+
+/// Watch the allocation at `ptr`, and run `free` which is expected to free it.
+/// Returns true if the allocation was zeroed right before it was freed.
+fn freed_zeroed<F: FnOnce()>(ptr: *const u8, free: F) -> bool {
+    WATCHED_FREED.store(false, Ordering::SeqCst);
+    WATCHED_ZEROED.store(false, Ordering::SeqCst);
+    WATCHED_PTR.store(ptr as usize, Ordering::SeqCst);
+
+    free();
+
+    assert!(WATCHED_FREED.load(Ordering::SeqCst));
+    WATCHED_ZEROED.load(Ordering::SeqCst)
+}
+
+// All the checks are done in a single test, because the watched allocation is global state.
+#[test]
+fn test_secrets_zeroed_on_drop() {
+    // Sanity: a plain allocation is not zeroed when freed:
+    let plain = Box::new([0xaau8; 32]);
+    let ptr = &*plain as *const [u8; 32] as *const u8;
+    assert!(!freed_zeroed(ptr, move || drop(plain)));
+
+    let symmetric_key = Box::new(SymmetricKey::from(&[0xaa; SYMMETRIC_KEY_LEN]));
+    let ptr = &*symmetric_key as *const SymmetricKey as *const u8;
+    assert!(freed_zeroed(ptr, move || drop(symmetric_key)));
+
+    let plain_lock = Box::new(PlainLock::from(&[0xbb; PLAIN_LOCK_LEN]));
+    let ptr = &*plain_lock as *const PlainLock as *const u8;
+    assert!(freed_zeroed(ptr, move || drop(plain_lock)));
+
+    let rng = DummyRandom::new(&[1, 2, 3]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    assert!(pkcs8.iter().any(|&byte| byte != 0));
+    let ptr = pkcs8.as_ptr();
+    assert!(freed_zeroed(ptr, move || drop(pkcs8)));
+}
@@ -18,6 +18,9 @@ pub struct DatabaseRequest<M> {
     pub response_sender: oneshot::Sender<()>,
 }
 
+/// A client used to apply mutations to a database served by `database_loop`.
+/// To export the state of a database (For example, for a backup), use `FileDb::snapshot()`:
+/// It reads the database file directly, and is safe to use while the database is served.
 #[derive(Clone)]
 pub struct DatabaseClient<M> {
     request_sender: mpsc::Sender<DatabaseRequest<M>>,
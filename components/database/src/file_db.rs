@@ -1,9 +1,9 @@
 use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use std::fmt::Debug;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -12,9 +12,37 @@ use serde::Serialize;
 use atomicwrites;
 use bincode;
 
+use common::int_convert::{u32_to_usize, usize_to_u32};
+use crypto::hash::{sha_512_256, HashResult, HASH_RESULT_LEN};
+
 use crate::atomic_db::AtomicDb;
 use common::mutable_state::MutableState;
 
+/// Length of the length prefix of every WAL record
+const WAL_LEN_PREFIX: usize = 4;
+
+/// Written at the beginning of every database file.
+/// Database files created before the header was introduced do not begin with it, and contain only
+/// the serialized state.
+const DB_MAGIC: &[u8; 8] = b"OFFSTDB\0";
+/// Version of the database file format. Written right after `DB_MAGIC`.
+/// Version 2: State version, serialized state, and a checksum of the serialized state.
+/// (Version 1 lacked the state version, and was never released)
+const DB_FORMAT_VERSION: u32 = 2;
+/// Length of the database file header (`DB_MAGIC`, `DB_FORMAT_VERSION` and the state version)
+const DB_HEADER_LEN: usize = 8 + 4 + 4;
+/// State version of database files written before the header was introduced
+const LEGACY_STATE_VERSION: u32 = 0;
+
+/// A state that can be stored in a database file.
+/// bincode does not allow adding fields (`#[serde(default)]` has no effect), so any change to the
+/// serialized layout of the state must come with a new `STATE_VERSION`.
+pub trait VersionedState {
+    /// Version of the serialized layout of the state. Saved in the header of the database file.
+    /// Database files without a header have version 0.
+    const STATE_VERSION: u32;
+}
+
 #[derive(Debug)]
 pub enum FileDbError<ME> {
     OpenError(io::Error),
@@ -24,6 +52,40 @@ pub enum FileDbError<ME> {
     SerializeError(bincode::Error),
     MutateError(ME),
     FileAlreadyExists,
+    WalWriteError(io::Error),
+    WalReadError(io::Error),
+    WalDeserializeError(bincode::Error),
+    WalRecordTooLong,
+    SyncDirError(io::Error),
+    /// The checksum of the database file does not match its contents
+    Corrupted,
+    /// The database file was written using an unknown (newer) format version
+    UnknownFormatVersion(u32),
+    /// The database file contains a state of a different version
+    StateVersionMismatch(u32),
+}
+
+enum WriteStateError {
+    WriteError(atomicwrites::Error<io::Error>),
+    SyncDirError(io::Error),
+}
+
+impl<ME> From<WriteStateError> for FileDbError<ME> {
+    fn from(e: WriteStateError) -> Self {
+        match e {
+            WriteStateError::WriteError(e) => FileDbError::WriteError(e),
+            WriteStateError::SyncDirError(e) => FileDbError::SyncDirError(e),
+        }
+    }
+}
+
+/// A batch of mutations, written to the write ahead log before it is applied.
+#[derive(Debug, Serialize, Deserialize)]
+struct WalRecord<M> {
+    /// Hash of the serialized state the mutations should be applied to.
+    /// Used to detect records that were already applied to the state file.
+    base_state_hash: HashResult,
+    mutations: Vec<M>,
 }
 
 pub struct FileDb<S> {
@@ -31,11 +93,147 @@ pub struct FileDb<S> {
     path_buf: PathBuf,
     /// Current state represented by the database:
     state: S,
+    /// Hash of the serialized state, saved as a checksum at the end of the database file
+    state_hash: HashResult,
+}
+
+/// Path of the write ahead log of the database in `path_buf`
+fn wal_path(path_buf: &Path) -> PathBuf {
+    let mut wal_os_string = path_buf.as_os_str().to_owned();
+    wal_os_string.push(".wal");
+    PathBuf::from(wal_os_string)
+}
+
+/// Make sure that a rename inside the directory of `path_buf` is persisted
+fn sync_parent_dir(path_buf: &Path) -> Result<(), io::Error> {
+    let parent = match path_buf.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+/// Save a serialized state to file, atomically.
+/// The file begins with a header (`DB_MAGIC`, `DB_FORMAT_VERSION` and `state_version`). A checksum
+/// of the serialized state is appended, allowing to detect corruption.
+/// Returns the checksum.
+fn write_state_file(
+    path_buf: &Path,
+    state_version: u32,
+    serialized_buff: &[u8],
+) -> Result<HashResult, WriteStateError> {
+    let checksum = sha_512_256(serialized_buff);
+
+    let af = atomicwrites::AtomicFile::new(path_buf, atomicwrites::AllowOverwrite);
+    af.write(|fw| {
+        fw.write_all(DB_MAGIC)?;
+        fw.write_all(&DB_FORMAT_VERSION.to_be_bytes())?;
+        fw.write_all(&state_version.to_be_bytes())?;
+        fw.write_all(serialized_buff)?;
+        fw.write_all(&checksum)
+    })
+    .map_err(WriteStateError::WriteError)?;
+    sync_parent_dir(path_buf).map_err(WriteStateError::SyncDirError)?;
+
+    Ok(checksum)
+}
+
+#[derive(Debug)]
+enum VerifyStateError {
+    Corrupted,
+    UnknownFormatVersion(u32),
+    StateVersionMismatch(u32),
+}
+
+impl<ME> From<VerifyStateError> for FileDbError<ME> {
+    fn from(e: VerifyStateError) -> Self {
+        match e {
+            VerifyStateError::Corrupted => FileDbError::Corrupted,
+            VerifyStateError::UnknownFormatVersion(version) => {
+                FileDbError::UnknownFormatVersion(version)
+            }
+            VerifyStateError::StateVersionMismatch(version) => {
+                FileDbError::StateVersionMismatch(version)
+            }
+        }
+    }
+}
+
+/// Read a big endian u32 from `buff`, starting at `offset`
+fn read_u32_be(buff: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buff[offset..offset + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+/// Split the contents of a database file into the serialized state and its checksum.
+/// Fails if the file does not contain a state of version `state_version`.
+/// Database files without a header (Written before the header was introduced) contain only the
+/// serialized state, and have no checksum to verify. They are rewritten using the current format
+/// on the next mutation.
+fn verify_state_file(
+    file_buff: &[u8],
+    state_version: u32,
+) -> Result<(&[u8], HashResult), VerifyStateError> {
+    if !file_buff.starts_with(DB_MAGIC) {
+        // A file truncated in the middle of the header:
+        if DB_MAGIC.starts_with(file_buff) {
+            return Err(VerifyStateError::Corrupted);
+        }
+        if state_version != LEGACY_STATE_VERSION {
+            return Err(VerifyStateError::StateVersionMismatch(LEGACY_STATE_VERSION));
+        }
+        return Ok((file_buff, sha_512_256(file_buff)));
+    }
+
+    if file_buff.len() < DB_HEADER_LEN {
+        return Err(VerifyStateError::Corrupted);
+    }
+    let format_version = read_u32_be(file_buff, DB_MAGIC.len());
+    if format_version != DB_FORMAT_VERSION {
+        return Err(VerifyStateError::UnknownFormatVersion(format_version));
+    }
+    let file_state_version = read_u32_be(file_buff, DB_MAGIC.len() + 4);
+    if file_state_version != state_version {
+        return Err(VerifyStateError::StateVersionMismatch(file_state_version));
+    }
+
+    let body_buff = &file_buff[DB_HEADER_LEN..];
+    if body_buff.len() < HASH_RESULT_LEN {
+        return Err(VerifyStateError::Corrupted);
+    }
+    let (serialized_buff, checksum_buff) = body_buff.split_at(body_buff.len() - HASH_RESULT_LEN);
+
+    let checksum = sha_512_256(serialized_buff);
+    if &checksum[..] != checksum_buff {
+        return Err(VerifyStateError::Corrupted);
+    }
+    Ok((serialized_buff, checksum))
+}
+
+/// Split the contents of a write ahead log into records.
+/// An incomplete last record is the result of a crash during an append. It was never applied, and
+/// is therefore ignored.
+fn split_wal_records(mut wal_buff: &[u8]) -> Vec<&[u8]> {
+    let mut records = Vec::new();
+    while wal_buff.len() >= WAL_LEN_PREFIX {
+        let mut len_bytes = [0u8; WAL_LEN_PREFIX];
+        len_bytes.copy_from_slice(&wal_buff[..WAL_LEN_PREFIX]);
+        let record_len = u32_to_usize(u32::from_be_bytes(len_bytes)).unwrap();
+
+        let rest = &wal_buff[WAL_LEN_PREFIX..];
+        if rest.len() < record_len {
+            break;
+        }
+        records.push(&rest[..record_len]);
+        wal_buff = &rest[record_len..];
+    }
+    records
 }
 
 impl<S> FileDb<S>
 where
-    S: Clone + Serialize + DeserializeOwned + MutableState,
+    S: Clone + Serialize + DeserializeOwned + MutableState + VersionedState,
     S::Mutation: Clone + Serialize + DeserializeOwned,
     S::MutateError: Debug,
 {
@@ -54,35 +252,167 @@ where
         let serialized_buff =
             bincode::serialize(&initial_state).map_err(FileDbError::SerializeError)?;
         // Save the new state to file, atomically:
-        let af = atomicwrites::AtomicFile::new(&path_buf, atomicwrites::AllowOverwrite);
-        af.write(|fw| fw.write_all(&serialized_buff))
-            .map_err(FileDbError::WriteError)?;
+        let state_hash = write_state_file(&path_buf, S::STATE_VERSION, &serialized_buff)?;
 
         let state: S =
             bincode::deserialize(&serialized_buff).map_err(FileDbError::DeserializeError)?;
 
-        Ok(FileDb { path_buf, state })
+        let file_db = FileDb {
+            path_buf,
+            state,
+            state_hash,
+        };
+        // A leftover write ahead log does not belong to the new database:
+        file_db.truncate_wal()?;
+        Ok(file_db)
     }
 
     /// Load an existing database from file
     /// Returns an error if database file does not exist
     pub fn load(path_buf: PathBuf) -> Result<Self, FileDbError<S::MutateError>> {
+        let mut file_db = Self::read_state_file(path_buf)?;
+        file_db.replay_wal()?;
+        Ok(file_db)
+    }
+
+    /// Obtain a snapshot of the state of an existing database, without modifying any file.
+    /// Safe to use while the database is in use by another process (For example, a running
+    /// node): The state file is always replaced atomically, and pending mutations in the write
+    /// ahead log are only applied to the returned state.
+    pub fn snapshot(path: &Path) -> Result<S, FileDbError<S::MutateError>> {
+        let mut file_db = Self::read_state_file(path.to_path_buf())?;
+        let wal_buff = file_db.read_wal()?;
+        file_db.apply_wal_records(&wal_buff)?;
+        Ok(file_db.state)
+    }
+
+    /// Write a new database file from a given state (For example, a backup obtained using
+    /// `snapshot()`).
+    /// Aborts if destination file already exists
+    pub fn restore(path: &Path, state: &S) -> Result<(), FileDbError<S::MutateError>> {
+        let _ = Self::create(path.to_path_buf(), state.clone())?;
+        Ok(())
+    }
+
+    fn read_state_file(path_buf: PathBuf) -> Result<Self, FileDbError<S::MutateError>> {
         let mut f = File::open(&path_buf).map_err(FileDbError::OpenError)?;
         // read the whole file
-        let mut serialized_buff = Vec::new();
-        f.read_to_end(&mut serialized_buff)
+        let mut file_buff = Vec::new();
+        f.read_to_end(&mut file_buff)
             .map_err(FileDbError::ReadError)?;
 
+        let (serialized_buff, state_hash) = verify_state_file(&file_buff, S::STATE_VERSION)?;
         let state: S =
-            bincode::deserialize(&serialized_buff).map_err(FileDbError::DeserializeError)?;
+            bincode::deserialize(serialized_buff).map_err(FileDbError::DeserializeError)?;
+
+        Ok(FileDb {
+            path_buf,
+            state,
+            state_hash,
+        })
+    }
+
+    /// Read the contents of the write ahead log. A missing log is considered empty.
+    fn read_wal(&self) -> Result<Vec<u8>, FileDbError<S::MutateError>> {
+        let mut wal_buff = Vec::new();
+        match File::open(wal_path(&self.path_buf)) {
+            Ok(mut wal_file) => {
+                wal_file
+                    .read_to_end(&mut wal_buff)
+                    .map_err(FileDbError::WalReadError)?;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(FileDbError::WalReadError(e)),
+        }
+        Ok(wal_buff)
+    }
+
+    /// Apply the records of the write ahead log that were not yet applied to the state file.
+    /// Only the in memory state is modified.
+    /// Returns whether any record was applied.
+    fn apply_wal_records(&mut self, wal_buff: &[u8]) -> Result<bool, FileDbError<S::MutateError>> {
+        let mut is_modified = false;
+        for record_buff in split_wal_records(wal_buff) {
+            let wal_record: WalRecord<S::Mutation> =
+                bincode::deserialize(record_buff).map_err(FileDbError::WalDeserializeError)?;
+
+            // Records that were already applied to the state file are skipped:
+            if wal_record.base_state_hash != self.state_hash {
+                continue;
+            }
+
+            for mutation in &wal_record.mutations {
+                self.state
+                    .mutate(mutation)
+                    .map_err(FileDbError::MutateError)?;
+            }
+            let serialized_buff =
+                bincode::serialize(&self.state).map_err(FileDbError::SerializeError)?;
+            self.state_hash = sha_512_256(&serialized_buff);
+            is_modified = true;
+        }
+        Ok(is_modified)
+    }
+
+    /// Apply mutations left in the write ahead log by an interrupted `mutate_db()`.
+    fn replay_wal(&mut self) -> Result<(), FileDbError<S::MutateError>> {
+        let wal_buff = self.read_wal()?;
+        if wal_buff.is_empty() {
+            return Ok(());
+        }
+
+        if self.apply_wal_records(&wal_buff)? {
+            self.write_state()?;
+        }
+        self.truncate_wal()
+    }
 
-        Ok(FileDb { path_buf, state })
+    /// Append a batch of mutations to the write ahead log, and make sure it is persisted.
+    fn append_wal(&self, mutations: &[S::Mutation]) -> Result<(), FileDbError<S::MutateError>> {
+        let wal_record = WalRecord {
+            base_state_hash: self.state_hash.clone(),
+            mutations: mutations.to_vec(),
+        };
+        let record_buff = bincode::serialize(&wal_record).map_err(FileDbError::SerializeError)?;
+        let record_len = usize_to_u32(record_buff.len()).ok_or(FileDbError::WalRecordTooLong)?;
+
+        let mut wal_buff = Vec::with_capacity(WAL_LEN_PREFIX + record_buff.len());
+        wal_buff.extend_from_slice(&record_len.to_be_bytes());
+        wal_buff.extend_from_slice(&record_buff);
+
+        let mut wal_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(wal_path(&self.path_buf))
+            .map_err(FileDbError::WalWriteError)?;
+        wal_file
+            .write_all(&wal_buff)
+            .map_err(FileDbError::WalWriteError)?;
+        wal_file.sync_all().map_err(FileDbError::WalWriteError)
+    }
+
+    /// Save the current state to the database file, atomically.
+    fn write_state(&mut self) -> Result<(), FileDbError<S::MutateError>> {
+        // Serialize the state:
+        let serialized_buff =
+            bincode::serialize(&self.state).map_err(FileDbError::SerializeError)?;
+
+        // Save the new state to file, atomically:
+        self.state_hash = write_state_file(&self.path_buf, S::STATE_VERSION, &serialized_buff)?;
+        Ok(())
+    }
+
+    /// Empty the write ahead log. Called after all its records were applied to the state file.
+    fn truncate_wal(&self) -> Result<(), FileDbError<S::MutateError>> {
+        let wal_file =
+            File::create(wal_path(&self.path_buf)).map_err(FileDbError::WalWriteError)?;
+        wal_file.sync_all().map_err(FileDbError::WalWriteError)
     }
 }
 
 impl<S> AtomicDb for FileDb<S>
 where
-    S: Debug + Clone + Serialize + DeserializeOwned + MutableState,
+    S: Debug + Clone + Serialize + DeserializeOwned + MutableState + VersionedState,
     S::Mutation: Clone + Serialize + DeserializeOwned,
     S::MutateError: Debug,
 {
@@ -96,24 +426,22 @@ where
     }
 
     /// Apply a set of mutations atomically the database, and save it.
+    /// The mutations are first written to a write ahead log, allowing to recover from a crash
+    /// during the write.
     fn mutate_db(&mut self, mutations: &[Self::Mutation]) -> Result<(), Self::Error> {
-        // Apply all mutations to state:
+        // Apply all mutations to a copy of the state first. This way mutations that fail never
+        // reach the write ahead log:
+        let mut new_state = self.state.clone();
         for mutation in mutations.iter() {
-            self.state
+            new_state
                 .mutate(mutation)
                 .map_err(FileDbError::MutateError)?;
         }
 
-        // Serialize the state:
-        let serialized_buff =
-            bincode::serialize(&self.state).map_err(FileDbError::SerializeError)?;
-
-        // Save the new state to file, atomically:
-        let af = atomicwrites::AtomicFile::new(&self.path_buf, atomicwrites::AllowOverwrite);
-        af.write(|fw| fw.write_all(&serialized_buff))
-            .map_err(FileDbError::WriteError)?;
-
-        Ok(())
+        self.append_wal(mutations)?;
+        self.state = new_state;
+        self.write_state()?;
+        self.truncate_wal()
     }
 }
 
@@ -144,6 +472,11 @@ mod tests {
     #[derive(Debug)]
     struct DummyMutateError;
 
+    impl VersionedState for DummyState {
+        // The layout did not change since before the database file header was introduced:
+        const STATE_VERSION: u32 = 0;
+    }
+
     impl MutableState for DummyState {
         type Mutation = DummyMutation;
         type MutateError = DummyMutateError;
@@ -203,4 +536,209 @@ mod tests {
         // Remove temporary directory:
         dir.close().unwrap();
     }
+
+    #[test]
+    fn test_file_db_wal_replay() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let mut file_db =
+            FileDb::<DummyState>::create(file_path.clone(), DummyState::new(0)).unwrap();
+        file_db.mutate_db(&[DummyMutation::Inc]).unwrap();
+
+        // Simulate a crash after the write ahead log was written, but before the state file was
+        // updated:
+        file_db
+            .append_wal(&[DummyMutation::Inc, DummyMutation::Inc])
+            .unwrap();
+        drop(file_db);
+
+        let mut file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 3);
+        // The write ahead log was truncated after the replay:
+        assert_eq!(std::fs::metadata(wal_path(&file_path)).unwrap().len(), 0);
+
+        // Simulate a crash after the state file was updated, but before the write ahead log was
+        // truncated:
+        file_db.append_wal(&[DummyMutation::Inc]).unwrap();
+        file_db.state.mutate(&DummyMutation::Inc).unwrap();
+        file_db.write_state().unwrap();
+        drop(file_db);
+
+        // The mutation must not be applied twice:
+        let file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 4);
+
+        // Simulate a crash in the middle of appending to the write ahead log:
+        file_db.append_wal(&[DummyMutation::Inc]).unwrap();
+        let wal_len = std::fs::metadata(wal_path(&file_path)).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(wal_path(&file_path))
+            .unwrap()
+            .set_len(wal_len - 1)
+            .unwrap();
+        drop(file_db);
+
+        // The incomplete record is ignored:
+        let file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 4);
+        assert_eq!(std::fs::metadata(wal_path(&file_path)).unwrap().len(), 0);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_file_db_snapshot_restore() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let mut file_db =
+            FileDb::<DummyState>::create(file_path.clone(), DummyState::new(0)).unwrap();
+        file_db.mutate_db(&[DummyMutation::Inc]).unwrap();
+        // A pending mutation in the write ahead log:
+        file_db.append_wal(&[DummyMutation::Inc]).unwrap();
+
+        let state = FileDb::<DummyState>::snapshot(&file_path).unwrap();
+        assert_eq!(state.x, 2);
+        // Taking a snapshot does not modify the database:
+        assert!(std::fs::metadata(wal_path(&file_path)).unwrap().len() > 0);
+        assert_eq!(file_db.get_state().x, 1);
+
+        let restored_path = dir.path().join("restored_database_file");
+        FileDb::<DummyState>::restore(&restored_path, &state).unwrap();
+        // Existing files are never overwritten:
+        assert!(FileDb::<DummyState>::restore(&restored_path, &state).is_err());
+
+        let restored_db = FileDb::<DummyState>::load(restored_path).unwrap();
+        assert_eq!(restored_db.get_state().x, 2);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_file_db_corrupted() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let mut file_db =
+            FileDb::<DummyState>::create(file_path.clone(), DummyState::new(0)).unwrap();
+        file_db.mutate_db(&[DummyMutation::Inc]).unwrap();
+        drop(file_db);
+
+        // Flip a byte of the stored state:
+        let mut file_buff = std::fs::read(&file_path).unwrap();
+        file_buff[DB_HEADER_LEN] ^= 0x01;
+        std::fs::write(&file_path, &file_buff).unwrap();
+
+        match FileDb::<DummyState>::load(file_path.clone()) {
+            Err(FileDbError::Corrupted) => {}
+            _ => unreachable!(),
+        }
+        match FileDb::<DummyState>::snapshot(&file_path) {
+            Err(FileDbError::Corrupted) => {}
+            _ => unreachable!(),
+        }
+
+        // A truncated file is also detected:
+        std::fs::write(&file_path, &file_buff[..DB_HEADER_LEN + 4]).unwrap();
+        match FileDb::<DummyState>::load(file_path.clone()) {
+            Err(FileDbError::Corrupted) => {}
+            _ => unreachable!(),
+        }
+        std::fs::write(&file_path, &file_buff[..4]).unwrap();
+        match FileDb::<DummyState>::load(file_path.clone()) {
+            Err(FileDbError::Corrupted) => {}
+            _ => unreachable!(),
+        }
+
+        // A file written using a newer format version:
+        let mut file_buff = DB_MAGIC.to_vec();
+        file_buff.extend_from_slice(&(DB_FORMAT_VERSION + 1).to_be_bytes());
+        file_buff.extend_from_slice(&DummyState::STATE_VERSION.to_be_bytes());
+        std::fs::write(&file_path, &file_buff).unwrap();
+        match FileDb::<DummyState>::load(file_path.clone()) {
+            Err(FileDbError::UnknownFormatVersion(version)) => {
+                assert_eq!(version, DB_FORMAT_VERSION + 1)
+            }
+            _ => unreachable!(),
+        }
+
+        dir.close().unwrap();
+    }
+
+    /// DummyState, after a change to its layout
+    #[derive(Debug, Serialize, Deserialize, Clone)]
+    struct DummyStateV1 {
+        pub x: u32,
+        pub y: u32,
+    }
+
+    impl MutableState for DummyStateV1 {
+        type Mutation = DummyMutation;
+        type MutateError = DummyMutateError;
+
+        fn mutate(&mut self, _mutation: &Self::Mutation) -> Result<(), Self::MutateError> {
+            Ok(())
+        }
+    }
+
+    impl VersionedState for DummyStateV1 {
+        const STATE_VERSION: u32 = 1;
+    }
+
+    #[test]
+    fn test_file_db_state_version_mismatch() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let _ = FileDb::<DummyState>::create(file_path.clone(), DummyState::new(3)).unwrap();
+
+        // The state is not deserialized using the wrong layout:
+        match FileDb::<DummyStateV1>::load(file_path.clone()) {
+            Err(FileDbError::StateVersionMismatch(version)) => {
+                assert_eq!(version, DummyState::STATE_VERSION)
+            }
+            _ => unreachable!(),
+        }
+
+        // Legacy database files (Without a header) have version 0:
+        let legacy_path = dir.path().join("legacy_database_file");
+        std::fs::write(
+            &legacy_path,
+            &bincode::serialize(&DummyState::new(3)).unwrap(),
+        )
+        .unwrap();
+        match FileDb::<DummyStateV1>::snapshot(&legacy_path) {
+            Err(FileDbError::StateVersionMismatch(version)) => assert_eq!(version, 0),
+            _ => unreachable!(),
+        }
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_file_db_legacy_format() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        // A database file written before the header and checksum were introduced contains only
+        // the serialized state:
+        let serialized_buff = bincode::serialize(&DummyState::new(5)).unwrap();
+        std::fs::write(&file_path, &serialized_buff).unwrap();
+
+        let mut file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 5);
+
+        // The next mutation rewrites the file using the current format:
+        file_db.mutate_db(&[DummyMutation::Inc]).unwrap();
+        drop(file_db);
+        let file_buff = std::fs::read(&file_path).unwrap();
+        assert!(file_buff.starts_with(DB_MAGIC));
+
+        let file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 6);
+
+        dir.close().unwrap();
+    }
 }
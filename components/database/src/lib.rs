@@ -12,7 +12,6 @@
     clippy::new_without_default
 )]
 
-#[cfg(test)]
 #[macro_use]
 extern crate serde_derive;
 
@@ -0,0 +1,146 @@
+use std::ops::{Add, AddAssign, Sub};
+
+use common::safe_arithmetic::SafeSignedArithmetic;
+
+/// Amount of credits one side of a mutual credit has against the other side.
+///
+/// Only checked arithmetic is possible: Every operation that might overflow returns an `Option`.
+/// Conversion to a raw `i128` is meant for the boundaries of the funder (Messages and reports).
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Balance(i128);
+
+impl Balance {
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn checked_neg(self) -> Option<Balance> {
+        Some(Balance(self.0.checked_neg()?))
+    }
+
+    pub fn checked_add_unsigned(self, u: u128) -> Option<Balance> {
+        Some(Balance(self.0.checked_add_unsigned(u)?))
+    }
+
+    pub fn checked_sub_unsigned(self, u: u128) -> Option<Balance> {
+        Some(Balance(self.0.checked_sub_unsigned(u)?))
+    }
+}
+
+impl From<i128> for Balance {
+    fn from(balance: i128) -> Self {
+        Balance(balance)
+    }
+}
+
+impl From<Balance> for i128 {
+    fn from(balance: Balance) -> Self {
+        balance.0
+    }
+}
+
+impl Add for Balance {
+    type Output = Option<Balance>;
+
+    fn add(self, other: Balance) -> Option<Balance> {
+        Some(Balance(self.0.checked_add(other.0)?))
+    }
+}
+
+impl Sub for Balance {
+    type Output = Option<Balance>;
+
+    fn sub(self, other: Balance) -> Option<Balance> {
+        Some(Balance(self.0.checked_sub(other.0)?))
+    }
+}
+
+/// Accumulate balances. Once an overflow occurs, the result stays `None`.
+impl AddAssign<Balance> for Option<Balance> {
+    fn add_assign(&mut self, other: Balance) {
+        *self = self.and_then(|balance| balance + other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_balance_overflow() {
+        let max = Balance::from(i128::max_value());
+        let min = Balance::from(i128::min_value());
+
+        assert_eq!(max + Balance::from(1), None);
+        assert_eq!(min - Balance::from(1), None);
+        assert_eq!(min.checked_neg(), None);
+        assert_eq!(max.checked_add_unsigned(1), None);
+        assert_eq!(min.checked_sub_unsigned(1), None);
+        assert_eq!(
+            min.checked_add_unsigned(u128::max_value()),
+            Some(Balance::from(i128::max_value()))
+        );
+
+        let mut sum = Some(max);
+        sum += Balance::from(1);
+        assert_eq!(sum, None);
+        // Stays None, even if the sum could fit again:
+        sum += Balance::from(-1);
+        assert_eq!(sum, None);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_add_sub_match_i128(a in any::<i128>(), b in any::<i128>()) {
+            prop_assert_eq!(
+                (Balance::from(a) + Balance::from(b)).map(i128::from),
+                a.checked_add(b)
+            );
+            prop_assert_eq!(
+                (Balance::from(a) - Balance::from(b)).map(i128::from),
+                a.checked_sub(b)
+            );
+            prop_assert_eq!(Balance::from(a).checked_neg().map(i128::from), a.checked_neg());
+        }
+
+        #[test]
+        fn prop_add_unsigned_no_overflow(a in any::<i128>(), u in any::<u128>()) {
+            // Distance from a to i128::max_value(). Can not overflow u128:
+            let headroom = (i128::max_value() as u128).wrapping_sub(a as u128);
+            let res = Balance::from(a).checked_add_unsigned(u);
+            prop_assert_eq!(res.is_some(), u <= headroom);
+            if let Some(balance) = res {
+                prop_assert_eq!(balance.checked_sub_unsigned(u), Some(Balance::from(a)));
+            }
+        }
+
+        #[test]
+        fn prop_sub_unsigned_no_overflow(a in any::<i128>(), u in any::<u128>()) {
+            // Distance from a to i128::min_value(). Can not overflow u128:
+            let footroom = (a as u128).wrapping_sub(i128::min_value() as u128);
+            let res = Balance::from(a).checked_sub_unsigned(u);
+            prop_assert_eq!(res.is_some(), u <= footroom);
+            if let Some(balance) = res {
+                prop_assert_eq!(balance.checked_add_unsigned(u), Some(Balance::from(a)));
+            }
+        }
+
+        #[test]
+        fn prop_add_assign_accumulates(values in prop::collection::vec(any::<i128>(), 0..16)) {
+            let mut sum = Some(Balance::from(0));
+            for value in &values {
+                sum += Balance::from(*value);
+            }
+            let expected = values
+                .iter()
+                .try_fold(0i128, |acc, value| acc.checked_add(*value));
+            prop_assert_eq!(sum.map(i128::from), expected);
+        }
+    }
+}
@@ -1,27 +1,88 @@
+use std::collections::VecDeque;
+
 use super::liveness::{Liveness, LivenessMutation};
 
+/// Amount of recent move token batch sizes kept for calculating the moving average.
+const MAX_RECENT_BATCH_SIZES: usize = 0x40;
+
 #[derive(Clone, Default)]
 pub struct Ephemeral {
     pub liveness: Liveness,
+    /// Estimated batch sizes of the most recently received move tokens
+    pub recent_batch_sizes: VecDeque<usize>,
+    /// Total amount of move tokens received
+    pub num_move_tokens: u64,
+    /// Amount of time ticks since the funder was started
+    pub current_tick: u64,
 }
 
 #[derive(Debug)]
 pub enum EphemeralMutation {
     LivenessMutation(LivenessMutation),
+    AddMoveTokenBatchSize(usize),
+    TimerTick,
 }
 
 impl Ephemeral {
     pub fn new() -> Ephemeral {
         Ephemeral {
             liveness: Liveness::new(),
+            recent_batch_sizes: VecDeque::new(),
+            num_move_tokens: 0,
+            current_tick: 0,
         }
     }
 
+    /// Moving average of the estimated batch sizes of recently received move tokens.
+    pub fn avg_batch_size(&self) -> usize {
+        if self.recent_batch_sizes.is_empty() {
+            return 0;
+        }
+        let sum = self
+            .recent_batch_sizes
+            .iter()
+            .fold(0usize, |acc, batch_size| acc.saturating_add(*batch_size));
+        sum / self.recent_batch_sizes.len()
+    }
+
     pub fn mutate(&mut self, mutation: &EphemeralMutation) {
         match mutation {
             EphemeralMutation::LivenessMutation(liveness_mutation) => {
                 self.liveness.mutate(liveness_mutation)
             }
+            EphemeralMutation::AddMoveTokenBatchSize(batch_size) => {
+                self.recent_batch_sizes.push_back(*batch_size);
+                if self.recent_batch_sizes.len() > MAX_RECENT_BATCH_SIZES {
+                    let _ = self.recent_batch_sizes.pop_front();
+                }
+                self.num_move_tokens = self.num_move_tokens.wrapping_add(1);
+            }
+            EphemeralMutation::TimerTick => {
+                self.current_tick = self.current_tick.saturating_add(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_avg_batch_size() {
+        let mut ephemeral = Ephemeral::new();
+        assert_eq!(ephemeral.avg_batch_size(), 0);
+
+        ephemeral.mutate(&EphemeralMutation::AddMoveTokenBatchSize(10));
+        ephemeral.mutate(&EphemeralMutation::AddMoveTokenBatchSize(20));
+        assert_eq!(ephemeral.avg_batch_size(), 15);
+        assert_eq!(ephemeral.num_move_tokens, 2);
+
+        // Old batch sizes are eventually forgotten:
+        for _ in 0..MAX_RECENT_BATCH_SIZES {
+            ephemeral.mutate(&EphemeralMutation::AddMoveTokenBatchSize(100));
         }
+        assert_eq!(ephemeral.avg_batch_size(), 100);
+        assert_eq!(ephemeral.recent_batch_sizes.len(), MAX_RECENT_BATCH_SIZES);
     }
 }
@@ -10,8 +10,10 @@ use proto::funder::messages::{
     CancelSendFundsOp, CollectSendFundsOp, FriendStatus, Rate, RequestSendFundsOp, RequestsStatus,
     ResetTerms, ResponseSendFundsOp,
 };
+use proto::report::messages::FriendLivenessReport;
 
-use crate::token_channel::{TcMutation, TokenChannel};
+use crate::report::create_friend_report;
+use crate::token_channel::{simulate_reset_outcome, TcMutation, TokenChannel};
 use crate::types::MoveTokenHashed;
 
 /// Any operation that goes backwards (With respect to the initial request)
@@ -105,6 +107,8 @@ pub struct FriendState<B: Clone> {
     pub sent_local_relays: SentLocalRelays<B>,
     /// Locally maintained name of the remote friend node.
     pub name: String,
+    /// Locally maintained free text notes about the remote friend node.
+    pub note: String,
     /// Rate of forwarding transactions that arrived from this friend to any other friend.
     pub rate: Rate,
     /// Friend status. If disabled, we don't attempt to connect to this friend. (Friend will think
@@ -130,6 +134,12 @@ pub struct FriendState<B: Clone> {
     /// We care more about these requests, because those are payments that our user wants to make.
     /// This queue is bounded in size (TODO: Check this)
     pub pending_user_requests: ImVec<RequestSendFundsOp>,
+    /// Moving average of the round trip time (in ticks) of a single hop, measured from responses
+    /// to requests sent through this friend. None if nothing was measured yet.
+    pub opt_avg_hop_latency_ticks: Option<u64>,
+    /// A frozen friend is not used for routing: We don't send new requests to this friend, but
+    /// the channel stays open and pending transactions may still complete.
+    pub is_frozen: bool,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -149,8 +159,11 @@ pub enum FriendMutation<B: Clone> {
     SetStatus(FriendStatus),
     SetRemoteRelays(Vec<RelayAddress<B>>),
     SetName(String),
+    SetNote(String),
     SetRate(Rate),
     SetSentLocalRelays(SentLocalRelays<B>),
+    AddHopLatencySample(u64), // round trip ticks of a single hop
+    SetFrozen(bool),
 }
 
 impl<B> FriendState<B>
@@ -162,6 +175,7 @@ where
         remote_public_key: &PublicKey,
         remote_relays: Vec<RelayAddress<B>>,
         name: String,
+        note: String,
         balance: i128,
     ) -> Self {
         let token_channel = TokenChannel::new(local_public_key, remote_public_key, balance);
@@ -172,6 +186,7 @@ where
             remote_relays,
             sent_local_relays: SentLocalRelays::NeverSent,
             name,
+            note,
             // Initial rate is 0 for a new friend:
             rate: Rate::new(),
             status: FriendStatus::Disabled,
@@ -186,6 +201,70 @@ where
             pending_requests: ImVec::new(),
             pending_backwards_ops: ImVec::new(),
             pending_user_requests: ImVec::new(),
+            opt_avg_hop_latency_ticks: None,
+            is_frozen: false,
+        }
+    }
+
+    /// Preview the token channel we would have with this friend after resetting the channel,
+    /// accepting the friend's reset terms. Nothing is mutated.
+    /// Returns None if the channel can not be reset: It is consistent, or the friend's reset
+    /// terms are not known yet.
+    pub fn simulate_reset_outcome(&self) -> Option<TokenChannel<B>> {
+        let channel_inconsistent = match &self.channel_status {
+            ChannelStatus::Consistent(_) => return None,
+            ChannelStatus::Inconsistent(channel_inconsistent) => channel_inconsistent,
+        };
+        let remote_reset_terms = channel_inconsistent.opt_remote_reset_terms.as_ref()?;
+
+        Some(simulate_reset_outcome(
+            &self.local_public_key,
+            &self.remote_public_key,
+            remote_reset_terms,
+            channel_inconsistent.opt_last_incoming_move_token.clone(),
+        ))
+    }
+
+    /// Check if this friend can be used as the first hop of a route that needs to freeze
+    /// `required_capacity` credits. See `FriendReport::is_good_for_routing()`, which apps use to
+    /// make the same decision from the node report.
+    pub fn is_good_for_routing(&self, required_capacity: u128) -> bool {
+        // Liveness does not affect routing:
+        create_friend_report(self, &FriendLivenessReport::Offline)
+            .is_good_for_routing(required_capacity)
+    }
+
+    /// Priority of the connection to this friend, used by the channeler to decide which friends
+    /// to connect to first:
+    /// - 2: There are pending transactions or pending operations waiting to be sent.
+    /// - 1: There are credits in the channel, or the channel is inconsistent and needs a reset.
+    /// - 0: The friend is idle (Or disabled).
+    pub fn connection_priority(&self) -> u8 {
+        if let FriendStatus::Disabled = self.status {
+            return 0;
+        }
+
+        if !self.pending_requests.is_empty()
+            || !self.pending_backwards_ops.is_empty()
+            || !self.pending_user_requests.is_empty()
+        {
+            return 2;
+        }
+
+        let token_channel = match &self.channel_status {
+            ChannelStatus::Inconsistent(_) => return 1,
+            ChannelStatus::Consistent(token_channel) => token_channel,
+        };
+
+        let mc_state = token_channel.get_mutual_credit().state();
+        if !mc_state.pending_transactions.local.is_empty()
+            || !mc_state.pending_transactions.remote.is_empty()
+        {
+            2
+        } else if i128::from(mc_state.balance.balance) != 0 {
+            1
+        } else {
+            0
         }
     }
 
@@ -266,12 +345,196 @@ where
             FriendMutation::SetName(friend_name) => {
                 self.name = friend_name.clone();
             }
+            FriendMutation::SetNote(friend_note) => {
+                self.note = friend_note.clone();
+            }
             FriendMutation::SetRate(friend_rate) => {
                 self.rate = friend_rate.clone();
             }
             FriendMutation::SetSentLocalRelays(sent_local_relays) => {
                 self.sent_local_relays = sent_local_relays.clone();
             }
+            FriendMutation::AddHopLatencySample(round_trip_ticks) => {
+                self.opt_avg_hop_latency_ticks = Some(match self.opt_avg_hop_latency_ticks {
+                    // Exponential moving average, giving the new sample a weight of 1/8:
+                    Some(avg_hop_latency_ticks) => {
+                        avg_hop_latency_ticks
+                            .saturating_mul(7)
+                            .saturating_add(*round_trip_ticks)
+                            / 8
+                    }
+                    None => *round_trip_ticks,
+                });
+            }
+            FriendMutation::SetFrozen(is_frozen) => {
+                self.is_frozen = *is_frozen;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::{Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+
+    use crate::balance::Balance;
+    use crate::mutual_credit::types::McMutation;
+    use crate::token_channel::TcDirection;
+
+    /// Create a friend that is good for routing up to (and including) 15 credits.
+    fn routable_friend() -> FriendState<u32> {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let mut friend = FriendState::new(
+            &local_public_key,
+            &remote_public_key,
+            Vec::new(),
+            "friend".into(),
+            String::new(),
+            10,
+        );
+
+        let friend_mutations = vec![
+            FriendMutation::SetStatus(FriendStatus::Enabled),
+            FriendMutation::TcMutation(TcMutation::McMutation(
+                McMutation::SetRemoteRequestsStatus(RequestsStatus::Open),
+            )),
+            FriendMutation::TcMutation(TcMutation::McMutation(McMutation::SetLocalMaxDebt(8))),
+            FriendMutation::TcMutation(TcMutation::McMutation(McMutation::SetLocalPendingDebt(3))),
+        ];
+        for friend_mutation in &friend_mutations {
+            friend.mutate(friend_mutation);
+        }
+        friend
+    }
+
+    #[test]
+    fn test_is_good_for_routing() {
+        let friend = routable_friend();
+        assert!(friend.is_good_for_routing(0));
+        assert!(friend.is_good_for_routing(15));
+        assert!(!friend.is_good_for_routing(16));
+    }
+
+    #[test]
+    fn test_is_good_for_routing_disabled() {
+        let mut friend = routable_friend();
+        friend.mutate(&FriendMutation::SetStatus(FriendStatus::Disabled));
+        assert!(!friend.is_good_for_routing(0));
+    }
+
+    #[test]
+    fn test_is_good_for_routing_frozen() {
+        let mut friend = routable_friend();
+        friend.mutate(&FriendMutation::SetFrozen(true));
+        assert!(!friend.is_good_for_routing(0));
+
+        friend.mutate(&FriendMutation::SetFrozen(false));
+        assert!(friend.is_good_for_routing(0));
+    }
+
+    #[test]
+    fn test_is_good_for_routing_inconsistent() {
+        let mut friend = routable_friend();
+        let channel_inconsistent = ChannelInconsistent {
+            opt_last_incoming_move_token: None,
+            local_reset_terms: ResetTerms {
+                reset_token: Signature::from(&[0; SIGNATURE_LEN]),
+                inconsistency_counter: 1,
+                balance_for_reset: 10,
+            },
+            opt_remote_reset_terms: None,
+        };
+        friend.mutate(&FriendMutation::SetInconsistent(channel_inconsistent));
+        assert!(!friend.is_good_for_routing(0));
+    }
+
+    #[test]
+    fn test_is_good_for_routing_remote_requests_closed() {
+        let mut friend = routable_friend();
+        friend.mutate(&FriendMutation::TcMutation(TcMutation::McMutation(
+            McMutation::SetRemoteRequestsStatus(RequestsStatus::Closed),
+        )));
+        assert!(!friend.is_good_for_routing(0));
+    }
+
+    #[test]
+    fn test_is_good_for_routing_insufficient_headroom() {
+        let mut friend = routable_friend();
+        // Negative balance that exceeds our max debt leaves no headroom at all:
+        friend.mutate(&FriendMutation::TcMutation(TcMutation::McMutation(
+            McMutation::SetBalance(Balance::from(-20)),
+        )));
+        assert!(friend.is_good_for_routing(0));
+        assert!(!friend.is_good_for_routing(1));
+    }
+
+    fn inconsistent_friend(opt_remote_reset_terms: Option<ResetTerms>) -> FriendState<u32> {
+        let mut friend = routable_friend();
+        let channel_inconsistent = ChannelInconsistent {
+            opt_last_incoming_move_token: None,
+            local_reset_terms: ResetTerms {
+                reset_token: Signature::from(&[0x01; SIGNATURE_LEN]),
+                inconsistency_counter: 3,
+                balance_for_reset: 7,
+            },
+            opt_remote_reset_terms,
+        };
+        friend.mutate(&FriendMutation::SetInconsistent(channel_inconsistent));
+        friend
+    }
+
+    #[test]
+    fn test_simulate_reset_outcome_no_reset() {
+        // A consistent channel can not be reset:
+        let friend = routable_friend();
+        assert!(friend.simulate_reset_outcome().is_none());
+
+        // The remote reset terms are not known yet:
+        let friend = inconsistent_friend(None);
+        assert!(friend.simulate_reset_outcome().is_none());
+    }
+
+    #[test]
+    fn test_simulate_reset_outcome() {
+        let remote_reset_terms = ResetTerms {
+            reset_token: Signature::from(&[0x02; SIGNATURE_LEN]),
+            inconsistency_counter: 3,
+            balance_for_reset: -7,
+        };
+        let friend = inconsistent_friend(Some(remote_reset_terms.clone()));
+        let token_channel = friend.simulate_reset_outcome().unwrap();
+
+        // Simulating does not change anything:
+        match &friend.channel_status {
+            ChannelStatus::Inconsistent(channel_inconsistent) => assert_eq!(
+                channel_inconsistent.opt_remote_reset_terms,
+                Some(remote_reset_terms.clone())
+            ),
+            ChannelStatus::Consistent(_) => unreachable!(),
+        }
+
+        let mc_state = token_channel.get_mutual_credit().state();
+        assert_eq!(mc_state.balance.balance, Balance::from(7));
+        assert_eq!(mc_state.balance.local_pending_debt, 0);
+        assert_eq!(mc_state.balance.remote_pending_debt, 0);
+        assert!(mc_state.pending_transactions.local.is_empty());
+        assert!(mc_state.pending_transactions.remote.is_empty());
+
+        // We are the side that sends the reset move token:
+        match token_channel.get_direction() {
+            TcDirection::Outgoing(tc_outgoing) => {
+                let move_token = &tc_outgoing.move_token_out;
+                assert_eq!(move_token.old_token, remote_reset_terms.reset_token);
+                assert_eq!(move_token.inconsistency_counter, 3);
+                assert_eq!(move_token.move_token_counter, 0);
+                assert_eq!(move_token.balance, 7);
+                assert!(move_token.operations.is_empty());
+                assert!(move_token.opt_local_relays.is_none());
+            }
+            TcDirection::Incoming(_) => unreachable!(),
         }
     }
 }
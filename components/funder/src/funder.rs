@@ -2,23 +2,27 @@ use std::fmt::Debug;
 
 use futures::channel::mpsc;
 use futures::stream::select;
-use futures::{future, stream, SinkExt, StreamExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
 
 use common::canonical_serialize::CanonicalSerialize;
 
 use crypto::crypto_rand::CryptoRandom;
 use identity::IdentityClient;
+use timer::TimerTick;
 
 // use crate::database::{AtomicDb, DbRunner, DbRunnerError};
 use database::DatabaseClient;
 
 use proto::funder::messages::{FunderIncomingControl, FunderOutgoingControl};
 
-use crate::ephemeral::Ephemeral;
+use crate::ephemeral::{Ephemeral, EphemeralMutation};
 use crate::handler::funder_handle_message;
 use crate::state::{FunderMutation, FunderState};
 use crate::types::{FunderIncoming, FunderIncomingComm, FunderOutgoingComm};
 
+/// Log the average move token batch size once every this amount of received move tokens.
+const BATCH_SIZE_LOG_INTERVAL: u64 = 100;
+
 #[derive(Debug)]
 pub enum FunderError {
     IncomingControlClosed,
@@ -27,6 +31,8 @@ pub enum FunderError {
     DbError,
     SendControlError,
     SendCommError,
+    /// At least one operation must fit in a move token batch
+    InvalidMaxOperationsInBatch,
 }
 
 #[derive(Debug, Clone)]
@@ -36,28 +42,46 @@ pub enum FunderEvent<B> {
     IncomingCommClosed,
 }
 
-pub async fn inner_funder_loop<B, R>(
+pub async fn inner_funder_loop<B, R, TS>(
     mut identity_client: IdentityClient,
     rng: R,
     incoming_control: mpsc::Receiver<FunderIncomingControl<B>>,
     incoming_comm: mpsc::Receiver<FunderIncomingComm<B>>,
+    timer_stream: TS,
     control_sender: mpsc::Sender<FunderOutgoingControl<B>>,
     comm_sender: mpsc::Sender<FunderOutgoingComm<B>>,
     mut funder_state: FunderState<B>,
     mut db_client: DatabaseClient<FunderMutation<B>>,
     max_operations_in_batch: usize,
     max_node_relays: usize,
+    max_friends: usize,
     max_pending_user_requests: usize,
+    max_pending_ticks: usize,
+    max_frozen_credits_threshold: u128,
+    history_retention_events: u64,
     mut opt_event_sender: Option<mpsc::Sender<FunderEvent<B>>>,
 ) -> Result<(), FunderError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom + 'static,
+    TS: Stream<Item = TimerTick> + Unpin,
 {
+    if max_operations_in_batch < 1 {
+        return Err(FunderError::InvalidMaxOperationsInBatch);
+    }
+
     // Transform error type:
     let mut comm_sender = comm_sender.sink_map_err(|_| ());
     let mut control_sender = control_sender.sink_map_err(|_| ());
 
+    // Forget payments that completed more than `history_retention_events` payment events ago.
+    // This is required on startup in case `history_retention_events` was decreased:
+    if let Some(min_tick) = funder_state.payment_history_prune_tick(history_retention_events) {
+        let mutation = FunderMutation::PrunePaymentHistory(min_tick);
+        funder_state.mutate(&mutation);
+        await!(db_client.mutate(vec![mutation])).map_err(|_| FunderError::DbError)?;
+    }
+
     // let mut db_runner = DbRunner::new(atomic_db);
     let mut ephemeral = Ephemeral::new();
 
@@ -74,11 +98,15 @@ where
             FunderEvent::FunderIncoming(FunderIncoming::Comm(incoming_comm_msg))
         })
         .chain(stream::once(future::ready(FunderEvent::IncomingCommClosed)));
+    let timer_stream = timer_stream.map(|_| FunderEvent::FunderIncoming(FunderIncoming::TimerTick));
     // Chain the Init message first:
     let mut incoming_messages = stream::once(future::ready(FunderEvent::FunderIncoming(
         FunderIncoming::Init,
     )))
-    .chain(select(incoming_control, incoming_comm));
+    .chain(select(
+        incoming_control,
+        select(incoming_comm, timer_stream),
+    ));
 
     while let Some(funder_event) = await!(incoming_messages.next()) {
         // For testing:
@@ -95,8 +123,10 @@ where
             funder_state.clone(),
             ephemeral.clone(),
             max_node_relays,
+            max_friends,
             max_operations_in_batch,
             max_pending_user_requests,
+            max_pending_ticks,
             funder_incoming
         ));
 
@@ -110,18 +140,44 @@ where
         };
 
         if !handler_output.funder_mutations.is_empty() {
+            let mut funder_mutations = handler_output.funder_mutations;
             // Mutate our funder_state in memory:
-            for mutation in &handler_output.funder_mutations {
+            for mutation in &funder_mutations {
                 funder_state.mutate(mutation);
             }
+            // Keep the payment history bounded as new entries are appended:
+            if let Some(min_tick) =
+                funder_state.payment_history_prune_tick(history_retention_events)
+            {
+                let mutation = FunderMutation::PrunePaymentHistory(min_tick);
+                funder_state.mutate(&mutation);
+                funder_mutations.push(mutation);
+            }
             // If there are any mutations, send them to the database:
-            await!(db_client.mutate(handler_output.funder_mutations))
-                .map_err(|_| FunderError::DbError)?;
+            await!(db_client.mutate(funder_mutations)).map_err(|_| FunderError::DbError)?;
+
+            let (local_frozen, remote_frozen) = funder_state.total_frozen_credits();
+            if local_frozen > max_frozen_credits_threshold
+                || remote_frozen > max_frozen_credits_threshold
+            {
+                warn!(
+                    "Frozen credits exceed threshold {}: local_frozen = {}, remote_frozen = {}",
+                    max_frozen_credits_threshold, local_frozen, remote_frozen
+                );
+            }
         }
 
         // Apply ephemeral mutations to our ephemeral:
         for mutation in &handler_output.ephemeral_mutations {
             ephemeral.mutate(mutation);
+            if let EphemeralMutation::AddMoveTokenBatchSize(_) = mutation {
+                if ephemeral.num_move_tokens % BATCH_SIZE_LOG_INTERVAL == 0 {
+                    debug!(
+                        "Average move token batch size: {}",
+                        ephemeral.avg_batch_size()
+                    );
+                }
+            }
         }
 
         // Send outgoing communication messages:
@@ -141,35 +197,46 @@ where
     Ok(())
 }
 
-pub async fn funder_loop<B, R>(
+pub async fn funder_loop<B, R, TS>(
     identity_client: IdentityClient,
     rng: R,
     incoming_control: mpsc::Receiver<FunderIncomingControl<B>>,
     incoming_comm: mpsc::Receiver<FunderIncomingComm<B>>,
+    timer_stream: TS,
     control_sender: mpsc::Sender<FunderOutgoingControl<B>>,
     comm_sender: mpsc::Sender<FunderOutgoingComm<B>>,
     max_operations_in_batch: usize,
     max_node_relays: usize,
+    max_friends: usize,
     max_pending_user_requests: usize,
+    max_pending_ticks: usize,
+    max_frozen_credits_threshold: u128,
+    history_retention_events: u64,
     funder_state: FunderState<B>,
     db_client: DatabaseClient<FunderMutation<B>>,
 ) -> Result<(), FunderError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom + 'static,
+    TS: Stream<Item = TimerTick> + Unpin,
 {
     await!(inner_funder_loop(
         identity_client,
         rng,
         incoming_control,
         incoming_comm,
+        timer_stream,
         control_sender,
         comm_sender,
         funder_state,
         db_client,
         max_operations_in_batch,
         max_node_relays,
+        max_friends,
         max_pending_user_requests,
+        max_pending_ticks,
+        max_frozen_credits_threshold,
+        history_retention_events,
         None
     ))
 }
@@ -0,0 +1,133 @@
+use common::canonical_serialize::CanonicalSerialize;
+use crypto::identity::PublicKey;
+
+use crate::friend::ChannelStatus;
+use crate::state::FunderState;
+
+/// A directed edge between two friends, used for visualization of the friends graph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEdge {
+    /// The side that sent the last move token
+    pub from_public_key: PublicKey,
+    /// The side that currently holds the token
+    pub to_public_key: PublicKey,
+    /// Balance, from the point of view of `from_public_key`
+    pub balance: i128,
+    pub is_consistent: bool,
+}
+
+/// Collect an edge for every friend channel.
+/// For consistent channels, the edge points in the direction of the last sent move token.
+/// Inconsistent channels are always represented as an edge from the local node, with the local
+/// reset terms balance.
+pub fn friend_graph_edges<B>(state: &FunderState<B>) -> Vec<GraphEdge>
+where
+    B: Clone + CanonicalSerialize,
+{
+    let mut edges = Vec::new();
+    for (friend_public_key, friend) in &state.friends {
+        let edge = match &friend.channel_status {
+            ChannelStatus::Consistent(token_channel) => {
+                let (local_public_key, remote_public_key, balance, is_consistent) =
+                    token_channel.to_graph_representation();
+                if token_channel.is_outgoing() {
+                    GraphEdge {
+                        from_public_key: local_public_key,
+                        to_public_key: remote_public_key,
+                        balance,
+                        is_consistent,
+                    }
+                } else {
+                    GraphEdge {
+                        from_public_key: remote_public_key,
+                        to_public_key: local_public_key,
+                        balance: balance.checked_neg().unwrap_or(i128::max_value()),
+                        is_consistent,
+                    }
+                }
+            }
+            ChannelStatus::Inconsistent(channel_inconsistent) => GraphEdge {
+                from_public_key: state.local_public_key.clone(),
+                to_public_key: friend_public_key.clone(),
+                balance: channel_inconsistent.local_reset_terms.balance_for_reset,
+                is_consistent: false,
+            },
+        };
+        edges.push(edge);
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    use proto::funder::messages::AddFriend;
+
+    use crate::state::FunderMutation;
+    use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+    fn add_friend(state: &mut FunderState<u32>, friend_public_key: &PublicKey, balance: i128) {
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![dummy_relay_address(0)],
+            name: "friend".into(),
+            note: String::new(),
+            balance,
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+    }
+
+    #[test]
+    fn test_friend_graph_edges_directions() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state =
+            FunderState::<u32>::new(local_pk.clone(), vec![dummy_named_relay_address(0)]);
+
+        // The initial direction of a token channel depends on the order of the public keys,
+        // so one of those friends has an incoming channel and the other an outgoing channel:
+        let pk_low = PublicKey::from(&[0x00; PUBLIC_KEY_LEN]);
+        let pk_high = PublicKey::from(&[0xff; PUBLIC_KEY_LEN]);
+        add_friend(&mut state, &pk_low, 7);
+        add_friend(&mut state, &pk_high, 7);
+
+        let is_outgoing = |friend_public_key: &PublicKey| match &state
+            .friends
+            .get(friend_public_key)
+            .unwrap()
+            .channel_status
+        {
+            ChannelStatus::Consistent(token_channel) => token_channel.is_outgoing(),
+            ChannelStatus::Inconsistent(_) => unreachable!(),
+        };
+        assert!(is_outgoing(&pk_low) ^ is_outgoing(&pk_high));
+        let (pk_out, pk_in) = if is_outgoing(&pk_low) {
+            (pk_low, pk_high)
+        } else {
+            (pk_high, pk_low)
+        };
+
+        let edges = friend_graph_edges(&state);
+        assert_eq!(edges.len(), 2);
+
+        // Outgoing channel: The edge points from us to the friend:
+        let out_edge = edges
+            .iter()
+            .find(|edge| edge.to_public_key == pk_out)
+            .unwrap();
+        assert_eq!(out_edge.from_public_key, local_pk);
+        assert_eq!(out_edge.balance, 7);
+        assert!(out_edge.is_consistent);
+
+        // Incoming channel: The edge points from the friend to us:
+        let in_edge = edges
+            .iter()
+            .find(|edge| edge.from_public_key == pk_in)
+            .unwrap();
+        assert_eq!(in_edge.to_public_key, local_pk);
+        assert_eq!(in_edge.balance, -7);
+        assert!(in_edge.is_consistent);
+    }
+}
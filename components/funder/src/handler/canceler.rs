@@ -4,9 +4,12 @@ use common::canonical_serialize::CanonicalSerialize;
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
+use crypto::invoice_id::InvoiceId;
 use crypto::uid::Uid;
 
-use proto::funder::messages::{FunderOutgoingControl, RequestResult, TransactionResult};
+use proto::funder::messages::{
+    FunderOutgoingControl, PaymentEventKind, RequestResult, TransactionResult,
+};
 
 use crate::handler::sender::SendCommands;
 use crate::handler::state_wrap::MutableFunderState;
@@ -14,7 +17,7 @@ use crate::handler::utils::find_request_origin;
 
 use crate::friend::{BackwardsOp, ChannelStatus, FriendMutation};
 use crate::state::{FunderMutation, Payment};
-use crate::types::{create_cancel_send_funds, create_pending_transaction};
+use crate::types::create_cancel_send_funds;
 
 /// Reply to a single request message with a cancellation.
 pub fn reply_with_cancel<B>(
@@ -34,6 +37,36 @@ pub fn reply_with_cancel<B>(
     send_commands.set_try_send(remote_public_key);
 }
 
+/// Remove an open invoice, cancelling all the pending transactions related to this invoice.
+pub fn remove_invoice<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    invoice_id: &InvoiceId,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let open_invoice = match m_state.state().open_invoices.get(invoice_id) {
+        Some(open_invoice) => open_invoice.clone(),
+        None => return,
+    };
+
+    // Cancel all pending transactions related to this invoice
+    for (_, incoming_transaction) in open_invoice.incoming_transactions {
+        let request_id = &incoming_transaction.request_id;
+        // Explaining the unwrap() below:
+        // We expect that the origin of this request must be from an existing friend.
+        // We can not be the originator of this request.
+        let friend_public_key = find_request_origin(m_state.state(), &request_id)
+            .unwrap()
+            .clone();
+        reply_with_cancel(m_state, send_commands, &friend_public_key, &request_id);
+    }
+
+    // Remove invoice:
+    let funder_mutation = FunderMutation::RemoveInvoice(invoice_id.clone());
+    m_state.mutate(funder_mutation);
+}
+
 /// Remove a local transaction (Where this node is the buyer side)
 pub fn remove_transaction<B, R>(m_state: &mut MutableFunderState<B>, rng: &R, request_id: &Uid)
 where
@@ -68,6 +101,7 @@ where
             let mut new_new_transactions = new_transactions.clone();
             new_new_transactions.num_transactions =
                 new_transactions.num_transactions.checked_sub(1).unwrap();
+            new_new_transactions.routes.remove(request_id);
             Some(Payment::NewTransactions(new_new_transactions))
         }
         Payment::InProgress(num_transactions) => {
@@ -100,12 +134,38 @@ where
         }
     };
 
-    let funder_mutation = if let Some(new_payment) = opt_new_payment {
-        FunderMutation::UpdatePayment((open_transaction.payment_id.clone(), new_payment))
+    let new_payment = if let Some(new_payment) = opt_new_payment {
+        new_payment
     } else {
-        FunderMutation::RemovePayment(open_transaction.payment_id.clone())
+        let funder_mutation = FunderMutation::RemovePayment(open_transaction.payment_id.clone());
+        m_state.mutate(funder_mutation);
+        return;
+    };
+
+    let is_canceled = if let Payment::Canceled(_) = new_payment {
+        true
+    } else {
+        false
     };
+
+    let funder_mutation =
+        FunderMutation::UpdatePayment((open_transaction.payment_id.clone(), new_payment));
     m_state.mutate(funder_mutation);
+
+    // Record the failure in the payment's timeline:
+    let funder_mutation = FunderMutation::AddPaymentEvent((
+        open_transaction.payment_id.clone(),
+        PaymentEventKind::TransactionFailed(request_id.clone()),
+    ));
+    m_state.mutate(funder_mutation);
+
+    if is_canceled {
+        let funder_mutation = FunderMutation::AddPaymentEvent((
+            open_transaction.payment_id.clone(),
+            PaymentEventKind::Canceled,
+        ));
+        m_state.mutate(funder_mutation);
+    }
 }
 
 /// Cancel outgoing local requests that are already inside the token channel (Possibly already
@@ -195,9 +255,8 @@ pub fn cancel_pending_requests<B, R>(
             find_request_origin(m_state.state(), &pending_request.request_id).cloned();
         match opt_origin_public_key {
             Some(origin_public_key) => {
-                let pending_local_transaction = create_pending_transaction(&pending_request);
                 let cancel_send_funds =
-                    create_cancel_send_funds(pending_local_transaction.request_id.clone());
+                    create_cancel_send_funds(pending_request.request_id.clone());
                 let friend_mutation = FriendMutation::PushBackPendingBackwardsOp(
                     BackwardsOp::Cancel(cancel_send_funds),
                 );
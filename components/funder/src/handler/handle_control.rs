@@ -1,5 +1,8 @@
+use std::collections::HashSet;
 use std::fmt::Debug;
 
+use im::hashmap::HashMap as ImHashMap;
+
 use common::canonical_serialize::CanonicalSerialize;
 
 use crypto::crypto_rand::CryptoRandom;
@@ -10,22 +13,24 @@ use crypto::payment_id::PaymentId;
 use crypto::uid::Uid;
 
 use crate::friend::{BackwardsOp, ChannelStatus, FriendMutation};
+use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
 use crate::state::{FunderMutation, NewTransactions, Payment};
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
     AckClosePayment, AddFriend, AddInvoice, ChannelerUpdateFriend, CollectSendFundsOp,
-    CreatePayment, CreateTransaction, FriendStatus, FunderControl, FunderOutgoingControl,
-    MultiCommit, PaymentStatus, RemoveFriend, RequestResult, RequestSendFundsOp,
-    ResetFriendChannel, ResponseClosePayment, SetFriendName, SetFriendRate, SetFriendRelays,
-    SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus, TransactionResult,
+    CreateMultiRoutePayment, CreatePayment, CreateTransaction, FriendStatus, FriendTcOp,
+    FunderControl, FunderOutgoingControl, MultiCommit, PaymentEventKind, PaymentHistoryResponse,
+    PaymentStatus, RemoveFriend, RequestResult, RequestSendFundsOp, ResetFriendChannel,
+    ResponseClosePayment, ResponsePaymentTimeline, SetFriendName, SetFriendNote, SetFriendRate,
+    SetFriendRelays, SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus, TransactionResult,
 };
 use proto::funder::signature_buff::{prepare_commit, verify_multi_commit};
 
 use crate::ephemeral::Ephemeral;
 use crate::handler::canceler::{
     cancel_local_pending_transactions, cancel_pending_requests, cancel_pending_user_requests,
-    reply_with_cancel,
+    remove_invoice,
 };
 use crate::handler::sender::SendCommands;
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
@@ -45,6 +50,7 @@ pub enum HandleControlError {
     PendingUserRequestsFull,
     FriendNotReady,
     MaxNodeRelaysReached,
+    FriendLimitReached,
     PaymentAlreadyOpen,
     OpenPaymentNotFound,
     NewTransactionsNotAllowed,
@@ -53,7 +59,12 @@ pub enum HandleControlError {
     AckMismatch,
     InvoiceAlreadyExists,
     InvoiceDoesNotExist,
+    InvalidValidityTicks,
     InvalidMultiCommit,
+    RequestNotFeasible(QueueOperationError),
+    FriendFrozen,
+    DuplicateRequestId,
+    MultiRouteDestPaymentMismatch,
 }
 
 fn control_set_friend_remote_max_debt<B>(
@@ -143,6 +154,7 @@ fn enable_friend<B>(
         friend_public_key: friend_public_key.clone(),
         friend_relays: friend_relays.to_vec(),
         local_relays: friend.sent_local_relays.to_vec(),
+        connection_priority: friend.connection_priority(),
     };
     let channeler_config = ChannelerConfig::UpdateFriend(channeler_add_friend);
     outgoing_channeler_config.push(channeler_config);
@@ -185,8 +197,14 @@ fn control_add_relay<B>(
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
-    // We can't have more than `max_node_relays` relays
-    if m_state.state().relays.len() >= max_node_relays {
+    // We can't have more than `max_node_relays` relays.
+    // Replacing an existing relay is always allowed:
+    let is_existing_relay = m_state
+        .state()
+        .relays
+        .iter()
+        .any(|relay| relay.public_key == named_relay_address.public_key);
+    if !is_existing_relay && !m_state.state().can_accept_more_relays(max_node_relays) {
         return Err(HandleControlError::MaxNodeRelaysReached);
     }
 
@@ -245,12 +263,32 @@ fn control_remove_relay<B>(
     }
 }
 
-fn control_add_friend<B>(m_state: &mut MutableFunderState<B>, add_friend: AddFriend<B>)
+fn control_add_friend<B>(
+    m_state: &mut MutableFunderState<B>,
+    max_friends: usize,
+    add_friend: AddFriend<B>,
+) -> Result<(), HandleControlError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
+    // Adding an existing friend again does nothing. Replacing the friend would throw away its
+    // token channel, and the limit does not apply to it:
+    if m_state
+        .state()
+        .friends
+        .contains_key(&add_friend.friend_public_key)
+    {
+        return Ok(());
+    }
+
+    // We can't have more than `max_friends` friends:
+    if !m_state.state().can_accept_more_friends(max_friends) {
+        return Err(HandleControlError::FriendLimitReached);
+    }
+
     let funder_mutation = FunderMutation::AddFriend(add_friend.clone());
     m_state.mutate(funder_mutation);
+    Ok(())
 }
 
 /// This is a violent operation, as it removes all the known state with the remote friend.
@@ -352,6 +390,52 @@ where
     Ok(())
 }
 
+/// Freeze or unfreeze a friend.
+/// When a friend is frozen, all the requests queued to this friend are canceled. The token
+/// channel is left untouched, so that pending transactions can still be completed.
+fn control_set_friend_frozen<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    rng: &R,
+    friend_public_key: PublicKey,
+    is_frozen: bool,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    // If the friend is already in the wanted state, we do nothing:
+    if friend.is_frozen == is_frozen {
+        return Ok(());
+    }
+
+    let friend_mutation = FriendMutation::SetFrozen(is_frozen);
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    if is_frozen {
+        cancel_pending_requests(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            &friend_public_key,
+        );
+        cancel_pending_user_requests(m_state, outgoing_control, rng, &friend_public_key);
+    }
+
+    Ok(())
+}
+
 fn control_set_requests_status<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
@@ -401,6 +485,7 @@ where
     }
 
     let local_relays = friend.sent_local_relays.to_vec();
+    let connection_priority = friend.connection_priority();
 
     let friend_mutation = FriendMutation::SetRemoteRelays(set_friend_relays.relays.clone());
     let funder_mutation = FunderMutation::FriendMutation((
@@ -415,6 +500,7 @@ where
             friend_public_key: set_friend_relays.friend_public_key.clone(),
             friend_relays: set_friend_relays.relays.clone(),
             local_relays,
+            connection_priority,
         };
         let channeler_config = ChannelerConfig::UpdateFriend(update_friend);
         outgoing_channeler_config.push(channeler_config);
@@ -452,6 +538,35 @@ where
     Ok(())
 }
 
+fn control_set_friend_note<B>(
+    m_state: &mut MutableFunderState<B>,
+    set_friend_note: SetFriendNote,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&set_friend_note.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    // If the newly proposed note is the same as the old one, we do nothing:
+    if friend.note == set_friend_note.note {
+        return Ok(());
+    }
+
+    let friend_mutation = FriendMutation::SetNote(set_friend_note.note);
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_note.friend_public_key.clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
 fn control_set_friend_rate<B>(
     m_state: &mut MutableFunderState<B>,
     set_friend_rate: SetFriendRate,
@@ -502,11 +617,15 @@ where
         invoice_id: create_payment.invoice_id.clone(),
         total_dest_payment: create_payment.total_dest_payment,
         dest_public_key: create_payment.dest_public_key.clone(),
+        routes: ImHashMap::new(),
     });
 
     // Add a new payment entry:
     let m_mutation = FunderMutation::UpdatePayment((create_payment.payment_id, payment));
+    m_state.mutate(m_mutation);
 
+    let m_mutation =
+        FunderMutation::AddPaymentEvent((create_payment.payment_id, PaymentEventKind::Created));
     m_state.mutate(m_mutation);
     Ok(())
 }
@@ -565,6 +684,11 @@ where
         return Err(HandleControlError::FriendNotReady);
     }
 
+    // We don't send new requests through a frozen friend:
+    if friend.is_frozen {
+        return Err(HandleControlError::FriendFrozen);
+    }
+
     // If payment is already in progress, we do nothing:
     // Check if there is already a pending user payment with the same payment_id:
     for user_request in &friend.pending_user_requests {
@@ -601,6 +725,23 @@ where
     // Randomly generate a new PlainLock:
     let src_plain_lock = PlainLock::new(rng);
 
+    let request_send_funds = RequestSendFundsOp {
+        request_id: create_transaction.request_id,
+        src_hashed_lock: src_plain_lock.hash(),
+        route: create_transaction.route,
+        dest_payment: create_transaction.dest_payment,
+        total_dest_payment: new_transactions.total_dest_payment,
+        invoice_id: new_transactions.invoice_id.clone(),
+        left_fees: create_transaction.fees,
+    };
+
+    // Make sure that the request could be sent through the current state of the mutual credit.
+    // Note that this is only an estimate: The state of the mutual credit might change until the
+    // request is actually sent.
+    OutgoingMc::new(token_channel.get_mutual_credit())
+        .simulate_operation(&FriendTcOp::RequestSendFunds(request_send_funds.clone()))
+        .map_err(HandleControlError::RequestNotFeasible)?;
+
     // Keep PlainLock:
     let funder_mutation = FunderMutation::AddTransaction((
         create_transaction.request_id,
@@ -609,10 +750,14 @@ where
     ));
     m_state.mutate(funder_mutation);
 
-    // Update OpenPayment (Increase open transactions count):
+    // Update OpenPayment (Increase open transactions count, keep the route):
     let mut updated_new_transactions = new_transactions.clone();
     updated_new_transactions.num_transactions =
         new_transactions.num_transactions.checked_add(1).unwrap();
+    updated_new_transactions.routes.insert(
+        create_transaction.request_id,
+        request_send_funds.route.clone(),
+    );
 
     let funder_mutation = FunderMutation::UpdatePayment((
         create_transaction.payment_id,
@@ -620,17 +765,13 @@ where
     ));
     m_state.mutate(funder_mutation);
 
-    // Push the request:
-    let request_send_funds = RequestSendFundsOp {
-        request_id: create_transaction.request_id,
-        src_hashed_lock: src_plain_lock.hash(),
-        route: create_transaction.route,
-        dest_payment: create_transaction.dest_payment,
-        total_dest_payment: new_transactions.total_dest_payment,
-        invoice_id: new_transactions.invoice_id.clone(),
-        left_fees: create_transaction.fees,
-    };
+    let funder_mutation = FunderMutation::AddPaymentEvent((
+        create_transaction.payment_id,
+        PaymentEventKind::TransactionAdded(create_transaction.request_id),
+    ));
+    m_state.mutate(funder_mutation);
 
+    // Push the request:
     let friend_mutation = FriendMutation::PushBackPendingUserRequest(request_send_funds);
     let funder_mutation =
         FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
@@ -711,6 +852,144 @@ where
     Ok(())
 }
 
+fn control_create_multi_route_payment_inner<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    ephemeral: &Ephemeral,
+    send_commands: &mut SendCommands,
+    rng: &R,
+    max_pending_user_requests: usize,
+    create_multi_route_payment: CreateMultiRoutePayment,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let CreateMultiRoutePayment {
+        payment_id,
+        invoice_id,
+        total_dest_payment,
+        dest_public_key,
+        transactions,
+    } = create_multi_route_payment;
+
+    // The transactions must pay exactly the total amount of the invoice:
+    let mut sum_dest_payment = 0u128;
+    for multi_route_transaction in &transactions {
+        sum_dest_payment = sum_dest_payment
+            .checked_add(multi_route_transaction.dest_payment)
+            .ok_or(HandleControlError::MultiRouteDestPaymentMismatch)?;
+    }
+    if sum_dest_payment != total_dest_payment {
+        return Err(HandleControlError::MultiRouteDestPaymentMismatch);
+    }
+
+    // Make sure that all the request ids are new:
+    let mut request_ids = HashSet::new();
+    for multi_route_transaction in &transactions {
+        if !request_ids.insert(multi_route_transaction.request_id)
+            || m_state
+                .state()
+                .open_transactions
+                .contains_key(&multi_route_transaction.request_id)
+        {
+            return Err(HandleControlError::DuplicateRequestId);
+        }
+    }
+
+    let create_payment = CreatePayment {
+        payment_id,
+        invoice_id,
+        total_dest_payment,
+        dest_public_key,
+    };
+    control_create_payment(m_state, create_payment)?;
+
+    for multi_route_transaction in transactions {
+        let create_transaction = CreateTransaction {
+            payment_id,
+            request_id: multi_route_transaction.request_id,
+            route: multi_route_transaction.route,
+            dest_payment: multi_route_transaction.dest_payment,
+            fees: multi_route_transaction.fees,
+        };
+        control_create_transaction_inner(
+            m_state,
+            ephemeral,
+            send_commands,
+            rng,
+            max_pending_user_requests,
+            create_transaction,
+        )?;
+    }
+    Ok(())
+}
+
+/// Create a payment together with all of its transactions.
+/// The transactions are first created over a copy of the state. Only if all of them were created
+/// successfully, the resulting mutations are applied to the real state. Otherwise, a failure
+/// result is returned for every transaction that is not already in flight, and the payment is not
+/// created.
+fn control_create_multi_route_payment<B, R>(
+    m_state: &mut MutableFunderState<B>,
+    ephemeral: &Ephemeral,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    send_commands: &mut SendCommands,
+    rng: &R,
+    max_pending_user_requests: usize,
+    create_multi_route_payment: CreateMultiRoutePayment,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+    R: CryptoRandom,
+{
+    let request_ids: Vec<_> = create_multi_route_payment
+        .transactions
+        .iter()
+        .map(|multi_route_transaction| multi_route_transaction.request_id)
+        .collect();
+
+    let mut temp_m_state = MutableFunderState::new(m_state.state().clone());
+    let mut temp_send_commands = send_commands.clone();
+
+    if let Err(e) = control_create_multi_route_payment_inner(
+        &mut temp_m_state,
+        ephemeral,
+        &mut temp_send_commands,
+        rng,
+        max_pending_user_requests,
+        create_multi_route_payment,
+    ) {
+        error!("control_create_multi_route_payment_inner() failed: {:?}", e);
+        // None of the requests were sent. We report a failure only for request ids that do not
+        // belong to a transaction that is already in flight, because those will still get their
+        // own result later:
+        let mut failed_request_ids = HashSet::new();
+        for request_id in request_ids {
+            if m_state.state().open_transactions.contains_key(&request_id)
+                || !failed_request_ids.insert(request_id)
+            {
+                continue;
+            }
+            let transaction_result = TransactionResult {
+                request_id,
+                result: RequestResult::Failure,
+            };
+            outgoing_control.push(FunderOutgoingControl::TransactionResult(transaction_result));
+        }
+        // Every transaction must have a matching response. Therefore we don't return an error
+        // here.
+        return Ok(());
+    }
+
+    let (_initial_state, mutations, _final_state) = temp_m_state.done();
+    for mutation in mutations {
+        m_state.mutate(mutation);
+    }
+    *send_commands = temp_send_commands;
+
+    Ok(())
+}
+
 fn control_request_close_payment<B, R>(
     m_state: &mut MutableFunderState<B>,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
@@ -781,6 +1060,11 @@ where
         response_close_payment,
     ));
 
+    // Closing a complete payment again has no effect:
+    if m_state.state().is_payment_complete(&payment_id) {
+        return Ok(());
+    }
+
     // Update or remove payment record:
     let new_payment = if let Some(new_payment) = opt_new_payment {
         new_payment
@@ -795,8 +1079,21 @@ where
         return Ok(());
     }
 
+    let opt_payment_event = match (payment, &new_payment) {
+        (Payment::NewTransactions(_), Payment::InProgress(_)) => {
+            Some(PaymentEventKind::ClosedRequested)
+        }
+        (Payment::InProgress(_), Payment::Canceled(_)) => Some(PaymentEventKind::Canceled),
+        _ => None,
+    };
+
     let funder_mutation = FunderMutation::UpdatePayment((payment_id, new_payment));
     m_state.mutate(funder_mutation);
+
+    if let Some(payment_event) = opt_payment_event {
+        let funder_mutation = FunderMutation::AddPaymentEvent((payment_id, payment_event));
+        m_state.mutate(funder_mutation);
+    }
     Ok(())
 }
 
@@ -857,6 +1154,7 @@ where
 
 fn control_add_invoice<B>(
     m_state: &mut MutableFunderState<B>,
+    ephemeral: &Ephemeral,
     add_invoice: AddInvoice,
 ) -> Result<(), HandleControlError>
 where
@@ -870,9 +1168,20 @@ where
         return Err(HandleControlError::InvoiceAlreadyExists);
     }
 
+    // An invoice with no validity ticks would already be expired when it is added:
+    if add_invoice.validity_ticks == 0 {
+        return Err(HandleControlError::InvalidValidityTicks);
+    }
+
     // Add new invoice:
-    let funder_mutation =
-        FunderMutation::AddInvoice((add_invoice.invoice_id, add_invoice.total_dest_payment));
+    let expires_at_tick = ephemeral
+        .current_tick
+        .saturating_add(add_invoice.validity_ticks);
+    let funder_mutation = FunderMutation::AddInvoice((
+        add_invoice.invoice_id,
+        add_invoice.total_dest_payment,
+        expires_at_tick,
+    ));
     m_state.mutate(funder_mutation);
 
     Ok(())
@@ -886,28 +1195,11 @@ fn control_cancel_invoice<B>(
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
-    let open_invoice = m_state
-        .state()
-        .open_invoices
-        .get(&invoice_id)
-        .ok_or(HandleControlError::InvoiceDoesNotExist)?
-        .clone();
-
-    // Cancel all pending transactions related to this invoice
-    for (_, incoming_transaction) in open_invoice.incoming_transactions {
-        let request_id = &incoming_transaction.request_id;
-        // Explaining the unwrap() below:
-        // We expect that the origin of this request must be from an existing friend.
-        // We can not be the originator of this request.
-        let friend_public_key = find_request_origin(m_state.state(), &request_id)
-            .unwrap()
-            .clone();
-        reply_with_cancel(m_state, send_commands, &friend_public_key, &request_id);
+    if !m_state.state().open_invoices.contains_key(&invoice_id) {
+        return Err(HandleControlError::InvoiceDoesNotExist);
     }
 
-    // Remove invoice:
-    let funder_mutation = FunderMutation::RemoveInvoice(invoice_id);
-    m_state.mutate(funder_mutation);
+    remove_invoice(m_state, send_commands, &invoice_id);
 
     Ok(())
 }
@@ -984,6 +1276,7 @@ pub fn handle_control_message<B, R>(
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
     rng: &R,
     max_node_relays: usize,
+    max_friends: usize,
     max_pending_user_requests: usize,
     incoming_control: FunderControl<B>,
 ) -> Result<(), HandleControlError>
@@ -1019,8 +1312,7 @@ where
         }
 
         FunderControl::AddFriend(add_friend) => {
-            control_add_friend(m_state, add_friend);
-            Ok(())
+            control_add_friend(m_state, max_friends, add_friend)
         }
 
         FunderControl::RemoveFriend(remove_friend) => control_remove_friend(
@@ -1052,9 +1344,28 @@ where
         FunderControl::SetFriendName(set_friend_name) => {
             control_set_friend_name(m_state, set_friend_name)
         }
+        FunderControl::SetFriendNote(set_friend_note) => {
+            control_set_friend_note(m_state, set_friend_note)
+        }
         FunderControl::SetFriendRate(set_friend_rate) => {
             control_set_friend_rate(m_state, set_friend_rate)
         }
+        FunderControl::FreezeFriend(friend_public_key) => control_set_friend_frozen(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            friend_public_key,
+            true,
+        ),
+        FunderControl::UnfreezeFriend(friend_public_key) => control_set_friend_frozen(
+            m_state,
+            send_commands,
+            outgoing_control,
+            rng,
+            friend_public_key,
+            false,
+        ),
 
         // Buyer API:
         FunderControl::CreatePayment(create_payment) => {
@@ -1069,15 +1380,51 @@ where
             max_pending_user_requests,
             create_transaction,
         ),
+        FunderControl::CreateMultiRoutePayment(create_multi_route_payment) => {
+            control_create_multi_route_payment(
+                m_state,
+                m_ephemeral.ephemeral(),
+                outgoing_control,
+                send_commands,
+                rng,
+                max_pending_user_requests,
+                create_multi_route_payment,
+            )
+        }
         FunderControl::RequestClosePayment(payment_id) => {
             control_request_close_payment(m_state, outgoing_control, rng, payment_id)
         }
         FunderControl::AckClosePayment(ack_close_payment) => {
             control_ack_close_payment(m_state, ack_close_payment)
         }
+        FunderControl::GetPaymentTimeline(payment_id) => {
+            let response_payment_timeline = ResponsePaymentTimeline {
+                timeline: m_state.state().payment_timeline(&payment_id),
+                payment_id,
+            };
+            outgoing_control.push(FunderOutgoingControl::ResponsePaymentTimeline(
+                response_payment_timeline,
+            ));
+            Ok(())
+        }
+        FunderControl::QueryPaymentHistory(query_payment_history) => {
+            let payment_history_response = PaymentHistoryResponse {
+                after_tick: query_payment_history.after_tick,
+                entries: m_state.state().query_payment_history(
+                    query_payment_history.after_tick,
+                    query_payment_history.limit,
+                ),
+            };
+            outgoing_control.push(FunderOutgoingControl::PaymentHistoryResponse(
+                payment_history_response,
+            ));
+            Ok(())
+        }
 
         // Seller API:
-        FunderControl::AddInvoice(add_invoice) => control_add_invoice(m_state, add_invoice),
+        FunderControl::AddInvoice(add_invoice) => {
+            control_add_invoice(m_state, m_ephemeral.ephemeral(), add_invoice)
+        }
         FunderControl::CancelInvoice(invoice_id) => {
             control_cancel_invoice(m_state, send_commands, invoice_id)
         }
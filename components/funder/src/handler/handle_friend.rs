@@ -1,4 +1,5 @@
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
 use std::fmt::Debug;
 
 use crypto::crypto_rand::CryptoRandom;
@@ -8,8 +9,8 @@ use crypto::uid::Uid;
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     CancelSendFundsOp, ChannelerUpdateFriend, CollectSendFundsOp, FriendMessage,
-    FunderOutgoingControl, MoveTokenRequest, PendingTransaction, RequestResult, RequestSendFundsOp,
-    ResetTerms, ResponseSendFundsOp, TransactionResult,
+    FunderOutgoingControl, MoveTokenRequest, PaymentEventKind, PendingTransaction, RequestResult,
+    RequestSendFundsOp, ResetTerms, ResponseSendFundsOp, TransactionResult,
 };
 use proto::funder::signature_buff::{prepare_commit, prepare_receipt, verify_move_token};
 
@@ -17,7 +18,9 @@ use crate::mutual_credit::incoming::{
     IncomingCancelSendFundsOp, IncomingCollectSendFundsOp, IncomingMessage,
     IncomingResponseSendFundsOp,
 };
-use crate::token_channel::{MoveTokenReceived, ReceiveMoveTokenOutput, TokenChannel};
+use crate::token_channel::{
+    batch_size_estimate, MoveTokenReceived, ReceiveMoveTokenOutput, TokenChannel,
+};
 
 use crate::types::{create_pending_transaction, ChannelerConfig};
 
@@ -26,7 +29,7 @@ use crate::friend::{
 };
 use crate::state::{FunderMutation, Payment};
 
-use crate::ephemeral::Ephemeral;
+use crate::ephemeral::{Ephemeral, EphemeralMutation};
 
 use crate::handler::canceler::{
     cancel_local_pending_transactions, cancel_pending_requests, cancel_pending_user_requests,
@@ -85,7 +88,7 @@ pub fn try_reset_channel<B>(
 
     // Check if incoming message is a valid attempt to reset the channel:
     if move_token.old_token != local_reset_terms.reset_token
-        || !move_token.operations.is_empty()
+        || !move_token.is_empty()
         || move_token.opt_local_relays.is_some()
         || move_token.inconsistency_counter != local_reset_terms.inconsistency_counter
         || move_token.move_token_counter != 0
@@ -166,8 +169,11 @@ fn handle_request_send_funds<B>(
             .open_invoices
             .get(&request_send_funds.invoice_id)
         {
+            // An expired invoice can not be paid anymore. It is removed on the next timer tick,
+            // until then requests for it are cancelled here:
             if (open_invoice.total_dest_payment == request_send_funds.total_dest_payment)
                 && (request_send_funds.dest_payment <= request_send_funds.total_dest_payment)
+                && !open_invoice.is_expired(ephemeral.current_tick)
             {
                 true
             } else {
@@ -188,7 +194,8 @@ fn handle_request_send_funds<B>(
         };
 
         // We return a response:
-        let pending_transaction = create_pending_transaction(&request_send_funds);
+        let pending_transaction =
+            create_pending_transaction(&request_send_funds, ephemeral.current_tick);
         m_state.queue_unsigned_response(remote_public_key.clone(), pending_transaction);
         /*
         let u_response_op = BackwardsOp::UnsignedResponse(pending_transaction);
@@ -204,35 +211,35 @@ fn handle_request_send_funds<B>(
     // We are not the destination of this request.
     // The node on the route has to be one of our friends:
     let next_public_key = request_send_funds.route.index_to_pk(next_index).unwrap();
-    let friend_exists = m_state.state().friends.contains_key(next_public_key);
-
     // This friend must be considered online for us to forward the message.
     // If we forward the request to an offline friend, the request could be stuck for a long
     // time before a response arrives.
-    let friend_ready = if friend_exists {
-        is_friend_ready(m_state.state(), ephemeral, &next_public_key)
-    } else {
-        false
+    // We also don't route new requests through a frozen friend.
+    let friend_ready = match m_state.state().friends.get(next_public_key) {
+        Some(next_friend) => {
+            !next_friend.is_frozen && is_friend_ready(m_state.state(), ephemeral, &next_public_key)
+        }
+        None => false,
     };
 
     // Attempt to take our fee for forwarding the request.
     // Note that the rate is determined by the rate we set with the node that sent us the request
     // (And **not** with the node that we forward the request to).
+    //
+    // An insufficient fee only cancels this request. It is not checked when the move token is
+    // received, because that would make the whole token channel inconsistent.
     let rate = &m_state.state().friends.get(remote_public_key).unwrap().rate;
-    let opt_local_fee = rate.calc_fee(request_send_funds.dest_payment);
+    let local_fee = rate.min_fee(request_send_funds.dest_payment);
 
     let request_id = request_send_funds.request_id.clone();
 
-    // Make sure that calc_fee() worked, and that we can take this amount of credits:
-    let opt_request_send_funds = if let Some(local_fee) = opt_local_fee {
-        if let Some(new_left_fees) = request_send_funds.left_fees.checked_sub(local_fee) {
+    // Make sure that we can take this amount of credits:
+    let opt_request_send_funds = match request_send_funds.left_fees.checked_sub(local_fee) {
+        Some(new_left_fees) => {
             request_send_funds.left_fees = new_left_fees;
             Some(request_send_funds)
-        } else {
-            None
         }
-    } else {
-        None
+        None => None,
     };
 
     let request_send_funds = match (opt_request_send_funds, friend_ready) {
@@ -247,6 +254,38 @@ fn handle_request_send_funds<B>(
     forward_request(m_state, send_commands, request_send_funds);
 }
 
+/// Measure the round trip time of a request we sent to `remote_public_key`,
+/// once the response arrives. The sample is the round trip time of a single hop:
+/// The total round trip time is divided by the amount of hops from us to the destination.
+fn add_hop_latency_sample<B>(
+    m_state: &mut MutableFunderState<B>,
+    ephemeral: &Ephemeral,
+    remote_public_key: &PublicKey,
+    pending_transaction: &PendingTransaction,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let route = &pending_transaction.route;
+    let local_index = match route.pk_to_index(&m_state.state().local_public_key) {
+        Some(local_index) => local_index,
+        None => return,
+    };
+    let num_hops = route.len().saturating_sub(local_index.saturating_add(1));
+    if num_hops == 0 {
+        return;
+    }
+
+    let round_trip_ticks = ephemeral
+        .current_tick
+        .saturating_sub(pending_transaction.created_at_tick);
+    let hop_latency_ticks = round_trip_ticks / usize_to_u64(num_hops).unwrap();
+
+    let friend_mutation = FriendMutation::AddHopLatencySample(hop_latency_ticks);
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+}
+
 fn handle_response_send_funds<B>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
@@ -358,6 +397,13 @@ fn handle_collect_send_funds<B, R>(
                 .payments
                 .get(&open_transaction.payment_id)
                 .unwrap();
+            let payment_id = open_transaction.payment_id.clone();
+
+            // This collect is the first successful transaction of this payment:
+            let is_first_success = match payment {
+                Payment::NewTransactions(_) | Payment::InProgress(_) => true,
+                _ => false,
+            };
 
             // Update payment status:
             let opt_new_payment = match payment {
@@ -407,13 +453,19 @@ fn handle_collect_send_funds<B, R>(
 
             let funder_mutation = if let Some(new_payment) = opt_new_payment {
                 // Update payment:
-                FunderMutation::UpdatePayment((open_transaction.payment_id.clone(), new_payment))
+                FunderMutation::UpdatePayment((payment_id.clone(), new_payment))
             } else {
-                FunderMutation::RemovePayment(open_transaction.payment_id.clone())
+                FunderMutation::RemovePayment(payment_id.clone())
                 // Remove payment:
             };
             m_state.mutate(funder_mutation);
 
+            if is_first_success {
+                let funder_mutation =
+                    FunderMutation::AddPaymentEvent((payment_id, PaymentEventKind::Succeeded));
+                m_state.mutate(funder_mutation);
+            }
+
             // Remove transaction:
             let funder_mutation =
                 FunderMutation::RemoveTransaction(collect_send_funds.request_id.clone());
@@ -460,6 +512,12 @@ fn handle_move_token_output<B, R>(
                 pending_transaction,
                 incoming_response,
             }) => {
+                add_hop_latency_sample(
+                    m_state,
+                    m_ephemeral.ephemeral(),
+                    remote_public_key,
+                    &pending_transaction,
+                );
                 handle_response_send_funds(
                     m_state,
                     send_commands,
@@ -635,6 +693,7 @@ fn handle_move_token_success<B, R>(
                         friend_public_key: remote_public_key.clone(),
                         friend_relays: friend.remote_relays.clone(),
                         local_relays,
+                        connection_priority: friend.connection_priority(),
                     };
                     let channeler_config = ChannelerConfig::UpdateFriend(update_friend);
                     outgoing_channeler_config.push(channeler_config);
@@ -706,13 +765,18 @@ where
         }
     };
 
+    let batch_size = batch_size_estimate(&friend_move_token_request.friend_move_token);
+
     // We will only consider move token messages if we are in a consistent state:
-    let receive_move_token_res =
-        token_channel.simulate_receive_move_token(friend_move_token_request.friend_move_token);
+    let receive_move_token_res = token_channel.simulate_receive_move_token(
+        friend_move_token_request.friend_move_token,
+        m_ephemeral.ephemeral().current_tick,
+    );
     let token_wanted = friend_move_token_request.token_wanted;
 
     match receive_move_token_res {
         Ok(receive_move_token_output) => {
+            m_ephemeral.mutate(EphemeralMutation::AddMoveTokenBatchSize(batch_size));
             handle_move_token_success(
                 m_state,
                 m_ephemeral,
@@ -850,3 +914,122 @@ where
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::hash_lock::{PlainLock, PLAIN_LOCK_LEN};
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::uid::UID_LEN;
+
+    use proto::funder::messages::{AddFriend, FriendsRoute};
+
+    use crate::state::FunderState;
+    use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+    #[test]
+    fn test_add_hop_latency_sample() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let pk_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(pk_b.clone(), vec![dummy_named_relay_address(0)]);
+
+        let add_friend = AddFriend {
+            friend_public_key: pk_c.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: "pk_c".into(),
+            note: String::new(),
+            balance: 0i128,
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        // We (pk_b) forwarded a request from pk_a to pk_c at tick 2.
+        // There are 2 hops from us to the destination (pk_d):
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[0x1; UID_LEN]),
+            src_hashed_lock: PlainLock::from(&[0x2; PLAIN_LOCK_LEN]).hash(),
+            route: FriendsRoute {
+                public_keys: vec![pk_a.clone(), pk_b.clone(), pk_c.clone(), pk_d.clone()],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id: InvoiceId::from(&[0x3; INVOICE_ID_LEN]),
+            left_fees: 0,
+        };
+        let pending_transaction = create_pending_transaction(&request_send_funds, 2);
+
+        let mut m_state = MutableFunderState::new(state);
+        let mut ephemeral = Ephemeral::new();
+
+        // The response arrives at tick 10:
+        ephemeral.current_tick = 10;
+        add_hop_latency_sample(&mut m_state, &ephemeral, &pk_c, &pending_transaction);
+        let friend = m_state.state().friends.get(&pk_c).unwrap();
+        assert_eq!(friend.opt_avg_hop_latency_ticks, Some(4));
+
+        // A slower response: 40 ticks round trip, 20 ticks per hop. (4 * 7 + 20) / 8 = 6:
+        ephemeral.current_tick = 42;
+        add_hop_latency_sample(&mut m_state, &ephemeral, &pk_c, &pending_transaction);
+        let friend = m_state.state().friends.get(&pk_c).unwrap();
+        assert_eq!(friend.opt_avg_hop_latency_ticks, Some(6));
+    }
+
+    #[test]
+    fn test_request_to_expired_invoice() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(pk_b.clone(), vec![dummy_named_relay_address(0)]);
+
+        let add_friend = AddFriend {
+            friend_public_key: pk_a.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: "pk_a".into(),
+            note: String::new(),
+            balance: 0i128,
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        // We (pk_b) have an invoice that expires at tick 5:
+        let invoice_id = InvoiceId::from(&[0x3; INVOICE_ID_LEN]);
+        state.mutate(&FunderMutation::AddInvoice((invoice_id.clone(), 10, 5)));
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[0x1; UID_LEN]),
+            src_hashed_lock: PlainLock::from(&[0x2; PLAIN_LOCK_LEN]).hash(),
+            route: FriendsRoute {
+                public_keys: vec![pk_a.clone(), pk_b.clone()],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id,
+            left_fees: 0,
+        };
+
+        // The request arrives at tick 5. The invoice has expired, but the timer tick did not
+        // remove it yet:
+        let mut m_state = MutableFunderState::new(state);
+        let mut ephemeral = Ephemeral::new();
+        ephemeral.current_tick = 5;
+        let mut send_commands = SendCommands::new();
+        handle_request_send_funds(
+            &mut m_state,
+            &ephemeral,
+            &mut send_commands,
+            &pk_a,
+            request_send_funds.clone(),
+        );
+
+        // The request is answered with a Cancel:
+        let friend = m_state.state().friends.get(&pk_a).unwrap();
+        assert_eq!(friend.pending_backwards_ops.len(), 1);
+        match &friend.pending_backwards_ops[0] {
+            BackwardsOp::Cancel(cancel_send_funds) => {
+                assert_eq!(cancel_send_funds.request_id, request_send_funds.request_id)
+            }
+            _ => unreachable!(),
+        };
+    }
+}
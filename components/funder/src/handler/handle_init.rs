@@ -21,6 +21,7 @@ pub fn handle_init<B>(
                     friend_public_key: friend.remote_public_key.clone(),
                     friend_relays: friend.remote_relays.clone(),
                     local_relays: friend.sent_local_relays.to_vec(),
+                    connection_priority: friend.connection_priority(),
                 };
                 enabled_friends.push(channeler_add_friend);
             }
@@ -78,6 +79,7 @@ mod tests {
             friend_public_key: pk_b.clone(),
             relays: vec![dummy_relay_address(3)],
             name: "pk_b".into(),
+            note: String::new(),
             balance: 0i128,
         };
         let f_mutation = FunderMutation::AddFriend(add_friend);
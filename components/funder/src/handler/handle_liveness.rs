@@ -137,6 +137,7 @@ mod tests {
             friend_public_key: remote_pk.clone(),
             relays: vec![dummy_relay_address(1)],
             name: "remote_pk".into(),
+            note: String::new(),
             balance: 0i128,
         };
         let funder_mutation = FunderMutation::AddFriend(add_friend);
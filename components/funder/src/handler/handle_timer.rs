@@ -0,0 +1,288 @@
+use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
+use std::fmt::Debug;
+
+use crypto::identity::PublicKey;
+use crypto::invoice_id::InvoiceId;
+use crypto::uid::Uid;
+
+use crate::ephemeral::EphemeralMutation;
+use crate::friend::{BackwardsOp, ChannelStatus};
+use crate::handler::canceler::{remove_invoice, reply_with_cancel};
+use crate::handler::sender::SendCommands;
+use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
+use crate::state::FunderMutation;
+
+/// Handle a time tick: Advance the funder's tick counter, remove all the open invoices that
+/// have expired and cancel all the timed out pending transactions.
+pub fn handle_timer_tick<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    max_pending_ticks: usize,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    m_ephemeral.mutate(EphemeralMutation::TimerTick);
+    let current_tick = m_ephemeral.ephemeral().current_tick;
+
+    let expired_invoice_ids: Vec<InvoiceId> = m_state
+        .state()
+        .open_invoices
+        .iter()
+        .filter(|(_, open_invoice)| open_invoice.is_expired(current_tick))
+        .map(|(invoice_id, _)| invoice_id.clone())
+        .collect();
+
+    for invoice_id in &expired_invoice_ids {
+        remove_invoice(m_state, send_commands, invoice_id);
+    }
+
+    cancel_timed_out_transactions(m_state, send_commands, current_tick, max_pending_ticks);
+}
+
+/// Is there already a pending Cancel or Collect for a certain request?
+fn is_request_closing<B>(
+    m_state: &MutableFunderState<B>,
+    friend_public_key: &PublicKey,
+    request_id: &Uid,
+) -> bool
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+    friend
+        .pending_backwards_ops
+        .iter()
+        .any(|backwards_op| match backwards_op {
+            BackwardsOp::Response(_) => false,
+            BackwardsOp::Cancel(cancel_send_funds) => &cancel_send_funds.request_id == request_id,
+            BackwardsOp::Collect(collect_send_funds) => {
+                &collect_send_funds.request_id == request_id
+            }
+        })
+}
+
+/// Cancel all the pending transactions for which we are the destination (The seller side), and
+/// that were not collected for at least `max_pending_ticks` time ticks.
+///
+/// Only the destination may time out a transaction: An intermediate node that cancels a
+/// transaction it has already forwarded might have to pay the next node without being able to
+/// collect from the previous node. The Cancel we send travels back along the route, releasing
+/// the frozen credits of all the nodes on the way, and eventually reaches the buyer.
+fn cancel_timed_out_transactions<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    current_tick: u64,
+    max_pending_ticks: usize,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let max_pending_ticks = usize_to_u64(max_pending_ticks).unwrap();
+    let local_public_key = m_state.state().local_public_key.clone();
+
+    // (friend_public_key, request_id, invoice_id)
+    let mut timed_out_transactions = Vec::new();
+    for (friend_public_key, friend) in &m_state.state().friends {
+        let token_channel = match &friend.channel_status {
+            ChannelStatus::Consistent(token_channel) => token_channel,
+            ChannelStatus::Inconsistent(_) => continue,
+        };
+        let pending_transactions = &token_channel
+            .get_mutual_credit()
+            .state()
+            .pending_transactions;
+        for (request_id, pending_transaction) in &pending_transactions.remote {
+            if pending_transaction.route.public_keys.last() != Some(&local_public_key) {
+                // We are not the destination of this transaction:
+                continue;
+            }
+            // Note that the tick counter starts from zero whenever the funder is started.
+            // A transaction created before a restart might time out late, but never early.
+            if current_tick.saturating_sub(pending_transaction.created_at_tick) < max_pending_ticks
+            {
+                continue;
+            }
+            timed_out_transactions.push((
+                friend_public_key.clone(),
+                request_id.clone(),
+                pending_transaction.invoice_id.clone(),
+            ));
+        }
+    }
+
+    for (friend_public_key, request_id, invoice_id) in timed_out_transactions {
+        if is_request_closing(m_state, &friend_public_key, &request_id) {
+            continue;
+        }
+
+        // The canceled transaction can not be collected anymore:
+        if m_state.state().open_invoices.contains_key(&invoice_id) {
+            let funder_mutation =
+                FunderMutation::RemoveIncomingTransaction((invoice_id, request_id.clone()));
+            m_state.mutate(funder_mutation);
+        }
+
+        reply_with_cancel(m_state, send_commands, &friend_public_key, &request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::hash_lock::{PlainLock, PLAIN_LOCK_LEN};
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::invoice_id::INVOICE_ID_LEN;
+    use crypto::uid::UID_LEN;
+
+    use proto::funder::messages::{AddFriend, FriendsRoute, RequestSendFundsOp};
+
+    use crate::ephemeral::Ephemeral;
+    use crate::friend::FriendMutation;
+    use crate::mutual_credit::types::McMutation;
+    use crate::state::FunderState;
+    use crate::token_channel::TcMutation;
+    use crate::types::create_pending_transaction;
+
+    use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+    #[test]
+    fn test_handle_timer_tick_expire_invoice() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk, relays);
+
+        let invoice_id_a = InvoiceId::from(&[0x1; INVOICE_ID_LEN]);
+        let invoice_id_b = InvoiceId::from(&[0x2; INVOICE_ID_LEN]);
+        state.mutate(&FunderMutation::AddInvoice((invoice_id_a.clone(), 100, 2)));
+        state.mutate(&FunderMutation::AddInvoice((invoice_id_b.clone(), 100, 3)));
+
+        let mut m_state = MutableFunderState::new(state);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let max_pending_ticks = 16;
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            max_pending_ticks,
+        );
+        assert_eq!(m_state.state().open_invoices.len(), 2);
+
+        // invoice_id_a expires:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            max_pending_ticks,
+        );
+        assert!(!m_state.state().open_invoices.contains_key(&invoice_id_a));
+        assert!(m_state.state().open_invoices.contains_key(&invoice_id_b));
+
+        // invoice_id_b expires:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            max_pending_ticks,
+        );
+        assert!(m_state.state().open_invoices.is_empty());
+
+        assert_eq!(m_ephemeral.ephemeral().current_tick, 3);
+    }
+
+    #[test]
+    fn test_handle_timer_tick_cancel_timed_out_transaction() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk.clone(), relays);
+
+        let add_friend = AddFriend {
+            friend_public_key: pk_b.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: "pk_b".into(),
+            note: String::new(),
+            balance: 0i128,
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        let invoice_id = InvoiceId::from(&[0x1; INVOICE_ID_LEN]);
+        state.mutate(&FunderMutation::AddInvoice((
+            invoice_id.clone(),
+            10,
+            u64::max_value(),
+        )));
+
+        // pk_b sent us a request, and we (The destination) have already responded:
+        let request_id = Uid::from(&[0x2; UID_LEN]);
+        let request_send_funds = RequestSendFundsOp {
+            request_id: request_id.clone(),
+            src_hashed_lock: PlainLock::from(&[0x3; PLAIN_LOCK_LEN]).hash(),
+            route: FriendsRoute {
+                public_keys: vec![pk_b.clone(), local_pk.clone()],
+            },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id: invoice_id.clone(),
+            left_fees: 0,
+        };
+        let pending_transaction = create_pending_transaction(&request_send_funds, 0);
+        let mc_mutation = McMutation::InsertRemotePendingTransaction(pending_transaction);
+        let friend_mutation = FriendMutation::TcMutation(TcMutation::McMutation(mc_mutation));
+        state.mutate(&FunderMutation::FriendMutation((
+            pk_b.clone(),
+            friend_mutation,
+        )));
+        state.mutate(&FunderMutation::AddIncomingTransaction((
+            invoice_id.clone(),
+            request_id.clone(),
+            PlainLock::from(&[0x4; PLAIN_LOCK_LEN]),
+            10,
+        )));
+
+        let mut m_state = MutableFunderState::new(state);
+        let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+        let mut send_commands = SendCommands::new();
+        let max_pending_ticks = 2;
+
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            max_pending_ticks,
+        );
+        let friend = m_state.state().friends.get(&pk_b).unwrap();
+        assert!(friend.pending_backwards_ops.is_empty());
+
+        // The transaction times out:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            max_pending_ticks,
+        );
+        let friend = m_state.state().friends.get(&pk_b).unwrap();
+        assert_eq!(friend.pending_backwards_ops.len(), 1);
+        match &friend.pending_backwards_ops[0] {
+            BackwardsOp::Cancel(cancel_send_funds) => {
+                assert_eq!(cancel_send_funds.request_id, request_id)
+            }
+            _ => unreachable!(),
+        };
+        let open_invoice = m_state.state().open_invoices.get(&invoice_id).unwrap();
+        assert!(open_invoice.incoming_transactions.is_empty());
+
+        // The transaction is canceled only once:
+        handle_timer_tick(
+            &mut m_state,
+            &mut m_ephemeral,
+            &mut send_commands,
+            max_pending_ticks,
+        );
+        let friend = m_state.state().friends.get(&pk_b).unwrap();
+        assert_eq!(friend.pending_backwards_ops.len(), 1);
+    }
+}
@@ -17,6 +17,7 @@ use crate::handler::handle_control::handle_control_message;
 use crate::handler::handle_friend::{handle_friend_message, HandleFriendError};
 use crate::handler::handle_init::handle_init;
 use crate::handler::handle_liveness::{handle_liveness_message, HandleLivenessError};
+use crate::handler::handle_timer::handle_timer_tick;
 use crate::handler::sender::{create_friend_messages, SendCommands};
 use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
 
@@ -52,7 +53,9 @@ pub fn funder_handle_incoming<B, R>(
     mut m_ephemeral: &mut MutableEphemeral,
     rng: &R,
     max_node_relays: usize,
+    max_friends: usize,
     max_pending_user_requests: usize,
+    max_pending_ticks: usize,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandleIncomingOutput<B>, FunderHandlerError>
 where
@@ -80,6 +83,7 @@ where
                 &mut outgoing_channeler_config,
                 rng,
                 max_node_relays,
+                max_friends,
                 max_pending_user_requests,
                 funder_incoming_control.funder_control,
             ) {
@@ -116,6 +120,16 @@ where
             };
             None
         }
+
+        FunderIncoming::TimerTick => {
+            handle_timer_tick(
+                &mut m_state,
+                &mut m_ephemeral,
+                &mut send_commands,
+                max_pending_ticks,
+            );
+            None
+        }
     };
 
     Ok((
@@ -163,8 +177,10 @@ pub async fn funder_handle_message<'a, B, R>(
     funder_state: FunderState<B>,
     funder_ephemeral: Ephemeral,
     max_node_relays: usize,
+    max_friends: usize,
     max_operations_in_batch: usize,
     max_pending_user_requests: usize,
+    max_pending_ticks: usize,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandlerOutput<B>, FunderHandlerError>
 where
@@ -181,7 +197,9 @@ where
             &mut m_ephemeral,
             rng,
             max_node_relays,
+            max_friends,
             max_pending_user_requests,
+            max_pending_ticks,
             funder_incoming,
         )?;
 
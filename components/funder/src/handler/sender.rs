@@ -15,15 +15,14 @@ use proto::funder::messages::{
 use identity::IdentityClient;
 
 use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
-use crate::types::{
-    create_cancel_send_funds, create_pending_transaction, create_unsigned_move_token,
-    sign_move_token, ChannelerConfig,
-};
+use crate::types::{create_cancel_send_funds, sign_move_token, ChannelerConfig};
 
 use crate::friend::{
     BackwardsOp, ChannelInconsistent, ChannelStatus, FriendMutation, SentLocalRelays,
 };
-use crate::token_channel::{SetDirection, TcDirection, TcMutation, TokenChannel};
+use crate::token_channel::{
+    create_unsigned_reset_move_token, SetDirection, TcDirection, TcMutation, TokenChannel,
+};
 
 use crate::ephemeral::Ephemeral;
 use crate::handler::state_wrap::MutableFunderState;
@@ -121,6 +120,8 @@ struct PendingMoveToken<B> {
     /// Can we send this move token with empty operations list
     /// and empty opt_local_address?
     may_send_empty: bool,
+    /// Recorded as the creation time of new pending transactions.
+    current_tick: u64,
 }
 
 impl<B> PendingMoveToken<B>
@@ -132,6 +133,7 @@ where
         outgoing_mc: OutgoingMc,
         max_operations_in_batch: usize,
         may_send_empty: bool,
+        current_tick: u64,
     ) -> Self {
         PendingMoveToken {
             friend_public_key,
@@ -141,6 +143,7 @@ where
             token_wanted: false,
             max_operations_in_batch,
             may_send_empty,
+            current_tick,
         }
     }
 
@@ -156,7 +159,10 @@ where
             return Err(PendingQueueError::MaxOperationsReached);
         }
 
-        let mc_mutations = match self.outgoing_mc.queue_operation(operation) {
+        let mc_mutations = match self
+            .outgoing_mc
+            .queue_operation(operation, self.current_tick)
+        {
             Ok(mc_mutations) => Ok(mc_mutations),
             Err(QueueOperationError::RequestAlreadyExists) => {
                 warn!("Request already exists: {:?}", operation);
@@ -233,23 +239,10 @@ pub async fn apply_local_reset<'a, B, R>(
     let remote_reset_terms = channel_inconsistent.opt_remote_reset_terms.clone().unwrap();
 
     let rand_nonce = RandValue::new(rng);
-    let move_token_counter = 0;
-
-    let local_pending_debt = 0;
-    let remote_pending_debt = 0;
-    let opt_local_relays = None;
-    let u_reset_move_token = create_unsigned_move_token(
-        // No operations are required for a reset move token
-        Vec::new(),
-        opt_local_relays,
-        remote_reset_terms.reset_token.clone(),
-        m_state.state().local_public_key.clone(),
-        friend_public_key.clone(),
-        remote_reset_terms.inconsistency_counter,
-        move_token_counter,
-        remote_reset_terms.balance_for_reset.checked_neg().unwrap(),
-        local_pending_debt,
-        remote_pending_debt,
+    let u_reset_move_token = create_unsigned_reset_move_token(
+        &m_state.state().local_public_key,
+        friend_public_key,
+        &remote_reset_terms,
         rand_nonce,
     );
 
@@ -277,6 +270,7 @@ async fn send_friend_iter1<'a, B, R>(
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
     max_operations_in_batch: usize,
+    current_tick: u64,
     cancel_public_keys: &'a mut HashSet<PublicKey>,
     mut outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
     outgoing_control: &'a mut Vec<FunderOutgoingControl<B>>,
@@ -366,6 +360,7 @@ async fn send_friend_iter1<'a, B, R>(
         outgoing_mc,
         max_operations_in_batch,
         may_send_empty,
+        current_tick,
     );
     pending_move_tokens.insert(friend_public_key.clone(), pending_move_token);
     let pending_move_token = pending_move_tokens.get_mut(friend_public_key).unwrap();
@@ -469,9 +464,8 @@ where
         Some(origin_public_key) => {
             // The friend with public key `origin_public_key` is the origin of this request.
             // We send him back a Cancel message:
-            let pending_transaction = create_pending_transaction(request_send_funds);
             let cancel_send_funds = BackwardsOp::Cancel(create_cancel_send_funds(
-                pending_transaction.request_id.clone(),
+                request_send_funds.request_id.clone(),
             ));
             let friend_mutation = FriendMutation::PushBackPendingBackwardsOp(cancel_send_funds);
             let funder_mutation =
@@ -573,6 +567,7 @@ where
             friend_public_key: friend_public_key.clone(),
             friend_relays: friend.remote_relays.clone(),
             local_relays: friend.sent_local_relays.to_vec(),
+            connection_priority: friend.connection_priority(),
         };
         let channeler_config = ChannelerConfig::UpdateFriend(update_friend);
         outgoing_channeler_config.push(channeler_config);
@@ -837,6 +832,7 @@ fn init_cancel_pending_move_token<B>(
             outgoing_mc,
             max_operations_in_batch,
             may_send_empty,
+            ephemeral.current_tick,
         );
         pending_move_tokens.insert(friend_public_key.clone(), pending_move_token);
     }
@@ -878,6 +874,7 @@ where
             identity_client,
             rng,
             max_operations_in_batch,
+            ephemeral.current_tick,
             &mut cancel_public_keys,
             &mut outgoing_messages,
             &mut outgoing_control,
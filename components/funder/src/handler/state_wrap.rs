@@ -102,6 +102,7 @@ where
                 pending_transaction.invoice_id,
                 pending_transaction.request_id,
                 dest_plain_lock,
+                pending_transaction.dest_payment,
             ));
             self.mutate(funder_mutation);
         }
@@ -79,6 +79,7 @@ async fn task_handler_change_address(
         friend_public_key: pk2.clone(),
         relays: vec![dummy_relay_address(2)],
         name: String::from("pk2"),
+        note: String::new(),
         balance: 0i128,
     };
     let incoming_control_message = FunderIncomingControl::new(
@@ -119,6 +120,7 @@ async fn task_handler_change_address(
         friend_public_key: pk1.clone(),
         relays: vec![dummy_relay_address(1)],
         name: String::from("pk1"),
+        note: String::new(),
         balance: 0i128,
     };
     let incoming_control_message = FunderIncomingControl::new(
@@ -220,7 +222,7 @@ async fn task_handler_change_address(
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, true);
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
 
                 assert_eq!(friend_move_token.move_token_counter, 1);
                 assert_eq!(friend_move_token.inconsistency_counter, 0);
@@ -256,7 +258,7 @@ async fn task_handler_change_address(
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
 
                 assert_eq!(friend_move_token.move_token_counter, 2);
                 assert_eq!(friend_move_token.inconsistency_counter, 0);
@@ -292,7 +294,7 @@ async fn task_handler_change_address(
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
 
                 assert_eq!(friend_move_token.move_token_counter, 3);
                 assert_eq!(friend_move_token.inconsistency_counter, 0);
@@ -365,7 +367,7 @@ async fn task_handler_change_address(
                 assert_eq!(pk, &pk2);
                 assert_eq!(move_token_request.token_wanted, true);
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
 
                 assert_eq!(friend_move_token.move_token_counter, 4);
                 assert_eq!(friend_move_token.inconsistency_counter, 0);
@@ -399,7 +401,7 @@ async fn task_handler_change_address(
                 assert_eq!(pk, &pk1);
                 assert_eq!(move_token_request.token_wanted, false);
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
 
                 assert_eq!(friend_move_token.move_token_counter, 5);
                 assert_eq!(friend_move_token.inconsistency_counter, 0);
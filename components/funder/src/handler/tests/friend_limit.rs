@@ -0,0 +1,73 @@
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+
+use proto::funder::messages::{AddFriend, FunderControl};
+
+use crate::ephemeral::Ephemeral;
+use crate::handler::handle_control::{handle_control_message, HandleControlError};
+use crate::handler::sender::SendCommands;
+use crate::handler::state_wrap::{MutableEphemeral, MutableFunderState};
+use crate::state::FunderState;
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+/// Apply an AddFriend control message to m_state, allowing at most `max_friends` friends.
+fn apply_add_friend(
+    m_state: &mut MutableFunderState<u32>,
+    max_friends: usize,
+    friend_public_key: &PublicKey,
+    name: &str,
+) -> Result<(), HandleControlError> {
+    let mut m_ephemeral = MutableEphemeral::new(Ephemeral::new());
+    let mut send_commands = SendCommands::new();
+    let mut outgoing_control = Vec::new();
+    let mut outgoing_channeler_config = Vec::new();
+    let rng = DummyRandom::new(&[1u8]);
+
+    let add_friend = AddFriend {
+        friend_public_key: friend_public_key.clone(),
+        relays: vec![dummy_relay_address(2)],
+        name: name.to_owned(),
+        note: String::new(),
+        balance: 0i128,
+    };
+
+    handle_control_message(
+        m_state,
+        &mut m_ephemeral,
+        &mut send_commands,
+        &mut outgoing_control,
+        &mut outgoing_channeler_config,
+        &rng,
+        16, // max_node_relays
+        max_friends,
+        16, // max_pending_user_requests
+        FunderControl::AddFriend(add_friend),
+    )
+}
+
+#[test]
+fn test_handler_add_friend_limit() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+
+    let state = FunderState::<u32>::new(local_public_key, vec![dummy_named_relay_address(1)]);
+    let mut m_state = MutableFunderState::new(state);
+
+    // The first friend fits:
+    apply_add_friend(&mut m_state, 1, &pk_b, "b").unwrap();
+    assert_eq!(m_state.state().friends.len(), 1);
+
+    // We are at the limit. A new friend is rejected and nothing is added:
+    match apply_add_friend(&mut m_state, 1, &pk_c, "c") {
+        Err(HandleControlError::FriendLimitReached) => {}
+        _ => unreachable!(),
+    };
+    assert_eq!(m_state.state().friends.len(), 1);
+    assert!(!m_state.state().friends.contains_key(&pk_c));
+
+    // Re-adding an existing friend at the limit still succeeds, and keeps the existing friend:
+    apply_add_friend(&mut m_state, 1, &pk_b, "b2").unwrap();
+    assert_eq!(m_state.state().friends.len(), 1);
+    assert_eq!(m_state.state().friends.get(&pk_b).unwrap().name, "b");
+}
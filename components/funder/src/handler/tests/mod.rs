@@ -1,4 +1,5 @@
 mod change_address;
+mod friend_limit;
 mod pair_basic;
 mod pair_inconsistency;
 mod utils;
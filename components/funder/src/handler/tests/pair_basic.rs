@@ -22,6 +22,7 @@ use proto::funder::messages::{
     SetFriendStatus, SetRequestsStatus,
 };
 
+use crate::balance::Balance;
 use crate::ephemeral::Ephemeral;
 use crate::friend::ChannelStatus;
 use crate::state::FunderState;
@@ -86,6 +87,7 @@ async fn task_handler_pair_basic<'a>(
         friend_public_key: pk2.clone(),
         relays: vec![dummy_relay_address(2)],
         name: String::from("pk2"),
+        note: String::new(),
         balance: 0i128,
     };
     let incoming_control_message = FunderIncomingControl::new(
@@ -126,6 +128,7 @@ async fn task_handler_pair_basic<'a>(
         friend_public_key: pk1.clone(),
         relays: vec![dummy_relay_address(1)],
         name: String::from("pk1"),
+        note: String::new(),
         balance: 0i128,
     };
     let incoming_control_message = FunderIncomingControl::new(
@@ -419,6 +422,7 @@ async fn task_handler_pair_basic<'a>(
     let add_invoice = AddInvoice {
         invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
         total_dest_payment: 16,
+        validity_ticks: 100,
     };
 
     let incoming_control_message = FunderIncomingControl::new(
@@ -835,7 +839,7 @@ async fn task_handler_pair_basic<'a>(
         ChannelStatus::Consistent(token_channel) => token_channel.get_mutual_credit().state(),
         _ => unreachable!(),
     };
-    assert_eq!(mutual_credit_state.balance.balance, 20);
+    assert_eq!(mutual_credit_state.balance.balance, Balance::from(20));
     assert_eq!(mutual_credit_state.balance.remote_pending_debt, 0);
     assert_eq!(mutual_credit_state.balance.local_pending_debt, 0);
 
@@ -845,7 +849,7 @@ async fn task_handler_pair_basic<'a>(
         ChannelStatus::Consistent(token_channel) => token_channel.get_mutual_credit().state(),
         _ => unreachable!(),
     };
-    assert_eq!(mutual_credit_state.balance.balance, -20);
+    assert_eq!(mutual_credit_state.balance.balance, Balance::from(-20));
     assert_eq!(mutual_credit_state.balance.remote_pending_debt, 0);
     assert_eq!(mutual_credit_state.balance.local_pending_debt, 0);
 
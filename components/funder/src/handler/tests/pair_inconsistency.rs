@@ -14,19 +14,61 @@ use crypto::test_utils::DummyRandom;
 use crypto::uid::{Uid, UID_LEN};
 
 use proto::funder::messages::{
-    AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl,
+    AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl, MoveToken,
     ResetFriendChannel, SetFriendStatus,
 };
 
+use crate::balance::Balance;
 use crate::ephemeral::Ephemeral;
 use crate::friend::ChannelStatus;
 use crate::state::FunderState;
+use crate::token_channel::{TcDirection, TokenChannel};
 use crate::types::{
     FunderIncoming, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
 };
 
 use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
 
+/// Check that a simulated reset outcome matches the token channel of an actual reset.
+/// Only the random nonce and the signature of the reset move token may differ.
+fn assert_same_reset_outcome(simulated: &TokenChannel<u32>, actual: &TokenChannel<u32>) {
+    let simulated_balance = &simulated.get_mutual_credit().state().balance;
+    let actual_balance = &actual.get_mutual_credit().state().balance;
+    assert_eq!(simulated_balance.balance, actual_balance.balance);
+    assert_eq!(
+        simulated_balance.local_max_debt,
+        actual_balance.local_max_debt
+    );
+    assert_eq!(
+        simulated_balance.remote_max_debt,
+        actual_balance.remote_max_debt
+    );
+    assert_eq!(
+        simulated_balance.local_pending_debt,
+        actual_balance.local_pending_debt
+    );
+    assert_eq!(
+        simulated_balance.remote_pending_debt,
+        actual_balance.remote_pending_debt
+    );
+    assert_eq!(
+        simulated.get_last_incoming_move_token_hashed(),
+        actual.get_last_incoming_move_token_hashed()
+    );
+
+    match (simulated.get_direction(), actual.get_direction()) {
+        (TcDirection::Outgoing(simulated_out), TcDirection::Outgoing(actual_out)) => {
+            let simulated_move_token = MoveToken {
+                rand_nonce: actual_out.move_token_out.rand_nonce.clone(),
+                new_token: actual_out.move_token_out.new_token.clone(),
+                ..simulated_out.move_token_out.clone()
+            };
+            assert_eq!(simulated_move_token, actual_out.move_token_out);
+        }
+        _ => unreachable!(),
+    }
+}
+
 async fn task_handler_pair_inconsistency<'a>(
     identity_client1: &'a mut IdentityClient,
     identity_client2: &'a mut IdentityClient,
@@ -81,6 +123,7 @@ async fn task_handler_pair_inconsistency<'a>(
         friend_public_key: pk2.clone(),
         relays: vec![dummy_relay_address(2)],
         name: String::from("pk2"),
+        note: String::new(),
         balance: 20i128,
     };
     let incoming_control_message = FunderIncomingControl::new(
@@ -123,6 +166,7 @@ async fn task_handler_pair_inconsistency<'a>(
         friend_public_key: pk1.clone(),
         relays: vec![dummy_relay_address(1)],
         name: String::from("pk1"),
+        note: String::new(),
         balance: -10i128,
     };
     let incoming_control_message = FunderIncomingControl::new(
@@ -321,6 +365,14 @@ async fn task_handler_pair_inconsistency<'a>(
     // Resolving the inconsistency
     // ---------------------------
 
+    // Node1: Preview the outcome of the reset:
+    let simulated_token_channel = state1
+        .friends
+        .get(&pk2)
+        .unwrap()
+        .simulate_reset_outcome()
+        .unwrap();
+
     // Node1: Reset channel, agreeing to Node2's conditions:
     let reset_friend_channel = ResetFriendChannel {
         friend_public_key: pk2.clone(),
@@ -345,8 +397,9 @@ async fn task_handler_pair_inconsistency<'a>(
         ChannelStatus::Consistent(token_channel) => {
             assert_eq!(
                 token_channel.get_mutual_credit().state().balance.balance,
-                10i128
+                Balance::from(10i128)
             );
+            assert_same_reset_outcome(&simulated_token_channel, token_channel);
         }
         _ => unreachable!(),
     };
@@ -396,7 +449,7 @@ async fn task_handler_pair_inconsistency<'a>(
                 assert_eq!(move_token_request.token_wanted, false);
 
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
                 assert_eq!(friend_move_token.move_token_counter, 1);
                 assert_eq!(friend_move_token.inconsistency_counter, 1);
                 assert_eq!(friend_move_token.balance, -10i128);
@@ -431,7 +484,7 @@ async fn task_handler_pair_inconsistency<'a>(
                 assert_eq!(move_token_request.token_wanted, true);
 
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
                 assert_eq!(friend_move_token.move_token_counter, 2);
                 assert_eq!(friend_move_token.inconsistency_counter, 1);
                 assert_eq!(friend_move_token.balance, 10i128);
@@ -467,7 +520,7 @@ async fn task_handler_pair_inconsistency<'a>(
                 assert_eq!(move_token_request.token_wanted, false);
 
                 let friend_move_token = &move_token_request.friend_move_token;
-                assert!(friend_move_token.operations.is_empty());
+                assert!(friend_move_token.is_empty());
                 assert_eq!(friend_move_token.move_token_counter, 3);
                 assert_eq!(friend_move_token.inconsistency_counter, 1);
                 assert_eq!(friend_move_token.balance, -10i128);
@@ -12,8 +12,10 @@ use crate::state::FunderState;
 use crate::types::{FunderIncoming, FunderOutgoingComm};
 
 const TEST_MAX_NODE_RELAYS: usize = 16;
+const TEST_MAX_FRIENDS: usize = 16;
 const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+const TEST_MAX_PENDING_TICKS: usize = 64;
 
 /// A helper function. Applies an incoming funder message, updating state and ephemeral
 /// accordingly:
@@ -34,8 +36,10 @@ where
         state.clone(),
         ephemeral.clone(),
         TEST_MAX_NODE_RELAYS,
+        TEST_MAX_FRIENDS,
         TEST_MAX_OPERATIONS_IN_BATCH,
         TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_MAX_PENDING_TICKS,
         funder_incoming
     ))?;
 
@@ -18,9 +18,11 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+mod balance;
 mod ephemeral;
 mod friend;
 mod funder;
+pub mod graph;
 mod handler;
 mod liveness;
 mod mutual_credit;
@@ -31,5 +33,9 @@ mod tests;
 mod token_channel;
 pub mod types;
 
+pub use self::friend::FriendMutation;
 pub use self::funder::{funder_loop, FunderError};
-pub use self::state::{FunderMutation, FunderState};
+pub use self::state::{
+    FunderMutation, FunderSnapshotError, FunderState, FunderStateSnapshot, JsonError,
+    FUNDER_STATE_SNAPSHOT_VERSION,
+};
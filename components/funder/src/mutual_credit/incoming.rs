@@ -1,7 +1,5 @@
 use crypto::identity::verify_signature;
 
-use common::safe_arithmetic::SafeSignedArithmetic;
-
 use proto::funder::messages::{
     CancelSendFundsOp, CollectSendFundsOp, FriendTcOp, PendingTransaction, RequestSendFundsOp,
     RequestsStatus, ResponseSendFundsOp, TransactionStage,
@@ -71,9 +69,11 @@ pub struct ProcessTransListError {
     process_trans_error: ProcessOperationError,
 }
 
+/// `current_tick` is the local funder's time tick, recorded for every new pending transaction.
 pub fn process_operations_list(
     mutual_credit: &mut MutualCredit,
     operations: Vec<FriendTcOp>,
+    current_tick: u64,
 ) -> Result<Vec<ProcessOperationOutput>, ProcessTransListError> {
     let mut outputs = Vec::new();
 
@@ -83,7 +83,7 @@ pub fn process_operations_list(
     // (specifically, HashMaps).
 
     for (index, funds) in operations.into_iter().enumerate() {
-        match process_operation(mutual_credit, funds) {
+        match process_operation(mutual_credit, funds, current_tick) {
             Err(e) => {
                 return Err(ProcessTransListError {
                     index,
@@ -99,6 +99,7 @@ pub fn process_operations_list(
 pub fn process_operation(
     mutual_credit: &mut MutualCredit,
     friend_tc_op: FriendTcOp,
+    current_tick: u64,
 ) -> Result<ProcessOperationOutput, ProcessOperationError> {
     match friend_tc_op {
         FriendTcOp::EnableRequests => process_enable_requests(mutual_credit),
@@ -107,7 +108,7 @@ pub fn process_operation(
             process_set_remote_max_debt(mutual_credit, proposed_max_debt)
         }
         FriendTcOp::RequestSendFunds(request_send_funds) => {
-            process_request_send_funds(mutual_credit, request_send_funds)
+            process_request_send_funds(mutual_credit, request_send_funds, current_tick)
         }
         FriendTcOp::ResponseSendFunds(response_send_funds) => {
             process_response_send_funds(mutual_credit, response_send_funds)
@@ -179,6 +180,7 @@ fn process_set_remote_max_debt(
 fn process_request_send_funds(
     mutual_credit: &mut MutualCredit,
     request_send_funds: RequestSendFundsOp,
+    current_tick: u64,
 ) -> Result<ProcessOperationOutput, ProcessOperationError> {
     if !request_send_funds.route.is_valid() {
         return Err(ProcessOperationError::InvalidRoute);
@@ -225,7 +227,7 @@ fn process_request_send_funds(
     if add
         .checked_sub_unsigned(balance.remote_max_debt)
         .ok_or(ProcessOperationError::CreditsCalcOverflow)?
-        > 0
+        .is_positive()
     {
         return Err(ProcessOperationError::InsufficientTrust);
     }
@@ -237,7 +239,7 @@ fn process_request_send_funds(
     }
 
     // Add pending transaction:
-    let pending_transaction = create_pending_transaction(&request_send_funds);
+    let pending_transaction = create_pending_transaction(&request_send_funds, current_tick);
 
     // let pending_friend_request = create_pending_transaction(&request_send_funds);
 
@@ -432,6 +434,14 @@ fn process_collect_send_funds(
     mutual_credit.mutate(&mc_mutation);
     mc_mutations.push(mc_mutation);
 
+    // The remote side collected the credits we have frozen:
+    let mc_mutation = McMutation::AddSentStats((
+        pending_transaction.dest_payment,
+        pending_transaction.left_fees,
+    ));
+    mutual_credit.mutate(&mc_mutation);
+    mc_mutations.push(mc_mutation);
+
     let incoming_message = Some(IncomingMessage::Collect(IncomingCollectSendFundsOp {
         pending_transaction,
         incoming_collect: collect_send_funds,
@@ -1,7 +1,5 @@
 use crypto::identity::verify_signature;
 
-use common::safe_arithmetic::SafeSignedArithmetic;
-
 use proto::funder::messages::{
     CancelSendFundsOp, CollectSendFundsOp, FriendTcOp, RequestSendFundsOp, RequestsStatus,
     ResponseSendFundsOp, TransactionStage,
@@ -10,10 +8,11 @@ use proto::funder::signature_buff::create_response_signature_buffer;
 
 use crate::types::create_pending_transaction;
 
-use super::types::{McMutation, MutualCredit, MAX_FUNDER_DEBT};
+use super::types::{McMutation, MutualCredit, MutualCreditState, MAX_FUNDER_DEBT};
 
 /// Processes outgoing funds for a token channel.
 /// Used to batch as many funds as possible.
+#[derive(Clone)]
 pub struct OutgoingMc {
     mutual_credit: MutualCredit,
 }
@@ -44,9 +43,27 @@ impl OutgoingMc {
         }
     }
 
+    /// The state of the mutual credit, including all the operations queued so far.
+    pub fn state(&self) -> &MutualCreditState {
+        self.mutual_credit.state()
+    }
+
+    /// Check if an operation could be queued, without queuing it.
+    /// The internal state of this OutgoingMc is not changed.
+    pub fn simulate_operation(&self, operation: &FriendTcOp) -> Result<(), QueueOperationError> {
+        // The tick does not affect the outcome of the operation:
+        let current_tick = 0;
+        self.clone()
+            .queue_operation(operation, current_tick)
+            .map(|_| ())
+    }
+
+    /// `current_tick` is the local funder's time tick, recorded for every new pending
+    /// transaction.
     pub fn queue_operation(
         &mut self,
         operation: &FriendTcOp,
+        current_tick: u64,
     ) -> Result<Vec<McMutation>, QueueOperationError> {
         // TODO: Maybe remove clone from here later:
         match operation.clone() {
@@ -56,7 +73,7 @@ impl OutgoingMc {
                 self.queue_set_remote_max_debt(proposed_max_debt)
             }
             FriendTcOp::RequestSendFunds(request_send_funds) => {
-                self.queue_request_send_funds(request_send_funds)
+                self.queue_request_send_funds(request_send_funds, current_tick)
             }
             FriendTcOp::ResponseSendFunds(response_send_funds) => {
                 self.queue_response_send_funds(response_send_funds)
@@ -107,6 +124,7 @@ impl OutgoingMc {
     fn queue_request_send_funds(
         &mut self,
         request_send_funds: RequestSendFundsOp,
+        current_tick: u64,
     ) -> Result<Vec<McMutation>, QueueOperationError> {
         if !request_send_funds.route.is_valid() {
             return Err(QueueOperationError::InvalidRoute);
@@ -153,7 +171,7 @@ impl OutgoingMc {
         if sub
             .checked_add_unsigned(balance.local_max_debt)
             .ok_or(QueueOperationError::CreditsCalcOverflow)?
-            < 0
+            .is_negative()
         {
             return Err(QueueOperationError::InsufficientTrust);
         }
@@ -166,7 +184,7 @@ impl OutgoingMc {
         }
 
         // Add pending transaction:
-        let pending_transaction = create_pending_transaction(&request_send_funds);
+        let pending_transaction = create_pending_transaction(&request_send_funds, current_tick);
 
         let mut mc_mutations = Vec::new();
         let mc_mutation = McMutation::InsertLocalPendingTransaction(pending_transaction);
@@ -341,6 +359,14 @@ impl OutgoingMc {
         self.mutual_credit.mutate(&mc_mutation);
         mc_mutations.push(mc_mutation);
 
+        // We collect the credits frozen by the remote side:
+        let mc_mutation = McMutation::AddReceivedStats((
+            pending_transaction.dest_payment,
+            pending_transaction.left_fees,
+        ));
+        self.mutual_credit.mutate(&mc_mutation);
+        mc_mutations.push(mc_mutation);
+
         Ok(mc_mutations)
     }
 }
@@ -15,13 +15,14 @@ use proto::funder::messages::{
 };
 use proto::funder::signature_buff::create_response_signature_buffer;
 
+use crate::balance::Balance;
 use crate::types::create_pending_transaction;
 
 use crate::mutual_credit::incoming::{
     process_operation, ProcessOperationError, ProcessOperationOutput,
 };
 use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
-use crate::mutual_credit::types::MutualCredit;
+use crate::mutual_credit::types::{ChannelStats, MutualCredit};
 
 /// Helper function for applying an outgoing operation over a token channel.
 fn apply_outgoing(
@@ -29,7 +30,7 @@ fn apply_outgoing(
     friend_tc_op: &FriendTcOp,
 ) -> Result<(), QueueOperationError> {
     let mut outgoing = OutgoingMc::new(mutual_credit);
-    let mutations = outgoing.queue_operation(friend_tc_op)?;
+    let mutations = outgoing.queue_operation(friend_tc_op, 0)?;
 
     for mutation in mutations {
         mutual_credit.mutate(&mutation);
@@ -42,7 +43,7 @@ fn apply_incoming(
     mut mutual_credit: &mut MutualCredit,
     friend_tc_op: FriendTcOp,
 ) -> Result<ProcessOperationOutput, ProcessOperationError> {
-    process_operation(&mut mutual_credit, friend_tc_op)
+    process_operation(&mut mutual_credit, friend_tc_op, 0)
 }
 
 #[test]
@@ -139,14 +140,14 @@ fn test_request_response_collect_send_funds() {
         left_fees: 5,
     };
 
-    let pending_transaction = create_pending_transaction(&request_send_funds);
+    let pending_transaction = create_pending_transaction(&request_send_funds, 0);
     apply_outgoing(
         &mut mutual_credit,
         &FriendTcOp::RequestSendFunds(request_send_funds),
     )
     .unwrap();
 
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
@@ -174,7 +175,7 @@ fn test_request_response_collect_send_funds() {
     .unwrap();
 
     // We expect that no changes to balance happened yet:
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
@@ -195,11 +196,103 @@ fn test_request_response_collect_send_funds() {
     .unwrap();
 
     // We expect that no changes to balance happened yet:
-    assert_eq!(mutual_credit.state().balance.balance, -15);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(-15));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
     assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+
+    let expected_channel_stats = ChannelStats {
+        total_sent: 10,
+        total_fees_sent: 5,
+        num_transactions_sent: 1,
+        ..ChannelStats::default()
+    };
+    assert_eq!(mutual_credit.state().channel_stats, expected_channel_stats);
+}
+
+#[test]
+fn test_outgoing_collect_send_funds() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    // Trust the remote side enough, and open our requests:
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+
+    let rng = DummyRandom::new(&[1u8]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+    let public_key_c = identity.get_public_key();
+
+    // -----[RequestSendFunds]--------
+    // -----------------------------
+    let request_id = Uid::from(&[3; UID_LEN]);
+    let src_plain_lock = PlainLock::from(&[1; PLAIN_LOCK_LEN]);
+    let request_send_funds = RequestSendFundsOp {
+        request_id: request_id.clone(),
+        src_hashed_lock: src_plain_lock.hash(),
+        route: FriendsRoute {
+            public_keys: vec![
+                remote_public_key.clone(),
+                local_public_key.clone(),
+                public_key_c,
+            ],
+        },
+        dest_payment: 10,
+        total_dest_payment: 10,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        left_fees: 5,
+    };
+    let pending_transaction = create_pending_transaction(&request_send_funds, 0);
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+
+    // -----[ResponseSendFunds]--------
+    // --------------------------------
+    let dest_plain_lock = PlainLock::from(&[2; PLAIN_LOCK_LEN]);
+    let mut response_send_funds = ResponseSendFundsOp {
+        request_id: request_id.clone(),
+        dest_hashed_lock: dest_plain_lock.hash(),
+        rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    let sign_buffer = create_response_signature_buffer(&response_send_funds, &pending_transaction);
+    response_send_funds.signature = identity.sign(&sign_buffer);
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::ResponseSendFunds(response_send_funds),
+    )
+    .unwrap();
+
+    // -----[CollectSendFunds]--------
+    // --------------------------------
+    let collect_send_funds = CollectSendFundsOp {
+        request_id,
+        src_plain_lock,
+        dest_plain_lock,
+    };
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::CollectSendFunds(collect_send_funds),
+    )
+    .unwrap();
+
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(15));
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+
+    let expected_channel_stats = ChannelStats {
+        total_received: 10,
+        total_fees_received: 5,
+        num_transactions_received: 1,
+        ..ChannelStats::default()
+    };
+    assert_eq!(mutual_credit.state().channel_stats, expected_channel_stats);
 }
 
 #[test]
@@ -253,7 +346,7 @@ fn test_request_cancel_send_funds() {
     )
     .unwrap();
 
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
@@ -269,7 +362,7 @@ fn test_request_cancel_send_funds() {
     )
     .unwrap();
 
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
@@ -321,14 +414,14 @@ fn test_request_response_cancel_send_funds() {
         left_fees: 5,
     };
 
-    let pending_transaction = create_pending_transaction(&request_send_funds);
+    let pending_transaction = create_pending_transaction(&request_send_funds, 0);
     apply_outgoing(
         &mut mutual_credit,
         &FriendTcOp::RequestSendFunds(request_send_funds),
     )
     .unwrap();
 
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
@@ -356,7 +449,7 @@ fn test_request_response_cancel_send_funds() {
     .unwrap();
 
     // We expect that no changes to balance happened yet:
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 10 + 5);
@@ -372,9 +465,125 @@ fn test_request_response_cancel_send_funds() {
     )
     .unwrap();
 
-    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(mutual_credit.state().balance.balance, Balance::from(0));
     assert_eq!(mutual_credit.state().balance.local_max_debt, 100);
     assert_eq!(mutual_credit.state().balance.remote_max_debt, 0);
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
     assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
 }
+
+#[test]
+fn test_simulate_request_send_funds() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    // Make enough trust from remote side, so that we will be able to send credits:
+    apply_incoming(&mut mutual_credit, FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+
+    let create_request = |request_id_byte: u8, dest_payment: u128| RequestSendFundsOp {
+        request_id: Uid::from(&[request_id_byte; UID_LEN]),
+        src_hashed_lock: PlainLock::from(&[1; PLAIN_LOCK_LEN]).hash(),
+        route: FriendsRoute {
+            public_keys: vec![local_public_key.clone(), remote_public_key.clone()],
+        },
+        dest_payment,
+        total_dest_payment: dest_payment,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        left_fees: 5,
+    };
+
+    let outgoing = OutgoingMc::new(&mutual_credit);
+
+    // Not enough trust for this request:
+    let res = outgoing.simulate_operation(&FriendTcOp::RequestSendFunds(create_request(3, 100)));
+    match res {
+        Err(QueueOperationError::InsufficientTrust) => {}
+        _ => unreachable!(),
+    };
+    assert_eq!(outgoing.state().balance.local_pending_debt, 0);
+    assert!(outgoing.state().pending_transactions.local.is_empty());
+
+    // A feasible request does not change the state either:
+    outgoing
+        .simulate_operation(&FriendTcOp::RequestSendFunds(create_request(4, 10)))
+        .unwrap();
+    assert_eq!(outgoing.state().balance.local_pending_debt, 0);
+    assert!(outgoing.state().pending_transactions.local.is_empty());
+
+    // The same request can be simulated again, as it was never queued:
+    outgoing
+        .simulate_operation(&FriendTcOp::RequestSendFunds(create_request(4, 10)))
+        .unwrap();
+
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
+    assert!(mutual_credit.state().pending_transactions.local.is_empty());
+}
+
+/// Process an incoming request that goes through the given route.
+/// The route should contain the pair (remote, local) = (0xbb, 0xaa).
+fn incoming_request_with_route(
+    public_keys: Vec<PublicKey>,
+) -> Result<ProcessOperationOutput, ProcessOperationError> {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    // Trust the remote side enough, and open our requests:
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+
+    let request_send_funds = RequestSendFundsOp {
+        request_id: Uid::from(&[3; UID_LEN]),
+        src_hashed_lock: PlainLock::from(&[1; PLAIN_LOCK_LEN]).hash(),
+        route: FriendsRoute { public_keys },
+        dest_payment: 10,
+        total_dest_payment: 10,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        left_fees: 5,
+    };
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+}
+
+#[test]
+fn test_incoming_request_route_without_cycle() {
+    let pk = |i: u8| PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+    incoming_request_with_route(vec![pk(0xcc), pk(0xbb), pk(0xaa), pk(0xdd)]).unwrap();
+}
+
+#[test]
+fn test_incoming_request_route_single_cycle() {
+    let pk = |i: u8| PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+    // First and last are the same (The request goes back to its origin).
+    // `FriendsRoute::is_valid()` accepts such a route on purpose:
+    incoming_request_with_route(vec![pk(0xcc), pk(0xbb), pk(0xaa), pk(0xcc)]).unwrap();
+    incoming_request_with_route(vec![pk(0xbb), pk(0xaa), pk(0xcc), pk(0xbb)]).unwrap();
+}
+
+#[test]
+fn test_incoming_request_route_cycle() {
+    let pk = |i: u8| PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+    let cyclic_routes = vec![
+        // First two:
+        vec![pk(0xcc), pk(0xcc), pk(0xbb), pk(0xaa)],
+        // Last two:
+        vec![pk(0xbb), pk(0xaa), pk(0xcc), pk(0xdd), pk(0xdd)],
+        // Middle of the route:
+        vec![pk(0xbb), pk(0xaa), pk(0xcc), pk(0xdd), pk(0xcc), pk(0xee)],
+        // A longer cycle that passes through us twice:
+        vec![pk(0xbb), pk(0xaa), pk(0xcc), pk(0xdd), pk(0xaa), pk(0xee)],
+    ];
+
+    for public_keys in cyclic_routes {
+        match incoming_request_with_route(public_keys) {
+            Err(ProcessOperationError::InvalidRoute) => {}
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,11 +1,12 @@
 use im::hashmap::HashMap as ImHashMap;
 
-use common::safe_arithmetic::SafeSignedArithmetic;
 use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
 use proto::funder::messages::{PendingTransaction, RequestsStatus, TransactionStage};
 
+use crate::balance::Balance;
+
 /// The maximum possible funder debt.
 /// We don't use the full u128 because i128 can not go beyond this value.
 pub const MAX_FUNDER_DEBT: u128 = (1 << 127) - 1;
@@ -24,7 +25,7 @@ pub struct McIdents {
 pub struct McBalance {
     /// Amount of credits this side has against the remote side.
     /// The other side keeps the negation of this value.
-    pub balance: i128,
+    pub balance: Balance,
     /// Maximum possible local debt
     pub local_max_debt: u128,
     /// Maximum possible remote debt
@@ -36,7 +37,7 @@ pub struct McBalance {
 }
 
 impl McBalance {
-    fn new(balance: i128) -> McBalance {
+    fn new(balance: Balance) -> McBalance {
         McBalance {
             balance,
             local_max_debt: 0,
@@ -53,8 +54,10 @@ impl McBalance {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct McPendingTransactions {
     /// Pending transactions that were opened locally and not yet completed
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub local: ImHashMap<Uid, PendingTransaction>,
     /// Pending transactions that were opened remotely and not yet completed
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub remote: ImHashMap<Uid, PendingTransaction>,
 }
 
@@ -84,6 +87,38 @@ impl McRequestsStatus {
     }
 }
 
+/// Cumulative amounts of credits that were collected through the channel with a friend.
+/// Kept since the channel was created or last reset.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct ChannelStats {
+    /// Credits paid to the friend for the destination of transactions (Not including fees)
+    pub total_sent: u128,
+    /// Credits received from the friend for the destination of transactions (Not including fees)
+    pub total_received: u128,
+    /// Fees paid to the friend, on top of `total_sent`.
+    /// These fees are split between the friend and the next nodes along the route.
+    pub total_fees_sent: u128,
+    /// Fees received from the friend, on top of `total_received`.
+    /// These fees are split between us and the next nodes along the route.
+    pub total_fees_received: u128,
+    pub num_transactions_sent: u64,
+    pub num_transactions_received: u64,
+}
+
+impl ChannelStats {
+    fn add_sent(&mut self, dest_payment: u128, fees: u128) {
+        self.total_sent = self.total_sent.saturating_add(dest_payment);
+        self.total_fees_sent = self.total_fees_sent.saturating_add(fees);
+        self.num_transactions_sent = self.num_transactions_sent.saturating_add(1);
+    }
+
+    fn add_received(&mut self, dest_payment: u128, fees: u128) {
+        self.total_received = self.total_received.saturating_add(dest_payment);
+        self.total_fees_received = self.total_fees_received.saturating_add(fees);
+        self.num_transactions_received = self.num_transactions_received.saturating_add(1);
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct MutualCreditState {
     /// Public identities of local and remote side
@@ -96,6 +131,8 @@ pub struct MutualCreditState {
     /// We can allow or disallow opening new requests from the remote side to our side.
     /// The remote side controls the opposite direction.
     pub requests_status: McRequestsStatus,
+    /// Credits collected through the channel
+    pub channel_stats: ChannelStats,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -109,7 +146,7 @@ pub enum McMutation {
     SetRemoteRequestsStatus(RequestsStatus),
     SetLocalMaxDebt(u128),
     SetRemoteMaxDebt(u128),
-    SetBalance(i128),
+    SetBalance(Balance),
     InsertLocalPendingTransaction(PendingTransaction),
     RemoveLocalPendingTransaction(Uid),
     SetLocalPendingTransactionStage((Uid, TransactionStage)),
@@ -118,6 +155,8 @@ pub enum McMutation {
     SetRemotePendingTransactionStage((Uid, TransactionStage)),
     SetLocalPendingDebt(u128),
     SetRemotePendingDebt(u128),
+    AddSentStats((u128, u128)),     // (dest_payment, fees)
+    AddReceivedStats((u128, u128)), // (dest_payment, fees)
 }
 
 impl MutualCredit {
@@ -132,9 +171,10 @@ impl MutualCredit {
                     local_public_key: local_public_key.clone(),
                     remote_public_key: remote_public_key.clone(),
                 },
-                balance: McBalance::new(balance),
+                balance: McBalance::new(Balance::from(balance)),
                 pending_transactions: McPendingTransactions::new(),
                 requests_status: McRequestsStatus::new(),
+                channel_stats: ChannelStats::default(),
             },
         }
     }
@@ -142,11 +182,13 @@ impl MutualCredit {
     /// Calculate required balance for reset.
     /// This would be current balance plus additional future profits.
     pub fn balance_for_reset(&self) -> i128 {
-        self.state
+        let balance_for_reset = self
+            .state
             .balance
             .balance
             .checked_add_unsigned(self.state.balance.remote_pending_debt)
-            .expect("Overflow when calculating balance_for_reset")
+            .expect("Overflow when calculating balance_for_reset");
+        i128::from(balance_for_reset)
         // TODO: Is this the correct formula?
         // Other options:
         // *    balance
@@ -196,6 +238,12 @@ impl MutualCredit {
             McMutation::SetRemotePendingDebt(remote_pending_debt) => {
                 self.set_remote_pending_debt(*remote_pending_debt)
             }
+            McMutation::AddSentStats((dest_payment, fees)) => {
+                self.state.channel_stats.add_sent(*dest_payment, *fees)
+            }
+            McMutation::AddReceivedStats((dest_payment, fees)) => {
+                self.state.channel_stats.add_received(*dest_payment, *fees)
+            }
         }
     }
 
@@ -215,7 +263,7 @@ impl MutualCredit {
         self.state.balance.local_max_debt = proposed_max_debt;
     }
 
-    fn set_balance(&mut self, balance: i128) {
+    fn set_balance(&mut self, balance: Balance) {
         self.state.balance.balance = balance;
     }
 
@@ -4,10 +4,11 @@ use common::canonical_serialize::CanonicalSerialize;
 use common::int_convert::usize_to_u64;
 
 use proto::report::messages::{
-    AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, DirectionReport,
-    FriendLivenessReport, FriendReport, FriendReportMutation, FriendStatusReport, FunderReport,
-    FunderReportMutation, McBalanceReport, McRequestsStatusReport, MoveTokenHashedReport,
-    RequestsStatusReport, ResetTermsReport, SentLocalRelaysReport, TcReport,
+    AddFriendReport, ChannelInconsistentReport, ChannelStatsReport, ChannelStatusReport,
+    DirectionReport, FriendLivenessReport, FriendReport, FriendReportMutation, FriendStatusReport,
+    FunderReport, FunderReportMutation, InvoiceProgressReport, McBalanceReport,
+    McRequestsStatusReport, MoveTokenHashedReport, RequestsStatusReport, ResetTermsReport,
+    SentLocalRelaysReport, TcReport,
 };
 
 use crate::types::MoveTokenHashed;
@@ -15,8 +16,8 @@ use crate::types::MoveTokenHashed;
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
 use crate::friend::{ChannelStatus, FriendMutation, FriendState, SentLocalRelays};
 use crate::liveness::LivenessMutation;
-use crate::mutual_credit::types::{McBalance, McRequestsStatus};
-use crate::state::{FunderMutation, FunderState};
+use crate::mutual_credit::types::{ChannelStats, McBalance, McMutation, McRequestsStatus};
+use crate::state::{FunderMutation, FunderState, OpenInvoice};
 use crate::token_channel::{TcDirection, TcMutation, TokenChannel};
 
 impl<B> Into<SentLocalRelaysReport<B>> for &SentLocalRelays<B>
@@ -32,6 +33,28 @@ where
     }
 }
 
+impl From<&OpenInvoice> for InvoiceProgressReport {
+    fn from(open_invoice: &OpenInvoice) -> InvoiceProgressReport {
+        InvoiceProgressReport {
+            collected: open_invoice.collected(),
+            total_dest_payment: open_invoice.total_dest_payment,
+        }
+    }
+}
+
+impl From<&ChannelStats> for ChannelStatsReport {
+    fn from(channel_stats: &ChannelStats) -> ChannelStatsReport {
+        ChannelStatsReport {
+            total_sent: channel_stats.total_sent,
+            total_received: channel_stats.total_received,
+            total_fees_sent: channel_stats.total_fees_sent,
+            total_fees_received: channel_stats.total_fees_received,
+            num_transactions_sent: channel_stats.num_transactions_sent,
+            num_transactions_received: channel_stats.num_transactions_received,
+        }
+    }
+}
+
 impl From<&McRequestsStatus> for McRequestsStatusReport {
     fn from(mc_requests_status: &McRequestsStatus) -> McRequestsStatusReport {
         McRequestsStatusReport {
@@ -44,7 +67,7 @@ impl From<&McRequestsStatus> for McRequestsStatusReport {
 impl From<&McBalance> for McBalanceReport {
     fn from(mc_balance: &McBalance) -> McBalanceReport {
         McBalanceReport {
-            balance: mc_balance.balance,
+            balance: i128::from(mc_balance.balance),
             remote_max_debt: mc_balance.remote_max_debt,
             local_max_debt: mc_balance.local_max_debt,
             local_pending_debt: mc_balance.local_pending_debt,
@@ -125,7 +148,20 @@ where
     }
 }
 
-fn create_friend_report<B>(
+/// Statistics of the token channel. A channel that is inconsistent has no statistics.
+fn create_channel_stats_report<B>(channel_status: &ChannelStatus<B>) -> ChannelStatsReport
+where
+    B: Clone + CanonicalSerialize,
+{
+    match channel_status {
+        ChannelStatus::Consistent(token_channel) => {
+            ChannelStatsReport::from(&token_channel.get_mutual_credit().state().channel_stats)
+        }
+        ChannelStatus::Inconsistent(_) => ChannelStatsReport::default(),
+    }
+}
+
+pub(crate) fn create_friend_report<B>(
     friend_state: &FriendState<B>,
     friend_liveness: &FriendLivenessReport,
 ) -> FriendReport<B>
@@ -136,6 +172,7 @@ where
 
     FriendReport {
         name: friend_state.name.clone(),
+        note: friend_state.note.clone(),
         rate: friend_state.rate.clone(),
         remote_relays: friend_state.remote_relays.clone(),
         sent_local_relays: (&friend_state.sent_local_relays).into(),
@@ -153,10 +190,17 @@ where
         num_pending_backwards_ops: usize_to_u64(friend_state.pending_backwards_ops.len()).unwrap(),
         status: FriendStatusReport::from(&friend_state.status),
         num_pending_user_requests: usize_to_u64(friend_state.pending_user_requests.len()).unwrap(),
+        channel_stats: create_channel_stats_report(&friend_state.channel_status),
+        is_frozen: friend_state.is_frozen,
     }
 }
 
-pub fn create_report<B>(funder_state: &FunderState<B>, ephemeral: &Ephemeral) -> FunderReport<B>
+pub fn create_report<B>(
+    funder_state: &FunderState<B>,
+    ephemeral: &Ephemeral,
+    max_friends: usize,
+    max_node_relays: usize,
+) -> FunderReport<B>
 where
     B: Clone + CanonicalSerialize,
 {
@@ -176,16 +220,38 @@ where
         relays: funder_state.relays.clone(),
         friends,
         num_open_invoices: usize_to_u64(funder_state.open_invoices.len()).unwrap(),
+        invoices_progress: funder_state
+            .open_invoices
+            .iter()
+            .map(|(invoice_id, open_invoice)| {
+                (
+                    invoice_id.clone(),
+                    InvoiceProgressReport::from(open_invoice),
+                )
+            })
+            .collect(),
         num_payments: usize_to_u64(funder_state.payments.len()).unwrap(),
         num_open_transactions: usize_to_u64(funder_state.open_transactions.len()).unwrap(),
+        total_frozen_credits: funder_state.total_frozen_credits(),
+        max_friends: usize_to_u64(max_friends).unwrap(),
+        max_node_relays: usize_to_u64(max_node_relays).unwrap(),
     }
 }
 
-pub fn create_initial_report<B>(funder_state: &FunderState<B>) -> FunderReport<B>
+pub fn create_initial_report<B>(
+    funder_state: &FunderState<B>,
+    max_friends: usize,
+    max_node_relays: usize,
+) -> FunderReport<B>
 where
     B: Clone + CanonicalSerialize,
 {
-    create_report(funder_state, &Ephemeral::new())
+    create_report(
+        funder_state,
+        &Ephemeral::new(),
+        max_friends,
+        max_node_relays,
+    )
 }
 
 pub fn friend_mutation_to_report_mutations<B>(
@@ -212,7 +278,18 @@ where
                                 MoveTokenHashedReport::from(&move_token_hashed)
                             }),
                     );
-                vec![set_channel_status, set_last_incoming_move_token]
+                let mut report_mutations = vec![set_channel_status, set_last_incoming_move_token];
+                let is_stats_mutation = match tc_mutation {
+                    TcMutation::McMutation(McMutation::AddSentStats(_))
+                    | TcMutation::McMutation(McMutation::AddReceivedStats(_)) => true,
+                    _ => false,
+                };
+                if is_stats_mutation {
+                    report_mutations.push(FriendReportMutation::SetChannelStats(
+                        create_channel_stats_report(&friend_after.channel_status),
+                    ));
+                }
+                report_mutations
             }
         },
         FriendMutation::SetWantedRemoteMaxDebt(wanted_remote_max_debt) => {
@@ -262,12 +339,15 @@ where
             vec![FriendReportMutation::SetRemoteRelays(remote_relays.clone())]
         }
         FriendMutation::SetName(name) => vec![FriendReportMutation::SetName(name.clone())],
+        FriendMutation::SetNote(note) => vec![FriendReportMutation::SetNote(note.clone())],
         FriendMutation::SetRate(rate) => vec![FriendReportMutation::SetRate(rate.clone())],
         FriendMutation::SetSentLocalRelays(sent_local_relays) => {
             vec![FriendReportMutation::SetSentLocalRelays(
                 sent_local_relays.into(),
             )]
         }
+        FriendMutation::AddHopLatencySample(_) => Vec::new(),
+        FriendMutation::SetFrozen(is_frozen) => vec![FriendReportMutation::SetFrozen(*is_frozen)],
         FriendMutation::SetInconsistent(_) | FriendMutation::SetConsistent(_) => {
             let channel_status_report = ChannelStatusReport::from(&friend_after.channel_status);
             let set_channel_status = FriendReportMutation::SetChannelStatus(channel_status_report);
@@ -277,7 +357,14 @@ where
                 .map(|move_token_hashed| MoveTokenHashedReport::from(&move_token_hashed));
             let set_last_incoming_move_token =
                 FriendReportMutation::SetOptLastIncomingMoveToken(opt_move_token_hashed_report);
-            vec![set_channel_status, set_last_incoming_move_token]
+            let set_channel_stats = FriendReportMutation::SetChannelStats(
+                create_channel_stats_report(&friend_after.channel_status),
+            );
+            vec![
+                set_channel_status,
+                set_last_incoming_move_token,
+                set_channel_stats,
+            ]
         }
     }
 }
@@ -298,7 +385,7 @@ where
 {
     let mut funder_state_after = funder_state.clone();
     funder_state_after.mutate(funder_mutation);
-    match funder_mutation {
+    let mut report_mutations = match funder_mutation {
         FunderMutation::FriendMutation((public_key, friend_mutation)) => {
             let friend = funder_state.friends.get(public_key).unwrap();
             friend_mutation_to_report_mutations(&friend_mutation, &friend)
@@ -325,6 +412,7 @@ where
             let add_friend_report = AddFriendReport {
                 friend_public_key: add_friend.friend_public_key.clone(),
                 name: add_friend.name.clone(),
+                note: add_friend.note.clone(),
                 relays: add_friend.relays.clone(),
                 balance: add_friend.balance, // Initial balance
                 opt_last_incoming_move_token: friend_after
@@ -340,16 +428,39 @@ where
                 friend_public_key.clone(),
             )]
         }
-        FunderMutation::AddInvoice(_) | FunderMutation::RemoveInvoice(_) => {
+        FunderMutation::AddInvoice((invoice_id, _, _))
+        | FunderMutation::RemoveInvoice(invoice_id) => {
+            let mut invoice_report_mutations = Vec::new();
             if funder_state_after.open_invoices.len() != funder_state.open_invoices.len() {
-                vec![FunderReportMutation::SetNumOpenInvoices(
+                invoice_report_mutations.push(FunderReportMutation::SetNumOpenInvoices(
                     usize_to_u64(funder_state_after.open_invoices.len()).unwrap(),
-                )]
-            } else {
-                Vec::new()
+                ));
+            }
+            match funder_state_after.open_invoices.get(invoice_id) {
+                Some(open_invoice) => {
+                    invoice_report_mutations.push(FunderReportMutation::SetInvoiceProgress((
+                        invoice_id.clone(),
+                        InvoiceProgressReport::from(open_invoice),
+                    )))
+                }
+                None => {
+                    if funder_state.open_invoices.contains_key(invoice_id) {
+                        invoice_report_mutations.push(FunderReportMutation::RemoveInvoiceProgress(
+                            invoice_id.clone(),
+                        ))
+                    }
+                }
             }
+            invoice_report_mutations
+        }
+        FunderMutation::AddIncomingTransaction((invoice_id, _, _, _))
+        | FunderMutation::RemoveIncomingTransaction((invoice_id, _)) => {
+            let open_invoice = funder_state_after.open_invoices.get(invoice_id).unwrap();
+            vec![FunderReportMutation::SetInvoiceProgress((
+                invoice_id.clone(),
+                InvoiceProgressReport::from(open_invoice),
+            ))]
         }
-        FunderMutation::AddIncomingTransaction(_) => vec![],
         FunderMutation::AddTransaction(_) | FunderMutation::RemoveTransaction(_) => {
             if funder_state_after.open_transactions.len() != funder_state.open_transactions.len() {
                 vec![FunderReportMutation::SetNumOpenTransactions(
@@ -360,6 +471,7 @@ where
             }
         }
         FunderMutation::SetTransactionResponse(_) => vec![],
+        FunderMutation::AddPaymentEvent(_) | FunderMutation::PrunePaymentHistory(_) => vec![],
         FunderMutation::UpdatePayment(_) | FunderMutation::RemovePayment(_) => {
             if funder_state_after.payments.len() != funder_state.payments.len() {
                 vec![FunderReportMutation::SetNumPayments(
@@ -369,7 +481,16 @@ where
                 Vec::new()
             }
         }
+    };
+
+    let total_frozen_credits_after = funder_state_after.total_frozen_credits();
+    if total_frozen_credits_after != funder_state.total_frozen_credits() {
+        report_mutations.push(FunderReportMutation::SetTotalFrozenCredits(
+            total_frozen_credits_after,
+        ));
     }
+
+    report_mutations
 }
 
 pub fn ephemeral_mutation_to_report_mutations<B>(
@@ -417,5 +538,6 @@ where
                 ))]
             }
         },
+        EphemeralMutation::AddMoveTokenBatchSize(_) | EphemeralMutation::TimerTick => Vec::new(),
     }
 }
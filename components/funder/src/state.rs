@@ -1,7 +1,11 @@
 use im::hashmap::HashMap as ImHashMap;
 use im::vector::Vector as ImVec;
 
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
 use crypto::hash_lock::{HashedLock, PlainLock};
 use crypto::identity::PublicKey;
 use crypto::invoice_id::InvoiceId;
@@ -9,9 +13,24 @@ use crypto::payment_id::PaymentId;
 use crypto::uid::Uid;
 
 use proto::app_server::messages::NamedRelayAddress;
-use proto::funder::messages::{AddFriend, Receipt, ResponseSendFundsOp};
+use proto::funder::messages::{
+    AddFriend, FriendsRoute, PaymentEvent, PaymentEventKind, PaymentHistoryEntry, PaymentOutcome,
+    Receipt, ResponseSendFundsOp,
+};
+
+use crate::friend::{ChannelStatus, FriendMutation, FriendState};
+
+/// Maximum amount of events kept in the timeline of a single payment.
+/// Older events are discarded first.
+pub const MAX_PAYMENT_TIMELINE_LEN: usize = 100;
+
+/// Version of the `FunderStateSnapshot` format.
+/// Should be increased whenever the serialized layout of `FunderState` changes (Together with
+/// `NODE_STATE_VERSION`, as `FunderState` is also stored in the node's database).
+pub const FUNDER_STATE_SNAPSHOT_VERSION: u32 = 6;
 
-use crate::friend::{FriendMutation, FriendState};
+/// An error loading a `FunderState` from its JSON representation.
+pub type JsonError = serde_json::Error;
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct FunderState<B: Clone> {
@@ -20,13 +39,56 @@ pub struct FunderState<B: Clone> {
     /// Addresses of relays we are going to connect to.
     pub relays: ImVec<NamedRelayAddress<B>>,
     /// All configured friends and their state
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub friends: ImHashMap<PublicKey, FriendState<B>>,
     /// Locally issued invoices in progress (For which this node is the seller)
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub open_invoices: ImHashMap<InvoiceId, OpenInvoice>,
     /// Locally created transaction in progress. (For which this node is the buyer).
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub open_transactions: ImHashMap<Uid, OpenTransaction>,
     /// Ongoing payments (For which this node is the buyer):
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub payments: ImHashMap<PaymentId, Payment>,
+    /// Secondary index over `payments`: The payments used to pay each invoice, oldest first.
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
+    pub payments_by_invoice: ImHashMap<InvoiceId, ImVec<PaymentId>>,
+    /// The invoice paid by each payment in `payments_by_invoice`.
+    /// Allows removing a payment from the index without searching it.
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
+    pub invoice_by_payment: ImHashMap<PaymentId, InvoiceId>,
+    /// Audit trail of every ongoing payment, bounded to `MAX_PAYMENT_TIMELINE_LEN` events.
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
+    pub payment_timelines: ImHashMap<PaymentId, ImVec<PaymentEvent>>,
+    /// Counter used as the tick of the next recorded payment event.
+    pub next_payment_event_tick: u64,
+    /// Details of ongoing payments, used to create a `payment_history` entry once they complete.
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
+    pub payment_details: ImHashMap<PaymentId, PaymentDetails>,
+    /// Append only log of completed payments, oldest first.
+    pub payment_history: ImVec<PaymentHistoryEntry>,
+}
+
+/// A FunderState, ready to be handed off to another process (For example, during an upgrade).
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct FunderStateSnapshot<B: Clone> {
+    pub version: u32,
+    pub funder_state: FunderState<B>,
+}
+
+#[derive(Debug)]
+pub enum FunderSnapshotError {
+    /// The snapshot was created by an incompatible version:
+    VersionMismatch(u32),
+}
+
+/// Details of an ongoing payment, that are not kept in all the states of `Payment`.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct PaymentDetails {
+    pub invoice_id: InvoiceId,
+    pub total_dest_payment: u128,
+    /// Amount of transactions that were added to the payment
+    pub num_transactions: u64,
 }
 
 /// A state of a Payment where new transactions may still be added.
@@ -36,6 +98,9 @@ pub struct NewTransactions {
     pub invoice_id: InvoiceId,
     pub total_dest_payment: u128,
     pub dest_public_key: PublicKey,
+    /// The route used by every open transaction of this payment.
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
+    pub routes: ImHashMap<Uid, FriendsRoute>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
@@ -59,6 +124,8 @@ pub struct IncomingTransaction {
     /// The lock we used on our ResponseSendFundsOp message.  
     /// We have to keep it, otherwise we will not be able to send a valid CollectSendFundsOp later.
     pub dest_plain_lock: PlainLock,
+    /// The amount of credits this transaction pays to us.
+    pub dest_payment: u128,
 }
 
 /// A local invoice in progress
@@ -67,15 +134,42 @@ pub struct OpenInvoice {
     /// Total payment required to fulfill this invoice:
     pub total_dest_payment: u128,
     /// Multiple transactions are possible for a single invoice in case of a multi-route payment.
+    #[serde(with = "proto::serde_utils::map_as_pairs")]
     pub incoming_transactions: ImHashMap<HashedLock, IncomingTransaction>,
+    /// The invoice is removed once the funder's tick counter reaches this value.
+    /// The tick counter starts from zero whenever the funder is started, so an invoice might
+    /// outlive its validity period across restarts, but it never expires early.
+    pub expires_at_tick: u64,
 }
 
 impl OpenInvoice {
-    pub fn new(total_dest_payment: u128) -> Self {
+    pub fn new(total_dest_payment: u128, expires_at_tick: u64) -> Self {
         OpenInvoice {
             total_dest_payment,
             incoming_transactions: ImHashMap::new(),
+            expires_at_tick,
+        }
+    }
+
+    pub fn is_expired(&self, current_tick: u64) -> bool {
+        current_tick >= self.expires_at_tick
+    }
+
+    /// Total amount of credits collected by all the incoming transactions (We have responded to)
+    pub fn collected(&self) -> u128 {
+        self.incoming_transactions
+            .values()
+            .fold(0u128, |acc, incoming_transaction| {
+                acc.saturating_add(incoming_transaction.dest_payment)
+            })
+    }
+
+    /// Fraction of the invoice that was already paid, between 0.0 and 1.0.
+    pub fn payment_progress(&self) -> f64 {
+        if self.total_dest_payment == 0 {
+            return 0.0;
         }
+        self.collected() as f64 / self.total_dest_payment as f64
     }
 }
 
@@ -97,14 +191,17 @@ pub enum FunderMutation<B: Clone> {
     RemoveRelay(PublicKey),
     AddFriend(AddFriend<B>),
     RemoveFriend(PublicKey),
-    AddInvoice((InvoiceId, u128)), // (InvoiceId, total_dest_payment)
-    AddIncomingTransaction((InvoiceId, Uid, PlainLock)), // (invoice_id, request_id, dest_plain_lock)
+    AddInvoice((InvoiceId, u128, u64)), // (InvoiceId, total_dest_payment, expires_at_tick)
+    AddIncomingTransaction((InvoiceId, Uid, PlainLock, u128)), // (invoice_id, request_id, dest_plain_lock, dest_payment)
+    RemoveIncomingTransaction((InvoiceId, Uid)),               // (invoice_id, request_id)
     RemoveInvoice(InvoiceId),
     AddTransaction((Uid, PaymentId, PlainLock)), // (request_id, payment_id,src_plain_lock)
     SetTransactionResponse(ResponseSendFundsOp), // (request_id, response_send_funds)
     RemoveTransaction(Uid),                      // request_id
     UpdatePayment((PaymentId, Payment)),
     RemovePayment(PaymentId),
+    AddPaymentEvent((PaymentId, PaymentEventKind)),
+    PrunePaymentHistory(u64), // Remove history entries that completed before this tick
 }
 
 impl<B> FunderState<B>
@@ -122,9 +219,131 @@ where
             open_invoices: ImHashMap::new(),
             open_transactions: ImHashMap::new(),
             payments: ImHashMap::new(),
+            payments_by_invoice: ImHashMap::new(),
+            invoice_by_payment: ImHashMap::new(),
+            payment_timelines: ImHashMap::new(),
+            next_payment_event_tick: 0,
+            payment_details: ImHashMap::new(),
+            payment_history: ImVec::new(),
+        }
+    }
+
+    /// Wrap this state in a version tagged snapshot, ready to be sent to another process.
+    pub fn into_snapshot(self) -> FunderStateSnapshot<B> {
+        FunderStateSnapshot {
+            version: FUNDER_STATE_SNAPSHOT_VERSION,
+            funder_state: self,
         }
     }
 
+    /// Restore a state from a snapshot.
+    /// Fails if the snapshot was created by an incompatible version.
+    pub fn from_snapshot(snapshot: FunderStateSnapshot<B>) -> Result<Self, FunderSnapshotError> {
+        if snapshot.version != FUNDER_STATE_SNAPSHOT_VERSION {
+            return Err(FunderSnapshotError::VersionMismatch(snapshot.version));
+        }
+        Ok(snapshot.funder_state)
+    }
+
+    /// Has the payment reached a terminal state?
+    /// A complete payment can not accept new transactions, and closing it again has no effect.
+    pub fn is_payment_complete(&self, payment_id: &PaymentId) -> bool {
+        match self.payments.get(payment_id) {
+            Some(Payment::Success(_)) | Some(Payment::Canceled(_)) => true,
+            Some(Payment::AfterSuccessAck(_)) => true,
+            Some(Payment::NewTransactions(_)) | Some(Payment::InProgress(_)) | None => false,
+        }
+    }
+
+    /// Find the payment used to pay a certain invoice.
+    /// If the invoice was paid by more than one payment, the latest one is returned.
+    pub fn find_payment_by_invoice(&self, invoice_id: &InvoiceId) -> Option<&PaymentId> {
+        self.payments_by_invoice
+            .get(invoice_id)
+            .and_then(|payment_ids| payment_ids.last())
+    }
+
+    /// Recorded events of a payment, oldest first.
+    /// Returns an empty timeline if the payment does not exist.
+    pub fn payment_timeline(&self, payment_id: &PaymentId) -> Vec<PaymentEvent> {
+        self.payment_timelines
+            .get(payment_id)
+            .map(|timeline| timeline.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Completed payments that completed strictly after `after_tick`, oldest first.
+    /// At most `limit` entries are returned.
+    pub fn query_payment_history(&self, after_tick: u64, limit: usize) -> Vec<PaymentHistoryEntry> {
+        self.payment_history
+            .iter()
+            .filter(|entry| entry.completed_at_tick > after_tick)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// The tick before which payment history entries should be pruned, if there are any entries
+    /// that completed more than `history_retention_events` payment events ago.
+    pub fn payment_history_prune_tick(&self, history_retention_events: u64) -> Option<u64> {
+        let min_tick = self
+            .next_payment_event_tick
+            .saturating_sub(history_retention_events);
+        let oldest_entry = self.payment_history.front()?;
+        if oldest_entry.completed_at_tick < min_tick {
+            Some(min_tick)
+        } else {
+            None
+        }
+    }
+
+    /// Can another friend be added without going over `max_friends` friends?
+    pub fn can_accept_more_friends(&self, max_friends: usize) -> bool {
+        self.friends.len() < max_friends
+    }
+
+    /// Can another relay be added without going over `max_node_relays` relays?
+    pub fn can_accept_more_relays(&self, max_node_relays: usize) -> bool {
+        self.relays.len() < max_node_relays
+    }
+
+    /// Sum the pending (frozen) debts over all consistent friend channels.
+    /// Returns (local_frozen, remote_frozen).
+    pub fn total_frozen_credits(&self) -> (u128, u128) {
+        let mut local_frozen: u128 = 0;
+        let mut remote_frozen: u128 = 0;
+        for friend in self.friends.values() {
+            if let ChannelStatus::Consistent(token_channel) = &friend.channel_status {
+                let balance = &token_channel.get_mutual_credit().state().balance;
+                local_frozen = local_frozen.saturating_add(balance.local_pending_debt);
+                remote_frozen = remote_frozen.saturating_add(balance.remote_pending_debt);
+            }
+        }
+        (local_frozen, remote_frozen)
+    }
+
+    /// Estimate the amount of seconds it will take for a payment to go through `route`.
+    /// We only know the round trip times of our direct friends, so the average round trip time
+    /// of the first hop friend is used for every hop along the route.
+    /// `default_hop_latency_ticks` is used if no round trip time was measured for the first hop.
+    /// Currently the estimate does not depend on the amount.
+    pub fn estimate_network_throughput(
+        &self,
+        route: &FriendsRoute,
+        _amount: u128,
+        ticks_per_second: f64,
+        default_hop_latency_ticks: usize,
+    ) -> f64 {
+        let avg_round_trip_per_hop_ticks = route
+            .public_keys
+            .get(1)
+            .and_then(|first_hop_public_key| self.friends.get(first_hop_public_key))
+            .and_then(|friend| friend.opt_avg_hop_latency_ticks)
+            .unwrap_or_else(|| usize_to_u64(default_hop_latency_ticks).unwrap());
+
+        route.len() as f64 * avg_round_trip_per_hop_ticks as f64 / ticks_per_second
+    }
+
     // TODO: Use MutableState trait instead:
     pub fn mutate(&mut self, funder_mutation: &FunderMutation<B>) {
         match funder_mutation {
@@ -138,7 +357,6 @@ where
                     cur_named_relay_address.public_key != named_relay_address.public_key
                 });
                 self.relays.push_back(named_relay_address.clone());
-                // TODO: Should check here if we have more than a constant amount of relays
             }
             FunderMutation::RemoveRelay(public_key) => {
                 self.relays.retain(|cur_named_relay_address| {
@@ -151,6 +369,7 @@ where
                     &add_friend.friend_public_key,
                     add_friend.relays.clone(),
                     add_friend.name.clone(),
+                    add_friend.note.clone(),
                     add_friend.balance,
                 );
                 // Insert friend, but also make sure that we didn't override an existing friend
@@ -163,20 +382,41 @@ where
             FunderMutation::RemoveFriend(public_key) => {
                 let _ = self.friends.remove(&public_key);
             }
-            FunderMutation::AddInvoice((invoice_id, total_dest_payment)) => {
-                self.open_invoices
-                    .insert(invoice_id.clone(), OpenInvoice::new(*total_dest_payment));
+            FunderMutation::AddInvoice((invoice_id, total_dest_payment, expires_at_tick)) => {
+                self.open_invoices.insert(
+                    invoice_id.clone(),
+                    OpenInvoice::new(*total_dest_payment, *expires_at_tick),
+                );
             }
-            FunderMutation::AddIncomingTransaction((invoice_id, request_id, dest_plain_lock)) => {
+            FunderMutation::AddIncomingTransaction((
+                invoice_id,
+                request_id,
+                dest_plain_lock,
+                dest_payment,
+            )) => {
                 let open_invoice = self.open_invoices.get_mut(invoice_id).unwrap();
                 let incoming_transaction = IncomingTransaction {
                     request_id: request_id.clone(),
                     dest_plain_lock: dest_plain_lock.clone(),
+                    dest_payment: *dest_payment,
                 };
                 open_invoice
                     .incoming_transactions
                     .insert(dest_plain_lock.hash().clone(), incoming_transaction);
             }
+            FunderMutation::RemoveIncomingTransaction((invoice_id, request_id)) => {
+                let open_invoice = self.open_invoices.get_mut(invoice_id).unwrap();
+                let opt_hashed_lock = open_invoice
+                    .incoming_transactions
+                    .iter()
+                    .find(|(_, incoming_transaction)| {
+                        incoming_transaction.request_id == *request_id
+                    })
+                    .map(|(hashed_lock, _)| hashed_lock.clone());
+                if let Some(hashed_lock) = opt_hashed_lock {
+                    let _ = open_invoice.incoming_transactions.remove(&hashed_lock);
+                }
+            }
             FunderMutation::RemoveInvoice(invoice_id) => {
                 let _ = self.open_invoices.remove(invoice_id);
             }
@@ -203,11 +443,837 @@ where
                 let _ = self.open_transactions.remove(request_id);
             }
             FunderMutation::UpdatePayment((payment_id, payment)) => {
+                // Only some of the payment states contain the invoice id:
+                let opt_invoice_id = match payment {
+                    Payment::NewTransactions(new_transactions) => {
+                        Some(&new_transactions.invoice_id)
+                    }
+                    Payment::Success((_num_transactions, receipt, _ack_uid)) => {
+                        Some(&receipt.invoice_id)
+                    }
+                    Payment::InProgress(_) | Payment::Canceled(_) | Payment::AfterSuccessAck(_) => {
+                        None
+                    }
+                };
+                // The invoice of a payment never changes, so a payment is indexed only once:
+                if let Some(invoice_id) = opt_invoice_id {
+                    if !self.invoice_by_payment.contains_key(payment_id) {
+                        self.payments_by_invoice
+                            .entry(invoice_id.clone())
+                            .or_insert_with(ImVec::new)
+                            .push_back(payment_id.clone());
+                        let _ = self
+                            .invoice_by_payment
+                            .insert(payment_id.clone(), invoice_id.clone());
+                    }
+                }
+                if let Payment::NewTransactions(new_transactions) = payment {
+                    if !self.payment_details.contains_key(payment_id) {
+                        let payment_details = PaymentDetails {
+                            invoice_id: new_transactions.invoice_id.clone(),
+                            total_dest_payment: new_transactions.total_dest_payment,
+                            num_transactions: 0,
+                        };
+                        let _ = self
+                            .payment_details
+                            .insert(payment_id.clone(), payment_details);
+                    }
+                }
                 let _ = self.payments.insert(payment_id.clone(), payment.clone());
             }
             FunderMutation::RemovePayment(payment_id) => {
+                // Other payments of the same invoice remain indexed:
+                if let Some(invoice_id) = self.invoice_by_payment.remove(payment_id) {
+                    let is_empty = match self.payments_by_invoice.get_mut(&invoice_id) {
+                        Some(payment_ids) => {
+                            payment_ids
+                                .retain(|indexed_payment_id| indexed_payment_id != payment_id);
+                            payment_ids.is_empty()
+                        }
+                        None => false,
+                    };
+                    if is_empty {
+                        let _ = self.payments_by_invoice.remove(&invoice_id);
+                    }
+                }
                 let _ = self.payments.remove(payment_id);
+                let _ = self.payment_timelines.remove(payment_id);
+                let _ = self.payment_details.remove(payment_id);
             }
+            FunderMutation::AddPaymentEvent((payment_id, event)) => {
+                let payment_event = PaymentEvent {
+                    tick: self.next_payment_event_tick,
+                    event: event.clone(),
+                };
+                self.next_payment_event_tick = self.next_payment_event_tick.wrapping_add(1);
+
+                let opt_outcome = match event {
+                    PaymentEventKind::TransactionAdded(_) => {
+                        if let Some(payment_details) = self.payment_details.get_mut(payment_id) {
+                            payment_details.num_transactions =
+                                payment_details.num_transactions.saturating_add(1);
+                        }
+                        None
+                    }
+                    PaymentEventKind::Succeeded => Some(PaymentOutcome::Succeeded),
+                    PaymentEventKind::Canceled => Some(PaymentOutcome::Canceled),
+                    PaymentEventKind::Created
+                    | PaymentEventKind::TransactionFailed(_)
+                    | PaymentEventKind::ClosedRequested => None,
+                };
+                // A payment completes at most once, because its details are removed here:
+                if let Some(outcome) = opt_outcome {
+                    if let Some(payment_details) = self.payment_details.remove(payment_id) {
+                        self.payment_history.push_back(PaymentHistoryEntry {
+                            payment_id: payment_id.clone(),
+                            invoice_id: payment_details.invoice_id,
+                            total_amount: payment_details.total_dest_payment,
+                            num_transactions: payment_details.num_transactions,
+                            outcome,
+                            completed_at_tick: payment_event.tick,
+                        });
+                    }
+                }
+
+                let mut timeline = self
+                    .payment_timelines
+                    .get(payment_id)
+                    .cloned()
+                    .unwrap_or_else(ImVec::new);
+                timeline.push_back(payment_event);
+                if timeline.len() > MAX_PAYMENT_TIMELINE_LEN {
+                    let _ = timeline.pop_front();
+                }
+                let _ = self.payment_timelines.insert(payment_id.clone(), timeline);
+            }
+            FunderMutation::PrunePaymentHistory(min_tick) => {
+                // Entries are ordered by completion tick:
+                while let Some(entry) = self.payment_history.front() {
+                    if entry.completed_at_tick >= *min_tick {
+                        break;
+                    }
+                    let _ = self.payment_history.pop_front();
+                }
+            }
+        }
+    }
+}
+
+impl<B> FunderState<B>
+where
+    B: Clone + Serialize + DeserializeOwned,
+{
+    /// Human readable (JSON) representation of the state, for backups and inspection.
+    /// Public keys are encoded using bech32.
+    pub fn to_json(&self) -> serde_json::Value {
+        // All the maps of the state have string keys, and none of the `Serialize`
+        // implementations can fail. Large credit values (Beyond u64) are kept exactly, because
+        // serde_json is used with the `arbitrary_precision` feature.
+        serde_json::to_value(self).unwrap()
+    }
+
+    /// Load a state from its JSON representation (As created by `to_json()`).
+    pub fn from_json(v: serde_json::Value) -> Result<Self, JsonError> {
+        serde_json::from_value(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::hash_lock::PLAIN_LOCK_LEN;
+    use crypto::identity::{Signature, PUBLIC_KEY_BECH32_HRP, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::INVOICE_ID_LEN;
+    use crypto::payment_id::PAYMENT_ID_LEN;
+    use crypto::uid::UID_LEN;
+
+    use crate::mutual_credit::types::McMutation;
+    use crate::tests::utils::{
+        assert_same_entries, dummy_named_relay_address, dummy_relay_address,
+    };
+    use crate::token_channel::TcMutation;
+
+    fn dummy_receipt() -> Receipt {
+        Receipt {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[0x03; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[0x04; PLAIN_LOCK_LEN]),
+            dest_payment: 10,
+            total_dest_payment: 10,
+            signature: Signature::from(&[0x05; SIGNATURE_LEN]),
+        }
+    }
+
+    /// Create a state with a single payment in the given state
+    fn state_with_payment(payment_id: &PaymentId, payment: Payment) -> FunderState<u32> {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id.clone(),
+            payment,
+        )));
+        state
+    }
+
+    #[test]
+    fn test_is_payment_complete_not_found() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        assert!(!state.is_payment_complete(&payment_id));
+    }
+
+    #[test]
+    fn test_is_payment_complete_in_progress() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let new_transactions = NewTransactions {
+            num_transactions: 1,
+            invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+            total_dest_payment: 10,
+            dest_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            routes: ImHashMap::new(),
+        };
+        let state = state_with_payment(&payment_id, Payment::NewTransactions(new_transactions));
+        assert!(!state.is_payment_complete(&payment_id));
+
+        let state = state_with_payment(&payment_id, Payment::InProgress(1));
+        assert!(!state.is_payment_complete(&payment_id));
+    }
+
+    #[test]
+    fn test_is_payment_complete_success() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let ack_uid = Uid::from(&[0x11; UID_LEN]);
+        let state =
+            state_with_payment(&payment_id, Payment::Success((1, dummy_receipt(), ack_uid)));
+        assert!(state.is_payment_complete(&payment_id));
+    }
+
+    #[test]
+    fn test_is_payment_complete_canceled() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let ack_uid = Uid::from(&[0x11; UID_LEN]);
+        let state = state_with_payment(&payment_id, Payment::Canceled(ack_uid));
+        assert!(state.is_payment_complete(&payment_id));
+    }
+
+    #[test]
+    fn test_is_payment_complete_after_success_ack() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let state = state_with_payment(&payment_id, Payment::AfterSuccessAck(2));
+        assert!(state.is_payment_complete(&payment_id));
+    }
+
+    #[test]
+    fn test_find_payment_by_invoice() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let invoice_id = InvoiceId::from(&[0x02; INVOICE_ID_LEN]);
+        let other_invoice_id = InvoiceId::from(&[0x03; INVOICE_ID_LEN]);
+        let new_transactions = NewTransactions {
+            num_transactions: 1,
+            invoice_id: invoice_id.clone(),
+            total_dest_payment: 10,
+            dest_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            routes: ImHashMap::new(),
+        };
+        let mut state = state_with_payment(&payment_id, Payment::NewTransactions(new_transactions));
+        assert_eq!(
+            state.find_payment_by_invoice(&invoice_id),
+            Some(&payment_id)
+        );
+        assert_eq!(state.find_payment_by_invoice(&other_invoice_id), None);
+
+        // The invoice id is not part of the InProgress state, but the payment can still be found:
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id.clone(),
+            Payment::InProgress(1),
+        )));
+        assert_eq!(
+            state.find_payment_by_invoice(&invoice_id),
+            Some(&payment_id)
+        );
+
+        state.mutate(&FunderMutation::RemovePayment(payment_id.clone()));
+        assert_eq!(state.find_payment_by_invoice(&invoice_id), None);
+        assert!(state.payments_by_invoice.is_empty());
+        assert!(state.invoice_by_payment.is_empty());
+    }
+
+    #[test]
+    fn test_find_payment_by_invoice_two_payments() {
+        let payment_id_a = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let payment_id_b = PaymentId::from(&[0x11; PAYMENT_ID_LEN]);
+        let invoice_id = InvoiceId::from(&[0x02; INVOICE_ID_LEN]);
+        let new_transactions = NewTransactions {
+            num_transactions: 1,
+            invoice_id: invoice_id.clone(),
+            total_dest_payment: 10,
+            dest_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            routes: ImHashMap::new(),
+        };
+        let mut state = state_with_payment(
+            &payment_id_a,
+            Payment::NewTransactions(new_transactions.clone()),
+        );
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id_b.clone(),
+            Payment::NewTransactions(new_transactions),
+        )));
+        // The latest payment is returned:
+        assert_eq!(
+            state.find_payment_by_invoice(&invoice_id),
+            Some(&payment_id_b)
+        );
+
+        // Removing the latest payment keeps the older one indexed:
+        state.mutate(&FunderMutation::RemovePayment(payment_id_b.clone()));
+        assert_eq!(
+            state.find_payment_by_invoice(&invoice_id),
+            Some(&payment_id_a)
+        );
+
+        state.mutate(&FunderMutation::RemovePayment(payment_id_a.clone()));
+        assert_eq!(state.find_payment_by_invoice(&invoice_id), None);
+    }
+
+    #[test]
+    fn test_find_payment_by_invoice_success() {
+        // dummy_receipt() pays the invoice 0x02:
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let ack_uid = Uid::from(&[0x11; UID_LEN]);
+        let state =
+            state_with_payment(&payment_id, Payment::Success((1, dummy_receipt(), ack_uid)));
+        let invoice_id = InvoiceId::from(&[0x02; INVOICE_ID_LEN]);
+        assert_eq!(
+            state.find_payment_by_invoice(&invoice_id),
+            Some(&payment_id)
+        );
+    }
+
+    /// Add a friend to the state and set its pending debts
+    fn add_friend_with_pending_debts(
+        state: &mut FunderState<u32>,
+        friend_public_key: &PublicKey,
+        local_pending_debt: u128,
+        remote_pending_debt: u128,
+    ) {
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![dummy_relay_address(0)],
+            name: "friend".into(),
+            note: String::new(),
+            balance: 0,
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        for mc_mutation in vec![
+            McMutation::SetLocalPendingDebt(local_pending_debt),
+            McMutation::SetRemotePendingDebt(remote_pending_debt),
+        ] {
+            let friend_mutation = FriendMutation::TcMutation(TcMutation::McMutation(mc_mutation));
+            state.mutate(&FunderMutation::FriendMutation((
+                friend_public_key.clone(),
+                friend_mutation,
+            )));
+        }
+    }
+
+    #[test]
+    fn test_total_frozen_credits_empty() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        assert_eq!(state.total_frozen_credits(), (0, 0));
+    }
+
+    #[test]
+    fn test_total_frozen_credits_three_friends() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let pk_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+
+        add_friend_with_pending_debts(&mut state, &pk_b, 10, 1);
+        add_friend_with_pending_debts(&mut state, &pk_c, 20, 2);
+        add_friend_with_pending_debts(&mut state, &pk_d, 30, 3);
+
+        assert_eq!(state.total_frozen_credits(), (60, 6));
+
+        // Removing a friend removes its frozen credits from the total:
+        state.mutate(&FunderMutation::RemoveFriend(pk_c.clone()));
+        assert_eq!(state.total_frozen_credits(), (40, 4));
+    }
+
+    #[test]
+    fn test_can_accept_more_friends() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        add_friend_with_pending_debts(&mut state, &pk_b, 0, 0);
+        add_friend_with_pending_debts(&mut state, &pk_c, 0, 0);
+
+        // Below the limit:
+        assert!(state.can_accept_more_friends(3));
+        // At the limit:
+        assert!(!state.can_accept_more_friends(2));
+        // Above the limit (For example, if the limit was lowered after friends were added):
+        assert!(!state.can_accept_more_friends(1));
+        assert!(!state.can_accept_more_friends(0));
+
+        // Removing a friend frees a slot:
+        state.mutate(&FunderMutation::RemoveFriend(pk_c.clone()));
+        assert!(state.can_accept_more_friends(2));
+    }
+
+    #[test]
+    fn test_can_accept_more_relays() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(
+            local_pk,
+            vec![dummy_named_relay_address(0), dummy_named_relay_address(1)],
+        );
+
+        // Below the limit:
+        assert!(state.can_accept_more_relays(3));
+        // At the limit:
+        assert!(!state.can_accept_more_relays(2));
+        // Above the limit:
+        assert!(!state.can_accept_more_relays(1));
+
+        // Re-adding an existing relay does not change the amount of relays:
+        state.mutate(&FunderMutation::AddRelay(dummy_named_relay_address(1)));
+        assert_eq!(state.relays.len(), 2);
+        assert!(!state.can_accept_more_relays(2));
+
+        // Removing a relay frees a slot:
+        let relay_public_key = dummy_named_relay_address(1).public_key;
+        state.mutate(&FunderMutation::RemoveRelay(relay_public_key));
+        assert!(state.can_accept_more_relays(2));
+    }
+
+    fn dummy_route(len: u8) -> FriendsRoute {
+        FriendsRoute {
+            public_keys: (0..len)
+                .map(|i| PublicKey::from(&[0xaa + i; PUBLIC_KEY_LEN]))
+                .collect(),
+        }
+    }
+
+    fn assert_estimate(state: &FunderState<u32>, route_len: u8, expected_seconds: f64) {
+        // Default hop latency is 4 ticks, 2 ticks per second:
+        let estimated_seconds =
+            state.estimate_network_throughput(&dummy_route(route_len), 10, 2.0, 4);
+        assert!((estimated_seconds - expected_seconds).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_estimate_network_throughput_scales_with_route_length() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+
+        // The first hop is not a friend, default hop latency is used:
+        assert_estimate(&state, 3, 6.0);
+        assert_estimate(&state, 6, 12.0);
+
+        // First hop is a friend without any measurements. Default hop latency is used:
+        let pk_b = PublicKey::from(&[0xab; PUBLIC_KEY_LEN]);
+        add_friend_with_pending_debts(&mut state, &pk_b, 0, 0);
+        assert_estimate(&state, 3, 6.0);
+
+        // The first sample sets the moving average:
+        state.mutate(&FunderMutation::FriendMutation((
+            pk_b.clone(),
+            FriendMutation::AddHopLatencySample(4),
+        )));
+        assert_estimate(&state, 3, 6.0);
+
+        // Add a round trip sample: (4 * 7 + 20) / 8 = 6
+        state.mutate(&FunderMutation::FriendMutation((
+            pk_b.clone(),
+            FriendMutation::AddHopLatencySample(20),
+        )));
+        assert_estimate(&state, 3, 9.0);
+        assert_estimate(&state, 6, 18.0);
+    }
+
+    /// Create a state with one open invoice, paid by transactions of the given amounts
+    fn state_with_invoice(
+        invoice_id: &InvoiceId,
+        total_dest_payment: u128,
+        dest_payments: &[u128],
+    ) -> FunderState<u32> {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        state.mutate(&FunderMutation::AddInvoice((
+            invoice_id.clone(),
+            total_dest_payment,
+            u64::max_value(),
+        )));
+        for (i, dest_payment) in dest_payments.iter().enumerate() {
+            state.mutate(&FunderMutation::AddIncomingTransaction((
+                invoice_id.clone(),
+                Uid::from(&[i as u8; UID_LEN]),
+                PlainLock::from(&[i as u8; PLAIN_LOCK_LEN]),
+                *dest_payment,
+            )));
+        }
+        state
+    }
+
+    fn assert_progress(state: &FunderState<u32>, invoice_id: &InvoiceId, expected: f64) {
+        let open_invoice = state.open_invoices.get(invoice_id).unwrap();
+        assert!((open_invoice.payment_progress() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_payment_progress_empty() {
+        let invoice_id = InvoiceId::from(&[0x02; INVOICE_ID_LEN]);
+        let state = state_with_invoice(&invoice_id, 100, &[]);
+        assert_progress(&state, &invoice_id, 0.0);
+
+        let state = state_with_invoice(&invoice_id, 0, &[]);
+        assert_progress(&state, &invoice_id, 0.0);
+    }
+
+    #[test]
+    fn test_payment_progress_half() {
+        let invoice_id = InvoiceId::from(&[0x02; INVOICE_ID_LEN]);
+        let state = state_with_invoice(&invoice_id, 100, &[20, 30]);
+        assert_eq!(
+            state.open_invoices.get(&invoice_id).unwrap().collected(),
+            50
+        );
+        assert_progress(&state, &invoice_id, 0.5);
+    }
+
+    #[test]
+    fn test_payment_progress_full() {
+        let invoice_id = InvoiceId::from(&[0x02; INVOICE_ID_LEN]);
+        let state = state_with_invoice(&invoice_id, 100, &[20, 30, 50]);
+        assert_progress(&state, &invoice_id, 1.0);
+    }
+
+    #[test]
+    fn test_payment_timeline_second_attempt_succeeds() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let request_id_a = Uid::from(&[0x20; UID_LEN]);
+        let request_id_b = Uid::from(&[0x21; UID_LEN]);
+        let mut state = state_with_payment(&payment_id, Payment::InProgress(0));
+        assert!(state.payment_timeline(&payment_id).is_empty());
+
+        let event_kinds = vec![
+            PaymentEventKind::Created,
+            PaymentEventKind::TransactionAdded(request_id_a.clone()),
+            PaymentEventKind::TransactionFailed(request_id_a.clone()),
+            PaymentEventKind::TransactionAdded(request_id_b.clone()),
+            PaymentEventKind::ClosedRequested,
+            PaymentEventKind::Succeeded,
+        ];
+        for event_kind in &event_kinds {
+            state.mutate(&FunderMutation::AddPaymentEvent((
+                payment_id.clone(),
+                event_kind.clone(),
+            )));
+        }
+
+        let timeline = state.payment_timeline(&payment_id);
+        assert_eq!(
+            timeline
+                .iter()
+                .map(|payment_event| payment_event.event.clone())
+                .collect::<Vec<_>>(),
+            event_kinds
+        );
+        // Ticks are strictly increasing:
+        for pair in timeline.windows(2) {
+            assert!(pair[0].tick < pair[1].tick);
+        }
+    }
+
+    #[test]
+    fn test_payment_timeline_bounded() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let mut state = state_with_payment(&payment_id, Payment::InProgress(0));
+
+        for i in 0..MAX_PAYMENT_TIMELINE_LEN + 10 {
+            let request_id = Uid::from(&[i as u8; UID_LEN]);
+            state.mutate(&FunderMutation::AddPaymentEvent((
+                payment_id.clone(),
+                PaymentEventKind::TransactionAdded(request_id),
+            )));
+        }
+
+        let timeline = state.payment_timeline(&payment_id);
+        assert_eq!(timeline.len(), MAX_PAYMENT_TIMELINE_LEN);
+        // The oldest events were dropped:
+        assert_eq!(timeline[0].tick, 10);
+        assert_eq!(
+            timeline[0].event,
+            PaymentEventKind::TransactionAdded(Uid::from(&[10u8; UID_LEN]))
+        );
+    }
+
+    #[test]
+    fn test_payment_timeline_removed_with_payment() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let mut state = state_with_payment(&payment_id, Payment::InProgress(0));
+        state.mutate(&FunderMutation::AddPaymentEvent((
+            payment_id.clone(),
+            PaymentEventKind::Created,
+        )));
+        assert_eq!(state.payment_timeline(&payment_id).len(), 1);
+
+        state.mutate(&FunderMutation::RemovePayment(payment_id.clone()));
+        assert!(state.payment_timeline(&payment_id).is_empty());
+    }
+
+    /// Create a payment, add `num_transactions` transactions and complete it with `outcome`.
+    fn add_completed_payment(
+        state: &mut FunderState<u32>,
+        payment_id: &PaymentId,
+        num_transactions: u8,
+        outcome: PaymentEventKind,
+    ) {
+        let new_transactions = NewTransactions {
+            num_transactions: 0,
+            invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+            total_dest_payment: 10,
+            dest_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            routes: ImHashMap::new(),
+        };
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id.clone(),
+            Payment::NewTransactions(new_transactions),
+        )));
+        state.mutate(&FunderMutation::AddPaymentEvent((
+            payment_id.clone(),
+            PaymentEventKind::Created,
+        )));
+        for i in 0..num_transactions {
+            state.mutate(&FunderMutation::AddPaymentEvent((
+                payment_id.clone(),
+                PaymentEventKind::TransactionAdded(Uid::from(&[i; UID_LEN])),
+            )));
+        }
+        state.mutate(&FunderMutation::UpdatePayment((
+            payment_id.clone(),
+            Payment::InProgress(u64::from(num_transactions)),
+        )));
+        state.mutate(&FunderMutation::AddPaymentEvent((
+            payment_id.clone(),
+            outcome,
+        )));
+    }
+
+    #[test]
+    fn test_payment_history() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+
+        let payment_id_a = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let payment_id_b = PaymentId::from(&[0x11; PAYMENT_ID_LEN]);
+        add_completed_payment(&mut state, &payment_id_a, 2, PaymentEventKind::Succeeded);
+        add_completed_payment(&mut state, &payment_id_b, 1, PaymentEventKind::Canceled);
+
+        // Removing the payments does not affect the history:
+        state.mutate(&FunderMutation::RemovePayment(payment_id_a.clone()));
+        state.mutate(&FunderMutation::RemovePayment(payment_id_b.clone()));
+        assert!(state.payment_details.is_empty());
+
+        let history = state.query_payment_history(0, 10);
+        assert_eq!(
+            history,
+            vec![
+                PaymentHistoryEntry {
+                    payment_id: payment_id_a.clone(),
+                    invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+                    total_amount: 10,
+                    num_transactions: 2,
+                    outcome: PaymentOutcome::Succeeded,
+                    completed_at_tick: 3,
+                },
+                PaymentHistoryEntry {
+                    payment_id: payment_id_b.clone(),
+                    invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+                    total_amount: 10,
+                    num_transactions: 1,
+                    outcome: PaymentOutcome::Canceled,
+                    completed_at_tick: 6,
+                },
+            ]
+        );
+
+        assert_eq!(state.query_payment_history(0, 1), history[..1].to_vec());
+        assert_eq!(state.query_payment_history(3, 10), history[1..].to_vec());
+        assert!(state.query_payment_history(6, 10).is_empty());
+    }
+
+    #[test]
+    fn test_payment_history_completes_once() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        add_completed_payment(&mut state, &payment_id, 1, PaymentEventKind::Succeeded);
+        state.mutate(&FunderMutation::AddPaymentEvent((
+            payment_id.clone(),
+            PaymentEventKind::Succeeded,
+        )));
+        assert_eq!(state.payment_history.len(), 1);
+    }
+
+    #[test]
+    fn test_prune_payment_history() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        for i in 0..3u8 {
+            let payment_id = PaymentId::from(&[i; PAYMENT_ID_LEN]);
+            add_completed_payment(&mut state, &payment_id, 0, PaymentEventKind::Succeeded);
+        }
+        // Completion ticks are 1, 3, 5:
+        state.mutate(&FunderMutation::PrunePaymentHistory(3));
+        assert_eq!(
+            state
+                .payment_history
+                .iter()
+                .map(|entry| entry.completed_at_tick)
+                .collect::<Vec<_>>(),
+            vec![3, 5]
+        );
+    }
+
+    #[test]
+    fn test_payment_history_prune_tick() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_pk, vec![dummy_named_relay_address(0)]);
+        assert_eq!(state.payment_history_prune_tick(0), None);
+
+        for i in 0..3u8 {
+            let payment_id = PaymentId::from(&[i; PAYMENT_ID_LEN]);
+            add_completed_payment(&mut state, &payment_id, 0, PaymentEventKind::Succeeded);
+        }
+        // Completion ticks are 1, 3, 5. 6 payment events were recorded:
+        assert_eq!(state.payment_history_prune_tick(6), None);
+        assert_eq!(state.payment_history_prune_tick(5), None);
+        assert_eq!(state.payment_history_prune_tick(4), Some(2));
+        assert_eq!(state.payment_history_prune_tick(0), Some(6));
+    }
+
+    #[test]
+    fn test_funder_state_snapshot() {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let state = state_with_payment(&payment_id, Payment::InProgress(3));
+
+        let snapshot = state.clone().into_snapshot();
+        assert_eq!(snapshot.version, FUNDER_STATE_SNAPSHOT_VERSION);
+
+        let restored = FunderState::from_snapshot(snapshot.clone()).unwrap();
+        assert_eq!(restored.local_public_key, state.local_public_key);
+        assert_eq!(
+            restored.payments.get(&payment_id),
+            Some(&Payment::InProgress(3))
+        );
+
+        // Snapshots of other versions are rejected:
+        let mut snapshot = snapshot;
+        snapshot.version = FUNDER_STATE_SNAPSHOT_VERSION + 1;
+        match FunderState::from_snapshot(snapshot) {
+            Err(FunderSnapshotError::VersionMismatch(version)) => {
+                assert_eq!(version, FUNDER_STATE_SNAPSHOT_VERSION + 1)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_funder_state_json_round_trip() {
+        let mut state = state_with_transactions(&[Uid::from(&[0x1; UID_LEN])]);
+
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let pk_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+        add_friend_with_pending_debts(&mut state, &pk_b, 10, 1);
+        add_friend_with_pending_debts(&mut state, &pk_c, 20, 2);
+        // Larger than u64::max_value():
+        add_friend_with_pending_debts(&mut state, &pk_d, u128::max_value(), 3);
+
+        let invoice_id_a = InvoiceId::from(&[0x20; INVOICE_ID_LEN]);
+        let invoice_id_b = InvoiceId::from(&[0x21; INVOICE_ID_LEN]);
+        state.mutate(&FunderMutation::AddInvoice((invoice_id_a.clone(), 100, 50)));
+        state.mutate(&FunderMutation::AddInvoice((invoice_id_b.clone(), 200, 60)));
+        state.mutate(&FunderMutation::AddIncomingTransaction((
+            invoice_id_a.clone(),
+            Uid::from(&[0x2; UID_LEN]),
+            PlainLock::from(&[0x04; PLAIN_LOCK_LEN]),
+            40,
+        )));
+
+        let json = state.to_json();
+        // Public keys are encoded using bech32:
+        let json_str = json.to_string();
+        assert!(json_str.contains(&pk_b.to_bech32(PUBLIC_KEY_BECH32_HRP)));
+        assert!(json_str.contains(&state.local_public_key.to_bech32(PUBLIC_KEY_BECH32_HRP)));
+
+        let restored = FunderState::<u32>::from_json(json.clone()).unwrap();
+        // Nothing was lost on the way:
+        assert_eq!(restored.to_json(), json);
+
+        assert_eq!(restored.local_public_key, state.local_public_key);
+        assert_eq!(restored.relays, state.relays);
+        assert_same_entries(&restored.friends, &state.friends);
+        assert_same_entries(&restored.open_invoices, &state.open_invoices);
+        assert_same_entries(&restored.open_transactions, &state.open_transactions);
+        assert_eq!(restored.payments, state.payments);
+        assert_eq!(restored.payments_by_invoice, state.payments_by_invoice);
+        assert_eq!(restored.invoice_by_payment, state.invoice_by_payment);
+        assert_eq!(restored.payment_timelines, state.payment_timelines);
+
+        // Every friend kept its channel:
+        assert_eq!(restored.friends.len(), 3);
+        for (friend_public_key, local_pending_debt, remote_pending_debt) in vec![
+            (&pk_b, 10, 1),
+            (&pk_c, 20, 2),
+            (&pk_d, u128::max_value(), 3),
+        ] {
+            let friend = restored.friends.get(friend_public_key).unwrap();
+            let balance = match &friend.channel_status {
+                ChannelStatus::Consistent(token_channel) => {
+                    token_channel.get_mutual_credit().state().balance.clone()
+                }
+                ChannelStatus::Inconsistent(_) => unreachable!(),
+            };
+            assert_eq!(balance.local_pending_debt, local_pending_debt);
+            assert_eq!(balance.remote_pending_debt, remote_pending_debt);
+        }
+
+        // Both invoices are open, and the incoming transaction is kept:
+        assert_eq!(restored.open_invoices.len(), 2);
+        let open_invoice_a = restored.open_invoices.get(&invoice_id_a).unwrap();
+        assert_eq!(open_invoice_a.total_dest_payment, 100);
+        assert_eq!(open_invoice_a.incoming_transactions.len(), 1);
+        let open_invoice_b = restored.open_invoices.get(&invoice_id_b).unwrap();
+        assert_eq!(open_invoice_b.total_dest_payment, 200);
+        assert!(open_invoice_b.incoming_transactions.is_empty());
+
+        assert!(FunderState::<u32>::from_json(serde_json::json!({})).is_err());
+    }
+
+    /// Create a state with open transactions for all the given request ids
+    fn state_with_transactions(request_ids: &[Uid]) -> FunderState<u32> {
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let mut state = state_with_payment(&payment_id, Payment::InProgress(0));
+        for request_id in request_ids {
+            state.mutate(&FunderMutation::AddTransaction((
+                request_id.clone(),
+                payment_id.clone(),
+                PlainLock::from(&[0x03; PLAIN_LOCK_LEN]),
+            )));
         }
+        state
     }
 }
@@ -1,2 +1,3 @@
+mod state_props;
 mod tests;
 pub mod utils;
@@ -0,0 +1,423 @@
+//! Property based tests for `FunderState::mutate()`.
+//!
+//! Mutations are generated over small key spaces (A few public keys, invoice ids etc.), so that
+//! generated mutations often refer to existing entries. Mutations that are not valid for the
+//! current state (For example, a `FriendMutation` for a friend that does not exist) are skipped.
+
+use im::hashmap::HashMap as ImHashMap;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
+use crypto::hash::{HashResult, HASH_RESULT_LEN};
+use crypto::hash_lock::{PlainLock, PLAIN_LOCK_LEN};
+use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::payment_id::{PaymentId, PAYMENT_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
+use proto::funder::messages::{
+    AddFriend, FriendStatus, PaymentEventKind, Rate, Receipt, RequestsStatus, ResponseSendFundsOp,
+};
+
+use crate::friend::{FriendMutation, FriendState};
+use crate::state::{FunderMutation, FunderState, NewTransactions, Payment};
+use crate::tests::utils::assert_same_entries;
+
+/// Amount of distinct values generated for every kind of key
+const NUM_KEYS: u8 = 4;
+
+fn local_public_key() -> PublicKey {
+    // Outside of the range of generated public keys:
+    PublicKey::from(&[0xff; PUBLIC_KEY_LEN])
+}
+
+fn public_key() -> impl Strategy<Value = PublicKey> {
+    (0..NUM_KEYS).prop_map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+}
+
+fn invoice_id() -> impl Strategy<Value = InvoiceId> {
+    (0..NUM_KEYS).prop_map(|i| InvoiceId::from(&[i; INVOICE_ID_LEN]))
+}
+
+fn uid() -> impl Strategy<Value = Uid> {
+    (0..NUM_KEYS).prop_map(|i| Uid::from(&[i; UID_LEN]))
+}
+
+fn payment_id() -> impl Strategy<Value = PaymentId> {
+    (0..NUM_KEYS).prop_map(|i| PaymentId::from(&[i; PAYMENT_ID_LEN]))
+}
+
+fn plain_lock() -> impl Strategy<Value = PlainLock> {
+    (0..NUM_KEYS).prop_map(|i| PlainLock::from(&[i; PLAIN_LOCK_LEN]))
+}
+
+fn name() -> impl Strategy<Value = String> {
+    "[a-z ]{0,8}"
+}
+
+/// Any balance a token channel can be created with.
+/// (`i128::min_value()` can not be negated for the remote side)
+fn balance() -> impl Strategy<Value = i128> {
+    (i128::min_value() + 1)..=i128::max_value()
+}
+
+fn relay_address() -> impl Strategy<Value = RelayAddress<u32>> {
+    (public_key(), any::<u32>()).prop_map(|(public_key, address)| RelayAddress {
+        public_key,
+        address,
+    })
+}
+
+fn named_relay_address() -> impl Strategy<Value = NamedRelayAddress<u32>> {
+    (public_key(), any::<u32>(), name()).prop_map(|(public_key, address, name)| NamedRelayAddress {
+        public_key,
+        address,
+        name,
+    })
+}
+
+fn add_friend() -> impl Strategy<Value = AddFriend<u32>> {
+    (
+        public_key(),
+        vec(relay_address(), 0..3),
+        name(),
+        name(),
+        balance(),
+    )
+        .prop_map(
+            |(friend_public_key, relays, name, note, balance)| AddFriend {
+                friend_public_key,
+                relays,
+                name,
+                note,
+                balance,
+            },
+        )
+}
+
+fn receipt() -> impl Strategy<Value = Receipt> {
+    (invoice_id(), any::<u128>(), any::<u128>()).prop_map(
+        |(invoice_id, dest_payment, total_dest_payment)| Receipt {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            invoice_id,
+            src_plain_lock: PlainLock::from(&[0x03; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[0x04; PLAIN_LOCK_LEN]),
+            dest_payment,
+            total_dest_payment,
+            signature: Signature::from(&[0x05; SIGNATURE_LEN]),
+        },
+    )
+}
+
+fn payment() -> impl Strategy<Value = Payment> {
+    prop_oneof![
+        (any::<u64>(), invoice_id(), any::<u128>(), public_key()).prop_map(
+            |(num_transactions, invoice_id, total_dest_payment, dest_public_key)| {
+                Payment::NewTransactions(NewTransactions {
+                    num_transactions,
+                    invoice_id,
+                    total_dest_payment,
+                    dest_public_key,
+                    routes: ImHashMap::new(),
+                })
+            }
+        ),
+        any::<u64>().prop_map(Payment::InProgress),
+        (any::<u64>(), receipt(), uid()).prop_map(Payment::Success),
+        uid().prop_map(Payment::Canceled),
+        any::<u64>().prop_map(Payment::AfterSuccessAck),
+    ]
+}
+
+fn payment_event_kind() -> impl Strategy<Value = PaymentEventKind> {
+    prop_oneof![
+        Just(PaymentEventKind::Created),
+        uid().prop_map(PaymentEventKind::TransactionAdded),
+        uid().prop_map(PaymentEventKind::TransactionFailed),
+        Just(PaymentEventKind::ClosedRequested),
+        Just(PaymentEventKind::Succeeded),
+        Just(PaymentEventKind::Canceled),
+    ]
+}
+
+fn response_send_funds_op() -> impl Strategy<Value = ResponseSendFundsOp> {
+    (uid(), plain_lock(), any::<u8>()).prop_map(|(request_id, dest_plain_lock, nonce)| {
+        ResponseSendFundsOp {
+            request_id,
+            dest_hashed_lock: dest_plain_lock.hash(),
+            rand_nonce: RandValue::from(&[nonce; RAND_VALUE_LEN]),
+            signature: Signature::from(&[nonce; SIGNATURE_LEN]),
+        }
+    })
+}
+
+/// Only mutations of the friend's configuration and bookkeeping are generated.
+/// Token channel mutations are covered by the token channel tests.
+impl Arbitrary for FriendMutation<u32> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        let config_mutation = prop_oneof![
+            any::<u128>().prop_map(FriendMutation::SetWantedRemoteMaxDebt),
+            prop_oneof![Just(RequestsStatus::Open), Just(RequestsStatus::Closed)]
+                .prop_map(FriendMutation::SetWantedLocalRequestsStatus),
+            prop_oneof![Just(FriendStatus::Enabled), Just(FriendStatus::Disabled)]
+                .prop_map(FriendMutation::SetStatus),
+            vec(relay_address(), 0..3).prop_map(FriendMutation::SetRemoteRelays),
+            name().prop_map(FriendMutation::SetName),
+            name().prop_map(FriendMutation::SetNote),
+            (any::<u32>(), any::<u32>())
+                .prop_map(|(mul, add)| FriendMutation::SetRate(Rate { mul, add })),
+            any::<bool>().prop_map(FriendMutation::SetFrozen),
+        ];
+        let stats_mutation = any::<u64>().prop_map(FriendMutation::AddHopLatencySample);
+        prop_oneof![config_mutation, stats_mutation].boxed()
+    }
+}
+
+/// A new friend of `local_public_key()`, after a few friend mutations.
+impl Arbitrary for FriendState<u32> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        (add_friend(), vec(any::<FriendMutation<u32>>(), 0..4))
+            .prop_map(|(add_friend, friend_mutations)| {
+                let mut friend = FriendState::new(
+                    &local_public_key(),
+                    &add_friend.friend_public_key,
+                    add_friend.relays,
+                    add_friend.name,
+                    add_friend.note,
+                    add_friend.balance,
+                );
+                for friend_mutation in &friend_mutations {
+                    friend.mutate(friend_mutation);
+                }
+                friend
+            })
+            .boxed()
+    }
+}
+
+/// Any mutation, not necessarily valid for a given state (See `is_valid_mutation()`).
+impl Arbitrary for FunderMutation<u32> {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        let config_mutation = prop_oneof![
+            (public_key(), any::<FriendMutation<u32>>()).prop_map(FunderMutation::FriendMutation),
+            named_relay_address().prop_map(FunderMutation::AddRelay),
+            public_key().prop_map(FunderMutation::RemoveRelay),
+            add_friend().prop_map(FunderMutation::AddFriend),
+            public_key().prop_map(FunderMutation::RemoveFriend),
+        ];
+        let invoice_mutation = prop_oneof![
+            (invoice_id(), any::<u128>(), any::<u64>()).prop_map(FunderMutation::AddInvoice),
+            (invoice_id(), uid(), plain_lock(), any::<u128>())
+                .prop_map(FunderMutation::AddIncomingTransaction),
+            (invoice_id(), uid()).prop_map(FunderMutation::RemoveIncomingTransaction),
+            invoice_id().prop_map(FunderMutation::RemoveInvoice),
+        ];
+        let payment_mutation = prop_oneof![
+            (uid(), payment_id(), plain_lock()).prop_map(FunderMutation::AddTransaction),
+            response_send_funds_op().prop_map(FunderMutation::SetTransactionResponse),
+            uid().prop_map(FunderMutation::RemoveTransaction),
+            (payment_id(), payment()).prop_map(FunderMutation::UpdatePayment),
+            payment_id().prop_map(FunderMutation::RemovePayment),
+            (payment_id(), payment_event_kind()).prop_map(FunderMutation::AddPaymentEvent),
+            any::<u64>().prop_map(FunderMutation::PrunePaymentHistory),
+        ];
+        prop_oneof![config_mutation, invoice_mutation, payment_mutation].boxed()
+    }
+}
+
+/// A state with the given relays and friends
+fn initial_state(
+    relays: Vec<NamedRelayAddress<u32>>,
+    friends: Vec<FriendState<u32>>,
+) -> FunderState<u32> {
+    let mut state = FunderState::new(local_public_key(), relays);
+    for friend in friends {
+        let _ = state
+            .friends
+            .insert(friend.remote_public_key.clone(), friend);
+    }
+    state
+}
+
+/// Can `funder_mutation` be applied to `state`?
+/// (Mutations are only created by the funder when they are valid, `mutate()` may panic otherwise)
+fn is_valid_mutation(state: &FunderState<u32>, funder_mutation: &FunderMutation<u32>) -> bool {
+    match funder_mutation {
+        FunderMutation::FriendMutation((public_key, _)) => state.friends.contains_key(public_key),
+        FunderMutation::AddFriend(add_friend) => {
+            !state.friends.contains_key(&add_friend.friend_public_key)
+        }
+        FunderMutation::AddIncomingTransaction((invoice_id, _, _, _))
+        | FunderMutation::RemoveIncomingTransaction((invoice_id, _)) => {
+            state.open_invoices.contains_key(invoice_id)
+        }
+        FunderMutation::SetTransactionResponse(response_send_funds) => {
+            match state.open_transactions.get(&response_send_funds.request_id) {
+                Some(open_transaction) => open_transaction.opt_response.is_none(),
+                None => false,
+            }
+        }
+        FunderMutation::AddRelay(_)
+        | FunderMutation::RemoveRelay(_)
+        | FunderMutation::RemoveFriend(_)
+        | FunderMutation::AddInvoice(_)
+        | FunderMutation::RemoveInvoice(_)
+        | FunderMutation::AddTransaction(_)
+        | FunderMutation::RemoveTransaction(_)
+        | FunderMutation::UpdatePayment(_)
+        | FunderMutation::RemovePayment(_)
+        | FunderMutation::AddPaymentEvent(_)
+        | FunderMutation::PrunePaymentHistory(_) => true,
+    }
+}
+
+/// A mutation that undoes `funder_mutation` when applied after it to `state`, if one exists.
+fn opt_inverse_mutation(
+    state: &FunderState<u32>,
+    funder_mutation: &FunderMutation<u32>,
+) -> Option<FunderMutation<u32>> {
+    match funder_mutation {
+        FunderMutation::AddRelay(named_relay_address) => {
+            let public_key = &named_relay_address.public_key;
+            if state
+                .relays
+                .iter()
+                .any(|relay| &relay.public_key == public_key)
+            {
+                return None;
+            }
+            Some(FunderMutation::RemoveRelay(public_key.clone()))
+        }
+        FunderMutation::AddFriend(add_friend) => {
+            if state.friends.contains_key(&add_friend.friend_public_key) {
+                return None;
+            }
+            Some(FunderMutation::RemoveFriend(
+                add_friend.friend_public_key.clone(),
+            ))
+        }
+        FunderMutation::AddInvoice((invoice_id, _, _)) => {
+            if state.open_invoices.contains_key(invoice_id) {
+                return None;
+            }
+            Some(FunderMutation::RemoveInvoice(invoice_id.clone()))
+        }
+        FunderMutation::AddIncomingTransaction((invoice_id, request_id, dest_plain_lock, _)) => {
+            let open_invoice = state.open_invoices.get(invoice_id)?;
+            let is_used = open_invoice.incoming_transactions.iter().any(
+                |(hashed_lock, incoming_transaction)| {
+                    hashed_lock == &dest_plain_lock.hash()
+                        || &incoming_transaction.request_id == request_id
+                },
+            );
+            if is_used {
+                return None;
+            }
+            Some(FunderMutation::RemoveIncomingTransaction((
+                invoice_id.clone(),
+                request_id.clone(),
+            )))
+        }
+        FunderMutation::AddTransaction((request_id, _, _)) => {
+            if state.open_transactions.contains_key(request_id) {
+                return None;
+            }
+            Some(FunderMutation::RemoveTransaction(request_id.clone()))
+        }
+        FunderMutation::UpdatePayment((payment_id, _payment)) => {
+            // RemovePayment also removes everything that was recorded about the payment:
+            if state.payments.contains_key(payment_id)
+                || state.payment_timelines.contains_key(payment_id)
+                || state.payment_details.contains_key(payment_id)
+                || state.invoice_by_payment.contains_key(payment_id)
+            {
+                return None;
+            }
+            Some(FunderMutation::RemovePayment(payment_id.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Check that two states are structurally equal
+fn assert_same_state(state1: &FunderState<u32>, state2: &FunderState<u32>) {
+    assert_eq!(state1.local_public_key, state2.local_public_key);
+    assert_eq!(state1.relays, state2.relays);
+    assert_same_entries(&state1.friends, &state2.friends);
+    assert_same_entries(&state1.open_invoices, &state2.open_invoices);
+    assert_same_entries(&state1.open_transactions, &state2.open_transactions);
+    assert_eq!(state1.payments, state2.payments);
+    assert_eq!(state1.payments_by_invoice, state2.payments_by_invoice);
+    assert_eq!(state1.invoice_by_payment, state2.invoice_by_payment);
+    assert_eq!(state1.payment_timelines, state2.payment_timelines);
+    assert_eq!(
+        state1.next_payment_event_tick,
+        state2.next_payment_event_tick
+    );
+    assert_eq!(state1.payment_details, state2.payment_details);
+    assert_eq!(state1.payment_history, state2.payment_history);
+}
+
+/// Apply all the valid mutations from `funder_mutations` to `state`
+fn apply_valid_mutations(state: &mut FunderState<u32>, funder_mutations: &[FunderMutation<u32>]) {
+    for funder_mutation in funder_mutations {
+        if is_valid_mutation(state, funder_mutation) {
+            state.mutate(funder_mutation);
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn prop_mutations_serialization_round_trip(
+        relays in vec(named_relay_address(), 0..3),
+        friends in vec(any::<FriendState<u32>>(), 0..3),
+        funder_mutations in vec(any::<FunderMutation<u32>>(), 0..32),
+    ) {
+        let mut state = initial_state(relays, friends);
+        apply_valid_mutations(&mut state, &funder_mutations);
+
+        // Human readable format:
+        let restored = FunderState::<u32>::from_json(state.to_json()).unwrap();
+        assert_same_state(&state, &restored);
+
+        // Database format:
+        let data = bincode::serialize(&state).unwrap();
+        let restored: FunderState<u32> = bincode::deserialize(&data).unwrap();
+        assert_same_state(&state, &restored);
+    }
+
+    #[test]
+    fn prop_inverse_mutation(
+        relays in vec(named_relay_address(), 0..3),
+        friends in vec(any::<FriendState<u32>>(), 0..3),
+        funder_mutations in vec(any::<FunderMutation<u32>>(), 0..16),
+        funder_mutation in any::<FunderMutation<u32>>(),
+    ) {
+        let mut state = initial_state(relays, friends);
+        apply_valid_mutations(&mut state, &funder_mutations);
+
+        prop_assume!(is_valid_mutation(&state, &funder_mutation));
+        let inverse_mutation = match opt_inverse_mutation(&state, &funder_mutation) {
+            Some(inverse_mutation) => inverse_mutation,
+            None => return Ok(()),
+        };
+
+        let mut mutated_state = state.clone();
+        mutated_state.mutate(&funder_mutation);
+        mutated_state.mutate(&inverse_mutation);
+        assert_same_state(&state, &mutated_state);
+    }
+}
@@ -1,23 +1,41 @@
+use futures::channel::mpsc;
 use futures::executor::ThreadPool;
+use futures::stream;
 use futures::task::Spawn;
 
-use crypto::identity::PublicKey;
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
 use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
 use crypto::payment_id::{PaymentId, PAYMENT_ID_LEN};
+use crypto::test_utils::DummyRandom;
 use crypto::uid::{Uid, UID_LEN};
 
 use proto::funder::messages::{
-    AckClosePayment, AddInvoice, CreatePayment, CreateTransaction, FriendStatus, FriendsRoute,
-    FunderControl, MultiCommit, PaymentStatus, Rate, RequestResult, RequestsStatus,
-    ResetFriendChannel,
+    AckClosePayment, AddInvoice, CreateMultiRoutePayment, CreatePayment, CreateTransaction,
+    FriendStatus, FriendsRoute, FunderControl, MultiCommit, MultiRouteTransaction,
+    PaymentEventKind, PaymentOutcome, PaymentStatus, QueryPaymentHistory, Rate, RequestResult,
+    RequestsStatus, ResetFriendChannel,
 };
 use proto::report::messages::{ChannelStatusReport, FunderReport};
 
-use super::utils::{create_node_controls, dummy_named_relay_address, dummy_relay_address};
+use database::DatabaseClient;
+use identity::IdentityClient;
+use timer::TimerTick;
+
+use crate::funder::{inner_funder_loop, FunderError};
+use crate::state::FunderState;
+
+use super::utils::{
+    create_node_controls, dummy_named_relay_address, dummy_relay_address,
+    TEST_MAX_OPERATIONS_IN_BATCH,
+};
 
 async fn task_funder_basic(spawner: impl Spawn + Clone + Send + 'static) {
     let num_nodes = 2;
-    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
 
     let public_keys = node_controls
         .iter()
@@ -50,6 +68,7 @@ async fn task_funder_basic(spawner: impl Spawn + Clone + Send + 'static) {
     let add_invoice = AddInvoice {
         invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
         total_dest_payment: 4,
+        validity_ticks: 100,
     };
     await!(node_controls[1].send(FunderControl::AddInvoice(add_invoice)));
 
@@ -152,7 +171,11 @@ async fn task_funder_forward_payment(spawner: impl Spawn + Clone + Send + 'stati
      * 0 -- 1 -- 2
      */
     let num_nodes = 3;
-    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
 
     // Create topology:
     // ----------------
@@ -200,6 +223,7 @@ async fn task_funder_forward_payment(spawner: impl Spawn + Clone + Send + 'stati
     let add_invoice = AddInvoice {
         invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
         total_dest_payment: 15,
+        validity_ticks: 100,
     };
     await!(node_controls[2].send(FunderControl::AddInvoice(add_invoice)));
 
@@ -322,6 +346,217 @@ fn test_funder_forward_payment() {
     thread_pool.run(task_funder_forward_payment(thread_pool.clone()));
 }
 
+async fn task_funder_multi_route_payment(spawner: impl Spawn + Clone + Send + 'static) {
+    /*
+     * 0 -- 1 -- 2
+     *  \       /
+     *   -------
+     */
+    let num_nodes = 3;
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
+
+    // Create topology:
+    // ----------------
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    // Add friends:
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    let relays2 = vec![dummy_relay_address(2)];
+    await!(node_controls[0].add_friend(&public_keys[1], relays1, "node1", 8));
+    await!(node_controls[0].add_friend(&public_keys[2], relays2.clone(), "node2", 0));
+    await!(node_controls[1].add_friend(&public_keys[0], relays0.clone(), "node0", -8));
+    await!(node_controls[1].add_friend(&public_keys[2], relays2, "node2", 6));
+    await!(node_controls[2].add_friend(&public_keys[1], relays0.clone(), "node1", -6));
+    await!(node_controls[2].add_friend(&public_keys[0], relays0, "node0", 0));
+
+    // Enable friends:
+    await!(node_controls[0].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[0].set_friend_status(&public_keys[2], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[2], FriendStatus::Enabled));
+    await!(node_controls[2].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[2].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+
+    // Set rate:
+    // This is the amount of credits node 1 takes from node 0 for forwarding messages.
+    await!(node_controls[1].set_friend_rate(&public_keys[0], Rate { mul: 0, add: 5 }));
+
+    // Set remote max debt:
+    await!(node_controls[1].set_remote_max_debt(&public_keys[0], 100));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[2], 300));
+    await!(node_controls[2].set_remote_max_debt(&public_keys[1], 400));
+    await!(node_controls[2].set_remote_max_debt(&public_keys[0], 100));
+
+    // Open requests, allowing the routes: 0 --> 1 --> 2 and 0 --> 2
+    await!(node_controls[1].set_requests_status(&public_keys[0], RequestsStatus::Open));
+    await!(node_controls[2].set_requests_status(&public_keys[1], RequestsStatus::Open));
+    await!(node_controls[2].set_requests_status(&public_keys[0], RequestsStatus::Open));
+
+    // Wait until the routes are ready (Online + Consistent + open requests)
+    await!(node_controls[0].wait_until_ready(&public_keys[1]));
+    await!(node_controls[1].wait_until_ready(&public_keys[2]));
+    await!(node_controls[0].wait_until_ready(&public_keys[2]));
+
+    // Let node 2 open an invoice:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        validity_ticks: 100,
+    };
+    await!(node_controls[2].send(FunderControl::AddInvoice(add_invoice)));
+
+    // Create a payment 0 --> 2, split over two routes:
+    let create_multi_route_payment = CreateMultiRoutePayment {
+        payment_id: PaymentId::from(&[2u8; PAYMENT_ID_LEN]),
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        dest_public_key: node_controls[2].public_key.clone(),
+        transactions: vec![
+            MultiRouteTransaction {
+                request_id: Uid::from(&[5u8; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![
+                        public_keys[0].clone(),
+                        public_keys[1].clone(),
+                        public_keys[2].clone(),
+                    ],
+                },
+                dest_payment: 10,
+                fees: 5,
+            },
+            MultiRouteTransaction {
+                request_id: Uid::from(&[6u8; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![public_keys[0].clone(), public_keys[2].clone()],
+                },
+                dest_payment: 5,
+                fees: 0,
+            },
+        ],
+    };
+    await!(
+        node_controls[0].send(FunderControl::CreateMultiRoutePayment(
+            create_multi_route_payment
+        ))
+    );
+
+    let mut commits = Vec::new();
+    for _ in 0..2 {
+        let transaction_result = await!(node_controls[0].recv_until_transaction_result()).unwrap();
+        match transaction_result.result {
+            RequestResult::Success(commit) => commits.push(commit),
+            _ => unreachable!(),
+        };
+    }
+
+    // A multi route payment that reuses an in flight request id (and lists another request id
+    // twice) is rejected. Only the new request id gets a failure, exactly once:
+    let create_multi_route_payment = CreateMultiRoutePayment {
+        payment_id: PaymentId::from(&[3u8; PAYMENT_ID_LEN]),
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        dest_public_key: node_controls[2].public_key.clone(),
+        transactions: vec![
+            MultiRouteTransaction {
+                request_id: Uid::from(&[5u8; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![public_keys[0].clone(), public_keys[2].clone()],
+                },
+                dest_payment: 5,
+                fees: 0,
+            },
+            MultiRouteTransaction {
+                request_id: Uid::from(&[7u8; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![public_keys[0].clone(), public_keys[2].clone()],
+                },
+                dest_payment: 5,
+                fees: 0,
+            },
+            MultiRouteTransaction {
+                request_id: Uid::from(&[7u8; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![public_keys[0].clone(), public_keys[2].clone()],
+                },
+                dest_payment: 5,
+                fees: 0,
+            },
+        ],
+    };
+    await!(
+        node_controls[0].send(FunderControl::CreateMultiRoutePayment(
+            create_multi_route_payment
+        ))
+    );
+    let transaction_result = await!(node_controls[0].recv_until_transaction_result()).unwrap();
+    assert_eq!(transaction_result.request_id, Uid::from(&[7u8; UID_LEN]));
+    assert_eq!(transaction_result.result, RequestResult::Failure);
+
+    // 0: Create a single multi commit for both transactions:
+    let multi_commit = MultiCommit {
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        commits,
+    };
+
+    // MultiCommit: 0 ==> 2  (Out of band)
+
+    // 2: Apply MultiCommit:
+    await!(node_controls[2].send(FunderControl::CommitInvoice(multi_commit)));
+
+    // 0: Expect a receipt:
+    let (receipt, ack_uid) = loop {
+        await!(
+            node_controls[0].send(FunderControl::RequestClosePayment(PaymentId::from(
+                &[2u8; PAYMENT_ID_LEN]
+            )))
+        );
+        let response_close_payment =
+            await!(node_controls[0].recv_until_response_close_payment()).unwrap();
+        match response_close_payment.status {
+            PaymentStatus::Success((receipt, ack_uid)) => break (receipt, ack_uid),
+            _ => {}
+        }
+    };
+
+    // 0: Acknowledge response close:
+    let ack_close_payment = AckClosePayment {
+        payment_id: PaymentId::from(&[2u8; PAYMENT_ID_LEN]),
+        ack_uid,
+    };
+    await!(node_controls[0].send(FunderControl::AckClosePayment(ack_close_payment)));
+
+    assert_eq!(receipt.invoice_id, InvoiceId::from(&[1u8; INVOICE_ID_LEN]));
+    assert_eq!(receipt.total_dest_payment, 15);
+
+    // Make sure that node2 got the credits from both routes:
+    let pred = |report: &FunderReport<_>| {
+        let balance_with = |public_key: &PublicKey| {
+            let friend = report.friends.get(public_key)?;
+            match &friend.channel_status {
+                ChannelStatusReport::Consistent(tc_report) => Some(tc_report.balance.balance),
+                _ => None,
+            }
+        };
+        balance_with(&public_keys[1]) == Some(-6 + 10) && balance_with(&public_keys[0]) == Some(5)
+    };
+    await!(node_controls[2].recv_until(pred));
+}
+
+#[test]
+fn test_funder_multi_route_payment() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_multi_route_payment(thread_pool.clone()));
+}
+
 async fn task_funder_payment_failure(spawner: impl Spawn + Clone + Send + 'static) {
     /*
      * 0 -- 1 -- 2
@@ -329,7 +564,11 @@ async fn task_funder_payment_failure(spawner: impl Spawn + Clone + Send + 'stati
      * where 3 does not exist. We expect that node 2 will return a failure response.
      */
     let num_nodes = 4;
-    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
 
     // Create topology:
     // ----------------
@@ -449,13 +688,401 @@ fn test_funder_payment_failure() {
     thread_pool.run(task_funder_payment_failure(thread_pool.clone()));
 }
 
+async fn task_funder_freeze_friend(spawner: impl Spawn + Clone + Send + 'static) {
+    /*
+     * 0 -- 1 -- 2
+     * Node 1 freezes node 2. We expect that a payment along the route 0 -- 1 -- 2 fails.
+     */
+    let num_nodes = 3;
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
+
+    // Create topology:
+    // ----------------
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    // Add friends:
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    let relays2 = vec![dummy_relay_address(2)];
+    await!(node_controls[0].add_friend(&public_keys[1], relays1.clone(), "node1", 8));
+    await!(node_controls[1].add_friend(&public_keys[0], relays0, "node0", -8));
+    await!(node_controls[1].add_friend(&public_keys[2], relays2, "node2", 6));
+    await!(node_controls[2].add_friend(&public_keys[1], relays1, "node1", -6));
+
+    // Enable friends:
+    await!(node_controls[0].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[2], FriendStatus::Enabled));
+    await!(node_controls[2].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+
+    // Set remote max debt:
+    await!(node_controls[0].set_remote_max_debt(&public_keys[1], 200));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[0], 100));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[2], 300));
+    await!(node_controls[2].set_remote_max_debt(&public_keys[1], 400));
+
+    // Open requests, allowing this route: 0 --> 1 --> 2
+    await!(node_controls[1].set_requests_status(&public_keys[0], RequestsStatus::Open));
+    await!(node_controls[2].set_requests_status(&public_keys[1], RequestsStatus::Open));
+
+    // Wait until route is ready (Online + Consistent + open requests)
+    await!(node_controls[0].wait_until_ready(&public_keys[1]));
+    await!(node_controls[1].wait_until_ready(&public_keys[2]));
+
+    // Node 1 freezes node 2:
+    await!(node_controls[1].send(FunderControl::FreezeFriend(public_keys[2].clone())));
+    let friend_report = node_controls[1]
+        .report
+        .friends
+        .get(&public_keys[2])
+        .unwrap();
+    assert!(friend_report.is_frozen);
+
+    // Let node 2 open an invoice:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        validity_ticks: 100,
+    };
+    await!(node_controls[2].send(FunderControl::AddInvoice(add_invoice)));
+
+    // Create payment 0 --> 2
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[2u8; PAYMENT_ID_LEN]),
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        dest_public_key: node_controls[2].public_key.clone(),
+    };
+    await!(node_controls[0].send(FunderControl::CreatePayment(create_payment)));
+
+    // Create transaction 0 --> 2:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PAYMENT_ID_LEN]),
+        request_id: Uid::from(&[5u8; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                public_keys[0].clone(),
+                public_keys[1].clone(),
+                public_keys[2].clone(),
+            ],
+        },
+        dest_payment: 15,
+        fees: 5,
+    };
+    await!(node_controls[0].send(FunderControl::CreateTransaction(create_transaction)));
+    let transaction_result = await!(node_controls[0].recv_until_transaction_result()).unwrap();
+
+    // Node 1 does not forward the request to node 2, so we expect failure:
+    match transaction_result.result {
+        RequestResult::Failure => {}
+        _ => unreachable!(),
+    }
+
+    // Node 1 unfreezes node 2:
+    await!(node_controls[1].send(FunderControl::UnfreezeFriend(public_keys[2].clone())));
+    let friend_report = node_controls[1]
+        .report
+        .friends
+        .get(&public_keys[2])
+        .unwrap();
+    assert!(!friend_report.is_frozen);
+}
+
+#[test]
+fn test_funder_freeze_friend() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_freeze_friend(thread_pool.clone()));
+}
+
+async fn task_funder_insufficient_fee(spawner: impl Spawn + Clone + Send + 'static) {
+    /*
+     * 0 -- 1 -- 2
+     * Node 1 charges node 0 a fee of 5 credits. A request offering 4 credits of fees is canceled
+     * by node 1, and the channel between node 0 and node 1 stays consistent.
+     */
+    let num_nodes = 3;
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
+
+    // Create topology:
+    // ----------------
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    // Add friends:
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    let relays2 = vec![dummy_relay_address(2)];
+    await!(node_controls[0].add_friend(&public_keys[1], relays1.clone(), "node1", 8));
+    await!(node_controls[1].add_friend(&public_keys[0], relays0, "node0", -8));
+    await!(node_controls[1].add_friend(&public_keys[2], relays2, "node2", 6));
+    await!(node_controls[2].add_friend(&public_keys[1], relays1, "node1", -6));
+
+    // Enable friends:
+    await!(node_controls[0].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[2], FriendStatus::Enabled));
+    await!(node_controls[2].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+
+    // Set rate:
+    // This is the amount of credits node 1 takes from node 0 for forwarding messages.
+    await!(node_controls[1].set_friend_rate(&public_keys[0], Rate { mul: 0, add: 5 }));
+
+    // Set remote max debt:
+    await!(node_controls[0].set_remote_max_debt(&public_keys[1], 200));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[0], 100));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[2], 300));
+    await!(node_controls[2].set_remote_max_debt(&public_keys[1], 400));
+
+    // Open requests, allowing this route: 0 --> 1 --> 2
+    await!(node_controls[1].set_requests_status(&public_keys[0], RequestsStatus::Open));
+    await!(node_controls[2].set_requests_status(&public_keys[1], RequestsStatus::Open));
+
+    // Wait until route is ready (Online + Consistent + open requests)
+    await!(node_controls[0].wait_until_ready(&public_keys[1]));
+    await!(node_controls[1].wait_until_ready(&public_keys[2]));
+
+    // Let node 2 open an invoice:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        validity_ticks: 100,
+    };
+    await!(node_controls[2].send(FunderControl::AddInvoice(add_invoice)));
+
+    // Create payment 0 --> 2
+    let create_payment = CreatePayment {
+        payment_id: PaymentId::from(&[2u8; PAYMENT_ID_LEN]),
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 15,
+        dest_public_key: node_controls[2].public_key.clone(),
+    };
+    await!(node_controls[0].send(FunderControl::CreatePayment(create_payment)));
+
+    // Create transaction 0 --> 2, with fees lower than the rate of node 1:
+    let create_transaction = CreateTransaction {
+        payment_id: PaymentId::from(&[2u8; PAYMENT_ID_LEN]),
+        request_id: Uid::from(&[5u8; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                public_keys[0].clone(),
+                public_keys[1].clone(),
+                public_keys[2].clone(),
+            ],
+        },
+        dest_payment: 15,
+        fees: 4,
+    };
+    await!(node_controls[0].send(FunderControl::CreateTransaction(create_transaction)));
+    let transaction_result = await!(node_controls[0].recv_until_transaction_result()).unwrap();
+
+    // Node 1 does not forward the request to node 2, so we expect failure:
+    match transaction_result.result {
+        RequestResult::Failure => {}
+        _ => unreachable!(),
+    }
+
+    // The channel between node 0 and node 1 is still consistent, and the balance is unchanged:
+    let pred = |report: &FunderReport<_>| {
+        let friend = match report.friends.get(&public_keys[1]) {
+            None => return false,
+            Some(friend) => friend,
+        };
+        let tc_report = match &friend.channel_status {
+            ChannelStatusReport::Consistent(tc_report) => tc_report,
+            _ => return false,
+        };
+        tc_report.balance.balance == 8
+    };
+    await!(node_controls[0].recv_until(pred));
+}
+
+#[test]
+fn test_funder_insufficient_fee() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_insufficient_fee(thread_pool.clone()));
+}
+
+async fn task_funder_payment_timeline(spawner: impl Spawn + Clone + Send + 'static) {
+    /*
+     * 0 -- 1
+     * The first transaction of the payment fails, because node 1 has no matching invoice yet.
+     * The second transaction (After node 1 opens the invoice) succeeds.
+     */
+    let num_nodes = 2;
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    await!(node_controls[0].add_friend(&public_keys[1], relays1, "node1", 8));
+    await!(node_controls[1].add_friend(&public_keys[0], relays0, "node0", -8));
+
+    await!(node_controls[0].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+
+    await!(node_controls[0].set_remote_max_debt(&public_keys[1], 200));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[0], 100));
+
+    await!(node_controls[0].set_requests_status(&public_keys[1], RequestsStatus::Open));
+    await!(node_controls[1].set_requests_status(&public_keys[0], RequestsStatus::Open));
+
+    await!(node_controls[0].wait_until_ready(&public_keys[1]));
+    await!(node_controls[1].wait_until_ready(&public_keys[0]));
+
+    let payment_id = PaymentId::from(&[2u8; PAYMENT_ID_LEN]);
+    let request_id_a = Uid::from(&[5u8; UID_LEN]);
+    let request_id_b = Uid::from(&[6u8; UID_LEN]);
+
+    // Create payment 0 --> 1
+    let create_payment = CreatePayment {
+        payment_id: payment_id.clone(),
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 4,
+        dest_public_key: node_controls[1].public_key.clone(),
+    };
+    await!(node_controls[0].send(FunderControl::CreatePayment(create_payment)));
+
+    let create_transaction = CreateTransaction {
+        payment_id: payment_id.clone(),
+        request_id: request_id_a.clone(),
+        route: FriendsRoute {
+            public_keys: vec![public_keys[0].clone(), public_keys[1].clone()],
+        },
+        dest_payment: 4,
+        fees: 1,
+    };
+
+    // First attempt: Node 1 doesn't have the invoice yet, so we expect a failure:
+    await!(node_controls[0].send(FunderControl::CreateTransaction(create_transaction.clone())));
+    let transaction_result = await!(node_controls[0].recv_until_transaction_result()).unwrap();
+    match transaction_result.result {
+        RequestResult::Failure => {}
+        _ => unreachable!(),
+    }
+
+    // Let node 1 open an invoice:
+    let add_invoice = AddInvoice {
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 4,
+        validity_ticks: 100,
+    };
+    await!(node_controls[1].send(FunderControl::AddInvoice(add_invoice)));
+
+    // Second attempt:
+    let mut create_transaction = create_transaction;
+    create_transaction.request_id = request_id_b.clone();
+    await!(node_controls[0].send(FunderControl::CreateTransaction(create_transaction)));
+    let transaction_result = await!(node_controls[0].recv_until_transaction_result()).unwrap();
+    let commit = match transaction_result.result {
+        RequestResult::Success(commit) => commit,
+        _ => unreachable!(),
+    };
+
+    // 0: Close the payment for new transactions:
+    await!(node_controls[0].send(FunderControl::RequestClosePayment(payment_id.clone())));
+    let response_close_payment =
+        await!(node_controls[0].recv_until_response_close_payment()).unwrap();
+    assert_eq!(response_close_payment.status, PaymentStatus::InProgress);
+
+    // 1: Apply MultiCommit:
+    let multi_commit = MultiCommit {
+        invoice_id: InvoiceId::from(&[1u8; INVOICE_ID_LEN]),
+        total_dest_payment: 4,
+        commits: vec![commit],
+    };
+    await!(node_controls[1].send(FunderControl::CommitInvoice(multi_commit)));
+
+    // 0: Wait for a receipt:
+    loop {
+        await!(node_controls[0].send(FunderControl::RequestClosePayment(payment_id.clone())));
+        let response_close_payment =
+            await!(node_controls[0].recv_until_response_close_payment()).unwrap();
+        match response_close_payment.status {
+            PaymentStatus::Success(_) => break,
+            _ => {}
+        }
+    }
+
+    await!(node_controls[0].send(FunderControl::GetPaymentTimeline(payment_id.clone())));
+    let response_payment_timeline =
+        await!(node_controls[0].recv_until_response_payment_timeline()).unwrap();
+    assert_eq!(response_payment_timeline.payment_id, payment_id);
+
+    let timeline = response_payment_timeline.timeline;
+    assert_eq!(
+        timeline
+            .iter()
+            .map(|payment_event| payment_event.event.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            PaymentEventKind::Created,
+            PaymentEventKind::TransactionAdded(request_id_a.clone()),
+            PaymentEventKind::TransactionFailed(request_id_a),
+            PaymentEventKind::TransactionAdded(request_id_b),
+            PaymentEventKind::ClosedRequested,
+            PaymentEventKind::Succeeded,
+        ]
+    );
+    for pair in timeline.windows(2) {
+        assert!(pair[0].tick < pair[1].tick);
+    }
+
+    // The completed payment was added to the payment history:
+    let query_payment_history = QueryPaymentHistory {
+        after_tick: 0,
+        limit: 10,
+    };
+    await!(node_controls[0].send(FunderControl::QueryPaymentHistory(query_payment_history)));
+    let payment_history_response =
+        await!(node_controls[0].recv_until_payment_history_response()).unwrap();
+    assert_eq!(payment_history_response.entries.len(), 1);
+    let entry = &payment_history_response.entries[0];
+    assert_eq!(entry.payment_id, payment_id);
+    assert_eq!(entry.invoice_id, InvoiceId::from(&[1u8; INVOICE_ID_LEN]));
+    assert_eq!(entry.total_amount, 4);
+    assert_eq!(entry.num_transactions, 2);
+    assert_eq!(entry.outcome, PaymentOutcome::Succeeded);
+    assert_eq!(entry.completed_at_tick, timeline.last().unwrap().tick);
+}
+
+#[test]
+fn test_funder_payment_timeline() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_payment_timeline(thread_pool.clone()));
+}
+
 /// Test a basic inconsistency between two adjacent nodes
 async fn task_funder_inconsistency_basic<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
 {
     let num_nodes = 2;
-    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
 
     let public_keys = node_controls
         .iter()
@@ -554,7 +1181,11 @@ fn test_funder_inconsistency_basic() {
 /// Test setting relay address for local node
 async fn task_funder_add_relay(spawner: impl Spawn + Clone + Send + 'static) {
     let num_nodes = 1;
-    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
 
     // Change the node's relay address:
     let named_relay = dummy_named_relay_address(5);
@@ -571,4 +1202,86 @@ fn test_funder_add_relay() {
     thread_pool.run(task_funder_add_relay(thread_pool.clone()));
 }
 
+/// Test that a node can not have more than `max_node_relays` relays
+async fn task_funder_max_node_relays(spawner: impl Spawn + Clone + Send + 'static) {
+    let num_nodes = 1;
+    let mut node_controls = await!(create_node_controls(
+        num_nodes,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        spawner
+    ));
+
+    let max_node_relays = node_controls[0].report.max_node_relays as usize;
+    // The node starts with one relay:
+    assert_eq!(node_controls[0].report.num_relays(), 1);
+
+    // Fill up all the relay slots:
+    for i in 1..max_node_relays {
+        await!(node_controls[0].add_relay(dummy_named_relay_address(i as u8)));
+    }
+    assert_eq!(node_controls[0].report.num_relays(), max_node_relays);
+
+    // Adding another relay should have no effect:
+    let extra_relay = dummy_named_relay_address(0xff);
+    await!(node_controls[0].add_relay(extra_relay.clone()));
+    assert_eq!(node_controls[0].report.num_relays(), max_node_relays);
+    assert!(!node_controls[0].report.relays.contains(&extra_relay));
+
+    // Replacing an existing relay is allowed when all the slots are taken:
+    let mut replaced_relay = dummy_named_relay_address(1);
+    replaced_relay.name = "replaced-relay".to_owned();
+    await!(node_controls[0].add_relay(replaced_relay.clone()));
+    assert_eq!(node_controls[0].report.num_relays(), max_node_relays);
+    assert!(node_controls[0].report.relays.contains(&replaced_relay));
+
+    // Removing a relay frees a slot:
+    await!(node_controls[0].remove_relay(dummy_named_relay_address(2).public_key));
+    await!(node_controls[0].add_relay(extra_relay.clone()));
+    assert_eq!(node_controls[0].report.num_relays(), max_node_relays);
+    assert!(node_controls[0].report.relays.contains(&extra_relay));
+}
+
+#[test]
+fn test_funder_max_node_relays() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_max_node_relays(thread_pool.clone()));
+}
+
 // TODO: Add a test for multi-route payment
+
+#[test]
+fn test_funder_invalid_max_operations_in_batch() {
+    let (identity_requests_sender, _identity_requests) = mpsc::channel(0);
+    let (db_requests_sender, _db_requests) = mpsc::channel(0);
+    let (_control_sender, incoming_control) = mpsc::channel(0);
+    let (_comm_sender, incoming_comm) = mpsc::channel(0);
+    let (outgoing_control_sender, _outgoing_control) = mpsc::channel(0);
+    let (outgoing_comm_sender, _outgoing_comm) = mpsc::channel(0);
+
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let funder_fut = inner_funder_loop(
+        IdentityClient::new(identity_requests_sender),
+        DummyRandom::new(&[0u8]),
+        incoming_control,
+        incoming_comm,
+        stream::empty::<TimerTick>(),
+        outgoing_control_sender,
+        outgoing_comm_sender,
+        FunderState::<u32>::new(local_public_key, Vec::new()),
+        DatabaseClient::new(db_requests_sender),
+        0,
+        16,
+        16,
+        16,
+        64,
+        1 << 64,
+        1 << 20,
+        None,
+    );
+
+    let mut thread_pool = ThreadPool::new().unwrap();
+    match thread_pool.run(funder_fut) {
+        Err(FunderError::InvalidMaxOperationsInBatch) => {}
+        _ => unreachable!(),
+    }
+}
@@ -1,4 +1,9 @@
 use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use im::hashmap::HashMap as ImHashMap;
+use serde::Serialize;
 
 use common::canonical_serialize::CanonicalSerialize;
 use common::mutable_state::MutableState;
@@ -6,7 +11,7 @@ use common::mutable_state::MutableState;
 use futures::channel::mpsc;
 use futures::stream::select;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, FutureExt, SinkExt, StreamExt};
+use futures::{future, stream, FutureExt, SinkExt, StreamExt};
 
 use crypto::identity::{
     generate_pkcs8_key_pair, PublicKey, SoftwareEd25519Identity, PUBLIC_KEY_LEN,
@@ -21,14 +26,15 @@ use proto::report::messages::{
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
-    AddFriend, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl, Rate,
-    RequestsStatus, ResponseClosePayment, SetFriendRate, SetFriendRemoteMaxDebt, SetFriendStatus,
-    SetRequestsStatus, TransactionResult,
+    AddFriend, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+    PaymentHistoryResponse, Rate, RequestsStatus, ResponseClosePayment, ResponsePaymentTimeline,
+    SetFriendRate, SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus, TransactionResult,
 };
 
 use database::DatabaseClient;
 
 use identity::{create_identity, IdentityClient};
+use timer::TimerTick;
 
 use crate::ephemeral::Ephemeral;
 use crate::funder::inner_funder_loop;
@@ -40,8 +46,12 @@ use crate::types::{
 };
 
 const TEST_MAX_NODE_RELAYS: usize = 16;
-const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
+const TEST_MAX_FRIENDS: usize = 16;
+pub const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+const TEST_MAX_PENDING_TICKS: usize = 64;
+const TEST_MAX_FROZEN_CREDITS_THRESHOLD: u128 = 1 << 64;
+const TEST_HISTORY_RETENTION_EVENTS: u64 = 1 << 20;
 
 // This is required to make sure the tests are not stuck.
 //
@@ -50,6 +60,23 @@ const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
 // approach makes tests difficult to write.
 const CHANNEL_SIZE: usize = 64;
 
+/// Check that two maps contain the same keys, and that every value has the same serialization.
+/// Useful for values that do not implement `PartialEq` (Like `FriendState`).
+pub fn assert_same_entries<K, V>(map1: &ImHashMap<K, V>, map2: &ImHashMap<K, V>)
+where
+    K: Hash + Eq + Clone + Debug,
+    V: Clone + Serialize,
+{
+    assert_eq!(map1.len(), map2.len());
+    for (key, value1) in map1 {
+        let value2 = map2.get(key).unwrap();
+        assert_eq!(
+            serde_json::to_value(value1).unwrap(),
+            serde_json::to_value(value2).unwrap()
+        );
+    }
+}
+
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
     NamedRelayAddress {
@@ -208,6 +235,8 @@ pub struct NodeControl<B: Clone> {
 pub enum NodeRecv<B: Clone> {
     ReportMutations(FunderReportMutations<B>),
     ResponseClosePayment(ResponseClosePayment),
+    ResponsePaymentTimeline(ResponsePaymentTimeline),
+    PaymentHistoryResponse(PaymentHistoryResponse),
     TransactionResult(TransactionResult),
 }
 
@@ -258,6 +287,12 @@ where
             FunderOutgoingControl::ResponseClosePayment(response_close_payment) => {
                 Some(NodeRecv::ResponseClosePayment(response_close_payment))
             }
+            FunderOutgoingControl::ResponsePaymentTimeline(response_payment_timeline) => {
+                Some(NodeRecv::ResponsePaymentTimeline(response_payment_timeline))
+            }
+            FunderOutgoingControl::PaymentHistoryResponse(payment_history_response) => {
+                Some(NodeRecv::PaymentHistoryResponse(payment_history_response))
+            }
             FunderOutgoingControl::TransactionResult(transaction_result) => {
                 Some(NodeRecv::TransactionResult(transaction_result))
             }
@@ -273,6 +308,8 @@ where
                 NodeRecv::ReportMutations(_) => {}
                 NodeRecv::TransactionResult(_) => unreachable!(),
                 NodeRecv::ResponseClosePayment(_) => unreachable!(),
+                NodeRecv::ResponsePaymentTimeline(_) => unreachable!(),
+                NodeRecv::PaymentHistoryResponse(_) => unreachable!(),
             };
         }
     }
@@ -283,6 +320,8 @@ where
                 NodeRecv::ReportMutations(_) => {}
                 NodeRecv::TransactionResult(transaction_result) => return Some(transaction_result),
                 NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::ResponsePaymentTimeline(_) => {}
+                NodeRecv::PaymentHistoryResponse(_) => {}
             };
         }
     }
@@ -295,6 +334,38 @@ where
                 NodeRecv::ResponseClosePayment(response_close_payment) => {
                     return Some(response_close_payment)
                 }
+                NodeRecv::ResponsePaymentTimeline(_) => {}
+                NodeRecv::PaymentHistoryResponse(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_response_payment_timeline(
+        &mut self,
+    ) -> Option<ResponsePaymentTimeline> {
+        loop {
+            match await!(self.recv())? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::ResponsePaymentTimeline(response_payment_timeline) => {
+                    return Some(response_payment_timeline)
+                }
+                NodeRecv::PaymentHistoryResponse(_) => {}
+            };
+        }
+    }
+
+    pub async fn recv_until_payment_history_response(&mut self) -> Option<PaymentHistoryResponse> {
+        loop {
+            match await!(self.recv())? {
+                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::TransactionResult(_) => {}
+                NodeRecv::ResponseClosePayment(_) => {}
+                NodeRecv::ResponsePaymentTimeline(_) => {}
+                NodeRecv::PaymentHistoryResponse(payment_history_response) => {
+                    return Some(payment_history_response)
+                }
             };
         }
     }
@@ -318,6 +389,7 @@ where
             friend_public_key: friend_public_key.clone(),
             relays,
             name: name.into(),
+            note: String::new(),
             balance,
         };
         await!(self.send(FunderControl::AddFriend(add_friend)));
@@ -390,7 +462,11 @@ where
 /// Create a few node_controls, together with a router connecting them all.
 /// This allows having a conversation between any two nodes.
 /// We use A = u32:
-pub async fn create_node_controls<S>(num_nodes: usize, mut spawner: S) -> Vec<NodeControl<u32>>
+pub async fn create_node_controls<S>(
+    num_nodes: usize,
+    max_operations_in_batch: usize,
+    mut spawner: S,
+) -> Vec<NodeControl<u32>>
 where
     S: Spawn + Clone + Send + 'static,
 {
@@ -417,7 +493,12 @@ where
         let relays = vec![dummy_named_relay_address(i as u8)];
         let funder_state = FunderState::new(public_key.clone(), relays);
         let ephemeral = Ephemeral::new();
-        let base_report = create_report(&funder_state, &ephemeral);
+        let base_report = create_report(
+            &funder_state,
+            &ephemeral,
+            TEST_MAX_FRIENDS,
+            TEST_MAX_NODE_RELAYS,
+        );
 
         // let report = create_report(&self.state, &self.ephemeral);
         // self.add_outgoing_control(FunderOutgoingControl::Report(report));
@@ -444,13 +525,18 @@ where
             DummyRandom::new(&[i as u8]),
             incoming_control,
             incoming_comm,
+            stream::empty::<TimerTick>(),
             control_sender,
             comm_sender,
             funder_state,
             db_client,
+            max_operations_in_batch,
             TEST_MAX_NODE_RELAYS,
-            TEST_MAX_OPERATIONS_IN_BATCH,
+            TEST_MAX_FRIENDS,
             TEST_MAX_PENDING_USER_REQUESTS,
+            TEST_MAX_PENDING_TICKS,
+            TEST_MAX_FROZEN_CREDITS_THRESHOLD,
+            TEST_HISTORY_RETENTION_EVENTS,
             None,
         );
 
@@ -1,16 +1,21 @@
 use std::cmp::Ordering;
 use std::convert::TryFrom;
+use std::mem;
 
 use common::canonical_serialize::CanonicalSerialize;
 
 use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
 use crypto::hash::sha_512_256;
+use crypto::hash_lock::{HASHED_LOCK_LEN, PLAIN_LOCK_LEN};
 use crypto::identity::{compare_public_key, PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use crypto::invoice_id::INVOICE_ID_LEN;
+use crypto::uid::UID_LEN;
 
 use proto::app_server::messages::RelayAddress;
-use proto::funder::messages::{FriendTcOp, MoveToken};
+use proto::funder::messages::{FriendTcOp, MoveToken, ResetTerms};
 use proto::funder::signature_buff::verify_move_token;
 
+use crate::balance::Balance;
 use crate::mutual_credit::incoming::{
     process_operations_list, IncomingMessage, ProcessOperationOutput, ProcessTransListError,
 };
@@ -108,6 +113,38 @@ fn rand_nonce_from_public_key(public_key: &PublicKey) -> RandValue {
     RandValue::try_from(&public_key_hash.as_ref()[..RAND_VALUE_LEN]).unwrap()
 }
 
+/// Estimate the serialized size (in bytes) of a single operation.
+fn operation_size_estimate(operation: &FriendTcOp) -> usize {
+    let u128_len = mem::size_of::<u128>();
+    match operation {
+        FriendTcOp::EnableRequests | FriendTcOp::DisableRequests => 0,
+        FriendTcOp::SetRemoteMaxDebt(_) => u128_len,
+        FriendTcOp::RequestSendFunds(request_send_funds) => {
+            // dest_payment, total_dest_payment and left_fees:
+            UID_LEN
+                + HASHED_LOCK_LEN
+                + request_send_funds.route.len() * PUBLIC_KEY_LEN
+                + 3 * u128_len
+                + INVOICE_ID_LEN
+        }
+        FriendTcOp::ResponseSendFunds(_) => {
+            UID_LEN + HASHED_LOCK_LEN + RAND_VALUE_LEN + SIGNATURE_LEN
+        }
+        FriendTcOp::CancelSendFunds(_) => UID_LEN,
+        FriendTcOp::CollectSendFunds(_) => UID_LEN + 2 * PLAIN_LOCK_LEN,
+    }
+}
+
+/// Estimate the size of the batch of operations carried by a move token.
+/// The result is the amount of operations, plus the estimated serialized size (in bytes) of all
+/// the operations. Used for logging and telemetry only.
+pub fn batch_size_estimate<B, S>(move_token: &MoveToken<B, S>) -> usize {
+    move_token
+        .operations_iter()
+        .map(operation_size_estimate)
+        .fold(move_token.len(), usize::saturating_add)
+}
+
 /// Create an initial move token in the relationship between two public keys.
 /// To canonicalize the initial move token (Having an equal move token for both sides), we sort the
 /// two public keys in some way.
@@ -137,6 +174,77 @@ fn initial_move_token<B>(
     }
 }
 
+/// Create the move token we send to reset the channel, accepting the reset terms of the remote
+/// side. The move token still has to be signed.
+pub fn create_unsigned_reset_move_token<B>(
+    local_public_key: &PublicKey,
+    remote_public_key: &PublicKey,
+    remote_reset_terms: &ResetTerms,
+    rand_nonce: RandValue,
+) -> UnsignedMoveToken<B> {
+    create_unsigned_move_token(
+        // No operations are required for a reset move token
+        Vec::new(),
+        None,
+        remote_reset_terms.reset_token.clone(),
+        local_public_key.clone(),
+        remote_public_key.clone(),
+        remote_reset_terms.inconsistency_counter,
+        // move_token_counter:
+        0,
+        remote_reset_terms.balance_for_reset.checked_neg().unwrap(),
+        // local_pending_debt and remote_pending_debt:
+        0,
+        0,
+        rand_nonce,
+    )
+}
+
+/// Preview the token channel we would have after resetting the channel, accepting the reset terms
+/// of the remote side. Nothing is mutated.
+///
+/// The token channel is built the same way as in an actual reset. It differs only in the
+/// `rand_nonce` and `new_token` of the reset move token: The actual reset move token carries a
+/// random nonce and is signed, here both are zeroed.
+pub fn simulate_reset_outcome<B>(
+    local_public_key: &PublicKey,
+    remote_public_key: &PublicKey,
+    remote_reset_terms: &ResetTerms,
+    opt_last_incoming_move_token: Option<MoveTokenHashed>,
+) -> TokenChannel<B>
+where
+    B: Clone + CanonicalSerialize,
+{
+    let u_reset_move_token = create_unsigned_reset_move_token::<B>(
+        local_public_key,
+        remote_public_key,
+        remote_reset_terms,
+        RandValue::from(&[0; RAND_VALUE_LEN]),
+    );
+    let reset_move_token = MoveToken {
+        operations: u_reset_move_token.operations,
+        opt_local_relays: u_reset_move_token.opt_local_relays,
+        old_token: u_reset_move_token.old_token,
+        local_public_key: u_reset_move_token.local_public_key,
+        remote_public_key: u_reset_move_token.remote_public_key,
+        inconsistency_counter: u_reset_move_token.inconsistency_counter,
+        move_token_counter: u_reset_move_token.move_token_counter,
+        balance: u_reset_move_token.balance,
+        local_pending_debt: u_reset_move_token.local_pending_debt,
+        remote_pending_debt: u_reset_move_token.remote_pending_debt,
+        rand_nonce: u_reset_move_token.rand_nonce,
+        new_token: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+
+    TokenChannel::new_from_local_reset(
+        local_public_key,
+        remote_public_key,
+        &reset_move_token,
+        remote_reset_terms.balance_for_reset.checked_neg().unwrap(),
+        opt_last_incoming_move_token,
+    )
+}
+
 impl<B> TokenChannel<B>
 where
     B: Clone + CanonicalSerialize,
@@ -288,13 +396,31 @@ where
         }
     }
 
+    /// Summarize this token channel as a graph edge, for visualization purposes.
+    /// Returns (local_public_key, remote_public_key, balance, is_consistent).
+    /// Note that a TokenChannel only exists for consistent channels.
+    pub fn to_graph_representation(&self) -> (PublicKey, PublicKey, i128, bool) {
+        let mc_state = self.get_mutual_credit().state();
+        (
+            mc_state.idents.local_public_key.clone(),
+            mc_state.idents.remote_public_key.clone(),
+            i128::from(mc_state.balance.balance),
+            true,
+        )
+    }
+
+    /// `current_tick` is the local funder's time tick, recorded for every new pending
+    /// transaction.
     pub fn simulate_receive_move_token(
         &self,
         new_move_token: MoveToken<B>,
+        current_tick: u64,
     ) -> Result<ReceiveMoveTokenOutput<B>, ReceiveMoveTokenError> {
         match &self.direction {
             TcDirection::Incoming(tc_incoming) => tc_incoming.handle_incoming(new_move_token),
-            TcDirection::Outgoing(tc_outgoing) => tc_outgoing.handle_incoming(new_move_token),
+            TcDirection::Outgoing(tc_outgoing) => {
+                tc_outgoing.handle_incoming(new_move_token, current_tick)
+            }
         }
     }
 }
@@ -334,7 +460,7 @@ impl TcIncoming {
             self.move_token_in.local_public_key.clone(),
             self.move_token_in.inconsistency_counter,
             self.move_token_in.move_token_counter.wrapping_add(1),
-            self.mutual_credit.state().balance.balance,
+            i128::from(self.mutual_credit.state().balance.balance),
             self.mutual_credit.state().balance.local_pending_debt,
             self.mutual_credit.state().balance.remote_pending_debt,
             rand_nonce,
@@ -354,6 +480,7 @@ where
     fn handle_incoming(
         &self,
         new_move_token: MoveToken<B>,
+        current_tick: u64,
     ) -> Result<ReceiveMoveTokenOutput<B>, ReceiveMoveTokenError> {
         // Make sure that the stated remote public key and local public key match:
         if !((self.mutual_credit.state().idents.local_public_key
@@ -365,7 +492,7 @@ where
         }
 
         if new_move_token.old_token == self.move_token_out.new_token {
-            self.handle_incoming_token_match(new_move_token)
+            self.handle_incoming_token_match(new_move_token, current_tick)
         // self.outgoing_to_incoming(friend_move_token, new_move_token)
         } else if self.move_token_out.old_token == new_move_token.new_token {
             // We should retransmit our move token message to the remote side.
@@ -380,6 +507,7 @@ where
     fn handle_incoming_token_match(
         &self,
         new_move_token: MoveToken<B>,
+        current_tick: u64,
     ) -> Result<ReceiveMoveTokenOutput<B>, ReceiveMoveTokenError> {
         // Verify signature:
         // Note that we only verify the signature here, and not at the Incoming part.
@@ -406,7 +534,11 @@ where
         }
 
         let mut mutual_credit = self.mutual_credit.clone();
-        let res = process_operations_list(&mut mutual_credit, new_move_token.operations.clone());
+        let res = process_operations_list(
+            &mut mutual_credit,
+            new_move_token.operations.clone(),
+            current_tick,
+        );
 
         match res {
             Ok(outputs) => {
@@ -440,7 +572,9 @@ where
 
                 // Verify stated balances:
                 let check_balance = &check_mutual_credit.state().balance;
-                if check_balance.balance != -new_move_token.balance
+                // The remote side keeps the negation of our balance:
+                if Some(check_balance.balance)
+                    != Balance::from(new_move_token.balance).checked_neg()
                     || check_balance.local_pending_debt != new_move_token.remote_pending_debt
                     || check_balance.remote_pending_debt != new_move_token.local_pending_debt
                 {
@@ -479,6 +613,11 @@ mod tests {
     use crypto::identity::{generate_pkcs8_key_pair, SoftwareEd25519Identity};
     use crypto::test_utils::DummyRandom;
 
+    use crypto::hash_lock::HashedLock;
+    use crypto::invoice_id::InvoiceId;
+    use crypto::uid::Uid;
+
+    use proto::funder::messages::{CancelSendFundsOp, FriendsRoute, RequestSendFundsOp};
     use proto::funder::signature_buff::move_token_signature_buff;
 
     /// A helper function to sign an UnsignedMoveToken using an identity:
@@ -547,6 +686,104 @@ mod tests {
         assert!(tc_outgoing.opt_prev_move_token_in.is_none());
     }
 
+    #[test]
+    fn test_to_graph_representation() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let token_channel_a_b = TokenChannel::<u32>::new(&pk_a, &pk_b, 5i128);
+        let token_channel_b_a = TokenChannel::<u32>::new(&pk_b, &pk_a, -5i128);
+
+        assert_eq!(
+            token_channel_a_b.to_graph_representation(),
+            (pk_a.clone(), pk_b.clone(), 5i128, true)
+        );
+        assert_eq!(
+            token_channel_b_a.to_graph_representation(),
+            (pk_b.clone(), pk_a.clone(), -5i128, true)
+        );
+    }
+
+    /// Create a move token that carries the given operations
+    fn move_token_with_operations(operations: Vec<FriendTcOp>) -> MoveToken<u32> {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let mut move_token = initial_move_token(&pk_a, &pk_b, 0i128);
+        move_token.operations = operations;
+        move_token
+    }
+
+    fn dummy_request_send_funds(route_len: usize) -> FriendTcOp {
+        let public_keys = (0..route_len)
+            .map(|i| PublicKey::from(&[i as u8; PUBLIC_KEY_LEN]))
+            .collect();
+        FriendTcOp::RequestSendFunds(RequestSendFundsOp {
+            request_id: Uid::from(&[0; UID_LEN]),
+            src_hashed_lock: HashedLock::from(&[1; HASHED_LOCK_LEN]),
+            route: FriendsRoute { public_keys },
+            dest_payment: 10,
+            total_dest_payment: 10,
+            invoice_id: InvoiceId::from(&[2; INVOICE_ID_LEN]),
+            left_fees: 0,
+        })
+    }
+
+    #[test]
+    fn test_batch_size_estimate() {
+        assert_eq!(
+            batch_size_estimate(&move_token_with_operations(Vec::new())),
+            0
+        );
+
+        // Operations without content are still counted:
+        let operations = vec![FriendTcOp::EnableRequests, FriendTcOp::DisableRequests];
+        assert_eq!(
+            batch_size_estimate(&move_token_with_operations(operations)),
+            2
+        );
+
+        let operations = vec![FriendTcOp::SetRemoteMaxDebt(100)];
+        assert_eq!(
+            batch_size_estimate(&move_token_with_operations(operations)),
+            1 + mem::size_of::<u128>()
+        );
+
+        // Longer routes result in larger requests:
+        let short_request =
+            batch_size_estimate(&move_token_with_operations(vec![dummy_request_send_funds(
+                2,
+            )]));
+        let long_request =
+            batch_size_estimate(&move_token_with_operations(vec![dummy_request_send_funds(
+                5,
+            )]));
+        assert_eq!(long_request - short_request, 3 * PUBLIC_KEY_LEN);
+    }
+
+    #[test]
+    fn test_batch_size_estimate_consistent() {
+        let first_operations = vec![dummy_request_send_funds(3), FriendTcOp::EnableRequests];
+        let second_operations = vec![
+            FriendTcOp::CancelSendFunds(CancelSendFundsOp {
+                request_id: Uid::from(&[3; UID_LEN]),
+            }),
+            FriendTcOp::SetRemoteMaxDebt(7),
+        ];
+        let mut all_operations = first_operations.clone();
+        all_operations.extend(second_operations.clone());
+
+        let first_estimate = batch_size_estimate(&move_token_with_operations(first_operations));
+        let second_estimate = batch_size_estimate(&move_token_with_operations(second_operations));
+        let all_estimate = batch_size_estimate(&move_token_with_operations(all_operations.clone()));
+
+        // The estimate of a batch is the sum of the estimates of its parts:
+        assert_eq!(all_estimate, first_estimate + second_estimate);
+        // Estimating the same batch again gives the same result:
+        assert_eq!(
+            batch_size_estimate(&move_token_with_operations(all_operations)),
+            all_estimate
+        );
+    }
+
     /// Sort the two identity client.
     /// The result will be a pair where the first is initially configured to have outgoing message,
     /// and the second is initially configured to have incoming message.
@@ -584,7 +821,7 @@ mod tests {
         };
         let mut outgoing_mc = tc2_incoming.begin_outgoing_move_token();
         let friend_tc_op = FriendTcOp::SetRemoteMaxDebt(100);
-        let mc_mutations = outgoing_mc.queue_operation(&friend_tc_op).unwrap();
+        let mc_mutations = outgoing_mc.queue_operation(&friend_tc_op, 0).unwrap();
         let operations = vec![friend_tc_op];
 
         let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
@@ -604,7 +841,7 @@ mod tests {
         assert!(tc2.is_outgoing());
 
         let receive_move_token_output = tc1
-            .simulate_receive_move_token(friend_move_token.clone())
+            .simulate_receive_move_token(friend_move_token.clone(), 0)
             .unwrap();
 
         let move_token_received = match receive_move_token_output {
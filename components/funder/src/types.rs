@@ -79,7 +79,10 @@ pub fn create_cancel_send_funds(request_id: Uid) -> CancelSendFundsOp {
     }
 }
 
-pub fn create_pending_transaction(request_send_funds: &RequestSendFundsOp) -> PendingTransaction {
+pub fn create_pending_transaction(
+    request_send_funds: &RequestSendFundsOp,
+    current_tick: u64,
+) -> PendingTransaction {
     PendingTransaction {
         request_id: request_send_funds.request_id,
         route: request_send_funds.route.clone(),
@@ -89,6 +92,7 @@ pub fn create_pending_transaction(request_send_funds: &RequestSendFundsOp) -> Pe
         left_fees: request_send_funds.left_fees,
         src_hashed_lock: request_send_funds.src_hashed_lock.clone(),
         stage: TransactionStage::Request,
+        created_at_tick: current_tick,
     }
 }
 
@@ -238,6 +242,7 @@ pub enum FunderIncoming<B> {
     Init,
     Control(FunderIncomingControl<B>),
     Comm(FunderIncomingComm<B>),
+    TimerTick,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -0,0 +1,122 @@
+use proto::index_client::messages::CircuitStateReport;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CircuitState {
+    /// The server may be used. Holds the amount of consecutive failures.
+    Closed(usize),
+    /// The server is skipped. Holds the amount of ticks left until the server is tried again.
+    Open(usize),
+    /// The server may be tried once. A failure will open the circuit again.
+    HalfOpen,
+}
+
+/// Keeps track of connection failures to a single index server.
+/// After `failure_threshold` consecutive failures, the server is not used for
+/// `open_duration_ticks` ticks.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: CircuitState,
+    failure_threshold: usize,
+    open_duration_ticks: usize,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: usize, open_duration_ticks: usize) -> Self {
+        CircuitBreaker {
+            state: CircuitState::Closed(0),
+            failure_threshold,
+            open_duration_ticks,
+        }
+    }
+
+    /// Can we attempt to connect to the server?
+    pub fn is_available(&self) -> bool {
+        match self.state {
+            CircuitState::Closed(_) | CircuitState::HalfOpen => true,
+            CircuitState::Open(_) => false,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.state = CircuitState::Closed(0);
+    }
+
+    pub fn record_failure(&mut self) {
+        self.state = match self.state {
+            CircuitState::Closed(num_failures) => {
+                let num_failures = num_failures.saturating_add(1);
+                if num_failures >= self.failure_threshold {
+                    CircuitState::Open(self.open_duration_ticks)
+                } else {
+                    CircuitState::Closed(num_failures)
+                }
+            }
+            CircuitState::HalfOpen => CircuitState::Open(self.open_duration_ticks),
+            CircuitState::Open(ticks_left) => CircuitState::Open(ticks_left),
+        };
+    }
+
+    pub fn tick(&mut self) {
+        if let CircuitState::Open(ticks_left) = self.state {
+            let ticks_left = ticks_left.saturating_sub(1);
+            self.state = if ticks_left == 0 {
+                CircuitState::HalfOpen
+            } else {
+                CircuitState::Open(ticks_left)
+            };
+        }
+    }
+
+    pub fn report(&self) -> CircuitStateReport {
+        match self.state {
+            CircuitState::Closed(_) => CircuitStateReport::Closed,
+            CircuitState::Open(_) => CircuitStateReport::Open,
+            CircuitState::HalfOpen => CircuitStateReport::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker_open_after_failures() {
+        let mut circuit_breaker = CircuitBreaker::new(3, 2);
+        circuit_breaker.record_failure();
+        circuit_breaker.record_failure();
+        assert!(circuit_breaker.is_available());
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::Closed);
+
+        circuit_breaker.record_failure();
+        assert!(!circuit_breaker.is_available());
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::Open);
+
+        circuit_breaker.tick();
+        assert!(!circuit_breaker.is_available());
+        circuit_breaker.tick();
+        assert!(circuit_breaker.is_available());
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::HalfOpen);
+
+        // A single failure in the half open state opens the circuit again:
+        circuit_breaker.record_failure();
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::Open);
+    }
+
+    #[test]
+    fn test_circuit_breaker_success_resets() {
+        let mut circuit_breaker = CircuitBreaker::new(2, 1);
+        circuit_breaker.record_failure();
+        circuit_breaker.record_success();
+        circuit_breaker.record_failure();
+        // Failures are not consecutive:
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::Closed);
+
+        circuit_breaker.record_failure();
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::Open);
+        circuit_breaker.tick();
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::HalfOpen);
+        circuit_breaker.record_success();
+        assert_eq!(circuit_breaker.report(), CircuitStateReport::Closed);
+    }
+}
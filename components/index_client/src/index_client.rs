@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::marker::Unpin;
 
@@ -15,6 +15,7 @@ use crypto::uid::Uid;
 
 use database::DatabaseClient;
 
+use proto::consts::ROUTE_CACHE_TTL_TICKS;
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientReportMutation,
     IndexClientReportMutations, IndexClientRequest, IndexClientToAppServer, IndexMutation,
@@ -22,20 +23,66 @@ use proto::index_client::messages::{
 };
 use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
 
+use crate::circuit_breaker::CircuitBreaker;
 use crate::client_session::{ControlSender, SessionHandle};
+use crate::route_cache::{RouteCache, RouteCacheKey};
+use crate::route_scorer::RouteScorer;
 use crate::seq_friends::SeqFriendsClient;
 use crate::single_client::SingleClientControl;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+fn default_cache_ttl_ticks() -> usize {
+    ROUTE_CACHE_TTL_TICKS
+}
+
+/// Note: This configuration is part of the node's database, which is serialized using bincode.
+/// Any change to its fields requires increasing `NODE_STATE_VERSION`. The serde defaults below only
+/// apply to JSON (For example, files created by `stmgr export-db`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexClientConfig<ISA> {
     pub index_servers: Vec<NamedIndexServerAddress<ISA>>,
+    /// Amount of ticks received routes are kept in cache. 0 disables caching.
+    #[serde(default = "default_cache_ttl_ticks")]
+    pub cache_ttl_ticks: usize,
+    /// Maximum amount of friends sent to the index servers. If more friends are known, the least
+    /// recently updated friends are evicted. None means unbounded.
+    #[serde(default)]
+    pub max_seq_friends: Option<usize>,
+}
+
+impl<ISA> Default for IndexClientConfig<ISA> {
+    fn default() -> Self {
+        IndexClientConfig::new()
+    }
 }
 
 impl<ISA> IndexClientConfig<ISA> {
     pub fn new() -> Self {
         IndexClientConfig {
             index_servers: Vec::new(),
+            cache_ttl_ticks: default_cache_ttl_ticks(),
+            max_seq_friends: None,
+        }
+    }
+
+    /// Create a mutation that replaces the index server `old_public_key` with `new_server`
+    /// in one step, so that there is no moment where neither of them is configured.
+    /// Returns None if `old_public_key` is not a configured index server.
+    pub fn rotate_server(
+        &self,
+        old_public_key: &PublicKey,
+        new_server: NamedIndexServerAddress<ISA>,
+    ) -> Option<IndexClientConfigMutation<ISA>> {
+        if !self
+            .index_servers
+            .iter()
+            .any(|named_index_server| &named_index_server.public_key == old_public_key)
+        {
+            return None;
         }
+        Some(IndexClientConfigMutation::RotateServer {
+            remove: old_public_key.clone(),
+            add: new_server,
+        })
     }
 }
 
@@ -43,6 +90,11 @@ impl<ISA> IndexClientConfig<ISA> {
 pub enum IndexClientConfigMutation<ISA> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
     RemoveIndexServer(PublicKey),
+    /// Remove an index server and add a new one atomically
+    RotateServer {
+        remove: PublicKey,
+        add: NamedIndexServerAddress<ISA>,
+    },
 }
 
 impl<ISA> MutableState for IndexClientConfig<ISA>
@@ -66,6 +118,13 @@ where
                 self.index_servers
                     .retain(|named_index_server| &named_index_server.public_key != public_key);
             }
+            IndexClientConfigMutation::RotateServer { remove, add } => {
+                self.index_servers.retain(|named_index_server| {
+                    &named_index_server.public_key != remove
+                        && named_index_server.public_key != add.public_key
+                });
+                self.index_servers.push(add.clone());
+            }
         };
         Ok(())
     }
@@ -90,6 +149,12 @@ struct ServerConnected<ISA> {
     ticks_to_send_keepalive: usize,
 }
 
+/// A routes request that was forwarded to the index server
+struct OpenRoutesRequest {
+    capacity: u128,
+    opt_cache_key: Option<RouteCacheKey>,
+}
+
 #[derive(Debug)]
 enum ConnStatus<ISA> {
     Empty(usize), // ticks_to_reconnect
@@ -129,8 +194,16 @@ struct IndexClient<ISA, TAS, ICS, S> {
     index_client_session: ICS,
     max_open_requests: usize,
     num_open_requests: usize,
+    route_cache: RouteCache,
+    route_scorer: RouteScorer,
+    /// Routes requests waiting for a response from the index server, by request_id
+    open_routes_requests: HashMap<Uid, OpenRoutesRequest>,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    /// Circuit breakers of index servers, by public key.
+    circuit_breakers: HashMap<PublicKey, CircuitBreaker>,
+    failure_threshold: usize,
+    open_duration_ticks: usize,
     conn_status: ConnStatus<ISA>,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
     spawner: S,
@@ -188,9 +261,12 @@ where
         max_open_requests: usize,
         keepalive_ticks: usize,
         backoff_ticks: usize,
+        failure_threshold: usize,
+        open_duration_ticks: usize,
         db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
         spawner: S,
     ) -> Self {
+        let route_cache = RouteCache::new(index_client_config.cache_ttl_ticks);
         let index_servers = index_client_config
             .index_servers
             .into_iter()
@@ -208,14 +284,52 @@ where
             index_client_session,
             max_open_requests,
             num_open_requests: 0,
+            route_cache,
+            route_scorer: RouteScorer::new(),
+            open_routes_requests: HashMap::new(),
             keepalive_ticks,
             backoff_ticks,
+            circuit_breakers: HashMap::new(),
+            failure_threshold,
+            open_duration_ticks,
             conn_status: ConnStatus::Empty(backoff_ticks),
             db_client,
             spawner,
         }
     }
 
+    /// Record the outcome of a connection attempt to an index server.
+    /// Returns a report mutation if the circuit state of the server has changed.
+    fn record_connection_outcome(
+        &mut self,
+        public_key: &PublicKey,
+        success: bool,
+    ) -> Option<IndexClientReportMutation<ISA>> {
+        let failure_threshold = self.failure_threshold;
+        let open_duration_ticks = self.open_duration_ticks;
+        let circuit_breaker = self
+            .circuit_breakers
+            .entry(public_key.clone())
+            .or_insert_with(|| CircuitBreaker::new(failure_threshold, open_duration_ticks));
+
+        let old_circuit_state = circuit_breaker.report();
+        if success {
+            circuit_breaker.record_success();
+        } else {
+            circuit_breaker.record_failure();
+        }
+        let new_circuit_state = circuit_breaker.report();
+
+        if old_circuit_state != new_circuit_state {
+            Some(IndexClientReportMutation::SetCircuitState((
+                public_key.clone(),
+                new_circuit_state,
+            )))
+        } else {
+            None
+        }
+    }
+
     /// Attempt to connect to server.
     /// If there are no index servers known, do nothing.
     fn try_connect_to_server(&mut self) -> Result<(), IndexClientError> {
@@ -225,16 +339,38 @@ where
             unreachable!();
         }
 
-        let index_server = match self.index_servers.pop_front() {
+        if self.index_servers.is_empty() {
+            // We don't have any index servers to connect to:
+            self.conn_status = ConnStatus::Empty(0);
+            return Ok(());
+        }
+
+        // Find the next server that is not skipped by its circuit breaker:
+        let mut opt_index_server = None;
+        for _ in 0..self.index_servers.len() {
+            let index_server = self.index_servers.pop_front().unwrap();
+            // Move the address to the end, rotating the addresses VecDeque 1 to the left:
+            self.index_servers.push_back(index_server.clone());
+
+            let is_available = self
+                .circuit_breakers
+                .get(&index_server.public_key)
+                .map(|circuit_breaker| circuit_breaker.is_available())
+                .unwrap_or(true);
+            if is_available {
+                opt_index_server = Some(index_server);
+                break;
+            }
+        }
+
+        let index_server = match opt_index_server {
             Some(index_server) => index_server,
             None => {
-                // We don't have any index servers to connect to:
-                self.conn_status = ConnStatus::Empty(0);
+                // All the servers are temporarily skipped. We will try again later:
+                self.conn_status = ConnStatus::Empty(self.backoff_ticks);
                 return Ok(());
             }
         };
-        // Move the chosen address to the end, rotating the addresses VecDeque 1 to the left:
-        self.index_servers.push_back(index_server.clone());
 
         let mut c_index_client_session = self.index_client_session.clone();
         let mut c_event_sender = self.event_sender.clone();
@@ -382,6 +518,7 @@ where
         // Remove address:
         self.index_servers
             .retain(|index_server| index_server.public_key != public_key);
+        self.circuit_breakers.remove(&public_key);
 
         // Send report:
         let index_client_report_mutation =
@@ -417,6 +554,95 @@ where
         Ok(())
     }
 
+    pub async fn handle_from_app_server_rotate_index_server(
+        &mut self,
+        app_request_id: Uid,
+        old_public_key: PublicKey,
+        named_index_server_address: NamedIndexServerAddress<ISA>,
+    ) -> Result<(), IndexClientError> {
+        if !self
+            .index_servers
+            .iter()
+            .any(|index_server| index_server.public_key == old_public_key)
+        {
+            warn!(
+                "RotateIndexServer: {:?} is not a configured index server",
+                old_public_key
+            );
+            // Send empty report (Indicates that we received the request):
+            let index_client_report_mutations = IndexClientReportMutations {
+                opt_app_request_id: Some(app_request_id),
+                mutations: Vec::new(),
+            };
+            return await!(self
+                .to_app_server
+                .send(IndexClientToAppServer::ReportMutations(
+                    index_client_report_mutations
+                )))
+            .map_err(|_| IndexClientError::SendToAppServerFailed);
+        }
+
+        // Update database, using a single mutation:
+        await!(self
+            .db_client
+            .mutate(vec![IndexClientConfigMutation::RotateServer {
+                remove: old_public_key.clone(),
+                add: named_index_server_address.clone(),
+            }]))
+        .map_err(|_| IndexClientError::DatabaseError)?;
+
+        // Replace the old server in memory.
+        // The new server takes the place of the old one in the cyclic list:
+        let index_server = IndexServerAddress::from(named_index_server_address.clone());
+        self.index_servers.retain(|index_server| {
+            index_server.public_key != named_index_server_address.public_key
+                || index_server.public_key == old_public_key
+        });
+        // We checked above that the old server is in the list:
+        let index = self
+            .index_servers
+            .iter()
+            .position(|index_server| index_server.public_key == old_public_key)
+            .unwrap();
+        self.index_servers[index] = index_server;
+        self.circuit_breakers.remove(&old_public_key);
+
+        // Send report. Both mutations are sent together, so that the report never shows a state
+        // where the old server was removed but the new one was not yet added:
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: vec![
+                IndexClientReportMutation::RemoveIndexServer(old_public_key.clone()),
+                IndexClientReportMutation::AddIndexServer(named_index_server_address.clone()),
+            ],
+        };
+        await!(self
+            .to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations
+            )))
+        .map_err(|_| IndexClientError::SendToAppServerFailed)?;
+
+        // Disconnect a current server connection if it uses the removed address:
+        match &mut self.conn_status {
+            ConnStatus::Empty(_) => return self.try_connect_to_server(),
+            ConnStatus::Connecting(server_connecting) => {
+                if server_connecting.index_server.public_key == old_public_key {
+                    if let Some(cancel_sender) = server_connecting.opt_cancel_sender.take() {
+                        let _ = cancel_sender.send(());
+                    }
+                }
+            }
+            ConnStatus::Connected(server_connected) => {
+                if server_connected.index_server.public_key == old_public_key {
+                    server_connected.opt_control_sender.take();
+                    server_connected.opt_cancel_sender.take();
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn handle_from_app_server_request_routes(
         &mut self,
         app_request_id: Uid,
@@ -434,6 +660,38 @@ where
             )))
         .map_err(|_| IndexClientError::SendToAppServerFailed)?;
 
+        // Routes that exclude an edge are not cached:
+        let opt_cache_key = if request_routes.opt_exclude.is_none() {
+            Some((
+                request_routes.source.clone(),
+                request_routes.destination.clone(),
+                request_routes.capacity,
+            ))
+        } else {
+            None
+        };
+
+        // Serve from cache if possible, without contacting the index server:
+        if let Some(cache_key) = &opt_cache_key {
+            if let Some(multi_routes) = self.route_cache.get(cache_key) {
+                let mut multi_routes = multi_routes.clone();
+                self.route_scorer
+                    .sort_multi_routes(&mut multi_routes, request_routes.capacity);
+                self.route_scorer
+                    .add_pending(request_routes.request_id.clone(), &multi_routes);
+                let client_response_routes = ClientResponseRoutes {
+                    request_id: request_routes.request_id,
+                    result: ResponseRoutesResult::Success(multi_routes),
+                };
+                return await!(self
+                    .to_app_server
+                    .send(IndexClientToAppServer::ResponseRoutes(
+                        client_response_routes
+                    )))
+                .map_err(|_| IndexClientError::SendToAppServerFailed);
+            }
+        }
+
         if self.num_open_requests >= self.max_open_requests {
             return await!(self.return_response_routes_failure(request_routes.request_id));
         }
@@ -452,6 +710,7 @@ where
         };
 
         let c_request_id = request_routes.request_id;
+        let capacity = request_routes.capacity;
         let (response_sender, response_receiver) = oneshot::channel();
         let single_client_control =
             SingleClientControl::RequestRoutes((request_routes, response_sender));
@@ -461,6 +720,13 @@ where
             Err(_) => return await!(self.return_response_routes_failure(c_request_id)),
         };
 
+        let open_routes_request = OpenRoutesRequest {
+            capacity,
+            opt_cache_key,
+        };
+        self.open_routes_requests
+            .insert(c_request_id.clone(), open_routes_request);
+
         let mut c_event_sender = self.event_sender.clone();
         let request_fut = async move {
             let response_routes_result = match await!(response_receiver) {
@@ -480,6 +746,27 @@ where
             .map_err(|_| IndexClientError::SpawnError)
     }
 
+    pub async fn handle_from_app_server_report_route_outcome(
+        &mut self,
+        app_request_id: Uid,
+        request_id: Uid,
+        success: bool,
+    ) -> Result<(), IndexClientError> {
+        self.route_scorer.report_route_outcome(&request_id, success);
+
+        // Send empty report (Indicates that we received the request):
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: Some(app_request_id),
+            mutations: Vec::new(),
+        };
+        await!(self
+            .to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations
+            )))
+        .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
     pub async fn handle_from_app_server_apply_mutations(
         &mut self,
         mut mutations: Vec<IndexMutation>,
@@ -488,6 +775,20 @@ where
         for mutation in &mutations {
             await!(self.seq_friends_client.mutate(mutation.clone()))
                 .map_err(|_| IndexClientError::SeqFriendsError)?;
+
+            // Cached routes through a friend that went offline or was removed are not usable
+            // anymore:
+            match mutation {
+                IndexMutation::UpdateFriend(update_friend) => {
+                    if update_friend.send_capacity == 0 && update_friend.recv_capacity == 0 {
+                        self.route_cache
+                            .invalidate_friend(&update_friend.public_key);
+                    }
+                }
+                IndexMutation::RemoveFriend(public_key) => {
+                    self.route_cache.invalidate_friend(public_key)
+                }
+            }
         }
 
         // Check if server is ready:
@@ -534,10 +835,23 @@ where
                         await!(self
                             .handle_from_app_server_remove_index_server(app_request_id, public_key))
                     }
+                    IndexClientRequest::RotateIndexServer((old_public_key, new_server)) => {
+                        await!(self.handle_from_app_server_rotate_index_server(
+                            app_request_id,
+                            old_public_key,
+                            new_server
+                        ))
+                    }
                     IndexClientRequest::RequestRoutes(request_routes) => {
                         await!(self
                             .handle_from_app_server_request_routes(app_request_id, request_routes))
                     }
+                    IndexClientRequest::ReportRouteOutcome((request_id, success)) => await!(self
+                        .handle_from_app_server_report_route_outcome(
+                            app_request_id,
+                            request_id,
+                            success
+                        )),
                 }
             }
             AppServerToIndexClient::ApplyMutations(mutations) => {
@@ -573,11 +887,15 @@ where
         });
 
         // Send report:
-        let index_client_report_mutation =
-            IndexClientReportMutation::SetConnectedServer(Some(index_server.public_key.clone()));
+        let mut mutations = vec![IndexClientReportMutation::SetConnectedServer(Some(
+            index_server.public_key.clone(),
+        ))];
+        if let Some(mutation) = self.record_connection_outcome(&index_server.public_key, true) {
+            mutations.push(mutation);
+        }
         let index_client_report_mutations = IndexClientReportMutations {
             opt_app_request_id: None,
-            mutations: vec![index_client_report_mutation],
+            mutations,
         };
         await!(self
             .to_app_server
@@ -590,12 +908,33 @@ where
     }
 
     pub async fn handle_index_server_closed(&mut self) -> Result<(), IndexClientError> {
-        if let ConnStatus::Connected(_) = self.conn_status {
+        let mut mutations = Vec::new();
+        match &self.conn_status {
+            ConnStatus::Empty(_) => {}
+            ConnStatus::Connecting(server_connecting) => {
+                // We failed to connect to the server.
+                // Note that we don't keep track of servers that were removed:
+                let public_key = server_connecting.index_server.public_key.clone();
+                if self
+                    .index_servers
+                    .iter()
+                    .any(|index_server| index_server.public_key == public_key)
+                {
+                    if let Some(mutation) = self.record_connection_outcome(&public_key, false) {
+                        mutations.push(mutation);
+                    }
+                }
+            }
+            ConnStatus::Connected(_) => {
+                mutations.push(IndexClientReportMutation::SetConnectedServer(None))
+            }
+        }
+
+        if !mutations.is_empty() {
             // Send report:
-            let index_client_report_mutation = IndexClientReportMutation::SetConnectedServer(None);
             let index_client_report_mutations = IndexClientReportMutations {
                 opt_app_request_id: None,
-                mutations: vec![index_client_report_mutation],
+                mutations,
             };
             await!(self
                 .to_app_server
@@ -611,10 +950,27 @@ where
     pub async fn handle_response_routes(
         &mut self,
         request_id: Uid,
-        response_routes_result: ResponseRoutesResult,
+        mut response_routes_result: ResponseRoutesResult,
     ) -> Result<(), IndexClientError> {
         self.num_open_requests = self.num_open_requests.checked_sub(1).unwrap();
 
+        if let Some(open_routes_request) = self.open_routes_requests.remove(&request_id) {
+            if let ResponseRoutesResult::Success(multi_routes) = &mut response_routes_result {
+                // Best routes first:
+                self.route_scorer
+                    .sort_multi_routes(multi_routes, open_routes_request.capacity);
+                self.route_scorer
+                    .add_pending(request_id.clone(), multi_routes);
+
+                // We don't cache empty results, as routes might show up soon:
+                if let Some(cache_key) = open_routes_request.opt_cache_key {
+                    if !multi_routes.is_empty() {
+                        self.route_cache.insert(cache_key, multi_routes.clone());
+                    }
+                }
+            }
+        }
+
         let client_response_routes = ClientResponseRoutes {
             request_id,
             result: response_routes_result,
@@ -629,6 +985,34 @@ where
     }
 
     pub async fn handle_timer_tick(&mut self) -> Result<(), IndexClientError> {
+        self.route_cache.tick();
+
+        // Let skipped servers be tried again after some time:
+        let mut mutations = Vec::new();
+        for (public_key, circuit_breaker) in &mut self.circuit_breakers {
+            let old_circuit_state = circuit_breaker.report();
+            circuit_breaker.tick();
+            let new_circuit_state = circuit_breaker.report();
+            if old_circuit_state != new_circuit_state {
+                mutations.push(IndexClientReportMutation::SetCircuitState((
+                    public_key.clone(),
+                    new_circuit_state,
+                )));
+            }
+        }
+        if !mutations.is_empty() {
+            let index_client_report_mutations = IndexClientReportMutations {
+                opt_app_request_id: None,
+                mutations,
+            };
+            await!(self
+                .to_app_server
+                .send(IndexClientToAppServer::ReportMutations(
+                    index_client_report_mutations
+                )))
+            .map_err(|_| IndexClientError::SendToAppServerFailed)?;
+        }
+
         // Make sure that we are connected to any server:
         let server_connected: &mut ServerConnected<ISA> = match self.conn_status {
             ConnStatus::Empty(ref mut ticks_to_reconnect) => {
@@ -692,6 +1076,8 @@ pub async fn index_client_loop<ISA, FAS, TAS, ICS, TS, S>(
     max_open_requests: usize,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    failure_threshold: usize,
+    open_duration_ticks: usize,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
     timer_stream: TS,
     spawner: S,
@@ -717,6 +1103,8 @@ where
         max_open_requests,
         keepalive_ticks,
         backoff_ticks,
+        failure_threshold,
+        open_duration_ticks,
         db_client,
         spawner,
     );
@@ -21,8 +21,11 @@ extern crate serde_derive;
 #[macro_use]
 extern crate common;
 
+mod circuit_breaker;
 mod client_session;
 mod index_client;
+mod route_cache;
+mod route_scorer;
 mod seq_friends;
 mod seq_map;
 mod single_client;
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use common::int_convert::usize_to_u64;
+
+use crypto::identity::PublicKey;
+
+use proto::index_server::messages::MultiRoute;
+
+/// (source_public_key, dest_public_key, min_capacity)
+pub type RouteCacheKey = (PublicKey, PublicKey, u128);
+
+/// Remembers recently received routes, to avoid asking the index server for the same routes
+/// again and again.
+pub struct RouteCache {
+    ttl_ticks: u64,
+    cur_tick: u64,
+    /// Cached routes, together with the tick in which they expire.
+    entries: HashMap<RouteCacheKey, (Vec<MultiRoute>, u64)>,
+}
+
+impl RouteCache {
+    /// Create a new RouteCache. Entries will be kept for `ttl_ticks` ticks.
+    /// A `ttl_ticks` of 0 disables caching.
+    pub fn new(ttl_ticks: usize) -> Self {
+        RouteCache {
+            ttl_ticks: usize_to_u64(ttl_ticks).unwrap(),
+            cur_tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &RouteCacheKey) -> Option<&Vec<MultiRoute>> {
+        match self.entries.get(key) {
+            Some((multi_routes, expires_at_tick)) if *expires_at_tick > self.cur_tick => {
+                Some(multi_routes)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, key: RouteCacheKey, multi_routes: Vec<MultiRoute>) {
+        if self.ttl_ticks == 0 {
+            return;
+        }
+        let expires_at_tick = self.cur_tick.saturating_add(self.ttl_ticks);
+        self.entries.insert(key, (multi_routes, expires_at_tick));
+    }
+
+    /// Remove all the cached entries that contain a route going through `public_key`.
+    pub fn invalidate_friend(&mut self, public_key: &PublicKey) {
+        self.entries
+            .retain(|_key, (multi_routes, _expires_at_tick)| {
+                !multi_routes.iter().any(|multi_route| {
+                    multi_route.routes.iter().any(|route_capacity_rate| {
+                        route_capacity_rate.route.pk_to_index(public_key).is_some()
+                    })
+                })
+            });
+    }
+
+    /// Advance time by one tick, evicting expired entries.
+    pub fn tick(&mut self) {
+        self.cur_tick = self.cur_tick.saturating_add(1);
+        let cur_tick = self.cur_tick;
+        self.entries
+            .retain(|_key, (_multi_routes, expires_at_tick)| *expires_at_tick > cur_tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    use proto::funder::messages::{FriendsRoute, Rate};
+    use proto::index_server::messages::RouteCapacityRate;
+
+    fn dummy_multi_route(public_keys: Vec<PublicKey>) -> MultiRoute {
+        MultiRoute {
+            routes: vec![RouteCapacityRate {
+                route: FriendsRoute { public_keys },
+                capacity: 100,
+                rate: Rate { mul: 0, add: 1 },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_route_cache_expiry() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let key = (pk_a.clone(), pk_b.clone(), 20);
+
+        let mut route_cache = RouteCache::new(2);
+        assert!(route_cache.get(&key).is_none());
+
+        let multi_routes = vec![dummy_multi_route(vec![pk_a.clone(), pk_b.clone()])];
+        route_cache.insert(key.clone(), multi_routes.clone());
+        assert_eq!(route_cache.get(&key), Some(&multi_routes));
+
+        // A different capacity is a different entry:
+        assert!(route_cache.get(&(pk_a, pk_b, 21)).is_none());
+
+        route_cache.tick();
+        assert_eq!(route_cache.get(&key), Some(&multi_routes));
+        route_cache.tick();
+        assert!(route_cache.get(&key).is_none());
+    }
+
+    #[test]
+    fn test_route_cache_invalidate_friend() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let pk_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+
+        let mut route_cache = RouteCache::new(8);
+        let key_b = (pk_a.clone(), pk_b.clone(), 20);
+        let key_d = (pk_a.clone(), pk_d.clone(), 20);
+        route_cache.insert(
+            key_b.clone(),
+            vec![dummy_multi_route(vec![
+                pk_a.clone(),
+                pk_c.clone(),
+                pk_b.clone(),
+            ])],
+        );
+        route_cache.insert(
+            key_d.clone(),
+            vec![dummy_multi_route(vec![pk_a.clone(), pk_d.clone()])],
+        );
+
+        route_cache.invalidate_friend(&pk_c);
+        assert!(route_cache.get(&key_b).is_none());
+        assert!(route_cache.get(&key_d).is_some());
+    }
+
+    #[test]
+    fn test_route_cache_disabled() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let key = (pk_a.clone(), pk_b.clone(), 20);
+
+        let mut route_cache = RouteCache::new(0);
+        route_cache.insert(key.clone(), vec![dummy_multi_route(vec![pk_a, pk_b])]);
+        assert!(route_cache.get(&key).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+
+use crypto::hash::HashResult;
+use crypto::uid::Uid;
+
+use proto::index_server::messages::{MultiRoute, RouteCapacityRate};
+
+/// Maximum amount of served route requests we remember while waiting for their outcome.
+const MAX_PENDING_OUTCOMES: usize = 0x100;
+
+/// Every route starts with this amount of imaginary successes, so that a single failure does not
+/// rule a route out forever.
+const PRIOR_SUCCESSES: u64 = 1;
+
+/// Once a route has this many recorded outcomes, its history is halved. This lets old outcomes
+/// fade away, so that a route that was down for a while can recover its score.
+const MAX_ROUTE_OUTCOMES: u64 = 0x20;
+
+#[derive(Debug, Default, Clone)]
+struct RouteHistory {
+    successes: u64,
+    failures: u64,
+}
+
+/// Score of a route. Higher is better.
+/// Routes are compared first by their historical success rate, then by the fees paid along the
+/// route and finally by the route length.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RouteScore {
+    /// (successes + PRIOR_SUCCESSES) / (successes + failures + PRIOR_SUCCESSES), in permille.
+    /// A route we have never used is assumed to always succeed.
+    success_permille: u64,
+    fees: Reverse<u128>,
+    len: Reverse<usize>,
+}
+
+/// Ranks routes returned from the index server, learning from the outcome of payments
+/// sent along previously returned routes.
+pub struct RouteScorer {
+    history: HashMap<HashResult, RouteHistory>,
+    /// Hashes of the routes we handed out, by the request_id of the routes request.
+    pending: HashMap<Uid, Vec<HashResult>>,
+    /// Order of insertion into `pending`, used to forget old requests.
+    pending_queue: VecDeque<Uid>,
+}
+
+impl RouteScorer {
+    pub fn new() -> Self {
+        RouteScorer {
+            history: HashMap::new(),
+            pending: HashMap::new(),
+            pending_queue: VecDeque::new(),
+        }
+    }
+
+    /// Score a route, given that we want to pass `capacity` credits along it.
+    pub fn score(&self, route_capacity_rate: &RouteCapacityRate, capacity: u128) -> RouteScore {
+        let route_history = self
+            .history
+            .get(&route_capacity_rate.route.hash())
+            .cloned()
+            .unwrap_or_default();
+        let successes = route_history.successes + PRIOR_SUCCESSES;
+        let success_permille =
+            successes.saturating_mul(1000) / (successes + route_history.failures);
+        let fees = route_capacity_rate
+            .rate
+            .calc_fee(capacity)
+            .unwrap_or(u128::max_value());
+
+        RouteScore {
+            success_permille,
+            fees: Reverse(fees),
+            len: Reverse(route_capacity_rate.route.len()),
+        }
+    }
+
+    /// A multi route is only as good as its worst route.
+    fn score_multi_route(&self, multi_route: &MultiRoute, capacity: u128) -> Option<RouteScore> {
+        multi_route
+            .routes
+            .iter()
+            .map(|route_capacity_rate| self.score(route_capacity_rate, capacity))
+            .min()
+    }
+
+    /// Sort routes from best to worst.
+    pub fn sort_multi_routes(&self, multi_routes: &mut Vec<MultiRoute>, capacity: u128) {
+        for multi_route in multi_routes.iter_mut() {
+            multi_route.routes.sort_by_key(|route_capacity_rate| {
+                Reverse(self.score(route_capacity_rate, capacity))
+            });
+        }
+        multi_routes
+            .sort_by_key(|multi_route| Reverse(self.score_multi_route(multi_route, capacity)));
+    }
+
+    /// Remember the routes handed out for the routes request `request_id`.
+    /// The outcome of the request is attributed to the routes of the best (first) multi route.
+    pub fn add_pending(&mut self, request_id: Uid, multi_routes: &[MultiRoute]) {
+        let multi_route = match multi_routes.first() {
+            Some(multi_route) => multi_route,
+            None => return,
+        };
+        let route_hashes = multi_route
+            .routes
+            .iter()
+            .map(|route_capacity_rate| route_capacity_rate.route.hash())
+            .collect();
+
+        if self
+            .pending
+            .insert(request_id.clone(), route_hashes)
+            .is_none()
+        {
+            self.pending_queue.push_back(request_id);
+        }
+        while self.pending_queue.len() > MAX_PENDING_OUTCOMES {
+            if let Some(old_request_id) = self.pending_queue.pop_front() {
+                self.pending.remove(&old_request_id);
+            }
+        }
+    }
+
+    /// Report whether a payment along the routes returned for `request_id` succeeded.
+    pub fn report_route_outcome(&mut self, request_id: &Uid, success: bool) {
+        let route_hashes = match self.pending.remove(request_id) {
+            Some(route_hashes) => route_hashes,
+            None => return,
+        };
+        self.pending_queue
+            .retain(|pending_request_id| pending_request_id != request_id);
+
+        for route_hash in route_hashes {
+            let route_history = self.history.entry(route_hash).or_default();
+            if success {
+                route_history.successes = route_history.successes.saturating_add(1);
+            } else {
+                route_history.failures = route_history.failures.saturating_add(1);
+            }
+            if route_history.successes + route_history.failures >= MAX_ROUTE_OUTCOMES {
+                route_history.successes /= 2;
+                route_history.failures /= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::uid::UID_LEN;
+
+    use proto::funder::messages::{FriendsRoute, Rate};
+
+    fn route(pks: &[u8], add: u32) -> RouteCapacityRate {
+        RouteCapacityRate {
+            route: FriendsRoute {
+                public_keys: pks
+                    .iter()
+                    .map(|&pk| PublicKey::from(&[pk; PUBLIC_KEY_LEN]))
+                    .collect(),
+            },
+            capacity: 100,
+            rate: Rate { mul: 0, add },
+        }
+    }
+
+    fn multi_route(route_capacity_rate: RouteCapacityRate) -> MultiRoute {
+        MultiRoute {
+            routes: vec![route_capacity_rate],
+        }
+    }
+
+    #[test]
+    fn test_route_scorer_fees_and_length() {
+        let route_scorer = RouteScorer::new();
+        let mut multi_routes = vec![
+            multi_route(route(&[0, 1, 2, 3], 1)),
+            multi_route(route(&[0, 4, 3], 2)),
+            multi_route(route(&[0, 5, 3], 1)),
+        ];
+        route_scorer.sort_multi_routes(&mut multi_routes, 10);
+
+        // Lower fees first, then shorter routes:
+        assert_eq!(multi_routes[0].routes[0], route(&[0, 5, 3], 1));
+        assert_eq!(multi_routes[1].routes[0], route(&[0, 1, 2, 3], 1));
+        assert_eq!(multi_routes[2].routes[0], route(&[0, 4, 3], 2));
+    }
+
+    #[test]
+    fn test_route_scorer_outcomes() {
+        let mut route_scorer = RouteScorer::new();
+        let request_id = Uid::from(&[1; UID_LEN]);
+
+        let mut multi_routes = vec![
+            multi_route(route(&[0, 5, 3], 1)),
+            multi_route(route(&[0, 4, 3], 2)),
+        ];
+        route_scorer.sort_multi_routes(&mut multi_routes, 10);
+        route_scorer.add_pending(request_id.clone(), &multi_routes);
+
+        // The cheapest route failed:
+        route_scorer.report_route_outcome(&request_id, false);
+        // Reporting twice has no effect:
+        route_scorer.report_route_outcome(&request_id, false);
+
+        route_scorer.sort_multi_routes(&mut multi_routes, 10);
+        assert_eq!(multi_routes[0].routes[0], route(&[0, 4, 3], 2));
+        assert_eq!(multi_routes[1].routes[0], route(&[0, 5, 3], 1));
+
+        let score = route_scorer.score(&route(&[0, 5, 3], 1), 10);
+        assert_eq!(score.success_permille, 500);
+        let score = route_scorer.score(&route(&[0, 4, 3], 2), 10);
+        assert_eq!(score.success_permille, 1000);
+    }
+
+    #[test]
+    fn test_route_scorer_recovery() {
+        let mut route_scorer = RouteScorer::new();
+        let multi_routes = vec![multi_route(route(&[0, 5, 3], 1))];
+
+        let mut report = |request_byte: u8, success: bool| {
+            let request_id = Uid::from(&[request_byte; UID_LEN]);
+            route_scorer.add_pending(request_id.clone(), &multi_routes);
+            route_scorer.report_route_outcome(&request_id, success);
+        };
+
+        // A long run of failures:
+        for i in 0..100u8 {
+            report(i, false);
+        }
+        // Followed by a shorter run of successes:
+        for i in 100..120u8 {
+            report(i, true);
+        }
+
+        // Old failures have faded away, the route is considered mostly good again:
+        let score = route_scorer.score(&route(&[0, 5, 3], 1), 10);
+        assert!(score.success_permille > 500);
+
+        let route_history = &route_scorer.history[&route(&[0, 5, 3], 1).route.hash()];
+        assert!(route_history.successes + route_history.failures < MAX_ROUTE_OUTCOMES);
+    }
+}
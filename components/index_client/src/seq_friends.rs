@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnError, SpawnExt};
 use futures::{SinkExt, StreamExt};
@@ -5,9 +7,74 @@ use futures::{SinkExt, StreamExt};
 use crypto::identity::PublicKey;
 use proto::index_client::messages::{FriendInfo, IndexMutation, UpdateFriend};
 
-use crate::seq_map::SeqMap;
+use crate::seq_map::{BoundedSeqMap, SeqMap};
+
+/// Friends of the local node, iterated in a consistent manner. Optionally bounded: If too many
+/// friends are known, the least recently updated friends are evicted.
+pub enum SeqFriends {
+    Unbounded(SeqMap<PublicKey, FriendInfo>),
+    Bounded(BoundedSeqMap<PublicKey, FriendInfo>),
+}
+
+impl SeqFriends {
+    pub fn new(
+        friends: HashMap<PublicKey, FriendInfo>,
+        opt_max_seq_friends: Option<usize>,
+    ) -> Self {
+        match opt_max_seq_friends {
+            Some(max_seq_friends) => {
+                if friends.len() > max_seq_friends {
+                    warn!(
+                        "SeqFriends: {} friends exceed max_seq_friends ({}). {} friends will not be sent to index servers.",
+                        friends.len(),
+                        max_seq_friends,
+                        friends.len() - max_seq_friends
+                    );
+                }
+                SeqFriends::Bounded(BoundedSeqMap::new(friends, max_seq_friends))
+            }
+            None => SeqFriends::Unbounded(SeqMap::new(friends)),
+        }
+    }
+
+    fn update(&mut self, public_key: PublicKey, friend_info: FriendInfo) -> Option<FriendInfo> {
+        match self {
+            SeqFriends::Unbounded(seq_map) => seq_map.update(public_key, friend_info),
+            SeqFriends::Bounded(bounded_seq_map) => {
+                let (opt_old_friend_info, opt_evicted) =
+                    bounded_seq_map.update(public_key, friend_info);
+                if let Some(evicted_public_key) = opt_evicted {
+                    warn!(
+                        "SeqFriends: max_seq_friends reached. Friend {} is no longer sent to index servers.",
+                        evicted_public_key.to_hex()
+                    );
+                }
+                opt_old_friend_info
+            }
+        }
+    }
+
+    fn remove(&mut self, public_key: &PublicKey) -> Option<FriendInfo> {
+        match self {
+            SeqFriends::Unbounded(seq_map) => seq_map.remove(public_key),
+            SeqFriends::Bounded(bounded_seq_map) => bounded_seq_map.remove(public_key),
+        }
+    }
+
+    fn reset_countdown(&mut self) {
+        match self {
+            SeqFriends::Unbounded(seq_map) => seq_map.reset_countdown(),
+            SeqFriends::Bounded(bounded_seq_map) => bounded_seq_map.reset_countdown(),
+        }
+    }
 
-pub type SeqFriends = SeqMap<PublicKey, FriendInfo>;
+    fn next(&mut self) -> Option<(usize, (PublicKey, FriendInfo))> {
+        match self {
+            SeqFriends::Unbounded(seq_map) => seq_map.next(),
+            SeqFriends::Bounded(bounded_seq_map) => bounded_seq_map.next(),
+        }
+    }
+}
 
 pub enum SeqFriendsRequest {
     Mutate(IndexMutation, oneshot::Sender<()>),
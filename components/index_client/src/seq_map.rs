@@ -1,4 +1,5 @@
 use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
 
 /// A basic map that allows to iterate over all its pairs in a consistent manner.
 /// This means that calling SeqMap::next() should iterate over all elements of the map, even if
@@ -71,6 +72,177 @@ where
     }
 }
 
+struct LruNode<K> {
+    key: K,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Keeps track of the order in which keys were used.
+/// Nodes are kept in a Vec and linked to each other using indices, so that all operations are
+/// O(1).
+struct LruList<K> {
+    indices: HashMap<K, usize>,
+    nodes: Vec<LruNode<K>>,
+    /// Indices of unused nodes, to be reused:
+    free_indices: Vec<usize>,
+    /// The most recently used node:
+    head: Option<usize>,
+    /// The least recently used node:
+    tail: Option<usize>,
+}
+
+impl<K> LruList<K>
+where
+    K: Hash + Eq + Clone,
+{
+    fn new() -> Self {
+        LruList {
+            indices: HashMap::new(),
+            nodes: Vec::new(),
+            free_indices: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn contains(&self, key: &K) -> bool {
+        self.indices.contains_key(key)
+    }
+
+    fn unlink(&mut self, index: usize) {
+        let prev = self.nodes[index].prev;
+        let next = self.nodes[index].next;
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, index: usize) {
+        self.nodes[index].prev = None;
+        self.nodes[index].next = self.head;
+        match self.head {
+            Some(head) => self.nodes[head].prev = Some(index),
+            None => self.tail = Some(index),
+        }
+        self.head = Some(index);
+    }
+
+    /// Mark `key` as the most recently used key. Adds `key` if it does not exist.
+    fn touch(&mut self, key: K) {
+        if let Some(&index) = self.indices.get(&key) {
+            self.unlink(index);
+            self.push_front(index);
+            return;
+        }
+
+        let node = LruNode {
+            key: key.clone(),
+            prev: None,
+            next: None,
+        };
+        let index = match self.free_indices.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.indices.insert(key, index);
+        self.push_front(index);
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(index) = self.indices.remove(key) {
+            self.unlink(index);
+            self.free_indices.push(index);
+        }
+    }
+
+    /// Remove the least recently used key.
+    fn pop_lru(&mut self) -> Option<K> {
+        let key = self.nodes[self.tail?].key.clone();
+        self.remove(&key);
+        Some(key)
+    }
+}
+
+/// A SeqMap that holds at most `capacity` pairs.
+/// Updating a new key when the map is full evicts the least recently updated key.
+pub struct BoundedSeqMap<K, V> {
+    seq_map: SeqMap<K, V>,
+    lru_list: LruList<K>,
+    capacity: usize,
+}
+
+impl<K, V> BoundedSeqMap<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    /// Create a new BoundedSeqMap. If `map` contains more than `capacity` pairs, some of them
+    /// are evicted.
+    pub fn new(map: HashMap<K, V>, capacity: usize) -> Self {
+        let mut bounded_seq_map = BoundedSeqMap {
+            seq_map: SeqMap::new(HashMap::new()),
+            lru_list: LruList::new(),
+            capacity,
+        };
+        for (key, value) in map {
+            let _ = bounded_seq_map.update(key, value);
+        }
+        bounded_seq_map.reset_countdown();
+        bounded_seq_map
+    }
+
+    /// Insert or update a pair.
+    /// Returns the previous value of `key` (If any), and the key that was evicted to make room
+    /// for it (If any). With a capacity of 0, `key` itself is never stored, and is returned as
+    /// evicted.
+    pub fn update(&mut self, key: K, value: V) -> (Option<V>, Option<K>) {
+        let mut opt_evicted = None;
+        if !self.lru_list.contains(&key) {
+            if self.capacity == 0 {
+                return (None, Some(key));
+            }
+            if self.lru_list.len() >= self.capacity {
+                if let Some(lru_key) = self.lru_list.pop_lru() {
+                    let _ = self.seq_map.remove(&lru_key);
+                    opt_evicted = Some(lru_key);
+                }
+            }
+        }
+        self.lru_list.touch(key.clone());
+        (self.seq_map.update(key, value), opt_evicted)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.lru_list.remove(key);
+        self.seq_map.remove(key)
+    }
+
+    pub fn reset_countdown(&mut self) {
+        self.seq_map.reset_countdown()
+    }
+
+    /// See SeqMap::next()
+    pub fn next(&mut self) -> Option<(usize, (K, V))> {
+        self.seq_map.next()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -185,4 +357,74 @@ mod tests {
             assert_eq!(countdown, 0);
         }
     }
+
+    /// Util test function to view the interval state of BoundedSeqMap
+    fn bounded_seq_map_keys<K, V>(bounded_seq_map: &mut BoundedSeqMap<K, V>) -> Vec<K>
+    where
+        K: std::hash::Hash + std::cmp::Eq + Clone + std::cmp::Ord,
+        V: Clone,
+    {
+        bounded_seq_map.reset_countdown();
+
+        let mut keys = Vec::new();
+        while let Some((countdown, (key, _value))) = bounded_seq_map.next() {
+            keys.push(key);
+            if countdown == 0 {
+                break;
+            }
+        }
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_bounded_seq_map_evict_lru() {
+        let mut bounded_seq_map = BoundedSeqMap::new(HashMap::new(), 3);
+        bounded_seq_map.update(0u32, 4u64);
+        bounded_seq_map.update(1u32, 5u64);
+        bounded_seq_map.update(2u32, 6u64);
+        assert_eq!(bounded_seq_map_keys(&mut bounded_seq_map), vec![0, 1, 2]);
+
+        // 0 is the least recently updated:
+        assert_eq!(bounded_seq_map.update(3u32, 7u64), (None, Some(0u32)));
+        assert_eq!(bounded_seq_map_keys(&mut bounded_seq_map), vec![1, 2, 3]);
+
+        // Updating 1 makes 2 the least recently updated.
+        // Iterating using next() does not count as a use:
+        assert_eq!(bounded_seq_map.update(1u32, 8u64), (Some(5u64), None));
+        bounded_seq_map.update(4u32, 9u64);
+        assert_eq!(bounded_seq_map_keys(&mut bounded_seq_map), vec![1, 3, 4]);
+
+        // Removing frees a place:
+        assert_eq!(bounded_seq_map.remove(&3u32), Some(7u64));
+        bounded_seq_map.update(5u32, 10u64);
+        assert_eq!(bounded_seq_map_keys(&mut bounded_seq_map), vec![1, 4, 5]);
+
+        // 1 is the least recently updated:
+        bounded_seq_map.update(6u32, 11u64);
+        assert_eq!(bounded_seq_map_keys(&mut bounded_seq_map), vec![4, 5, 6]);
+    }
+
+    #[test]
+    fn test_bounded_seq_map_new_over_capacity() {
+        let mut hash_map = HashMap::new();
+        for i in 0..8u32 {
+            hash_map.insert(i, u64::from(i));
+        }
+
+        let mut bounded_seq_map = BoundedSeqMap::new(hash_map, 5);
+
+        // The countdown covers all the pairs kept:
+        let (countdown, _pair) = bounded_seq_map.next().unwrap();
+        assert_eq!(countdown, 4);
+
+        assert_eq!(bounded_seq_map_keys(&mut bounded_seq_map).len(), 5);
+    }
+
+    #[test]
+    fn test_bounded_seq_map_zero_capacity() {
+        let mut bounded_seq_map = BoundedSeqMap::new(HashMap::new(), 0);
+        assert_eq!(bounded_seq_map.update(0u32, 4u64), (None, Some(0u32)));
+        assert!(bounded_seq_map.next().is_none());
+    }
 }
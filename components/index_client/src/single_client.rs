@@ -98,16 +98,16 @@ where
                     Some(request_sender) => request_sender,
                     None => {
                         warn!(
-                            "Received a response for unrecognized request_id: {:?}",
-                            &request_id
+                            "Received a response for unrecognized request_id: {}",
+                            request_id.to_hex()
                         );
                         return Ok(());
                     }
                 };
                 if request_sender.send(multi_routes).is_err() {
                     warn!(
-                        "Failed to return response for request_id: {:?} ",
-                        &request_id
+                        "Failed to return response for request_id: {} ",
+                        request_id.to_hex()
                     );
                 }
             }
@@ -12,6 +12,7 @@ use timer::TimerClient;
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
 
+use proto::consts::{INDEX_SERVER_FAILURE_THRESHOLD, INDEX_SERVER_OPEN_CIRCUIT_TICKS};
 use proto::index_client::messages::{
     AppServerToIndexClient, IndexClientState, IndexClientToAppServer,
 };
@@ -25,8 +26,7 @@ use crate::client_session::IndexClientSession;
 use crate::index_client::{
     index_client_loop, IndexClientConfig, IndexClientConfigMutation, IndexClientError,
 };
-use crate::seq_friends::create_seq_friends_service;
-use crate::seq_map::SeqMap;
+use crate::seq_friends::{create_seq_friends_service, SeqFriends};
 use crate::single_client::ServerConn;
 
 #[derive(Clone)]
@@ -137,7 +137,10 @@ where
     let timer_stream = await!(timer_client.request_timer_stream())
         .map_err(|_| SpawnIndexClientError::RequestTimerStreamError)?;
 
-    let seq_friends = SeqMap::new(index_client_state.friends);
+    let seq_friends = SeqFriends::new(
+        index_client_state.friends,
+        index_client_config.max_seq_friends,
+    );
     let seq_friends_client = create_seq_friends_service(seq_friends, spawner.clone())
         .map_err(|_| SpawnIndexClientError::SpawnError)?;
 
@@ -160,6 +163,8 @@ where
         max_open_index_client_requests,
         keepalive_ticks,
         backoff_ticks,
+        INDEX_SERVER_FAILURE_THRESHOLD,
+        INDEX_SERVER_OPEN_CIRCUIT_TICKS,
         database_client,
         timer_stream,
         spawner.clone(),
@@ -4,15 +4,18 @@ use futures::task::{Spawn, SpawnExt};
 use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
 use common::dummy_connector::{ConnRequest, DummyConnector};
+use common::mutable_state::MutableState;
 
 use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
 use crypto::uid::{Uid, UID_LEN};
-use proto::funder::messages::Rate;
+use proto::funder::messages::{FriendsRoute, Rate};
 use proto::index_client::messages::{
-    AppServerToIndexClient, IndexClientReportMutation, IndexClientRequest, IndexClientToAppServer,
-    IndexMutation, RequestRoutes, ResponseRoutesResult, UpdateFriend,
+    AppServerToIndexClient, CircuitStateReport, IndexClientReportMutation, IndexClientRequest,
+    IndexClientToAppServer, IndexMutation, RequestRoutes, ResponseRoutesResult, UpdateFriend,
+};
+use proto::index_server::messages::{
+    IndexServerAddress, MultiRoute, NamedIndexServerAddress, RouteCapacityRate,
 };
-use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
 
 use database::{DatabaseClient, DatabaseRequest};
 
@@ -35,6 +38,7 @@ struct IndexClientControl<ISA> {
     #[allow(unused)]
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    open_duration_ticks: usize,
 }
 
 /// Create a basic IndexClientControl, used for testing
@@ -52,6 +56,8 @@ where
     };
     let index_client_config = IndexClientConfig {
         index_servers: vec![index_server37],
+        cache_ttl_ticks: 8,
+        max_seq_friends: None,
     };
 
     let (seq_friends_sender, seq_friends_receiver) = mpsc::channel(0);
@@ -66,6 +72,8 @@ where
     let max_open_requests = 2;
     let keepalive_ticks = 8;
     let backoff_ticks = 4;
+    let failure_threshold = 1;
+    let open_duration_ticks = 8;
 
     let (tick_sender, timer_stream) = mpsc::channel::<()>(0);
 
@@ -78,6 +86,8 @@ where
         max_open_requests,
         keepalive_ticks,
         backoff_ticks,
+        failure_threshold,
+        open_duration_ticks,
         db_client,
         timer_stream,
         spawner.clone(),
@@ -97,6 +107,7 @@ where
         max_open_requests,
         keepalive_ticks,
         backoff_ticks,
+        open_duration_ticks,
     }
 }
 
@@ -444,6 +455,212 @@ fn test_index_client_loop_request_routes_basic() {
     ));
 }
 
+async fn task_index_client_loop_request_routes_cached<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    let (mut control_receiver, _close_sender) = await!(icc.expect_server_connection(index_server));
+
+    let pk_ee = PublicKey::from(&[0xee; PUBLIC_KEY_LEN]);
+    let pk_ff = PublicKey::from(&[0xff; PUBLIC_KEY_LEN]);
+    let multi_routes = vec![MultiRoute {
+        routes: vec![RouteCapacityRate {
+            route: FriendsRoute {
+                public_keys: vec![pk_ee.clone(), pk_ff.clone()],
+            },
+            capacity: 300,
+            rate: Rate { mul: 0, add: 1 },
+        }],
+    }];
+
+    for i in 0..2u8 {
+        let request_routes = RequestRoutes {
+            request_id: Uid::from(&[i; UID_LEN]),
+            capacity: 250,
+            source: pk_ee.clone(),
+            destination: pk_ff.clone(),
+            opt_exclude: None,
+        };
+        let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+            Uid::from(&[50 + i; UID_LEN]),
+            IndexClientRequest::RequestRoutes(request_routes.clone()),
+        ));
+        await!(icc.app_server_sender.send(app_server_to_index_client)).unwrap();
+
+        // Only the first request is forwarded to the server.
+        // The second one is served from the cache:
+        if i == 0 {
+            match await!(control_receiver.next()).unwrap() {
+                SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+                    assert_eq!(request_routes0, request_routes);
+                    response_sender.send(multi_routes.clone()).unwrap();
+                }
+                _ => unreachable!(),
+            };
+        }
+
+        // Expect empty report mutations:
+        match await!(icc.app_server_receiver.next()).unwrap() {
+            IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+                assert_eq!(
+                    ic_report_mutations.opt_app_request_id,
+                    Some(Uid::from(&[50 + i; UID_LEN]))
+                );
+                assert!(ic_report_mutations.mutations.is_empty());
+            }
+            _ => unreachable!(),
+        };
+
+        match await!(icc.app_server_receiver.next()).unwrap() {
+            IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
+                assert_eq!(client_response_routes.request_id, Uid::from(&[i; UID_LEN]));
+                assert_eq!(
+                    client_response_routes.result,
+                    ResponseRoutesResult::Success(multi_routes.clone())
+                );
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    // Friend 0xff goes offline. The cached routes are not usable anymore:
+    let update_friend = UpdateFriend {
+        public_key: pk_ff.clone(),
+        send_capacity: 0,
+        recv_capacity: 0,
+        rate: Rate { mul: 0, add: 1 },
+    };
+    let mutations = vec![IndexMutation::UpdateFriend(update_friend)];
+    await!(icc
+        .app_server_sender
+        .send(AppServerToIndexClient::ApplyMutations(mutations)))
+    .unwrap();
+
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::Mutate(_index_mutation, response_sender) => {
+            response_sender.send(()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::NextUpdate(response_sender) => response_sender.send(None).unwrap(),
+        _ => unreachable!(),
+    };
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::SendMutations(_) => {}
+        _ => unreachable!(),
+    };
+
+    let request_routes = RequestRoutes {
+        request_id: Uid::from(&[2; UID_LEN]),
+        capacity: 250,
+        source: pk_ee.clone(),
+        destination: pk_ff.clone(),
+        opt_exclude: None,
+    };
+    let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+        Uid::from(&[52; UID_LEN]),
+        IndexClientRequest::RequestRoutes(request_routes.clone()),
+    ));
+    await!(icc.app_server_sender.send(app_server_to_index_client)).unwrap();
+
+    // The request is forwarded to the server again:
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, _response_sender)) => {
+            assert_eq!(request_routes0, request_routes);
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_request_routes_cached() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_request_routes_cached(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_index_client_loop_circuit_breaker<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+
+    // Connecting to the server fails:
+    let session_conn_request = await!(icc.session_receiver.next()).unwrap();
+    assert_eq!(session_conn_request.address, index_server);
+    session_conn_request.reply(None);
+
+    // The server is skipped from now on:
+    let expected_mutation = IndexClientReportMutation::SetCircuitState((
+        index_server.public_key.clone(),
+        CircuitStateReport::Open,
+    ));
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(ic_report_mutations.opt_app_request_id, None);
+            assert_eq!(ic_report_mutations.mutations, vec![expected_mutation]);
+        }
+        _ => unreachable!(),
+    };
+
+    // After open_duration_ticks the server is tried again:
+    for _ in 0..icc.open_duration_ticks {
+        await!(icc.tick_sender.send(())).unwrap();
+    }
+    let expected_mutation = IndexClientReportMutation::SetCircuitState((
+        index_server.public_key.clone(),
+        CircuitStateReport::HalfOpen,
+    ));
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(ic_report_mutations.mutations, vec![expected_mutation]);
+        }
+        _ => unreachable!(),
+    };
+
+    // This time the connection succeeds:
+    let session_conn_request = await!(icc.session_receiver.next()).unwrap();
+    assert_eq!(session_conn_request.address, index_server);
+    let (control_sender, _control_receiver) = mpsc::channel(0);
+    let (_close_sender, close_receiver) = oneshot::channel();
+    session_conn_request.reply(Some((control_sender, close_receiver)));
+
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(
+                ic_report_mutations.mutations,
+                vec![
+                    IndexClientReportMutation::SetConnectedServer(Some(
+                        index_server.public_key.clone()
+                    )),
+                    IndexClientReportMutation::SetCircuitState((
+                        index_server.public_key.clone(),
+                        CircuitStateReport::Closed,
+                    )),
+                ]
+            );
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_circuit_breaker() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_circuit_breaker(thread_pool.clone()));
+}
+
 async fn task_index_client_loop_connecting_state<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
@@ -545,4 +762,167 @@ fn test_index_client_loop_connecting_state() {
     thread_pool.run(task_index_client_loop_connecting_state(thread_pool.clone()));
 }
 
+async fn task_index_client_loop_rotate_index_server<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    let (mut control_receiver, close_sender) = await!(icc.expect_server_connection(index_server));
+
+    // Replace the index server in use (0x1337) with a new one (0x133a):
+    let new_server = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0x3a; PUBLIC_KEY_LEN]),
+        address: 0x133a,
+        name: "0x133a".to_owned(),
+    };
+    let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+        Uid::from(&[54; UID_LEN]),
+        IndexClientRequest::RotateIndexServer((
+            PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+            new_server.clone(),
+        )),
+    ));
+    await!(icc.app_server_sender.send(app_server_to_index_client)).unwrap();
+
+    // The database is updated using a single mutation:
+    let db_request = await!(icc.database_req_receiver.next()).unwrap();
+    assert_eq!(
+        db_request.mutations,
+        vec![IndexClientConfigMutation::RotateServer {
+            remove: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+            add: new_server.clone(),
+        }]
+    );
+    db_request.response_sender.send(()).unwrap();
+
+    // The old server disappears and the new one appears in the same report event:
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(
+                ic_report_mutations.opt_app_request_id,
+                Some(Uid::from(&[54; UID_LEN]))
+            );
+            assert_eq!(
+                ic_report_mutations.mutations,
+                vec![
+                    IndexClientReportMutation::RemoveIndexServer(PublicKey::from(
+                        &[0x37; PUBLIC_KEY_LEN]
+                    )),
+                    IndexClientReportMutation::AddIndexServer(new_server.clone()),
+                ]
+            );
+        }
+        _ => unreachable!(),
+    };
+
+    // The connection to the old server should be closed:
+    while let Some(_control_message) = await!(control_receiver.next()) {}
+    let _ = close_sender.send(Ok(()));
+    await!(icc.expect_set_connected_server(None));
+
+    for _ in 0..icc.backoff_ticks {
+        await!(icc.tick_sender.send(())).unwrap();
+    }
+
+    // The next connection attempt is made to the new server:
+    let session_conn_request = await!(icc.session_receiver.next()).unwrap();
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x3a; PUBLIC_KEY_LEN]),
+        address: 0x133a,
+    };
+    assert_eq!(session_conn_request.address, index_server);
+}
+
+#[test]
+fn test_index_client_loop_rotate_index_server() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_rotate_index_server(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_index_client_loop_rotate_unknown_index_server<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    let (_control_receiver, _close_sender) = await!(icc.expect_server_connection(index_server));
+
+    // Attempt to replace an index server that is not configured (0x39):
+    let new_server = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0x3a; PUBLIC_KEY_LEN]),
+        address: 0x133a,
+        name: "0x133a".to_owned(),
+    };
+    let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+        Uid::from(&[55; UID_LEN]),
+        IndexClientRequest::RotateIndexServer((
+            PublicKey::from(&[0x39; PUBLIC_KEY_LEN]),
+            new_server.clone(),
+        )),
+    ));
+    await!(icc.app_server_sender.send(app_server_to_index_client)).unwrap();
+
+    // The request is acknowledged, but nothing is changed:
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(
+                ic_report_mutations.opt_app_request_id,
+                Some(Uid::from(&[55; UID_LEN]))
+            );
+            assert!(ic_report_mutations.mutations.is_empty());
+        }
+        _ => unreachable!(),
+    };
+    assert!(icc.database_req_receiver.try_next().is_err());
+}
+
+#[test]
+fn test_index_client_loop_rotate_unknown_index_server() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_rotate_unknown_index_server(
+        thread_pool.clone(),
+    ));
+}
+
+#[test]
+fn test_index_client_config_rotate_server() {
+    let server37 = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337u32,
+        name: "0x1337".to_owned(),
+    };
+    let server38 = NamedIndexServerAddress {
+        public_key: PublicKey::from(&[0x38; PUBLIC_KEY_LEN]),
+        address: 0x1338u32,
+        name: "0x1338".to_owned(),
+    };
+    let mut index_client_config = IndexClientConfig {
+        index_servers: vec![server37.clone()],
+        cache_ttl_ticks: 8,
+        max_seq_friends: None,
+    };
+
+    // Rotating a server that is not configured is not possible:
+    assert!(index_client_config
+        .rotate_server(&PublicKey::from(&[0x39; PUBLIC_KEY_LEN]), server38.clone())
+        .is_none());
+
+    let mutation = index_client_config
+        .rotate_server(&server37.public_key, server38.clone())
+        .unwrap();
+    index_client_config.mutate(&mutation).unwrap();
+    assert_eq!(index_client_config.index_servers, vec![server38]);
+}
+
 // TODO: Add more tests.
@@ -9,7 +9,10 @@ use futures::{FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 use common::conn::{BoxFuture, ConnPair, ConnPairVec, FuncFutTransform, FutTransform};
 use common::transform_pool::transform_pool_loop;
 
-use proto::consts::{INDEX_NODE_TIMEOUT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
+use proto::consts::{
+    INCOMING_CONN_WAIT_TICKS, INDEX_NODE_TIMEOUT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION,
+    REKEY_MIN_TICKS, TICKS_TO_REKEY,
+};
 use proto::index_server::messages::{
     IndexClientToServer, IndexServerToClient, IndexServerToServer,
 };
@@ -22,6 +25,7 @@ use timer::TimerClient;
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::{compare_public_key, PublicKey};
+use crypto::sym_encrypt::SymEncryptAlgorithm;
 
 use identity::IdentityClient;
 use keepalive::KeepAliveChannel;
@@ -293,6 +297,7 @@ where
 pub enum NetIndexServerError {
     IndexServerError(IndexServerError),
     RequestPublicKeyError,
+    RequestTimerStreamError,
     SpawnError,
 }
 
@@ -321,12 +326,16 @@ where
     let local_public_key = await!(identity_client.request_public_key())
         .map_err(|_| NetIndexServerError::RequestPublicKeyError)?;
 
+    let mut c_timer_client = timer_client.clone();
+
     let version_transform = VersionPrefix::new(PROTOCOL_VERSION, spawner.clone());
     let encrypt_transform = SecureChannel::new(
         identity_client,
         rng.clone(),
         timer_client.clone(),
         TICKS_TO_REKEY,
+        REKEY_MIN_TICKS,
+        SymEncryptAlgorithm::default(),
         spawner.clone(),
     );
 
@@ -349,11 +358,15 @@ where
         })
     });
     let (client_conns_sender, incoming_client_conns) = mpsc::channel(0);
+    let client_pool_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NetIndexServerError::RequestTimerStreamError)?;
     let pool_fut = transform_pool_loop(
         incoming_client_raw_conns,
         client_conns_sender,
         incoming_client_transform,
         max_concurrent_encrypt,
+        client_pool_timer_stream,
+        INCOMING_CONN_WAIT_TICKS,
         spawner.clone(),
     )
     .map_err(|e| error!("client incoming transform_pool_loop() error: {:?}", e))
@@ -371,11 +384,15 @@ where
         })
     });
     let (server_conns_sender, incoming_server_conns) = mpsc::channel(0);
+    let server_pool_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NetIndexServerError::RequestTimerStreamError)?;
     let pool_fut = transform_pool_loop(
         incoming_server_raw_conns,
         server_conns_sender,
         incoming_server_transform,
         max_concurrent_encrypt,
+        server_pool_timer_stream,
+        INCOMING_CONN_WAIT_TICKS,
         spawner.clone(),
     )
     .map_err(|e| error!("server incoming transform_pool_loop() error: {:?}", e))
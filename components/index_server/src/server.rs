@@ -525,8 +525,8 @@ where
                 let mut remote_server = match index_server.remote_servers.remove(&public_key) {
                     None => {
                         error!(
-                            "Non trusted server {:?} attempted connection. Aborting.",
-                            public_key
+                            "Non trusted server {} attempted connection. Aborting.",
+                            public_key.to_hex()
                         );
                         continue;
                     }
@@ -535,7 +535,10 @@ where
 
                 match remote_server.state {
                     RemoteServerState::Connected(_) => {
-                        error!("Server {:?} is already connected! Aborting.", public_key);
+                        error!(
+                            "Server {} is already connected! Aborting.",
+                            public_key.to_hex()
+                        );
                         index_server
                             .remote_servers
                             .insert(public_key, remote_server);
@@ -577,8 +580,8 @@ where
                 let old_server = match index_server.remote_servers.remove(&public_key) {
                     None => {
                         error!(
-                            "A non existent server {:?} was closed. Aborting.",
-                            public_key
+                            "A non existent server {} was closed. Aborting.",
+                            public_key.to_hex()
                         );
                         continue;
                     }
@@ -590,7 +593,10 @@ where
             }
             IndexServerEvent::ClientConnection((public_key, client_conn)) => {
                 if index_server.clients.contains_key(&public_key) {
-                    error!("Client {:?} already connected! Aborting.", public_key);
+                    error!(
+                        "Client {} already connected! Aborting.",
+                        public_key.to_hex()
+                    );
                     continue;
                 }
 
@@ -635,7 +641,7 @@ where
             IndexServerEvent::ClientClosed(public_key) => {
                 // Client connection closed
                 if index_server.clients.remove(&public_key).is_none() {
-                    error!("A non existent client {:?} was closed.", public_key);
+                    error!("A non existent client {} was closed.", public_key.to_hex());
                 }
             }
             IndexServerEvent::TimerTick => await!(index_server.handle_timer_tick())?,
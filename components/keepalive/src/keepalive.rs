@@ -1,6 +1,7 @@
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
+use std::cmp::max;
 use std::marker::Unpin;
 use timer::{TimerClient, TimerTick};
 
@@ -85,9 +86,12 @@ where
 
     // Amount of ticks remaining until we decide to close this connection (Because remote is idle):
     let mut ticks_to_close = keepalive_ticks;
-    // Amount of ticks remaining until we need to send a new keepalive (To make sure remote side
-    // knows we are alive).
-    let mut ticks_to_send_keepalive = keepalive_ticks / 2;
+    // Amount of ticks passed since the loop started:
+    let mut cur_tick: usize = 0;
+    // Tick of the last keepalive we have sent:
+    let mut last_keepalive_tick: usize = 0;
+    // Tick of the last real (Non keepalive) message we have sent:
+    let mut last_real_message_tick: usize = 0;
 
     while let Some(event) = await!(events.next()) {
         if let Some(ref mut event_sender) = opt_event_sender {
@@ -112,22 +116,27 @@ where
                     warn!("keepalive_loop(): Can not send to remote side");
                     break;
                 }
-                ticks_to_send_keepalive = keepalive_ticks / 2;
+                last_real_message_tick = cur_tick;
             }
             KeepAliveEvent::TimerTick => {
                 ticks_to_close = ticks_to_close.saturating_sub(1);
-                ticks_to_send_keepalive = ticks_to_send_keepalive.saturating_sub(1);
+                cur_tick += 1;
                 if ticks_to_close == 0 {
                     return Err(KeepAliveError::RemoteTimeout);
                 }
-                if ticks_to_send_keepalive == 0 {
+                // A real message we sent proves to the remote side that we are alive, so it
+                // postpones the next keepalive.
+                // Note that messages we receive can not postpone the next keepalive: The remote
+                // side only knows we are alive if we send something.
+                let last_send_tick = max(last_keepalive_tick, last_real_message_tick);
+                if cur_tick - last_send_tick >= keepalive_ticks / 2 {
                     let ka_message = KaMessage::KeepAlive;
                     let ser_ka_message = serialize_ka_message(&ka_message);
                     if await!(to_remote.send(ser_ka_message)).is_err() {
                         warn!("Keepalive_loop(): Can not send to remote side");
                         break;
                     }
-                    ticks_to_send_keepalive = keepalive_ticks / 2;
+                    last_keepalive_tick = cur_tick;
                 }
             }
             KeepAliveEvent::TimerClosed
@@ -149,6 +158,8 @@ impl<S> KeepAliveChannel<S>
 where
     S: Spawn + Send,
 {
+    /// A keepalive is sent only after `keepalive_ticks / 2` ticks in which nothing was sent to
+    /// the remote side.
     pub fn new(
         timer_client: TimerClient,
         keepalive_ticks: usize,
@@ -342,6 +353,84 @@ mod tests {
         thread_pool.run(task_keepalive_loop_basic(thread_pool.clone()));
     }
 
+    async fn task_keepalive_loop_postpone(mut spawner: impl Spawn + Clone) {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let mut timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+
+        let (to_remote, mut remote_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut remote_sender, from_remote) = mpsc::channel::<Vec<u8>>(0);
+
+        let (to_user, _user_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
+
+        let timer_stream = await!(timer_client.request_timer_stream()).unwrap();
+        let keepalive_ticks = 16;
+        let fut_keepalive_loop = inner_keepalive_loop(
+            to_remote,
+            from_remote,
+            to_user,
+            from_user,
+            timer_stream,
+            keepalive_ticks,
+            Some(event_sender),
+        )
+        .map(|_| ());
+
+        spawner.spawn(fut_keepalive_loop).unwrap();
+
+        // Move time forward, and then send a message from user to remote:
+        for _ in 0..4usize {
+            await!(tick_sender.send(())).unwrap();
+            await!(event_receiver.next()).unwrap();
+        }
+        await!(user_sender.send(vec![1, 2, 3])).unwrap();
+        await!(event_receiver.next()).unwrap();
+        let vec = await!(remote_receiver.next()).unwrap();
+        assert_eq!(
+            vec,
+            serialize_ka_message(&KaMessage::Message(vec![1, 2, 3]))
+        );
+
+        // keepalive_ticks / 2 ticks passed since the loop started:
+        for _ in 0..4usize {
+            await!(tick_sender.send(())).unwrap();
+            await!(event_receiver.next()).unwrap();
+        }
+        await!(user_sender.send(vec![4, 5, 6])).unwrap();
+        await!(event_receiver.next()).unwrap();
+
+        // The real message postponed the keepalive:
+        let vec = await!(remote_receiver.next()).unwrap();
+        assert_eq!(
+            vec,
+            serialize_ka_message(&KaMessage::Message(vec![4, 5, 6]))
+        );
+
+        // Remote sends a keepalive, so that the connection is not closed:
+        let vec = serialize_ka_message(&KaMessage::KeepAlive);
+        await!(remote_sender.send(vec)).unwrap();
+        await!(event_receiver.next()).unwrap();
+
+        // keepalive_ticks / 2 ticks of silence:
+        for _ in 0..8usize {
+            await!(tick_sender.send(())).unwrap();
+            await!(event_receiver.next()).unwrap();
+        }
+
+        // We expect to see a keepalive being sent:
+        let vec = await!(remote_receiver.next()).unwrap();
+        assert_eq!(vec, serialize_ka_message(&KaMessage::KeepAlive));
+    }
+
+    #[test]
+    fn test_keepalive_loop_postpone() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_keepalive_loop_postpone(thread_pool.clone()));
+    }
+
     async fn task_keepalive_channel_basic(spawner: impl Spawn + Clone) {
         // Create a mock time service:
         let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
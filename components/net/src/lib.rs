@@ -18,6 +18,7 @@ extern crate log;
 
 mod net_connector;
 mod resolver;
+mod socks5_connector;
 mod tcp_connector;
 mod tcp_listener;
 #[cfg(test)]
@@ -26,4 +27,7 @@ mod types;
 mod utils;
 
 pub use self::net_connector::NetConnector;
-pub use self::tcp_listener::TcpListener;
+pub use self::socks5_connector::{
+    socks5_handshake, socks5_proxy_from_env, Socks5Connector, Socks5Error, SOCKS5_PROXY_ENV,
+};
+pub use self::tcp_listener::{RawTcpListener, TcpListener};
@@ -1,22 +1,44 @@
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use futures::future;
 use futures::task::Spawn;
 
 use proto::net::messages::NetAddress;
 
 use crate::resolver::Resolver;
+use crate::socks5_connector::Socks5Connector;
 use crate::tcp_connector::TcpConnector;
 
 #[derive(Clone)]
 pub struct NetConnector<S, RS> {
     resolver: Resolver<RS>,
     tcp_connector: TcpConnector<S>,
+    /// If set, all connections are made through a SOCKS5 proxy
+    opt_socks5_connector: Option<Socks5Connector<S, RS>>,
 }
 
-impl<S, RS> NetConnector<S, RS> {
-    pub fn new(max_frame_length: usize, resolve_spawner: RS, spawner: S) -> Self {
+impl<S, RS> NetConnector<S, RS>
+where
+    S: Clone,
+    RS: Clone,
+{
+    pub fn new(
+        max_frame_length: usize,
+        resolve_spawner: RS,
+        spawner: S,
+        opt_socks5_proxy: Option<NetAddress>,
+    ) -> Self {
+        let opt_socks5_connector = opt_socks5_proxy.map(|proxy_address| {
+            Socks5Connector::new(
+                proxy_address,
+                max_frame_length,
+                resolve_spawner.clone(),
+                spawner.clone(),
+            )
+        });
         NetConnector {
             resolver: Resolver::new(resolve_spawner),
             tcp_connector: TcpConnector::new(max_frame_length, spawner),
+            opt_socks5_connector,
         }
     }
 }
@@ -30,13 +52,32 @@ where
     type Output = Option<ConnPairVec>;
 
     fn transform(&mut self, net_address: Self::Input) -> BoxFuture<'_, Self::Output> {
+        let NetConnector {
+            resolver,
+            tcp_connector,
+            opt_socks5_connector,
+        } = self;
+
+        if let Some(socks5_connector) = opt_socks5_connector {
+            return socks5_connector.transform(net_address);
+        }
+
+        if net_address.is_onion() {
+            // Onion addresses can not be resolved, they are only reachable through a proxy:
+            warn!(
+                "NetConnector: Can not connect to {:?} without a SOCKS5 proxy",
+                net_address
+            );
+            return Box::pin(future::ready(None));
+        }
+
         debug!("Connecting to {:?}", net_address);
         Box::pin(async move {
-            let socket_addr_vec = await!(self.resolver.transform(net_address));
+            let socket_addr_vec = await!(resolver.transform(net_address));
             // A trivial implementation: We try to connect to the first address on the list.
             // TODO: Maybe choose a random address in the future?
             let socket_addr = socket_addr_vec.get(0)?;
-            await!(self.tcp_connector.transform(*socket_addr))
+            await!(tcp_connector.transform(*socket_addr))
         })
     }
 }
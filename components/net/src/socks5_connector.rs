@@ -0,0 +1,263 @@
+use std::convert::TryFrom;
+use std::env;
+use std::net::IpAddr;
+
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+
+use futures::compat::Future01CompatExt;
+use futures::task::Spawn;
+
+use tokio::io::{read_exact, write_all};
+use tokio::net::TcpStream;
+
+use proto::net::messages::NetAddress;
+
+use crate::resolver::Resolver;
+use crate::utils::tcp_stream_to_conn_pair;
+
+/// Environment variable holding the address of a SOCKS5 proxy (For example: `127.0.0.1:9050`)
+pub const SOCKS5_PROXY_ENV: &str = "OFFST_SOCKS5_PROXY";
+
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_AUTH_NONE: u8 = 0;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_REPLY_SUCCEEDED: u8 = 0;
+
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+
+#[derive(Debug)]
+pub enum Socks5Error {
+    InvalidAddress,
+    DomainTooLong,
+    IoError,
+    InvalidVersion,
+    AuthMethodRejected,
+    ConnectFailed(u8),
+    InvalidAddressType,
+}
+
+/// Read the SOCKS5 proxy address from the `OFFST_SOCKS5_PROXY` environment variable.
+pub fn socks5_proxy_from_env() -> Option<NetAddress> {
+    let proxy_address = env::var(SOCKS5_PROXY_ENV).ok()?;
+    if proxy_address.is_empty() {
+        return None;
+    }
+    NetAddress::try_from(proxy_address).ok()
+}
+
+/// Split a NetAddress of the form `host:port` into its host and port parts.
+/// The host may be a domain name, an IPv4 address or an IPv6 address
+/// (Optionally surrounded by square brackets).
+fn split_host_port(net_address: &NetAddress) -> Result<(&str, u16), Socks5Error> {
+    let address = net_address.as_str();
+    let colon_index = address.rfind(':').ok_or(Socks5Error::InvalidAddress)?;
+    let (host, port) = (&address[..colon_index], &address[colon_index + 1..]);
+    let port = port.parse().map_err(|_| Socks5Error::InvalidAddress)?;
+
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    };
+
+    if host.is_empty() {
+        return Err(Socks5Error::InvalidAddress);
+    }
+    Ok((host, port))
+}
+
+/// Create a SOCKS5 CONNECT request for the given address.
+fn create_connect_request(net_address: &NetAddress) -> Result<Vec<u8>, Socks5Error> {
+    let (host, port) = split_host_port(net_address)?;
+
+    let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ipv4_addr)) => {
+            request.push(SOCKS5_ATYP_IPV4);
+            request.extend_from_slice(&ipv4_addr.octets());
+        }
+        Ok(IpAddr::V6(ipv6_addr)) => {
+            request.push(SOCKS5_ATYP_IPV6);
+            request.extend_from_slice(&ipv6_addr.octets());
+        }
+        Err(_) => {
+            // Let the proxy resolve the domain name. This is required for onion addresses,
+            // which can only be resolved by Tor:
+            if host.len() > usize::from(u8::max_value()) {
+                return Err(Socks5Error::DomainTooLong);
+            }
+            request.push(SOCKS5_ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+/// Perform a SOCKS5 CONNECT handshake (Without authentication) over a connection to a proxy.
+/// On success, returns the connection, which is now tunneled to `net_address`.
+pub async fn socks5_handshake(
+    tcp_stream: TcpStream,
+    net_address: &NetAddress,
+) -> Result<TcpStream, Socks5Error> {
+    let connect_request = create_connect_request(net_address)?;
+
+    // Greeting: We only support the "no authentication" method:
+    let greeting = vec![SOCKS5_VERSION, 1, SOCKS5_AUTH_NONE];
+    let (tcp_stream, _) =
+        await!(write_all(tcp_stream, greeting).compat()).map_err(|_| Socks5Error::IoError)?;
+    let (tcp_stream, method_reply) =
+        await!(read_exact(tcp_stream, [0u8; 2]).compat()).map_err(|_| Socks5Error::IoError)?;
+    if method_reply[0] != SOCKS5_VERSION {
+        return Err(Socks5Error::InvalidVersion);
+    }
+    if method_reply[1] != SOCKS5_AUTH_NONE {
+        return Err(Socks5Error::AuthMethodRejected);
+    }
+
+    let (tcp_stream, _) = await!(write_all(tcp_stream, connect_request).compat())
+        .map_err(|_| Socks5Error::IoError)?;
+    let (tcp_stream, reply_header) =
+        await!(read_exact(tcp_stream, [0u8; 4]).compat()).map_err(|_| Socks5Error::IoError)?;
+    if reply_header[0] != SOCKS5_VERSION {
+        return Err(Socks5Error::InvalidVersion);
+    }
+    if reply_header[1] != SOCKS5_REPLY_SUCCEEDED {
+        return Err(Socks5Error::ConnectFailed(reply_header[1]));
+    }
+
+    // The reply contains the address bound by the proxy. We don't need it,
+    // but we have to read it before the tunneled data begins:
+    let (tcp_stream, bound_addr_len) = match reply_header[3] {
+        SOCKS5_ATYP_IPV4 => (tcp_stream, 4),
+        SOCKS5_ATYP_IPV6 => (tcp_stream, 16),
+        SOCKS5_ATYP_DOMAIN => {
+            let (tcp_stream, domain_len) = await!(read_exact(tcp_stream, [0u8; 1]).compat())
+                .map_err(|_| Socks5Error::IoError)?;
+            (tcp_stream, usize::from(domain_len[0]))
+        }
+        _ => return Err(Socks5Error::InvalidAddressType),
+    };
+    // Bound address and bound port:
+    let (tcp_stream, _) = await!(read_exact(tcp_stream, vec![0u8; bound_addr_len + 2]).compat())
+        .map_err(|_| Socks5Error::IoError)?;
+
+    Ok(tcp_stream)
+}
+
+/// Connects to remote addresses through a SOCKS5 proxy.
+#[derive(Clone)]
+pub struct Socks5Connector<S, RS> {
+    proxy_address: NetAddress,
+    resolver: Resolver<RS>,
+    max_frame_length: usize,
+    spawner: S,
+}
+
+impl<S, RS> Socks5Connector<S, RS> {
+    pub fn new(
+        proxy_address: NetAddress,
+        max_frame_length: usize,
+        resolve_spawner: RS,
+        spawner: S,
+    ) -> Self {
+        Socks5Connector {
+            proxy_address,
+            resolver: Resolver::new(resolve_spawner),
+            max_frame_length,
+            spawner,
+        }
+    }
+}
+
+impl<S, RS> FutTransform for Socks5Connector<S, RS>
+where
+    S: Spawn + Send,
+    RS: Spawn + Send,
+{
+    type Input = NetAddress;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, net_address: Self::Input) -> BoxFuture<'_, Self::Output> {
+        debug!(
+            "Connecting to {:?} through SOCKS5 proxy {:?}",
+            net_address, self.proxy_address
+        );
+        Box::pin(async move {
+            let socket_addr_vec = await!(self.resolver.transform(self.proxy_address.clone()));
+            let socket_addr = socket_addr_vec.get(0)?;
+            let tcp_stream = await!(TcpStream::connect(socket_addr).compat()).ok()?;
+
+            let tcp_stream = match await!(socks5_handshake(tcp_stream, &net_address)) {
+                Ok(tcp_stream) => tcp_stream,
+                Err(e) => {
+                    warn!("Socks5Connector: Handshake with proxy failed: {:?}", e);
+                    return None;
+                }
+            };
+
+            Some(tcp_stream_to_conn_pair(
+                tcp_stream,
+                self.max_frame_length,
+                &mut self.spawner,
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net_address(address: &str) -> NetAddress {
+        NetAddress::try_from(address.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn test_create_connect_request() {
+        assert_eq!(
+            create_connect_request(&net_address("127.0.0.1:1337")).unwrap(),
+            vec![5, 1, 0, 1, 127, 0, 0, 1, 0x05, 0x39]
+        );
+
+        let mut expected = vec![5, 1, 0, 4];
+        expected.extend_from_slice(&[0; 15]);
+        expected.extend_from_slice(&[1, 0x05, 0x3a]);
+        assert_eq!(
+            create_connect_request(&net_address("[::1]:1338")).unwrap(),
+            expected
+        );
+
+        let mut expected = vec![5, 1, 0, 3, 11];
+        expected.extend_from_slice(b"example.com");
+        expected.extend_from_slice(&[0x01, 0xbb]);
+        assert_eq!(
+            create_connect_request(&net_address("example.com:443")).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_create_connect_request_onion() {
+        let onion_host = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid.onion";
+        let onion_address = net_address(&format!("{}:9735", onion_host));
+        assert!(onion_address.is_onion());
+
+        // The host name is sent to the proxy as is:
+        let mut expected = vec![5, 1, 0, 3, 62];
+        expected.extend_from_slice(onion_host.as_bytes());
+        expected.extend_from_slice(&[0x26, 0x07]);
+        assert_eq!(create_connect_request(&onion_address).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_create_connect_request_invalid() {
+        assert!(create_connect_request(&net_address("example.com")).is_err());
+        assert!(create_connect_request(&net_address("example.com:port")).is_err());
+        assert!(create_connect_request(&net_address(":443")).is_err());
+        assert!(create_connect_request(&net_address("example.com:70000")).is_err());
+    }
+}
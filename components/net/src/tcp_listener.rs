@@ -1,12 +1,12 @@
 use std::net::SocketAddr;
 
-use tokio::net::TcpListener as TokioTcpListener;
+use tokio::net::{TcpListener as TokioTcpListener, TcpStream};
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{SinkExt, StreamExt};
 
-use crate::utils::tcp_stream_to_conn_pair;
+use crate::utils::{tcp_stream_to_conn_pair, tcp_stream_to_raw_conn_pair};
 use common::conn::{ConnPairVec, Listener};
 
 use futures::compat::Stream01CompatExt;
@@ -26,6 +26,56 @@ impl<S> TcpListener<S> {
     }
 }
 
+/// Listen for incoming TCP connections, without any framing.
+/// Useful for serving protocols that are not framed (For example, HTTP).
+pub struct RawTcpListener<S> {
+    spawner: S,
+}
+
+impl<S> RawTcpListener<S> {
+    pub fn new(spawner: S) -> Self {
+        RawTcpListener { spawner }
+    }
+}
+
+/// Listen on `socket_addr`, converting every incoming TCP stream into a connection pair
+/// using `to_conn_pair`.
+fn listen_tcp<S, F>(
+    socket_addr: SocketAddr,
+    mut spawner: S,
+    to_conn_pair: F,
+) -> (mpsc::Sender<()>, mpsc::Receiver<ConnPairVec>)
+where
+    S: Spawn + Send + Clone + 'static,
+    F: Fn(TcpStream, &mut S) -> ConnPairVec + Send + 'static,
+{
+    let (config_sender, _config_sender_receiver) = mpsc::channel(0);
+    let (mut conn_receiver_sender, conn_receiver) = mpsc::channel(0);
+
+    let listener = match TokioTcpListener::bind(&socket_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            warn!("Failed listening on {:?}: {:?}", socket_addr, e);
+            // Return empty channels:
+            return (config_sender, conn_receiver);
+        }
+    };
+
+    let mut incoming_conns = listener.incoming().compat();
+    let mut c_spawner = spawner.clone();
+    let _ = spawner.spawn(async move {
+        while let Some(Ok(tcp_stream)) = await!(incoming_conns.next()) {
+            let conn_pair = to_conn_pair(tcp_stream, &mut c_spawner);
+            if let Err(e) = await!(conn_receiver_sender.send(conn_pair)) {
+                warn!("TcpListener::listen(): Send error: {:?}", e);
+                return;
+            }
+        }
+    });
+
+    (config_sender, conn_receiver)
+}
+
 impl<S> Listener for TcpListener<S>
 where
     S: Spawn + Send + Clone + 'static,
@@ -35,35 +85,28 @@ where
     type Arg = SocketAddr;
 
     fn listen(
-        mut self,
+        self,
         socket_addr: Self::Arg,
     ) -> (mpsc::Sender<Self::Config>, mpsc::Receiver<Self::Connection>) {
-        let (config_sender, _config_sender_receiver) = mpsc::channel(0);
-        let (mut conn_receiver_sender, conn_receiver) = mpsc::channel(0);
-
-        let listener = match TokioTcpListener::bind(&socket_addr) {
-            Ok(listener) => listener,
-            Err(e) => {
-                warn!("Failed listening on {:?}: {:?}", socket_addr, e);
-                // Return empty channels:
-                return (config_sender, conn_receiver);
-            }
-        };
-
-        let mut incoming_conns = listener.incoming().compat();
-        let mut c_spawner = self.spawner.clone();
-        let c_max_frame_length = self.max_frame_length;
-        let _ = self.spawner.spawn(async move {
-            while let Some(Ok(tcp_stream)) = await!(incoming_conns.next()) {
-                let conn_pair =
-                    tcp_stream_to_conn_pair(tcp_stream, c_max_frame_length, &mut c_spawner);
-                if let Err(e) = await!(conn_receiver_sender.send(conn_pair)) {
-                    warn!("TcpListener::listen(): Send error: {:?}", e);
-                    return;
-                }
-            }
-        });
+        let max_frame_length = self.max_frame_length;
+        listen_tcp(socket_addr, self.spawner, move |tcp_stream, spawner| {
+            tcp_stream_to_conn_pair(tcp_stream, max_frame_length, spawner)
+        })
+    }
+}
 
-        (config_sender, conn_receiver)
+impl<S> Listener for RawTcpListener<S>
+where
+    S: Spawn + Send + Clone + 'static,
+{
+    type Connection = ConnPairVec;
+    type Config = ();
+    type Arg = SocketAddr;
+
+    fn listen(
+        self,
+        socket_addr: Self::Arg,
+    ) -> (mpsc::Sender<Self::Config>, mpsc::Receiver<Self::Connection>) {
+        listen_tcp(socket_addr, self.spawner, tcp_stream_to_raw_conn_pair)
     }
 }
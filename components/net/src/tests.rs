@@ -3,17 +3,22 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use env_logger;
 
+use futures::compat::Future01CompatExt;
 use futures::executor::ThreadPool;
-use futures::task::Spawn;
+use futures::task::{Spawn, SpawnExt};
 use futures::{SinkExt, StreamExt};
 
+use futures_01::stream::Stream as Stream01;
+
 use common::conn::{FutTransform, Listener};
 use proto::net::messages::NetAddress;
 
 use crate::net_connector::NetConnector;
 use crate::tcp_connector::TcpConnector;
-use crate::tcp_listener::TcpListener;
+use crate::tcp_listener::{RawTcpListener, TcpListener};
+use crate::utils::tcp_stream_to_conn_pair;
 
+use tokio::io::{read_exact, write_all};
 use tokio::net::TcpListener as TokioTcpListener;
 
 /// Get an available port we can listen on
@@ -73,6 +78,41 @@ fn test_tcp_client_server_v4() {
     thread_pool.run(task_tcp_client_server_v4(thread_pool.clone()));
 }
 
+async fn task_raw_tcp_listener_v4<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let available_port = get_available_port_v4();
+    let loopback = Ipv4Addr::new(127, 0, 0, 1);
+    let socket_addr = SocketAddr::new(IpAddr::V4(loopback), available_port);
+
+    let raw_tcp_listener = RawTcpListener::new(spawner.clone());
+    let mut tcp_connector = TcpConnector::new(TEST_MAX_FRAME_LEN, spawner.clone());
+
+    let (_config_sender, mut incoming_connections) = raw_tcp_listener.listen(socket_addr.clone());
+
+    let (mut client_sender, mut client_receiver) =
+        await!(tcp_connector.transform(socket_addr.clone())).unwrap();
+    let (mut server_sender, mut server_receiver) = await!(incoming_connections.next()).unwrap();
+
+    // The raw side sees the length prefix of the frame:
+    await!(client_sender.send(vec![1, 2, 3])).unwrap();
+    let mut received = Vec::new();
+    while received.len() < 7 {
+        received.extend(await!(server_receiver.next()).unwrap());
+    }
+    assert_eq!(received, vec![0, 0, 0, 3, 1, 2, 3]);
+
+    await!(server_sender.send(vec![0, 0, 0, 2, 5, 6])).unwrap();
+    assert_eq!(await!(client_receiver.next()).unwrap(), vec![5, 6]);
+}
+
+#[test]
+fn test_raw_tcp_listener_v4() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_raw_tcp_listener_v4(thread_pool.clone()));
+}
+
 async fn task_net_connector_v4_basic<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
@@ -82,7 +122,8 @@ where
     let socket_addr = SocketAddr::new(IpAddr::V4(loopback), available_port);
 
     let tcp_listener = TcpListener::new(TEST_MAX_FRAME_LEN, spawner.clone());
-    let mut net_connector = NetConnector::new(TEST_MAX_FRAME_LEN, spawner.clone(), spawner.clone());
+    let mut net_connector =
+        NetConnector::new(TEST_MAX_FRAME_LEN, spawner.clone(), spawner.clone(), None);
 
     let (_config_sender, mut incoming_connections) = tcp_listener.listen(socket_addr.clone());
 
@@ -116,7 +157,8 @@ where
     let socket_addr = SocketAddr::new(IpAddr::V4(loopback), available_port);
 
     let tcp_listener = TcpListener::new(TEST_MAX_FRAME_LEN, spawner.clone());
-    let mut net_connector = NetConnector::new(TEST_MAX_FRAME_LEN, spawner.clone(), spawner.clone());
+    let mut net_connector =
+        NetConnector::new(TEST_MAX_FRAME_LEN, spawner.clone(), spawner.clone(), None);
 
     let (_config_sender, mut incoming_connections) = tcp_listener.listen(socket_addr.clone());
 
@@ -140,3 +182,81 @@ fn test_net_connector_v4_drop_sender() {
     let mut thread_pool = ThreadPool::new().unwrap();
     thread_pool.run(task_net_connector_v4_drop_sender(thread_pool.clone()));
 }
+
+async fn task_net_connector_socks5<S>(mut spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let loopback = Ipv4Addr::new(127, 0, 0, 1);
+    let proxy_listener = TokioTcpListener::bind(&SocketAddr::new(IpAddr::V4(loopback), 0)).unwrap();
+    let proxy_port = proxy_listener.local_addr().unwrap().port();
+    let proxy_address: NetAddress = format!("127.0.0.1:{}", proxy_port).try_into().unwrap();
+
+    let mut net_connector = NetConnector::new(
+        TEST_MAX_FRAME_LEN,
+        spawner.clone(),
+        spawner.clone(),
+        Some(proxy_address),
+    );
+
+    // A fake proxy. Checks the handshake, and then acts as the remote side:
+    let proxy_fut = async move {
+        let (opt_tcp_stream, _) = await!(proxy_listener.incoming().into_future().compat())
+            .ok()
+            .unwrap();
+        let tcp_stream = opt_tcp_stream.unwrap();
+
+        let (tcp_stream, greeting) = await!(read_exact(tcp_stream, [0u8; 3]).compat()).unwrap();
+        assert_eq!(greeting, [5, 1, 0]);
+        let (tcp_stream, _) = await!(write_all(tcp_stream, [5u8, 0]).compat()).unwrap();
+
+        // The proxy is expected to resolve the domain name:
+        let mut expected_request = vec![5, 1, 0, 3, 11];
+        expected_request.extend_from_slice(b"example.com");
+        expected_request.extend_from_slice(&[0x01, 0xbb]);
+        let (tcp_stream, request) =
+            await!(read_exact(tcp_stream, vec![0u8; expected_request.len()]).compat()).unwrap();
+        assert_eq!(request, expected_request);
+
+        let reply = [5u8, 0, 0, 1, 127, 0, 0, 1, 0x05, 0x39];
+        let (tcp_stream, _) = await!(write_all(tcp_stream, reply).compat()).unwrap();
+        tcp_stream
+    };
+    let proxy_handle = spawner.spawn_with_handle(proxy_fut).unwrap();
+
+    let net_address: NetAddress = "example.com:443".to_owned().try_into().unwrap();
+    let (mut client_sender, mut client_receiver) =
+        await!(net_connector.transform(net_address)).unwrap();
+
+    let proxy_tcp_stream = await!(proxy_handle);
+    let (mut server_sender, mut server_receiver) =
+        tcp_stream_to_conn_pair(proxy_tcp_stream, TEST_MAX_FRAME_LEN, &mut spawner);
+
+    await!(client_sender.send(vec![1, 2, 3])).unwrap();
+    assert_eq!(await!(server_receiver.next()).unwrap(), vec![1, 2, 3]);
+
+    await!(server_sender.send(vec![3, 2, 1])).unwrap();
+    assert_eq!(await!(client_receiver.next()).unwrap(), vec![3, 2, 1]);
+}
+
+#[test]
+fn test_net_connector_socks5() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_net_connector_socks5(thread_pool.clone()));
+}
+
+#[test]
+fn test_net_connector_onion_without_proxy() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    let mut net_connector = NetConnector::new(
+        TEST_MAX_FRAME_LEN,
+        thread_pool.clone(),
+        thread_pool.clone(),
+        None,
+    );
+
+    let net_address: NetAddress = "expyuzz4wqqyqhjn.onion:9735".to_owned().try_into().unwrap();
+    assert!(thread_pool
+        .run(net_connector.transform(net_address))
+        .is_none());
+}
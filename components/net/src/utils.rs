@@ -8,7 +8,7 @@ use futures::{FutureExt, SinkExt, StreamExt};
 use futures_01::sink::Sink as Sink01;
 use futures_01::stream::Stream as Stream01;
 
-use tokio::codec::{Framed, LengthDelimitedCodec};
+use tokio::codec::{BytesCodec, Framed, LengthDelimitedCodec};
 use tokio::net::TcpStream;
 
 use common::conn::ConnPairVec;
@@ -117,6 +117,24 @@ where
     conn_pair_01_to_03((sender_01, receiver_01), spawner)
 }
 
+/// Convert a TCP stream into a connection pair without any framing.
+/// Every received `Vec<u8>` is an arbitrary chunk of the incoming bytes.
+pub fn tcp_stream_to_raw_conn_pair<S>(tcp_stream: TcpStream, spawner: &mut S) -> ConnPairVec
+where
+    S: Spawn + Send,
+{
+    let (sender_01, receiver_01) = Framed::new(tcp_stream, BytesCodec::new()).split();
+
+    // Conversion layer between Vec<u8> to Bytes:
+    let sender_01 = sender_01
+        .sink_map_err(|_| ())
+        .with(|vec: Vec<u8>| -> Result<Bytes, ()> { Ok(Bytes::from(vec)) });
+
+    let receiver_01 = receiver_01.map(|bytes| bytes.to_vec());
+
+    conn_pair_01_to_03((sender_01, receiver_01), spawner)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
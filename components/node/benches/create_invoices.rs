@@ -0,0 +1,128 @@
+#![feature(test, async_await, await_macro)]
+
+extern crate test;
+
+use std::collections::HashMap;
+
+use test::Bencher;
+
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::SpawnExt;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{
+    AppPermissions, AppServerToApp, AppToAppServer, NodeReport, ReportMutations,
+};
+use proto::index_client::messages::IndexClientReport;
+use proto::report::messages::FunderReport;
+
+use timer::dummy_timer_multi_sender;
+
+use offst_node::connect::{NodeConnectionBuilder, SellerNodeConnection};
+
+const NUM_INVOICES: usize = 100;
+
+/// Create a seller connection to a fake app server, that confirms every request immediately.
+fn create_seller_connection(thread_pool: &mut ThreadPool) -> SellerNodeConnection<DummyRandom> {
+    let app_permissions = AppPermissions {
+        routes: false,
+        buyer: false,
+        seller: true,
+        config: false,
+        max_requests_per_second: None,
+        friend_permissions: None,
+    };
+
+    let node_report = NodeReport {
+        funder_report: FunderReport {
+            local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            relays: Default::default(),
+            friends: Default::default(),
+            num_open_invoices: 0,
+            invoices_progress: Default::default(),
+            num_payments: 0,
+            num_open_transactions: 0,
+            total_frozen_credits: (0, 0),
+            max_friends: 0x100,
+            max_node_relays: 0x10,
+        },
+        index_client_report: IndexClientReport {
+            index_servers: Vec::new(),
+            opt_connected_server: None,
+            circuit_states: HashMap::new(),
+        },
+    };
+
+    let (app_sender, mut app_server_receiver) = mpsc::channel::<AppToAppServer>(0);
+    let (mut app_server_sender, app_receiver) = mpsc::channel(0);
+    thread_pool
+        .spawn(async move {
+            while let Some(to_app_server) = await!(app_server_receiver.next()) {
+                let report_mutations = ReportMutations {
+                    opt_app_request_id: Some(to_app_server.app_request_id),
+                    mutations: Vec::new(),
+                };
+                if await!(app_server_sender.send(AppServerToApp::ReportMutations(report_mutations)))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        })
+        .unwrap();
+
+    let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(thread_pool.clone());
+    NodeConnectionBuilder::new(
+        (
+            app_permissions,
+            Uid::from(&[0; UID_LEN]),
+            node_report,
+            (app_sender, app_receiver),
+        ),
+        timer_client,
+        1,
+        DummyRandom::new(&[1u8]),
+    )
+    .build_with_seller(thread_pool)
+    .unwrap()
+}
+
+fn create_invoices_list() -> Vec<(InvoiceId, u128)> {
+    (0..NUM_INVOICES)
+        .map(|i| (InvoiceId::from(&[i as u8; INVOICE_ID_LEN]), 100))
+        .collect()
+}
+
+#[bench]
+fn bench_create_invoices_sequential(b: &mut Bencher) {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    let mut seller_connection = create_seller_connection(&mut thread_pool);
+    let app_seller = seller_connection.seller();
+
+    b.iter(|| {
+        thread_pool.run(async {
+            for (invoice_id, total_dest_payment) in create_invoices_list() {
+                await!(app_seller.add_invoice(invoice_id, total_dest_payment, 20)).unwrap();
+            }
+        })
+    });
+}
+
+#[bench]
+fn bench_create_invoices_batch(b: &mut Bencher) {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    let mut seller_connection = create_seller_connection(&mut thread_pool);
+    let app_seller = seller_connection.seller();
+
+    b.iter(|| {
+        thread_pool
+            .run(app_seller.create_invoices(create_invoices_list(), 20))
+            .unwrap()
+    });
+}
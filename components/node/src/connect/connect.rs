@@ -6,15 +6,18 @@ use common::conn::{ConnPairVec, FutTransform};
 
 use proto::app_server::messages::AppServerToApp;
 use proto::app_server::serialize::{
-    deserialize_app_permissions, deserialize_app_server_to_app, serialize_app_to_app_server,
+    deserialize_app_permissions, deserialize_app_server_to_app, serialize_app_session_request,
+    serialize_app_to_app_server,
 };
-use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
+use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, REKEY_MIN_TICKS, TICKS_TO_REKEY};
 use proto::net::messages::NetAddress;
 
 use timer::TimerClient;
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
+use crypto::sym_encrypt::SymEncryptAlgorithm;
+use crypto::uid::Uid;
 use identity::IdentityClient;
 
 pub use super::node_connection::NodeConnection;
@@ -29,18 +32,22 @@ pub enum SetupConnectionError {
     EncryptSetupError,
     RecvAppPermissionsError,
     DeserializeAppPermissionsError,
+    SendAppSessionRequestError,
     ClosedBeforeNodeReport,
     DeserializeNodeReportError,
     FirstMessageNotNodeReport,
 }
 
 /// Connect to an offst-node
+/// `opt_app_session` is the session token received on a previous connection. If given, the node
+/// will deliver the responses to requests that were sent over the previous connection.
 pub async fn setup_connection<R, S>(
     conn_pair: ConnPairVec,
     timer_client: TimerClient,
     rng: R,
     node_public_key: PublicKey,
     app_identity_client: IdentityClient,
+    opt_app_session: Option<Uid>,
     mut spawner: S,
 ) -> Result<NodeConnectionTuple, SetupConnectionError>
 where
@@ -54,6 +61,8 @@ where
         rng.clone(),
         timer_client.clone(),
         TICKS_TO_REKEY,
+        REKEY_MIN_TICKS,
+        SymEncryptAlgorithm::default(),
         spawner.clone(),
     );
 
@@ -78,13 +87,17 @@ where
     let app_permissions = deserialize_app_permissions(&app_permissions_data)
         .map_err(|_| SetupConnectionError::DeserializeAppPermissionsError)?;
 
+    // Resume a previous session, or ask for a new one:
+    await!(sender.send(serialize_app_session_request(&opt_app_session)))
+        .map_err(|_| SetupConnectionError::SendAppSessionRequestError)?;
+
     // Wait for the first NodeReport.
     let data = await!(receiver.next()).ok_or(SetupConnectionError::ClosedBeforeNodeReport)?;
     let message = deserialize_app_server_to_app(&data)
         .map_err(|_| SetupConnectionError::DeserializeNodeReportError)?;
 
-    let node_report = if let AppServerToApp::Report(node_report) = message {
-        node_report
+    let (app_session, node_report) = if let AppServerToApp::Report(report) = message {
+        report
     } else {
         return Err(SetupConnectionError::FirstMessageNotNodeReport)?;
     };
@@ -116,7 +129,12 @@ where
         }
     });
 
-    Ok((app_permissions, node_report, (user_sender, user_receiver)))
+    Ok((
+        app_permissions,
+        app_session,
+        node_report,
+        (user_sender, user_receiver),
+    ))
 }
 
 #[derive(Debug)]
@@ -128,12 +146,15 @@ pub enum NodeConnectError {
 }
 
 /// Connect to an offst node
+/// See `setup_connection` for the meaning of `opt_app_session`.
 pub async fn node_connect<C, R, S>(
     mut net_connector: C,
     node_public_key: PublicKey,
     node_net_address: NetAddress,
     timer_client: TimerClient,
     app_identity_client: IdentityClient,
+    opt_app_session: Option<Uid>,
+    snapshot_interval_ticks: usize,
     rng: R,
     mut spawner: S,
 ) -> Result<NodeConnection<R>, NodeConnectError>
@@ -147,14 +168,21 @@ where
 
     let conn_tuple = await!(setup_connection(
         conn_pair,
-        timer_client,
+        timer_client.clone(),
         rng.clone(),
         node_public_key,
         app_identity_client,
+        opt_app_session,
         spawner.clone()
     ))
     .map_err(NodeConnectError::SetupConnectionError)?;
 
-    NodeConnection::new(conn_tuple, rng, &mut spawner)
-        .map_err(|_| NodeConnectError::CreateNodeConnectionError)
+    NodeConnection::new(
+        conn_tuple,
+        timer_client,
+        snapshot_interval_ticks,
+        rng,
+        &mut spawner,
+    )
+    .map_err(|_| NodeConnectError::CreateNodeConnectionError)
 }
@@ -1,8 +1,14 @@
 mod connect;
 mod node_connection;
 
-pub use self::connect::{node_connect, NodeConnection};
+pub use self::connect::{node_connect, setup_connection, NodeConnection, SetupConnectionError};
 
 pub use self::node_connection::{
-    buyer::AppBuyer, config::AppConfig, report::AppReport, routes::AppRoutes, seller::AppSeller,
+    buyer::{AppBuyer, CancelPaymentError, PaymentProgressEvent, PaymentProgressStream},
+    config::AppConfig,
+    report::{AppReport, ReportDiff},
+    routes::AppRoutes,
+    seller::AppSeller,
+    BuyerNodeConnection, ConfigNodeConnection, NodeConnectionBuilder, NodeConnectionTuple,
+    ReconnectError, ReconnectingNodeConnection, RoutesNodeConnection, SellerNodeConnection,
 };
@@ -0,0 +1,247 @@
+use futures::task::Spawn;
+
+use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
+
+use timer::TimerClient;
+
+use super::buyer::AppBuyer;
+use super::config::AppConfig;
+use super::node_connection::{NodeConnection, NodeConnectionError, NodeConnectionTuple};
+use super::report::AppReport;
+use super::routes::AppRoutes;
+use super::seller::AppSeller;
+
+/// Creates a NodeConnection from an established connection to a node.
+///
+/// The `build_with_*()` methods check that the app was granted the corresponding permission,
+/// and return a connection that exposes the capability directly (Without an `Option`).
+pub struct NodeConnectionBuilder<R = OffstSystemRandom> {
+    conn_tuple: NodeConnectionTuple,
+    timer_client: TimerClient,
+    snapshot_interval_ticks: usize,
+    rng: R,
+}
+
+impl<R> NodeConnectionBuilder<R>
+where
+    R: CryptoRandom + Clone,
+{
+    pub fn new(
+        conn_tuple: NodeConnectionTuple,
+        timer_client: TimerClient,
+        snapshot_interval_ticks: usize,
+        rng: R,
+    ) -> Self {
+        NodeConnectionBuilder {
+            conn_tuple,
+            timer_client,
+            snapshot_interval_ticks,
+            rng,
+        }
+    }
+
+    /// Build a NodeConnection, where every capability is optional.
+    pub fn build<S>(self, spawner: &mut S) -> Result<NodeConnection<R>, NodeConnectionError>
+    where
+        S: Spawn,
+    {
+        NodeConnection::new(
+            self.conn_tuple,
+            self.timer_client,
+            self.snapshot_interval_ticks,
+            self.rng,
+            spawner,
+        )
+    }
+}
+
+/// Define a connection type that is guaranteed to have a certain capability,
+/// and a `NodeConnectionBuilder` method for building it.
+/// `$cap_func` is both the name of the `AppPermissions` field and of the `NodeConnection` method
+/// for the capability.
+macro_rules! typed_node_connection {
+    ($(#[$meta:meta])* $conn_name:ident, $build_func:ident, $cap_func:ident, $cap_type:ident) => {
+        $(#[$meta])*
+        #[derive(Clone)]
+        pub struct $conn_name<R = OffstSystemRandom> {
+            node_connection: NodeConnection<R>,
+            $cap_func: $cap_type<R>,
+        }
+
+        impl<R> $conn_name<R>
+        where
+            R: CryptoRandom + Clone,
+        {
+            pub fn $cap_func(&mut self) -> &mut $cap_type<R> {
+                &mut self.$cap_func
+            }
+
+            pub fn report(&mut self) -> &mut AppReport {
+                self.node_connection.report()
+            }
+
+            /// Access the other (optional) capabilities of the connection
+            pub fn node_connection(&mut self) -> &mut NodeConnection<R> {
+                &mut self.node_connection
+            }
+        }
+
+        impl<R> NodeConnectionBuilder<R>
+        where
+            R: CryptoRandom + Clone,
+        {
+            pub fn $build_func<S>(
+                self,
+                spawner: &mut S,
+            ) -> Result<$conn_name<R>, NodeConnectionError>
+            where
+                S: Spawn,
+            {
+                let (ref app_permissions, _, _, _) = self.conn_tuple;
+                if !app_permissions.$cap_func {
+                    return Err(NodeConnectionError::MissingPermission);
+                }
+
+                let mut node_connection = self.build(spawner)?;
+                let $cap_func = node_connection
+                    .$cap_func()
+                    .cloned()
+                    .ok_or(NodeConnectionError::MissingPermission)?;
+
+                Ok($conn_name {
+                    node_connection,
+                    $cap_func,
+                })
+            }
+        }
+    };
+}
+
+typed_node_connection!(
+    /// A NodeConnection of an app with the `config` permission
+    ConfigNodeConnection,
+    build_with_config,
+    config,
+    AppConfig
+);
+
+typed_node_connection!(
+    /// A NodeConnection of an app with the `routes` permission
+    RoutesNodeConnection,
+    build_with_routes,
+    routes,
+    AppRoutes
+);
+
+typed_node_connection!(
+    /// A NodeConnection of an app with the `buyer` permission
+    BuyerNodeConnection,
+    build_with_buyer,
+    buyer,
+    AppBuyer
+);
+
+typed_node_connection!(
+    /// A NodeConnection of an app with the `seller` permission
+    SellerNodeConnection,
+    build_with_seller,
+    seller,
+    AppSeller
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use futures::channel::mpsc;
+    use futures::executor::ThreadPool;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::test_utils::DummyRandom;
+    use crypto::uid::{Uid, UID_LEN};
+
+    use proto::app_server::messages::{AppPermissions, NodeReport};
+    use proto::index_client::messages::IndexClientReport;
+    use proto::report::messages::FunderReport;
+
+    use timer::dummy_timer_multi_sender;
+
+    fn create_builder(
+        app_permissions: AppPermissions,
+        timer_client: TimerClient,
+    ) -> NodeConnectionBuilder<DummyRandom> {
+        let node_report = NodeReport {
+            funder_report: FunderReport {
+                local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                relays: Default::default(),
+                friends: Default::default(),
+                num_open_invoices: 0,
+                invoices_progress: Default::default(),
+                num_payments: 0,
+                num_open_transactions: 0,
+                total_frozen_credits: (0, 0),
+                max_friends: 0x100,
+                max_node_relays: 0x10,
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                circuit_states: HashMap::new(),
+            },
+        };
+
+        // The other sides of the channels are dropped, as if the node closed the connection:
+        let (sender, _) = mpsc::channel(0);
+        let (_, receiver) = mpsc::channel(0);
+
+        NodeConnectionBuilder::new(
+            (
+                app_permissions,
+                Uid::from(&[0; UID_LEN]),
+                node_report,
+                (sender, receiver),
+            ),
+            timer_client,
+            1,
+            DummyRandom::new(&[1u8]),
+        )
+    }
+
+    #[test]
+    fn test_node_connection_builder() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(thread_pool.clone());
+
+        let app_permissions = AppPermissions {
+            routes: false,
+            buyer: false,
+            seller: true,
+            config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
+        };
+
+        let builder = create_builder(app_permissions.clone(), timer_client.clone());
+        let mut config_connection = builder.build_with_config(&mut thread_pool).unwrap();
+        let _app_config: &mut AppConfig<_> = config_connection.config();
+        assert!(config_connection.node_connection().seller().is_some());
+        assert!(config_connection.node_connection().buyer().is_none());
+
+        let builder = create_builder(app_permissions.clone(), timer_client.clone());
+        assert!(builder.build_with_seller(&mut thread_pool).is_ok());
+
+        let builder = create_builder(app_permissions.clone(), timer_client.clone());
+        match builder.build_with_buyer(&mut thread_pool) {
+            Err(NodeConnectionError::MissingPermission) => {}
+            _ => unreachable!(),
+        }
+
+        let builder = create_builder(app_permissions, timer_client);
+        match builder.build_with_routes(&mut thread_pool) {
+            Err(NodeConnectionError::MissingPermission) => {}
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,6 +1,11 @@
+use std::collections::{HashMap, VecDeque};
+
 use common::multi_consumer::MultiConsumerClient;
-use futures::channel::mpsc;
-use futures::{SinkExt, StreamExt};
+use common::mutable_state::BatchMutable;
+use common::select_streams::{select_streams, BoxStream};
+use common::state_service::StateClient;
+use futures::channel::{mpsc, oneshot};
+use futures::{future, stream, SinkExt, StreamExt};
 
 use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
 use crypto::identity::PublicKey;
@@ -8,12 +13,18 @@ use crypto::invoice_id::InvoiceId;
 use crypto::payment_id::PaymentId;
 use crypto::uid::Uid;
 
-use proto::app_server::messages::{AppRequest, AppToAppServer};
+use proto::app_server::messages::{AppRequest, AppToAppServer, NodeReport, NodeReportMutation};
 use proto::funder::messages::{
-    AckClosePayment, Commit, CreatePayment, CreateTransaction, FriendsRoute, PaymentStatus,
-    RequestResult, ResponseClosePayment, TransactionResult,
+    AckClosePayment, Commit, CreatePayment, CreateTransaction, FriendsRoute, PaymentEvent,
+    PaymentStatus, Receipt, RequestResult, ResponseClosePayment, ResponsePaymentTimeline,
+    TransactionResult,
 };
 
+use timer::TimerClient;
+
+/// Maximum amount of complete payments an `AppBuyer` remembers the status of.
+const MAX_COMPLETE_PAYMENTS: usize = 0x100;
+
 // TODO: Different in naming convention from AppConfigError and AppRoutesError:
 #[derive(Debug)]
 pub enum BuyerError {
@@ -26,6 +37,186 @@ pub enum BuyerError {
     /// The request was issued, but no response was received.
     /// The request should be saved (By the caller) and resent at another time.
     NoResponse,
+    /// The first hop of the route can not be used to forward the transaction.
+    /// (Friend is disabled, inconsistent channel, closed requests or not enough credits)
+    FriendNotReady,
+    /// The node does not know about this payment.
+    /// (It was never created, or it was already acked)
+    PaymentNotFound,
+    /// The payment was canceled before it could complete.
+    /// Contains the ack uid of the canceled payment.
+    PaymentCanceled(Uid),
+}
+
+#[derive(Debug)]
+pub enum CancelPaymentError {
+    /// All the transactions of the payment were already committed, so it can not be canceled.
+    /// Contains the receipt and the ack uid of the successful payment.
+    AlreadyCommitted((Receipt, Uid)),
+    /// Some transactions of the payment are still in flight.
+    /// No new transactions may be added, and the payment will be canceled if any of them fails.
+    InProgress,
+    BuyerError(BuyerError),
+}
+
+impl From<BuyerError> for CancelPaymentError {
+    fn from(e: BuyerError) -> Self {
+        CancelPaymentError::BuyerError(e)
+    }
+}
+
+/// An event in the progress of a payment, see `AppBuyer::payment_progress()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentProgressEvent {
+    /// A transaction of the payment was committed.
+    /// `amount` is the amount of credits the destination will receive for this transaction.
+    TransactionCommitted { request_id: Uid, amount: u128 },
+    /// A transaction of the payment failed.
+    TransactionFailed { request_id: Uid },
+}
+
+/// Progress events of a single payment.
+pub type PaymentProgressStream = BoxStream<'static, PaymentProgressEvent>;
+
+pub(super) enum PaymentTransactionsRequest {
+    /// Attribute a new transaction (request_id) to a payment.
+    /// The response is sent once the transaction was registered.
+    AddTransaction((Uid, PaymentId, oneshot::Sender<()>)),
+    /// Subscribe to the progress events of a payment.
+    PaymentProgress(
+        (
+            PaymentId,
+            oneshot::Sender<mpsc::Receiver<PaymentProgressEvent>>,
+        ),
+    ),
+}
+
+#[derive(Debug)]
+pub(super) enum PaymentTransactionsError {
+    RequestStreamError,
+}
+
+enum PaymentTransactionsEvent {
+    TransactionResult(TransactionResult),
+    ResponseClosePayment(ResponseClosePayment),
+    Request(PaymentTransactionsRequest),
+    TransactionResultsClosed,
+}
+
+/// Send `progress_event` to all the subscribers of `payment_id`,
+/// forgetting subscribers that are gone.
+async fn send_progress_event(
+    subscribers: &mut HashMap<PaymentId, Vec<mpsc::Sender<PaymentProgressEvent>>>,
+    payment_id: PaymentId,
+    progress_event: PaymentProgressEvent,
+) {
+    let senders = match subscribers.remove(&payment_id) {
+        Some(senders) => senders,
+        None => return,
+    };
+    let mut new_senders = Vec::new();
+    for mut sender in senders {
+        if await!(sender.send(progress_event.clone())).is_ok() {
+            new_senders.push(sender);
+        }
+    }
+    if !new_senders.is_empty() {
+        subscribers.insert(payment_id, new_senders);
+    }
+}
+
+/// Keeps track of the payment of every transaction created through an `AppBuyer` (Or any of its
+/// clones), because transaction results only contain the request id of the transaction.
+///
+/// A transaction is forgotten once its result arrives. All the transactions and progress
+/// subscribers of a payment are forgotten once the payment is reported to be closed
+/// (Success or Canceled), no matter which buyer asked to close it.
+pub(super) async fn payment_transactions_service(
+    mut transaction_results_mc: MultiConsumerClient<TransactionResult>,
+    mut response_close_payments_mc: MultiConsumerClient<ResponseClosePayment>,
+    incoming_requests: mpsc::Receiver<PaymentTransactionsRequest>,
+) -> Result<(), PaymentTransactionsError> {
+    let incoming_transaction_results = await!(transaction_results_mc.request_stream())
+        .map_err(|_| PaymentTransactionsError::RequestStreamError)?
+        .map(PaymentTransactionsEvent::TransactionResult)
+        .chain(stream::once(future::ready(
+            PaymentTransactionsEvent::TransactionResultsClosed,
+        )));
+
+    let incoming_response_close_payments = await!(response_close_payments_mc.request_stream())
+        .map_err(|_| PaymentTransactionsError::RequestStreamError)?
+        .map(PaymentTransactionsEvent::ResponseClosePayment);
+
+    let incoming_requests = incoming_requests.map(PaymentTransactionsEvent::Request);
+
+    let mut events = select_streams![
+        incoming_transaction_results,
+        incoming_response_close_payments,
+        incoming_requests
+    ];
+
+    let mut transactions: HashMap<Uid, PaymentId> = HashMap::new();
+    let mut subscribers: HashMap<PaymentId, Vec<mpsc::Sender<PaymentProgressEvent>>> =
+        HashMap::new();
+
+    while let Some(event) = await!(events.next()) {
+        match event {
+            PaymentTransactionsEvent::TransactionResult(transaction_result) => {
+                let TransactionResult { request_id, result } = transaction_result;
+                let payment_id = match transactions.remove(&request_id) {
+                    Some(payment_id) => payment_id,
+                    None => continue,
+                };
+                let progress_event = match result {
+                    RequestResult::Success(commit) => PaymentProgressEvent::TransactionCommitted {
+                        request_id,
+                        amount: commit.dest_payment,
+                    },
+                    RequestResult::Failure => {
+                        PaymentProgressEvent::TransactionFailed { request_id }
+                    }
+                };
+                await!(send_progress_event(
+                    &mut subscribers,
+                    payment_id,
+                    progress_event
+                ));
+            }
+            PaymentTransactionsEvent::ResponseClosePayment(response_close_payment) => {
+                match response_close_payment.status {
+                    PaymentStatus::Success(_) | PaymentStatus::Canceled(_) => {
+                        let payment_id = response_close_payment.payment_id;
+                        transactions
+                            .retain(|_request_id, tx_payment_id| tx_payment_id != &payment_id);
+                        // Ends the progress streams of the payment:
+                        subscribers.remove(&payment_id);
+                    }
+                    PaymentStatus::PaymentNotFound | PaymentStatus::InProgress => {}
+                }
+            }
+            PaymentTransactionsEvent::Request(request) => match request {
+                PaymentTransactionsRequest::AddTransaction((
+                    request_id,
+                    payment_id,
+                    response_sender,
+                )) => {
+                    transactions.insert(request_id, payment_id);
+                    let _ = response_sender.send(());
+                }
+                PaymentTransactionsRequest::PaymentProgress((payment_id, response_sender)) => {
+                    let (sender, receiver) = mpsc::channel(0);
+                    if response_sender.send(receiver).is_ok() {
+                        subscribers
+                            .entry(payment_id)
+                            .or_insert_with(Vec::new)
+                            .push(sender);
+                    }
+                }
+            },
+            PaymentTransactionsEvent::TransactionResultsClosed => break,
+        }
+    }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -33,7 +224,17 @@ pub struct AppBuyer<R = OffstSystemRandom> {
     sender: mpsc::Sender<AppToAppServer>,
     transaction_results_mc: MultiConsumerClient<TransactionResult>,
     response_close_payments_mc: MultiConsumerClient<ResponseClosePayment>,
+    response_payment_timelines_mc: MultiConsumerClient<ResponsePaymentTimeline>,
     done_app_requests_mc: MultiConsumerClient<Uid>,
+    report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+    timer_client: TimerClient,
+    /// Status of payments that were reported to be in a terminal state (Success or Canceled).
+    /// Closing one of those payments again returns the remembered status.
+    complete_payments: HashMap<PaymentId, PaymentStatus>,
+    /// Order in which payments were added to `complete_payments`, oldest first.
+    complete_payments_order: VecDeque<PaymentId>,
+    /// See `payment_transactions_service()`.
+    payment_transactions_sender: mpsc::Sender<PaymentTransactionsRequest>,
     rng: R,
 }
 
@@ -45,18 +246,53 @@ where
         sender: mpsc::Sender<AppToAppServer>,
         transaction_results_mc: MultiConsumerClient<TransactionResult>,
         response_close_payments_mc: MultiConsumerClient<ResponseClosePayment>,
+        response_payment_timelines_mc: MultiConsumerClient<ResponsePaymentTimeline>,
         done_app_requests_mc: MultiConsumerClient<Uid>,
+        report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+        timer_client: TimerClient,
+        payment_transactions_sender: mpsc::Sender<PaymentTransactionsRequest>,
         rng: R,
     ) -> Self {
         AppBuyer {
             sender,
             transaction_results_mc,
             response_close_payments_mc,
+            response_payment_timelines_mc,
             done_app_requests_mc,
+            report_client,
+            timer_client,
+            complete_payments: HashMap::new(),
+            complete_payments_order: VecDeque::new(),
+            payment_transactions_sender,
             rng,
         }
     }
 
+    /// Remember the status of a complete payment.
+    /// The oldest remembered payment is forgotten if there are too many.
+    fn add_complete_payment(&mut self, payment_id: PaymentId, payment_status: PaymentStatus) {
+        if self
+            .complete_payments
+            .insert(payment_id.clone(), payment_status)
+            .is_some()
+        {
+            return;
+        }
+        self.complete_payments_order.push_back(payment_id);
+        if self.complete_payments_order.len() > MAX_COMPLETE_PAYMENTS {
+            if let Some(old_payment_id) = self.complete_payments_order.pop_front() {
+                let _ = self.complete_payments.remove(&old_payment_id);
+            }
+        }
+    }
+
+    fn remove_complete_payment(&mut self, payment_id: &PaymentId) {
+        if self.complete_payments.remove(payment_id).is_some() {
+            self.complete_payments_order
+                .retain(|complete_payment_id| complete_payment_id != payment_id);
+        }
+    }
+
     pub async fn create_payment(
         &mut self,
         payment_id: PaymentId,
@@ -100,6 +336,38 @@ where
         dest_payment: u128,
         fees: u128,
     ) -> Result<Commit, BuyerError> {
+        // Pre-flight check: Make sure that the first hop can take this transaction:
+        let (batch_mutable, _incoming_mutations) = await!(self.report_client.request_state())
+            .map_err(|_| BuyerError::ConnectivityError)?;
+        let node_report = batch_mutable.0;
+
+        // The first public key on the route is our own public key:
+        let first_hop_public_key = route.public_keys.get(1).ok_or(BuyerError::FriendNotReady)?;
+        let required_capacity = dest_payment.saturating_add(fees);
+        let is_ready = node_report
+            .funder_report
+            .friends
+            .get(first_hop_public_key)
+            .map_or(false, |friend_report| {
+                friend_report.is_good_for_routing(required_capacity)
+            });
+        if !is_ready {
+            return Err(BuyerError::FriendNotReady);
+        }
+
+        // Remember the payment of this transaction, for payment_progress():
+        let (response_sender, response_receiver) = oneshot::channel();
+        let payment_transactions_request = PaymentTransactionsRequest::AddTransaction((
+            request_id.clone(),
+            payment_id.clone(),
+            response_sender,
+        ));
+        await!(self
+            .payment_transactions_sender
+            .send(payment_transactions_request))
+        .map_err(|_| BuyerError::ConnectivityError)?;
+        await!(response_receiver).map_err(|_| BuyerError::ConnectivityError)?;
+
         let create_transaction = CreateTransaction {
             payment_id,
             request_id,
@@ -137,6 +405,11 @@ where
         &mut self,
         payment_id: PaymentId,
     ) -> Result<PaymentStatus, BuyerError> {
+        // No need to bother the node about a payment we already know is complete:
+        if let Some(payment_status) = self.complete_payments.get(&payment_id) {
+            return Ok(payment_status.clone());
+        }
+
         let app_request_id = Uid::new(&self.rng);
         let to_app_server = AppToAppServer::new(
             app_request_id,
@@ -154,6 +427,12 @@ where
                 // This is not our close request
                 continue;
             }
+            match &response_close_payment.status {
+                PaymentStatus::Success(_) | PaymentStatus::Canceled(_) => {
+                    self.add_complete_payment(payment_id, response_close_payment.status.clone());
+                }
+                PaymentStatus::PaymentNotFound | PaymentStatus::InProgress => {}
+            }
             return Ok(response_close_payment.status);
         }
 
@@ -161,6 +440,110 @@ where
         Err(BuyerError::NoResponse)
     }
 
+    /// Cancel a payment that was not fully committed yet.
+    /// On success the cancellation is also acked, so the node forgets about the payment.
+    pub async fn cancel_payment(
+        &mut self,
+        payment_id: PaymentId,
+    ) -> Result<(), CancelPaymentError> {
+        match await!(self.request_close_payment(payment_id.clone()))? {
+            PaymentStatus::Canceled(ack_uid) => {
+                await!(self.ack_close_payment(payment_id, ack_uid))?;
+                Ok(())
+            }
+            PaymentStatus::Success(receipt_ack_uid) => {
+                Err(CancelPaymentError::AlreadyCommitted(receipt_ack_uid))
+            }
+            PaymentStatus::InProgress => Err(CancelPaymentError::InProgress),
+            PaymentStatus::PaymentNotFound => Err(BuyerError::PaymentNotFound.into()),
+        }
+    }
+
+    /// Observe the transactions of a payment as they complete.
+    /// Only transactions created through this buyer (Or any of its clones) are reported.
+    /// The stream ends when the payment is reported to be closed (Success or Canceled), or when
+    /// the connection to the node is closed.
+    pub async fn payment_progress(
+        &mut self,
+        payment_id: &PaymentId,
+    ) -> Result<PaymentProgressStream, BuyerError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let payment_transactions_request =
+            PaymentTransactionsRequest::PaymentProgress((payment_id.clone(), response_sender));
+        await!(self
+            .payment_transactions_sender
+            .send(payment_transactions_request))
+        .map_err(|_| BuyerError::ConnectivityError)?;
+        let progress_receiver =
+            await!(response_receiver).map_err(|_| BuyerError::ConnectivityError)?;
+        Ok(Box::pin(progress_receiver))
+    }
+
+    /// Get the recorded events of a payment, oldest first.
+    /// The timeline is empty if the node does not know about this payment.
+    pub async fn payment_timeline(
+        &mut self,
+        payment_id: PaymentId,
+    ) -> Result<Vec<PaymentEvent>, BuyerError> {
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::GetPaymentTimeline(payment_id.clone()),
+        );
+
+        let mut incoming_response_payment_timeline =
+            await!(self.response_payment_timelines_mc.request_stream())
+                .map_err(|_| BuyerError::ConnectivityError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| BuyerError::ConnectivityError)?;
+
+        while let Some(response_payment_timeline) =
+            await!(incoming_response_payment_timeline.next())
+        {
+            if response_payment_timeline.payment_id != payment_id {
+                // This is not our request
+                continue;
+            }
+            return Ok(response_payment_timeline.timeline);
+        }
+
+        // We lost connectivity before we got any response:
+        Err(BuyerError::NoResponse)
+    }
+
+    /// Wait until the payment completes, for at most `timeout_ticks` timer ticks.
+    /// The payment is closed (No new transactions may be added), and then polled once every tick.
+    /// Returns `Ok(Some(receipt))` if the payment succeeded in time, and `Ok(None)` on timeout.
+    pub async fn payment_receipt_timeout(
+        &mut self,
+        payment_id: PaymentId,
+        timeout_ticks: usize,
+    ) -> Result<Option<Receipt>, BuyerError> {
+        let mut timer_stream = await!(self.timer_client.request_timer_stream())
+            .map_err(|_| BuyerError::ConnectivityError)?;
+
+        let mut ticks_left = timeout_ticks;
+        loop {
+            match await!(self.request_close_payment(payment_id.clone()))? {
+                PaymentStatus::Success((receipt, _ack_uid)) => return Ok(Some(receipt)),
+                PaymentStatus::Canceled(ack_uid) => {
+                    return Err(BuyerError::PaymentCanceled(ack_uid))
+                }
+                PaymentStatus::PaymentNotFound => return Err(BuyerError::PaymentNotFound),
+                PaymentStatus::InProgress => {}
+            }
+
+            if ticks_left == 0 {
+                return Ok(None);
+            }
+            // Wait for the next tick before asking again:
+            if await!(timer_stream.next()).is_none() {
+                return Err(BuyerError::ConnectivityError);
+            }
+            ticks_left -= 1;
+        }
+    }
+
     pub async fn ack_close_payment(
         &mut self,
         payment_id: PaymentId,
@@ -168,7 +551,7 @@ where
     ) -> Result<(), BuyerError> {
         let app_request_id = Uid::new(&self.rng);
         let ack_close_payment = AckClosePayment {
-            payment_id: payment_id,
+            payment_id: payment_id.clone(),
             ack_uid: ack_uid,
         };
 
@@ -187,6 +570,8 @@ where
         // Wait for a sign that our request was received:
         while let Some(done_request_id) = await!(incoming_done_requests.next()) {
             if app_request_id == done_request_id {
+                // The node forgets about the payment once the close is acked:
+                self.remove_complete_payment(&payment_id);
                 return Ok(());
             }
         }
@@ -195,3 +580,566 @@ where
         Err(BuyerError::NoResponse)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::{FutureExt, TryFutureExt};
+
+    use common::multi_consumer::multi_consumer_service;
+
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::hash_lock::{HashedLock, PlainLock, HASHED_LOCK_LEN, PLAIN_LOCK_LEN};
+    use crypto::identity::{Signature, SIGNATURE_LEN};
+    use crypto::invoice_id::INVOICE_ID_LEN;
+    use crypto::payment_id::PAYMENT_ID_LEN;
+    use crypto::test_utils::DummyRandom;
+    use crypto::uid::UID_LEN;
+
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
+    fn dummy_receipt() -> Receipt {
+        Receipt {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[0x03; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[0x04; PLAIN_LOCK_LEN]),
+            dest_payment: 10,
+            total_dest_payment: 10,
+            signature: Signature::from(&[0x05; SIGNATURE_LEN]),
+        }
+    }
+
+    /// Call `payment_receipt_timeout()` against a fake app server that answers every
+    /// RequestClosePayment with the next status from `statuses`.
+    async fn task_payment_receipt_timeout<S>(
+        statuses: Vec<PaymentStatus>,
+        timeout_ticks: usize,
+        mut spawner: S,
+    ) -> Result<Option<Receipt>, BuyerError>
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_response_close_payments_sender, incoming_response_close_payments) =
+            mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let response_close_payments_mc = MultiConsumerClient::new(requests_sender);
+        let response_close_payments_fut =
+            multi_consumer_service(incoming_response_close_payments, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(response_close_payments_fut).unwrap();
+
+        // Services that are not used by payment_receipt_timeout():
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let transaction_results_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let response_payment_timelines_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+        let (payment_transactions_sender, _incoming_requests) = mpsc::channel(0);
+
+        // A mock timer, ticking whenever the buyer waits for a tick:
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        spawner
+            .spawn(async move {
+                while let Some(mut tick_sender) = await!(tick_sender_receiver.next()) {
+                    while await!(tick_sender.send(TimerTick)).is_ok() {}
+                }
+            })
+            .unwrap();
+
+        // A fake app server, answering RequestClosePayment requests:
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+        spawner
+            .spawn(async move {
+                for status in statuses {
+                    let to_app_server: AppToAppServer = await!(app_server_receiver.next()).unwrap();
+                    let payment_id = match to_app_server.app_request {
+                        AppRequest::RequestClosePayment(payment_id) => payment_id,
+                        _ => unreachable!(),
+                    };
+                    let response_close_payment = ResponseClosePayment { payment_id, status };
+                    await!(incoming_response_close_payments_sender.send(response_close_payment))
+                        .unwrap();
+                }
+            })
+            .unwrap();
+
+        let mut app_buyer = AppBuyer::new(
+            sender,
+            transaction_results_mc,
+            response_close_payments_mc,
+            response_payment_timelines_mc,
+            done_app_requests_mc,
+            report_client,
+            timer_client,
+            payment_transactions_sender,
+            DummyRandom::new(&[1u8]),
+        );
+
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        await!(app_buyer.payment_receipt_timeout(payment_id, timeout_ticks))
+    }
+
+    #[test]
+    fn test_payment_receipt_timeout_success() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        let ack_uid = Uid::from(&[0x20; UID_LEN]);
+        let statuses = vec![
+            PaymentStatus::InProgress,
+            PaymentStatus::InProgress,
+            PaymentStatus::Success((dummy_receipt(), ack_uid)),
+        ];
+        let res = thread_pool.run(task_payment_receipt_timeout(
+            statuses,
+            5,
+            thread_pool.clone(),
+        ));
+        assert_eq!(res.unwrap(), Some(dummy_receipt()));
+    }
+
+    #[test]
+    fn test_payment_receipt_timeout_timeout() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        // One initial attempt, and then another attempt after every tick:
+        let statuses = vec![PaymentStatus::InProgress; 3];
+        let res = thread_pool.run(task_payment_receipt_timeout(
+            statuses,
+            2,
+            thread_pool.clone(),
+        ));
+        assert_eq!(res.unwrap(), None);
+    }
+
+    #[test]
+    fn test_payment_receipt_timeout_canceled() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        let ack_uid = Uid::from(&[0x20; UID_LEN]);
+        let statuses = vec![
+            PaymentStatus::InProgress,
+            PaymentStatus::Canceled(ack_uid.clone()),
+        ];
+        let res = thread_pool.run(task_payment_receipt_timeout(
+            statuses,
+            5,
+            thread_pool.clone(),
+        ));
+        match res {
+            Err(BuyerError::PaymentCanceled(res_ack_uid)) => assert_eq!(res_ack_uid, ack_uid),
+            _ => unreachable!(),
+        }
+    }
+
+    async fn task_request_close_payment_twice<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_response_close_payments_sender, incoming_response_close_payments) =
+            mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let response_close_payments_mc = MultiConsumerClient::new(requests_sender);
+        let response_close_payments_fut =
+            multi_consumer_service(incoming_response_close_payments, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(response_close_payments_fut).unwrap();
+
+        // Services that are not used by request_close_payment():
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let transaction_results_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let response_payment_timelines_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+        let (payment_transactions_sender, _incoming_requests) = mpsc::channel(0);
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        // A fake app server, answering a single RequestClosePayment request:
+        let ack_uid = Uid::from(&[0x20; UID_LEN]);
+        let status = PaymentStatus::Success((dummy_receipt(), ack_uid));
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+        let c_status = status.clone();
+        spawner
+            .spawn(async move {
+                let to_app_server: AppToAppServer = await!(app_server_receiver.next()).unwrap();
+                let payment_id = match to_app_server.app_request {
+                    AppRequest::RequestClosePayment(payment_id) => payment_id,
+                    _ => unreachable!(),
+                };
+                let response_close_payment = ResponseClosePayment {
+                    payment_id,
+                    status: c_status,
+                };
+                await!(incoming_response_close_payments_sender.send(response_close_payment))
+                    .unwrap();
+            })
+            .unwrap();
+
+        let mut app_buyer = AppBuyer::new(
+            sender,
+            transaction_results_mc,
+            response_close_payments_mc,
+            response_payment_timelines_mc,
+            done_app_requests_mc,
+            report_client,
+            timer_client,
+            payment_transactions_sender,
+            DummyRandom::new(&[1u8]),
+        );
+
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let res = await!(app_buyer.request_close_payment(payment_id.clone())).unwrap();
+        assert_eq!(res, status);
+
+        // The fake app server is gone, but the payment is known to be complete:
+        let res = await!(app_buyer.request_close_payment(payment_id.clone())).unwrap();
+        assert_eq!(res, status);
+    }
+
+    #[test]
+    fn test_request_close_payment_twice() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_request_close_payment_twice(thread_pool.clone()));
+    }
+
+    #[test]
+    fn test_complete_payments_bounded() {
+        let thread_pool = ThreadPool::new().unwrap();
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let transaction_results_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let response_close_payments_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let response_payment_timelines_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+        let (payment_transactions_sender, _incoming_requests) = mpsc::channel(0);
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(thread_pool);
+        let (sender, _app_server_receiver) = mpsc::channel(0);
+
+        let mut app_buyer = AppBuyer::new(
+            sender,
+            transaction_results_mc,
+            response_close_payments_mc,
+            response_payment_timelines_mc,
+            done_app_requests_mc,
+            report_client,
+            timer_client,
+            payment_transactions_sender,
+            DummyRandom::new(&[1u8]),
+        );
+
+        let payment_id = |i: usize| {
+            let mut payment_id_bytes = [0u8; PAYMENT_ID_LEN];
+            payment_id_bytes[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            PaymentId::from(&payment_id_bytes)
+        };
+        let ack_uid = Uid::from(&[0x20; UID_LEN]);
+        for i in 0..=MAX_COMPLETE_PAYMENTS {
+            app_buyer.add_complete_payment(payment_id(i), PaymentStatus::Canceled(ack_uid));
+        }
+        assert_eq!(app_buyer.complete_payments.len(), MAX_COMPLETE_PAYMENTS);
+        assert_eq!(
+            app_buyer.complete_payments_order.len(),
+            MAX_COMPLETE_PAYMENTS
+        );
+        // The oldest payment was forgotten:
+        assert!(!app_buyer.complete_payments.contains_key(&payment_id(0)));
+        assert!(app_buyer
+            .complete_payments
+            .contains_key(&payment_id(MAX_COMPLETE_PAYMENTS)));
+
+        app_buyer.remove_complete_payment(&payment_id(1));
+        assert_eq!(app_buyer.complete_payments.len(), MAX_COMPLETE_PAYMENTS - 1);
+        assert_eq!(
+            app_buyer.complete_payments_order.len(),
+            MAX_COMPLETE_PAYMENTS - 1
+        );
+    }
+
+    /// Call `cancel_payment()` against a fake app server that answers RequestClosePayment with
+    /// `status`, and acknowledges AckClosePayment requests.
+    async fn task_cancel_payment<S>(
+        status: PaymentStatus,
+        mut spawner: S,
+    ) -> Result<(), CancelPaymentError>
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_response_close_payments_sender, incoming_response_close_payments) =
+            mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let response_close_payments_mc = MultiConsumerClient::new(requests_sender);
+        let response_close_payments_fut =
+            multi_consumer_service(incoming_response_close_payments, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(response_close_payments_fut).unwrap();
+
+        let (mut incoming_done_app_requests_sender, incoming_done_app_requests) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let done_app_requests_fut =
+            multi_consumer_service(incoming_done_app_requests, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(done_app_requests_fut).unwrap();
+
+        // Services that are not used by cancel_payment():
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let transaction_results_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let response_payment_timelines_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+        let (payment_transactions_sender, _incoming_requests) = mpsc::channel(0);
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        // A fake app server:
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+        spawner
+            .spawn(async move {
+                while let Some(to_app_server) = await!(app_server_receiver.next()) {
+                    let to_app_server: AppToAppServer = to_app_server;
+                    match to_app_server.app_request {
+                        AppRequest::RequestClosePayment(payment_id) => {
+                            let response_close_payment = ResponseClosePayment {
+                                payment_id,
+                                status: status.clone(),
+                            };
+                            await!(incoming_response_close_payments_sender
+                                .send(response_close_payment))
+                            .unwrap();
+                        }
+                        AppRequest::AckClosePayment(_) => {
+                            await!(incoming_done_app_requests_sender
+                                .send(to_app_server.app_request_id))
+                            .unwrap();
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+            })
+            .unwrap();
+
+        let mut app_buyer = AppBuyer::new(
+            sender,
+            transaction_results_mc,
+            response_close_payments_mc,
+            response_payment_timelines_mc,
+            done_app_requests_mc,
+            report_client,
+            timer_client,
+            payment_transactions_sender,
+            DummyRandom::new(&[1u8]),
+        );
+
+        let payment_id = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        await!(app_buyer.cancel_payment(payment_id))
+    }
+
+    #[test]
+    fn test_cancel_payment() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        let ack_uid = Uid::from(&[0x20; UID_LEN]);
+
+        let status = PaymentStatus::Canceled(ack_uid.clone());
+        let res = thread_pool.run(task_cancel_payment(status, thread_pool.clone()));
+        assert!(res.is_ok());
+
+        let status = PaymentStatus::Success((dummy_receipt(), ack_uid.clone()));
+        let res = thread_pool.run(task_cancel_payment(status, thread_pool.clone()));
+        match res {
+            Err(CancelPaymentError::AlreadyCommitted((receipt, res_ack_uid))) => {
+                assert_eq!(receipt, dummy_receipt());
+                assert_eq!(res_ack_uid, ack_uid);
+            }
+            _ => unreachable!(),
+        }
+
+        let res = thread_pool.run(task_cancel_payment(
+            PaymentStatus::InProgress,
+            thread_pool.clone(),
+        ));
+        match res {
+            Err(CancelPaymentError::InProgress) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    async fn task_payment_progress<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_transaction_results_sender, incoming_transaction_results) =
+            mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let transaction_results_mc = MultiConsumerClient::new(requests_sender);
+        let transaction_results_fut =
+            multi_consumer_service(incoming_transaction_results, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(transaction_results_fut).unwrap();
+
+        let (mut incoming_response_close_payments_sender, incoming_response_close_payments) =
+            mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let response_close_payments_mc = MultiConsumerClient::new(requests_sender);
+        let response_close_payments_fut =
+            multi_consumer_service(incoming_response_close_payments, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(response_close_payments_fut).unwrap();
+
+        let (mut payment_transactions_sender, incoming_requests) = mpsc::channel(0);
+        let payment_transactions_fut = payment_transactions_service(
+            transaction_results_mc.clone(),
+            response_close_payments_mc.clone(),
+            incoming_requests,
+        )
+        .map_err(|e| error!("payment_transactions_service() error: {:?}", e))
+        .map(|_| ());
+        spawner.spawn(payment_transactions_fut).unwrap();
+
+        // Services that are not used by payment_progress():
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let response_payment_timelines_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        let (sender, _app_server_receiver) = mpsc::channel(0);
+
+        let mut app_buyer = AppBuyer::new(
+            sender,
+            transaction_results_mc,
+            response_close_payments_mc,
+            response_payment_timelines_mc,
+            done_app_requests_mc,
+            report_client,
+            timer_client,
+            payment_transactions_sender.clone(),
+            DummyRandom::new(&[1u8]),
+        );
+
+        let payment_id_a = PaymentId::from(&[0x10; PAYMENT_ID_LEN]);
+        let payment_id_b = PaymentId::from(&[0x11; PAYMENT_ID_LEN]);
+        let request_id_a0 = Uid::from(&[0x20; UID_LEN]);
+        let request_id_a1 = Uid::from(&[0x21; UID_LEN]);
+        let request_id_a2 = Uid::from(&[0x22; UID_LEN]);
+        let request_id_b = Uid::from(&[0x23; UID_LEN]);
+
+        // Register transactions, as create_transaction() does:
+        for (request_id, payment_id) in vec![
+            (request_id_a0.clone(), payment_id_a.clone()),
+            (request_id_a1.clone(), payment_id_a.clone()),
+            (request_id_a2.clone(), payment_id_a.clone()),
+            (request_id_b.clone(), payment_id_b.clone()),
+        ] {
+            let (response_sender, response_receiver) = oneshot::channel();
+            await!(
+                payment_transactions_sender.send(PaymentTransactionsRequest::AddTransaction((
+                    request_id,
+                    payment_id,
+                    response_sender
+                )))
+            )
+            .unwrap();
+            await!(response_receiver).unwrap();
+        }
+
+        let mut progress_stream = await!(app_buyer.payment_progress(&payment_id_a)).unwrap();
+
+        let commit = Commit {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            dest_payment: 7,
+            src_plain_lock: PlainLock::from(&[0x03; PLAIN_LOCK_LEN]),
+            dest_hashed_lock: HashedLock::from(&[0x04; HASHED_LOCK_LEN]),
+            signature: Signature::from(&[0x05; SIGNATURE_LEN]),
+        };
+
+        // Belongs to another payment:
+        await!(incoming_transaction_results_sender.send(TransactionResult {
+            request_id: request_id_b,
+            result: RequestResult::Success(commit.clone()),
+        }))
+        .unwrap();
+        // Unknown transaction:
+        await!(incoming_transaction_results_sender.send(TransactionResult {
+            request_id: Uid::from(&[0x24; UID_LEN]),
+            result: RequestResult::Failure,
+        }))
+        .unwrap();
+
+        await!(incoming_transaction_results_sender.send(TransactionResult {
+            request_id: request_id_a0.clone(),
+            result: RequestResult::Success(commit.clone()),
+        }))
+        .unwrap();
+        assert_eq!(
+            await!(progress_stream.next()).unwrap(),
+            PaymentProgressEvent::TransactionCommitted {
+                request_id: request_id_a0.clone(),
+                amount: 7,
+            }
+        );
+
+        await!(incoming_transaction_results_sender.send(TransactionResult {
+            request_id: request_id_a1.clone(),
+            result: RequestResult::Failure,
+        }))
+        .unwrap();
+        assert_eq!(
+            await!(progress_stream.next()).unwrap(),
+            PaymentProgressEvent::TransactionFailed {
+                request_id: request_id_a1,
+            }
+        );
+
+        // A transaction is forgotten once its result arrived:
+        await!(incoming_transaction_results_sender.send(TransactionResult {
+            request_id: request_id_a0,
+            result: RequestResult::Success(commit),
+        }))
+        .unwrap();
+
+        // The payment is closed. This ends the progress stream, and forgets the remaining
+        // transaction of the payment:
+        await!(
+            incoming_response_close_payments_sender.send(ResponseClosePayment {
+                payment_id: payment_id_a.clone(),
+                status: PaymentStatus::Canceled(Uid::from(&[0x30; UID_LEN])),
+            })
+        )
+        .unwrap();
+        assert!(await!(progress_stream.next()).is_none());
+
+        let mut progress_stream = await!(app_buyer.payment_progress(&payment_id_a)).unwrap();
+        await!(incoming_transaction_results_sender.send(TransactionResult {
+            request_id: request_id_a2,
+            result: RequestResult::Failure,
+        }))
+        .unwrap();
+
+        // The stream ends when the connection is closed:
+        drop(incoming_transaction_results_sender);
+        assert!(await!(progress_stream.next()).is_none());
+    }
+
+    #[test]
+    fn test_payment_progress() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_payment_progress(thread_pool.clone()));
+    }
+}
@@ -1,15 +1,22 @@
+use std::collections::HashSet;
+
 use futures::channel::mpsc;
 use futures::{SinkExt, StreamExt};
 
 use common::multi_consumer::MultiConsumerClient;
+use common::mutable_state::BatchMutable;
+use common::state_service::StateClient;
 
 use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
 use crypto::identity::{PublicKey, Signature};
 use crypto::uid::Uid;
 
-use proto::app_server::messages::{AppRequest, AppToAppServer, NamedRelayAddress, RelayAddress};
+use proto::app_server::messages::{
+    AppRequest, AppToAppServer, NamedRelayAddress, NodeReport, NodeReportMutation, RelayAddress,
+};
 use proto::funder::messages::{
-    AddFriend, Rate, ResetFriendChannel, SetFriendRate, SetFriendRelays, SetFriendRemoteMaxDebt,
+    AddFriend, Rate, ResetFriendChannel, SetFriendNote, SetFriendRate, SetFriendRelays,
+    SetFriendRemoteMaxDebt,
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
@@ -20,6 +27,7 @@ pub struct AppConfigError;
 pub struct AppConfig<R = OffstSystemRandom> {
     sender: mpsc::Sender<AppToAppServer>,
     done_app_requests_mc: MultiConsumerClient<Uid>,
+    report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
     rng: R,
 }
 
@@ -30,11 +38,13 @@ where
     pub(super) fn new(
         sender: mpsc::Sender<AppToAppServer>,
         done_app_requests_mc: MultiConsumerClient<Uid>,
+        report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
         rng: R,
     ) -> Self {
         AppConfig {
             sender,
             done_app_requests_mc,
+            report_client,
             rng,
         }
     }
@@ -60,6 +70,29 @@ where
         Err(AppConfigError)
     }
 
+    /// Send a batch of requests, and wait until all of them were received.
+    async fn send_requests(&mut self, app_requests: Vec<AppRequest>) -> Result<(), AppConfigError> {
+        // Start listening to done requests:
+        let mut incoming_done_requests =
+            await!(self.done_app_requests_mc.request_stream()).map_err(|_| AppConfigError)?;
+
+        let mut pending_request_ids = HashSet::new();
+        for app_request in app_requests {
+            // Randomly generate a new app_request_id:
+            let app_request_id = Uid::new(&self.rng);
+            pending_request_ids.insert(app_request_id.clone());
+            let to_app_server = AppToAppServer::new(app_request_id, app_request);
+            await!(self.sender.send(to_app_server)).map_err(|_| AppConfigError)?;
+        }
+
+        // Wait for all of our requests to be acknowledged:
+        while !pending_request_ids.is_empty() {
+            let done_request_id = await!(incoming_done_requests.next()).ok_or(AppConfigError)?;
+            pending_request_ids.remove(&done_request_id);
+        }
+        Ok(())
+    }
+
     pub async fn add_relay(
         &mut self,
         named_relay_address: NamedRelayAddress,
@@ -85,6 +118,7 @@ where
             friend_public_key,
             relays,
             name,
+            note: String::new(),
             balance,
         };
         await!(self.send_request(AppRequest::AddFriend(add_friend)))
@@ -137,6 +171,21 @@ where
         await!(self.send_request(AppRequest::CloseFriend(friend_public_key)))
     }
 
+    /// Temporarily stop routing requests through a friend, without closing the channel.
+    pub async fn freeze_friend(
+        &mut self,
+        friend_public_key: PublicKey,
+    ) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::FreezeFriend(friend_public_key)))
+    }
+
+    pub async fn unfreeze_friend(
+        &mut self,
+        friend_public_key: PublicKey,
+    ) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::UnfreezeFriend(friend_public_key)))
+    }
+
     pub async fn set_friend_remote_max_debt(
         &mut self,
         friend_public_key: PublicKey,
@@ -151,6 +200,31 @@ where
         )))
     }
 
+    /// Set the remote max debt of all the friends that currently appear in the node report.
+    /// Returns only after all the requests were acknowledged.
+    pub async fn set_max_debt_for_all_friends(
+        &mut self,
+        max_debt: u128,
+    ) -> Result<(), AppConfigError> {
+        let (batch_mutable, _incoming_mutations) =
+            await!(self.report_client.request_state()).map_err(|_| AppConfigError)?;
+        let node_report = batch_mutable.0;
+
+        let app_requests = node_report
+            .funder_report
+            .friends
+            .keys()
+            .map(|friend_public_key| {
+                AppRequest::SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt {
+                    friend_public_key: friend_public_key.clone(),
+                    remote_max_debt: max_debt,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        await!(self.send_requests(app_requests))
+    }
+
     pub async fn set_friend_rate(
         &mut self,
         friend_public_key: PublicKey,
@@ -163,6 +237,19 @@ where
         await!(self.send_request(AppRequest::SetFriendRate(set_friend_rate)))
     }
 
+    /// Set free text notes about a friend. Notes are only kept locally.
+    pub async fn set_friend_note(
+        &mut self,
+        friend_public_key: PublicKey,
+        note: String,
+    ) -> Result<(), AppConfigError> {
+        let set_friend_note = SetFriendNote {
+            friend_public_key,
+            note,
+        };
+        await!(self.send_request(AppRequest::SetFriendNote(set_friend_note)))
+    }
+
     pub async fn reset_friend_channel(
         &mut self,
         friend_public_key: PublicKey,
@@ -192,4 +279,158 @@ where
     ) -> Result<(), AppConfigError> {
         await!(self.send_request(AppRequest::RemoveIndexServer(index_public_key)))
     }
+
+    /// Replace the index server `old_public_key` with `new_index_server`, without a moment
+    /// where neither of them is configured.
+    pub async fn rotate_index_server(
+        &mut self,
+        old_public_key: PublicKey,
+        new_index_server: NamedIndexServerAddress,
+    ) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::RotateIndexServer((
+            old_public_key,
+            new_index_server
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::{FutureExt, TryFutureExt};
+
+    use common::multi_consumer::multi_consumer_service;
+    use common::mutable_state::MutableState;
+    use common::state_service::state_service;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::index_client::messages::IndexClientReport;
+    use proto::report::messages::{
+        AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, FunderReport,
+        FunderReportMutation,
+    };
+
+    /// Create a NodeReport with `num_friends` friends
+    fn create_node_report(num_friends: u8) -> NodeReport {
+        let mut funder_report = FunderReport {
+            local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            relays: Default::default(),
+            friends: Default::default(),
+            num_open_invoices: 0,
+            invoices_progress: Default::default(),
+            num_payments: 0,
+            num_open_transactions: 0,
+            total_frozen_credits: (0, 0),
+            max_friends: 0x100,
+            max_node_relays: 0x10,
+        };
+
+        for i in 0..num_friends {
+            let add_friend_report = AddFriendReport {
+                friend_public_key: PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+                name: format!("friend{}", i),
+                note: String::new(),
+                relays: Vec::new(),
+                balance: 0,
+                opt_last_incoming_move_token: None,
+                channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+                    local_reset_terms_balance: 0,
+                    opt_remote_reset_terms: None,
+                }),
+            };
+            funder_report
+                .mutate(&FunderReportMutation::AddFriend(add_friend_report))
+                .unwrap();
+        }
+
+        NodeReport {
+            funder_report,
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                circuit_states: HashMap::new(),
+            },
+        }
+    }
+
+    async fn task_set_max_debt_for_all_friends<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let node_report = create_node_report(5);
+
+        let (_incoming_mutations_sender, incoming_mutations) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+        let state_service_fut = state_service(
+            incoming_requests,
+            BatchMutable(node_report),
+            incoming_mutations,
+        )
+        .map_err(|e| error!("state_service() error: {:?}", e))
+        .map(|_| ());
+        spawner.spawn(state_service_fut).unwrap();
+
+        let (mut incoming_done_app_requests_sender, incoming_done_app_requests) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let done_app_requests_fut =
+            multi_consumer_service(incoming_done_app_requests, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(done_app_requests_fut).unwrap();
+
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+
+        // A fake app server, acknowledging every incoming request:
+        let (mut max_debts_sender, mut max_debts_receiver) = mpsc::channel(0);
+        spawner
+            .spawn(async move {
+                let mut max_debts = HashMap::new();
+                for _ in 0..5 {
+                    let to_app_server: AppToAppServer = await!(app_server_receiver.next()).unwrap();
+                    match to_app_server.app_request {
+                        AppRequest::SetFriendRemoteMaxDebt(set_friend_remote_max_debt) => {
+                            max_debts.insert(
+                                set_friend_remote_max_debt.friend_public_key,
+                                set_friend_remote_max_debt.remote_max_debt,
+                            );
+                        }
+                        _ => unreachable!(),
+                    }
+                    await!(incoming_done_app_requests_sender.send(to_app_server.app_request_id))
+                        .unwrap();
+                }
+                await!(max_debts_sender.send(max_debts)).unwrap();
+            })
+            .unwrap();
+
+        let mut app_config = AppConfig::new(
+            sender,
+            done_app_requests_mc,
+            report_client,
+            DummyRandom::new(&[1u8]),
+        );
+        await!(app_config.set_max_debt_for_all_friends(100)).unwrap();
+
+        let max_debts = await!(max_debts_receiver.next()).unwrap();
+        assert_eq!(max_debts.len(), 5);
+        for i in 0..5u8 {
+            let friend_public_key = PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+            assert_eq!(max_debts.get(&friend_public_key), Some(&100));
+        }
+    }
+
+    #[test]
+    fn test_set_max_debt_for_all_friends() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_set_max_debt_for_all_friends(thread_pool.clone()));
+    }
 }
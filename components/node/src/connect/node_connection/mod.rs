@@ -4,6 +4,13 @@ pub mod report;
 pub mod routes;
 pub mod seller;
 
+mod builder;
 mod node_connection;
+mod reconnect;
 
+pub use self::builder::{
+    BuyerNodeConnection, ConfigNodeConnection, NodeConnectionBuilder, RoutesNodeConnection,
+    SellerNodeConnection,
+};
 pub use self::node_connection::{NodeConnection, NodeConnectionError, NodeConnectionTuple};
+pub use self::reconnect::{ReconnectError, ReconnectingNodeConnection};
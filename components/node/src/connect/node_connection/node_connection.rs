@@ -1,24 +1,29 @@
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
 use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
 use proto::app_server::messages::{AppPermissions, AppServerToApp, AppToAppServer, NodeReport};
 
 use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
+use crypto::uid::Uid;
 
 use common::conn::ConnPair;
 use common::multi_consumer::{multi_consumer_service, MultiConsumerClient};
 use common::mutable_state::BatchMutable;
 use common::state_service::{state_service, StateClient};
 
+use timer::TimerClient;
+
 use super::config::AppConfig;
-use super::report::AppReport;
+use super::report::{AppReport, ReportHistoryClient};
 use super::routes::AppRoutes;
-use super::buyer::AppBuyer;
+use super::buyer::{payment_transactions_service, AppBuyer};
 use super::seller::AppSeller;
 
+/// Permissions of the app, session token of the app, initial node report and a connection.
 pub type NodeConnectionTuple = (
     AppPermissions,
+    Uid,
     NodeReport,
     ConnPair<AppToAppServer, AppServerToApp>,
 );
@@ -26,6 +31,8 @@ pub type NodeConnectionTuple = (
 #[derive(Debug)]
 pub enum NodeConnectionError {
     SpawnError,
+    /// The app was not granted the permission required for the requested capability
+    MissingPermission,
 }
 
 // TODO: Do we need a way to close this connection?
@@ -37,6 +44,9 @@ pub struct NodeConnection<R = OffstSystemRandom> {
     opt_routes: Option<AppRoutes<R>>,
     opt_buyer: Option<AppBuyer<R>>,
     opt_seller: Option<AppSeller<R>>,
+    /// Session token given by the node. Presenting it on the next connection
+    /// allows to receive responses to requests sent over this connection.
+    app_session: Uid,
     rng: R,
 }
 
@@ -46,13 +56,42 @@ where
 {
     pub fn new<S>(
         conn_tuple: NodeConnectionTuple,
+        timer_client: TimerClient,
+        snapshot_interval_ticks: usize,
         rng: R,
         spawner: &mut S,
     ) -> Result<Self, NodeConnectionError>
     where
         S: Spawn,
     {
-        let (app_permissions, node_report, (sender, mut receiver)) = conn_tuple;
+        let report_history =
+            ReportHistoryClient::spawn(timer_client.clone(), snapshot_interval_ticks, spawner)
+                .map_err(|_| NodeConnectionError::SpawnError)?;
+        let (node_connection, _closed_receiver) = NodeConnection::new_with_closed_receiver(
+            conn_tuple,
+            timer_client,
+            &report_history,
+            rng,
+            spawner,
+        )?;
+        Ok(node_connection)
+    }
+
+    /// Create a NodeConnection, together with a receiver that is notified (By the sender being
+    /// dropped) when the connection to the node is closed.
+    /// The reports of the connection are recorded as a new session of `report_history`.
+    pub(super) fn new_with_closed_receiver<S>(
+        conn_tuple: NodeConnectionTuple,
+        timer_client: TimerClient,
+        report_history: &ReportHistoryClient,
+        rng: R,
+        spawner: &mut S,
+    ) -> Result<(Self, oneshot::Receiver<()>), NodeConnectionError>
+    where
+        S: Spawn,
+    {
+        let (app_permissions, app_session, node_report, (sender, mut receiver)) = conn_tuple;
+        let (closed_sender, closed_receiver) = oneshot::channel::<()>();
 
         let (mut incoming_mutations_sender, incoming_mutations) = mpsc::channel(0);
         let (requests_sender, incoming_requests) = mpsc::channel(0);
@@ -68,6 +107,10 @@ where
             .spawn(state_service_fut)
             .map_err(|_| NodeConnectionError::SpawnError)?;
 
+        report_history
+            .add_session(report_client.clone(), spawner)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
         let (mut incoming_routes_sender, incoming_routes) = mpsc::channel(0);
         let (requests_sender, incoming_requests) = mpsc::channel(0);
         let routes_mc = MultiConsumerClient::new(requests_sender);
@@ -102,6 +145,18 @@ where
             .spawn(response_close_payments_fut)
             .map_err(|_| NodeConnectionError::SpawnError)?;
 
+        let (mut incoming_response_payment_timelines_sender, incoming_response_payment_timelines) =
+            mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let response_payment_timelines_mc = MultiConsumerClient::new(requests_sender);
+        let response_payment_timelines_fut =
+            multi_consumer_service(incoming_response_payment_timelines, incoming_requests)
+                .map_err(|e| error!("Buyer multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner
+            .spawn(response_payment_timelines_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
         let (mut incoming_done_app_requests_sender, incoming_done_app_requests) = mpsc::channel(0);
         let (requests_sender, incoming_requests) = mpsc::channel(0);
         let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
@@ -115,6 +170,8 @@ where
 
         spawner
             .spawn(async move {
+                // Dropped when this loop exits:
+                let _closed_sender = closed_sender;
                 while let Some(message) = await!(receiver.next()) {
                     match message {
                         AppServerToApp::TransactionResult(transaction_result) => {
@@ -126,6 +183,10 @@ where
                             let _ = await!(incoming_response_close_payments_sender
                                 .send(response_close_payment));
                         }
+                        AppServerToApp::ResponsePaymentTimeline(response_payment_timeline) => {
+                            let _ = await!(incoming_response_payment_timelines_sender
+                                .send(response_payment_timeline));
+                        }
                         AppServerToApp::Report(_node_report) => {
                             // TODO: Maybe somehow redesign the type AppServerToApp
                             // so that we don't have this edge case?
@@ -144,6 +205,14 @@ where
                         AppServerToApp::ResponseRoutes(client_response_routes) => {
                             let _ = await!(incoming_routes_sender.send(client_response_routes));
                         }
+                        AppServerToApp::ResponseDeadLetterQueue(dead_letters) => {
+                            // Late transaction results are delivered like any other result:
+                            for (_request_id, transaction_result) in dead_letters {
+                                let _ =
+                                    await!(incoming_transaction_results_sender
+                                        .send(transaction_result));
+                            }
+                        }
                     }
                 }
             })
@@ -153,6 +222,7 @@ where
             Some(AppConfig::new(
                 sender.clone(),
                 done_app_requests_mc.clone(),
+                report_client.clone(),
                 rng.clone(),
             ))
         } else {
@@ -170,11 +240,27 @@ where
         };
 
         let opt_buyer = if app_permissions.buyer {
+            let (payment_transactions_sender, incoming_requests) = mpsc::channel(0);
+            let payment_transactions_fut = payment_transactions_service(
+                transaction_results_mc.clone(),
+                response_close_payments_mc.clone(),
+                incoming_requests,
+            )
+            .map_err(|e| error!("payment_transactions_service() error: {:?}", e))
+            .map(|_| ());
+            spawner
+                .spawn(payment_transactions_fut)
+                .map_err(|_| NodeConnectionError::SpawnError)?;
+
             Some(AppBuyer::new(
                 sender.clone(),
                 transaction_results_mc.clone(),
                 response_close_payments_mc.clone(),
+                response_payment_timelines_mc.clone(),
                 done_app_requests_mc.clone(),
+                report_client.clone(),
+                timer_client.clone(),
+                payment_transactions_sender,
                 rng.clone(),
             ))
         } else {
@@ -191,14 +277,16 @@ where
             None
         };
 
-        Ok(NodeConnection {
-            report: AppReport::new(report_client.clone()),
+        let node_connection = NodeConnection {
+            report: AppReport::new(report_client.clone(), report_history.requests_sender()),
             opt_config,
             opt_routes,
             opt_buyer,
             opt_seller,
+            app_session,
             rng,
-        })
+        };
+        Ok((node_connection, closed_receiver))
     }
 
     pub fn report(&mut self) -> &mut AppReport {
@@ -220,4 +308,8 @@ where
     pub fn seller(&mut self) -> Option<&mut AppSeller<R>> {
         self.opt_seller.as_mut()
     }
+
+    pub fn app_session(&self) -> &Uid {
+        &self.app_session
+    }
 }
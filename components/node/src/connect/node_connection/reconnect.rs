@@ -0,0 +1,322 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::RemoteHandle;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use common::conn::BoxFuture;
+
+use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
+use crypto::uid::Uid;
+
+use timer::TimerClient;
+
+use super::node_connection::{NodeConnection, NodeConnectionError, NodeConnectionTuple};
+use super::report::ReportHistoryClient;
+
+#[derive(Debug)]
+pub enum ReconnectError {
+    ConnectError,
+    NodeConnectionError(NodeConnectionError),
+    RequestTimerStreamError,
+    TimerClosed,
+    SpawnError,
+    /// Reconnection was stopped due to an error
+    ReconnectStopped,
+}
+
+/// Wait for the current connection to close, and then connect again using `connector`.
+/// `connector` is given the session token of the closed connection.
+/// Failed connection attempts are retried every `backoff_ticks` ticks.
+/// All the connections share `report_history`.
+async fn reconnect_loop<C, R, S>(
+    connector: C,
+    mut app_session: Uid,
+    mut closed_receiver: oneshot::Receiver<()>,
+    mut timer_client: TimerClient,
+    report_history: ReportHistoryClient,
+    backoff_ticks: usize,
+    rng: R,
+    mut spawner: S,
+    mut connections_sender: mpsc::Sender<NodeConnection<R>>,
+) -> Result<(), ReconnectError>
+where
+    C: Fn(Option<Uid>) -> BoxFuture<'static, Option<NodeConnectionTuple>>,
+    R: CryptoRandom + Clone,
+    S: Spawn,
+{
+    loop {
+        // Resolves (With an error) when the sender is dropped:
+        let _ = await!(&mut closed_receiver);
+        warn!("reconnect_loop(): Connection to node was closed. Reconnecting...");
+
+        // The timer stream is only requested if the first connection attempt fails:
+        let mut opt_timer_stream = None;
+        let conn_tuple = loop {
+            if let Some(conn_tuple) = await!(connector(Some(app_session.clone()))) {
+                break conn_tuple;
+            }
+
+            if opt_timer_stream.is_none() {
+                opt_timer_stream = Some(
+                    await!(timer_client.request_timer_stream())
+                        .map_err(|_| ReconnectError::RequestTimerStreamError)?,
+                );
+            }
+            let timer_stream = opt_timer_stream.as_mut().unwrap();
+            for _ in 0..backoff_ticks {
+                await!(timer_stream.next()).ok_or(ReconnectError::TimerClosed)?;
+            }
+        };
+
+        let (node_connection, new_closed_receiver) = NodeConnection::new_with_closed_receiver(
+            conn_tuple,
+            timer_client.clone(),
+            &report_history,
+            rng.clone(),
+            &mut spawner,
+        )
+        .map_err(ReconnectError::NodeConnectionError)?;
+        closed_receiver = new_closed_receiver;
+        app_session = node_connection.app_session().clone();
+
+        if await!(connections_sender.send(node_connection)).is_err() {
+            // The ReconnectingNodeConnection was dropped:
+            return Ok(());
+        }
+    }
+}
+
+/// A NodeConnection that connects again whenever the connection to the node is closed.
+///
+/// Every new connection starts from the initial NodeReport sent by the node.
+/// The report history (`AppReport::get_report_as_of_tick()` and `AppReport::diff_since_tick()`)
+/// is kept across connections, and its ticks keep counting.
+/// Every new connection presents the session token of the previous connection, so that the node
+/// delivers responses to requests sent over the previous connection through the new connection.
+/// Requests that were in flight when the connection was closed still fail
+/// (For example, with `BuyerError::NoResponse`).
+pub struct ReconnectingNodeConnection<R = OffstSystemRandom> {
+    node_connection: NodeConnection<R>,
+    incoming_connections: mpsc::Receiver<NodeConnection<R>>,
+    /// Dropping the handle stops reconnecting
+    _reconnect_handle: RemoteHandle<Result<(), ReconnectError>>,
+}
+
+impl<R> ReconnectingNodeConnection<R>
+where
+    R: CryptoRandom + Clone + Send + 'static,
+{
+    pub async fn new<C, S>(
+        connector: C,
+        timer_client: TimerClient,
+        snapshot_interval_ticks: usize,
+        backoff_ticks: usize,
+        rng: R,
+        mut spawner: S,
+    ) -> Result<Self, ReconnectError>
+    where
+        C: Fn(Option<Uid>) -> BoxFuture<'static, Option<NodeConnectionTuple>> + Send + 'static,
+        S: Spawn + Clone + Send + 'static,
+    {
+        let conn_tuple = await!(connector(None)).ok_or(ReconnectError::ConnectError)?;
+        let report_history =
+            ReportHistoryClient::spawn(timer_client.clone(), snapshot_interval_ticks, &mut spawner)
+                .map_err(|_| ReconnectError::SpawnError)?;
+        let (node_connection, closed_receiver) = NodeConnection::new_with_closed_receiver(
+            conn_tuple,
+            timer_client.clone(),
+            &report_history,
+            rng.clone(),
+            &mut spawner,
+        )
+        .map_err(ReconnectError::NodeConnectionError)?;
+
+        let (connections_sender, incoming_connections) = mpsc::channel(0);
+        let reconnect_fut = reconnect_loop(
+            connector,
+            node_connection.app_session().clone(),
+            closed_receiver,
+            timer_client,
+            report_history,
+            backoff_ticks,
+            rng,
+            spawner.clone(),
+            connections_sender,
+        );
+        let reconnect_handle = spawner
+            .spawn_with_handle(reconnect_fut)
+            .map_err(|_| ReconnectError::SpawnError)?;
+
+        Ok(ReconnectingNodeConnection {
+            node_connection,
+            incoming_connections,
+            _reconnect_handle: reconnect_handle,
+        })
+    }
+
+    /// The most recently established connection.
+    /// Note that the connection might be closed, if reconnecting is still in progress.
+    pub fn connection(&mut self) -> &mut NodeConnection<R> {
+        while let Ok(Some(node_connection)) = self.incoming_connections.try_next() {
+            self.node_connection = node_connection;
+        }
+        &mut self.node_connection
+    }
+
+    /// Wait until a new connection is established (After the current connection is closed).
+    pub async fn wait_reconnect(&mut self) -> Result<&mut NodeConnection<R>, ReconnectError> {
+        self.node_connection =
+            await!(self.incoming_connections.next()).ok_or(ReconnectError::ReconnectStopped)?;
+        Ok(&mut self.node_connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use futures::executor::ThreadPool;
+    use futures::future;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::test_utils::DummyRandom;
+    use crypto::uid::UID_LEN;
+
+    use proto::app_server::messages::{AppPermissions, NodeReport};
+    use proto::index_client::messages::IndexClientReport;
+    use proto::report::messages::FunderReport;
+
+    use timer::dummy_timer_multi_sender;
+
+    use crate::connect::ReportDiff;
+
+    fn create_node_report(local_public_key: PublicKey) -> NodeReport {
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key,
+                relays: Default::default(),
+                friends: Default::default(),
+                num_open_invoices: 0,
+                invoices_progress: Default::default(),
+                num_payments: 0,
+                num_open_transactions: 0,
+                total_frozen_credits: (0, 0),
+                max_friends: 0x100,
+                max_node_relays: 0x10,
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                circuit_states: HashMap::new(),
+            },
+        }
+    }
+
+    async fn task_reconnecting_node_connection<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let app_permissions = AppPermissions {
+            routes: false,
+            buyer: true,
+            seller: false,
+            config: false,
+            max_requests_per_second: None,
+            friend_permissions: None,
+        };
+
+        // Connections the connector will hand out, in order:
+        let mut conn_tuples = Vec::new();
+        let mut node_senders = Vec::new();
+        let mut node_receivers = Vec::new();
+        for i in 0..2u8 {
+            let (app_sender, node_receiver) = mpsc::channel(0);
+            let (node_sender, app_receiver) = mpsc::channel(0);
+            let node_report = create_node_report(PublicKey::from(&[i; PUBLIC_KEY_LEN]));
+            conn_tuples.push((
+                app_permissions.clone(),
+                Uid::from(&[i; UID_LEN]),
+                node_report,
+                (app_sender, app_receiver),
+            ));
+            node_senders.push(node_sender);
+            node_receivers.push(node_receiver);
+        }
+        conn_tuples.reverse();
+        let conn_tuples = Arc::new(Mutex::new(conn_tuples));
+
+        // Session tokens presented by the connector, in order:
+        let app_sessions = Arc::new(Mutex::new(Vec::new()));
+        let c_app_sessions = app_sessions.clone();
+
+        let connector =
+            move |opt_app_session: Option<Uid>| -> BoxFuture<'static, Option<NodeConnectionTuple>> {
+                c_app_sessions.lock().unwrap().push(opt_app_session);
+                let opt_conn_tuple = conn_tuples.lock().unwrap().pop();
+                Box::pin(future::ready(opt_conn_tuple))
+            };
+
+        let mut reconnecting = await!(ReconnectingNodeConnection::new(
+            connector,
+            timer_client,
+            1,
+            1,
+            DummyRandom::new(&[1u8]),
+            spawner.clone()
+        ))
+        .unwrap();
+
+        let (node_report, _) =
+            await!(reconnecting.connection().report().incoming_reports()).unwrap();
+        assert_eq!(
+            node_report.funder_report.local_public_key,
+            PublicKey::from(&[0; PUBLIC_KEY_LEN])
+        );
+
+        // The node closes the first connection:
+        node_senders.remove(0);
+
+        // The report is rebuilt from the initial report of the new connection:
+        let node_connection = await!(reconnecting.wait_reconnect()).unwrap();
+        let (node_report, _) = await!(node_connection.report().incoming_reports()).unwrap();
+        assert_eq!(
+            node_report.funder_report.local_public_key,
+            PublicKey::from(&[1; PUBLIC_KEY_LEN])
+        );
+        assert!(reconnecting.connection().buyer().is_some());
+
+        // The report history is shared by both connections. The changes since the first
+        // connection can only be given as a full report:
+        loop {
+            match await!(reconnecting.connection().report().diff_since_tick(0)).unwrap() {
+                ReportDiff::Report(node_report) => {
+                    assert_eq!(
+                        node_report.funder_report.local_public_key,
+                        PublicKey::from(&[1; PUBLIC_KEY_LEN])
+                    );
+                    break;
+                }
+                // The new session was not registered yet:
+                ReportDiff::Mutations(_) => {}
+            }
+        }
+
+        // The first connection starts a new session, and the second connection
+        // resumes the session of the first connection:
+        assert_eq!(
+            *app_sessions.lock().unwrap(),
+            vec![None, Some(Uid::from(&[0; UID_LEN]))]
+        );
+    }
+
+    #[test]
+    fn test_reconnecting_node_connection() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_reconnecting_node_connection(thread_pool.clone()));
+    }
+}
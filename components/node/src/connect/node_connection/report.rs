@@ -1,23 +1,87 @@
-use futures::channel::mpsc;
+use std::collections::VecDeque;
 
-use common::mutable_state::BatchMutable;
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
+
+use common::int_convert::usize_to_u64;
+use common::mutable_state::{BatchMutable, MutableState};
+use common::select_streams::{select_streams, BoxStream};
 use common::state_service::StateClient;
+use crypto::identity::PublicKey;
 use proto::app_server::messages::{NodeReport, NodeReportMutation};
+use proto::report::messages::{FriendReport, FunderReportMutation};
+
+use timer::{TimerClient, TimerTick};
+
+/// Maximum amount of report snapshots we keep in memory for historical queries.
+const MAX_REPORT_SNAPSHOTS: usize = 0x40;
+
+/// Maximum amount of mutations we keep in memory for `diff_since_tick()` queries.
+const MAX_LOG_SIZE: usize = 0x400;
 
 #[derive(Debug)]
 pub struct AppReportError;
 
+/// Does this mutation change the set of friends, or the state of some friend?
+fn is_friend_mutation(mutation: &NodeReportMutation) -> bool {
+    match mutation {
+        NodeReportMutation::Funder(FunderReportMutation::AddFriend(_))
+        | NodeReportMutation::Funder(FunderReportMutation::RemoveFriend(_))
+        | NodeReportMutation::Funder(FunderReportMutation::FriendReportMutation(_)) => true,
+        _ => false,
+    }
+}
+
+/// Does this mutation change the state of the friend `public_key`?
+fn is_mutation_of_friend(mutation: &NodeReportMutation, public_key: &PublicKey) -> bool {
+    match mutation {
+        NodeReportMutation::Funder(FunderReportMutation::AddFriend(add_friend_report)) => {
+            &add_friend_report.friend_public_key == public_key
+        }
+        NodeReportMutation::Funder(FunderReportMutation::FriendReportMutation((
+            friend_public_key,
+            _,
+        ))) => friend_public_key == public_key,
+        _ => false,
+    }
+}
+
+/// Changes to the NodeReport since a given tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportDiff {
+    /// All the mutations applied since the requested tick, in order.
+    Mutations(Vec<NodeReportMutation>),
+    /// The requested tick is older than the mutation log. The full current report is returned
+    /// instead.
+    Report(NodeReport),
+}
+
+/// A session of the report history: The initial report sent by the node when a connection was
+/// established, and the mutations received over the same connection.
+pub(super) type ReportSession = (NodeReport, mpsc::Receiver<Vec<NodeReportMutation>>);
+
+pub enum ReportHistoryRequest {
+    ReportAsOfTick((u64, oneshot::Sender<Option<NodeReport>>)),
+    DiffSinceTick((u64, oneshot::Sender<Option<ReportDiff>>)),
+}
+
 #[derive(Clone)]
 pub struct AppReport {
     report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+    history_sender: mpsc::Sender<ReportHistoryRequest>,
 }
 
 impl AppReport {
     // TODO: Should this be private?
     pub(super) fn new(
         report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+        history_sender: mpsc::Sender<ReportHistoryRequest>,
     ) -> Self {
-        AppReport { report_client }
+        AppReport {
+            report_client,
+            history_sender,
+        }
     }
 
     pub async fn incoming_reports(
@@ -28,4 +92,838 @@ impl AppReport {
 
         Ok((batch_mutable.0, incoming_mutations))
     }
+
+    /// Receive only the batches of mutations that contain at least one friend related mutation.
+    pub async fn subscribe_to_friend_events(
+        &mut self,
+    ) -> Result<mpsc::Receiver<Vec<NodeReportMutation>>, AppReportError> {
+        await!(self
+            .report_client
+            .subscribe_filtered(|mutations: &Vec<NodeReportMutation>| {
+                mutations.iter().any(is_friend_mutation)
+            }))
+        .map_err(|_| AppReportError)
+    }
+
+    /// Receive a snapshot of the report of the friend `public_key` whenever it changes.
+    /// Nothing is emitted while the friend does not exist.
+    /// The current state of the friend can be found in the report from `incoming_reports()`.
+    pub async fn watch_friend(
+        &mut self,
+        public_key: &PublicKey,
+    ) -> Result<BoxStream<'static, FriendReport>, AppReportError> {
+        let (batch_mutable, incoming_mutations) =
+            await!(self.report_client.request_state()).map_err(|_| AppReportError)?;
+
+        let mut node_report = batch_mutable.0;
+        let public_key = public_key.clone();
+        let friend_reports = incoming_mutations.filter_map(move |mutations| {
+            let mut friend_changed = false;
+            for mutation in &mutations {
+                if let Err(e) = node_report.mutate(mutation) {
+                    error!("watch_friend(): Failed to apply mutation: {:?}", e);
+                    return future::ready(None);
+                }
+                friend_changed |= is_mutation_of_friend(mutation, &public_key);
+            }
+            if !friend_changed {
+                return future::ready(None);
+            }
+            future::ready(node_report.funder_report.friends.get(&public_key).cloned())
+        });
+        Ok(Box::pin(friend_reports))
+    }
+
+    /// Get the NodeReport as it was at `target_tick`.
+    /// Ticks are counted from the moment the first connection to the node was established, and
+    /// keep counting across reconnections.
+    /// Returns an error if `target_tick` is in the future, or older than the oldest snapshot we
+    /// still keep.
+    pub async fn get_report_as_of_tick(
+        &mut self,
+        target_tick: u64,
+    ) -> Result<NodeReport, AppReportError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let request = ReportHistoryRequest::ReportAsOfTick((target_tick, response_sender));
+        await!(self.history_sender.send(request)).map_err(|_| AppReportError)?;
+        await!(response_receiver)
+            .map_err(|_| AppReportError)?
+            .ok_or(AppReportError)
+    }
+
+    /// Get all the mutations applied to the NodeReport after `since_tick`.
+    /// Useful for an app that reconnects and wants to catch up with what it missed.
+    /// If `since_tick` is older than the mutation log, or from before the latest reconnection (The
+    /// mutations applied while disconnected are unknown), the full current report is returned.
+    /// Returns an error if `since_tick` is in the future.
+    pub async fn diff_since_tick(&mut self, since_tick: u64) -> Result<ReportDiff, AppReportError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let request = ReportHistoryRequest::DiffSinceTick((since_tick, response_sender));
+        await!(self.history_sender.send(request)).map_err(|_| AppReportError)?;
+        await!(response_receiver)
+            .map_err(|_| AppReportError)?
+            .ok_or(AppReportError)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReportHistoryError {
+    RequestStateError,
+    RequestTimerStreamError,
+    HistoryClosed,
+    MutateError,
+}
+
+struct ReportHistory {
+    snapshot_interval_ticks: u64,
+    cur_tick: u64,
+    cur_report: NodeReport,
+    /// Saved (tick, report) pairs, ordered by tick.
+    snapshots: VecDeque<(u64, NodeReport)>,
+    /// All mutation batches received since the oldest snapshot, with the tick they were
+    /// received at.
+    mutations: VecDeque<(u64, Vec<NodeReportMutation>)>,
+    /// The last `MAX_LOG_SIZE` mutations, with the tick they were received at.
+    mutation_log: VecDeque<(u64, NodeReportMutation)>,
+    /// The oldest tick for which `mutation_log` contains all the mutations received after it.
+    log_start_tick: u64,
+}
+
+impl ReportHistory {
+    fn new(node_report: NodeReport, snapshot_interval_ticks: usize) -> Self {
+        let mut snapshots = VecDeque::new();
+        snapshots.push_back((0, node_report.clone()));
+
+        ReportHistory {
+            // An interval of 0 ticks is treated as a snapshot every tick:
+            snapshot_interval_ticks: usize_to_u64(snapshot_interval_ticks).unwrap().max(1),
+            cur_tick: 0,
+            cur_report: node_report,
+            snapshots,
+            mutations: VecDeque::new(),
+            mutation_log: VecDeque::new(),
+            log_start_tick: 0,
+        }
+    }
+
+    /// A new connection to the node was established, starting from `node_report`.
+    /// The mutations applied while we were disconnected are unknown, so the mutation log can not
+    /// describe changes from before this moment.
+    fn handle_new_session(&mut self, node_report: NodeReport) {
+        // Mutations received during the current tick precede the new report:
+        while let Some((tick, _)) = self.mutations.back() {
+            if *tick < self.cur_tick {
+                break;
+            }
+            let _ = self.mutations.pop_back();
+        }
+        self.snapshots
+            .push_back((self.cur_tick, node_report.clone()));
+        if self.snapshots.len() > MAX_REPORT_SNAPSHOTS {
+            let _ = self.snapshots.pop_front();
+        }
+
+        self.mutation_log.clear();
+        self.log_start_tick = self.cur_tick.saturating_add(1);
+        self.cur_report = node_report;
+    }
+
+    fn handle_tick(&mut self) {
+        self.cur_tick = self.cur_tick.saturating_add(1);
+        if self.cur_tick % self.snapshot_interval_ticks != 0 {
+            return;
+        }
+
+        self.snapshots
+            .push_back((self.cur_tick, self.cur_report.clone()));
+        if self.snapshots.len() > MAX_REPORT_SNAPSHOTS {
+            let _ = self.snapshots.pop_front();
+        }
+
+        // Mutations older than the oldest snapshot are not needed anymore:
+        let oldest_tick = self.snapshots.front().unwrap().0;
+        while let Some((tick, _)) = self.mutations.front() {
+            if *tick >= oldest_tick {
+                break;
+            }
+            let _ = self.mutations.pop_front();
+        }
+    }
+
+    fn handle_mutations(
+        &mut self,
+        mutations: Vec<NodeReportMutation>,
+    ) -> Result<(), ReportHistoryError> {
+        for mutation in &mutations {
+            self.cur_report
+                .mutate(mutation)
+                .map_err(|_| ReportHistoryError::MutateError)?;
+
+            self.mutation_log
+                .push_back((self.cur_tick, mutation.clone()));
+            if self.mutation_log.len() > MAX_LOG_SIZE {
+                let (dropped_tick, _) = self.mutation_log.pop_front().unwrap();
+                self.log_start_tick = dropped_tick;
+            }
+        }
+        self.mutations.push_back((self.cur_tick, mutations));
+        Ok(())
+    }
+
+    fn diff_since_tick(&self, since_tick: u64) -> Option<ReportDiff> {
+        if since_tick > self.cur_tick {
+            return None;
+        }
+
+        // Some of the mutations we need are not in the log:
+        if since_tick < self.log_start_tick {
+            return Some(ReportDiff::Report(self.cur_report.clone()));
+        }
+
+        Some(ReportDiff::Mutations(
+            self.mutation_log
+                .iter()
+                .filter(|(tick, _)| *tick > since_tick)
+                .map(|(_, mutation)| mutation.clone())
+                .collect(),
+        ))
+    }
+
+    fn report_as_of_tick(&self, target_tick: u64) -> Option<NodeReport> {
+        if target_tick > self.cur_tick {
+            return None;
+        }
+
+        // Find the latest snapshot saved not after target_tick:
+        let (snapshot_tick, snapshot) = self
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(tick, _)| *tick <= target_tick)?;
+
+        let mut node_report = snapshot.clone();
+        for (tick, mutations) in &self.mutations {
+            if tick < snapshot_tick {
+                continue;
+            }
+            if *tick > target_tick {
+                break;
+            }
+            for mutation in mutations {
+                node_report.mutate(mutation).ok()?;
+            }
+        }
+        Some(node_report)
+    }
+}
+
+#[allow(clippy::large_enum_variant)]
+enum ReportHistoryEvent {
+    TimerTick,
+    TimerClosed,
+    NewSession(NodeReport),
+    Mutations(Vec<NodeReportMutation>),
+    MutationsClosed,
+    Request(ReportHistoryRequest),
+    RequestsClosed,
+}
+
+async fn report_history_service<TS>(
+    mut incoming_sessions: mpsc::Receiver<ReportSession>,
+    timer_stream: TS,
+    incoming_requests: mpsc::Receiver<ReportHistoryRequest>,
+    snapshot_interval_ticks: usize,
+) -> Result<(), ReportHistoryError>
+where
+    TS: Stream<Item = TimerTick> + Unpin + Send + 'static,
+{
+    let (node_report, incoming_mutations) = match await!(incoming_sessions.next()) {
+        Some(report_session) => report_session,
+        None => return Ok(()),
+    };
+    let mut report_history = ReportHistory::new(node_report, snapshot_interval_ticks);
+
+    let timer_stream = timer_stream
+        .map(|_| ReportHistoryEvent::TimerTick)
+        .chain(stream::once(future::ready(ReportHistoryEvent::TimerClosed)));
+
+    // A session starts only after the mutations of the previous session are exhausted, which
+    // happens when the previous connection is closed:
+    let next_sessions = incoming_sessions
+        .map(|(node_report, incoming_mutations)| {
+            stream::once(future::ready(ReportHistoryEvent::NewSession(node_report)))
+                .chain(incoming_mutations.map(ReportHistoryEvent::Mutations))
+        })
+        .flatten();
+    let incoming_mutations = incoming_mutations
+        .map(ReportHistoryEvent::Mutations)
+        .chain(next_sessions)
+        .chain(stream::once(future::ready(
+            ReportHistoryEvent::MutationsClosed,
+        )));
+
+    let incoming_requests = incoming_requests
+        .map(ReportHistoryEvent::Request)
+        .chain(stream::once(future::ready(
+            ReportHistoryEvent::RequestsClosed,
+        )));
+
+    let mut events = select_streams![timer_stream, incoming_mutations, incoming_requests];
+
+    while let Some(event) = await!(events.next()) {
+        match event {
+            ReportHistoryEvent::TimerTick => report_history.handle_tick(),
+            ReportHistoryEvent::NewSession(node_report) => {
+                report_history.handle_new_session(node_report)
+            }
+            ReportHistoryEvent::Mutations(mutations) => {
+                report_history.handle_mutations(mutations)?
+            }
+            ReportHistoryEvent::Request(request) => match request {
+                ReportHistoryRequest::ReportAsOfTick((target_tick, response_sender)) => {
+                    let _ = response_sender.send(report_history.report_as_of_tick(target_tick));
+                }
+                ReportHistoryRequest::DiffSinceTick((since_tick, response_sender)) => {
+                    let _ = response_sender.send(report_history.diff_since_tick(since_tick));
+                }
+            },
+            ReportHistoryEvent::TimerClosed
+            | ReportHistoryEvent::MutationsClosed
+            | ReportHistoryEvent::RequestsClosed => break,
+        }
+    }
+    Ok(())
+}
+
+/// Keep a history of NodeReport snapshots, to be able to serve historical report queries.
+async fn report_history_loop(
+    mut timer_client: TimerClient,
+    incoming_sessions: mpsc::Receiver<ReportSession>,
+    incoming_requests: mpsc::Receiver<ReportHistoryRequest>,
+    snapshot_interval_ticks: usize,
+) -> Result<(), ReportHistoryError> {
+    let timer_stream = await!(timer_client.request_timer_stream())
+        .map_err(|_| ReportHistoryError::RequestTimerStreamError)?;
+
+    await!(report_history_service(
+        incoming_sessions,
+        timer_stream,
+        incoming_requests,
+        snapshot_interval_ticks
+    ))
+}
+
+/// A handle to a report history service. The history may outlive a single connection to the
+/// node: Every connection registers its report as a new session.
+#[derive(Clone)]
+pub(super) struct ReportHistoryClient {
+    sessions_sender: mpsc::Sender<ReportSession>,
+    requests_sender: mpsc::Sender<ReportHistoryRequest>,
+}
+
+#[derive(Debug)]
+pub(super) struct SpawnReportHistoryError;
+
+impl ReportHistoryClient {
+    /// Spawn a report history service.
+    /// The service is closed when all the handles to it (Including the `AppReport`s using it) are
+    /// dropped, and the mutations of the last session are exhausted.
+    pub(super) fn spawn<S>(
+        timer_client: TimerClient,
+        snapshot_interval_ticks: usize,
+        spawner: &mut S,
+    ) -> Result<Self, SpawnReportHistoryError>
+    where
+        S: Spawn,
+    {
+        let (sessions_sender, incoming_sessions) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let report_history_fut = report_history_loop(
+            timer_client,
+            incoming_sessions,
+            incoming_requests,
+            snapshot_interval_ticks,
+        )
+        .map_err(|e| error!("report_history_loop() error: {:?}", e))
+        .map(|_| ());
+        spawner
+            .spawn(report_history_fut)
+            .map_err(|_| SpawnReportHistoryError)?;
+
+        Ok(ReportHistoryClient {
+            sessions_sender,
+            requests_sender,
+        })
+    }
+
+    /// Record the reports of a new connection to the node, starting from its initial report.
+    pub(super) fn add_session<S>(
+        &self,
+        report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+        spawner: &mut S,
+    ) -> Result<(), SpawnReportHistoryError>
+    where
+        S: Spawn,
+    {
+        let mut report_client = report_client;
+        let mut sessions_sender = self.sessions_sender.clone();
+        let add_session_fut = async move {
+            let (batch_mutable, incoming_mutations) = await!(report_client.request_state())
+                .map_err(|_| ReportHistoryError::RequestStateError)?;
+            await!(sessions_sender.send((batch_mutable.0, incoming_mutations)))
+                .map_err(|_| ReportHistoryError::HistoryClosed)
+        }
+            .map_err(|e| error!("ReportHistoryClient::add_session() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(add_session_fut)
+            .map_err(|_| SpawnReportHistoryError)
+    }
+
+    pub(super) fn requests_sender(&self) -> mpsc::Sender<ReportHistoryRequest> {
+        self.requests_sender.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::FutureExt;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+
+    use common::state_service::state_service;
+
+    use proto::index_client::messages::IndexClientReport;
+    use proto::report::messages::{
+        AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, FriendReportMutation,
+        FunderReport,
+    };
+
+    fn create_node_report() -> NodeReport {
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                relays: Default::default(),
+                friends: Default::default(),
+                num_open_invoices: 0,
+                invoices_progress: Default::default(),
+                num_payments: 0,
+                num_open_transactions: 0,
+                total_frozen_credits: (0, 0),
+                max_friends: 0x100,
+                max_node_relays: 0x10,
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                circuit_states: HashMap::new(),
+            },
+        }
+    }
+
+    /// Wait until the history service has processed all the ticks up to `tick`.
+    async fn wait_for_tick(app_report: &mut AppReport, tick: u64) {
+        while await!(app_report.get_report_as_of_tick(tick)).is_err() {}
+    }
+
+    async fn task_get_report_as_of_tick<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let node_report = create_node_report();
+
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+
+        let (mut mutations_sender, incoming_mutations) = mpsc::channel(0);
+        let (mut sessions_sender, incoming_sessions) = mpsc::channel(0);
+        let (mut tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+        let (history_sender, incoming_history_requests) = mpsc::channel(0);
+
+        spawner
+            .spawn(
+                report_history_service(
+                    incoming_sessions,
+                    timer_stream,
+                    incoming_history_requests,
+                    3,
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+        await!(sessions_sender.send((node_report.clone(), incoming_mutations))).unwrap();
+
+        let mut app_report = AppReport::new(report_client, history_sender);
+
+        // live_reports[t] is the report as observed live at the end of tick t:
+        let mut live_reports = Vec::new();
+        let mut live_report = BatchMutable(node_report);
+
+        for tick in 0..10u64 {
+            let mutations = vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumPayments(tick + 1),
+            )];
+            live_report.mutate(&mutations).unwrap();
+            await!(mutations_sender.send(mutations)).unwrap();
+            // An empty batch, to make sure the previous batch was consumed before we tick:
+            await!(mutations_sender.send(Vec::new())).unwrap();
+            live_reports.push(live_report.0.clone());
+
+            // Move on to the next tick:
+            await!(tick_sender.send(TimerTick)).unwrap();
+            await!(wait_for_tick(&mut app_report, tick + 1));
+        }
+
+        for (tick, live_report) in live_reports.iter().enumerate() {
+            let tick = usize_to_u64(tick).unwrap();
+            let report = await!(app_report.get_report_as_of_tick(tick)).unwrap();
+            assert_eq!(&report, live_report);
+            assert_eq!(report.funder_report.num_payments, tick + 1);
+        }
+
+        // Future ticks can not be queried:
+        assert!(await!(app_report.get_report_as_of_tick(11)).is_err());
+    }
+
+    #[test]
+    fn test_get_report_as_of_tick() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_get_report_as_of_tick(thread_pool.clone()));
+    }
+
+    async fn task_diff_since_tick<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let node_report = create_node_report();
+
+        let (requests_sender, _incoming_requests) = mpsc::channel(0);
+        let report_client = StateClient::new(requests_sender);
+
+        let (mut mutations_sender, incoming_mutations) = mpsc::channel(0);
+        let (mut sessions_sender, incoming_sessions) = mpsc::channel(0);
+        let (mut tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+        let (history_sender, incoming_history_requests) = mpsc::channel(0);
+
+        spawner
+            .spawn(
+                report_history_service(
+                    incoming_sessions,
+                    timer_stream,
+                    incoming_history_requests,
+                    3,
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+        await!(sessions_sender.send((node_report.clone(), incoming_mutations))).unwrap();
+
+        let mut app_report = AppReport::new(report_client, history_sender);
+
+        for tick in 0..5u64 {
+            let mutations = vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumPayments(tick + 1),
+            )];
+            await!(mutations_sender.send(mutations)).unwrap();
+            // An empty batch, to make sure the previous batch was consumed before we tick:
+            await!(mutations_sender.send(Vec::new())).unwrap();
+
+            await!(tick_sender.send(TimerTick)).unwrap();
+            await!(wait_for_tick(&mut app_report, tick + 1));
+        }
+
+        // Mutations received during ticks 3 and 4:
+        let report_diff = await!(app_report.diff_since_tick(2)).unwrap();
+        let mutations = match report_diff {
+            ReportDiff::Mutations(mutations) => mutations,
+            ReportDiff::Report(_) => unreachable!(),
+        };
+        assert_eq!(
+            mutations,
+            vec![
+                NodeReportMutation::Funder(FunderReportMutation::SetNumPayments(4)),
+                NodeReportMutation::Funder(FunderReportMutation::SetNumPayments(5)),
+            ]
+        );
+
+        // Catching up from tick 2 results in the current report:
+        let mut report = await!(app_report.get_report_as_of_tick(2)).unwrap();
+        for mutation in &mutations {
+            report.mutate(mutation).unwrap();
+        }
+        assert_eq!(report, await!(app_report.get_report_as_of_tick(5)).unwrap());
+
+        // Nothing happened since the current tick:
+        assert_eq!(
+            await!(app_report.diff_since_tick(5)).unwrap(),
+            ReportDiff::Mutations(Vec::new())
+        );
+
+        // Future ticks can not be queried:
+        assert!(await!(app_report.diff_since_tick(6)).is_err());
+    }
+
+    #[test]
+    fn test_diff_since_tick() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_diff_since_tick(thread_pool.clone()));
+    }
+
+    #[test]
+    fn test_diff_since_tick_older_than_log() {
+        let mut report_history = ReportHistory::new(create_node_report(), 1);
+
+        // Overflow the log during tick 1:
+        report_history.handle_tick();
+        for i in 0..=MAX_LOG_SIZE {
+            let mutations = vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumPayments(usize_to_u64(i).unwrap()),
+            )];
+            report_history.handle_mutations(mutations).unwrap();
+        }
+
+        // The first mutation of tick 1 was dropped, so we get the full report:
+        match report_history.diff_since_tick(0).unwrap() {
+            ReportDiff::Report(report) => {
+                assert_eq!(report, report_history.cur_report);
+                assert_eq!(
+                    report.funder_report.num_payments,
+                    usize_to_u64(MAX_LOG_SIZE).unwrap()
+                );
+            }
+            ReportDiff::Mutations(_) => unreachable!(),
+        }
+
+        // Tick 1 is still fully covered by the log:
+        assert_eq!(
+            report_history.diff_since_tick(1).unwrap(),
+            ReportDiff::Mutations(Vec::new())
+        );
+        assert!(report_history.diff_since_tick(2).is_none());
+    }
+
+    #[test]
+    fn test_diff_since_tick_new_session() {
+        let mut report_history = ReportHistory::new(create_node_report(), 1);
+        let set_num_payments = |num_payments| {
+            NodeReportMutation::Funder(FunderReportMutation::SetNumPayments(num_payments))
+        };
+
+        report_history.handle_tick();
+        report_history
+            .handle_mutations(vec![set_num_payments(1)])
+            .unwrap();
+
+        // Reconnect during tick 1. Some mutations were missed while disconnected:
+        let mut node_report = create_node_report();
+        node_report.funder_report.num_payments = 7;
+        report_history.handle_new_session(node_report.clone());
+
+        // Changes from before the new session can not be described using mutations:
+        for since_tick in 0..=1 {
+            assert_eq!(
+                report_history.diff_since_tick(since_tick).unwrap(),
+                ReportDiff::Report(node_report.clone())
+            );
+        }
+
+        report_history.handle_tick();
+        report_history.handle_tick();
+        report_history
+            .handle_mutations(vec![set_num_payments(8)])
+            .unwrap();
+        report_history.handle_tick();
+
+        // Ticks keep counting across sessions:
+        assert_eq!(
+            report_history.diff_since_tick(2).unwrap(),
+            ReportDiff::Mutations(vec![set_num_payments(8)])
+        );
+        assert_eq!(
+            report_history.diff_since_tick(1).unwrap(),
+            ReportDiff::Report(report_history.cur_report.clone())
+        );
+
+        // Reports of the previous session are still available:
+        assert_eq!(
+            report_history.report_as_of_tick(0).unwrap(),
+            create_node_report()
+        );
+        assert_eq!(report_history.report_as_of_tick(1).unwrap(), node_report);
+        assert_eq!(
+            report_history
+                .report_as_of_tick(3)
+                .unwrap()
+                .funder_report
+                .num_payments,
+            8
+        );
+    }
+
+    async fn task_subscribe_to_friend_events<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let (mut mutations_sender, incoming_mutations) = mpsc::channel(0);
+        spawner
+            .spawn(
+                state_service(
+                    incoming_requests,
+                    BatchMutable(create_node_report()),
+                    incoming_mutations,
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        let report_client = StateClient::new(requests_sender);
+        let (history_sender, _incoming_history_requests) = mpsc::channel(0);
+        let mut app_report = AppReport::new(report_client, history_sender);
+        let friend_events = await!(app_report.subscribe_to_friend_events()).unwrap();
+        drop(app_report);
+
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let add_friend_report = AddFriendReport {
+            friend_public_key: friend_public_key.clone(),
+            name: "friend".into(),
+            note: String::new(),
+            relays: Vec::new(),
+            balance: 0,
+            opt_last_incoming_move_token: None,
+            channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+                local_reset_terms_balance: 0,
+                opt_remote_reset_terms: None,
+            }),
+        };
+        let batches = vec![
+            vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumPayments(1),
+            )],
+            vec![NodeReportMutation::Funder(FunderReportMutation::AddFriend(
+                add_friend_report,
+            ))],
+            vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumOpenTransactions(2),
+            )],
+            vec![NodeReportMutation::Funder(
+                FunderReportMutation::FriendReportMutation((
+                    friend_public_key.clone(),
+                    FriendReportMutation::SetName("friend1".into()),
+                )),
+            )],
+            vec![
+                NodeReportMutation::Funder(FunderReportMutation::SetNumPayments(3)),
+                NodeReportMutation::Funder(FunderReportMutation::RemoveFriend(
+                    friend_public_key.clone(),
+                )),
+            ],
+        ];
+        let expected_batches = vec![batches[1].clone(), batches[3].clone(), batches[4].clone()];
+
+        spawner
+            .spawn(async move {
+                for batch in batches {
+                    await!(mutations_sender.send(batch)).unwrap();
+                }
+            })
+            .unwrap();
+
+        // Batches without friend related mutations are never delivered:
+        let received_batches = await!(friend_events.collect::<Vec<_>>());
+        assert_eq!(received_batches, expected_batches);
+    }
+
+    #[test]
+    fn test_subscribe_to_friend_events() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_subscribe_to_friend_events(thread_pool.clone()));
+    }
+
+    async fn task_watch_friend<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let (mut mutations_sender, incoming_mutations) = mpsc::channel(0);
+        spawner
+            .spawn(
+                state_service(
+                    incoming_requests,
+                    BatchMutable(create_node_report()),
+                    incoming_mutations,
+                )
+                .map(|res| res.unwrap()),
+            )
+            .unwrap();
+
+        let report_client = StateClient::new(requests_sender);
+        let (history_sender, _incoming_history_requests) = mpsc::channel(0);
+        let mut app_report = AppReport::new(report_client, history_sender);
+
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let friend_reports = await!(app_report.watch_friend(&pk_b)).unwrap();
+        drop(app_report);
+
+        let add_friend = |friend_public_key: &PublicKey, name: &str| {
+            NodeReportMutation::Funder(FunderReportMutation::AddFriend(AddFriendReport {
+                friend_public_key: friend_public_key.clone(),
+                name: name.into(),
+                note: String::new(),
+                relays: Vec::new(),
+                balance: 0,
+                opt_last_incoming_move_token: None,
+                channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+                    local_reset_terms_balance: 0,
+                    opt_remote_reset_terms: None,
+                }),
+            }))
+        };
+        let set_name = |friend_public_key: &PublicKey, name: &str| {
+            NodeReportMutation::Funder(FunderReportMutation::FriendReportMutation((
+                friend_public_key.clone(),
+                FriendReportMutation::SetName(name.into()),
+            )))
+        };
+
+        let batches = vec![
+            vec![add_friend(&pk_c, "c")],
+            vec![add_friend(&pk_b, "b")],
+            vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumPayments(1),
+            )],
+            vec![set_name(&pk_c, "c1")],
+            vec![set_name(&pk_b, "b1"), set_name(&pk_b, "b2")],
+            vec![NodeReportMutation::Funder(
+                FunderReportMutation::RemoveFriend(pk_b.clone()),
+            )],
+        ];
+
+        spawner
+            .spawn(async move {
+                for batch in batches {
+                    await!(mutations_sender.send(batch)).unwrap();
+                }
+            })
+            .unwrap();
+
+        // Only changes of pk_b are reported, one snapshot per batch:
+        let names = await!(friend_reports
+            .map(|friend_report| friend_report.name)
+            .collect::<Vec<_>>());
+        assert_eq!(names, vec!["b".to_owned(), "b2".to_owned()]);
+    }
+
+    #[test]
+    fn test_watch_friend() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_watch_friend(thread_pool.clone()));
+    }
 }
@@ -102,4 +102,133 @@ where
         }
         Err(AppRoutesError)
     }
+
+    /// Request routes that can carry at least `min_capacity` credits.
+    /// Routes with a lower capacity are removed from the returned multi routes, and multi routes
+    /// that are left empty are removed altogether.
+    pub async fn request_routes_with_capacity(
+        &mut self,
+        source: PublicKey,
+        destination: PublicKey,
+        min_capacity: u128,
+    ) -> Result<Vec<MultiRoute>, AppRoutesError> {
+        let multi_routes = await!(self.request_routes(min_capacity, source, destination, None))?;
+        Ok(filter_routes_by_capacity(multi_routes, min_capacity))
+    }
+}
+
+fn filter_routes_by_capacity(multi_routes: Vec<MultiRoute>, min_capacity: u128) -> Vec<MultiRoute> {
+    multi_routes
+        .into_iter()
+        .filter_map(|mut multi_route| {
+            multi_route
+                .routes
+                .retain(|route_capacity_rate| route_capacity_rate.capacity >= min_capacity);
+            if multi_route.routes.is_empty() {
+                None
+            } else {
+                Some(multi_route)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::{FutureExt, TryFutureExt};
+
+    use common::multi_consumer::multi_consumer_service;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::test_utils::DummyRandom;
+
+    use proto::funder::messages::{FriendsRoute, Rate};
+    use proto::index_server::messages::RouteCapacityRate;
+
+    fn route_capacity_rate(index: u8, capacity: u128) -> RouteCapacityRate {
+        RouteCapacityRate {
+            route: FriendsRoute {
+                public_keys: vec![
+                    PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                    PublicKey::from(&[index; PUBLIC_KEY_LEN]),
+                    PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                ],
+            },
+            capacity,
+            rate: Rate { mul: 0, add: 1 },
+        }
+    }
+
+    async fn task_request_routes_with_capacity<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_routes_sender, incoming_routes) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let routes_mc = MultiConsumerClient::new(requests_sender);
+        let routes_fut = multi_consumer_service(incoming_routes, incoming_requests)
+            .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner.spawn(routes_fut).unwrap();
+
+        // A fake app server, returning routes of varying capacities:
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+        spawner
+            .spawn(async move {
+                let to_app_server: AppToAppServer = await!(app_server_receiver.next()).unwrap();
+                let request_routes = match to_app_server.app_request {
+                    AppRequest::RequestRoutes(request_routes) => request_routes,
+                    _ => unreachable!(),
+                };
+                assert_eq!(request_routes.capacity, 50);
+
+                let multi_routes = vec![
+                    MultiRoute {
+                        routes: vec![route_capacity_rate(0, 10), route_capacity_rate(1, 100)],
+                    },
+                    MultiRoute {
+                        routes: vec![route_capacity_rate(2, 49)],
+                    },
+                    MultiRoute {
+                        routes: vec![route_capacity_rate(3, 50)],
+                    },
+                ];
+                let client_response_routes = ClientResponseRoutes {
+                    request_id: request_routes.request_id,
+                    result: ResponseRoutesResult::Success(multi_routes),
+                };
+                await!(incoming_routes_sender.send(client_response_routes)).unwrap();
+            })
+            .unwrap();
+
+        let mut app_routes = AppRoutes::new(sender, routes_mc, DummyRandom::new(&[1u8]));
+        let multi_routes = await!(app_routes.request_routes_with_capacity(
+            PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            50
+        ))
+        .unwrap();
+
+        assert_eq!(
+            multi_routes,
+            vec![
+                MultiRoute {
+                    routes: vec![route_capacity_rate(1, 100)],
+                },
+                MultiRoute {
+                    routes: vec![route_capacity_rate(3, 50)],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_request_routes_with_capacity() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_request_routes_with_capacity(thread_pool.clone()));
+    }
 }
@@ -1,6 +1,8 @@
+use std::collections::HashSet;
+
 use common::multi_consumer::MultiConsumerClient;
 use futures::channel::mpsc;
-use futures::{SinkExt, StreamExt};
+use futures::{future, SinkExt, StreamExt};
 
 use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
 use crypto::invoice_id::InvoiceId;
@@ -23,6 +25,34 @@ pub enum SellerError {
     NoResponse,
 }
 
+/// Send all the given requests to the app server.
+async fn send_requests(
+    mut sender: mpsc::Sender<AppToAppServer>,
+    to_app_servers: Vec<AppToAppServer>,
+) -> Result<(), SellerError> {
+    for to_app_server in to_app_servers {
+        await!(sender.send(to_app_server)).map_err(|_| SellerError::ConnectivityError)?;
+    }
+    Ok(())
+}
+
+/// Wait until all the requests in `pending_requests` are done.
+async fn wait_requests_done(
+    mut incoming_done_requests: mpsc::Receiver<Uid>,
+    mut pending_requests: HashSet<Uid>,
+) -> Result<(), SellerError> {
+    while !pending_requests.is_empty() {
+        match await!(incoming_done_requests.next()) {
+            Some(done_request_id) => {
+                pending_requests.remove(&done_request_id);
+            }
+            // We lost connectivity before we got all the responses:
+            None => return Err(SellerError::NoResponse),
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct AppSeller<R = OffstSystemRandom> {
     sender: mpsc::Sender<AppToAppServer>,
@@ -50,11 +80,13 @@ where
         &mut self,
         invoice_id: InvoiceId,
         total_dest_payment: u128,
+        validity_ticks: u64,
     ) -> Result<(), SellerError> {
         let app_request_id = Uid::new(&self.rng);
         let add_invoice = AddInvoice {
             invoice_id,
             total_dest_payment,
+            validity_ticks,
         };
         let to_app_server =
             AppToAppServer::new(app_request_id, AppRequest::AddInvoice(add_invoice));
@@ -76,6 +108,49 @@ where
         Err(SellerError::NoResponse)
     }
 
+    /// Add many invoices at once.
+    /// All the AddInvoice requests are sent without waiting for a round trip between them,
+    /// and the confirmations are collected as they arrive.
+    /// Returns the ids of the added invoices, in the original order.
+    pub async fn create_invoices(
+        &mut self,
+        invoices: Vec<(InvoiceId, u128)>,
+        validity_ticks: u64,
+    ) -> Result<Vec<InvoiceId>, SellerError> {
+        let mut invoice_ids = Vec::new();
+        let mut pending_requests = HashSet::new();
+        let mut to_app_servers = Vec::new();
+        for (invoice_id, total_dest_payment) in invoices {
+            let app_request_id = Uid::new(&self.rng);
+            let add_invoice = AddInvoice {
+                invoice_id: invoice_id.clone(),
+                total_dest_payment,
+                validity_ticks,
+            };
+            pending_requests.insert(app_request_id.clone());
+            to_app_servers.push(AppToAppServer::new(
+                app_request_id,
+                AppRequest::AddInvoice(add_invoice),
+            ));
+            invoice_ids.push(invoice_id);
+        }
+
+        // Start listening to done requests:
+        let incoming_done_requests = await!(self.done_app_requests_mc.request_stream())
+            .map_err(|_| SellerError::ConnectivityError)?;
+
+        // Confirmations are received while sending, otherwise the app server might block
+        // trying to send us confirmations we don't read yet:
+        let (send_res, done_res) = await!(future::join(
+            send_requests(self.sender.clone(), to_app_servers),
+            wait_requests_done(incoming_done_requests, pending_requests)
+        ));
+        send_res?;
+        done_res?;
+
+        Ok(invoice_ids)
+    }
+
     pub async fn cancel_invoice(&mut self, invoice_id: InvoiceId) -> Result<(), SellerError> {
         let app_request_id = Uid::new(&self.rng);
         let to_app_server =
@@ -120,3 +195,77 @@ where
         Err(SellerError::NoResponse)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::{FutureExt, TryFutureExt};
+
+    use common::multi_consumer::multi_consumer_service;
+
+    use crypto::invoice_id::INVOICE_ID_LEN;
+    use crypto::test_utils::DummyRandom;
+
+    async fn task_create_invoices<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut incoming_done_app_requests_sender, incoming_done_app_requests) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        let done_app_requests_fut =
+            multi_consumer_service(incoming_done_app_requests, incoming_requests)
+                .map_err(|e| error!("multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner.spawn(done_app_requests_fut).unwrap();
+
+        // A fake app server, confirming every AddInvoice request:
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+        let (mut added_sender, mut added_receiver) = mpsc::channel(0);
+        spawner
+            .spawn(async move {
+                while let Some(to_app_server) = await!(app_server_receiver.next()) {
+                    let to_app_server: AppToAppServer = to_app_server;
+                    let add_invoice = match to_app_server.app_request {
+                        AppRequest::AddInvoice(add_invoice) => add_invoice,
+                        _ => unreachable!(),
+                    };
+                    await!(incoming_done_app_requests_sender.send(to_app_server.app_request_id))
+                        .unwrap();
+                    await!(added_sender.send(add_invoice)).unwrap();
+                }
+            })
+            .unwrap();
+
+        let mut app_seller = AppSeller::new(sender, done_app_requests_mc, DummyRandom::new(&[1u8]));
+
+        let invoices = (0..100u8)
+            .map(|i| (InvoiceId::from(&[i; INVOICE_ID_LEN]), u128::from(i) + 1))
+            .collect::<Vec<_>>();
+        let expected_invoice_ids = invoices
+            .iter()
+            .map(|(invoice_id, _)| invoice_id.clone())
+            .collect::<Vec<_>>();
+
+        let (res, added_invoices) = await!(future::join(
+            app_seller.create_invoices(invoices, 20),
+            added_receiver.by_ref().take(100).collect::<Vec<_>>()
+        ));
+        assert_eq!(res.unwrap(), expected_invoice_ids);
+
+        for (i, add_invoice) in added_invoices.into_iter().enumerate() {
+            assert_eq!(add_invoice.invoice_id, expected_invoice_ids[i]);
+            assert_eq!(add_invoice.total_dest_payment, i as u128 + 1);
+            assert_eq!(add_invoice.validity_ticks, 20);
+        }
+    }
+
+    #[test]
+    fn test_create_invoices() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_create_invoices(thread_pool.clone()));
+    }
+}
@@ -20,10 +20,13 @@ extern crate serde_derive;
 
 mod adapters;
 pub mod connect;
+mod metrics;
 mod net_node;
 mod node;
+mod shutdown;
 mod types;
 
+pub use self::metrics::{metrics_handler, MetricsHandlerError, NodeMetrics};
 pub use self::net_node::{net_node, NetNodeError};
-pub use self::types::{NodeConfig, NodeState};
+pub use self::types::{InitialNodeReport, NodeConfig, NodeMutation, NodeState};
 pub use app_server::IncomingAppConnection;
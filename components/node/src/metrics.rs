@@ -0,0 +1,416 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::channel::mpsc;
+use futures::stream::select;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
+
+use common::conn::ConnPairVec;
+use common::int_convert::usize_to_u64;
+
+use proto::funder::messages::FunderOutgoingControl;
+use proto::index_client::messages::{IndexClientReportMutation, IndexClientToAppServer};
+use proto::report::messages::FunderReportMutation;
+
+use timer::TimerClient;
+
+#[derive(Debug)]
+pub enum MetricsHandlerError {
+    SpawnError,
+}
+
+/// Counters describing the state of a running node.
+///
+/// Every counter is written by a single task, so relaxed atomic operations are enough.
+/// Reading the counters never blocks the writers.
+#[derive(Debug, Default)]
+pub struct NodeMetrics {
+    /// Amount of relays the channeler listens on.
+    /// Relays waiting to reconnect after their connection was closed are not counted.
+    /// Written by the channeler's listen pool.
+    relays: Arc<AtomicU64>,
+    /// Amount of friends the channeler is connected to
+    online_friends: AtomicU64,
+    /// Amount of open payments
+    open_payments: AtomicU64,
+    /// Amount of open transactions
+    open_transactions: AtomicU64,
+    /// 1 if the node is connected to an index server, 0 otherwise
+    index_server_connected: AtomicU64,
+}
+
+impl NodeMetrics {
+    pub fn new() -> Self {
+        NodeMetrics::default()
+    }
+
+    /// The relays counter, to be updated by the channeler
+    pub(crate) fn listening_relays(&self) -> Arc<AtomicU64> {
+        self.relays.clone()
+    }
+
+    pub(crate) fn set_online_friends(&self, num_online_friends: usize) {
+        self.online_friends
+            .store(usize_to_u64(num_online_friends).unwrap(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_open_payments(&self, num_payments: u64) {
+        self.open_payments.store(num_payments, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_open_transactions(&self, num_transactions: u64) {
+        self.open_transactions
+            .store(num_transactions, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_index_server_connected(&self, is_connected: bool) {
+        self.index_server_connected
+            .store(u64::from(is_connected), Ordering::Relaxed);
+    }
+
+    /// Update counters according to a message sent from the funder to the app server
+    pub(crate) fn observe_funder_outgoing<B>(&self, funder_outgoing: &FunderOutgoingControl<B>)
+    where
+        B: Clone,
+    {
+        if let FunderOutgoingControl::ReportMutations(report_mutations) = funder_outgoing {
+            for mutation in &report_mutations.mutations {
+                match mutation {
+                    FunderReportMutation::SetNumPayments(num_payments) => {
+                        self.set_open_payments(*num_payments)
+                    }
+                    FunderReportMutation::SetNumOpenTransactions(num_transactions) => {
+                        self.set_open_transactions(*num_transactions)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Update counters according to a message sent from the index client to the app server
+    pub(crate) fn observe_index_client_outgoing<ISA>(
+        &self,
+        index_client_outgoing: &IndexClientToAppServer<ISA>,
+    ) {
+        if let IndexClientToAppServer::ReportMutations(report_mutations) = index_client_outgoing {
+            for mutation in &report_mutations.mutations {
+                if let IndexClientReportMutation::SetConnectedServer(opt_server) = mutation {
+                    self.set_index_server_connected(opt_server.is_some());
+                }
+            }
+        }
+    }
+
+    /// Render all counters using the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let gauges = [
+            (
+                "offst_node_relays",
+                "Amount of relays the node listens on, not counting relays it reconnects to.",
+                &*self.relays,
+            ),
+            (
+                "offst_node_online_friends",
+                "Amount of friends the node is connected to.",
+                &self.online_friends,
+            ),
+            (
+                "offst_node_open_payments",
+                "Amount of open payments.",
+                &self.open_payments,
+            ),
+            (
+                "offst_node_open_transactions",
+                "Amount of open transactions.",
+                &self.open_transactions,
+            ),
+            (
+                "offst_node_index_server_connected",
+                "Whether the node is connected to an index server.",
+                &self.index_server_connected,
+            ),
+        ];
+
+        let mut output = String::new();
+        for (name, help, value) in &gauges {
+            // Writing into a String never fails:
+            writeln!(output, "# HELP {} {}", name, help).unwrap();
+            writeln!(output, "# TYPE {} gauge", name).unwrap();
+            writeln!(output, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+        }
+        output
+    }
+}
+
+fn http_response(body: &str) -> Vec<u8> {
+    format!(
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        body.len(),
+        body
+    )
+    .into_bytes()
+}
+
+enum MetricsConnEvent {
+    Request,
+    RequestClosed,
+    TimerTick,
+}
+
+/// Wait for a request to arrive on a metrics connection, for at most `request_timeout_ticks`.
+/// Returns false if the connection was closed or timed out first.
+async fn wait_request<R, TS>(receiver: R, timer_stream: TS, request_timeout_ticks: usize) -> bool
+where
+    R: Stream<Item = Vec<u8>> + Unpin,
+    TS: Stream + Unpin,
+{
+    let receiver = receiver
+        .map(|_| MetricsConnEvent::Request)
+        .chain(stream::once(future::ready(MetricsConnEvent::RequestClosed)));
+    let timer_stream = timer_stream.map(|_| MetricsConnEvent::TimerTick);
+    let mut events = select(receiver, timer_stream);
+
+    let mut ticks_left = request_timeout_ticks;
+    while let Some(event) = await!(events.next()) {
+        match event {
+            MetricsConnEvent::Request => return true,
+            MetricsConnEvent::RequestClosed => return false,
+            MetricsConnEvent::TimerTick => {
+                ticks_left = ticks_left.saturating_sub(1);
+                if ticks_left == 0 {
+                    return false;
+                }
+            }
+        }
+    }
+    false
+}
+
+enum MetricsHandlerEvent {
+    IncomingConn(ConnPairVec),
+    IncomingConnsClosed,
+    ConnDone,
+}
+
+/// Serve the node metrics over incoming raw (Unframed) connections.
+/// Every connection is answered with a single HTTP response containing the metrics, and then
+/// closed. The contents of the request are ignored.
+///
+/// A connection that does not send a request within `request_timeout_ticks` is closed.
+/// At most `max_concurrent_conns` connections are served at the same time, further incoming
+/// connections are closed immediately.
+pub async fn metrics_handler<IC, S>(
+    metrics: Arc<NodeMetrics>,
+    incoming_conns: IC,
+    timer_client: TimerClient,
+    request_timeout_ticks: usize,
+    max_concurrent_conns: usize,
+    mut spawner: S,
+) -> Result<(), MetricsHandlerError>
+where
+    IC: Stream<Item = ConnPairVec> + Unpin,
+    S: Spawn,
+{
+    let incoming_conns = incoming_conns
+        .map(MetricsHandlerEvent::IncomingConn)
+        .chain(stream::once(future::ready(
+            MetricsHandlerEvent::IncomingConnsClosed,
+        )));
+
+    let (conn_done_sender, conn_done_receiver) = mpsc::channel::<()>(0);
+    let conn_done_receiver = conn_done_receiver.map(|()| MetricsHandlerEvent::ConnDone);
+
+    let mut events = select(incoming_conns, conn_done_receiver);
+    let mut num_conns: usize = 0;
+
+    while let Some(event) = await!(events.next()) {
+        let (mut sender, mut receiver) = match event {
+            MetricsHandlerEvent::IncomingConn(conn_pair) => conn_pair,
+            MetricsHandlerEvent::IncomingConnsClosed => break,
+            MetricsHandlerEvent::ConnDone => {
+                num_conns = num_conns.checked_sub(1).unwrap();
+                continue;
+            }
+        };
+
+        if num_conns >= max_concurrent_conns {
+            warn!("metrics_handler(): Too many connections. Closing connection.");
+            continue;
+        }
+
+        let c_metrics = metrics.clone();
+        let mut c_timer_client = timer_client.clone();
+        let mut c_conn_done_sender = conn_done_sender.clone();
+        let conn_fut = async move {
+            if let Ok(timer_stream) = await!(c_timer_client.request_timer_stream()) {
+                if await!(wait_request(
+                    &mut receiver,
+                    timer_stream,
+                    request_timeout_ticks
+                )) {
+                    let response = http_response(&c_metrics.to_prometheus());
+                    if let Err(e) = await!(sender.send(response)) {
+                        warn!("metrics_handler(): Failed to send response: {:?}", e);
+                    }
+                }
+            }
+            let _ = await!(c_conn_done_sender.send(()));
+        };
+        spawner
+            .spawn(conn_fut)
+            .map_err(|_| MetricsHandlerError::SpawnError)?;
+        num_conns = num_conns.checked_add(1).unwrap();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+
+    use proto::index_client::messages::IndexClientReportMutations;
+    use proto::net::messages::NetAddress;
+    use proto::report::messages::FunderReportMutations;
+
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
+    #[test]
+    fn test_node_metrics_observe() {
+        let metrics = NodeMetrics::new();
+        metrics.relays.store(2, Ordering::Relaxed);
+        metrics.set_online_friends(3);
+
+        metrics.observe_funder_outgoing(&FunderOutgoingControl::<NetAddress>::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: None,
+                mutations: vec![
+                    FunderReportMutation::SetNumPayments(4),
+                    FunderReportMutation::SetNumOpenTransactions(5),
+                ],
+            },
+        ));
+
+        metrics.observe_index_client_outgoing(
+            &IndexClientToAppServer::<NetAddress>::ReportMutations(IndexClientReportMutations {
+                opt_app_request_id: None,
+                mutations: vec![IndexClientReportMutation::SetConnectedServer(Some(
+                    PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                ))],
+            }),
+        );
+
+        let output = metrics.to_prometheus();
+        assert!(output.contains("# TYPE offst_node_relays gauge\n"));
+        assert!(output.contains("\noffst_node_relays 2\n"));
+        assert!(output.contains("\noffst_node_online_friends 3\n"));
+        assert!(output.contains("\noffst_node_open_payments 4\n"));
+        assert!(output.contains("\noffst_node_open_transactions 5\n"));
+        assert!(output.contains("\noffst_node_index_server_connected 1\n"));
+    }
+
+    async fn task_metrics_handler<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let metrics = Arc::new(NodeMetrics::new());
+        metrics.relays.store(1, Ordering::Relaxed);
+
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let (mut conn_sender, incoming_conns) = mpsc::channel(0);
+        spawner
+            .clone()
+            .spawn(async move {
+                await!(metrics_handler(
+                    metrics,
+                    incoming_conns,
+                    timer_client,
+                    2,
+                    8,
+                    spawner
+                ))
+                .unwrap();
+            })
+            .unwrap();
+
+        let (mut client_sender, server_receiver) = mpsc::channel(0);
+        let (server_sender, mut client_receiver) = mpsc::channel(0);
+        await!(conn_sender.send((server_sender, server_receiver))).unwrap();
+        let _tick_sender = await!(tick_sender_receiver.next()).unwrap();
+
+        await!(client_sender.send(b"GET /metrics HTTP/1.1\r\n\r\n".to_vec())).unwrap();
+        let response = String::from_utf8(await!(client_receiver.next()).unwrap()).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\noffst_node_index_server_connected 0\n"));
+        assert!(response.contains("\noffst_node_relays 1\n"));
+
+        // The connection is closed after the response:
+        assert!(await!(client_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_metrics_handler() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_metrics_handler(thread_pool.clone()));
+    }
+
+    async fn task_metrics_handler_timeout_and_limit<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let metrics = Arc::new(NodeMetrics::new());
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let (mut conn_sender, incoming_conns) = mpsc::channel(0);
+        spawner
+            .clone()
+            .spawn(async move {
+                await!(metrics_handler(
+                    metrics,
+                    incoming_conns,
+                    timer_client,
+                    2,
+                    1,
+                    spawner
+                ))
+                .unwrap();
+            })
+            .unwrap();
+
+        // A connection that never sends a request:
+        let (_client_sender1, server_receiver) = mpsc::channel(0);
+        let (server_sender, mut client_receiver1) = mpsc::channel(0);
+        await!(conn_sender.send((server_sender, server_receiver))).unwrap();
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+
+        // Only one connection is served at a time, so another connection is closed immediately:
+        let (_client_sender2, server_receiver) = mpsc::channel(0);
+        let (server_sender, mut client_receiver2) = mpsc::channel(0);
+        await!(conn_sender.send((server_sender, server_receiver))).unwrap();
+        assert!(await!(client_receiver2.next()).is_none());
+
+        // The first connection is closed after request_timeout_ticks:
+        for _ in 0..2 {
+            await!(tick_sender.send(TimerTick)).unwrap();
+        }
+        assert!(await!(client_receiver1.next()).is_none());
+    }
+
+    #[test]
+    fn test_metrics_handler_timeout_and_limit() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_metrics_handler_timeout_and_limit(thread_pool.clone()));
+    }
+}
@@ -1,32 +1,38 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::SystemTime;
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
+use futures::{future, stream, FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 
 use common::conn::{BoxFuture, ConnPairVec, FuncFutTransform, FutTransform};
+use common::select_streams::{select_streams, BoxStream};
 use common::transform_pool::transform_pool_loop;
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::messages::{AppPermissions, AppToAppServer};
 use proto::app_server::serialize::{
-    deserialize_app_to_app_server, serialize_app_permissions, serialize_app_server_to_app,
+    deserialize_app_session_request, deserialize_app_to_app_server, serialize_app_permissions,
+    serialize_app_server_to_app,
 };
-use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
+use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, REKEY_MIN_TICKS, TICKS_TO_REKEY};
+use proto::file::app::TrustedApp;
 use proto::net::messages::NetAddress;
 
 use database::{database_loop, AtomicDb, DatabaseClient};
 use identity::IdentityClient;
-use timer::TimerClient;
+use timer::{TimerClient, TimerTick};
 
 use app_server::IncomingAppConnection;
 use keepalive::KeepAliveChannel;
 use secure_channel::SecureChannel;
 use version::VersionPrefix;
 
+use crate::metrics::NodeMetrics;
 use crate::node::{node, NodeError};
 use crate::types::{NodeConfig, NodeMutation, NodeState};
 
@@ -34,16 +40,121 @@ use crate::types::{NodeConfig, NodeMutation, NodeState};
 pub enum NetNodeError {
     CreateThreadPoolError,
     RequestPublicKeyError,
+    RequestTimerStreamError,
     SpawnError,
     DatabaseIdentityMismatch,
     NodeError(NodeError),
 }
 
+/// Find the ticket of an app.
+/// Returns None if the app is not trusted, or if its ticket has expired.
+fn valid_trusted_app<'a>(
+    trusted_apps: &'a HashMap<PublicKey, TrustedApp>,
+    public_key: &PublicKey,
+    now: SystemTime,
+) -> Option<&'a TrustedApp> {
+    let trusted_app = trusted_apps.get(public_key)?;
+    if trusted_app.is_expired(now) {
+        warn!("App ticket has expired: {}", public_key.to_hex());
+        return None;
+    }
+    Some(trusted_app)
+}
+
+/// Read the directory of trusted apps, and find the valid ticket of an app.
+async fn load_trusted_app<'a, GT, TS>(
+    get_trusted_apps: GT,
+    trusted_apps_spawner: &'a mut TS,
+    public_key: &'a PublicKey,
+) -> Option<TrustedApp>
+where
+    GT: Fn() -> Option<HashMap<PublicKey, TrustedApp>> + Send + 'static,
+    TS: Spawn,
+{
+    // Reading the directory of all trusted apps could be slow, therefore we perform this
+    // operation on trusted_apps_spawner and not on the main executor of this program.
+    let trusted_apps_fut = trusted_apps_spawner
+        .spawn_with_handle(future::lazy(move |_| (get_trusted_apps)()))
+        .ok()?;
+    let trusted_apps = await!(trusted_apps_fut)?;
+    valid_trusted_app(&trusted_apps, public_key, SystemTime::now()).cloned()
+}
+
+#[derive(Debug)]
+enum AppIncomingEvent {
+    Data(Vec<u8>),
+    ReceiverClosed,
+    TimerTick,
+}
+
+/// Forward messages from a connected app.
+/// The ticket of the app is checked once every tick. When it expires, the trusted apps are read
+/// again: If the ticket was renewed (With the same permissions), the app stays connected.
+/// Otherwise the loop ends, which closes the connection to the app.
+async fn app_incoming_loop<GT, TS>(
+    receiver: mpsc::Receiver<Vec<u8>>,
+    mut to_user_receiver: mpsc::Sender<AppToAppServer<NetAddress>>,
+    timer_stream: BoxStream<'static, TimerTick>,
+    mut trusted_app: TrustedApp,
+    get_trusted_apps: GT,
+    mut trusted_apps_spawner: TS,
+) where
+    GT: Fn() -> Option<HashMap<PublicKey, TrustedApp>> + Clone + Send + 'static,
+    TS: Spawn,
+{
+    let receiver = receiver
+        .map(AppIncomingEvent::Data)
+        .chain(stream::once(future::ready(
+            AppIncomingEvent::ReceiverClosed,
+        )));
+    let timer_stream = timer_stream.map(|_| AppIncomingEvent::TimerTick);
+
+    let mut events = select_streams![receiver, timer_stream];
+
+    while let Some(event) = await!(events.next()) {
+        match event {
+            AppIncomingEvent::Data(data) => {
+                let message = match deserialize_app_to_app_server(&data) {
+                    Ok(message) => message,
+                    Err(_) => return,
+                };
+                if await!(to_user_receiver.send(message)).is_err() {
+                    return;
+                }
+            }
+            AppIncomingEvent::ReceiverClosed => return,
+            AppIncomingEvent::TimerTick => {
+                if !trusted_app.is_expired(SystemTime::now()) {
+                    continue;
+                }
+                // The ticket has expired. Check if it was renewed:
+                let renewed_app = match await!(load_trusted_app(
+                    get_trusted_apps.clone(),
+                    &mut trusted_apps_spawner,
+                    &trusted_app.public_key
+                )) {
+                    Some(renewed_app) => renewed_app,
+                    None => return,
+                };
+                if renewed_app.permissions != trusted_app.permissions {
+                    warn!(
+                        "App permissions have changed, closing connection: {}",
+                        trusted_app.public_key.to_hex()
+                    );
+                    return;
+                }
+                trusted_app = renewed_app;
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 struct AppConnTransform<VT, ET, KT, GT, TS, S> {
     version_transform: VT,
     encrypt_transform: ET,
     keepalive_transform: KT,
+    timer_client: TimerClient,
     get_trusted_apps: GT,
     /// An extra spawner used for running get_trusted_apps:
     trusted_apps_spawner: TS,
@@ -55,6 +166,7 @@ impl<VT, ET, KT, GT, TS, S> AppConnTransform<VT, ET, KT, GT, TS, S> {
         version_transform: VT,
         encrypt_transform: ET,
         keepalive_transform: KT,
+        timer_client: TimerClient,
         get_trusted_apps: GT,
         trusted_apps_spawner: TS,
         spawner: S,
@@ -63,6 +175,7 @@ impl<VT, ET, KT, GT, TS, S> AppConnTransform<VT, ET, KT, GT, TS, S> {
             version_transform,
             encrypt_transform,
             keepalive_transform,
+            timer_client,
             get_trusted_apps,
             trusted_apps_spawner,
             spawner,
@@ -79,8 +192,8 @@ where
         > + Clone
         + Send,
     KT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send,
-    GT: Fn() -> Option<HashMap<PublicKey, AppPermissions>> + Clone + Send + 'static,
-    TS: Spawn + Clone + Send,
+    GT: Fn() -> Option<HashMap<PublicKey, TrustedApp>> + Clone + Send + 'static,
+    TS: Spawn + Clone + Send + 'static,
     S: Spawn + Clone + Send,
 {
     type Input = ConnPairVec;
@@ -94,20 +207,20 @@ where
             let (public_key, enc_conn) =
                 await!(self.encrypt_transform.transform((None, ver_conn)))?;
 
-            // Obtain permissions for app (Or reject it if not trusted):
-            let c_get_trusted_apps = self.get_trusted_apps.clone();
-
-            // Obtain trusted apps using a separate spawner.
+            // Obtain permissions for app (Or reject it if not trusted).
             // At this point we re-read the directory of all trusted apps.
-            // This could be slow, therefore we perform this operation on self.trusted_apps_spawner
-            // and not on self.spawner, which represents the main executor for this program.
-            let trusted_apps_fut = self
-                .trusted_apps_spawner
-                .spawn_with_handle(future::lazy(move |_| (c_get_trusted_apps)()))
-                .ok()?;
-            let trusted_apps = await!(trusted_apps_fut)?;
-
-            let app_permissions = trusted_apps.get(&public_key)?;
+            let trusted_app = await!(load_trusted_app(
+                self.get_trusted_apps.clone(),
+                &mut self.trusted_apps_spawner,
+                &public_key
+            ))?;
+            let app_permissions = trusted_app.permissions.clone();
+
+            // Used to check for expiry of the app's ticket while the app is connected:
+            let timer_stream: BoxStream<'static, TimerTick> = match trusted_app.expires_at {
+                Some(_) => Box::pin(await!(self.timer_client.request_timer_stream()).ok()?),
+                None => Box::pin(stream::empty()),
+            };
 
             // Keepalive wrapper:
             let (mut sender, mut receiver) = await!(self.keepalive_transform.transform(enc_conn));
@@ -115,22 +228,25 @@ where
             // Tell app about its permissions: (TODO: Is this required?)
             await!(sender.send(serialize_app_permissions(&app_permissions))).ok()?;
 
+            // The app may present the session token of a previous connection,
+            // to keep receiving responses to its pending requests:
+            let app_session_request_data = await!(receiver.next())?;
+            let opt_app_session =
+                deserialize_app_session_request(&app_session_request_data).ok()?;
+
             // serialization:
             let (user_sender, mut from_user_sender) = mpsc::channel(0);
-            let (mut to_user_receiver, user_receiver) = mpsc::channel(0);
-
-            // Deserialize received data
-            let _ = self.spawner.spawn(async move {
-                while let Some(data) = await!(receiver.next()) {
-                    let message = match deserialize_app_to_app_server(&data) {
-                        Ok(message) => message,
-                        Err(_) => return,
-                    };
-                    if await!(to_user_receiver.send(message)).is_err() {
-                        return;
-                    }
-                }
-            });
+            let (to_user_receiver, user_receiver) = mpsc::channel(0);
+
+            // Deserialize received data, until the app's ticket expires:
+            let _ = self.spawner.spawn(app_incoming_loop(
+                receiver,
+                to_user_receiver,
+                timer_stream,
+                trusted_app,
+                self.get_trusted_apps.clone(),
+                self.trusted_apps_spawner.clone(),
+            ));
 
             // Serialize sent data:
             let _ = self.spawner.spawn(async move {
@@ -142,7 +258,12 @@ where
                 }
             });
 
-            Some((app_permissions.clone(), (user_sender, user_receiver)))
+            Some((
+                public_key,
+                app_permissions,
+                opt_app_session,
+                (user_sender, user_receiver),
+            ))
         })
     }
 }
@@ -150,12 +271,14 @@ where
 pub async fn net_node<IAC, C, R, GT, AD, DS, TS, S>(
     incoming_app_raw_conns: IAC,
     net_connector: C,
-    timer_client: TimerClient,
+    mut timer_client: TimerClient,
     identity_client: IdentityClient,
     rng: R,
     node_config: NodeConfig,
     get_trusted_apps: GT,
     atomic_db: AD,
+    shutdown_receiver: oneshot::Receiver<()>,
+    metrics: Arc<NodeMetrics>,
     trusted_apps_spawner: TS,
     database_spawner: DS,
     mut spawner: S,
@@ -168,7 +291,7 @@ where
         + Sync
         + 'static,
     R: CryptoRandom + Clone + 'static,
-    GT: Fn() -> Option<HashMap<PublicKey, AppPermissions>> + Clone + Send + 'static,
+    GT: Fn() -> Option<HashMap<PublicKey, TrustedApp>> + Clone + Send + 'static,
     AD: AtomicDb<State = NodeState<NetAddress>, Mutation = NodeMutation<NetAddress>>
         + Send
         + 'static,
@@ -218,6 +341,8 @@ where
         rng.clone(),
         timer_client.clone(),
         TICKS_TO_REKEY,
+        REKEY_MIN_TICKS,
+        node_config.sym_encrypt_algorithm,
         spawner.clone(),
     );
 
@@ -228,6 +353,7 @@ where
         version_transform,
         encrypt_transform,
         keepalive_transform,
+        timer_client.clone(),
         get_trusted_apps,
         trusted_apps_spawner,
         spawner.clone(),
@@ -235,12 +361,17 @@ where
 
     let (incoming_apps_sender, incoming_apps) = mpsc::channel(0);
 
+    let pool_timer_stream = await!(timer_client.request_timer_stream())
+        .map_err(|_| NetNodeError::RequestTimerStreamError)?;
+
     // Apply transform over every incoming app connection:
     let pool_fut = transform_pool_loop(
         incoming_app_raw_conns,
         incoming_apps_sender,
         app_conn_transform,
         node_config.max_concurrent_incoming_apps,
+        pool_timer_stream,
+        node_config.incoming_conn_wait_ticks,
         spawner.clone(),
     )
     .map_err(|e| error!("transform_pool_loop() error: {:?}", e))
@@ -260,8 +391,151 @@ where
         database_client,
         version_connector,
         incoming_apps,
+        shutdown_receiver,
+        metrics,
         rng,
         spawner.clone()
     ))
     .map_err(NetNodeError::NodeError)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use futures::executor::ThreadPool;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::uid::{Uid, UID_LEN};
+
+    use proto::app_server::messages::AppRequest;
+    use proto::app_server::serialize::serialize_app_to_app_server;
+
+    fn dummy_permissions() -> AppPermissions {
+        AppPermissions {
+            routes: true,
+            buyer: false,
+            seller: false,
+            config: false,
+            max_requests_per_second: None,
+            friend_permissions: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_trusted_app_expiry() {
+        let now = SystemTime::now();
+        let permissions = dummy_permissions();
+
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            pk_a.clone(),
+            TrustedApp {
+                public_key: pk_a.clone(),
+                permissions: permissions.clone(),
+                expires_at: Some(now + Duration::from_secs(60)),
+            },
+        );
+        trusted_apps.insert(
+            pk_b.clone(),
+            TrustedApp {
+                public_key: pk_b.clone(),
+                permissions: permissions.clone(),
+                expires_at: Some(now - Duration::from_secs(60)),
+            },
+        );
+
+        assert_eq!(
+            valid_trusted_app(&trusted_apps, &pk_a, now).map(|app| &app.permissions),
+            Some(&permissions)
+        );
+        // An expired ticket is rejected:
+        assert_eq!(valid_trusted_app(&trusted_apps, &pk_b, now), None);
+        // Untrusted app:
+        assert_eq!(valid_trusted_app(&trusted_apps, &pk_c, now), None);
+    }
+
+    async fn task_app_incoming_loop_expiry<S>(renewed: bool, spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let now = SystemTime::now();
+        let public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        // The directory of trusted apps, as it is after the ticket has expired:
+        let expires_at = if renewed {
+            now + Duration::from_secs(3600)
+        } else {
+            now - Duration::from_secs(1)
+        };
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            public_key.clone(),
+            TrustedApp {
+                public_key: public_key.clone(),
+                permissions: dummy_permissions(),
+                expires_at: Some(expires_at),
+            },
+        );
+        let get_trusted_apps = move || Some(trusted_apps.clone());
+
+        let (mut app_sender, receiver) = mpsc::channel(0);
+        let (to_user_receiver, mut user_receiver) = mpsc::channel(0);
+        let (mut tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
+        spawner
+            .clone()
+            .spawn(app_incoming_loop(
+                receiver,
+                to_user_receiver,
+                Box::pin(timer_stream),
+                // The ticket expired when the app was already connected:
+                TrustedApp {
+                    public_key: public_key.clone(),
+                    permissions: dummy_permissions(),
+                    expires_at: Some(now - Duration::from_secs(1)),
+                },
+                get_trusted_apps,
+                spawner,
+            ))
+            .unwrap();
+
+        let app_to_app_server = AppToAppServer::new(
+            Uid::from(&[0x11; UID_LEN]),
+            AppRequest::RemoveRelay(public_key),
+        );
+
+        // Messages are forwarded until the next tick:
+        await!(app_sender.send(serialize_app_to_app_server(&app_to_app_server))).unwrap();
+        assert_eq!(await!(user_receiver.next()).unwrap(), app_to_app_server);
+
+        await!(tick_sender.send(TimerTick)).unwrap();
+
+        if renewed {
+            // The ticket was renewed, the app stays connected:
+            await!(app_sender.send(serialize_app_to_app_server(&app_to_app_server))).unwrap();
+            assert_eq!(await!(user_receiver.next()).unwrap(), app_to_app_server);
+        } else {
+            // The connection is closed:
+            assert!(await!(user_receiver.next()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_app_incoming_loop_expired() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_app_incoming_loop_expiry(false, thread_pool.clone()));
+    }
+
+    #[test]
+    fn test_app_incoming_loop_renewed() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_app_incoming_loop_expiry(true, thread_pool.clone()));
+    }
+}
@@ -1,6 +1,9 @@
-use futures::channel::mpsc;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
-use futures::{select, Future, FutureExt, SinkExt, Stream, StreamExt};
+use futures::{Future, SinkExt, Stream, StreamExt, TryFutureExt};
 
 use derive_more::*;
 
@@ -10,9 +13,9 @@ use crypto::identity::PublicKey;
 
 use database::DatabaseClient;
 use identity::IdentityClient;
-use timer::TimerClient;
+use timer::{TimerClient, TimerTick};
 
-use app_server::{app_server_loop, AppServerError, IncomingAppConnection};
+use app_server::{app_server_loop, AppServerError, AppServerStats, IncomingAppConnection};
 use channeler::{spawn_channeler, ChannelerError};
 use funder::types::{
     ChannelerConfig, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
@@ -23,7 +26,7 @@ use secure_channel::SecureChannel;
 
 use index_client::{spawn_index_client, IndexClientError};
 
-use proto::app_server::messages::RelayAddress;
+use proto::app_server::messages::{NodeReport, RelayAddress};
 use proto::funder::messages::{
     ChannelerToFunder, FunderIncomingControl, FunderOutgoingControl, FunderToChanneler,
 };
@@ -33,11 +36,14 @@ use proto::net::messages::NetAddress;
 use proto::report::convert::funder_report_to_index_client_state;
 
 use crate::adapters::{EncKeepaliveConnector, EncRelayConnector};
-use crate::types::{create_node_report, NodeConfig, NodeMutation, NodeState};
+use crate::metrics::NodeMetrics;
+use crate::shutdown::{until_shutdown, NodeComponent, ShutdownCoordinator};
+use crate::types::{InitialNodeReport, NodeConfig, NodeMutation, NodeState};
 
 #[derive(Debug, From)]
 pub enum NodeError {
     RequestPublicKeyError,
+    RequestTimerStreamError,
     SpawnError,
     ChannelerError(ChannelerError),
     FunderError(FunderError),
@@ -52,6 +58,7 @@ fn node_spawn_channeler<C, R, S>(
     timer_client: TimerClient,
     version_connector: C,
     rng: R,
+    metrics: &NodeMetrics,
     from_funder: mpsc::Receiver<FunderToChanneler<RelayAddress>>,
     to_funder: mpsc::Sender<ChannelerToFunder>,
     mut spawner: S,
@@ -70,6 +77,8 @@ where
         rng.clone(),
         timer_client.clone(),
         node_config.ticks_to_rekey,
+        node_config.rekey_min_ticks,
+        node_config.sym_encrypt_algorithm,
         spawner.clone(),
     );
 
@@ -88,9 +97,12 @@ where
             node_config.backoff_ticks,
             node_config.conn_timeout_ticks,
             node_config.max_concurrent_encrypt,
+            node_config.incoming_conn_wait_ticks,
             enc_relay_connector,
             encrypt_transform,
             keepalive_transform,
+            node_config.opt_access_control_path.clone(),
+            metrics.listening_relays(),
             from_funder,
             to_funder,
             spawner.clone(),
@@ -107,6 +119,8 @@ fn node_spawn_funder<R, S>(
     mut to_channeler: mpsc::Sender<FunderToChanneler<RelayAddress>>,
     from_app_server: mpsc::Receiver<FunderIncomingControl<NetAddress>>,
     to_app_server: mpsc::Sender<FunderOutgoingControl<NetAddress>>,
+    timer_stream: mpsc::Receiver<TimerTick>,
+    metrics: Arc<NodeMetrics>,
     rng: R,
     mut spawner: S,
 ) -> Result<impl Future<Output = Result<(), FunderError>>, NodeError>
@@ -143,14 +157,24 @@ where
     // Channeler to funder adapter:
     let (mut incoming_comm_sender, incoming_comm) = mpsc::channel(0);
     let channeler_to_funder_adapter = async move {
+        // Used for metrics:
+        let mut online_friends = HashSet::new();
         while let Some(channeler_message) = await!(from_channeler.next()) {
             let opt_to_funder_message = match channeler_message {
-                ChannelerToFunder::Online(public_key) => Some(FunderIncomingComm::Liveness(
-                    IncomingLivenessMessage::Online(public_key),
-                )),
-                ChannelerToFunder::Offline(public_key) => Some(FunderIncomingComm::Liveness(
-                    IncomingLivenessMessage::Offline(public_key),
-                )),
+                ChannelerToFunder::Online(public_key) => {
+                    online_friends.insert(public_key.clone());
+                    metrics.set_online_friends(online_friends.len());
+                    Some(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::Online(public_key),
+                    ))
+                }
+                ChannelerToFunder::Offline(public_key) => {
+                    online_friends.remove(&public_key);
+                    metrics.set_online_friends(online_friends.len());
+                    Some(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::Offline(public_key),
+                    ))
+                }
                 ChannelerToFunder::Message((public_key, data)) => {
                     if let Ok(friend_message) = deserialize_friend_message(&data[..]) {
                         Some(FunderIncomingComm::Friend((public_key, friend_message)))
@@ -209,11 +233,16 @@ where
         rng.clone(),
         from_app_server,
         incoming_comm,
+        timer_stream,
         to_app_server,
         outgoing_comm_sender,
-        node_config.max_node_relays,
         node_config.max_operations_in_batch,
+        node_config.max_node_relays,
+        node_config.max_friends,
         node_config.max_pending_user_requests,
+        node_config.max_pending_ticks,
+        node_config.max_frozen_credits_threshold,
+        node_config.history_retention_events,
         funder_state,
         funder_db_client,
     );
@@ -245,7 +274,12 @@ where
     R: CryptoRandom + Clone + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
-    let initial_node_report = create_node_report(&node_state);
+    let initial_node_report = NodeReport::from_initial_state(
+        &node_state.funder_state,
+        &node_state.index_client_config,
+        node_config.max_friends,
+        node_config.max_node_relays,
+    );
 
     // Database adapter:
     let (request_sender, mut request_receiver) = mpsc::channel(0);
@@ -281,6 +315,8 @@ where
         rng.clone(),
         timer_client.clone(),
         node_config.ticks_to_rekey,
+        node_config.rekey_min_ticks,
+        node_config.sym_encrypt_algorithm,
         spawner.clone(),
     );
 
@@ -324,6 +360,8 @@ pub async fn node<C, IA, R, S>(
     database_client: DatabaseClient<NodeMutation<NetAddress>>,
     version_connector: C,
     incoming_apps: IA,
+    shutdown_receiver: oneshot::Receiver<()>,
+    metrics: Arc<NodeMetrics>,
     rng: R,
     mut spawner: S,
 ) -> Result<(), NodeError>
@@ -341,13 +379,34 @@ where
     let local_public_key = await!(identity_client.request_public_key())
         .map_err(|_| NodeError::RequestPublicKeyError)?;
 
-    let initial_node_report = create_node_report(&node_state);
+    let initial_node_report = NodeReport::from_initial_state(
+        &node_state.funder_state,
+        &node_state.index_client_config,
+        node_config.max_friends,
+        node_config.max_node_relays,
+    );
+
+    let funder_report = &initial_node_report.funder_report;
+    metrics.set_open_payments(funder_report.num_payments);
+    metrics.set_open_transactions(funder_report.num_open_transactions);
+
+    // Components are shut down in this order. Every component is signaled to shut down by closing
+    // its main incoming stream:
+    let (app_server_shutdown_sender, app_server_shutdown_receiver) = oneshot::channel();
+    let (funder_shutdown_sender, funder_shutdown_receiver) = oneshot::channel();
+    let (channeler_shutdown_sender, channeler_shutdown_receiver) = oneshot::channel();
+    let (index_client_shutdown_sender, index_client_shutdown_receiver) = oneshot::channel();
 
     // Channeler <--> Funder
     let (channeler_to_funder_sender, channeler_to_funder_receiver) =
         mpsc::channel(node_config.channel_len);
     let (funder_to_channeler_sender, funder_to_channeler_receiver) =
         mpsc::channel(node_config.channel_len);
+    let funder_to_channeler_receiver = until_shutdown(
+        funder_to_channeler_receiver,
+        channeler_shutdown_receiver,
+        &mut spawner,
+    )?;
 
     let channeler_handle = node_spawn_channeler(
         &node_config,
@@ -356,6 +415,7 @@ where
         timer_client.clone(),
         version_connector.clone(),
         rng.clone(),
+        &metrics,
         funder_to_channeler_receiver,
         channeler_to_funder_sender,
         spawner.clone(),
@@ -366,6 +426,16 @@ where
         mpsc::channel(node_config.channel_len);
     let (funder_to_app_server_sender, funder_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
+    let app_server_to_funder_receiver = until_shutdown(
+        app_server_to_funder_receiver,
+        funder_shutdown_receiver,
+        &mut spawner,
+    )?;
+
+    // Used to expire open invoices:
+    let mut c_timer_client = timer_client.clone();
+    let funder_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NodeError::RequestTimerStreamError)?;
 
     let funder_handle = node_spawn_funder(
         &node_config,
@@ -376,6 +446,8 @@ where
         funder_to_channeler_sender,
         app_server_to_funder_receiver,
         funder_to_app_server_sender,
+        funder_timer_stream,
+        metrics.clone(),
         rng.clone(),
         spawner.clone(),
     )?;
@@ -385,6 +457,26 @@ where
         mpsc::channel(node_config.channel_len);
     let (index_client_to_app_server_sender, index_client_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
+    let app_server_to_index_client_receiver = until_shutdown(
+        app_server_to_index_client_receiver,
+        index_client_shutdown_receiver,
+        &mut spawner,
+    )?;
+    let incoming_apps = until_shutdown(incoming_apps, app_server_shutdown_receiver, &mut spawner)?;
+
+    // Used to limit the rate of requests from apps:
+    let mut c_timer_client = timer_client.clone();
+    let app_server_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NodeError::RequestTimerStreamError)?;
+
+    // Observe messages sent to the app server for metrics:
+    let c_metrics = metrics.clone();
+    let funder_to_app_server_receiver = funder_to_app_server_receiver
+        .inspect(move |funder_outgoing| c_metrics.observe_funder_outgoing(funder_outgoing));
+    let index_client_to_app_server_receiver =
+        index_client_to_app_server_receiver.inspect(move |index_client_outgoing| {
+            metrics.observe_index_client_outgoing(index_client_outgoing)
+        });
 
     let app_server_fut = app_server_loop(
         funder_to_app_server_receiver,
@@ -392,34 +484,50 @@ where
         index_client_to_app_server_receiver,
         app_server_to_index_client_sender,
         incoming_apps,
+        app_server_timer_stream,
         initial_node_report.clone(),
+        AppServerStats::new(),
+        rng.clone(),
         spawner.clone(),
     );
 
-    let app_server_handle = spawner
-        .spawn_with_handle(app_server_fut)
-        .map_err(|_| NodeError::SpawnError)?;
-
     let index_client_handle = await!(node_spawn_index_client(
         &node_config,
         local_public_key,
         identity_client,
-        timer_client,
+        timer_client.clone(),
         &node_state,
         database_client,
         app_server_to_index_client_receiver,
         index_client_to_app_server_sender,
         version_connector,
         rng,
-        spawner
+        spawner.clone()
     ))?;
 
-    // Wait for death of any component
-    select! {
-        res = channeler_handle.fuse() => res?,
-        res = funder_handle.fuse() => res?,
-        res = app_server_handle.fuse() => res?,
-        res = index_client_handle.fuse() => res?,
-    }
-    Ok(())
+    let mut shutdown_coordinator =
+        ShutdownCoordinator::new(timer_client, node_config.drain_timeout_ticks, spawner);
+    shutdown_coordinator.add_component(
+        NodeComponent::AppServer,
+        app_server_fut.map_err(NodeError::from),
+        app_server_shutdown_sender,
+    )?;
+    shutdown_coordinator.add_component(
+        NodeComponent::Funder,
+        funder_handle.map_err(NodeError::from),
+        funder_shutdown_sender,
+    )?;
+    shutdown_coordinator.add_component(
+        NodeComponent::Channeler,
+        channeler_handle.map_err(NodeError::from),
+        channeler_shutdown_sender,
+    )?;
+    shutdown_coordinator.add_component(
+        NodeComponent::IndexClient,
+        index_client_handle.map_err(NodeError::from),
+        index_client_shutdown_sender,
+    )?;
+
+    // Wait for death of any component, or for a shutdown request
+    await!(shutdown_coordinator.run(shutdown_receiver))
 }
@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+use std::mem;
+
+use futures::channel::{mpsc, oneshot};
+use futures::future::RemoteHandle;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, Future, SinkExt, Stream, StreamExt};
+
+use timer::TimerClient;
+
+use crate::node::NodeError;
+
+/// A component of the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeComponent {
+    AppServer,
+    Funder,
+    Channeler,
+    IndexClient,
+}
+
+enum CoordinatorEvent {
+    ComponentDone((NodeComponent, Result<(), NodeError>)),
+    Shutdown,
+}
+
+enum DrainEvent {
+    ComponentDone((NodeComponent, Result<(), NodeError>)),
+    TimerTick,
+}
+
+struct ShutdownComponent {
+    component: NodeComponent,
+    shutdown_sender: oneshot::Sender<()>,
+    /// Dropping the handle aborts the component.
+    handle: RemoteHandle<()>,
+}
+
+/// Runs the components of the node, and shuts them down one by one when requested.
+pub struct ShutdownCoordinator<S> {
+    /// Components, in shutdown order
+    components: Vec<ShutdownComponent>,
+    done_sender: mpsc::Sender<(NodeComponent, Result<(), NodeError>)>,
+    done_receiver: mpsc::Receiver<(NodeComponent, Result<(), NodeError>)>,
+    timer_client: TimerClient,
+    drain_timeout_ticks: usize,
+    spawner: S,
+}
+
+impl<S> ShutdownCoordinator<S>
+where
+    S: Spawn,
+{
+    pub fn new(timer_client: TimerClient, drain_timeout_ticks: usize, spawner: S) -> Self {
+        let (done_sender, done_receiver) = mpsc::channel(0);
+        ShutdownCoordinator {
+            components: Vec::new(),
+            done_sender,
+            done_receiver,
+            timer_client,
+            drain_timeout_ticks,
+            spawner,
+        }
+    }
+
+    /// Spawn a component. Components are shut down in the order they were added.
+    /// `shutdown_sender` is used to signal the component that it should shut down.
+    pub fn add_component<F>(
+        &mut self,
+        component: NodeComponent,
+        component_fut: F,
+        shutdown_sender: oneshot::Sender<()>,
+    ) -> Result<(), NodeError>
+    where
+        F: Future<Output = Result<(), NodeError>> + Send + 'static,
+    {
+        let mut done_sender = self.done_sender.clone();
+        let fut = async move {
+            let res = await!(component_fut);
+            let _ = await!(done_sender.send((component, res)));
+        };
+        let handle = self
+            .spawner
+            .spawn_with_handle(fut)
+            .map_err(|_| NodeError::SpawnError)?;
+
+        self.components.push(ShutdownComponent {
+            component,
+            shutdown_sender,
+            handle,
+        });
+        Ok(())
+    }
+
+    /// Wait until any component ends, or until a shutdown is requested through
+    /// `shutdown_receiver`. If the sender side of `shutdown_receiver` is dropped, a shutdown is
+    /// never requested.
+    pub async fn run(mut self, shutdown_receiver: oneshot::Receiver<()>) -> Result<(), NodeError> {
+        let shutdown_stream = stream::once(shutdown_receiver).filter_map(|res| {
+            future::ready(match res {
+                Ok(()) => Some(CoordinatorEvent::Shutdown),
+                Err(oneshot::Canceled) => None,
+            })
+        });
+
+        let opt_event = {
+            let mut events = stream::select(
+                (&mut self.done_receiver).map(CoordinatorEvent::ComponentDone),
+                shutdown_stream,
+            );
+            await!(events.next())
+        };
+
+        match opt_event {
+            Some(CoordinatorEvent::ComponentDone((component, res))) => {
+                error!("Node component {:?} has ended: {:?}", component, res);
+                res
+            }
+            Some(CoordinatorEvent::Shutdown) => {
+                info!("Shutting down node");
+                await!(self.shutdown());
+                Ok(())
+            }
+            // We keep `done_sender`, so this should never happen:
+            None => Ok(()),
+        }
+    }
+
+    /// Shut down all the components, one by one.
+    /// Every component is given up to `drain_timeout_ticks` ticks to finish handling in-flight
+    /// requests. A component that does not finish in time is aborted.
+    async fn shutdown(&mut self) {
+        let mut timer_stream = match await!(self.timer_client.request_timer_stream()) {
+            Ok(timer_stream) => timer_stream,
+            Err(e) => {
+                error!("Failed to obtain a timer stream for shutdown: {:?}", e);
+                return;
+            }
+        };
+
+        let mut done_components = HashSet::new();
+        for shutdown_component in mem::replace(&mut self.components, Vec::new()) {
+            let ShutdownComponent {
+                component,
+                shutdown_sender,
+                handle,
+            } = shutdown_component;
+
+            let _ = shutdown_sender.send(());
+
+            let mut ticks_left = self.drain_timeout_ticks;
+            while !done_components.contains(&component) && ticks_left > 0 {
+                let opt_event = {
+                    let mut events = stream::select(
+                        (&mut self.done_receiver).map(DrainEvent::ComponentDone),
+                        (&mut timer_stream).map(|_| DrainEvent::TimerTick),
+                    );
+                    await!(events.next())
+                };
+                match opt_event {
+                    Some(DrainEvent::ComponentDone((done_component, res))) => {
+                        info!("Node component {:?} has ended: {:?}", done_component, res);
+                        done_components.insert(done_component);
+                    }
+                    Some(DrainEvent::TimerTick) => ticks_left -= 1,
+                    None => break,
+                }
+            }
+
+            if !done_components.contains(&component) {
+                warn!(
+                    "Node component {:?} did not shut down in time. Aborting.",
+                    component
+                );
+            }
+            drop(handle);
+        }
+    }
+}
+
+/// Forward items from `incoming` until a shutdown signal is received through
+/// `shutdown_receiver` (Or until the corresponding sender is dropped).
+/// A component reading from the returned receiver will see it closed on shutdown.
+pub fn until_shutdown<T, St, S>(
+    incoming: St,
+    shutdown_receiver: oneshot::Receiver<()>,
+    spawner: &mut S,
+) -> Result<mpsc::Receiver<T>, NodeError>
+where
+    T: Send + 'static,
+    St: Stream<Item = T> + Unpin + Send + 'static,
+    S: Spawn,
+{
+    enum ForwardEvent<T> {
+        Item(T),
+        Shutdown,
+    }
+
+    let (mut sender, receiver) = mpsc::channel(0);
+
+    let incoming = incoming
+        .map(ForwardEvent::Item)
+        .chain(stream::once(future::ready(ForwardEvent::Shutdown)));
+    let shutdown_stream = stream::once(shutdown_receiver).map(|_| ForwardEvent::Shutdown);
+    let mut events = stream::select(incoming, shutdown_stream);
+
+    let forward_fut = async move {
+        while let Some(ForwardEvent::Item(item)) = await!(events.next()) {
+            if await!(sender.send(item)).is_err() {
+                return;
+            }
+        }
+    };
+    spawner
+        .spawn(forward_fut)
+        .map_err(|_| NodeError::SpawnError)?;
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
+    async fn task_shutdown_coordinator<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        let mut shutdown_coordinator = ShutdownCoordinator::new(timer_client, 2, spawner.clone());
+
+        // A component that shuts down when it is signaled:
+        let (a_shutdown_sender, a_shutdown_receiver) = oneshot::channel();
+        let (a_done_sender, a_done_receiver) = oneshot::channel();
+        let a_fut = async move {
+            await!(a_shutdown_receiver).unwrap();
+            a_done_sender.send(()).unwrap();
+            Ok(())
+        };
+        shutdown_coordinator
+            .add_component(NodeComponent::AppServer, a_fut, a_shutdown_sender)
+            .unwrap();
+
+        // A component that never shuts down:
+        let (b_shutdown_sender, _b_shutdown_receiver) = oneshot::channel();
+        let (b_alive_sender, mut b_alive_receiver) = mpsc::channel::<()>(0);
+        let b_fut = async move {
+            let _b_alive_sender = b_alive_sender;
+            await!(future::pending::<()>());
+            Ok(())
+        };
+        shutdown_coordinator
+            .add_component(NodeComponent::Funder, b_fut, b_shutdown_sender)
+            .unwrap();
+
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let run_handle = spawner
+            .spawn_with_handle(shutdown_coordinator.run(shutdown_receiver))
+            .unwrap();
+
+        shutdown_sender.send(()).unwrap();
+
+        // Keep sending ticks until the coordinator is done with the timer stream:
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+        spawner
+            .spawn(async move { while await!(tick_sender.send(TimerTick)).is_ok() {} })
+            .unwrap();
+
+        assert!(await!(run_handle).is_ok());
+        assert!(await!(a_done_receiver).is_ok());
+        // The stuck component was aborted:
+        assert!(await!(b_alive_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_shutdown_coordinator() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_shutdown_coordinator(thread_pool.clone()));
+    }
+
+    async fn task_shutdown_coordinator_component_ended<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        let mut shutdown_coordinator = ShutdownCoordinator::new(timer_client, 2, spawner.clone());
+
+        let (a_shutdown_sender, _a_shutdown_receiver) = oneshot::channel();
+        shutdown_coordinator
+            .add_component(
+                NodeComponent::Channeler,
+                future::ready(Err(NodeError::SpawnError)),
+                a_shutdown_sender,
+            )
+            .unwrap();
+
+        // No shutdown is requested if the sender is dropped:
+        let (_, shutdown_receiver) = oneshot::channel();
+        match await!(shutdown_coordinator.run(shutdown_receiver)) {
+            Err(NodeError::SpawnError) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_shutdown_coordinator_component_ended() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_shutdown_coordinator_component_ended(
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_until_shutdown<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (mut sender, receiver) = mpsc::channel::<u32>(0);
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let mut receiver = until_shutdown(receiver, shutdown_receiver, &mut spawner).unwrap();
+
+        await!(sender.send(1)).unwrap();
+        assert_eq!(await!(receiver.next()), Some(1));
+
+        shutdown_sender.send(()).unwrap();
+        assert!(await!(receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_until_shutdown() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_until_shutdown(thread_pool.clone()));
+    }
+}
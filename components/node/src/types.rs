@@ -1,13 +1,19 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
 use common::canonical_serialize::CanonicalSerialize;
 use common::mutable_state::MutableState;
 
 use crypto::identity::PublicKey;
+use crypto::sym_encrypt::SymEncryptAlgorithm;
+use database::file_db::VersionedState;
 use funder::report::create_initial_report;
 use funder::{FunderMutation, FunderState};
 use index_client::{IndexClientConfig, IndexClientConfigMutation};
 
 use proto::app_server::messages::NodeReport;
 use proto::index_client::messages::IndexClientReport;
+use proto::net::messages::NetAddress;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NodeMutation<B: Clone> {
@@ -15,12 +21,28 @@ pub enum NodeMutation<B: Clone> {
     IndexClient(IndexClientConfigMutation<B>),
 }
 
+/// Version of the serialized layout of NodeState, saved in the node's database file.
+/// Version 0: Database files written before the database file header was introduced.
+/// Version 1: Adds route caching and bounded friends advertising to IndexClientConfig, together
+/// with the FunderState changes of the same release.
+/// Version 2: Payments keep the routes of their open transactions.
+/// Version 3: Channel statistics are kept in the mutual credit state instead of the friend state.
+/// Version 4: The invoice index of payments keeps every payment of an invoice.
+pub const NODE_STATE_VERSION: u32 = 4;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeState<B: Clone> {
     pub funder_state: FunderState<B>,
     pub index_client_config: IndexClientConfig<B>,
 }
 
+impl<B> VersionedState for NodeState<B>
+where
+    B: Clone,
+{
+    const STATE_VERSION: u32 = NODE_STATE_VERSION;
+}
+
 impl<B> NodeState<B>
 where
     B: Clone + CanonicalSerialize,
@@ -66,17 +88,40 @@ where
         index_servers: index_client_config.index_servers.clone(),
         // Initially we are not connected to a server:
         opt_connected_server: None,
+        circuit_states: HashMap::new(),
     }
 }
 
-/// Create an initial NodeReport, based on a NodeState
-pub fn create_node_report<B>(node_state: &NodeState<B>) -> NodeReport<B>
+/// Construct the initial NodeReport of a node.
+/// (NodeReport is defined in `proto`, which does not know about FunderState and
+/// IndexClientConfig, hence the trait)
+pub trait InitialNodeReport<B>
+where
+    B: Clone,
+{
+    /// Create an initial NodeReport, based on the persistent state of a node.
+    fn from_initial_state(
+        funder_state: &FunderState<B>,
+        index_client_config: &IndexClientConfig<B>,
+        max_friends: usize,
+        max_node_relays: usize,
+    ) -> Self;
+}
+
+impl<B> InitialNodeReport<B> for NodeReport<B>
 where
     B: Clone + CanonicalSerialize,
 {
-    NodeReport {
-        funder_report: create_initial_report(&node_state.funder_state),
-        index_client_report: create_index_client_report(&node_state.index_client_config),
+    fn from_initial_state(
+        funder_state: &FunderState<B>,
+        index_client_config: &IndexClientConfig<B>,
+        max_friends: usize,
+        max_node_relays: usize,
+    ) -> Self {
+        NodeReport {
+            funder_report: create_initial_report(funder_state, max_friends, max_node_relays),
+            index_client_report: create_index_client_report(index_client_config),
+        }
     }
 }
 
@@ -90,6 +135,12 @@ pub struct NodeConfig {
     pub keepalive_ticks: usize,
     /// Amount of ticks to wait until the next rekeying (Channel encryption)
     pub ticks_to_rekey: usize,
+    /// Minimal amount of ticks between two rekeys. A remote side that initiates rekeying more
+    /// often will be disconnected.
+    pub rekey_min_ticks: usize,
+    /// Preferred symmetric encryption algorithm (Channel encryption). Used only with remote sides
+    /// that prefer it too, otherwise the default algorithm is used.
+    pub sym_encrypt_algorithm: SymEncryptAlgorithm,
     /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
     /// time from external communications (Channeler side)
     pub max_concurrent_encrypt: usize,
@@ -99,11 +150,136 @@ pub struct NodeConfig {
     pub max_operations_in_batch: usize,
     /// The size we allocate for the user send funds requests queue.
     pub max_pending_user_requests: usize,
+    /// The amount of ticks we wait for a pending transaction (for which we are the destination)
+    /// to be collected, before we cancel it.
+    pub max_pending_ticks: usize,
+    /// Total amount of frozen credits (local or remote) above which the funder emits a warning.
+    pub max_frozen_credits_threshold: u128,
+    /// Completed payments are kept in the payment history until this amount of payment events
+    /// was recorded after their completion. (A payment event is recorded whenever a payment is
+    /// created, gets a new transaction or changes its state).
+    pub history_retention_events: u64,
     /// Maximum amount of concurrent index client requests:
     pub max_open_index_client_requests: usize,
     /// Maximum amount of relays a node may use.
     pub max_node_relays: usize,
+    /// Maximum amount of friends a node may have.
+    pub max_friends: usize,
     /// Maximum amount of encryption set ups we allow to occur at the same time
     /// for incoming app connections
     pub max_concurrent_incoming_apps: usize,
+    /// The amount of ticks an incoming connection (App or channeler) waits for a free slot when
+    /// the maximum amount of concurrent encryption set ups is reached, before it is dropped.
+    pub incoming_conn_wait_ticks: usize,
+    /// The amount of ticks we wait for every component to finish its in-flight work during a
+    /// graceful shutdown, before it is aborted.
+    pub drain_timeout_ticks: usize,
+    /// Address of a SOCKS5 proxy. If set, all outgoing connections (To relays and index servers)
+    /// are made through the proxy.
+    pub opt_socks5_proxy: Option<NetAddress>,
+    /// A file used to persist the access control list of the relay listeners between runs.
+    pub opt_access_control_path: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    use funder::report::funder_mutation_to_report_mutations;
+    use proto::app_server::messages::{NamedRelayAddress, NodeReportMutation};
+    use proto::funder::messages::AddFriend;
+    use proto::index_client::messages::IndexClientReportMutation;
+    use proto::index_server::messages::NamedIndexServerAddress;
+
+    const MAX_FRIENDS: usize = 0x10;
+    const MAX_NODE_RELAYS: usize = 0x10;
+
+    /// Convert a NodeMutation to the matching report mutations.
+    /// `node_state` is the state before the mutation is applied.
+    fn node_mutation_to_report_mutations(
+        node_mutation: &NodeMutation<u32>,
+        node_state: &NodeState<u32>,
+    ) -> Vec<NodeReportMutation<u32>> {
+        match node_mutation {
+            NodeMutation::Funder(funder_mutation) => {
+                funder_mutation_to_report_mutations(funder_mutation, &node_state.funder_state)
+                    .into_iter()
+                    .map(NodeReportMutation::Funder)
+                    .collect()
+            }
+            NodeMutation::IndexClient(index_client_mutation) => match index_client_mutation {
+                IndexClientConfigMutation::AddIndexServer(named_index_server_address) => {
+                    vec![NodeReportMutation::IndexClient(
+                        IndexClientReportMutation::AddIndexServer(
+                            named_index_server_address.clone(),
+                        ),
+                    )]
+                }
+                IndexClientConfigMutation::RemoveIndexServer(public_key) => {
+                    vec![NodeReportMutation::IndexClient(
+                        IndexClientReportMutation::RemoveIndexServer(public_key.clone()),
+                    )]
+                }
+                IndexClientConfigMutation::RotateServer { remove, add } => vec![
+                    NodeReportMutation::IndexClient(IndexClientReportMutation::RemoveIndexServer(
+                        remove.clone(),
+                    )),
+                    NodeReportMutation::IndexClient(IndexClientReportMutation::AddIndexServer(
+                        add.clone(),
+                    )),
+                ],
+            },
+        }
+    }
+
+    #[test]
+    fn test_from_initial_state_with_mutations() {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut node_state = NodeState::<u32>::new(local_public_key);
+        let mut node_report = NodeReport::from_initial_state(
+            &node_state.funder_state,
+            &node_state.index_client_config,
+            MAX_FRIENDS,
+            MAX_NODE_RELAYS,
+        );
+
+        let node_mutations = vec![
+            NodeMutation::Funder(FunderMutation::AddRelay(NamedRelayAddress {
+                public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                address: 0xbb,
+                name: "relay".into(),
+            })),
+            NodeMutation::Funder(FunderMutation::AddFriend(AddFriend {
+                friend_public_key: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+                relays: Vec::new(),
+                name: "friend".into(),
+                note: String::new(),
+                balance: 5,
+            })),
+            NodeMutation::IndexClient(IndexClientConfigMutation::AddIndexServer(
+                NamedIndexServerAddress {
+                    public_key: PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+                    address: 0xdd,
+                    name: "index_server".into(),
+                },
+            )),
+        ];
+
+        for node_mutation in &node_mutations {
+            for report_mutation in node_mutation_to_report_mutations(node_mutation, &node_state) {
+                node_report.mutate(&report_mutation).unwrap();
+            }
+            node_state.mutate(node_mutation).unwrap();
+        }
+
+        let steady_state_report = NodeReport::from_initial_state(
+            &node_state.funder_state,
+            &node_state.index_client_config,
+            MAX_FRIENDS,
+            MAX_NODE_RELAYS,
+        );
+        assert_eq!(node_report, steady_state_report);
+    }
 }
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use proto::secure_channel::serialize::deserialize_exchange_rand_nonce;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = deserialize_exchange_rand_nonce(data);
+});
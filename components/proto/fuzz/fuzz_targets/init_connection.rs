@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use proto::relay::serialize::deserialize_init_connection;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = deserialize_init_connection(data);
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use proto::keepalive::serialize::deserialize_ka_message;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = deserialize_ka_message(data);
+});
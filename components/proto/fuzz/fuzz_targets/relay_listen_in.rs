@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use proto::relay::serialize::deserialize_relay_listen_in;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = deserialize_relay_listen_in(data);
+});
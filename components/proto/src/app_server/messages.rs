@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use common::canonical_serialize::CanonicalSerialize;
 use common::mutable_state::MutableState;
 use crypto::identity::PublicKey;
@@ -6,8 +8,9 @@ use crypto::payment_id::PaymentId;
 use crypto::uid::Uid;
 
 use crate::funder::messages::{
-    AckClosePayment, AddFriend, AddInvoice, CreatePayment, CreateTransaction, MultiCommit,
-    ResetFriendChannel, ResponseClosePayment, SetFriendName, SetFriendRate, SetFriendRelays,
+    AckClosePayment, AddFriend, AddInvoice, CreateMultiRoutePayment, CreatePayment,
+    CreateTransaction, MultiCommit, ResetFriendChannel, ResponseClosePayment,
+    ResponsePaymentTimeline, SetFriendName, SetFriendNote, SetFriendRate, SetFriendRelays,
     SetFriendRemoteMaxDebt, TransactionResult,
 };
 use crate::index_client::messages::{
@@ -25,7 +28,7 @@ pub struct NamedRelayAddress<B = NetAddress> {
     pub name: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, CanonicalSerialize)]
 pub struct RelayAddress<B = NetAddress> {
     pub public_key: PublicKey,
     pub address: B,
@@ -40,19 +43,7 @@ impl<B> From<NamedRelayAddress<B>> for RelayAddress<B> {
     }
 }
 
-impl<B> CanonicalSerialize for RelayAddress<B>
-where
-    B: CanonicalSerialize,
-{
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.public_key);
-        res_bytes.extend_from_slice(&self.address.canonical_serialize());
-        res_bytes
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NodeReport<B = NetAddress>
 where
     B: Clone,
@@ -61,7 +52,7 @@ where
     pub index_client_report: IndexClientReport<B>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeReportMutation<B = NetAddress>
 where
     B: Clone,
@@ -70,7 +61,7 @@ where
     IndexClient(IndexClientReportMutation<B>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ReportMutations<B = NetAddress>
 where
     B: Clone,
@@ -80,7 +71,7 @@ where
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AppServerToApp<B = NetAddress>
 where
     B: Clone,
@@ -88,19 +79,25 @@ where
     /// Funds:
     TransactionResult(TransactionResult),
     ResponseClosePayment(ResponseClosePayment),
-    /// Reports about current state:
-    Report(NodeReport<B>),
+    ResponsePaymentTimeline(ResponsePaymentTimeline),
+    /// Reports about current state.
+    /// The initial report also carries the session token of the app. The app may present this
+    /// token when it reconnects, to keep receiving responses for its pending requests:
+    Report((Uid, NodeReport<B>)),
     ReportMutations(ReportMutations<B>),
     ResponseRoutes(ClientResponseRoutes),
+    /// Transaction results that could not be delivered to the app that issued the transaction,
+    /// together with the request_id of the transaction:
+    ResponseDeadLetterQueue(Vec<(Uid, TransactionResult)>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NamedRelaysMutation<B = NetAddress> {
     AddRelay(NamedRelayAddress<B>),
     RemoveRelay(PublicKey),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AppRequest<B = NetAddress> {
     /// Manage locally used relays:
     AddRelay(NamedRelayAddress<B>),
@@ -109,6 +106,7 @@ pub enum AppRequest<B = NetAddress> {
     AddFriend(AddFriend<B>),
     SetFriendRelays(SetFriendRelays<B>),
     SetFriendName(SetFriendName),
+    SetFriendNote(SetFriendNote),
     RemoveFriend(PublicKey),
     EnableFriend(PublicKey),
     DisableFriend(PublicKey),
@@ -117,22 +115,33 @@ pub enum AppRequest<B = NetAddress> {
     SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt),
     SetFriendRate(SetFriendRate),
     ResetFriendChannel(ResetFriendChannel),
+    FreezeFriend(PublicKey),
+    UnfreezeFriend(PublicKey),
     /// Buyer:
     CreatePayment(CreatePayment),
     CreateTransaction(CreateTransaction),
+    CreateMultiRoutePayment(CreateMultiRoutePayment),
     RequestClosePayment(PaymentId),
     AckClosePayment(AckClosePayment),
+    /// Retrieve the recorded events of a payment (For debugging failed payments):
+    GetPaymentTimeline(PaymentId),
     /// Seller:
     AddInvoice(AddInvoice),
     CancelInvoice(InvoiceId),
     CommitInvoice(MultiCommit),
     /// Request routes from one node to another:
     RequestRoutes(RequestRoutes),
+    /// Report whether a payment along the routes returned for a routes request succeeded:
+    ReportRouteOutcome((Uid, bool)), // (request_id, success)
     /// Manage index servers:
     AddIndexServer(NamedIndexServerAddress<B>),
     RemoveIndexServer(PublicKey),
+    /// Replace a configured index server with a new one in one step:
+    RotateIndexServer((PublicKey, NamedIndexServerAddress<B>)), // (old_public_key, new_server)
+    /// Retrieve transaction results that could not be delivered to their originating app:
+    GetDeadLetterQueue,
 }
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AppToAppServer<B = NetAddress> {
     pub app_request_id: Uid,
     pub app_request: AppRequest<B>,
@@ -181,6 +190,17 @@ where
     }
 }
 
+/// Permissions of an app with respect to a specific friend.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FriendPermissions {
+    /// Can see the friend in reports and report mutations
+    pub read: bool,
+    /// Can set the remote max debt of the friend
+    pub set_max_debt: bool,
+    /// Can open and close requests through the friend
+    pub open_close: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct AppPermissions {
     /// Can request routes
@@ -191,4 +211,17 @@ pub struct AppPermissions {
     pub seller: bool,
     /// Can configure friends
     pub config: bool,
+    /// Maximum amount of requests the app may send per second.
+    /// None means unlimited.
+    #[serde(default)]
+    pub max_requests_per_second: Option<u32>,
+    /// Permissions for requests naming a specific friend.
+    /// For friends without an entry, the `config` permission applies to requests, and the friend
+    /// is visible in reports.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::serde_utils::opt_map_as_pairs"
+    )]
+    pub friend_permissions: Option<HashMap<PublicKey, FriendPermissions>>,
 }
@@ -1,16 +1,17 @@
 use std::io;
 
 use crate::capnp_common::{
-    read_custom_int128, read_custom_u_int128, read_invoice_id, read_named_index_server_address,
-    read_named_relay_address, read_public_key, /*read_receipt,*/ read_relay_address,
-    read_signature, read_uid, write_custom_int128, write_custom_u_int128, write_invoice_id,
-    write_named_index_server_address, write_named_relay_address,
-    write_public_key, /*write_receipt,*/
+    check_list_len, read_custom_int128, read_custom_u_int128, read_invoice_id,
+    read_named_index_server_address, read_named_relay_address, read_public_key,
+    /*read_receipt,*/ read_relay_address, read_signature, read_string, read_uid,
+    write_custom_int128, write_custom_u_int128, write_invoice_id, write_named_index_server_address,
+    write_named_relay_address, write_public_key, /*write_receipt,*/
     write_relay_address, write_signature, write_uid,
 };
 use capnp;
 use capnp::serialize_packed;
 use common::int_convert::usize_to_u32;
+use crypto::uid::Uid;
 
 use crate::serialize::SerializeError;
 use app_server_capnp;
@@ -25,7 +26,7 @@ use index_server::serialize::{deser_request_routes, ser_request_routes};
 use crate::funder::messages::{
     AddFriend, ReceiptAck,
     ResetFriendChannel, /* ResponseReceived, ResponseSendFundsResult, */
-    SetFriendName, SetFriendRelays, SetFriendRemoteMaxDebt, UserRequestSendFunds,
+    SetFriendName, SetFriendNote, SetFriendRelays, SetFriendRemoteMaxDebt, UserRequestSendFunds,
 };
 use crate::funder::serialize::{deser_friends_route, ser_friends_route};
 
@@ -163,6 +164,7 @@ fn ser_add_friend(
     }
 
     add_friend_builder.reborrow().set_name(&add_friend.name);
+    add_friend_builder.reborrow().set_note(&add_friend.note);
     write_custom_int128(
         add_friend.balance,
         &mut add_friend_builder.reborrow().init_balance(),
@@ -174,14 +176,17 @@ fn deser_add_friend(
 ) -> Result<AddFriend, SerializeError> {
     // TODO
     let mut relays = Vec::new();
-    for relay_address in add_friend_reader.get_relays()? {
+    let relays_reader = add_friend_reader.get_relays()?;
+    check_list_len(relays_reader.len())?;
+    for relay_address in relays_reader {
         relays.push(read_relay_address(&relay_address)?);
     }
 
     Ok(AddFriend {
         friend_public_key: read_public_key(&add_friend_reader.get_friend_public_key()?)?,
         relays,
-        name: add_friend_reader.get_name()?.to_owned(),
+        name: read_string(add_friend_reader.get_name()?)?,
+        note: read_string(add_friend_reader.get_note()?)?,
         balance: read_custom_int128(&add_friend_reader.get_balance()?)?,
     })
 }
@@ -203,7 +208,28 @@ fn deser_set_friend_name(
 ) -> Result<SetFriendName, SerializeError> {
     Ok(SetFriendName {
         friend_public_key: read_public_key(&set_friend_name_reader.get_friend_public_key()?)?,
-        name: set_friend_name_reader.get_name()?.to_owned(),
+        name: read_string(set_friend_name_reader.get_name()?)?,
+    })
+}
+
+fn ser_set_friend_note(
+    set_friend_note: &SetFriendNote,
+    set_friend_note_builder: &mut app_server_capnp::set_friend_note::Builder,
+) {
+    write_public_key(
+        &set_friend_note.friend_public_key,
+        &mut set_friend_note_builder.reborrow().init_friend_public_key(),
+    );
+
+    set_friend_note_builder.set_note(&set_friend_note.note);
+}
+
+fn deser_set_friend_note(
+    set_friend_note_reader: &app_server_capnp::set_friend_note::Reader,
+) -> Result<SetFriendNote, SerializeError> {
+    Ok(SetFriendNote {
+        friend_public_key: read_public_key(&set_friend_note_reader.get_friend_public_key()?)?,
+        note: read_string(set_friend_note_reader.get_note()?)?,
     })
 }
 
@@ -230,7 +256,9 @@ fn deser_set_friend_relays(
     set_friend_relays_reader: &app_server_capnp::set_friend_relays::Reader,
 ) -> Result<SetFriendRelays, SerializeError> {
     let mut relays = Vec::new();
-    for relay_address in set_friend_relays_reader.get_relays()? {
+    let relays_reader = set_friend_relays_reader.get_relays()?;
+    check_list_len(relays_reader.len())?;
+    for relay_address in relays_reader {
         relays.push(read_relay_address(&relay_address)?);
     }
 
@@ -331,7 +359,9 @@ fn deser_response_routes_result(
     Ok(match response_routes_result_reader.which()? {
         app_server_capnp::response_routes_result::Success(routes_with_capacity_reader) => {
             let mut routes_with_capacity = Vec::new();
-            for route_with_capacity in routes_with_capacity_reader? {
+            let routes_with_capacity_reader = routes_with_capacity_reader?;
+            check_list_len(routes_with_capacity_reader.len())?;
+            for route_with_capacity in routes_with_capacity_reader {
                 routes_with_capacity.push(deser_route_with_capacity(&route_with_capacity)?);
             }
             ResponseRoutesResult::Success(routes_with_capacity)
@@ -379,7 +409,7 @@ fn deser_add_index_server(add_index_server_reader: &app_server_capnp::add_index_
     Ok(AddIndexServer {
         public_key: read_public_key(&add_index_server_reader.get_public_key()?)?,
         address: read_net_address(&add_index_server_reader.get_address()?)?,
-        name: add_index_server_reader.get_name()?.to_owned(),
+        name: read_string(add_index_server_reader.get_name()?)?,
     })
 }
 */
@@ -456,7 +486,9 @@ fn deser_report_mutations(
     };
 
     let mut mutations = Vec::new();
-    for node_report_mutation in report_mutations_reader.get_mutations()? {
+    let mutations_reader = report_mutations_reader.get_mutations()?;
+    check_list_len(mutations_reader.len())?;
+    for node_report_mutation in mutations_reader {
         mutations.push(deser_node_report_mutation(&node_report_mutation)?);
     }
 
@@ -554,6 +586,10 @@ fn ser_app_request(
             set_friend_name,
             &mut app_request_builder.reborrow().init_set_friend_name(),
         ),
+        AppRequest::SetFriendNote(set_friend_note) => ser_set_friend_note(
+            set_friend_note,
+            &mut app_request_builder.reborrow().init_set_friend_note(),
+        ),
         AppRequest::RemoveFriend(friend_public_key) => write_public_key(
             friend_public_key,
             &mut app_request_builder.reborrow().init_remove_friend(),
@@ -633,6 +669,9 @@ fn deser_app_request(
         app_server_capnp::app_request::SetFriendName(set_friend_name) => {
             AppRequest::SetFriendName(deser_set_friend_name(&set_friend_name?)?)
         }
+        app_server_capnp::app_request::SetFriendNote(set_friend_note) => {
+            AppRequest::SetFriendNote(deser_set_friend_note(&set_friend_note?)?)
+        }
         app_server_capnp::app_request::RemoveFriend(public_key_reader) => {
             AppRequest::RemoveFriend(read_public_key(&public_key_reader?)?)
         }
@@ -717,6 +756,38 @@ pub fn deserialize_app_permissions(data: &[u8]) -> Result<AppPermissions, Serial
     deser_app_permissions(&app_permissions_reader)
 }
 
+pub fn serialize_app_session_request(opt_app_session: &Option<Uid>) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut app_session_request_builder =
+        builder.init_root::<app_server_capnp::app_session_request::Builder>();
+    match opt_app_session {
+        Some(app_session) => write_uid(
+            app_session,
+            &mut app_session_request_builder.reborrow().init_app_session(),
+        ),
+        None => app_session_request_builder.set_empty(()),
+    };
+
+    let mut ser_buff = Vec::new();
+    serialize_packed::write_message(&mut ser_buff, &builder).unwrap();
+    ser_buff
+}
+
+pub fn deserialize_app_session_request(data: &[u8]) -> Result<Option<Uid>, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let app_session_request_reader =
+        reader.get_root::<app_server_capnp::app_session_request::Reader>()?;
+
+    Ok(match app_session_request_reader.which()? {
+        app_server_capnp::app_session_request::AppSession(app_session_reader) => {
+            Some(read_uid(&app_session_reader?)?)
+        }
+        app_server_capnp::app_session_request::Empty(()) => None,
+    })
+}
+
 pub fn serialize_app_server_to_app(app_server_to_app: &AppServerToApp) -> Vec<u8> {
     let mut builder = capnp::message::Builder::new_default();
     let mut app_server_to_app_builder =
@@ -823,6 +894,7 @@ mod tests {
             friend_public_key: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
             relays,
             name: "Friend name".to_owned(),
+            note: String::new(),
             balance: -500,
         };
         let app_to_app_server = AppToAppServer {
@@ -4,19 +4,23 @@ use std::io;
 
 use common_capnp::{
     buffer128, buffer256, buffer512, custom_int128, custom_u_int128, dh_public_key, hash,
-    invoice_id, named_index_server_address, named_relay_address, net_address, public_key,
-    rand_nonce, receipt, relay_address, salt, signature, uid,
+    invoice_id, named_index_server_address, named_relay_address, net_address, plain_lock,
+    public_key, rand_nonce, receipt, relay_address, salt, signature, uid,
 };
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
+use crate::consts::{MAX_LIST_LEN, MAX_STRING_LEN};
 use crate::funder::messages::Receipt;
 use crate::index_server::messages::NamedIndexServerAddress;
 use crate::net::messages::NetAddress;
 use crate::serialize::SerializeError;
 
+use common::int_convert::u32_to_usize;
+
 use crypto::crypto_rand::RandValue;
 use crypto::dh::{DhPublicKey, Salt};
 use crypto::hash::HashResult;
+use crypto::hash_lock::PlainLock;
 use crypto::identity::{PublicKey, Signature};
 use crypto::invoice_id::InvoiceId;
 use crypto::uid::Uid;
@@ -157,6 +161,14 @@ type_capnp_serde!(
     read_buffer256,
     write_buffer256
 );
+type_capnp_serde!(
+    plain_lock,
+    PlainLock,
+    read_plain_lock,
+    write_plain_lock,
+    read_buffer256,
+    write_buffer256
+);
 
 // 512 bits:
 type_capnp_serde!(
@@ -194,6 +206,23 @@ pub fn write_custom_int128(from: i128, to: &mut custom_int128::Builder) {
     write_buffer128(&data_bytes, &mut inner);
 }
 
+/// Make sure that a list inside a received message is not too long, before reading its
+/// elements.
+pub fn check_list_len(len: u32) -> Result<(), SerializeError> {
+    match u32_to_usize(len) {
+        Some(len) if len <= MAX_LIST_LEN => Ok(()),
+        _ => Err(SerializeError::ListTooLong),
+    }
+}
+
+/// Read a string from a received message, making sure it is not too long.
+pub fn read_string(from: &str) -> Result<String, SerializeError> {
+    if from.len() > MAX_STRING_LEN {
+        return Err(SerializeError::StringTooLong);
+    }
+    Ok(from.to_owned())
+}
+
 pub fn read_net_address(from: &net_address::Reader) -> Result<NetAddress, SerializeError> {
     Ok(from.get_address()?.to_string().try_into()?)
 }
@@ -222,7 +251,7 @@ pub fn read_named_relay_address(
     Ok(NamedRelayAddress {
         public_key: read_public_key(&from.get_public_key()?)?,
         address: read_net_address(&from.get_address()?)?,
-        name: from.get_name()?.to_string(),
+        name: read_string(from.get_name()?)?,
     })
 }
 
@@ -241,7 +270,7 @@ pub fn read_named_index_server_address(
     Ok(NamedIndexServerAddress {
         public_key: read_public_key(&from.get_public_key()?)?,
         address: read_net_address(&from.get_address()?)?,
-        name: from.get_name()?.to_owned(),
+        name: read_string(from.get_name()?)?,
     })
 }
 
@@ -270,23 +299,131 @@ pub fn write_index_server_address(from: &IndexServerAddress, to: &mut index_serv
 */
 
 pub fn read_receipt(from: &receipt::Reader) -> Result<Receipt, SerializeError> {
-    unimplemented!();
-    /*
     Ok(Receipt {
         response_hash: read_hash(&from.get_response_hash()?)?,
         invoice_id: read_invoice_id(&from.get_invoice_id()?)?,
+        src_plain_lock: read_plain_lock(&from.get_src_plain_lock()?)?,
+        dest_plain_lock: read_plain_lock(&from.get_dest_plain_lock()?)?,
         dest_payment: read_custom_u_int128(&from.get_dest_payment()?)?,
+        total_dest_payment: read_custom_u_int128(&from.get_total_dest_payment()?)?,
         signature: read_signature(&from.get_signature()?)?,
     })
-    */
 }
 
 pub fn write_receipt(from: &Receipt, to: &mut receipt::Builder) {
-    unimplemented!();
-    /*
     write_hash(&from.response_hash, &mut to.reborrow().init_response_hash());
     write_invoice_id(&from.invoice_id, &mut to.reborrow().init_invoice_id());
+    write_plain_lock(
+        &from.src_plain_lock,
+        &mut to.reborrow().init_src_plain_lock(),
+    );
+    write_plain_lock(
+        &from.dest_plain_lock,
+        &mut to.reborrow().init_dest_plain_lock(),
+    );
     write_custom_u_int128(from.dest_payment, &mut to.reborrow().init_dest_payment());
+    write_custom_u_int128(
+        from.total_dest_payment,
+        &mut to.reborrow().init_total_dest_payment(),
+    );
     write_signature(&from.signature, &mut to.reborrow().init_signature());
-    */
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use capnp::serialize_packed;
+
+    use common::int_convert::usize_to_u32;
+
+    use crypto::hash::HASH_RESULT_LEN;
+    use crypto::hash_lock::PLAIN_LOCK_LEN;
+    use crypto::identity::{PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::INVOICE_ID_LEN;
+
+    use crate::funder::serialize::deser_friends_route;
+    use funder_capnp::friends_route;
+
+    #[test]
+    fn test_receipt_roundtrip() {
+        let receipt = Receipt {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[0x02; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[0x03; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[0x04; PLAIN_LOCK_LEN]),
+            dest_payment: 10,
+            total_dest_payment: u128::max_value(),
+            signature: Signature::from(&[0x05; SIGNATURE_LEN]),
+        };
+
+        let mut builder = capnp::message::Builder::new_default();
+        let mut receipt_builder = builder.init_root::<receipt::Builder>();
+        write_receipt(&receipt, &mut receipt_builder);
+
+        let mut serialized_msg = Vec::new();
+        serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+
+        let mut cursor = io::Cursor::new(&serialized_msg[..]);
+        let reader =
+            serialize_packed::read_message(&mut cursor, capnp::message::ReaderOptions::new())
+                .unwrap();
+        let receipt_reader = reader.get_root::<receipt::Reader>().unwrap();
+        assert_eq!(read_receipt(&receipt_reader).unwrap(), receipt);
+    }
+
+    #[test]
+    fn test_check_list_len() {
+        assert!(check_list_len(0).is_ok());
+        assert!(check_list_len(usize_to_u32(MAX_LIST_LEN).unwrap()).is_ok());
+        assert!(check_list_len(usize_to_u32(MAX_LIST_LEN + 1).unwrap()).is_err());
+        assert!(check_list_len(u32::max_value()).is_err());
+    }
+
+    #[test]
+    fn test_read_friends_route_too_long() {
+        for &(public_keys_len, is_valid) in &[(MAX_LIST_LEN, true), (MAX_LIST_LEN + 1, false)] {
+            let mut builder = capnp::message::Builder::new_default();
+            let mut friends_route_builder = builder.init_root::<friends_route::Builder>();
+            friends_route_builder
+                .reborrow()
+                .init_public_keys(usize_to_u32(public_keys_len).unwrap());
+
+            let friends_route_reader = friends_route_builder.into_reader();
+            match deser_friends_route(&friends_route_reader) {
+                Ok(friends_route) => {
+                    assert!(is_valid);
+                    assert_eq!(friends_route.public_keys.len(), public_keys_len);
+                }
+                Err(SerializeError::ListTooLong) => assert!(!is_valid),
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_named_relay_address_name_too_long() {
+        for &(name_len, is_valid) in &[(MAX_STRING_LEN, true), (MAX_STRING_LEN + 1, false)] {
+            let mut builder = capnp::message::Builder::new_default();
+            let mut named_relay_address_builder =
+                builder.init_root::<named_relay_address::Builder>();
+            write_named_relay_address(
+                &NamedRelayAddress {
+                    public_key: PublicKey::from(&[0x01; PUBLIC_KEY_LEN]),
+                    address: "MyAddress:1337".to_owned().try_into().unwrap(),
+                    name: "a".repeat(name_len),
+                },
+                &mut named_relay_address_builder,
+            );
+
+            let named_relay_address_reader = named_relay_address_builder.into_reader();
+            match read_named_relay_address(&named_relay_address_reader) {
+                Ok(named_relay_address) => {
+                    assert!(is_valid);
+                    assert_eq!(named_relay_address.name.len(), name_len);
+                }
+                Err(SerializeError::StringTooLong) => assert!(!is_valid),
+                Err(e) => panic!("Unexpected error: {:?}", e),
+            }
+        }
+    }
 }
@@ -1,5 +1,8 @@
-/// The current protocol version
-pub const PROTOCOL_VERSION: u32 = 0;
+/// The current protocol version.
+/// Version 1: `ExchangeDh` carries (and signs) the preferred symmetric encryption algorithm, the
+/// relay listener exchanges `Ping`/`Pong` messages, and apps send an `AppSessionRequest` after
+/// the node's permissions.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 /// Maximum amount of friend operations sent in one move token message.
 pub const MAX_OPERATIONS_IN_BATCH: usize = 16;
@@ -13,25 +16,91 @@ pub const TICK_MS: usize = 1000;
 /// Amount of ticks to wait before rekeying a secure channel.
 pub const TICKS_TO_REKEY: usize = 60 * 60 * (1000 / TICK_MS); // 1 hour
 
+/// Minimal amount of ticks between two rekeys of a secure channel.
+/// A remote side that initiates rekeys more often will be disconnected.
+pub const REKEY_MIN_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
 /// If no message was sent for this amount of ticks, the connection will be closed
 pub const KEEPALIVE_TICKS: usize = 0x20;
 
+/// Relay client: Amount of ticks between two pings sent to the relay over a listen connection.
+pub const RELAY_PING_INTERVAL_TICKS: usize = 0x10;
+
+/// Relay client: If no pong arrives from the relay this amount of ticks after a ping, the listen
+/// connection is considered dead and is closed.
+pub const RELAY_PONG_TIMEOUT_TICKS: usize = 0x8;
+
 /// Relay server: The amount of ticks to wait before a relay connection from a client
 /// sends identification of which type of connection it is.
 pub const CONN_TIMEOUT_TICKS: usize = 4;
 
+/// The amount of ticks an incoming connection waits for a free slot, when the maximum amount of
+/// concurrent connection set ups (Encryption) is reached. The connection is dropped afterwards.
+pub const INCOMING_CONN_WAIT_TICKS: usize = 4;
+
 /// The stream TCP connection is split into prefix length frames. This is the maximum allowed
 /// length for such frame, measured in bytes.
 pub const MAX_FRAME_LENGTH: usize = 1 << 20; // 1[MB]
 
+/// Relay server: Maximum amount of bytes a single connection may send in a burst.
+pub const RELAY_RATE_LIMIT_CAPACITY: usize = 4 * MAX_FRAME_LENGTH;
+
+/// Relay server: Amount of bytes a single connection may send every tick, on average.
+pub const RELAY_RATE_LIMIT_REFILL_PER_TICK: usize = MAX_FRAME_LENGTH;
+
+/// Relay server: The amount of ticks to wait for open tunnels to close during a shutdown.
+pub const RELAY_DRAIN_TIMEOUT_TICKS: usize = 0x10;
+
+/// Node: The amount of ticks to wait for every component to finish its in-flight work during a
+/// shutdown.
+pub const NODE_DRAIN_TIMEOUT_TICKS: usize = 0x10;
+
+/// Node: The amount of ticks to wait for a request on a metrics connection. The connection is
+/// closed afterwards.
+pub const METRICS_REQUEST_TIMEOUT_TICKS: usize = 4;
+
+/// Node: Maximum amount of metrics connections served at the same time. Further connections are
+/// closed immediately.
+pub const METRICS_MAX_CONCURRENT_CONNS: usize = 0x10;
+
 /// Index server: The amount of ticks it takes for an idle node to be removed from the
 /// index server database.
 pub const INDEX_NODE_TIMEOUT_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
 
+/// Index client: The amount of ticks routes received from an index server are kept in cache.
+pub const ROUTE_CACHE_TTL_TICKS: usize = 10 * (1000 / TICK_MS); // 10 seconds
+
+/// Index client: The amount of consecutive connection failures after which an index server is
+/// temporarily skipped.
+pub const INDEX_SERVER_FAILURE_THRESHOLD: usize = 3;
+
+/// Index client: The amount of ticks an index server is skipped after too many consecutive
+/// connection failures.
+pub const INDEX_SERVER_OPEN_CIRCUIT_TICKS: usize = 5 * 60 * (1000 / TICK_MS); // 5 minutes
+
 /// Maximum length for an address string used in NetAddress
 pub const MAX_NET_ADDRESS_LENGTH: usize = 256;
 
+/// Maximum amount of elements in a list inside a received message.
+/// Protects against messages that try to make us allocate a large amount of memory.
+pub const MAX_LIST_LEN: usize = 4096;
+
+/// Maximum length for a string inside a received message (For example, the name of a relay)
+pub const MAX_STRING_LEN: usize = 256;
+
+/// Default estimated round trip time (in ticks) of a single hop, used before any actual round
+/// trip time was measured.
+pub const DEFAULT_HOP_LATENCY_TICKS: usize = 2;
+
+/// The amount of ticks a seller waits for a pending transaction to be collected before it
+/// cancels the transaction, releasing the credits frozen along the route.
+pub const MAX_PENDING_TICKS: usize = 60 * 60 * (1000 / TICK_MS); // 1 hour
+
 /// Maximum amount of relays a node may use.
 /// We limit this number because sending many relays in a single move token message
 /// might exceed frame length
 pub const MAX_NODE_RELAYS: usize = 16;
+
+/// Maximum amount of friends a node may have.
+/// Every friend adds CPU and memory overhead to the node.
+pub const MAX_FRIENDS: usize = 0x100;
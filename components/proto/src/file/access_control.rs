@@ -0,0 +1,104 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use toml;
+
+use crypto::identity::PublicKey;
+
+use crate::file::ser_string::{public_key_to_string, string_to_public_key, SerStringError};
+
+#[derive(Debug, From)]
+pub enum AccessControlFileError {
+    IoError(io::Error),
+    TomlDeError(toml::de::Error),
+    TomlSeError(toml::ser::Error),
+    SerStringError,
+}
+
+impl From<SerStringError> for AccessControlFileError {
+    fn from(_e: SerStringError) -> Self {
+        AccessControlFileError::SerStringError
+    }
+}
+
+/// A helper structure for serialize and deserializing a list of allowed public keys.
+#[derive(Serialize, Deserialize)]
+struct AccessControlFile {
+    allowed: Vec<String>,
+}
+
+/// Load a list of allowed public keys from a file
+pub fn load_access_control_from_file(
+    path: &Path,
+) -> Result<Vec<PublicKey>, AccessControlFileError> {
+    let data = fs::read_to_string(&path)?;
+    let access_control_file: AccessControlFile = toml::from_str(&data)?;
+
+    let mut allowed = Vec::new();
+    for public_key_str in &access_control_file.allowed {
+        allowed.push(string_to_public_key(public_key_str)?);
+    }
+    Ok(allowed)
+}
+
+/// Path of the temporary file used while storing to `path`
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_os_string = path.as_os_str().to_owned();
+    tmp_os_string.push(".tmp");
+    PathBuf::from(tmp_os_string)
+}
+
+/// Store a list of allowed public keys to a file.
+/// The list is first written to a temporary file, which is then renamed over `path`, so that a
+/// crash in the middle of a write never leaves a truncated file behind.
+pub fn store_access_control_to_file(
+    allowed: &[PublicKey],
+    path: &Path,
+) -> Result<(), AccessControlFileError> {
+    let access_control_file = AccessControlFile {
+        allowed: allowed.iter().map(public_key_to_string).collect(),
+    };
+
+    let data = toml::to_string(&access_control_file)?;
+
+    let tmp_path = tmp_path(path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(&data.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    #[test]
+    fn test_store_load_access_control() {
+        // Create a temporary directory:
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("access_control_file");
+
+        let allowed = vec![
+            PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+        ];
+
+        store_access_control_to_file(&allowed, &file_path).unwrap();
+        let allowed2 = load_access_control_from_file(&file_path).unwrap();
+
+        assert_eq!(allowed, allowed2);
+
+        // Storing again overwrites the previous list, and leaves no temporary file behind:
+        let allowed = vec![PublicKey::from(&[0xcc; PUBLIC_KEY_LEN])];
+        store_access_control_to_file(&allowed, &file_path).unwrap();
+        let allowed2 = load_access_control_from_file(&file_path).unwrap();
+        assert_eq!(allowed, allowed2);
+        assert!(!tmp_path(&file_path).exists());
+    }
+}
@@ -1,11 +1,13 @@
+use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::file::ser_string::{public_key_to_string, string_to_public_key, SerStringError};
 use toml;
 
-use crate::app_server::messages::AppPermissions;
+use crate::app_server::messages::{AppPermissions, FriendPermissions};
 use crypto::identity::PublicKey;
 
 #[derive(Debug, From)]
@@ -15,19 +17,44 @@ pub enum AppFileError {
     TomlSeError(toml::ser::Error),
     SerStringError,
     InvalidPublicKey,
+    InvalidExpiry,
 }
 
 /// A helper structure for serialize and deserializing IndexServerAddress.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TrustedAppFile {
     public_key: String,
+    /// Expiry time, in seconds since the unix epoch
+    expires_at: Option<u64>,
+    /// Permissions, without the per friend permissions
     permissions: AppPermissions,
+    friend_permissions: Option<Vec<FriendPermissionsFile>>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// A helper structure for serializing the permissions of an app with respect to a friend.
+/// (TOML does not allow maps with non string keys)
+#[derive(Debug, Serialize, Deserialize)]
+struct FriendPermissionsFile {
+    public_key: String,
+    permissions: FriendPermissions,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TrustedApp {
     pub public_key: PublicKey,
     pub permissions: AppPermissions,
+    /// The app is not trusted after this point in time.
+    /// `None` means that the app is trusted until the ticket is removed.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl TrustedApp {
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now >= expires_at,
+            None => false,
+        }
+    }
 }
 
 impl From<SerStringError> for AppFileError {
@@ -42,10 +69,29 @@ pub fn load_trusted_app_from_file(path: &Path) -> Result<TrustedApp, AppFileErro
     let trusted_app_file: TrustedAppFile = toml::from_str(&data)?;
 
     let public_key = string_to_public_key(&trusted_app_file.public_key)?;
+    let expires_at = trusted_app_file
+        .expires_at
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+    let mut permissions = trusted_app_file.permissions;
+    permissions.friend_permissions = match trusted_app_file.friend_permissions {
+        Some(friend_permissions_files) => {
+            let mut friend_permissions = HashMap::new();
+            for friend_permissions_file in friend_permissions_files {
+                friend_permissions.insert(
+                    string_to_public_key(&friend_permissions_file.public_key)?,
+                    friend_permissions_file.permissions,
+                );
+            }
+            Some(friend_permissions)
+        }
+        None => None,
+    };
 
     Ok(TrustedApp {
         public_key,
-        permissions: trusted_app_file.permissions,
+        permissions,
+        expires_at,
     })
 }
 
@@ -57,11 +103,38 @@ pub fn store_trusted_app_to_file(
     let TrustedApp {
         ref public_key,
         ref permissions,
+        ref expires_at,
     } = trusted_app;
 
+    let expires_at = match expires_at {
+        Some(expires_at) => Some(
+            expires_at
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| AppFileError::InvalidExpiry)?
+                .as_secs(),
+        ),
+        None => None,
+    };
+
+    let mut permissions = permissions.clone();
+    let friend_permissions = permissions
+        .friend_permissions
+        .take()
+        .map(|friend_permissions| {
+            friend_permissions
+                .into_iter()
+                .map(|(public_key, permissions)| FriendPermissionsFile {
+                    public_key: public_key_to_string(&public_key),
+                    permissions,
+                })
+                .collect()
+        });
+
     let trusted_app_file = TrustedAppFile {
         public_key: public_key_to_string(&public_key),
-        permissions: permissions.clone(),
+        expires_at,
+        permissions,
+        friend_permissions,
     };
 
     let data = toml::to_string(&trusted_app_file)?;
@@ -120,10 +193,86 @@ mod tests {
             buyer: false,
             seller: false,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
+        };
+        let trusted_app = TrustedApp {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            permissions,
+            expires_at: None,
+        };
+
+        store_trusted_app_to_file(&trusted_app, &file_path).unwrap();
+        let trusted_app2 = load_trusted_app_from_file(&file_path).unwrap();
+
+        assert_eq!(trusted_app, trusted_app2);
+    }
+
+    #[test]
+    fn test_store_load_trusted_app_expiry() {
+        // Create a temporary directory:
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("trusted_app_file");
+
+        let permissions = AppPermissions {
+            routes: false,
+            buyer: true,
+            seller: false,
+            config: false,
+            max_requests_per_second: None,
+            friend_permissions: None,
+        };
+        let expires_at = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        let trusted_app = TrustedApp {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            permissions,
+            expires_at: Some(expires_at),
+        };
+
+        store_trusted_app_to_file(&trusted_app, &file_path).unwrap();
+        let trusted_app2 = load_trusted_app_from_file(&file_path).unwrap();
+        assert_eq!(trusted_app, trusted_app2);
+
+        assert!(!trusted_app.is_expired(expires_at - Duration::from_secs(1)));
+        assert!(trusted_app.is_expired(expires_at));
+    }
+
+    #[test]
+    fn test_store_load_trusted_app_friend_permissions() {
+        // Create a temporary directory:
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("trusted_app_file");
+
+        let mut friend_permissions = HashMap::new();
+        friend_permissions.insert(
+            PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            FriendPermissions {
+                read: true,
+                set_max_debt: false,
+                open_close: false,
+            },
+        );
+        friend_permissions.insert(
+            PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+            FriendPermissions {
+                read: true,
+                set_max_debt: true,
+                open_close: true,
+            },
+        );
+
+        let permissions = AppPermissions {
+            routes: false,
+            buyer: false,
+            seller: false,
+            config: false,
+            max_requests_per_second: None,
+            friend_permissions: Some(friend_permissions),
         };
         let trusted_app = TrustedApp {
             public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
             permissions,
+            expires_at: None,
         };
 
         store_trusted_app_to_file(&trusted_app, &file_path).unwrap();
@@ -143,10 +292,13 @@ mod tests {
             buyer: false,
             seller: false,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         };
         let trusted_app1 = TrustedApp {
             public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
             permissions,
+            expires_at: None,
         };
         store_trusted_app_to_file(&trusted_app1, &file_path).unwrap();
 
@@ -156,10 +308,13 @@ mod tests {
             buyer: true,
             seller: true,
             config: false,
+            max_requests_per_second: Some(20),
+            friend_permissions: None,
         };
         let trusted_app2 = TrustedApp {
             public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
             permissions,
+            expires_at: None,
         };
         store_trusted_app_to_file(&trusted_app2, &file_path).unwrap();
 
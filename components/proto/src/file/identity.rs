@@ -44,12 +44,9 @@ pub fn load_raw_identity_from_file(path: &Path) -> Result<[u8; 85], IdentityFile
 }
 
 /// Store Identity to file
-pub fn store_raw_identity_to_file(
-    identity: &[u8; 85],
-    path: &Path,
-) -> Result<(), IdentityFileError> {
+pub fn store_raw_identity_to_file(identity: &[u8], path: &Path) -> Result<(), IdentityFileError> {
     let identity_file = IdentityFile {
-        private_key: private_key_to_string(&identity),
+        private_key: private_key_to_string(identity),
     };
 
     let data = toml::to_string(&identity_file)?;
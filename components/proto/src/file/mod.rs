@@ -1,3 +1,4 @@
+pub mod access_control;
 pub mod app;
 pub mod friend;
 pub mod identity;
@@ -98,10 +98,8 @@ str_convert_funcs!(
 // We currently use [u8; 85] directly because of ring limitations.
 
 /// Convert a private key into a string
-pub fn private_key_to_string(private_key: &[u8; 85]) -> String {
-    // We have to do this because [u8; 85] doesn't implement AsRef, due to compiler limitations
-    let private_key_slice = &private_key[0..85];
-    base64::encode_config(&private_key_slice, URL_SAFE_NO_PAD)
+pub fn private_key_to_string(private_key: &[u8]) -> String {
+    base64::encode_config(private_key, URL_SAFE_NO_PAD)
 }
 
 // TODO: Fix all 85 hacks here
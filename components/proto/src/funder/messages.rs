@@ -1,4 +1,3 @@
-use byteorder::{BigEndian, WriteBytesExt};
 use std::collections::HashSet;
 
 use num_bigint::BigUint;
@@ -17,18 +16,20 @@ use crate::consts::MAX_ROUTE_LEN;
 use crate::net::messages::NetAddress;
 use crate::report::messages::FunderReportMutations;
 use common::canonical_serialize::CanonicalSerialize;
-use common::int_convert::usize_to_u64;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChannelerUpdateFriend<RA> {
     pub friend_public_key: PublicKey,
     /// We should try to connect to this address:
     pub friend_relays: Vec<RA>,
     /// We should be listening on this address:
     pub local_relays: Vec<RA>,
+    /// When the channeler can not connect to all friends at the same time,
+    /// friends with higher priority are connected first.
+    pub connection_priority: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum FunderToChanneler<RA> {
     /// Send a message to a friend
     Message((PublicKey, Vec<u8>)), // (friend_public_key, message)
@@ -40,7 +41,7 @@ pub enum FunderToChanneler<RA> {
     RemoveFriend(PublicKey), // friend_public_key
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ChannelerToFunder {
     /// A friend is now online
     Online(PublicKey),
@@ -59,23 +60,27 @@ pub const INVOICE_ID_LEN: usize = 32;
 define_fixed_bytes!(InvoiceId, INVOICE_ID_LEN);
 */
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, CanonicalSerialize)]
 pub struct FriendsRoute {
     pub public_keys: Vec<PublicKey>,
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize, CanonicalSerialize)]
 pub struct RequestSendFundsOp {
     pub request_id: Uid,
     pub src_hashed_lock: HashedLock,
     pub route: FriendsRoute,
     pub dest_payment: u128,
+    #[canonical_serialize(skip)]
     pub total_dest_payment: u128,
     pub invoice_id: InvoiceId,
+    // We do not sign over`left_fees`, because this field changes as the request message is
+    // forwarded.
+    #[canonical_serialize(skip)]
     pub left_fees: u128,
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize, CanonicalSerialize)]
 pub struct ResponseSendFundsOp<S = Signature> {
     pub request_id: Uid,
     pub dest_hashed_lock: HashedLock,
@@ -83,7 +88,7 @@ pub struct ResponseSendFundsOp<S = Signature> {
     pub signature: S,
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize, CanonicalSerialize)]
 pub struct CancelSendFundsOp {
     pub request_id: Uid,
 }
@@ -104,14 +109,14 @@ pub struct MultiCommit {
     pub commits: Vec<Commit>,
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize, CanonicalSerialize)]
 pub struct CollectSendFundsOp {
     pub request_id: Uid,
     pub src_plain_lock: PlainLock,
     pub dest_plain_lock: PlainLock,
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize, CanonicalSerialize)]
 pub enum FriendTcOp {
     EnableRequests,
     DisableRequests,
@@ -145,7 +150,7 @@ pub struct ResetTerms {
     pub balance_for_reset: i128,
 }
 
-#[derive(PartialEq, Eq, Clone, Serialize, Debug)]
+#[derive(PartialEq, Eq, Clone, Serialize, Debug, Deserialize)]
 pub struct MoveTokenRequest<B = NetAddress> {
     pub friend_move_token: MoveToken<B>,
     // Do we want the remote side to return the token:
@@ -153,7 +158,7 @@ pub struct MoveTokenRequest<B = NetAddress> {
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
 pub enum FriendMessage<B = NetAddress> {
     MoveTokenRequest(MoveTokenRequest<B>),
     InconsistencyError(ResetTerms),
@@ -161,7 +166,7 @@ pub enum FriendMessage<B = NetAddress> {
 
 /// A `Receipt` is received if a `RequestSendFunds` is successful.
 /// It can be used a proof of payment for a specific `invoice_id`.
-#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, CanonicalSerialize)]
 pub struct Receipt {
     pub response_hash: HashResult,
     // = sha512/256(requestId || sha512/256(route) || randNonce)
@@ -200,105 +205,14 @@ pub struct PendingTransaction {
     pub left_fees: u128,
     pub src_hashed_lock: HashedLock,
     pub stage: TransactionStage,
+    /// The local funder's time tick when this transaction was created.
+    /// This is local information, and it is never sent to the remote side.
+    pub created_at_tick: u64,
 }
 
 // ==================================================================
 // ==================================================================
 
-impl CanonicalSerialize for RequestSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes.extend_from_slice(&self.src_hashed_lock);
-        res_bytes.extend_from_slice(&self.route.canonical_serialize());
-        res_bytes
-            .write_u128::<BigEndian>(self.dest_payment)
-            .unwrap();
-        res_bytes.extend_from_slice(&self.invoice_id);
-        // We do not sign over`left_fees`, because this field changes as the request message is
-        // forwarded.
-        // res_bytes.write_u128::<BigEndian>(self.left_fees).unwrap();
-        res_bytes
-    }
-}
-
-impl CanonicalSerialize for ResponseSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes.extend_from_slice(&self.dest_hashed_lock);
-        res_bytes.extend_from_slice(&self.rand_nonce);
-        res_bytes.extend_from_slice(&self.signature);
-        res_bytes
-    }
-}
-
-impl CanonicalSerialize for CancelSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes
-    }
-}
-
-impl CanonicalSerialize for CollectSendFundsOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.request_id);
-        res_bytes.extend_from_slice(&self.src_plain_lock);
-        res_bytes.extend_from_slice(&self.dest_plain_lock);
-        res_bytes
-    }
-}
-
-impl CanonicalSerialize for FriendTcOp {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        match self {
-            FriendTcOp::EnableRequests => {
-                res_bytes.push(0u8);
-            }
-            FriendTcOp::DisableRequests => {
-                res_bytes.push(1u8);
-            }
-            FriendTcOp::SetRemoteMaxDebt(remote_max_debt) => {
-                res_bytes.push(2u8);
-                res_bytes.write_u128::<BigEndian>(*remote_max_debt).unwrap();
-            }
-            FriendTcOp::RequestSendFunds(request_send_funds) => {
-                res_bytes.push(3u8);
-                res_bytes.append(&mut request_send_funds.canonical_serialize())
-            }
-            FriendTcOp::ResponseSendFunds(response_send_funds) => {
-                res_bytes.push(4u8);
-                res_bytes.append(&mut response_send_funds.canonical_serialize())
-            }
-            FriendTcOp::CancelSendFunds(cancel_send_funds) => {
-                res_bytes.push(5u8);
-                res_bytes.append(&mut cancel_send_funds.canonical_serialize())
-            }
-            FriendTcOp::CollectSendFunds(commit_send_funds) => {
-                res_bytes.push(6u8);
-                res_bytes.append(&mut commit_send_funds.canonical_serialize())
-            }
-        }
-        res_bytes
-    }
-}
-
-impl CanonicalSerialize for FriendsRoute {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes
-            .write_u64::<BigEndian>(usize_to_u64(self.public_keys.len()).unwrap())
-            .unwrap();
-        for public_key in &self.public_keys {
-            res_bytes.extend_from_slice(public_key);
-        }
-        res_bytes
-    }
-}
-
 impl FriendsRoute {
     pub fn len(&self) -> usize {
         self.public_keys.len()
@@ -346,7 +260,9 @@ impl FriendsRoute {
 
     /// Produce a cryptographic hash over the contents of the route.
     pub fn hash(&self) -> HashResult {
-        hash::sha_512_256(&self.canonical_serialize())
+        let mut writer = hash::hash_writer();
+        self.canonical_serialize_into(&mut writer).unwrap();
+        writer.finalize()
     }
 
     /// Find the index of a public key inside the route.
@@ -367,16 +283,19 @@ impl FriendsRoute {
     }
 }
 
-impl CanonicalSerialize for Receipt {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.response_hash);
-        res_bytes.extend_from_slice(&self.invoice_id);
-        res_bytes
-            .write_u128::<BigEndian>(self.dest_payment)
-            .unwrap();
-        res_bytes.extend_from_slice(&self.signature);
-        res_bytes
+impl<B, S> MoveToken<B, S> {
+    /// Iterate over the operations carried by this move token.
+    pub fn operations_iter(&self) -> impl Iterator<Item = &FriendTcOp> + '_ {
+        self.operations.iter()
+    }
+
+    /// Amount of operations carried by this move token.
+    pub fn len(&self) -> usize {
+        self.operations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
     }
 }
 
@@ -429,6 +348,13 @@ impl Rate {
         res.to_u128()
     }
 
+    /// Minimal amount of fees a node charging this rate expects in order to forward a request
+    /// of `dest_payment` credits. Saturates at `u128::max_value()` if the fee does not fit
+    /// in a u128.
+    pub fn min_fee(&self, dest_payment: u128) -> u128 {
+        self.calc_fee(dest_payment).unwrap_or(u128::max_value())
+    }
+
     /// Maximum amount of credits we should be able to pay
     /// through a given capacity.
     ///
@@ -458,51 +384,59 @@ pub struct AddFriend<B = NetAddress> {
     pub friend_public_key: PublicKey,
     pub relays: Vec<RelayAddress<B>>,
     pub name: String,
+    /// Free text notes about the friend, kept locally
+    pub note: String,
     pub balance: i128, // Initial balance
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RemoveFriend {
     pub friend_public_key: PublicKey,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetRequestsStatus {
     pub friend_public_key: PublicKey,
     pub status: RequestsStatus,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendStatus {
     pub friend_public_key: PublicKey,
     pub status: FriendStatus,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendRemoteMaxDebt {
     pub friend_public_key: PublicKey,
     pub remote_max_debt: u128,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendName {
     pub friend_public_key: PublicKey,
     pub name: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetFriendNote {
+    pub friend_public_key: PublicKey,
+    pub note: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendRelays<B = NetAddress> {
     pub friend_public_key: PublicKey,
     pub relays: Vec<RelayAddress<B>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResetFriendChannel {
     pub friend_public_key: PublicKey,
     pub reset_token: Signature,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SetFriendRate {
     pub friend_public_key: PublicKey,
     pub rate: Rate,
@@ -516,7 +450,7 @@ struct FriendsRouteCapacity {
 }
 
 /// A request to send funds that originates from the user
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UserRequestSendFunds {
     pub payment_id: PaymentId,
     pub route: FriendsRoute,
@@ -524,14 +458,14 @@ pub struct UserRequestSendFunds {
     pub dest_payment: u128,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ReceiptAck {
     pub request_id: Uid,
     pub receipt_signature: Signature,
 }
 
 /// Start a payment, possibly by paying through multiple routes.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CreatePayment {
     /// payment_id is a randomly generated value (by the user), allowing the user to refer to a
     /// certain payment.
@@ -542,7 +476,7 @@ pub struct CreatePayment {
 }
 
 /// Start a payment, possibly by paying through multiple routes.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CreateTransaction {
     /// A payment id of an existing payment.
     pub payment_id: PaymentId,
@@ -554,23 +488,51 @@ pub struct CreateTransaction {
     pub fees: u128,
 }
 
+/// A single transaction of a multi route payment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MultiRouteTransaction {
+    /// Randomly generated request_id (by the user),
+    /// allows the user to refer to this request later.
+    pub request_id: Uid,
+    pub route: FriendsRoute,
+    pub dest_payment: u128,
+    pub fees: u128,
+}
+
+/// Start a payment that is split over multiple routes.
+/// Either all the transactions are created, or none of them.
+/// The `dest_payment` of all the transactions must sum up to `total_dest_payment`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreateMultiRoutePayment {
+    /// payment_id is a randomly generated value (by the user), allowing the user to refer to a
+    /// certain payment.
+    pub payment_id: PaymentId,
+    pub invoice_id: InvoiceId,
+    pub total_dest_payment: u128,
+    pub dest_public_key: PublicKey,
+    pub transactions: Vec<MultiRouteTransaction>,
+}
+
 /// Start an invoice (A request for payment).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddInvoice {
     /// Randomly generated invoice_id, allows to refer to this invoice.
     pub invoice_id: InvoiceId,
     /// Total amount of credits to be paid.
     pub total_dest_payment: u128,
+    /// Amount of time ticks until the invoice expires. Must be positive.
+    /// An expired invoice is removed, and can not be paid anymore.
+    pub validity_ticks: u64,
 }
 
 /// Start an invoice (A request for payment).
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AckClosePayment {
     pub payment_id: PaymentId,
     pub ack_uid: Uid,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FunderControl<B> {
     AddRelay(NamedRelayAddress<B>),
     RemoveRelay(PublicKey),
@@ -581,20 +543,27 @@ pub enum FunderControl<B> {
     SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt),
     SetFriendRelays(SetFriendRelays<B>),
     SetFriendName(SetFriendName),
+    SetFriendNote(SetFriendNote),
     SetFriendRate(SetFriendRate),
     ResetFriendChannel(ResetFriendChannel),
+    /// Temporarily stop routing requests through a friend, without closing the channel:
+    FreezeFriend(PublicKey),
+    UnfreezeFriend(PublicKey),
     // Buyer API:
     CreatePayment(CreatePayment),
     CreateTransaction(CreateTransaction), // TODO
+    CreateMultiRoutePayment(CreateMultiRoutePayment),
     RequestClosePayment(PaymentId),
     AckClosePayment(AckClosePayment),
+    GetPaymentTimeline(PaymentId),
+    QueryPaymentHistory(QueryPaymentHistory),
     // Seller API:
     AddInvoice(AddInvoice),
     CancelInvoice(InvoiceId),
     CommitInvoice(MultiCommit),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FunderIncomingControl<B> {
     pub app_request_id: Uid,
     pub funder_control: FunderControl<B>,
@@ -631,21 +600,21 @@ impl UserRequestSendFunds {
     */
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RequestResult {
     Success(Commit),
     // TODO: Should we add more information to the failure here?
     Failure,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransactionResult {
     pub request_id: Uid,
     pub result: RequestResult,
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PaymentStatus {
     PaymentNotFound,
     InProgress,              // Can not be acked
@@ -653,16 +622,260 @@ pub enum PaymentStatus {
     Canceled(Uid),           // ack_id
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResponseClosePayment {
     pub payment_id: PaymentId,
     pub status: PaymentStatus,
 }
 
+/// Something that happened during the lifetime of a payment (For which this node is the buyer).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentEventKind {
+    Created,
+    TransactionAdded(Uid),  // request_id
+    TransactionFailed(Uid), // request_id
+    ClosedRequested,
+    Succeeded,
+    Canceled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentEvent {
+    /// Value of the funder's payment events counter when the event was recorded.
+    /// Events of all payments share the same counter, so ticks are only useful for ordering.
+    pub tick: u64,
+    pub event: PaymentEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResponsePaymentTimeline {
+    pub payment_id: PaymentId,
+    /// Recorded events, oldest first. Empty if the payment does not exist.
+    pub timeline: Vec<PaymentEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentOutcome {
+    Succeeded,
+    Canceled,
+}
+
+/// A completed payment (For which this node is the buyer).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentHistoryEntry {
+    pub payment_id: PaymentId,
+    pub invoice_id: InvoiceId,
+    pub total_amount: u128,
+    /// Amount of transactions that were added to the payment
+    pub num_transactions: u64,
+    pub outcome: PaymentOutcome,
+    /// Value of the funder's payment events counter when the payment completed.
+    pub completed_at_tick: u64,
+}
+
+/// Request completed payments, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryPaymentHistory {
+    /// Only payments that completed strictly after this tick are returned.
+    pub after_tick: u64,
+    /// Maximum amount of entries to return
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentHistoryResponse {
+    pub after_tick: u64,
+    /// Entries, oldest first.
+    /// Less than `limit` entries means there are no more entries to query.
+    pub entries: Vec<PaymentHistoryEntry>,
+}
+
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum FunderOutgoingControl<B: Clone> {
     TransactionResult(TransactionResult),
     ResponseClosePayment(ResponseClosePayment),
+    ResponsePaymentTimeline(ResponsePaymentTimeline),
+    PaymentHistoryResponse(PaymentHistoryResponse),
     ReportMutations(FunderReportMutations<B>),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::crypto_rand::RAND_VALUE_LEN;
+    use crypto::hash::HASH_RESULT_LEN;
+    use crypto::hash_lock::{HASHED_LOCK_LEN, PLAIN_LOCK_LEN};
+    use crypto::identity::{PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::INVOICE_ID_LEN;
+    use crypto::uid::UID_LEN;
+
+    fn create_move_token(operations: Vec<FriendTcOp>) -> MoveToken {
+        MoveToken {
+            operations,
+            opt_local_relays: None,
+            old_token: Signature::from(&[0; SIGNATURE_LEN]),
+            local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            remote_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            inconsistency_counter: 0,
+            move_token_counter: 0,
+            balance: 0,
+            local_pending_debt: 0,
+            remote_pending_debt: 0,
+            rand_nonce: RandValue::from(&[0xcc; RAND_VALUE_LEN]),
+            new_token: Signature::from(&[1; SIGNATURE_LEN]),
+        }
+    }
+
+    #[test]
+    fn test_move_token_operations_empty() {
+        let move_token = create_move_token(Vec::new());
+        assert!(move_token.is_empty());
+        assert_eq!(move_token.len(), 0);
+        assert_eq!(move_token.operations_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_move_token_operations_iter() {
+        let operations = vec![
+            FriendTcOp::EnableRequests,
+            FriendTcOp::SetRemoteMaxDebt(100),
+            FriendTcOp::DisableRequests,
+        ];
+        let move_token = create_move_token(operations.clone());
+        assert!(!move_token.is_empty());
+        assert_eq!(move_token.len(), 3);
+        let collected: Vec<FriendTcOp> = move_token.operations_iter().cloned().collect();
+        assert_eq!(collected, operations);
+    }
+
+    #[test]
+    fn test_rate_min_fee() {
+        // About 1%: 2^32 / 100, rounded up.
+        let rate = Rate {
+            mul: 42_949_673,
+            add: 0,
+        };
+        assert_eq!(rate.min_fee(1000), 10);
+
+        // The flat part of the rate is added:
+        let rate = Rate {
+            mul: 42_949_673,
+            add: 1,
+        };
+        assert_eq!(rate.min_fee(1000), 11);
+    }
+
+    #[test]
+    fn test_canonical_serialize_into_matches() {
+        let route = FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            ],
+        };
+        assert_eq!(
+            route.hash(),
+            hash::sha_512_256(&route.canonical_serialize())
+        );
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[1; UID_LEN]),
+            src_hashed_lock: HashedLock::from(&[2; HASHED_LOCK_LEN]),
+            route,
+            dest_payment: 10,
+            total_dest_payment: 20,
+            invoice_id: InvoiceId::from(&[3; INVOICE_ID_LEN]),
+            left_fees: 5,
+        };
+        let friend_tc_op = FriendTcOp::RequestSendFunds(request_send_funds.clone());
+
+        let mut written = Vec::new();
+        friend_tc_op.canonical_serialize_into(&mut written).unwrap();
+        let mut expected = vec![3u8];
+        expected.extend_from_slice(&request_send_funds.canonical_serialize());
+        assert_eq!(written, expected);
+        assert_eq!(friend_tc_op.canonical_serialize(), expected);
+    }
+
+    /// The derived serialization must match the serialization that was used for signatures
+    /// before it was derived.
+    #[test]
+    fn test_canonical_serialize_layout() {
+        let route = FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            ],
+        };
+        let mut route_bytes = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        route_bytes.extend_from_slice(&[0xaa; PUBLIC_KEY_LEN]);
+        route_bytes.extend_from_slice(&[0xbb; PUBLIC_KEY_LEN]);
+        assert_eq!(route.canonical_serialize(), route_bytes);
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: Uid::from(&[1; UID_LEN]),
+            src_hashed_lock: HashedLock::from(&[2; HASHED_LOCK_LEN]),
+            route,
+            dest_payment: 10,
+            total_dest_payment: 20,
+            invoice_id: InvoiceId::from(&[3; INVOICE_ID_LEN]),
+            left_fees: 5,
+        };
+        // `total_dest_payment` and `left_fees` are not serialized:
+        let mut expected = vec![3u8];
+        expected.extend_from_slice(&[1; UID_LEN]);
+        expected.extend_from_slice(&[2; HASHED_LOCK_LEN]);
+        expected.extend_from_slice(&route_bytes);
+        expected.extend_from_slice(&10u128.to_be_bytes());
+        expected.extend_from_slice(&[3; INVOICE_ID_LEN]);
+        assert_eq!(
+            FriendTcOp::RequestSendFunds(request_send_funds).canonical_serialize(),
+            expected
+        );
+
+        let response_send_funds = ResponseSendFundsOp {
+            request_id: Uid::from(&[1; UID_LEN]),
+            dest_hashed_lock: HashedLock::from(&[2; HASHED_LOCK_LEN]),
+            rand_nonce: RandValue::from(&[3; RAND_VALUE_LEN]),
+            signature: Signature::from(&[4; SIGNATURE_LEN]),
+        };
+        let mut expected = vec![4u8];
+        expected.extend_from_slice(&[1; UID_LEN]);
+        expected.extend_from_slice(&[2; HASHED_LOCK_LEN]);
+        expected.extend_from_slice(&[3; RAND_VALUE_LEN]);
+        expected.extend_from_slice(&[4; SIGNATURE_LEN]);
+        assert_eq!(
+            FriendTcOp::ResponseSendFunds(response_send_funds).canonical_serialize(),
+            expected
+        );
+
+        assert_eq!(FriendTcOp::EnableRequests.canonical_serialize(), vec![0]);
+        assert_eq!(FriendTcOp::DisableRequests.canonical_serialize(), vec![1]);
+        let mut expected = vec![2u8];
+        expected.extend_from_slice(&100u128.to_be_bytes());
+        assert_eq!(
+            FriendTcOp::SetRemoteMaxDebt(100).canonical_serialize(),
+            expected
+        );
+
+        let receipt = Receipt {
+            response_hash: HashResult::from(&[1; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[2; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[3; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[4; PLAIN_LOCK_LEN]),
+            dest_payment: 5,
+            total_dest_payment: 6,
+            signature: Signature::from(&[7; SIGNATURE_LEN]),
+        };
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&[1; HASH_RESULT_LEN]);
+        expected.extend_from_slice(&[2; INVOICE_ID_LEN]);
+        expected.extend_from_slice(&[3; PLAIN_LOCK_LEN]);
+        expected.extend_from_slice(&[4; PLAIN_LOCK_LEN]);
+        expected.extend_from_slice(&5u128.to_be_bytes());
+        expected.extend_from_slice(&6u128.to_be_bytes());
+        expected.extend_from_slice(&[7; SIGNATURE_LEN]);
+        assert_eq!(receipt.canonical_serialize(), expected);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod messages;
+pub mod receipt_url;
 #[allow(unused)]
 pub mod serialize;
 pub mod signature_buff;
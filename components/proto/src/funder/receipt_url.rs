@@ -0,0 +1,142 @@
+use std::convert::TryFrom;
+
+use base64::{self, URL_SAFE_NO_PAD};
+use byteorder::{BigEndian, ByteOrder};
+
+use crypto::hash::{HashResult, HASH_RESULT_LEN};
+use crypto::hash_lock::{PlainLock, PLAIN_LOCK_LEN};
+use crypto::identity::{Signature, SIGNATURE_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+
+use common::canonical_serialize::CanonicalSerialize;
+
+use crate::funder::messages::Receipt;
+
+/// Name of the query parameter holding the encoded receipt.
+const RECEIPT_QUERY_PARAM: &str = "receipt";
+
+/// Length of a canonically serialized receipt.
+const RECEIPT_LEN: usize =
+    HASH_RESULT_LEN + INVOICE_ID_LEN + 2 * PLAIN_LOCK_LEN + 2 * 16 + SIGNATURE_LEN;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    MissingReceiptParam,
+    Base64Error,
+    InvalidLength,
+}
+
+/// Take the next `len` bytes from `data`.
+/// The caller must make sure that `data` is long enough.
+fn take<'a>(data: &mut &'a [u8], len: usize) -> &'a [u8] {
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    head
+}
+
+impl Receipt {
+    /// Encode the receipt as a query parameter on the given base URL.
+    /// Allows a seller to share a proof of payment as a single link.
+    pub fn to_url(&self, base: &str) -> String {
+        let separator = if base.contains('?') { '&' } else { '?' };
+        let encoded = base64::encode_config(&self.canonical_serialize(), URL_SAFE_NO_PAD);
+        format!("{}{}{}={}", base, separator, RECEIPT_QUERY_PARAM, encoded)
+    }
+
+    /// Decode a receipt from a URL created by `to_url()`.
+    /// Note that this function does not verify the receipt's signature.
+    pub fn from_url(url: &str) -> Result<Self, ParseError> {
+        let query = url
+            .splitn(2, '?')
+            .nth(1)
+            .ok_or(ParseError::MissingReceiptParam)?;
+        let encoded = query
+            .split('&')
+            .filter_map(|param| {
+                let mut split = param.splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some(RECEIPT_QUERY_PARAM), Some(value)) => Some(value),
+                    _ => None,
+                }
+            })
+            .next()
+            .ok_or(ParseError::MissingReceiptParam)?;
+
+        let data =
+            base64::decode_config(encoded, URL_SAFE_NO_PAD).map_err(|_| ParseError::Base64Error)?;
+        if data.len() != RECEIPT_LEN {
+            return Err(ParseError::InvalidLength);
+        }
+
+        // The fields are ordered as in the canonical serialization of Receipt:
+        let mut data = &data[..];
+        let response_hash = HashResult::try_from(take(&mut data, HASH_RESULT_LEN)).unwrap();
+        let invoice_id = InvoiceId::try_from(take(&mut data, INVOICE_ID_LEN)).unwrap();
+        let src_plain_lock = PlainLock::try_from(take(&mut data, PLAIN_LOCK_LEN)).unwrap();
+        let dest_plain_lock = PlainLock::try_from(take(&mut data, PLAIN_LOCK_LEN)).unwrap();
+        let dest_payment = BigEndian::read_u128(take(&mut data, 16));
+        let total_dest_payment = BigEndian::read_u128(take(&mut data, 16));
+        let signature = Signature::try_from(take(&mut data, SIGNATURE_LEN)).unwrap();
+
+        Ok(Receipt {
+            response_hash,
+            invoice_id,
+            src_plain_lock,
+            dest_plain_lock,
+            dest_payment,
+            total_dest_payment,
+            signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dummy_receipt() -> Receipt {
+        Receipt {
+            response_hash: HashResult::from(&[0; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[2; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[3; PLAIN_LOCK_LEN]),
+            dest_payment: 100,
+            total_dest_payment: 200,
+            signature: Signature::from(&[4; SIGNATURE_LEN]),
+        }
+    }
+
+    #[test]
+    fn test_receipt_url_round_trip() {
+        let receipt = dummy_receipt();
+
+        let url = receipt.to_url("https://shop.example.com/verify");
+        assert!(url.starts_with("https://shop.example.com/verify?receipt="));
+        assert_eq!(Receipt::from_url(&url).unwrap(), receipt);
+
+        // A base URL that already has a query:
+        let url = receipt.to_url("https://shop.example.com/verify?lang=en");
+        assert!(url.starts_with("https://shop.example.com/verify?lang=en&receipt="));
+        assert_eq!(Receipt::from_url(&url).unwrap(), receipt);
+    }
+
+    #[test]
+    fn test_receipt_url_invalid() {
+        assert_eq!(
+            Receipt::from_url("https://shop.example.com/verify"),
+            Err(ParseError::MissingReceiptParam)
+        );
+        assert_eq!(
+            Receipt::from_url("https://shop.example.com/verify?lang=en"),
+            Err(ParseError::MissingReceiptParam)
+        );
+        assert_eq!(
+            Receipt::from_url("https://shop.example.com/verify?receipt=***"),
+            Err(ParseError::Base64Error)
+        );
+        assert_eq!(
+            Receipt::from_url("https://shop.example.com/verify?receipt=AAAA"),
+            Err(ParseError::InvalidLength)
+        );
+    }
+}
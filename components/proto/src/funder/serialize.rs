@@ -1,8 +1,8 @@
 use crate::capnp_common::{
-    read_custom_int128, read_custom_u_int128, /*read_invoice_id,*/ read_public_key,
-    read_rand_nonce, read_relay_address, read_signature, read_uid, write_custom_int128,
-    write_custom_u_int128, write_invoice_id, write_public_key, write_rand_nonce,
-    write_relay_address, write_signature, write_uid,
+    check_list_len, read_custom_int128, read_custom_u_int128,
+    /*read_invoice_id,*/ read_public_key, read_rand_nonce, read_relay_address, read_signature,
+    read_uid, write_custom_int128, write_custom_u_int128, write_invoice_id, write_public_key,
+    write_rand_nonce, write_relay_address, write_signature, write_uid,
 };
 use capnp;
 use capnp::serialize_packed;
@@ -140,11 +140,11 @@ fn ser_move_token(
     move_token: &MoveToken,
     move_token_builder: &mut funder_capnp::move_token::Builder,
 ) {
-    let operations_len = usize_to_u32(move_token.operations.len()).unwrap();
+    let operations_len = usize_to_u32(move_token.len()).unwrap();
     let mut operations_builder = move_token_builder
         .reborrow()
         .init_operations(operations_len);
-    for (index, operation) in move_token.operations.iter().enumerate() {
+    for (index, operation) in move_token.operations_iter().enumerate() {
         let mut operation_builder = operations_builder
             .reborrow()
             .get(usize_to_u32(index).unwrap());
@@ -274,7 +274,9 @@ pub fn deser_friends_route(
     friends_route_reader: &funder_capnp::friends_route::Reader,
 ) -> Result<FriendsRoute, SerializeError> {
     let mut public_keys = Vec::new();
-    for public_key_reader in friends_route_reader.get_public_keys()? {
+    let public_keys_reader = friends_route_reader.get_public_keys()?;
+    check_list_len(public_keys_reader.len())?;
+    for public_key_reader in public_keys_reader {
         public_keys.push(read_public_key(&public_key_reader)?);
     }
 
@@ -354,7 +356,9 @@ fn deser_move_token(
     move_token_reader: &funder_capnp::move_token::Reader,
 ) -> Result<MoveToken, SerializeError> {
     let mut operations: Vec<FriendTcOp> = Vec::new();
-    for operation_reader in move_token_reader.get_operations()? {
+    let operations_reader = move_token_reader.get_operations()?;
+    check_list_len(operations_reader.len())?;
+    for operation_reader in operations_reader {
         operations.push(deser_friend_operation(&operation_reader)?);
     }
 
@@ -363,7 +367,9 @@ fn deser_move_token(
         funder_capnp::move_token::opt_local_relays::Empty(()) => None,
         funder_capnp::move_token::opt_local_relays::Relays(relay_address_reader) => {
             let mut addresses = Vec::new();
-            for address in relay_address_reader? {
+            let relay_address_reader = relay_address_reader?;
+            check_list_len(relay_address_reader.len())?;
+            for address in relay_address_reader {
                 addresses.push(read_relay_address(&address)?);
             }
             Some(addresses)
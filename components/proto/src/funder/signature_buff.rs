@@ -1,6 +1,8 @@
+use std::io::Write;
+
 use byteorder::{BigEndian, WriteBytesExt};
 
-use crypto::hash::{self, sha_512_256, HashResult};
+use crypto::hash::{self, hash_writer, sha_512_256, HashResult};
 use crypto::hash_lock::PlainLock;
 use crypto::identity::{verify_signature, PublicKey};
 use crypto::invoice_id::InvoiceId;
@@ -85,6 +87,14 @@ pub fn verify_receipt(receipt: &Receipt, public_key: &PublicKey) -> bool {
     verify_signature(&data, public_key, &receipt.signature)
 }
 
+impl Receipt {
+    /// Check that the receipt was signed by the seller, proving that the seller has received the
+    /// payment.
+    pub fn verify(&self, seller_public_key: &PublicKey) -> bool {
+        verify_receipt(self, seller_public_key)
+    }
+}
+
 /// Create a Commit (out of band) message given a ResponseSendFunds
 pub fn prepare_commit(
     response_send_funds: &ResponseSendFundsOp,
@@ -164,14 +174,14 @@ pub const TOKEN_NEXT: &[u8] = b"NEXT";
 
 /// Combine all operations into one hash value.
 pub fn operations_hash<B>(move_token: &MoveToken<B>) -> HashResult {
-    let mut operations_data = Vec::new();
-    operations_data
-        .write_u64::<BigEndian>(usize_to_u64(move_token.operations.len()).unwrap())
+    let mut writer = hash_writer();
+    writer
+        .write_u64::<BigEndian>(usize_to_u64(move_token.len()).unwrap())
         .unwrap();
-    for op in &move_token.operations {
-        operations_data.extend_from_slice(&op.canonical_serialize());
+    for op in move_token.operations_iter() {
+        op.canonical_serialize_into(&mut writer).unwrap();
     }
-    sha_512_256(&operations_data)
+    writer.finalize()
 }
 
 /// Combine all operations into one hash value.
@@ -179,7 +189,12 @@ pub fn local_address_hash<B>(move_token: &MoveToken<B>) -> HashResult
 where
     B: CanonicalSerialize,
 {
-    sha_512_256(&move_token.opt_local_relays.canonical_serialize())
+    let mut writer = hash_writer();
+    move_token
+        .opt_local_relays
+        .canonical_serialize_into(&mut writer)
+        .unwrap();
+    writer.finalize()
 }
 
 /// Hash operations and local_address:
@@ -187,20 +202,23 @@ pub fn prefix_hash<B, S>(move_token: &MoveToken<B, S>) -> HashResult
 where
     B: CanonicalSerialize,
 {
-    let mut hash_buff = Vec::new();
+    let mut writer = hash_writer();
 
-    hash_buff.extend_from_slice(&move_token.old_token);
+    writer.write_all(&move_token.old_token).unwrap();
 
     // TODO: Use CanonicalSerialize instead here:
-    hash_buff
-        .write_u64::<BigEndian>(usize_to_u64(move_token.operations.len()).unwrap())
+    writer
+        .write_u64::<BigEndian>(usize_to_u64(move_token.len()).unwrap())
         .unwrap();
-    for op in &move_token.operations {
-        hash_buff.extend_from_slice(&op.canonical_serialize());
+    for op in move_token.operations_iter() {
+        op.canonical_serialize_into(&mut writer).unwrap();
     }
 
-    hash_buff.extend_from_slice(&move_token.opt_local_relays.canonical_serialize());
-    sha_512_256(&hash_buff)
+    move_token
+        .opt_local_relays
+        .canonical_serialize_into(&mut writer)
+        .unwrap();
+    writer.finalize()
 }
 
 pub fn move_token_signature_buff<B, S>(move_token: &MoveToken<B, S>) -> Vec<u8>
@@ -7,15 +7,16 @@ use crate::funder::messages::Rate;
 pub use crate::index_server::messages::{IndexMutation, RequestRoutes, UpdateFriend};
 use crate::index_server::messages::{MultiRoute, NamedIndexServerAddress};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FriendInfo {
     pub send_capacity: u128,
     pub recv_capacity: u128,
     pub rate: Rate,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexClientState {
+    #[serde(with = "crate::serde_utils::map_as_pairs")]
     pub friends: HashMap<PublicKey, FriendInfo>,
 }
 
@@ -23,13 +24,28 @@ pub struct IndexClientState {
 // IndexClient <--> AppServer communication
 // ---------------------------------------------------
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// State of the circuit breaker of an index server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CircuitStateReport {
+    /// The server is used normally
+    Closed,
+    /// The server failed too many times, and is temporarily skipped
+    Open,
+    /// The server will be tried again once
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// ISA stands for Index Server Address
 pub struct IndexClientReport<ISA> {
     /// A list of trusted index servers.
     pub index_servers: Vec<NamedIndexServerAddress<ISA>>,
     /// The server we are currently connected to (None if not connected).
     pub opt_connected_server: Option<PublicKey>,
+    /// Circuit breaker states of index servers. Servers that are not listed are in the
+    /// Closed state.
+    #[serde(with = "crate::serde_utils::map_as_pairs")]
+    pub circuit_states: HashMap<PublicKey, CircuitStateReport>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -39,45 +55,50 @@ pub struct AddIndexServer<ISA> {
     pub name: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IndexClientReportMutation<ISA> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
     RemoveIndexServer(PublicKey),
     SetConnectedServer(Option<PublicKey>),
+    SetCircuitState((PublicKey, CircuitStateReport)),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ResponseRoutesResult {
     Success(Vec<MultiRoute>),
     Failure,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ClientResponseRoutes {
     pub request_id: Uid,
     pub result: ResponseRoutesResult,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct IndexClientReportMutations<ISA> {
     pub opt_app_request_id: Option<Uid>,
     pub mutations: Vec<IndexClientReportMutation<ISA>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IndexClientToAppServer<ISA> {
     ReportMutations(IndexClientReportMutations<ISA>),
     ResponseRoutes(ClientResponseRoutes),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum IndexClientRequest<ISA> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
     RemoveIndexServer(PublicKey),
+    /// Atomically replace an index server with a new one
+    RotateIndexServer((PublicKey, NamedIndexServerAddress<ISA>)), // (old_public_key, new_server)
     RequestRoutes(RequestRoutes),
+    /// Report whether a payment along the routes returned for a routes request succeeded
+    ReportRouteOutcome((Uid, bool)), // (request_id, success)
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum AppServerToIndexClient<ISA> {
     AppRequest((Uid, IndexClientRequest<ISA>)), // (app_request_id, app_request)
     ApplyMutations(Vec<IndexMutation>),
@@ -102,10 +123,19 @@ where
             IndexClientReportMutation::RemoveIndexServer(public_key) => {
                 self.index_servers
                     .retain(|index_server| &index_server.public_key != public_key);
+                self.circuit_states.remove(public_key);
             }
             IndexClientReportMutation::SetConnectedServer(opt_public_key) => {
                 self.opt_connected_server = opt_public_key.clone();
             }
+            IndexClientReportMutation::SetCircuitState((public_key, circuit_state)) => {
+                match circuit_state {
+                    CircuitStateReport::Closed => self.circuit_states.remove(public_key),
+                    _ => self
+                        .circuit_states
+                        .insert(public_key.clone(), *circuit_state),
+                };
+            }
         }
     }
 }
@@ -3,11 +3,13 @@ use crypto::hash::HashResult;
 use crypto::identity::{PublicKey, Signature};
 use crypto::uid::Uid;
 
+use common::canonical_serialize::CanonicalSerialize;
+
 use crate::funder::messages::{FriendsRoute, Rate};
 use crate::net::messages::NetAddress;
 
 /// IndexClient -> IndexServer
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct RequestRoutes {
     pub request_id: Uid,
     /// Wanted capacity for the route.
@@ -20,7 +22,7 @@ pub struct RequestRoutes {
     pub opt_exclude: Option<(PublicKey, PublicKey)>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct RouteCapacityRate {
     pub route: FriendsRoute,
     /// How many credits we can push along this route?
@@ -31,7 +33,7 @@ pub struct RouteCapacityRate {
 
 /// Multiple routes that together allow to pass a certain amount of credits to a destination.
 /// All routes must have the same beginning and the same end.
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct MultiRoute {
     pub routes: Vec<RouteCapacityRate>,
 }
@@ -45,7 +47,7 @@ pub struct ResponseRoutes {
     pub multi_routes: Vec<MultiRoute>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, CanonicalSerialize)]
 pub struct UpdateFriend {
     /// Friend's public key
     pub public_key: PublicKey,
@@ -60,11 +62,13 @@ pub struct UpdateFriend {
     ///       --- C
     /// We can set how much we charge A for forwarding funds. The same rate applies either when A
     /// sends funds to B or to C.
+    // Not part of the signed bytes of `MutationsUpdate`:
+    #[canonical_serialize(skip)]
     pub rate: Rate,
 }
 
 /// IndexClient -> IndexServer
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, CanonicalSerialize)]
 pub enum IndexMutation {
     UpdateFriend(UpdateFriend),
     RemoveFriend(PublicKey),
@@ -156,3 +160,43 @@ impl<ISA> From<NamedIndexServerAddress<ISA>> for IndexServerAddress<ISA> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    /// The derived serialization must match the serialization that was used for signatures
+    /// before it was derived. Changing it would break the signatures of `MutationsUpdate`
+    /// between old and new nodes.
+    #[test]
+    fn test_canonical_serialize_layout() {
+        let update_friend = UpdateFriend {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            send_capacity: 5,
+            recv_capacity: 6,
+            rate: Rate { mul: 7, add: 8 },
+        };
+        // `rate` is not serialized:
+        let mut update_friend_bytes = Vec::new();
+        update_friend_bytes.extend_from_slice(&[0xaa; PUBLIC_KEY_LEN]);
+        update_friend_bytes.extend_from_slice(&5u128.to_be_bytes());
+        update_friend_bytes.extend_from_slice(&6u128.to_be_bytes());
+        assert_eq!(update_friend.canonical_serialize(), update_friend_bytes);
+
+        let mut expected = vec![0u8];
+        expected.extend_from_slice(&update_friend_bytes);
+        assert_eq!(
+            IndexMutation::UpdateFriend(update_friend).canonical_serialize(),
+            expected
+        );
+
+        let mut expected = vec![1u8];
+        expected.extend_from_slice(&[0xbb; PUBLIC_KEY_LEN]);
+        assert_eq!(
+            IndexMutation::RemoveFriend(PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]))
+                .canonical_serialize(),
+            expected
+        );
+    }
+}
@@ -2,9 +2,9 @@ use capnp::serialize_packed;
 use std::io;
 
 use crate::capnp_common::{
-    read_custom_u_int128, read_hash, read_public_key, read_rand_nonce, read_signature, read_uid,
-    write_custom_u_int128, write_hash, write_public_key, write_rand_nonce, write_signature,
-    write_uid,
+    check_list_len, read_custom_u_int128, read_hash, read_public_key, read_rand_nonce,
+    read_signature, read_uid, write_custom_u_int128, write_hash, write_public_key,
+    write_rand_nonce, write_signature, write_uid,
 };
 use common::int_convert::usize_to_u32;
 use index_capnp;
@@ -136,7 +136,9 @@ fn deser_response_routes(
     unimplemented!();
     /*
     let mut routes = Vec::new();
-    for route_with_capacity in response_routes_reader.get_routes()? {
+    let routes_reader = response_routes_reader.get_routes()?;
+    check_list_len(routes_reader.len())?;
+    for route_with_capacity in routes_reader {
         routes.push(deser_route_with_capacity(&route_with_capacity)?);
     }
 
@@ -257,7 +259,9 @@ fn deser_mutations_update(
     mutations_update_reader: &index_capnp::mutations_update::Reader,
 ) -> Result<MutationsUpdate, SerializeError> {
     let mut index_mutations = Vec::new();
-    for index_mutation_reader in mutations_update_reader.get_index_mutations()? {
+    let index_mutations_reader = mutations_update_reader.get_index_mutations()?;
+    check_list_len(index_mutations_reader.len())?;
+    for index_mutation_reader in index_mutations_reader {
         index_mutations.push(deser_index_mutation(&index_mutation_reader)?);
     }
 
@@ -289,7 +293,9 @@ fn deser_time_proof_link(
     time_proof_link_reader: &index_capnp::time_proof_link::Reader,
 ) -> Result<TimeProofLink, SerializeError> {
     let mut hashes = Vec::new();
-    for hash_reader in time_proof_link_reader.get_hashes()? {
+    let hashes_reader = time_proof_link_reader.get_hashes()?;
+    check_list_len(hashes_reader.len())?;
+    for hash_reader in hashes_reader {
         hashes.push(read_hash(&hash_reader)?);
     }
 
@@ -325,7 +331,9 @@ fn deser_forward_mutations_update(
     forward_mutations_update_reader: &index_capnp::forward_mutations_update::Reader,
 ) -> Result<ForwardMutationsUpdate, SerializeError> {
     let mut time_proof_chain = Vec::new();
-    for time_proof_link_reader in forward_mutations_update_reader.get_time_proof_chain()? {
+    let time_proof_chain_reader = forward_mutations_update_reader.get_time_proof_chain()?;
+    check_list_len(time_proof_chain_reader.len())?;
+    for time_proof_link_reader in time_proof_chain_reader {
         time_proof_chain.push(deser_time_proof_link(&time_proof_link_reader)?);
     }
 
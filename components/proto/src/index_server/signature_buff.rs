@@ -4,42 +4,11 @@ use common::int_convert::usize_to_u64;
 use crypto::hash;
 use crypto::identity::verify_signature;
 
-use super::messages::{IndexMutation, MutationsUpdate, UpdateFriend};
+use super::messages::MutationsUpdate;
 
 // Canonical Serialization (To be used for signatures):
 // ----------------------------------------------------
 
-impl CanonicalSerialize for UpdateFriend {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        res_bytes.extend_from_slice(&self.public_key);
-        res_bytes
-            .write_u128::<BigEndian>(self.send_capacity)
-            .unwrap();
-        res_bytes
-            .write_u128::<BigEndian>(self.recv_capacity)
-            .unwrap();
-        res_bytes
-    }
-}
-
-impl CanonicalSerialize for IndexMutation {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        let mut res_bytes = Vec::new();
-        match self {
-            IndexMutation::UpdateFriend(update_friend) => {
-                res_bytes.push(0);
-                res_bytes.extend(update_friend.canonical_serialize());
-            }
-            IndexMutation::RemoveFriend(public_key) => {
-                res_bytes.push(1);
-                res_bytes.extend_from_slice(public_key);
-            }
-        };
-        res_bytes
-    }
-}
-
 pub const MUTATIONS_UPDATE_PREFIX: &[u8] = b"MUTATIONS_UPDATE";
 
 impl MutationsUpdate {
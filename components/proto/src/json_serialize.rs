@@ -0,0 +1,998 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Debug, From)]
+pub enum JsonSerializeError {
+    SerdeJsonError(serde_json::Error),
+}
+
+/// Serialize a message into JSON.
+/// The JSON counterpart of the Cap'n Proto based `serialize_*` functions.
+pub trait JsonSerializer {
+    fn serialize_json(&self) -> Vec<u8>;
+    /// Serialize into indented JSON, meant to be read (and edited) by humans.
+    fn serialize_json_pretty(&self) -> Vec<u8>;
+}
+
+/// Deserialize a message from JSON.
+/// The JSON counterpart of the Cap'n Proto based `deserialize_*` functions.
+pub trait JsonDeserializer: Sized {
+    fn deserialize_json(data: &[u8]) -> Result<Self, JsonSerializeError>;
+}
+
+impl<T> JsonSerializer for T
+where
+    T: Serialize,
+{
+    fn serialize_json(&self) -> Vec<u8> {
+        // Serialization may only fail for maps with non string keys, or for failing `Serialize`
+        // implementations. Messages are not supposed to contain either.
+        serde_json::to_vec(self).unwrap()
+    }
+
+    fn serialize_json_pretty(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+}
+
+impl<T> JsonDeserializer for T
+where
+    T: DeserializeOwned,
+{
+    fn deserialize_json(data: &[u8]) -> Result<Self, JsonSerializeError> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::fmt::Debug;
+
+    use im::hashmap::HashMap as ImHashMap;
+    use im::vector::Vector as ImVec;
+
+    use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::hash_lock::{HashedLock, PlainLock, HASHED_LOCK_LEN, PLAIN_LOCK_LEN};
+    use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::payment_id::{PaymentId, PAYMENT_ID_LEN};
+    use crypto::uid::{Uid, UID_LEN};
+
+    use crate::app_server::messages::{
+        AppPermissions, AppRequest, AppServerToApp, AppToAppServer, FriendPermissions,
+        NamedRelayAddress, NamedRelaysMutation, NodeReport, NodeReportMutation, RelayAddress,
+        ReportMutations,
+    };
+    use crate::funder::messages::{
+        AckClosePayment, AddFriend, AddInvoice, CancelSendFundsOp, ChannelerToFunder,
+        ChannelerUpdateFriend, CollectSendFundsOp, Commit, CreateMultiRoutePayment, CreatePayment,
+        CreateTransaction, FriendMessage, FriendStatus, FriendTcOp, FriendsRoute, FunderControl,
+        FunderIncomingControl, FunderOutgoingControl, FunderToChanneler, MoveToken,
+        MoveTokenRequest, MultiCommit, MultiRouteTransaction, PaymentEvent, PaymentEventKind,
+        PaymentHistoryEntry, PaymentHistoryResponse, PaymentOutcome, PaymentStatus,
+        PendingTransaction, QueryPaymentHistory, Rate, Receipt, ReceiptAck, RemoveFriend,
+        RequestResult, RequestSendFundsOp, RequestsStatus, ResetFriendChannel, ResetTerms,
+        ResponseClosePayment, ResponsePaymentTimeline, ResponseSendFundsOp, SetFriendName,
+        SetFriendNote, SetFriendRate, SetFriendRelays, SetFriendRemoteMaxDebt, SetFriendStatus,
+        SetRequestsStatus, TransactionResult, TransactionStage, UserRequestSendFunds,
+    };
+    use crate::index_client::messages::{
+        AddIndexServer, AppServerToIndexClient, CircuitStateReport, ClientResponseRoutes,
+        FriendInfo, IndexClientReport, IndexClientReportMutation, IndexClientReportMutations,
+        IndexClientRequest, IndexClientState, IndexClientToAppServer, IndexMutation, RequestRoutes,
+        ResponseRoutesResult, UpdateFriend,
+    };
+    use crate::index_server::messages::{MultiRoute, NamedIndexServerAddress, RouteCapacityRate};
+    use crate::net::messages::NetAddress;
+    use crate::relay::messages::{
+        ConnectionStats, IncomingConnection, InitConnection, RejectConnection, RelayListenIn,
+        RelayListenOut,
+    };
+    use crate::report::messages::{
+        AddFriendReport, ChannelInconsistentReport, ChannelStatsReport, ChannelStatusReport,
+        DirectionReport, FriendLivenessReport, FriendReport, FriendReportMutation,
+        FriendStatusReport, FunderReport, FunderReportMutation, FunderReportMutations,
+        InvoiceProgressReport, McBalanceReport, McRequestsStatusReport, MoveTokenHashedReport,
+        RequestsStatusReport, ResetTermsReport, SentLocalRelaysReport, TcReport,
+    };
+
+    fn check_round_trip<T>(msg: &T)
+    where
+        T: JsonSerializer + JsonDeserializer + Debug + PartialEq,
+    {
+        let serialized = msg.serialize_json();
+        let msg2 = T::deserialize_json(&serialized).unwrap();
+        assert_eq!(msg, &msg2);
+
+        let serialized = msg.serialize_json_pretty();
+        let msg2 = T::deserialize_json(&serialized).unwrap();
+        assert_eq!(msg, &msg2);
+    }
+
+    /// Round trip check for messages that do not implement `PartialEq`.
+    /// The message is considered intact if it serializes back into the same JSON.
+    fn check_round_trip_reserialize<T>(msg: &T)
+    where
+        T: JsonSerializer + JsonDeserializer,
+    {
+        let serialized = msg.serialize_json();
+        let msg2 = T::deserialize_json(&serialized).unwrap();
+        assert_eq!(serialized, msg2.serialize_json());
+
+        let serialized = msg.serialize_json_pretty();
+        let msg2 = T::deserialize_json(&serialized).unwrap();
+        assert_eq!(serialized, msg2.serialize_json_pretty());
+    }
+
+    fn dummy_net_address() -> NetAddress {
+        NetAddress::try_from("net_address".to_owned()).unwrap()
+    }
+
+    fn dummy_named_relay_address() -> NamedRelayAddress {
+        NamedRelayAddress {
+            public_key: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+            address: dummy_net_address(),
+            name: "relay_name".to_owned(),
+        }
+    }
+
+    fn dummy_relay_address() -> RelayAddress {
+        dummy_named_relay_address().into()
+    }
+
+    fn dummy_named_index_server_address() -> NamedIndexServerAddress {
+        NamedIndexServerAddress {
+            public_key: PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+            address: dummy_net_address(),
+            name: "index_server_name".to_owned(),
+        }
+    }
+
+    fn dummy_route() -> FriendsRoute {
+        FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            ],
+        }
+    }
+
+    fn dummy_commit() -> Commit {
+        Commit {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            dest_payment: 10,
+            src_plain_lock: PlainLock::from(&[0x02; PLAIN_LOCK_LEN]),
+            dest_hashed_lock: HashedLock::from(&[0x03; HASHED_LOCK_LEN]),
+            signature: Signature::from(&[0x04; SIGNATURE_LEN]),
+        }
+    }
+
+    fn dummy_multi_commit() -> MultiCommit {
+        MultiCommit {
+            invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+            total_dest_payment: 20,
+            commits: vec![dummy_commit(), dummy_commit()],
+        }
+    }
+
+    fn dummy_receipt() -> Receipt {
+        Receipt {
+            response_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+            src_plain_lock: PlainLock::from(&[0x02; PLAIN_LOCK_LEN]),
+            dest_plain_lock: PlainLock::from(&[0x05; PLAIN_LOCK_LEN]),
+            dest_payment: 10,
+            total_dest_payment: u128::max_value(),
+            signature: Signature::from(&[0x04; SIGNATURE_LEN]),
+        }
+    }
+
+    fn dummy_multi_route() -> MultiRoute {
+        MultiRoute {
+            routes: vec![RouteCapacityRate {
+                route: dummy_route(),
+                capacity: 100,
+                rate: Rate { mul: 0, add: 1 },
+            }],
+        }
+    }
+
+    fn dummy_request_routes() -> RequestRoutes {
+        RequestRoutes {
+            request_id: Uid::from(&[0x33; UID_LEN]),
+            capacity: 250,
+            source: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            destination: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            opt_exclude: Some((
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            )),
+        }
+    }
+
+    fn dummy_move_token_hashed_report() -> MoveTokenHashedReport {
+        MoveTokenHashedReport {
+            prefix_hash: HashResult::from(&[0x01; HASH_RESULT_LEN]),
+            local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            remote_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            inconsistency_counter: 1,
+            move_token_counter: u128::max_value(),
+            balance: i128::min_value(),
+            local_pending_debt: 2,
+            remote_pending_debt: 3,
+            rand_nonce: RandValue::from(&[0x06; RAND_VALUE_LEN]),
+            new_token: Signature::from(&[0x04; SIGNATURE_LEN]),
+        }
+    }
+
+    fn dummy_tc_report() -> TcReport {
+        TcReport {
+            direction: DirectionReport::Outgoing,
+            balance: McBalanceReport {
+                balance: -5,
+                local_max_debt: 10,
+                remote_max_debt: u128::max_value(),
+                local_pending_debt: 1,
+                remote_pending_debt: 2,
+            },
+            requests_status: McRequestsStatusReport {
+                local: RequestsStatusReport::Open,
+                remote: RequestsStatusReport::Closed,
+            },
+            num_local_pending_requests: 3,
+            num_remote_pending_requests: 4,
+        }
+    }
+
+    fn dummy_node_report() -> NodeReport {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let friend_report = FriendReport {
+            name: "friend_name".to_owned(),
+            note: String::new(),
+            rate: Rate { mul: 1, add: 2 },
+            remote_relays: vec![RelayAddress {
+                public_key: pk_b.clone(),
+                address: dummy_net_address(),
+            }],
+            sent_local_relays: SentLocalRelaysReport::NeverSent,
+            opt_last_incoming_move_token: None,
+            liveness: FriendLivenessReport::Online,
+            channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+                local_reset_terms_balance: -10,
+                opt_remote_reset_terms: Some(ResetTermsReport {
+                    reset_token: Signature::from(&[0x11; SIGNATURE_LEN]),
+                    balance_for_reset: 20,
+                }),
+            }),
+            wanted_remote_max_debt: u128::max_value(),
+            wanted_local_requests_status: RequestsStatusReport::Open,
+            num_pending_requests: 1,
+            num_pending_backwards_ops: 2,
+            status: FriendStatusReport::Enabled,
+            num_pending_user_requests: 3,
+            channel_stats: ChannelStatsReport::default(),
+            is_frozen: false,
+        };
+
+        let mut friends = ImHashMap::new();
+        friends.insert(pk_b.clone(), friend_report);
+
+        let mut invoices_progress = ImHashMap::new();
+        invoices_progress.insert(
+            InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+            InvoiceProgressReport {
+                collected: 5,
+                total_dest_payment: 10,
+            },
+        );
+
+        let funder_report = FunderReport {
+            local_public_key: pk_a.clone(),
+            relays: vec![NamedRelayAddress {
+                public_key: pk_a.clone(),
+                address: dummy_net_address(),
+                name: "relay_name".to_owned(),
+            }]
+            .into_iter()
+            .collect(),
+            friends,
+            num_open_invoices: 1,
+            invoices_progress,
+            num_payments: 0,
+            num_open_transactions: 0,
+            total_frozen_credits: (u128::max_value(), 0),
+            max_friends: 8,
+            max_node_relays: 4,
+        };
+
+        let mut circuit_states = HashMap::new();
+        circuit_states.insert(pk_b.clone(), CircuitStateReport::Open);
+
+        let index_client_report = IndexClientReport {
+            index_servers: vec![dummy_named_index_server_address()],
+            opt_connected_server: Some(pk_a),
+            circuit_states,
+        };
+
+        NodeReport {
+            funder_report,
+            index_client_report,
+        }
+    }
+
+    /// One mutation of every kind that may be applied to a `FriendReport`
+    fn dummy_friend_report_mutations() -> Vec<FriendReportMutation> {
+        let relays: ImVec<_> = vec![dummy_named_relay_address()].into_iter().collect();
+        vec![
+            FriendReportMutation::SetRemoteRelays(vec![dummy_relay_address()]),
+            FriendReportMutation::SetName("friend_name".to_owned()),
+            FriendReportMutation::SetNote("friend_note".to_owned()),
+            FriendReportMutation::SetRate(Rate { mul: 3, add: 4 }),
+            FriendReportMutation::SetSentLocalRelays(SentLocalRelaysReport::NeverSent),
+            FriendReportMutation::SetSentLocalRelays(SentLocalRelaysReport::Transition((
+                relays.clone(),
+                ImVec::new(),
+            ))),
+            FriendReportMutation::SetSentLocalRelays(SentLocalRelaysReport::LastSent(relays)),
+            FriendReportMutation::SetChannelStatus(ChannelStatusReport::Inconsistent(
+                ChannelInconsistentReport {
+                    local_reset_terms_balance: 7,
+                    opt_remote_reset_terms: None,
+                },
+            )),
+            FriendReportMutation::SetChannelStatus(ChannelStatusReport::Consistent(
+                dummy_tc_report(),
+            )),
+            FriendReportMutation::SetWantedRemoteMaxDebt(u128::max_value()),
+            FriendReportMutation::SetWantedLocalRequestsStatus(RequestsStatusReport::Closed),
+            FriendReportMutation::SetNumPendingRequests(1),
+            FriendReportMutation::SetNumPendingBackwardsOps(2),
+            FriendReportMutation::SetStatus(FriendStatusReport::Disabled),
+            FriendReportMutation::SetNumPendingUserRequests(3),
+            FriendReportMutation::SetOptLastIncomingMoveToken(None),
+            FriendReportMutation::SetOptLastIncomingMoveToken(Some(
+                dummy_move_token_hashed_report(),
+            )),
+            FriendReportMutation::SetLiveness(FriendLivenessReport::Offline),
+            FriendReportMutation::SetChannelStats(ChannelStatsReport {
+                total_sent: 1,
+                total_received: 2,
+                total_fees_sent: 3,
+                total_fees_received: u128::max_value(),
+                num_transactions_sent: 5,
+                num_transactions_received: 6,
+            }),
+            FriendReportMutation::SetFrozen(true),
+        ]
+    }
+
+    /// One mutation of every kind that may be applied to a `FunderReport`
+    fn dummy_funder_report_mutations() -> Vec<FunderReportMutation> {
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let invoice_id = InvoiceId::from(&[0x22; INVOICE_ID_LEN]);
+
+        let mut mutations = vec![
+            FunderReportMutation::AddRelay(dummy_named_relay_address()),
+            FunderReportMutation::RemoveRelay(PublicKey::from(&[0xcc; PUBLIC_KEY_LEN])),
+            FunderReportMutation::AddFriend(AddFriendReport {
+                friend_public_key: pk_b.clone(),
+                name: "friend_name".to_owned(),
+                note: String::new(),
+                relays: vec![dummy_relay_address()],
+                balance: i128::max_value(),
+                opt_last_incoming_move_token: Some(dummy_move_token_hashed_report()),
+                channel_status: ChannelStatusReport::Consistent(dummy_tc_report()),
+            }),
+            FunderReportMutation::RemoveFriend(pk_b.clone()),
+            FunderReportMutation::SetNumOpenInvoices(1),
+            FunderReportMutation::SetInvoiceProgress((
+                invoice_id.clone(),
+                InvoiceProgressReport {
+                    collected: 5,
+                    total_dest_payment: 10,
+                },
+            )),
+            FunderReportMutation::RemoveInvoiceProgress(invoice_id),
+            FunderReportMutation::SetNumPayments(2),
+            FunderReportMutation::SetNumOpenTransactions(3),
+            FunderReportMutation::SetTotalFrozenCredits((u128::max_value(), 4)),
+        ];
+        for friend_report_mutation in dummy_friend_report_mutations() {
+            mutations.push(FunderReportMutation::FriendReportMutation((
+                pk_b.clone(),
+                friend_report_mutation,
+            )));
+        }
+        mutations
+    }
+
+    /// One mutation of every kind that may be applied to an `IndexClientReport`
+    fn dummy_index_client_report_mutations() -> Vec<IndexClientReportMutation<NetAddress>> {
+        vec![
+            IndexClientReportMutation::AddIndexServer(dummy_named_index_server_address()),
+            IndexClientReportMutation::RemoveIndexServer(PublicKey::from(&[0xdd; PUBLIC_KEY_LEN])),
+            IndexClientReportMutation::SetConnectedServer(None),
+            IndexClientReportMutation::SetConnectedServer(Some(PublicKey::from(
+                &[0xdd; PUBLIC_KEY_LEN],
+            ))),
+            IndexClientReportMutation::SetCircuitState((
+                PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+                CircuitStateReport::Closed,
+            )),
+            IndexClientReportMutation::SetCircuitState((
+                PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+                CircuitStateReport::HalfOpen,
+            )),
+        ]
+    }
+
+    #[test]
+    fn test_json_app_server_messages() {
+        let uid = Uid::from(&[0x33; UID_LEN]);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let payment_id = PaymentId::from(&[0x44; PAYMENT_ID_LEN]);
+        let invoice_id = InvoiceId::from(&[0x22; INVOICE_ID_LEN]);
+
+        check_round_trip(&dummy_named_relay_address());
+        check_round_trip(&dummy_relay_address());
+        check_round_trip(&dummy_node_report());
+
+        let mut node_report_mutations = Vec::new();
+        for mutation in dummy_funder_report_mutations() {
+            node_report_mutations.push(NodeReportMutation::Funder(mutation));
+        }
+        for mutation in dummy_index_client_report_mutations() {
+            node_report_mutations.push(NodeReportMutation::IndexClient(mutation));
+        }
+        for mutation in &node_report_mutations {
+            check_round_trip(mutation);
+        }
+
+        let transaction_result = TransactionResult {
+            request_id: uid.clone(),
+            result: RequestResult::Success(dummy_commit()),
+        };
+        let app_server_to_app_msgs: Vec<AppServerToApp> = vec![
+            AppServerToApp::TransactionResult(transaction_result.clone()),
+            AppServerToApp::ResponseClosePayment(ResponseClosePayment {
+                payment_id: payment_id.clone(),
+                status: PaymentStatus::Canceled(uid.clone()),
+            }),
+            AppServerToApp::ResponsePaymentTimeline(ResponsePaymentTimeline {
+                payment_id: payment_id.clone(),
+                timeline: vec![PaymentEvent {
+                    tick: 1,
+                    event: PaymentEventKind::Created,
+                }],
+            }),
+            AppServerToApp::Report((uid.clone(), dummy_node_report())),
+            AppServerToApp::ReportMutations(ReportMutations {
+                opt_app_request_id: None,
+                mutations: Vec::new(),
+            }),
+            AppServerToApp::ReportMutations(ReportMutations {
+                opt_app_request_id: Some(uid.clone()),
+                mutations: node_report_mutations,
+            }),
+            AppServerToApp::ResponseRoutes(ClientResponseRoutes {
+                request_id: uid.clone(),
+                result: ResponseRoutesResult::Failure,
+            }),
+            AppServerToApp::ResponseDeadLetterQueue(vec![(uid.clone(), transaction_result)]),
+        ];
+        for msg in &app_server_to_app_msgs {
+            check_round_trip(msg);
+        }
+
+        check_round_trip(&NamedRelaysMutation::AddRelay(dummy_named_relay_address()));
+        check_round_trip(&NamedRelaysMutation::<NetAddress>::RemoveRelay(
+            pk_a.clone(),
+        ));
+
+        let app_requests: Vec<AppRequest> = vec![
+            AppRequest::AddRelay(dummy_named_relay_address()),
+            AppRequest::RemoveRelay(pk_a.clone()),
+            AppRequest::AddFriend(AddFriend {
+                friend_public_key: pk_a.clone(),
+                relays: vec![dummy_relay_address()],
+                name: "friend_name".to_owned(),
+                note: String::new(),
+                balance: i128::min_value(),
+            }),
+            AppRequest::SetFriendRelays(SetFriendRelays {
+                friend_public_key: pk_a.clone(),
+                relays: vec![dummy_relay_address()],
+            }),
+            AppRequest::SetFriendName(SetFriendName {
+                friend_public_key: pk_a.clone(),
+                name: "friend_name".to_owned(),
+            }),
+            AppRequest::SetFriendNote(SetFriendNote {
+                friend_public_key: pk_a.clone(),
+                note: "Rate: 0.1%".to_owned(),
+            }),
+            AppRequest::RemoveFriend(pk_a.clone()),
+            AppRequest::EnableFriend(pk_a.clone()),
+            AppRequest::DisableFriend(pk_a.clone()),
+            AppRequest::OpenFriend(pk_a.clone()),
+            AppRequest::CloseFriend(pk_a.clone()),
+            AppRequest::SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt {
+                friend_public_key: pk_a.clone(),
+                remote_max_debt: u128::max_value(),
+            }),
+            AppRequest::SetFriendRate(SetFriendRate {
+                friend_public_key: pk_a.clone(),
+                rate: Rate {
+                    mul: u32::max_value(),
+                    add: 1,
+                },
+            }),
+            AppRequest::ResetFriendChannel(ResetFriendChannel {
+                friend_public_key: pk_a.clone(),
+                reset_token: Signature::from(&[0x11; SIGNATURE_LEN]),
+            }),
+            AppRequest::FreezeFriend(pk_a.clone()),
+            AppRequest::UnfreezeFriend(pk_a.clone()),
+            AppRequest::CreatePayment(CreatePayment {
+                payment_id: payment_id.clone(),
+                invoice_id: invoice_id.clone(),
+                total_dest_payment: 30,
+                dest_public_key: pk_a.clone(),
+            }),
+            AppRequest::CreateTransaction(CreateTransaction {
+                payment_id: payment_id.clone(),
+                request_id: uid.clone(),
+                route: dummy_route(),
+                dest_payment: 10,
+                fees: 1,
+            }),
+            AppRequest::CreateMultiRoutePayment(CreateMultiRoutePayment {
+                payment_id: payment_id.clone(),
+                invoice_id: invoice_id.clone(),
+                total_dest_payment: 30,
+                dest_public_key: pk_a.clone(),
+                transactions: vec![MultiRouteTransaction {
+                    request_id: uid.clone(),
+                    route: dummy_route(),
+                    dest_payment: 30,
+                    fees: 2,
+                }],
+            }),
+            AppRequest::RequestClosePayment(payment_id.clone()),
+            AppRequest::AckClosePayment(AckClosePayment {
+                payment_id: payment_id.clone(),
+                ack_uid: uid.clone(),
+            }),
+            AppRequest::GetPaymentTimeline(payment_id),
+            AppRequest::AddInvoice(AddInvoice {
+                invoice_id: invoice_id.clone(),
+                total_dest_payment: 30,
+                validity_ticks: 0x100,
+            }),
+            AppRequest::CancelInvoice(invoice_id),
+            AppRequest::CommitInvoice(dummy_multi_commit()),
+            AppRequest::RequestRoutes(dummy_request_routes()),
+            AppRequest::ReportRouteOutcome((uid.clone(), true)),
+            AppRequest::AddIndexServer(dummy_named_index_server_address()),
+            AppRequest::RemoveIndexServer(pk_a.clone()),
+            AppRequest::RotateIndexServer((pk_a.clone(), dummy_named_index_server_address())),
+            AppRequest::GetDeadLetterQueue,
+        ];
+        for app_request in app_requests {
+            check_round_trip(&AppToAppServer::new(uid.clone(), app_request));
+        }
+
+        let mut friend_permissions = HashMap::new();
+        friend_permissions.insert(
+            pk_a,
+            FriendPermissions {
+                read: true,
+                set_max_debt: false,
+                open_close: true,
+            },
+        );
+        check_round_trip(&AppPermissions {
+            routes: true,
+            buyer: false,
+            seller: true,
+            config: false,
+            max_requests_per_second: Some(10),
+            friend_permissions: Some(friend_permissions),
+        });
+        check_round_trip(&AppPermissions {
+            routes: false,
+            buyer: true,
+            seller: false,
+            config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
+        });
+    }
+
+    #[test]
+    fn test_json_funder_messages() {
+        let uid = Uid::from(&[0x33; UID_LEN]);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let payment_id = PaymentId::from(&[0x44; PAYMENT_ID_LEN]);
+        let invoice_id = InvoiceId::from(&[0x22; INVOICE_ID_LEN]);
+
+        let update_friend = ChannelerUpdateFriend {
+            friend_public_key: pk_b.clone(),
+            friend_relays: vec![dummy_relay_address()],
+            local_relays: vec![dummy_relay_address()],
+            connection_priority: 1,
+        };
+        check_round_trip_reserialize(&update_friend);
+        check_round_trip_reserialize(&FunderToChanneler::<RelayAddress>::Message((
+            pk_b.clone(),
+            vec![1, 2, 3],
+        )));
+        check_round_trip_reserialize(&FunderToChanneler::SetRelays(vec![dummy_relay_address()]));
+        check_round_trip_reserialize(&FunderToChanneler::UpdateFriend(update_friend));
+        check_round_trip_reserialize(&FunderToChanneler::<RelayAddress>::RemoveFriend(
+            pk_b.clone(),
+        ));
+        check_round_trip_reserialize(&ChannelerToFunder::Online(pk_b.clone()));
+        check_round_trip_reserialize(&ChannelerToFunder::Offline(pk_b.clone()));
+        check_round_trip_reserialize(&ChannelerToFunder::Message((pk_b.clone(), Vec::new())));
+
+        let request_send_funds = RequestSendFundsOp {
+            request_id: uid.clone(),
+            src_hashed_lock: HashedLock::from(&[0x07; HASHED_LOCK_LEN]),
+            route: dummy_route(),
+            dest_payment: 10,
+            total_dest_payment: 20,
+            invoice_id: invoice_id.clone(),
+            left_fees: u128::max_value(),
+        };
+        let operations = vec![
+            FriendTcOp::EnableRequests,
+            FriendTcOp::DisableRequests,
+            FriendTcOp::SetRemoteMaxDebt(u128::max_value()),
+            FriendTcOp::RequestSendFunds(request_send_funds),
+            FriendTcOp::ResponseSendFunds(ResponseSendFundsOp {
+                request_id: uid.clone(),
+                dest_hashed_lock: HashedLock::from(&[0x03; HASHED_LOCK_LEN]),
+                rand_nonce: RandValue::from(&[0x06; RAND_VALUE_LEN]),
+                signature: Signature::from(&[0x04; SIGNATURE_LEN]),
+            }),
+            FriendTcOp::CancelSendFunds(CancelSendFundsOp {
+                request_id: uid.clone(),
+            }),
+            FriendTcOp::CollectSendFunds(CollectSendFundsOp {
+                request_id: uid.clone(),
+                src_plain_lock: PlainLock::from(&[0x02; PLAIN_LOCK_LEN]),
+                dest_plain_lock: PlainLock::from(&[0x05; PLAIN_LOCK_LEN]),
+            }),
+        ];
+        let move_token = MoveToken {
+            operations,
+            opt_local_relays: Some(vec![dummy_relay_address()]),
+            old_token: Signature::from(&[0x08; SIGNATURE_LEN]),
+            local_public_key: pk_a.clone(),
+            remote_public_key: pk_b.clone(),
+            inconsistency_counter: 1,
+            move_token_counter: u128::max_value(),
+            balance: i128::min_value(),
+            local_pending_debt: 2,
+            remote_pending_debt: 3,
+            rand_nonce: RandValue::from(&[0x06; RAND_VALUE_LEN]),
+            new_token: Signature::from(&[0x09; SIGNATURE_LEN]),
+        };
+        check_round_trip(&FriendMessage::MoveTokenRequest(MoveTokenRequest {
+            friend_move_token: move_token,
+            token_wanted: true,
+        }));
+        check_round_trip(&FriendMessage::<NetAddress>::InconsistencyError(
+            ResetTerms {
+                reset_token: Signature::from(&[0x11; SIGNATURE_LEN]),
+                inconsistency_counter: 2,
+                balance_for_reset: -20,
+            },
+        ));
+
+        check_round_trip(&dummy_receipt());
+        check_round_trip(&PendingTransaction {
+            request_id: uid.clone(),
+            route: dummy_route(),
+            dest_payment: 10,
+            total_dest_payment: 20,
+            invoice_id: invoice_id.clone(),
+            left_fees: 1,
+            src_hashed_lock: HashedLock::from(&[0x07; HASHED_LOCK_LEN]),
+            stage: TransactionStage::Response(HashedLock::from(&[0x03; HASHED_LOCK_LEN])),
+            created_at_tick: 5,
+        });
+        check_round_trip(&TransactionStage::Request);
+        check_round_trip(&UserRequestSendFunds {
+            payment_id: payment_id.clone(),
+            route: dummy_route(),
+            invoice_id: invoice_id.clone(),
+            dest_payment: 10,
+        });
+        check_round_trip(&ReceiptAck {
+            request_id: uid.clone(),
+            receipt_signature: Signature::from(&[0x04; SIGNATURE_LEN]),
+        });
+
+        let funder_controls: Vec<FunderControl<NetAddress>> = vec![
+            FunderControl::AddRelay(dummy_named_relay_address()),
+            FunderControl::RemoveRelay(pk_a.clone()),
+            FunderControl::AddFriend(AddFriend {
+                friend_public_key: pk_b.clone(),
+                relays: vec![dummy_relay_address()],
+                name: "friend_name".to_owned(),
+                note: "friend_note".to_owned(),
+                balance: -1,
+            }),
+            FunderControl::RemoveFriend(RemoveFriend {
+                friend_public_key: pk_b.clone(),
+            }),
+            FunderControl::SetRequestsStatus(SetRequestsStatus {
+                friend_public_key: pk_b.clone(),
+                status: RequestsStatus::Open,
+            }),
+            FunderControl::SetRequestsStatus(SetRequestsStatus {
+                friend_public_key: pk_b.clone(),
+                status: RequestsStatus::Closed,
+            }),
+            FunderControl::SetFriendStatus(SetFriendStatus {
+                friend_public_key: pk_b.clone(),
+                status: FriendStatus::Enabled,
+            }),
+            FunderControl::SetFriendStatus(SetFriendStatus {
+                friend_public_key: pk_b.clone(),
+                status: FriendStatus::Disabled,
+            }),
+            FunderControl::SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt {
+                friend_public_key: pk_b.clone(),
+                remote_max_debt: u128::max_value(),
+            }),
+            FunderControl::SetFriendRelays(SetFriendRelays {
+                friend_public_key: pk_b.clone(),
+                relays: Vec::new(),
+            }),
+            FunderControl::SetFriendName(SetFriendName {
+                friend_public_key: pk_b.clone(),
+                name: "friend_name".to_owned(),
+            }),
+            FunderControl::SetFriendNote(SetFriendNote {
+                friend_public_key: pk_b.clone(),
+                note: String::new(),
+            }),
+            FunderControl::SetFriendRate(SetFriendRate {
+                friend_public_key: pk_b.clone(),
+                rate: Rate::new(),
+            }),
+            FunderControl::ResetFriendChannel(ResetFriendChannel {
+                friend_public_key: pk_b.clone(),
+                reset_token: Signature::from(&[0x11; SIGNATURE_LEN]),
+            }),
+            FunderControl::FreezeFriend(pk_b.clone()),
+            FunderControl::UnfreezeFriend(pk_b.clone()),
+            FunderControl::CreatePayment(CreatePayment {
+                payment_id: payment_id.clone(),
+                invoice_id: invoice_id.clone(),
+                total_dest_payment: 30,
+                dest_public_key: pk_b.clone(),
+            }),
+            FunderControl::CreateTransaction(CreateTransaction {
+                payment_id: payment_id.clone(),
+                request_id: uid.clone(),
+                route: dummy_route(),
+                dest_payment: 10,
+                fees: 1,
+            }),
+            FunderControl::CreateMultiRoutePayment(CreateMultiRoutePayment {
+                payment_id: payment_id.clone(),
+                invoice_id: invoice_id.clone(),
+                total_dest_payment: 30,
+                dest_public_key: pk_b.clone(),
+                transactions: Vec::new(),
+            }),
+            FunderControl::RequestClosePayment(payment_id.clone()),
+            FunderControl::AckClosePayment(AckClosePayment {
+                payment_id: payment_id.clone(),
+                ack_uid: uid.clone(),
+            }),
+            FunderControl::GetPaymentTimeline(payment_id.clone()),
+            FunderControl::QueryPaymentHistory(QueryPaymentHistory {
+                after_tick: 3,
+                limit: 10,
+            }),
+            FunderControl::AddInvoice(AddInvoice {
+                invoice_id: invoice_id.clone(),
+                total_dest_payment: 30,
+                validity_ticks: 0,
+            }),
+            FunderControl::CancelInvoice(invoice_id.clone()),
+            FunderControl::CommitInvoice(dummy_multi_commit()),
+        ];
+        for funder_control in funder_controls {
+            check_round_trip(&FunderIncomingControl::new(uid.clone(), funder_control));
+        }
+
+        let payment_statuses = vec![
+            PaymentStatus::PaymentNotFound,
+            PaymentStatus::InProgress,
+            PaymentStatus::Success((dummy_receipt(), uid.clone())),
+            PaymentStatus::Canceled(uid.clone()),
+        ];
+        for status in payment_statuses {
+            check_round_trip_reserialize(
+                &FunderOutgoingControl::<NetAddress>::ResponseClosePayment(ResponseClosePayment {
+                    payment_id: payment_id.clone(),
+                    status,
+                }),
+            );
+        }
+
+        let request_results = vec![
+            RequestResult::Success(dummy_commit()),
+            RequestResult::Failure,
+        ];
+        for result in request_results {
+            check_round_trip_reserialize(&FunderOutgoingControl::<NetAddress>::TransactionResult(
+                TransactionResult {
+                    request_id: uid.clone(),
+                    result,
+                },
+            ));
+        }
+
+        let timeline = vec![
+            PaymentEventKind::Created,
+            PaymentEventKind::TransactionAdded(uid.clone()),
+            PaymentEventKind::TransactionFailed(uid.clone()),
+            PaymentEventKind::ClosedRequested,
+            PaymentEventKind::Succeeded,
+            PaymentEventKind::Canceled,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(tick, event)| PaymentEvent {
+            tick: tick as u64,
+            event,
+        })
+        .collect();
+        check_round_trip_reserialize(
+            &FunderOutgoingControl::<NetAddress>::ResponsePaymentTimeline(
+                ResponsePaymentTimeline {
+                    payment_id: payment_id.clone(),
+                    timeline,
+                },
+            ),
+        );
+
+        let entries = vec![PaymentOutcome::Succeeded, PaymentOutcome::Canceled]
+            .into_iter()
+            .map(|outcome| PaymentHistoryEntry {
+                payment_id: payment_id.clone(),
+                invoice_id: invoice_id.clone(),
+                total_amount: u128::max_value(),
+                num_transactions: 2,
+                outcome,
+                completed_at_tick: 7,
+            })
+            .collect();
+        check_round_trip_reserialize(
+            &FunderOutgoingControl::<NetAddress>::PaymentHistoryResponse(PaymentHistoryResponse {
+                after_tick: 0,
+                entries,
+            }),
+        );
+
+        check_round_trip_reserialize(&FunderOutgoingControl::ReportMutations(
+            FunderReportMutations {
+                opt_app_request_id: Some(uid),
+                mutations: dummy_funder_report_mutations(),
+            },
+        ));
+    }
+
+    #[test]
+    fn test_json_index_client_messages() {
+        let uid = Uid::from(&[0x33; UID_LEN]);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let friend_info = FriendInfo {
+            send_capacity: u128::max_value(),
+            recv_capacity: 0,
+            rate: Rate { mul: 1, add: 0 },
+        };
+        check_round_trip_reserialize(&friend_info);
+        // A single friend, to keep the serialized order of the map stable:
+        let mut friends = HashMap::new();
+        friends.insert(pk_a.clone(), friend_info);
+        check_round_trip_reserialize(&IndexClientState { friends });
+
+        check_round_trip(&AddIndexServer {
+            public_key: pk_a.clone(),
+            address: dummy_net_address(),
+            name: "index_server_name".to_owned(),
+        });
+
+        check_round_trip(&IndexClientToAppServer::<NetAddress>::ResponseRoutes(
+            ClientResponseRoutes {
+                request_id: uid.clone(),
+                result: ResponseRoutesResult::Success(vec![dummy_multi_route()]),
+            },
+        ));
+        check_round_trip(&IndexClientToAppServer::<NetAddress>::ResponseRoutes(
+            ClientResponseRoutes {
+                request_id: uid.clone(),
+                result: ResponseRoutesResult::Failure,
+            },
+        ));
+        check_round_trip(&IndexClientToAppServer::ReportMutations(
+            IndexClientReportMutations {
+                opt_app_request_id: None,
+                mutations: dummy_index_client_report_mutations(),
+            },
+        ));
+
+        let index_client_requests: Vec<IndexClientRequest<NetAddress>> = vec![
+            IndexClientRequest::AddIndexServer(dummy_named_index_server_address()),
+            IndexClientRequest::RemoveIndexServer(pk_a.clone()),
+            IndexClientRequest::RotateIndexServer((
+                pk_a.clone(),
+                dummy_named_index_server_address(),
+            )),
+            IndexClientRequest::RequestRoutes(dummy_request_routes()),
+            IndexClientRequest::ReportRouteOutcome((uid.clone(), false)),
+        ];
+        for index_client_request in index_client_requests {
+            check_round_trip(&AppServerToIndexClient::AppRequest((
+                uid.clone(),
+                index_client_request,
+            )));
+        }
+
+        check_round_trip(&AppServerToIndexClient::<NetAddress>::ApplyMutations(vec![
+            IndexMutation::UpdateFriend(UpdateFriend {
+                public_key: pk_b.clone(),
+                send_capacity: 10,
+                recv_capacity: u128::max_value(),
+                rate: Rate { mul: 2, add: 3 },
+            }),
+            IndexMutation::RemoveFriend(pk_b),
+        ]));
+    }
+
+    #[test]
+    fn test_json_relay_messages() {
+        let public_key = PublicKey::from(&[0x55; PUBLIC_KEY_LEN]);
+        check_round_trip(&InitConnection::Listen);
+        check_round_trip(&InitConnection::Accept(public_key.clone()));
+        check_round_trip(&InitConnection::Connect(public_key.clone()));
+        check_round_trip(&RelayListenIn::RejectConnection(RejectConnection {
+            public_key: public_key.clone(),
+        }));
+        check_round_trip(&RelayListenIn::Ping);
+        check_round_trip(&RelayListenOut::IncomingConnection(IncomingConnection {
+            public_key,
+        }));
+        check_round_trip(&RelayListenOut::Pong);
+        check_round_trip(&ConnectionStats {
+            connected_at_tick: 1,
+            bytes_sent: u64::max_value(),
+            bytes_received: 2,
+            messages_sent: 3,
+        });
+    }
+
+    #[test]
+    fn test_json_deserialize_invalid() {
+        assert!(InitConnection::deserialize_json(b"not json").is_err());
+        assert!(RelayListenIn::deserialize_json(b"\"Pong\"").is_err());
+    }
+}
@@ -38,11 +38,13 @@ pub fn deserialize_ka_message(data: &[u8]) -> Result<KaMessage, SerializeError>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::serialize::write_fuzz_seed;
 
     #[test]
     fn test_basic_serialize_ka_message_message() {
         let ka_message = KaMessage::Message(vec![1, 2, 3, 4, 5]);
         let ser_data = serialize_ka_message(&ka_message);
+        write_fuzz_seed("ka_message", &ser_data);
         let ka_message2 = deserialize_ka_message(&ser_data).unwrap();
         assert_eq!(ka_message, ka_message2);
     }
@@ -51,6 +53,7 @@ mod tests {
     fn test_basic_serialize_ka_message_keepalive() {
         let ka_message = KaMessage::KeepAlive;
         let ser_data = serialize_ka_message(&ka_message);
+        write_fuzz_seed("ka_message", &ser_data);
         let ka_message2 = deserialize_ka_message(&ser_data).unwrap();
         assert_eq!(ka_message, ka_message2);
     }
@@ -19,6 +19,8 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate bytes;
+#[cfg(feature = "json-serde")]
+extern crate serde_json;
 
 extern crate base64;
 extern crate im;
@@ -43,12 +45,15 @@ pub mod file;
 pub mod funder;
 pub mod index_client;
 pub mod index_server;
+#[cfg(feature = "json-serde")]
+pub mod json_serialize;
 pub mod keepalive;
 pub mod net;
 pub mod node;
 pub mod relay;
 pub mod report;
 pub mod secure_channel;
+pub mod serde_utils;
 pub mod serialize;
 
 include_schema!(report_capnp, "report_capnp");
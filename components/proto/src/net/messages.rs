@@ -3,7 +3,26 @@ use std::convert::TryFrom;
 use crate::consts::MAX_NET_ADDRESS_LENGTH;
 use common::canonical_serialize::CanonicalSerialize;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Display)]
+/// Suffix of Tor hidden service host names
+const ONION_SUFFIX: &str = ".onion";
+/// Length of a Tor hidden service name (Without the `.onion` suffix), version 2
+const ONION_V2_NAME_LEN: usize = 16;
+/// Length of a Tor hidden service name (Without the `.onion` suffix), version 3
+const ONION_V3_NAME_LEN: usize = 56;
+
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    Display,
+    CanonicalSerialize,
+)]
 #[display(fmt = "{}", _0)]
 pub struct NetAddress(String);
 
@@ -11,17 +30,51 @@ impl NetAddress {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// Is this the address of a Tor hidden service (`<name>.onion:<port>`)?
+    /// Such an address can only be reached through a proxy (Tor's SOCKS5 proxy),
+    /// which should also resolve it.
+    pub fn is_onion(&self) -> bool {
+        opt_onion_name(&self.0).is_some()
+    }
 }
 
-impl CanonicalSerialize for NetAddress {
-    fn canonical_serialize(&self) -> Vec<u8> {
-        self.0.canonical_serialize()
+/// Get the host part of an address of the form `host:port`
+fn host_part(address: &str) -> &str {
+    match address.rfind(':') {
+        Some(colon_index) => &address[..colon_index],
+        None => address,
     }
 }
 
+/// If `address` is an onion address, get the name of the hidden service
+/// (The last label before the `.onion` suffix).
+fn opt_onion_name(address: &str) -> Option<&str> {
+    let host = host_part(address);
+    if host.len() < ONION_SUFFIX.len() {
+        return None;
+    }
+    let (name, suffix) = host.split_at(host.len() - ONION_SUFFIX.len());
+    if !suffix.eq_ignore_ascii_case(ONION_SUFFIX) {
+        return None;
+    }
+    // Subdomains of a hidden service are allowed:
+    name.rsplit('.').next()
+}
+
+/// Check that the name of a hidden service has the length of a v2 or v3 address,
+/// and contains only base32 characters.
+fn is_valid_onion_name(name: &str) -> bool {
+    (name.len() == ONION_V2_NAME_LEN || name.len() == ONION_V3_NAME_LEN)
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphabetic() || ('2'..='7').contains(&c))
+}
+
 #[derive(Debug)]
 pub enum NetAddressError {
     AddressTooLong,
+    InvalidOnionAddress,
 }
 
 impl TryFrom<String> for NetAddress {
@@ -30,6 +83,49 @@ impl TryFrom<String> for NetAddress {
         if address.len() > MAX_NET_ADDRESS_LENGTH {
             return Err(NetAddressError::AddressTooLong);
         }
+        if let Some(onion_name) = opt_onion_name(&address) {
+            if !is_valid_onion_name(onion_name) {
+                return Err(NetAddressError::InvalidOnionAddress);
+            }
+        }
         Ok(NetAddress(address))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONION_V2: &str = "expyuzz4wqqyqhjn";
+    const ONION_V3: &str = "2gzyxa5ihm7nsggfxnu52rck2vv4rvmdlkiu3zzui5du4xyclen53wid";
+
+    #[test]
+    fn test_net_address_is_onion() {
+        let net_address = NetAddress::try_from(format!("{}.onion:9735", ONION_V2)).unwrap();
+        assert!(net_address.is_onion());
+
+        let net_address = NetAddress::try_from(format!("{}.onion:9735", ONION_V3)).unwrap();
+        assert!(net_address.is_onion());
+
+        let net_address = NetAddress::try_from(format!("www.{}.ONION:443", ONION_V3)).unwrap();
+        assert!(net_address.is_onion());
+
+        let net_address = NetAddress::try_from("example.com:443".to_owned()).unwrap();
+        assert!(!net_address.is_onion());
+
+        let net_address = NetAddress::try_from("onion:443".to_owned()).unwrap();
+        assert!(!net_address.is_onion());
+    }
+
+    #[test]
+    fn test_net_address_invalid_onion() {
+        // Too short:
+        assert!(NetAddress::try_from("abcdefgh.onion:9735".to_owned()).is_err());
+        // Too long:
+        assert!(NetAddress::try_from(format!("a{}.onion:9735", ONION_V3)).is_err());
+        // Not base32:
+        assert!(NetAddress::try_from("expyuzz4wqqyqhj1.onion:9735".to_owned()).is_err());
+        // Empty name:
+        assert!(NetAddress::try_from(".onion:9735".to_owned()).is_err());
+    }
+}
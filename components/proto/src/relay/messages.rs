@@ -1,6 +1,6 @@
 use crypto::identity::PublicKey;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InitConnection {
     Listen,
     // remote side wants to accept a connection from public_key
@@ -9,12 +9,38 @@ pub enum InitConnection {
     Connect(PublicKey),
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct RejectConnection {
     pub public_key: PublicKey,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct IncomingConnection {
     pub public_key: PublicKey,
 }
+
+/// Messages sent from a listening client to the relay.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum RelayListenIn {
+    RejectConnection(RejectConnection),
+    /// Check that the relay is still alive. The relay responds with a Pong.
+    Ping,
+}
+
+/// Messages sent from the relay to a listening client.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum RelayListenOut {
+    IncomingConnection(IncomingConnection),
+    Pong,
+}
+
+/// Traffic statistics of a single client connection to the relay.
+/// `bytes_sent` and `messages_sent` count what the client sent through the relay,
+/// `bytes_received` counts what the relay delivered to the client.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct ConnectionStats {
+    pub connected_at_tick: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+}
@@ -5,7 +5,9 @@ use std::io;
 
 use relay_capnp;
 
-use super::messages::{IncomingConnection, InitConnection, RejectConnection};
+use super::messages::{
+    IncomingConnection, InitConnection, RejectConnection, RelayListenIn, RelayListenOut,
+};
 
 use crate::serialize::SerializeError;
 
@@ -92,9 +94,86 @@ pub fn deserialize_incoming_connection(data: &[u8]) -> Result<IncomingConnection
     Ok(IncomingConnection { public_key })
 }
 
+pub fn serialize_relay_listen_in(relay_listen_in: &RelayListenIn) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<relay_capnp::relay_listen_in::Builder>();
+
+    match relay_listen_in {
+        RelayListenIn::RejectConnection(reject_connection) => {
+            let reject_connection_builder = msg.init_reject_connection();
+            write_public_key(
+                &reject_connection.public_key,
+                &mut reject_connection_builder.init_public_key(),
+            );
+        }
+        RelayListenIn::Ping => msg.set_ping(()),
+    }
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_relay_listen_in(data: &[u8]) -> Result<RelayListenIn, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<relay_capnp::relay_listen_in::Reader>()?;
+
+    match msg.which() {
+        Ok(relay_capnp::relay_listen_in::RejectConnection(reject_connection)) => {
+            let public_key = read_public_key(&(reject_connection?.get_public_key()?))?;
+            Ok(RelayListenIn::RejectConnection(RejectConnection {
+                public_key,
+            }))
+        }
+        Ok(relay_capnp::relay_listen_in::Ping(())) => Ok(RelayListenIn::Ping),
+        Err(e) => Err(SerializeError::NotInSchema(e)),
+    }
+}
+
+pub fn serialize_relay_listen_out(relay_listen_out: &RelayListenOut) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<relay_capnp::relay_listen_out::Builder>();
+
+    match relay_listen_out {
+        RelayListenOut::IncomingConnection(incoming_connection) => {
+            let incoming_connection_builder = msg.init_incoming_connection();
+            write_public_key(
+                &incoming_connection.public_key,
+                &mut incoming_connection_builder.init_public_key(),
+            );
+        }
+        RelayListenOut::Pong => msg.set_pong(()),
+    }
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_relay_listen_out(data: &[u8]) -> Result<RelayListenOut, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<relay_capnp::relay_listen_out::Reader>()?;
+
+    match msg.which() {
+        Ok(relay_capnp::relay_listen_out::IncomingConnection(incoming_connection)) => {
+            let public_key = read_public_key(&(incoming_connection?.get_public_key()?))?;
+            Ok(RelayListenOut::IncomingConnection(IncomingConnection {
+                public_key,
+            }))
+        }
+        Ok(relay_capnp::relay_listen_out::Pong(())) => Ok(RelayListenOut::Pong),
+        Err(e) => Err(SerializeError::NotInSchema(e)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::serialize::write_fuzz_seed;
     use crypto::identity::PublicKey;
     use crypto::identity::PUBLIC_KEY_LEN;
     use std::convert::TryFrom;
@@ -103,18 +182,21 @@ mod tests {
     fn test_serialize_init_connection() {
         let msg = InitConnection::Listen;
         let serialized = serialize_init_connection(&msg);
+        write_fuzz_seed("init_connection", &serialized);
         let msg2 = deserialize_init_connection(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
 
         let public_key = PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap();
         let msg = InitConnection::Accept(public_key);
         let serialized = serialize_init_connection(&msg);
+        write_fuzz_seed("init_connection", &serialized);
         let msg2 = deserialize_init_connection(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
 
         let public_key = PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap();
         let msg = InitConnection::Connect(public_key);
         let serialized = serialize_init_connection(&msg);
+        write_fuzz_seed("init_connection", &serialized);
         let msg2 = deserialize_init_connection(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
     }
@@ -124,6 +206,7 @@ mod tests {
         let public_key = PublicKey::try_from(&[0x55u8; PUBLIC_KEY_LEN][..]).unwrap();
         let msg = RejectConnection { public_key };
         let serialized = serialize_reject_connection(&msg);
+        write_fuzz_seed("reject_connection", &serialized);
         let msg2 = deserialize_reject_connection(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
     }
@@ -133,7 +216,40 @@ mod tests {
         let public_key = PublicKey::try_from(&[0x55u8; PUBLIC_KEY_LEN][..]).unwrap();
         let msg = IncomingConnection { public_key };
         let serialized = serialize_incoming_connection(&msg);
+        write_fuzz_seed("incoming_connection", &serialized);
         let msg2 = deserialize_incoming_connection(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
     }
+
+    #[test]
+    fn test_serialize_relay_listen_in() {
+        let public_key = PublicKey::try_from(&[0x55u8; PUBLIC_KEY_LEN][..]).unwrap();
+        let msg = RelayListenIn::RejectConnection(RejectConnection { public_key });
+        let serialized = serialize_relay_listen_in(&msg);
+        write_fuzz_seed("relay_listen_in", &serialized);
+        let msg2 = deserialize_relay_listen_in(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+
+        let msg = RelayListenIn::Ping;
+        let serialized = serialize_relay_listen_in(&msg);
+        write_fuzz_seed("relay_listen_in", &serialized);
+        let msg2 = deserialize_relay_listen_in(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+    }
+
+    #[test]
+    fn test_serialize_relay_listen_out() {
+        let public_key = PublicKey::try_from(&[0x55u8; PUBLIC_KEY_LEN][..]).unwrap();
+        let msg = RelayListenOut::IncomingConnection(IncomingConnection { public_key });
+        let serialized = serialize_relay_listen_out(&msg);
+        write_fuzz_seed("relay_listen_out", &serialized);
+        let msg2 = deserialize_relay_listen_out(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+
+        let msg = RelayListenOut::Pong;
+        let serialized = serialize_relay_listen_out(&msg);
+        write_fuzz_seed("relay_listen_out", &serialized);
+        let msg2 = deserialize_relay_listen_out(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+    }
 }
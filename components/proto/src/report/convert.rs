@@ -31,6 +31,7 @@ where
 {
     if friend_report.status == FriendStatusReport::Disabled
         || friend_report.liveness == FriendLivenessReport::Offline
+        || friend_report.is_frozen
     {
         return (0, 0);
     }
@@ -138,8 +139,11 @@ where
         FunderReportMutation::AddRelay(_)
         | FunderReportMutation::RemoveRelay(_)
         | FunderReportMutation::SetNumOpenInvoices(_)
+        | FunderReportMutation::SetInvoiceProgress(_)
+        | FunderReportMutation::RemoveInvoiceProgress(_)
         | FunderReportMutation::SetNumPayments(_)
-        | FunderReportMutation::SetNumOpenTransactions(_) => None,
+        | FunderReportMutation::SetNumOpenTransactions(_)
+        | FunderReportMutation::SetTotalFrozenCredits(_) => None,
         FunderReportMutation::AddFriend(add_friend_report) => {
             create_update_friend(&add_friend_report.friend_public_key)
         }
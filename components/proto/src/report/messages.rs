@@ -2,17 +2,19 @@ use im::hashmap::HashMap as ImHashMap;
 use im::vector::Vector as ImVec;
 
 use common::mutable_state::MutableState;
+use common::safe_arithmetic::SafeUnsignedArithmetic;
 
 use crypto::crypto_rand::RandValue;
 use crypto::hash::HashResult;
 use crypto::identity::{PublicKey, Signature};
+use crypto::invoice_id::InvoiceId;
 use crypto::uid::Uid;
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
 use crate::funder::messages::{FriendStatus, Rate, RequestsStatus};
 use crate::net::messages::NetAddress;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MoveTokenHashedReport {
     pub prefix_hash: HashResult,
     pub local_public_key: PublicKey,
@@ -71,7 +73,7 @@ pub struct McBalanceReport {
     pub remote_pending_debt: u128,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DirectionReport {
     Incoming,
     Outgoing,
@@ -95,7 +97,7 @@ impl DirectionReport {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum FriendLivenessReport {
     Online,
     Offline,
@@ -111,7 +113,7 @@ impl FriendLivenessReport {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TcReport {
     pub direction: DirectionReport,
     pub balance: McBalanceReport,
@@ -120,30 +122,45 @@ pub struct TcReport {
     pub num_remote_pending_requests: u64,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResetTermsReport {
     pub reset_token: Signature,
     pub balance_for_reset: i128,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChannelInconsistentReport {
     pub local_reset_terms_balance: i128,
     pub opt_remote_reset_terms: Option<ResetTermsReport>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChannelStatusReport {
     Inconsistent(ChannelInconsistentReport),
     Consistent(TcReport),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Cumulative amounts of credits that were collected through the channel with a friend.
+/// Fees are the credits paid on top of the destination payment. They are split between the
+/// receiving side of the channel and the next nodes along the route.
+#[derive(Clone, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ChannelStatsReport {
+    pub total_sent: u128,
+    pub total_received: u128,
+    pub total_fees_sent: u128,
+    pub total_fees_received: u128,
+    pub num_transactions_sent: u64,
+    pub num_transactions_received: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FriendReport<B = NetAddress>
 where
     B: Clone,
 {
     pub name: String,
+    /// Free text notes about the friend, kept locally
+    pub note: String,
     pub rate: Rate,
     pub remote_relays: Vec<RelayAddress<B>>,
     pub sent_local_relays: SentLocalRelaysReport<B>,
@@ -154,6 +171,10 @@ where
     // Can we somehow express this in the type system?
     pub liveness: FriendLivenessReport, // is the friend online/offline?
     pub channel_status: ChannelStatusReport,
+    /// Credits collected through the channel, since it was created or last reset.
+    pub channel_stats: ChannelStatsReport,
+    /// Is routing through this friend temporarily stopped?
+    pub is_frozen: bool,
     pub wanted_remote_max_debt: u128,
     pub wanted_local_requests_status: RequestsStatusReport,
     pub num_pending_requests: u64,
@@ -165,9 +186,28 @@ where
     // but have not been processed yet. Bounded in size.
 }
 
+/// Progress of payment for a locally issued invoice
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvoiceProgressReport {
+    /// Credits collected so far by incoming transactions
+    pub collected: u128,
+    /// Total payment required to fulfill the invoice
+    pub total_dest_payment: u128,
+}
+
+impl InvoiceProgressReport {
+    /// Fraction of the invoice that was already paid, between 0.0 and 1.0.
+    pub fn payment_progress(&self) -> f64 {
+        if self.total_dest_payment == 0 {
+            return 0.0;
+        }
+        self.collected as f64 / self.total_dest_payment as f64
+    }
+}
+
 /// A FunderReport is a summary of a FunderState.
 /// It contains the information the Funder exposes to the user apps of the Offst node.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 // TODO: Removed A: Clone here and ImHashMap. Should this struct be cloneable for some reason?
 pub struct FunderReport<B = NetAddress>
 where
@@ -175,20 +215,31 @@ where
 {
     pub local_public_key: PublicKey,
     pub relays: ImVec<NamedRelayAddress<B>>,
+    #[serde(with = "crate::serde_utils::map_as_pairs")]
     pub friends: ImHashMap<PublicKey, FriendReport<B>>,
     pub num_open_invoices: u64,
+    /// Payment progress for every open invoice
+    #[serde(with = "crate::serde_utils::map_as_pairs")]
+    pub invoices_progress: ImHashMap<InvoiceId, InvoiceProgressReport>,
     pub num_payments: u64,
     pub num_open_transactions: u64,
+    /// Credits frozen in pending requests over all friends: (local_frozen, remote_frozen)
+    pub total_frozen_credits: (u128, u128),
+    /// Maximum amount of friends this node may have
+    pub max_friends: u64,
+    /// Maximum amount of relays this node may use
+    pub max_node_relays: u64,
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FriendReportMutation<B = NetAddress>
 where
     B: Clone,
 {
     SetRemoteRelays(Vec<RelayAddress<B>>),
     SetName(String),
+    SetNote(String),
     SetRate(Rate),
     SetSentLocalRelays(SentLocalRelaysReport<B>),
     SetChannelStatus(ChannelStatusReport),
@@ -200,12 +251,15 @@ where
     SetNumPendingUserRequests(u64),
     SetOptLastIncomingMoveToken(Option<MoveTokenHashedReport>),
     SetLiveness(FriendLivenessReport),
+    SetChannelStats(ChannelStatsReport),
+    SetFrozen(bool),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AddFriendReport<B = NetAddress> {
     pub friend_public_key: PublicKey,
     pub name: String,
+    pub note: String,
     pub relays: Vec<RelayAddress<B>>,
     pub balance: i128, // Initial balance
     pub opt_last_incoming_move_token: Option<MoveTokenHashedReport>,
@@ -213,7 +267,7 @@ pub struct AddFriendReport<B = NetAddress> {
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum FunderReportMutation<B = NetAddress>
 where
     B: Clone,
@@ -224,11 +278,14 @@ where
     RemoveFriend(PublicKey),
     FriendReportMutation((PublicKey, FriendReportMutation<B>)),
     SetNumOpenInvoices(u64),
+    SetInvoiceProgress((InvoiceId, InvoiceProgressReport)),
+    RemoveInvoiceProgress(InvoiceId),
     SetNumPayments(u64),
     SetNumOpenTransactions(u64),
+    SetTotalFrozenCredits((u128, u128)),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FunderReportMutations<B: Clone> {
     pub opt_app_request_id: Option<Uid>,
     pub mutations: Vec<FunderReportMutation<B>>,
@@ -252,6 +309,41 @@ impl From<&RequestsStatus> for RequestsStatusReport {
     }
 }
 
+impl<B> FriendReport<B>
+where
+    B: Clone,
+{
+    /// Check if this friend can be used as the first hop of a route that needs to freeze
+    /// `required_capacity` credits: The friend is enabled and not frozen, the channel is
+    /// consistent, the remote side has open requests, and we have enough credits left to freeze.
+    /// The funder's `FriendState::is_good_for_routing()` is based on this method.
+    pub fn is_good_for_routing(&self, required_capacity: u128) -> bool {
+        if let FriendStatusReport::Disabled = self.status {
+            return false;
+        }
+
+        if self.is_frozen {
+            return false;
+        }
+
+        let tc_report = match &self.channel_status {
+            ChannelStatusReport::Inconsistent(_) => return false,
+            ChannelStatusReport::Consistent(tc_report) => tc_report,
+        };
+
+        if let RequestsStatusReport::Closed = tc_report.requests_status.remote {
+            return false;
+        }
+
+        let balance = &tc_report.balance;
+        let headroom = balance
+            .local_max_debt
+            .saturating_add_signed(balance.balance)
+            .saturating_sub(balance.local_pending_debt);
+        headroom >= required_capacity
+    }
+}
+
 #[derive(Debug)]
 pub enum FunderReportMutateError {
     FriendDoesNotExist,
@@ -270,6 +362,9 @@ where
             FriendReportMutation::SetName(name) => {
                 self.name = name.clone();
             }
+            FriendReportMutation::SetNote(note) => {
+                self.note = note.clone();
+            }
             FriendReportMutation::SetRate(rate) => {
                 self.rate = rate.clone();
             }
@@ -306,11 +401,32 @@ where
             FriendReportMutation::SetLiveness(friend_liveness_report) => {
                 self.liveness = friend_liveness_report.clone();
             }
+            FriendReportMutation::SetChannelStats(channel_stats_report) => {
+                self.channel_stats = channel_stats_report.clone();
+            }
+            FriendReportMutation::SetFrozen(is_frozen) => {
+                self.is_frozen = *is_frozen;
+            }
         };
         Ok(())
     }
 }
 
+impl<B> FunderReport<B>
+where
+    B: Clone,
+{
+    /// Current amount of friends
+    pub fn num_friends(&self) -> usize {
+        self.friends.len()
+    }
+
+    /// Current amount of relays
+    pub fn num_relays(&self) -> usize {
+        self.relays.len()
+    }
+}
+
 impl<B> MutableState for FunderReport<B>
 where
     B: Clone,
@@ -338,6 +454,7 @@ where
             FunderReportMutation::AddFriend(add_friend_report) => {
                 let friend_report = FriendReport {
                     name: add_friend_report.name.clone(),
+                    note: add_friend_report.note.clone(),
                     rate: Rate::new(),
                     remote_relays: add_friend_report.relays.clone(),
                     sent_local_relays: SentLocalRelaysReport::NeverSent,
@@ -354,6 +471,8 @@ where
                     num_pending_requests: 0,
                     status: FriendStatusReport::from(&FriendStatus::Disabled),
                     num_pending_user_requests: 0,
+                    channel_stats: ChannelStatsReport::default(),
+                    is_frozen: false,
                 };
                 if self
                     .friends
@@ -387,6 +506,16 @@ where
                 self.num_open_invoices = *num_open_invoices;
                 Ok(())
             }
+            FunderReportMutation::SetInvoiceProgress((invoice_id, invoice_progress)) => {
+                let _ = self
+                    .invoices_progress
+                    .insert(invoice_id.clone(), invoice_progress.clone());
+                Ok(())
+            }
+            FunderReportMutation::RemoveInvoiceProgress(invoice_id) => {
+                let _ = self.invoices_progress.remove(invoice_id);
+                Ok(())
+            }
             FunderReportMutation::SetNumPayments(num_payments) => {
                 self.num_payments = *num_payments;
                 Ok(())
@@ -395,6 +524,10 @@ where
                 self.num_open_transactions = *num_open_transactions;
                 Ok(())
             }
+            FunderReportMutation::SetTotalFrozenCredits(total_frozen_credits) => {
+                self.total_frozen_credits = *total_frozen_credits;
+                Ok(())
+            }
         }
     }
 }
@@ -1,12 +1,14 @@
+use std::collections::HashMap;
+
 use im::hashmap::HashMap as ImHashMap;
 use im::vector::Vector as ImVec;
 
 use crate::capnp_common::{
-    read_custom_int128, read_custom_u_int128, read_hash, read_named_index_server_address,
-    read_named_relay_address, read_public_key, read_rand_nonce, read_relay_address, read_signature,
-    write_custom_int128, write_custom_u_int128, write_hash, write_named_index_server_address,
-    write_named_relay_address, write_public_key, write_rand_nonce, write_relay_address,
-    write_signature,
+    check_list_len, read_custom_int128, read_custom_u_int128, read_hash,
+    read_named_index_server_address, read_named_relay_address, read_public_key, read_rand_nonce,
+    read_relay_address, read_signature, read_string, write_custom_int128, write_custom_u_int128,
+    write_hash, write_named_index_server_address, write_named_relay_address, write_public_key,
+    write_rand_nonce, write_relay_address, write_signature,
 };
 use common::int_convert::usize_to_u32;
 use crypto::identity::PublicKey;
@@ -22,7 +24,9 @@ use report_capnp;
 
 use crate::app_server::messages::NamedRelayAddress;
 use crate::app_server::messages::{NodeReport, NodeReportMutation};
-use crate::index_client::messages::{IndexClientReport, IndexClientReportMutation};
+use crate::index_client::messages::{
+    CircuitStateReport, IndexClientReport, IndexClientReportMutation,
+};
 use crate::net::messages::NetAddress;
 
 fn ser_move_token_hashed_report(
@@ -481,12 +485,16 @@ fn deser_relays_transition(
     relays_transition_reader: &report_capnp::relays_transition::Reader,
 ) -> Result<RelaysTransitions, SerializeError> {
     let mut last_sent = ImVec::new();
-    for named_relay_address in relays_transition_reader.get_last_sent()? {
+    let last_sent_reader = relays_transition_reader.get_last_sent()?;
+    check_list_len(last_sent_reader.len())?;
+    for named_relay_address in last_sent_reader {
         last_sent.push_back(read_named_relay_address(&named_relay_address)?);
     }
 
     let mut before_last_sent = ImVec::new();
-    for named_relay_address in relays_transition_reader.get_before_last_sent()? {
+    let before_last_sent_reader = relays_transition_reader.get_before_last_sent()?;
+    check_list_len(before_last_sent_reader.len())?;
+    for named_relay_address in before_last_sent_reader {
         before_last_sent.push_back(read_named_relay_address(&named_relay_address)?);
     }
 
@@ -532,7 +540,9 @@ fn deser_sent_local_relays_report(
         }
         report_capnp::sent_local_relays_report::LastSent(last_sent_reader) => {
             let mut last_sent = Vec::new();
-            for named_relay_address in last_sent_reader? {
+            let last_sent_reader = last_sent_reader?;
+            check_list_len(last_sent_reader.len())?;
+            for named_relay_address in last_sent_reader {
                 last_sent.push(read_named_relay_address(&named_relay_address)?);
             }
             SentLocalRelaysReport::LastSent(last_sent.into_iter().collect())
@@ -550,6 +560,9 @@ fn ser_friend_report(
     friend_report_builder
         .reborrow()
         .set_name(&friend_report.name);
+    friend_report_builder
+        .reborrow()
+        .set_note(&friend_report.note);
 
     // remote_relays:
     let relays_len = usize_to_u32(friend_report.remote_relays.len()).unwrap();
@@ -615,12 +628,15 @@ fn deser_friend_report(
     unimplemented!();
     /*
     let mut remote_relays = Vec::new();
-    for relay_address in friend_report_reader.get_remote_relays()? {
+    let remote_relays_reader = friend_report_reader.get_remote_relays()?;
+    check_list_len(remote_relays_reader.len())?;
+    for relay_address in remote_relays_reader {
         remote_relays.push(read_relay_address(&relay_address)?);
     }
 
     Ok(FriendReport {
-        name: friend_report_reader.get_name()?.to_owned(),
+        name: read_string(friend_report_reader.get_name()?)?,
+        note: read_string(friend_report_reader.get_note()?)?,
         remote_relays,
         sent_local_relays: deser_sent_local_relays_report(
             &friend_report_reader.get_sent_local_relays()?,
@@ -704,12 +720,16 @@ fn deser_funder_report(
     unimplemented!();
     /*
     let mut named_relays = Vec::new();
-    for named_relay_address in funder_report_reader.get_relays()? {
+    let relays_reader = funder_report_reader.get_relays()?;
+    check_list_len(relays_reader.len())?;
+    for named_relay_address in relays_reader {
         named_relays.push(read_named_relay_address(&named_relay_address)?);
     }
 
     let mut friends = ImHashMap::new();
-    for pk_friend in funder_report_reader.get_friends()? {
+    let friends_reader = funder_report_reader.get_friends()?;
+    check_list_len(friends_reader.len())?;
+    for pk_friend in friends_reader {
         let (friend_public_key, friend_report) = deser_pk_friend_report(&pk_friend)?;
         friends.insert(friend_public_key, friend_report);
     }
@@ -735,6 +755,7 @@ fn ser_add_friend_report(
     );
 
     add_friend_report_builder.set_name(&add_friend_report.name);
+    add_friend_report_builder.set_note(&add_friend_report.note);
 
     let relays_len = usize_to_u32(add_friend_report.relays.len()).unwrap();
     let mut relays_builder = add_friend_report_builder.reborrow().init_relays(relays_len);
@@ -765,13 +786,16 @@ fn deser_add_friend_report(
     add_friend_report_reader: &report_capnp::add_friend_report::Reader,
 ) -> Result<AddFriendReport, SerializeError> {
     let mut relays = Vec::new();
-    for relay_address in add_friend_report_reader.get_relays()? {
+    let relays_reader = add_friend_report_reader.get_relays()?;
+    check_list_len(relays_reader.len())?;
+    for relay_address in relays_reader {
         relays.push(read_relay_address(&relay_address)?);
     }
 
     Ok(AddFriendReport {
         friend_public_key: read_public_key(&add_friend_report_reader.get_friend_public_key()?)?,
-        name: add_friend_report_reader.get_name()?.to_owned(),
+        name: read_string(add_friend_report_reader.get_name()?)?,
+        note: read_string(add_friend_report_reader.get_note()?)?,
         relays,
         balance: read_custom_int128(&add_friend_report_reader.get_balance()?)?,
         opt_last_incoming_move_token: deser_opt_last_incoming_move_token(
@@ -804,6 +828,9 @@ fn ser_friend_report_mutation(
         FriendReportMutation::SetName(name) => {
             friend_report_mutation_builder.reborrow().set_set_name(name)
         }
+        FriendReportMutation::SetNote(note) => {
+            friend_report_mutation_builder.reborrow().set_set_note(note)
+        }
         FriendReportMutation::SetSentLocalRelays(sent_local_relays_report) => {
             ser_sent_local_relays_report(
                 sent_local_relays_report,
@@ -880,13 +907,18 @@ fn deser_friend_report_mutation(
     Ok(match friend_report_mutation.which()? {
         report_capnp::friend_report_mutation::SetRemoteRelays(relays_reader) => {
             let mut relays = Vec::new();
-            for relay_address in relays_reader? {
+            let relays_reader = relays_reader?;
+            check_list_len(relays_reader.len())?;
+            for relay_address in relays_reader {
                 relays.push(read_relay_address(&relay_address)?);
             }
             FriendReportMutation::SetRemoteRelays(relays)
         }
         report_capnp::friend_report_mutation::SetName(name) => {
-            FriendReportMutation::SetName(name?.to_owned())
+            FriendReportMutation::SetName(read_string(name?)?)
+        }
+        report_capnp::friend_report_mutation::SetNote(note) => {
+            FriendReportMutation::SetNote(read_string(note?)?)
         }
         report_capnp::friend_report_mutation::SetSentLocalRelays(
             sent_local_relays_report_reader,
@@ -1046,6 +1078,53 @@ fn deser_funder_report_mutation(
     */
 }
 
+fn ser_circuit_state_report(
+    circuit_state_report: &CircuitStateReport,
+    circuit_state_report_builder: &mut report_capnp::circuit_state_report::Builder,
+) {
+    match circuit_state_report {
+        CircuitStateReport::Closed => circuit_state_report_builder.set_closed(()),
+        CircuitStateReport::Open => circuit_state_report_builder.set_open(()),
+        CircuitStateReport::HalfOpen => circuit_state_report_builder.set_half_open(()),
+    }
+}
+
+fn deser_circuit_state_report(
+    circuit_state_report_reader: &report_capnp::circuit_state_report::Reader,
+) -> Result<CircuitStateReport, SerializeError> {
+    Ok(match circuit_state_report_reader.which()? {
+        report_capnp::circuit_state_report::Closed(()) => CircuitStateReport::Closed,
+        report_capnp::circuit_state_report::Open(()) => CircuitStateReport::Open,
+        report_capnp::circuit_state_report::HalfOpen(()) => CircuitStateReport::HalfOpen,
+    })
+}
+
+fn ser_pk_circuit_state_report(
+    public_key: &PublicKey,
+    circuit_state_report: &CircuitStateReport,
+    pk_circuit_state_report_builder: &mut report_capnp::pk_circuit_state_report::Builder,
+) {
+    write_public_key(
+        public_key,
+        &mut pk_circuit_state_report_builder.reborrow().init_public_key(),
+    );
+    ser_circuit_state_report(
+        circuit_state_report,
+        &mut pk_circuit_state_report_builder
+            .reborrow()
+            .init_circuit_state(),
+    );
+}
+
+fn deser_pk_circuit_state_report(
+    pk_circuit_state_report_reader: &report_capnp::pk_circuit_state_report::Reader,
+) -> Result<(PublicKey, CircuitStateReport), SerializeError> {
+    Ok((
+        read_public_key(&pk_circuit_state_report_reader.get_public_key()?)?,
+        deser_circuit_state_report(&pk_circuit_state_report_reader.get_circuit_state()?)?,
+    ))
+}
+
 fn ser_index_client_report(
     index_client_report: &IndexClientReport<NetAddress>,
     index_client_report_builder: &mut report_capnp::index_client_report::Builder,
@@ -1075,13 +1154,32 @@ fn ser_index_client_report(
             opt_connected_server_builder.set_empty(());
         }
     }
+
+    let circuit_states_len = usize_to_u32(index_client_report.circuit_states.len()).unwrap();
+    let mut circuit_states_builder = index_client_report_builder
+        .reborrow()
+        .init_circuit_states(circuit_states_len);
+    for (index, (public_key, circuit_state_report)) in
+        index_client_report.circuit_states.iter().enumerate()
+    {
+        let mut pk_circuit_state_report_builder = circuit_states_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_pk_circuit_state_report(
+            public_key,
+            circuit_state_report,
+            &mut pk_circuit_state_report_builder,
+        );
+    }
 }
 
 fn deser_index_client_report(
     index_client_report_reader: &report_capnp::index_client_report::Reader,
 ) -> Result<IndexClientReport<NetAddress>, SerializeError> {
     let mut index_servers = Vec::new();
-    for named_index_server_reader in index_client_report_reader.get_index_servers()? {
+    let index_servers_reader = index_client_report_reader.get_index_servers()?;
+    check_list_len(index_servers_reader.len())?;
+    for named_index_server_reader in index_servers_reader {
         index_servers.push(read_named_index_server_address(&named_index_server_reader)?);
     }
 
@@ -1095,9 +1193,19 @@ fn deser_index_client_report(
         report_capnp::index_client_report::opt_connected_server::Empty(()) => None,
     };
 
+    let mut circuit_states = HashMap::new();
+    let circuit_states_reader = index_client_report_reader.get_circuit_states()?;
+    check_list_len(circuit_states_reader.len())?;
+    for pk_circuit_state_report_reader in circuit_states_reader {
+        let (public_key, circuit_state_report) =
+            deser_pk_circuit_state_report(&pk_circuit_state_report_reader)?;
+        circuit_states.insert(public_key, circuit_state_report);
+    }
+
     Ok(IndexClientReport {
         index_servers,
         opt_connected_server,
+        circuit_states,
     })
 }
 
@@ -1132,6 +1240,15 @@ fn ser_index_client_report_mutation(
                 None => set_connected_server_builder.set_empty(()),
             }
         }
+        IndexClientReportMutation::SetCircuitState((public_key, circuit_state_report)) => {
+            ser_pk_circuit_state_report(
+                public_key,
+                circuit_state_report,
+                &mut index_client_report_mutation_builder
+                    .reborrow()
+                    .init_set_circuit_state(),
+            )
+        }
     }
 }
 
@@ -1159,6 +1276,11 @@ fn deser_index_client_report_mutation(
                 IndexClientReportMutation::SetConnectedServer(None)
             }
         },
+        report_capnp::index_client_report_mutation::SetCircuitState(
+            pk_circuit_state_report_reader,
+        ) => IndexClientReportMutation::SetCircuitState(deser_pk_circuit_state_report(
+            &pk_circuit_state_report_reader?,
+        )?),
     })
 }
 
@@ -1,6 +1,7 @@
 use crypto::crypto_rand::RandValue;
 use crypto::dh::{DhPublicKey, Salt};
 use crypto::identity::{PublicKey, Signature};
+use crypto::sym_encrypt::SymEncryptAlgorithm;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct EncryptedData(pub Vec<u8>);
@@ -21,6 +22,8 @@ pub struct ExchangeDh {
     pub rand_nonce: RandValue,
     pub key_salt: Salt,
     pub signature: Signature,
+    /// Symmetric encryption algorithm preferred by the sender
+    pub sym_encrypt_algorithm: SymEncryptAlgorithm,
 }
 
 impl ExchangeDh {
@@ -29,6 +32,11 @@ impl ExchangeDh {
         sbuffer.extend_from_slice(&self.dh_public_key);
         sbuffer.extend_from_slice(&self.rand_nonce);
         sbuffer.extend_from_slice(&self.key_salt);
+        // The algorithm is signed, so that it can not be downgraded by an attacker:
+        sbuffer.push(match self.sym_encrypt_algorithm {
+            SymEncryptAlgorithm::ChaCha20Poly1305 => 0,
+            SymEncryptAlgorithm::Aes128Gcm => 1,
+        });
         sbuffer
     }
 }
@@ -3,6 +3,8 @@ use capnp::serialize_packed;
 use dh_capnp;
 use std::io;
 
+use crypto::sym_encrypt::SymEncryptAlgorithm;
+
 use crate::capnp_common::{
     read_dh_public_key, read_public_key, read_rand_nonce, read_salt, read_signature,
     write_dh_public_key, write_public_key, write_rand_nonce, write_salt, write_signature,
@@ -67,6 +69,11 @@ pub fn serialize_exchange_dh(exchange_dh: &ExchangeDh) -> Vec<u8> {
         &exchange_dh.signature,
         &mut msg.reborrow().get_signature().unwrap(),
     );
+    let mut sym_encrypt_algorithm = msg.reborrow().init_sym_encrypt_algorithm();
+    match exchange_dh.sym_encrypt_algorithm {
+        SymEncryptAlgorithm::ChaCha20Poly1305 => sym_encrypt_algorithm.set_cha_cha20_poly1305(()),
+        SymEncryptAlgorithm::Aes128Gcm => sym_encrypt_algorithm.set_aes128_gcm(()),
+    };
 
     let mut serialized_msg = Vec::new();
     serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
@@ -83,12 +90,20 @@ pub fn deserialize_exchange_dh(data: &[u8]) -> Result<ExchangeDh, SerializeError
     let rand_nonce = read_rand_nonce(&msg.get_rand_nonce()?)?;
     let key_salt = read_salt(&msg.get_key_salt()?)?;
     let signature = read_signature(&msg.get_signature()?)?;
+    let sym_encrypt_algorithm = match msg.get_sym_encrypt_algorithm()?.which() {
+        Ok(dh_capnp::sym_encrypt_algorithm::ChaCha20Poly1305(())) => {
+            SymEncryptAlgorithm::ChaCha20Poly1305
+        }
+        Ok(dh_capnp::sym_encrypt_algorithm::Aes128Gcm(())) => SymEncryptAlgorithm::Aes128Gcm,
+        Err(e) => return Err(SerializeError::NotInSchema(e)),
+    };
 
     Ok(ExchangeDh {
         dh_public_key,
         rand_nonce,
         key_salt,
         signature,
+        sym_encrypt_algorithm,
     })
 }
 
@@ -154,6 +169,7 @@ pub fn deserialize_channel_message(data: &[u8]) -> Result<ChannelMessage, Serial
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::serialize::write_fuzz_seed;
     use crypto::crypto_rand::RandValue;
     use crypto::crypto_rand::RAND_VALUE_LEN;
     use crypto::dh::{DhPublicKey, Salt};
@@ -169,6 +185,7 @@ mod tests {
             public_key: PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap(),
         };
         let serialized = serialize_exchange_rand_nonce(&msg);
+        write_fuzz_seed("exchange_rand_nonce", &serialized);
         let msg2 = deserialize_exchange_rand_nonce(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
     }
@@ -180,8 +197,10 @@ mod tests {
             rand_nonce: RandValue::try_from(&[0x02u8; RAND_VALUE_LEN][..]).unwrap(),
             key_salt: Salt::try_from(&[0x03u8; SALT_LEN][..]).unwrap(),
             signature: Signature::try_from(&[0x03u8; SIGNATURE_LEN][..]).unwrap(),
+            sym_encrypt_algorithm: SymEncryptAlgorithm::Aes128Gcm,
         };
         let serialized = serialize_exchange_dh(&msg);
+        write_fuzz_seed("exchange_dh", &serialized);
         let msg2 = deserialize_exchange_dh(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
     }
@@ -198,6 +217,7 @@ mod tests {
             content,
         };
         let serialized = serialize_channel_message(&msg);
+        write_fuzz_seed("channel_message", &serialized);
         let msg2 = deserialize_channel_message(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
     }
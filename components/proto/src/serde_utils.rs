@@ -0,0 +1,66 @@
+/// Serialize a map as a sequence of (key, value) pairs.
+///
+/// Some formats (For example, JSON) only allow strings as map keys. Using this module
+/// allows maps with other keys (Like `PublicKey`) to be serialized into such formats.
+///
+/// Usage: `#[serde(with = "crate::serde_utils::map_as_pairs")]`
+pub mod map_as_pairs {
+    use std::iter::FromIterator;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<M, K, V, S>(map: &M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        for<'a> &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: IntoIterator<Item = (K, V)> + FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs = Vec::<(K, V)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+/// Serialize an optional map as an optional sequence of (key, value) pairs.
+/// See `map_as_pairs`.
+///
+/// Usage: `#[serde(with = "crate::serde_utils::opt_map_as_pairs")]`
+pub mod opt_map_as_pairs {
+    use std::iter::FromIterator;
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<M, K, V, S>(opt_map: &Option<M>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        for<'a> &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        let opt_pairs = opt_map
+            .as_ref()
+            .map(|map| map.into_iter().collect::<Vec<_>>());
+        opt_pairs.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<Option<M>, D::Error>
+    where
+        M: IntoIterator<Item = (K, V)> + FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let opt_pairs = Option::<Vec<(K, V)>>::deserialize(deserializer)?;
+        Ok(opt_pairs.map(|pairs| pairs.into_iter().collect()))
+    }
+}
@@ -8,4 +8,33 @@ pub enum SerializeError {
     NotInSchema(capnp::NotInSchema),
     IoError(io::Error),
     NetAddressError(NetAddressError),
+    ListTooLong,
+    StringTooLong,
+}
+
+/// Setting this environment variable while running the tests adds the messages serialized by
+/// the tests to the seed corpus of the fuzz targets (See `fuzz/README.md`).
+#[cfg(test)]
+pub const FUZZ_SEED_CORPUS_ENV: &str = "OFFST_FUZZ_SEED_CORPUS";
+
+/// Add a serialized message to the seed corpus of the fuzz target `fuzz_target`
+/// (`fuzz/corpus/<fuzz_target>/`). Does nothing unless `OFFST_FUZZ_SEED_CORPUS` is set.
+#[cfg(test)]
+pub fn write_fuzz_seed(fuzz_target: &str, data: &[u8]) {
+    use crypto::hash::sha_512_256;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    if env::var_os(FUZZ_SEED_CORPUS_ENV).is_none() {
+        return;
+    }
+
+    let corpus_dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz")
+        .join("corpus")
+        .join(fuzz_target);
+    fs::create_dir_all(&corpus_dir).unwrap();
+    // Name seeds by their content, the same way libFuzzer names corpus entries:
+    fs::write(corpus_dir.join(sha_512_256(data).to_hex()), data).unwrap();
 }
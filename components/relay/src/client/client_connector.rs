@@ -2,6 +2,10 @@ use crypto::identity::PublicKey;
 use futures::{FutureExt, SinkExt};
 
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use common::int_convert::u32_to_usize;
+
+use timer::utils::sleep_ticks;
+use timer::TimerClient;
 
 use proto::relay::messages::InitConnection;
 use proto::relay::serialize::serialize_init_connection;
@@ -10,6 +14,8 @@ use proto::relay::serialize::serialize_init_connection;
 pub enum ClientConnectorError {
     InnerConnectorError,
     SendInitConnectionError,
+    RequestTimerStreamError,
+    AllRetriesExhausted,
 }
 
 /// ClientConnector is an end-to-end connector to a remote node.
@@ -58,6 +64,44 @@ where
 
         Ok((user_to_tunnel, user_from_tunnel))
     }
+
+    /// Connect to a remote node through one of the given relays.
+    /// Relays are tried in a cyclic order, up to `max_retries` attempts in total.
+    /// We wait `retry_delay_ticks` between two consecutive attempts.
+    pub async fn connect_with_retry(
+        &mut self,
+        relays: Vec<A>,
+        remote_public_key: PublicKey,
+        max_retries: u32,
+        retry_delay_ticks: usize,
+        timer_client: TimerClient,
+    ) -> Result<ConnPairVec, ClientConnectorError>
+    where
+        A: Clone,
+    {
+        if relays.is_empty() {
+            return Err(ClientConnectorError::AllRetriesExhausted);
+        }
+
+        for attempt in 0..u32_to_usize(max_retries).unwrap() {
+            if attempt > 0 && retry_delay_ticks > 0 {
+                await!(sleep_ticks(retry_delay_ticks, timer_client.clone()))
+                    .map_err(|_| ClientConnectorError::RequestTimerStreamError)?;
+            }
+
+            let relay_address = relays[attempt % relays.len()].clone();
+            match await!(self.relay_connect(relay_address, remote_public_key.clone())) {
+                Ok(conn_pair) => return Ok(conn_pair),
+                Err(e) => warn!(
+                    "connect_with_retry(): Attempt {} to connect to {} failed: {:?}",
+                    attempt,
+                    remote_public_key.to_hex(),
+                    e
+                ),
+            }
+        }
+        Err(ClientConnectorError::AllRetriesExhausted)
+    }
 }
 
 impl<A, C, FT> FutTransform for ClientConnector<C, FT>
@@ -92,6 +136,8 @@ mod tests {
     use common::conn::FuncFutTransform;
     use common::dummy_connector::DummyConnector;
 
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
     async fn task_client_connector_basic(mut spawner: impl Spawn + Clone + Sync + Send + 'static) {
         let (local_sender, mut relay_receiver) = mpsc::channel::<Vec<u8>>(0);
         let (mut relay_sender, local_receiver) = mpsc::channel::<Vec<u8>>(0);
@@ -139,4 +185,102 @@ mod tests {
         let mut thread_pool = ThreadPool::new().unwrap();
         thread_pool.run(task_client_connector_basic(thread_pool.clone()));
     }
+
+    async fn task_client_connector_connect_with_retry(
+        mut spawner: impl Spawn + Clone + Sync + Send + 'static,
+    ) {
+        let (local_sender, mut relay_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (_relay_sender, local_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let conn_pair = (local_sender, local_receiver);
+
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(req_sender);
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+        let mut client_connector = ClientConnector::new(connector, keepalive_transform);
+
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let relays: Vec<u32> = vec![10, 11, 12];
+        let public_key = PublicKey::from(&[0x77; PUBLIC_KEY_LEN]);
+        let c_public_key = public_key.clone();
+        let fut_conn_pair = spawner
+            .spawn_with_handle(async move {
+                await!(client_connector.connect_with_retry(
+                    relays,
+                    c_public_key,
+                    6,
+                    1,
+                    timer_client
+                ))
+            })
+            .unwrap();
+
+        // The first 5 attempts fail:
+        for attempt in 0..5 {
+            let req = await!(req_receiver.next()).unwrap();
+            assert_eq!(req.address, 10 + (attempt % 3));
+            req.reply(None);
+
+            // Let the retry delay pass:
+            let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+            await!(tick_sender.send(TimerTick)).unwrap();
+        }
+
+        // The 6th attempt succeeds:
+        let req = await!(req_receiver.next()).unwrap();
+        assert_eq!(req.address, 12);
+        req.reply(Some(conn_pair));
+
+        assert!(await!(fut_conn_pair).is_ok());
+        let vec = await!(relay_receiver.next()).unwrap();
+        match deserialize_init_connection(&vec).unwrap() {
+            InitConnection::Connect(conn_public_key) => assert_eq!(conn_public_key, public_key),
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_client_connector_connect_with_retry() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_client_connector_connect_with_retry(
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_client_connector_connect_with_retry_exhausted(
+        mut spawner: impl Spawn + Clone + Sync + Send + 'static,
+    ) {
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(req_sender);
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+        let mut client_connector = ClientConnector::new(connector, keepalive_transform);
+
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let relays: Vec<u32> = vec![10, 11];
+        let public_key = PublicKey::from(&[0x77; PUBLIC_KEY_LEN]);
+        let fut_res = spawner
+            .spawn_with_handle(async move {
+                await!(client_connector.connect_with_retry(relays, public_key, 3, 0, timer_client))
+            })
+            .unwrap();
+
+        for _ in 0..3 {
+            let req = await!(req_receiver.next()).unwrap();
+            req.reply(None);
+        }
+
+        match await!(fut_res) {
+            Err(ClientConnectorError::AllRetriesExhausted) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_client_connector_connect_with_retry_exhausted() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_client_connector_connect_with_retry_exhausted(
+            thread_pool.clone(),
+        ));
+    }
 }
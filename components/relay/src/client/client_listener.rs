@@ -1,20 +1,25 @@
 use std::marker::Unpin;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, select, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
 
 use common::conn::{ConnPairVec, ConstFutTransform, FutTransform, Listener};
-use common::int_convert::usize_to_u64;
 use crypto::identity::PublicKey;
-use proto::relay::messages::{IncomingConnection, InitConnection, RejectConnection};
+use proto::file::access_control::{load_access_control_from_file, store_access_control_to_file};
+use proto::relay::messages::{
+    IncomingConnection, InitConnection, RejectConnection, RelayListenIn, RelayListenOut,
+};
 use proto::relay::serialize::{
-    deserialize_incoming_connection, serialize_init_connection, serialize_reject_connection,
+    deserialize_relay_listen_out, serialize_init_connection, serialize_relay_listen_in,
 };
 
 use common::access_control::{AccessControl, AccessControlOp};
 use common::select_streams::{select_streams, BoxStream};
-use timer::{TimerClient, TimerTick};
+use timer::{TimerClient, TimerClientError};
 
 type AccessControlPk = AccessControl<PublicKey>;
 type AccessControlOpPk = AccessControlOp<PublicKey>;
@@ -27,6 +32,19 @@ pub enum ClientListenerError {
     SendToServerError,
     // ServerClosed,
     SpawnError,
+    RequestTimerStreamError,
+    PongTimeout,
+}
+
+/// Configuration for pinging the relay over the listen connection.
+/// Allows to detect a dead connection to the relay even if we have nothing to send.
+#[derive(Debug, Clone)]
+pub struct PingConfig {
+    /// Amount of ticks between two pings.
+    pub ping_interval_ticks: usize,
+    /// Amount of ticks we are willing to wait for a pong before the connection is declared
+    /// dead.
+    pub pong_timeout_ticks: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -34,8 +52,10 @@ enum ClientListenerEvent {
     AccessControlOp(AccessControlOpPk),
     AccessControlClosed,
     ServerMessage(IncomingConnection),
+    Pong,
     ServerClosed,
     PendingReject(PublicKey),
+    TimerTick,
 }
 
 #[derive(Debug)]
@@ -79,28 +99,24 @@ where
 }
 */
 
-async fn connect_with_timeout<C, TS>(
+async fn connect_with_timeout<C>(
     mut connector: C,
     conn_timeout_ticks: usize,
-    timer_stream: TS,
-) -> Option<ConnPairVec>
+    timer_client: TimerClient,
+) -> Result<Option<ConnPairVec>, TimerClientError>
 where
     C: FutTransform<Input = (), Output = Option<ConnPairVec>> + Send,
-    TS: Stream<Item = TimerTick> + Unpin,
 {
-    let conn_timeout_ticks = usize_to_u64(conn_timeout_ticks).unwrap();
-    let mut fut_timeout = timer_stream
-        .take(conn_timeout_ticks)
-        .for_each(|_| future::ready(()))
-        .fuse();
+    let mut fut_timeout = timer_client.request_timeout(conn_timeout_ticks).fuse();
     let mut fut_connect = connector.transform(()).fuse();
 
     select! {
-        _fut_timeout = fut_timeout => {
+        res_timeout = fut_timeout => {
+            res_timeout?;
             warn!("connection_with_timeout(): Timeout occurred during connection attempt");
-            None
+            Ok(None)
         },
-        fut_connect = fut_connect => fut_connect,
+        fut_connect = fut_connect => Ok(fut_connect),
     }
 }
 
@@ -111,20 +127,19 @@ async fn accept_connection<C, CS, CSE, FT>(
     mut connections_sender: CS,
     mut keepalive_transform: FT,
     conn_timeout_ticks: usize,
-    mut timer_client: TimerClient,
+    timer_client: TimerClient,
 ) -> Result<(), AcceptConnectionError>
 where
     C: FutTransform<Input = (), Output = Option<ConnPairVec>> + Send,
     CS: Sink<(PublicKey, ConnPairVec), SinkError = CSE> + Unpin + 'static,
     FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec>,
 {
-    let timer_stream = await!(timer_client.request_timer_stream())
-        .map_err(|_| AcceptConnectionError::RequestTimerStreamError)?;
     let opt_conn_pair = await!(connect_with_timeout(
         connector,
         conn_timeout_ticks,
-        timer_stream
-    ));
+        timer_client
+    ))
+    .map_err(|_| AcceptConnectionError::RequestTimerStreamError)?;
     let conn_pair = match opt_conn_pair {
         Some(conn_pair) => Ok(conn_pair),
         None => {
@@ -167,7 +182,8 @@ async fn inner_client_listener<'a, C, IAC, CS, CSE, FT>(
     connections_sender: CS,
     mut keepalive_transform: FT,
     conn_timeout_ticks: usize,
-    timer_client: TimerClient,
+    opt_ping_config: Option<PingConfig>,
+    mut timer_client: TimerClient,
     mut spawner: impl Spawn + Clone + Send + 'static,
     mut opt_event_sender: Option<mpsc::Sender<ClientListenerEvent>>,
 ) -> Result<(), ClientListenerError>
@@ -198,24 +214,25 @@ where
     let (sender, receiver) = await!(keepalive_transform.transform(conn_pair));
 
     // Add serialization for sender:
-    let mut sender = sender
-        .sink_map_err(|_| ())
-        .with(|vec| -> future::Ready<Result<_, ()>> {
-            future::ready(Ok(serialize_reject_connection(&vec)))
-        });
+    let mut sender =
+        sender
+            .sink_map_err(|_| ())
+            .with(|relay_listen_in| -> future::Ready<Result<_, ()>> {
+                future::ready(Ok(serialize_relay_listen_in(&relay_listen_in)))
+            });
 
     // Add deserialization for receiver:
     let receiver = receiver
-        .map(|ser_incoming_connection| {
-            match deserialize_incoming_connection(&ser_incoming_connection) {
-                Ok(incoming_connection) => Some(incoming_connection),
+        .map(
+            |ser_relay_listen_out| match deserialize_relay_listen_out(&ser_relay_listen_out) {
+                Ok(relay_listen_out) => Some(relay_listen_out),
                 Err(e) => {
-                    error!("Error deserializing incoming connection {:?}", e);
+                    error!("Error deserializing relay listen message {:?}", e);
                     None
                 }
-            }
-        })
-        .take_while(|opt_incoming_connection| future::ready(opt_incoming_connection.is_some()))
+            },
+        )
+        .take_while(|opt_relay_listen_out| future::ready(opt_relay_listen_out.is_some()))
         .map(Option::unwrap);
 
     let incoming_access_control = incoming_access_control
@@ -225,19 +242,42 @@ where
         )));
 
     let server_receiver = receiver
-        .map(ClientListenerEvent::ServerMessage)
+        .map(|relay_listen_out| match relay_listen_out {
+            RelayListenOut::IncomingConnection(incoming_connection) => {
+                ClientListenerEvent::ServerMessage(incoming_connection)
+            }
+            RelayListenOut::Pong => ClientListenerEvent::Pong,
+        })
         .chain(stream::once(future::ready(
             ClientListenerEvent::ServerClosed,
         )));
 
     let pending_reject_receiver = pending_reject_receiver.map(ClientListenerEvent::PendingReject);
 
+    // Time ticks are only used for pinging the relay:
+    let timer_stream: BoxStream<'_, ClientListenerEvent> = if opt_ping_config.is_some() {
+        let timer_stream = await!(timer_client.request_timer_stream())
+            .map_err(|_| ClientListenerError::RequestTimerStreamError)?;
+        Box::pin(timer_stream.map(|_| ClientListenerEvent::TimerTick))
+    } else {
+        Box::pin(stream::empty())
+    };
+
     let mut events = select_streams![
         incoming_access_control,
         server_receiver,
-        pending_reject_receiver
+        pending_reject_receiver,
+        timer_stream
     ];
 
+    // Amount of ticks left until we send the next ping:
+    let mut ticks_to_ping = opt_ping_config
+        .as_ref()
+        .map(|ping_config| ping_config.ping_interval_ticks)
+        .unwrap_or(0);
+    // Amount of ticks left to receive a pong. None if we don't wait for a pong:
+    let mut opt_pong_ticks: Option<usize> = None;
+
     while let Some(event) = await!(events.next()) {
         if let Some(ref mut event_sender) = opt_event_sender {
             let _ = await!(event_sender.send(event.clone()));
@@ -249,7 +289,8 @@ where
             ClientListenerEvent::ServerMessage(incoming_connection) => {
                 let public_key = incoming_connection.public_key.clone();
                 if !access_control.is_allowed(&public_key) {
-                    await!(sender.send(RejectConnection { public_key }))
+                    let reject_connection = RejectConnection { public_key };
+                    await!(sender.send(RelayListenIn::RejectConnection(reject_connection)))
                         .map_err(|_| ClientListenerError::SendToServerError)?;
                 } else {
                     // We will attempt to accept the connection
@@ -271,10 +312,33 @@ where
                         .map_err(|_| ClientListenerError::SpawnError)?;
                 }
             }
+            ClientListenerEvent::Pong => opt_pong_ticks = None,
             ClientListenerEvent::PendingReject(public_key) => {
-                await!(sender.send(RejectConnection { public_key }))
+                let reject_connection = RejectConnection { public_key };
+                await!(sender.send(RelayListenIn::RejectConnection(reject_connection)))
                     .map_err(|_| ClientListenerError::SendToServerError)?;
             }
+            ClientListenerEvent::TimerTick => {
+                let ping_config = match &opt_ping_config {
+                    Some(ping_config) => ping_config,
+                    None => continue,
+                };
+                if let Some(pong_ticks) = &mut opt_pong_ticks {
+                    *pong_ticks = pong_ticks.saturating_sub(1);
+                    if *pong_ticks == 0 {
+                        warn!("inner_client_listener(): Pong timeout, relay connection is dead");
+                        return Err(ClientListenerError::PongTimeout);
+                    }
+                } else {
+                    ticks_to_ping = ticks_to_ping.saturating_sub(1);
+                    if ticks_to_ping == 0 {
+                        await!(sender.send(RelayListenIn::Ping))
+                            .map_err(|_| ClientListenerError::SendToServerError)?;
+                        opt_pong_ticks = Some(ping_config.pong_timeout_ticks);
+                        ticks_to_ping = ping_config.ping_interval_ticks;
+                    }
+                }
+            }
             ClientListenerEvent::ServerClosed => break,
             ClientListenerEvent::AccessControlClosed => break,
         }
@@ -282,12 +346,39 @@ where
     Ok(())
 }
 
+/// Replace `access_control` with the list of public keys stored in the file at `path` (If it
+/// exists). The stored list is the complete list at the time of the last store, so keys that were
+/// removed before it was stored are not restored.
+fn restore_access_control(access_control: AccessControlPk, path: &Path) -> AccessControlPk {
+    if !path.exists() {
+        return access_control;
+    }
+    match load_access_control_from_file(path) {
+        Ok(stored_allowed) => AccessControlPk::from_snapshot(stored_allowed),
+        Err(e) => {
+            warn!(
+                "restore_access_control(): Failed to load access control: {:?}",
+                e
+            );
+            access_control
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ClientListener<C, FT, S> {
     connector: C,
     keepalive_transform: FT,
     conn_timeout_ticks: usize,
+    /// Ping the relay periodically, to detect a dead connection. None disables pinging.
+    opt_ping_config: Option<PingConfig>,
     timer_client: TimerClient,
+    /// A file used to persist the access control list between runs.
+    opt_access_control_path: Option<PathBuf>,
+    /// Set once the stored access control list was restored. Later calls to `listen()` (For
+    /// example, after reconnecting to the relay) are given an up to date list by the caller, which
+    /// must not be replaced by the stored one.
+    access_control_restored: Arc<AtomicBool>,
     spawner: S,
 }
 
@@ -296,19 +387,88 @@ impl<C, FT, S> ClientListener<C, FT, S> {
         connector: C,
         keepalive_transform: FT,
         conn_timeout_ticks: usize,
+        opt_ping_config: Option<PingConfig>,
         timer_client: TimerClient,
+        opt_access_control_path: Option<PathBuf>,
         spawner: S,
     ) -> ClientListener<C, FT, S> {
         ClientListener {
             connector,
             keepalive_transform,
             conn_timeout_ticks,
+            opt_ping_config,
             timer_client,
+            opt_access_control_path,
+            access_control_restored: Arc::new(AtomicBool::new(false)),
             spawner,
         }
     }
 }
 
+impl<C, FT, S> ClientListener<C, FT, S>
+where
+    S: Spawn + Clone + Send + 'static,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send + 'static,
+{
+    /// Replace `access_control` with the access control list saved during a previous shutdown
+    /// (If any). Only the first call restores, later calls return `access_control` unchanged.
+    pub(super) fn restore_access_control(
+        &self,
+        access_control: AccessControlPk,
+    ) -> AccessControlPk {
+        match &self.opt_access_control_path {
+            Some(access_control_path)
+                if !self.access_control_restored.swap(true, Ordering::SeqCst) =>
+            {
+                restore_access_control(access_control, access_control_path)
+            }
+            _ => access_control,
+        }
+    }
+
+    /// Persist the access control list, so that it could be reloaded on startup.
+    pub(super) fn store_access_control(&self, access_control: &AccessControlPk) {
+        if let Some(access_control_path) = &self.opt_access_control_path {
+            let allowed = access_control.snapshot();
+            if let Err(e) = store_access_control_to_file(&allowed, access_control_path) {
+                warn!("ClientListener: Failed to store access control: {:?}", e);
+            }
+        }
+    }
+
+    /// Open a single listen connection to the relay at `relay_address`.
+    /// Returns when the connection is closed, or if connecting to the relay failed.
+    pub(super) async fn connect_and_listen<'a, A, IAC, CS, CSE>(
+        self,
+        relay_address: A,
+        access_control: &'a mut AccessControlPk,
+        incoming_access_control: &'a mut IAC,
+        connections_sender: CS,
+    ) -> Result<(), ClientListenerError>
+    where
+        A: Clone + Send + Sync + 'static,
+        C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+        IAC: Stream<Item = AccessControlOp<PublicKey>> + Unpin + Send + 'static,
+        CS: Sink<(PublicKey, ConnPairVec), SinkError = CSE> + Unpin + Clone + Send + 'static,
+        CSE: 'static,
+    {
+        let const_connector = ConstFutTransform::new(self.connector, relay_address);
+
+        await!(inner_client_listener(
+            const_connector,
+            access_control,
+            incoming_access_control,
+            connections_sender,
+            self.keepalive_transform,
+            self.conn_timeout_ticks,
+            self.opt_ping_config,
+            self.timer_client,
+            self.spawner,
+            None
+        ))
+    }
+}
+
 impl<A, C, FT, S> Listener for ClientListener<C, FT, S>
 where
     A: Clone + Send + Sync + 'static,
@@ -327,28 +487,29 @@ where
         mpsc::Sender<AccessControlOp<PublicKey>>,
         mpsc::Receiver<(PublicKey, ConnPairVec)>,
     ) {
-        let (relay_address, mut access_control) = arg;
+        let (relay_address, access_control) = arg;
+
+        // Reload the access control list saved during the previous shutdown:
+        let mut access_control = self.restore_access_control(access_control);
 
         let mut c_spawner = self.spawner.clone();
         let (access_control_sender, mut access_control_receiver) = mpsc::channel(0);
         let (connections_sender, connections_receiver) = mpsc::channel(0);
 
-        let const_connector = ConstFutTransform::new(self.connector.clone(), relay_address);
-
         let fut = async move {
-            await!(inner_client_listener(
-                const_connector,
-                &mut access_control,
-                &mut access_control_receiver,
-                connections_sender,
-                self.keepalive_transform,
-                self.conn_timeout_ticks,
-                self.timer_client,
-                self.spawner,
-                None
-            )
-            .map_err(|e| warn!("inner_client_listener() error: {:?}", e))
-            .map(|_| ()))
+            let c_self = self.clone();
+            await!(c_self
+                .connect_and_listen(
+                    relay_address,
+                    &mut access_control,
+                    &mut access_control_receiver,
+                    connections_sender
+                )
+                .map_err(|e| warn!("inner_client_listener() error: {:?}", e))
+                .map(|_| ()));
+
+            // Persist the access control list, so that it could be reloaded on startup:
+            self.store_access_control(&access_control);
         };
 
         let _ = c_spawner.spawn(fut);
@@ -364,20 +525,22 @@ mod tests {
     use futures::channel::oneshot;
     use futures::executor::ThreadPool;
     use proto::relay::serialize::deserialize_init_connection;
-    use timer::create_timer_incoming;
+    use timer::{create_timer_incoming, dummy_timer_multi_sender, TimerTick};
 
-    use proto::relay::serialize::{deserialize_reject_connection, serialize_incoming_connection};
+    use proto::relay::serialize::{deserialize_relay_listen_in, serialize_relay_listen_out};
 
     use common::conn::FuncFutTransform;
     use common::dummy_connector::DummyConnector;
 
-    async fn task_connect_with_timeout_basic(mut spawner: impl Spawn) {
+    use tempfile::tempdir;
+
+    async fn task_connect_with_timeout_basic(mut spawner: impl Spawn + Clone) {
         let conn_timeout_ticks = 8;
-        let (_timer_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+        let (_tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
         let (req_sender, mut req_receiver) = mpsc::channel(0);
         let connector = DummyConnector::new(req_sender);
 
-        let fut_connect = connect_with_timeout(connector, conn_timeout_ticks, timer_stream);
+        let fut_connect = connect_with_timeout(connector, conn_timeout_ticks, timer_client);
         let fut_conn = spawner.spawn_with_handle(fut_connect).unwrap();
 
         let req = await!(req_receiver.next()).unwrap();
@@ -385,7 +548,7 @@ mod tests {
         let conn_pair = (dummy_sender, dummy_receiver);
         req.reply(Some(conn_pair));
 
-        assert!(await!(fut_conn).is_some());
+        assert!(await!(fut_conn).unwrap().is_some());
     }
 
     #[test]
@@ -394,9 +557,9 @@ mod tests {
         thread_pool.run(task_connect_with_timeout_basic(thread_pool.clone()));
     }
 
-    async fn task_connect_with_timeout_timeout(mut spawner: impl Spawn) {
+    async fn task_connect_with_timeout_timeout(mut spawner: impl Spawn + Clone) {
         let conn_timeout_ticks = 8;
-        let (mut timer_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
         let (req_sender, mut req_receiver) = mpsc::channel(0);
         let connector = DummyConnector::new(req_sender);
 
@@ -407,7 +570,7 @@ mod tests {
                 let res = await!(connect_with_timeout(
                     connector,
                     conn_timeout_ticks,
-                    timer_stream
+                    timer_client
                 ));
                 res_sender.send(res).unwrap();
             })
@@ -416,11 +579,12 @@ mod tests {
         let req = await!(req_receiver.next()).unwrap();
         assert_eq!(req.address, ());
 
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
         for _ in 0..8usize {
-            await!(timer_sender.send(TimerTick)).unwrap();
+            await!(tick_sender.send(TimerTick)).unwrap();
         }
 
-        assert!(await!(res_receiver).unwrap().is_none());
+        assert!(await!(res_receiver).unwrap().unwrap().is_none());
     }
 
     #[test]
@@ -518,6 +682,7 @@ mod tests {
                 connections_sender,
                 keepalive_transform,
                 conn_timeout_ticks,
+                None,
                 timer_client,
                 c_spawner,
                 Some(event_sender)
@@ -551,24 +716,29 @@ mod tests {
         // Relay will now send a message about incoming connection from a public key that is not
         // allowed:
         let public_key_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
-        let relay_listen_out = IncomingConnection {
+        let relay_listen_out = RelayListenOut::IncomingConnection(IncomingConnection {
             public_key: public_key_b.clone(),
-        };
-        let vec_incoming_connection = serialize_incoming_connection(&relay_listen_out);
+        });
+        let vec_incoming_connection = serialize_relay_listen_out(&relay_listen_out);
         await!(relay_sender.send(vec_incoming_connection)).unwrap();
         await!(event_receiver.next()).unwrap();
 
         // Listener will reject the connection:
         let vec_relay_listen_in = await!(relay_receiver.next()).unwrap();
-        let reject_connection = deserialize_reject_connection(&vec_relay_listen_in).unwrap();
-        assert_eq!(reject_connection.public_key, public_key_b);
+        let relay_listen_in = deserialize_relay_listen_in(&vec_relay_listen_in).unwrap();
+        assert_eq!(
+            relay_listen_in,
+            RelayListenIn::RejectConnection(RejectConnection {
+                public_key: public_key_b
+            })
+        );
 
         // Relay will now send a message about incoming connection from a public key that is
         // allowed:
-        let incoming_connection = IncomingConnection {
+        let relay_listen_out = RelayListenOut::IncomingConnection(IncomingConnection {
             public_key: public_key_a.clone(),
-        };
-        let vec_incoming_connection = serialize_incoming_connection(&incoming_connection);
+        });
+        let vec_incoming_connection = serialize_relay_listen_out(&relay_listen_out);
         await!(relay_sender.send(vec_incoming_connection)).unwrap();
         await!(event_receiver.next()).unwrap();
 
@@ -596,6 +766,142 @@ mod tests {
         thread_pool.run(task_client_listener_basic(thread_pool.clone()));
     }
 
+    async fn task_client_listener_ping(mut spawner: impl Spawn + Clone + Send + 'static) {
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(req_sender);
+        let (connections_sender, _connections_receiver) = mpsc::channel(0);
+        let conn_timeout_ticks = 8;
+        let (mut tick_sender, tick_receiver) = mpsc::channel(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut acl_sender, mut incoming_access_control) = mpsc::channel(0);
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+
+        let ping_config = PingConfig {
+            ping_interval_ticks: 4,
+            pong_timeout_ticks: 2,
+        };
+
+        let c_spawner = spawner.clone();
+        let fut_listener = async move {
+            let mut access_control = AccessControlPk::new();
+            await!(inner_client_listener(
+                connector,
+                &mut access_control,
+                &mut incoming_access_control,
+                connections_sender,
+                keepalive_transform,
+                conn_timeout_ticks,
+                Some(ping_config),
+                timer_client,
+                c_spawner,
+                Some(event_sender)
+            ))
+        };
+        let listener_handle = spawner.spawn_with_handle(fut_listener).unwrap();
+
+        // listener will attempt to start a main connection to the relay:
+        let (mut relay_sender, local_receiver) = mpsc::channel(0);
+        let (local_sender, mut relay_receiver) = mpsc::channel(0);
+        let conn_pair = (local_sender, local_receiver);
+        let req = await!(req_receiver.next()).unwrap();
+        req.reply(Some(conn_pair));
+
+        let vec_init_connection = await!(relay_receiver.next()).unwrap();
+        let init_connection = deserialize_init_connection(&vec_init_connection).unwrap();
+        assert_eq!(init_connection, InitConnection::Listen);
+
+        // Make sure that the listener main loop is running (And the timer stream was obtained):
+        let public_key_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        await!(acl_sender.send(AccessControlOp::Add(public_key_a.clone()))).unwrap();
+        await!(event_receiver.next()).unwrap();
+
+        // Listener pings the relay after ping_interval_ticks:
+        for _ in 0..4usize {
+            await!(tick_sender.send(())).unwrap();
+            match await!(event_receiver.next()).unwrap() {
+                ClientListenerEvent::TimerTick => {}
+                _ => unreachable!(),
+            };
+        }
+        let vec_relay_listen_in = await!(relay_receiver.next()).unwrap();
+        let relay_listen_in = deserialize_relay_listen_in(&vec_relay_listen_in).unwrap();
+        assert_eq!(relay_listen_in, RelayListenIn::Ping);
+
+        // Relay responds in time:
+        let vec_relay_listen_out = serialize_relay_listen_out(&RelayListenOut::Pong);
+        await!(relay_sender.send(vec_relay_listen_out)).unwrap();
+        match await!(event_receiver.next()).unwrap() {
+            ClientListenerEvent::Pong => {}
+            _ => unreachable!(),
+        };
+
+        // Another ping after ping_interval_ticks:
+        for _ in 0..4usize {
+            await!(tick_sender.send(())).unwrap();
+            match await!(event_receiver.next()).unwrap() {
+                ClientListenerEvent::TimerTick => {}
+                _ => unreachable!(),
+            };
+        }
+        let vec_relay_listen_in = await!(relay_receiver.next()).unwrap();
+        let relay_listen_in = deserialize_relay_listen_in(&vec_relay_listen_in).unwrap();
+        assert_eq!(relay_listen_in, RelayListenIn::Ping);
+
+        // This time the relay doesn't respond.
+        // After pong_timeout_ticks the connection is declared dead:
+        for _ in 0..2usize {
+            await!(tick_sender.send(())).unwrap();
+            match await!(event_receiver.next()).unwrap() {
+                ClientListenerEvent::TimerTick => {}
+                _ => unreachable!(),
+            };
+        }
+        match await!(listener_handle) {
+            Err(ClientListenerError::PongTimeout) => {}
+            _ => unreachable!(),
+        };
+        assert!(await!(relay_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_client_listener_ping() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_client_listener_ping(thread_pool.clone()));
+    }
+
+    #[test]
+    fn test_restore_access_control() {
+        // Create a temporary directory:
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("access_control_file");
+
+        let public_key_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let public_key_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let public_key_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+
+        // Nothing was stored yet:
+        let access_control = restore_access_control(AccessControlPk::new(), &file_path);
+        assert!(!access_control.is_allowed(&public_key_a));
+
+        // Access control list at shutdown. public_key_b was removed during the run:
+        let mut access_control = AccessControlPk::new();
+        access_control.apply_op(AccessControlOp::Add(public_key_a.clone()));
+        access_control.apply_op(AccessControlOp::Add(public_key_b.clone()));
+        access_control.apply_op(AccessControlOp::Remove(public_key_b.clone()));
+        store_access_control_to_file(&access_control.snapshot(), &file_path).unwrap();
+
+        // Restore on startup. The stored list replaces the initial one:
+        let mut initial_access_control = AccessControlPk::new();
+        initial_access_control.apply_op(AccessControlOp::Add(public_key_b.clone()));
+        initial_access_control.apply_op(AccessControlOp::Add(public_key_c.clone()));
+        let access_control = restore_access_control(initial_access_control, &file_path);
+        assert!(access_control.is_allowed(&public_key_a));
+        assert!(!access_control.is_allowed(&public_key_b));
+        assert!(!access_control.is_allowed(&public_key_c));
+    }
+
     // TODO: Add a test for ClientListener.
 
 }
@@ -0,0 +1,428 @@
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{FutureExt, TryFutureExt};
+
+use common::access_control::{AccessControl, AccessControlOp};
+use common::conn::{ConnPairVec, FutTransform, Listener};
+use crypto::identity::PublicKey;
+use timer::TimerClient;
+
+use super::client_listener::{ClientListener, ClientListenerError};
+
+type AccessControlPk = AccessControl<PublicKey>;
+type AccessControlOpPk = AccessControlOp<PublicKey>;
+
+#[derive(Debug)]
+enum LoadBalancedClientListenerError {
+    NoRelays,
+    RequestTimerStreamError,
+}
+
+/// Maximum backoff of a failing relay is `2^(MAX_BACKOFF_SHIFT + 1)` attempts.
+const MAX_BACKOFF_SHIFT: usize = 6;
+
+/// Amount of active listening sessions for every relay, together with the recent connection
+/// failures of every relay.
+#[derive(Debug)]
+struct RelaysLoad {
+    active_sessions: Vec<usize>,
+    /// Amount of consecutive failed connection attempts to every relay.
+    failures: Vec<usize>,
+    /// A relay that failed is not picked again before this attempt, unless all the relays are
+    /// backing off.
+    retry_attempt: Vec<u64>,
+    /// Amount of relays picked so far. Used as the clock of the backoff.
+    num_attempts: u64,
+    /// The relay we start searching from, used to break ties round robin.
+    next_index: usize,
+}
+
+impl RelaysLoad {
+    fn new(num_relays: usize) -> Self {
+        RelaysLoad {
+            active_sessions: vec![0; num_relays],
+            failures: vec![0; num_relays],
+            retry_attempt: vec![0; num_relays],
+            num_attempts: 0,
+            next_index: 0,
+        }
+    }
+
+    /// Pick the relay with the fewest active sessions and open a session on it.
+    /// Relays that failed recently are skipped, unless all the relays failed recently.
+    /// If a few relays have the same load, they are picked in a round robin fashion.
+    /// Returns None if there are no relays.
+    fn acquire(&mut self) -> Option<usize> {
+        let num_relays = self.active_sessions.len();
+        let num_attempts = self.num_attempts;
+        let mut candidates: Vec<usize> = (0..num_relays)
+            .filter(|&index| self.retry_attempt[index] <= num_attempts)
+            .collect();
+        if candidates.is_empty() {
+            // All the relays are backing off. Pick from the relays that may be retried first:
+            let min_retry_attempt = *self.retry_attempt.iter().min()?;
+            candidates = (0..num_relays)
+                .filter(|&index| self.retry_attempt[index] == min_retry_attempt)
+                .collect();
+        }
+
+        let min_sessions = candidates
+            .iter()
+            .map(|&index| self.active_sessions[index])
+            .min()?;
+        let index = (0..num_relays)
+            .map(|i| (self.next_index + i) % num_relays)
+            .find(|index| {
+                candidates.contains(index) && self.active_sessions[*index] == min_sessions
+            })?;
+
+        self.num_attempts = self.num_attempts.wrapping_add(1);
+        self.next_index = (index + 1) % num_relays;
+        self.active_sessions[index] += 1;
+        Some(index)
+    }
+
+    /// Close a session previously opened on relay `index`.
+    /// `failed` is true if we could not connect to the relay. The relay will not be picked again
+    /// for a number of attempts that doubles with every consecutive failure.
+    fn release(&mut self, index: usize, failed: bool) {
+        self.active_sessions[index] = self.active_sessions[index].saturating_sub(1);
+        if failed {
+            let backoff = 2u64 << self.failures[index].min(MAX_BACKOFF_SHIFT);
+            self.failures[index] = self.failures[index].saturating_add(1);
+            self.retry_attempt[index] = self.num_attempts.saturating_add(backoff);
+        } else {
+            self.failures[index] = 0;
+            self.retry_attempt[index] = 0;
+        }
+    }
+}
+
+async fn load_balanced_client_listener_loop<A, C, FT, S>(
+    relays: Vec<(A, ClientListener<C, FT, S>)>,
+    relays_load: Arc<Mutex<RelaysLoad>>,
+    access_control: &mut AccessControlPk,
+    mut access_control_receiver: mpsc::Receiver<AccessControlOpPk>,
+    connections_sender: mpsc::Sender<(PublicKey, ConnPairVec)>,
+    timer_client: TimerClient,
+    reconnect_delay_ticks: usize,
+) -> Result<(), LoadBalancedClientListenerError>
+where
+    A: Clone + Send + Sync + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+    S: Spawn + Clone + Send + 'static,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send + 'static,
+{
+    loop {
+        // The relay is chosen again on every attempt, so that a relay that has recovered
+        // will be used again:
+        let index = relays_load
+            .lock()
+            .unwrap()
+            .acquire()
+            .ok_or(LoadBalancedClientListenerError::NoRelays)?;
+        let (relay_address, client_listener) = relays[index].clone();
+
+        let res = await!(client_listener.connect_and_listen(
+            relay_address,
+            access_control,
+            &mut access_control_receiver,
+            connections_sender.clone()
+        ));
+        let failed = match res {
+            Err(ClientListenerError::ConnectionFailure) => true,
+            _ => false,
+        };
+        relays_load.lock().unwrap().release(index, failed);
+
+        if let Err(e) = res {
+            warn!(
+                "load_balanced_client_listener_loop(): Relay {} error: {:?}",
+                index, e
+            );
+        }
+
+        // Stop if the access control sender was dropped:
+        match access_control_receiver.try_next() {
+            Ok(Some(access_control_op)) => access_control.apply_op(access_control_op),
+            Ok(None) => break,
+            Err(_) => {}
+        }
+
+        // Wait before reconnecting:
+        await!(timer_client.request_timeout(reconnect_delay_ticks))
+            .map_err(|_| LoadBalancedClientListenerError::RequestTimerStreamError)?;
+    }
+    Ok(())
+}
+
+/// A listener that spreads its listening sessions between a few relays.
+/// Every new session (And every reconnection) is opened with the relay that has the fewest
+/// active sessions.
+#[derive(Clone)]
+pub struct LoadBalancedClientListener<A, C, FT, S> {
+    /// A ClientListener for every relay address.
+    relays: Vec<(A, ClientListener<C, FT, S>)>,
+    /// Shared between all the clones of this listener.
+    relays_load: Arc<Mutex<RelaysLoad>>,
+    timer_client: TimerClient,
+    /// Amount of ticks to wait before reconnecting after a session was closed.
+    reconnect_delay_ticks: usize,
+    spawner: S,
+}
+
+impl<A, C, FT, S> LoadBalancedClientListener<A, C, FT, S>
+where
+    C: Clone,
+    FT: Clone,
+    S: Clone,
+{
+    pub fn new(
+        relay_addresses: Vec<A>,
+        client_listener: ClientListener<C, FT, S>,
+        timer_client: TimerClient,
+        reconnect_delay_ticks: usize,
+        spawner: S,
+    ) -> LoadBalancedClientListener<A, C, FT, S> {
+        let relays_load = Arc::new(Mutex::new(RelaysLoad::new(relay_addresses.len())));
+        let relays = relay_addresses
+            .into_iter()
+            .map(|relay_address| (relay_address, client_listener.clone()))
+            .collect();
+
+        LoadBalancedClientListener {
+            relays,
+            relays_load,
+            timer_client,
+            reconnect_delay_ticks,
+            spawner,
+        }
+    }
+}
+
+impl<A, C, FT, S> Listener for LoadBalancedClientListener<A, C, FT, S>
+where
+    A: Clone + Send + Sync + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+    S: Spawn + Clone + Send + 'static,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send + 'static,
+{
+    type Connection = (PublicKey, ConnPairVec);
+    type Config = AccessControlOpPk;
+    type Arg = AccessControlPk;
+
+    fn listen(
+        self,
+        access_control: AccessControlPk,
+    ) -> (
+        mpsc::Sender<AccessControlOp<PublicKey>>,
+        mpsc::Receiver<(PublicKey, ConnPairVec)>,
+    ) {
+        let LoadBalancedClientListener {
+            relays,
+            relays_load,
+            timer_client,
+            reconnect_delay_ticks,
+            mut spawner,
+        } = self;
+
+        let (access_control_sender, access_control_receiver) = mpsc::channel(0);
+        let (connections_sender, connections_receiver) = mpsc::channel(0);
+
+        // All the relays share the same access control file:
+        let opt_client_listener = relays
+            .first()
+            .map(|(_relay_address, client_listener)| client_listener.clone());
+        let mut access_control = match &opt_client_listener {
+            Some(client_listener) => client_listener.restore_access_control(access_control),
+            None => access_control,
+        };
+
+        let fut = async move {
+            await!(load_balanced_client_listener_loop(
+                relays,
+                relays_load,
+                &mut access_control,
+                access_control_receiver,
+                connections_sender,
+                timer_client,
+                reconnect_delay_ticks
+            )
+            .map_err(|e| warn!("load_balanced_client_listener_loop() error: {:?}", e))
+            .map(|_| ()));
+
+            // Persist the access control list, so that it could be reloaded on startup:
+            if let Some(client_listener) = opt_client_listener {
+                client_listener.store_access_control(&access_control);
+            }
+        };
+
+        let _ = spawner.spawn(fut);
+
+        (access_control_sender, connections_receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::ThreadPool;
+    use futures::{future, SinkExt, StreamExt};
+
+    use common::conn::FuncFutTransform;
+    use common::dummy_connector::DummyConnector;
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
+    #[test]
+    fn test_relays_load_fewest_sessions() {
+        let mut relays_load = RelaysLoad::new(3);
+        assert_eq!(relays_load.acquire(), Some(0));
+        assert_eq!(relays_load.acquire(), Some(1));
+        assert_eq!(relays_load.acquire(), Some(2));
+
+        // Relay 1 has the fewest sessions:
+        relays_load.release(1, false);
+        assert_eq!(relays_load.acquire(), Some(1));
+
+        relays_load.release(2, false);
+        relays_load.release(0, false);
+        assert_eq!(relays_load.acquire(), Some(2));
+        assert_eq!(relays_load.acquire(), Some(0));
+    }
+
+    #[test]
+    fn test_relays_load_round_robin() {
+        let mut relays_load = RelaysLoad::new(3);
+        for &index in &[0, 1, 2, 0, 1, 2] {
+            assert_eq!(relays_load.acquire(), Some(index));
+            relays_load.release(index, false);
+        }
+    }
+
+    #[test]
+    fn test_relays_load_backoff() {
+        let mut relays_load = RelaysLoad::new(2);
+        // Relay 0 fails:
+        assert_eq!(relays_load.acquire(), Some(0));
+        relays_load.release(0, true);
+
+        // Relay 0 is skipped for 2 attempts, although it has no sessions:
+        assert_eq!(relays_load.acquire(), Some(1));
+        assert_eq!(relays_load.acquire(), Some(1));
+        assert_eq!(relays_load.acquire(), Some(0));
+
+        // Relay 0 fails again, and is skipped for 4 attempts this time:
+        relays_load.release(0, true);
+        for _ in 0..4 {
+            assert_eq!(relays_load.acquire(), Some(1));
+        }
+        assert_eq!(relays_load.acquire(), Some(0));
+
+        // Relay 0 recovers:
+        relays_load.release(0, false);
+        assert_eq!(relays_load.acquire(), Some(0));
+    }
+
+    #[test]
+    fn test_relays_load_all_failing() {
+        let mut relays_load = RelaysLoad::new(2);
+        assert_eq!(relays_load.acquire(), Some(0));
+        relays_load.release(0, true);
+        assert_eq!(relays_load.acquire(), Some(1));
+        relays_load.release(1, true);
+
+        // Both relays are backing off. Relay 0 may be retried first:
+        assert_eq!(relays_load.acquire(), Some(0));
+    }
+
+    #[test]
+    fn test_relays_load_empty() {
+        let mut relays_load = RelaysLoad::new(0);
+        assert_eq!(relays_load.acquire(), None);
+    }
+
+    async fn task_load_balanced_client_listener_basic(
+        spawner: impl Spawn + Clone + Send + 'static,
+    ) {
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::<u32, Option<ConnPairVec>>::new(req_sender);
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+        let conn_timeout_ticks = 8;
+
+        let client_listener = ClientListener::new(
+            connector,
+            keepalive_transform,
+            conn_timeout_ticks,
+            None,
+            timer_client.clone(),
+            None,
+            spawner.clone(),
+        );
+        let reconnect_delay_ticks = 2;
+        let load_balanced_client_listener = LoadBalancedClientListener::new(
+            vec![0u32, 1u32],
+            client_listener,
+            timer_client,
+            reconnect_delay_ticks,
+            spawner.clone(),
+        );
+
+        // First session goes to relay 0:
+        let (_access_control_sender0, _connections_receiver0) = load_balanced_client_listener
+            .clone()
+            .listen(AccessControlPk::new());
+        let req = await!(req_receiver.next()).unwrap();
+        assert_eq!(req.address, 0);
+        let (relay_sender0, local_receiver) = mpsc::channel(0);
+        let (local_sender, mut relay_receiver0) = mpsc::channel(0);
+        req.reply(Some((local_sender, local_receiver)));
+        // Init message:
+        await!(relay_receiver0.next()).unwrap();
+
+        // Second session goes to relay 1, which has fewer sessions:
+        let (_access_control_sender1, _connections_receiver1) = load_balanced_client_listener
+            .clone()
+            .listen(AccessControlPk::new());
+        let req = await!(req_receiver.next()).unwrap();
+        assert_eq!(req.address, 1);
+        let (_relay_sender1, local_receiver) = mpsc::channel(0);
+        let (local_sender, mut relay_receiver1) = mpsc::channel(0);
+        req.reply(Some((local_sender, local_receiver)));
+        await!(relay_receiver1.next()).unwrap();
+
+        // Relay 0 closes its session:
+        drop(relay_sender0);
+        drop(relay_receiver0);
+
+        // Wait for the reconnect delay:
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+        for _ in 0..reconnect_delay_ticks {
+            await!(tick_sender.send(TimerTick)).unwrap();
+        }
+
+        // Relay 0 is now the least loaded relay, so we reconnect to it:
+        let req = await!(req_receiver.next()).unwrap();
+        assert_eq!(req.address, 0);
+        req.reply(None);
+
+        // Failed connection attempt. Relay 0 still has the fewest sessions, but it is backing
+        // off, so we use relay 1:
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+        for _ in 0..reconnect_delay_ticks {
+            await!(tick_sender.send(TimerTick)).unwrap();
+        }
+        let req = await!(req_receiver.next()).unwrap();
+        assert_eq!(req.address, 1);
+    }
+
+    #[test]
+    fn test_load_balanced_client_listener_basic() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_load_balanced_client_listener_basic(
+            thread_pool.clone(),
+        ));
+    }
+}
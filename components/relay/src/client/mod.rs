@@ -1,2 +1,4 @@
 pub mod client_connector;
 pub mod client_listener;
+pub mod load_balanced_client_listener;
+pub mod retry_client_listener;
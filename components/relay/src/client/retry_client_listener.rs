@@ -0,0 +1,255 @@
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{FutureExt, TryFutureExt};
+
+use common::access_control::{AccessControl, AccessControlOp};
+use common::conn::{ConnPairVec, FutTransform, Listener};
+use crypto::identity::PublicKey;
+use timer::TimerClient;
+
+use super::client_listener::{ClientListener, ClientListenerError};
+
+type AccessControlPk = AccessControl<PublicKey>;
+type AccessControlOpPk = AccessControlOp<PublicKey>;
+
+#[derive(Debug)]
+enum RetryClientListenerError {
+    RequestTimerStreamError,
+}
+
+/// Wait until `delay_ticks` time ticks have passed.
+async fn wait_ticks(
+    timer_client: TimerClient,
+    delay_ticks: usize,
+) -> Result<(), RetryClientListenerError> {
+    await!(timer_client.request_timeout(delay_ticks))
+        .map_err(|_| RetryClientListenerError::RequestTimerStreamError)
+}
+
+async fn retry_client_listener_loop<A, C, FT, S>(
+    client_listener: ClientListener<C, FT, S>,
+    relay_address: A,
+    access_control: &mut AccessControlPk,
+    mut access_control_receiver: mpsc::Receiver<AccessControlOpPk>,
+    connections_sender: mpsc::Sender<(PublicKey, ConnPairVec)>,
+    timer_client: TimerClient,
+    initial_delay_ticks: usize,
+    max_delay_ticks: usize,
+    multiplier: usize,
+) -> Result<(), RetryClientListenerError>
+where
+    A: Clone + Send + Sync + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+    S: Spawn + Clone + Send + 'static,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send + 'static,
+{
+    let mut delay_ticks = initial_delay_ticks;
+    loop {
+        let res = await!(client_listener.clone().connect_and_listen(
+            relay_address.clone(),
+            access_control,
+            &mut access_control_receiver,
+            connections_sender.clone()
+        ));
+        match res {
+            Err(ClientListenerError::ConnectionFailure) => {
+                warn!("retry_client_listener_loop(): Failed to connect to relay");
+            }
+            res => {
+                // We managed to connect to the relay, so the next delay starts over:
+                if let Err(e) = res {
+                    warn!("retry_client_listener_loop(): Connection error: {:?}", e);
+                }
+                delay_ticks = initial_delay_ticks;
+            }
+        }
+
+        // Stop if the access control sender was dropped:
+        match access_control_receiver.try_next() {
+            Ok(Some(access_control_op)) => access_control.apply_op(access_control_op),
+            Ok(None) => break,
+            Err(_) => {}
+        }
+
+        await!(wait_ticks(timer_client.clone(), delay_ticks))?;
+        delay_ticks = delay_ticks.saturating_mul(multiplier).min(max_delay_ticks);
+    }
+    Ok(())
+}
+
+/// A ClientListener that reconnects to the relay when the connection fails.
+/// Consecutive connection failures are retried with an exponentially growing delay.
+#[derive(Clone)]
+pub struct RetryClientListener<C, FT, S> {
+    client_listener: ClientListener<C, FT, S>,
+    timer_client: TimerClient,
+    /// Delay before the first reconnection attempt.
+    initial_delay_ticks: usize,
+    /// Upper bound for the delay between reconnection attempts.
+    max_delay_ticks: usize,
+    /// The delay is multiplied by this value after every consecutive failure.
+    multiplier: usize,
+    spawner: S,
+}
+
+impl<C, FT, S> RetryClientListener<C, FT, S> {
+    pub fn new(
+        client_listener: ClientListener<C, FT, S>,
+        timer_client: TimerClient,
+        initial_delay_ticks: usize,
+        max_delay_ticks: usize,
+        multiplier: usize,
+        spawner: S,
+    ) -> RetryClientListener<C, FT, S> {
+        RetryClientListener {
+            client_listener,
+            timer_client,
+            initial_delay_ticks,
+            max_delay_ticks,
+            multiplier,
+            spawner,
+        }
+    }
+}
+
+impl<A, C, FT, S> Listener for RetryClientListener<C, FT, S>
+where
+    A: Clone + Send + Sync + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+    S: Spawn + Clone + Send + 'static,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send + 'static,
+{
+    type Connection = (PublicKey, ConnPairVec);
+    type Config = AccessControlOpPk;
+    type Arg = (A, AccessControlPk);
+
+    fn listen(
+        self,
+        arg: (A, AccessControlPk),
+    ) -> (
+        mpsc::Sender<AccessControlOp<PublicKey>>,
+        mpsc::Receiver<(PublicKey, ConnPairVec)>,
+    ) {
+        let RetryClientListener {
+            client_listener,
+            timer_client,
+            initial_delay_ticks,
+            max_delay_ticks,
+            multiplier,
+            mut spawner,
+        } = self;
+        let (relay_address, access_control) = arg;
+
+        // Reload the access control list saved during the previous shutdown:
+        let mut access_control = client_listener.restore_access_control(access_control);
+
+        let (access_control_sender, access_control_receiver) = mpsc::channel(0);
+        let (connections_sender, connections_receiver) = mpsc::channel(0);
+
+        let fut = async move {
+            await!(retry_client_listener_loop(
+                client_listener.clone(),
+                relay_address,
+                &mut access_control,
+                access_control_receiver,
+                connections_sender,
+                timer_client,
+                initial_delay_ticks,
+                max_delay_ticks,
+                multiplier
+            )
+            .map_err(|e| warn!("retry_client_listener_loop() error: {:?}", e))
+            .map(|_| ()));
+
+            // Persist the access control list, so that it could be reloaded on startup:
+            client_listener.store_access_control(&access_control);
+        };
+
+        let _ = spawner.spawn(fut);
+
+        (access_control_sender, connections_receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::ThreadPool;
+    use futures::{future, SinkExt, StreamExt};
+
+    use common::conn::FuncFutTransform;
+    use common::dummy_connector::DummyConnector;
+    use proto::relay::messages::InitConnection;
+    use proto::relay::serialize::deserialize_init_connection;
+    use timer::{dummy_timer_multi_sender, TimerTick};
+
+    async fn task_retry_client_listener_backoff(spawner: impl Spawn + Clone + Send + 'static) {
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::<u32, Option<ConnPairVec>>::new(req_sender);
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+        let conn_timeout_ticks = 8;
+
+        let client_listener = ClientListener::new(
+            connector,
+            keepalive_transform,
+            conn_timeout_ticks,
+            None,
+            timer_client.clone(),
+            None,
+            spawner.clone(),
+        );
+        let retry_client_listener =
+            RetryClientListener::new(client_listener, timer_client, 1, 4, 2, spawner.clone());
+
+        let (_access_control_sender, _connections_receiver) =
+            retry_client_listener.listen((0x1337u32, AccessControlPk::new()));
+
+        // Consecutive connection failures.
+        // The delay doubles on every failure, up to max_delay_ticks:
+        let mut num_attempts = 0usize;
+        for &delay_ticks in &[1usize, 2, 4, 4] {
+            let req = await!(req_receiver.next()).unwrap();
+            assert_eq!(req.address, 0x1337);
+            req.reply(None);
+            num_attempts += 1;
+
+            let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+            for _ in 0..delay_ticks {
+                await!(tick_sender.send(TimerTick)).unwrap();
+            }
+        }
+        assert_eq!(num_attempts, 4);
+
+        // This time the connection attempt succeeds:
+        let req = await!(req_receiver.next()).unwrap();
+        let (relay_sender, local_receiver) = mpsc::channel(0);
+        let (local_sender, mut relay_receiver) = mpsc::channel(0);
+        req.reply(Some((local_sender, local_receiver)));
+
+        let vec_init_connection = await!(relay_receiver.next()).unwrap();
+        let init_connection = deserialize_init_connection(&vec_init_connection).unwrap();
+        assert_eq!(init_connection, InitConnection::Listen);
+
+        // The relay closes the connection:
+        drop(relay_sender);
+        drop(relay_receiver);
+
+        // The delay was reset after the successful connection:
+        for &delay_ticks in &[1usize, 2] {
+            let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+            for _ in 0..delay_ticks {
+                await!(tick_sender.send(TimerTick)).unwrap();
+            }
+
+            let req = await!(req_receiver.next()).unwrap();
+            req.reply(None);
+        }
+    }
+
+    #[test]
+    fn test_retry_client_listener_backoff() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_retry_client_listener_backoff(thread_pool.clone()));
+    }
+}
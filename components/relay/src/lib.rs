@@ -23,5 +23,10 @@ mod client;
 mod server;
 
 pub use self::client::client_connector::ClientConnector;
-pub use self::client::client_listener::ClientListener;
-pub use self::server::net_server::{net_relay_server, NetRelayServerError};
+pub use self::client::client_listener::{ClientListener, PingConfig};
+pub use self::client::load_balanced_client_listener::LoadBalancedClientListener;
+pub use self::client::retry_client_listener::RetryClientListener;
+pub use self::server::net_server::{
+    net_relay_server, NetRelayServerError, RateLimitConfig, RelayServerHandle,
+    RelayServerHandleError, RelayServerRequest, ShutdownHandle,
+};
@@ -12,9 +12,9 @@ use timer::TimerClient;
 use super::types::{
     IncomingAccept, IncomingConn, IncomingConnInner, IncomingConnect, IncomingListen,
 };
-use proto::relay::messages::{IncomingConnection, InitConnection, RejectConnection};
+use proto::relay::messages::{InitConnection, RelayListenIn, RelayListenOut};
 use proto::relay::serialize::{
-    deserialize_init_connection, deserialize_reject_connection, serialize_incoming_connection,
+    deserialize_init_connection, deserialize_relay_listen_in, serialize_relay_listen_out,
 };
 
 async fn dispatch_conn<FT>(
@@ -25,8 +25,8 @@ async fn dispatch_conn<FT>(
     mut keepalive_transform: FT,
 ) -> Option<
     IncomingConn<
-        impl Stream<Item = RejectConnection> + Unpin,
-        impl Sink<IncomingConnection, SinkError = ()> + Unpin,
+        impl Stream<Item = RelayListenIn> + Unpin,
+        impl Sink<RelayListenOut, SinkError = ()> + Unpin,
         impl Stream<Item = Vec<u8>> + Unpin,
         impl Sink<Vec<u8>, SinkError = ()> + Unpin,
         impl Stream<Item = Vec<u8>> + Unpin,
@@ -42,10 +42,10 @@ where
     let inner = match deserialize_init_connection(&first_msg).ok()? {
         InitConnection::Listen => IncomingConnInner::Listen(IncomingListen {
             receiver: receiver
-                .map(|data| deserialize_reject_connection(&data))
+                .map(|data| deserialize_relay_listen_in(&data))
                 .take_while(|res| future::ready(res.is_ok()))
                 .map(Result::unwrap),
-            sender: sender.with(|msg| future::ready(Ok(serialize_incoming_connection(&msg)))),
+            sender: sender.with(|msg| future::ready(Ok(serialize_relay_listen_out(&msg)))),
         }),
         InitConnection::Accept(accept_public_key) => IncomingConnInner::Accept(IncomingAccept {
             receiver,
@@ -73,8 +73,8 @@ async fn process_conn<FT>(
     conn_timeout_ticks: usize,
 ) -> Option<
     IncomingConn<
-        impl Stream<Item = RejectConnection> + Unpin,
-        impl Sink<IncomingConnection, SinkError = ()> + Unpin,
+        impl Stream<Item = RelayListenIn> + Unpin,
+        impl Sink<RelayListenOut, SinkError = ()> + Unpin,
         impl Stream<Item = Vec<u8>> + Unpin,
         impl Sink<Vec<u8>, SinkError = ()> + Unpin,
         impl Stream<Item = Vec<u8>> + Unpin,
@@ -125,8 +125,8 @@ pub fn conn_processor<T, FT>(
     conn_timeout_ticks: usize,
 ) -> impl Stream<
     Item = IncomingConn<
-        impl Stream<Item = RejectConnection>,
-        impl Sink<IncomingConnection, SinkError = ()>,
+        impl Stream<Item = RelayListenIn>,
+        impl Sink<RelayListenOut, SinkError = ()>,
         impl Stream<Item = Vec<u8>>,
         impl Sink<Vec<u8>, SinkError = ()>,
         impl Stream<Item = Vec<u8>>,
@@ -1,5 +1,6 @@
 mod conn_limiter;
 mod conn_processor;
 pub mod net_server;
+mod rate_limit;
 mod server;
 mod types;
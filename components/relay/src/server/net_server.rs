@@ -1,18 +1,22 @@
 use std::marker::Unpin;
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
-use futures::{FutureExt, Stream, StreamExt, TryFutureExt};
+use futures::{Future, FutureExt, Stream, StreamExt, TryFutureExt};
 
 use derive_more::*;
 
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
 use common::transform_pool::transform_pool_loop;
 
-use proto::consts::{CONN_TIMEOUT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
+use proto::consts::{
+    CONN_TIMEOUT_TICKS, INCOMING_CONN_WAIT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION,
+    REKEY_MIN_TICKS, TICKS_TO_REKEY,
+};
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
+use crypto::sym_encrypt::SymEncryptAlgorithm;
 
 use identity::IdentityClient;
 use keepalive::KeepAliveChannel;
@@ -22,8 +26,11 @@ use secure_channel::SecureChannel;
 use version::VersionPrefix;
 
 use super::conn_processor::conn_processor;
+pub use super::rate_limit::RateLimitConfig;
 use super::server::relay_server_loop;
-pub use super::server::RelayServerError;
+pub use super::server::{
+    RelayServerError, RelayServerHandle, RelayServerHandleError, RelayServerRequest, ShutdownHandle,
+};
 
 /// A relay server loop. Incoming connections should contain both (sender, receiver) and a
 /// public_key of the remote side (Should be obtained after authentication).
@@ -32,11 +39,18 @@ pub use super::server::RelayServerError;
 /// its purpose.
 /// `keepalive_ticks` is the amount of time we are willing to let the remote side to be idle before
 /// we disconnect. It is also used to timeout open half tunnels that were not claimed.
+/// `rate_limit` is the rate limit applied separately to every connection.
+/// `drain_timeout_ticks` is the amount of time we are willing to wait for open tunnels to close
+/// after a shutdown was requested through `shutdown_receiver`.
 async fn relay_server<IC, S>(
     incoming_conns: IC,
+    incoming_requests: mpsc::Receiver<RelayServerRequest>,
+    shutdown_receiver: oneshot::Receiver<()>,
     timer_client: TimerClient,
     conn_timeout_ticks: usize,
     keepalive_ticks: usize,
+    drain_timeout_ticks: usize,
+    rate_limit: RateLimitConfig,
     spawner: S,
 ) -> Result<(), RelayServerError>
 where
@@ -65,7 +79,11 @@ where
     await!(relay_server_loop(
         timer_client,
         processed_conns,
+        incoming_requests,
+        shutdown_receiver,
         half_tunnel_ticks,
+        drain_timeout_ticks,
+        rate_limit,
         spawner
     ))
 }
@@ -73,6 +91,7 @@ where
 #[derive(Debug, From)]
 pub enum NetRelayServerError {
     RelayServerError(RelayServerError),
+    RequestTimerStreamError,
     SpawnError,
 }
 
@@ -104,12 +123,16 @@ where
     }
 }
 
-pub async fn net_relay_server<IRC, R, S>(
+async fn net_relay_server_loop<IRC, R, S>(
     incoming_raw_conns: IRC,
+    incoming_requests: mpsc::Receiver<RelayServerRequest>,
+    shutdown_receiver: oneshot::Receiver<()>,
     identity_client: IdentityClient,
     timer_client: TimerClient,
     rng: R,
     max_concurrent_encrypt: usize,
+    drain_timeout_ticks: usize,
+    rate_limit: RateLimitConfig,
     mut spawner: S,
 ) -> Result<(), NetRelayServerError>
 where
@@ -117,6 +140,10 @@ where
     R: CryptoRandom + Clone + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
+    let mut c_timer_client = timer_client.clone();
+    let pool_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NetRelayServerError::RequestTimerStreamError)?;
+
     let version_transform = VersionPrefix::new(PROTOCOL_VERSION, spawner.clone());
 
     let encrypt_transform = SecureChannel::new(
@@ -124,6 +151,8 @@ where
         rng,
         timer_client.clone(),
         TICKS_TO_REKEY,
+        REKEY_MIN_TICKS,
+        SymEncryptAlgorithm::default(),
         spawner.clone(),
     );
 
@@ -143,6 +172,8 @@ where
         enc_conns_sender,
         AnonSecureChannel::new(encrypt_transform),
         max_concurrent_encrypt,
+        pool_timer_stream,
+        INCOMING_CONN_WAIT_TICKS,
         spawner.clone(),
     )
     .map_err(|e| error!("transform_pool_loop() error: {:?}", e))
@@ -154,10 +185,56 @@ where
 
     await!(relay_server(
         incoming_enc_conns,
+        incoming_requests,
+        shutdown_receiver,
         timer_client,
         CONN_TIMEOUT_TICKS,
         KEEPALIVE_TICKS,
+        drain_timeout_ticks,
+        rate_limit,
         spawner.clone()
     ))?;
     Ok(())
 }
+
+/// `incoming_requests` receives queries sent through a `RelayServerHandle`.
+/// `rate_limit` configures the amount of bytes every relayed connection may send. A connection
+/// exceeding the rate limit is not read from until its token bucket is refilled.
+///
+/// Returns a `ShutdownHandle` together with the relay server future. After a shutdown is
+/// requested, the relay server future waits up to `drain_timeout_ticks` for the open tunnels to
+/// close, and then completes.
+pub fn net_relay_server<IRC, R, S>(
+    incoming_raw_conns: IRC,
+    incoming_requests: mpsc::Receiver<RelayServerRequest>,
+    identity_client: IdentityClient,
+    timer_client: TimerClient,
+    rng: R,
+    max_concurrent_encrypt: usize,
+    drain_timeout_ticks: usize,
+    rate_limit: RateLimitConfig,
+    spawner: S,
+) -> (
+    ShutdownHandle,
+    impl Future<Output = Result<(), NetRelayServerError>>,
+)
+where
+    IRC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+    let relay_server_fut = net_relay_server_loop(
+        incoming_raw_conns,
+        incoming_requests,
+        shutdown_receiver,
+        identity_client,
+        timer_client,
+        rng,
+        max_concurrent_encrypt,
+        drain_timeout_ticks,
+        rate_limit,
+        spawner,
+    );
+    (ShutdownHandle::new(shutdown_sender), relay_server_fut)
+}
@@ -0,0 +1,127 @@
+use std::convert::TryFrom;
+
+/// Configuration of the rate limit applied to every relayed connection.
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Maximum amount of bytes a connection may send in a burst.
+    pub capacity: usize,
+    /// Amount of bytes added to the bucket of a connection on every time tick.
+    pub refill_per_tick: usize,
+}
+
+/// A token bucket, counting bytes.
+/// Initially the bucket is full.
+///
+/// A message is always allowed to take more tokens than the bucket holds. The missing tokens
+/// are recorded as debt, which is paid before the bucket is refilled again. This allows messages
+/// larger than the capacity of the bucket to pass, while keeping the average rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: usize,
+    refill_per_tick: usize,
+    tokens: usize,
+    debt: usize,
+    last_tick: u64,
+}
+
+impl TokenBucket {
+    pub fn new(rate_limit: &RateLimitConfig, cur_tick: u64) -> Self {
+        TokenBucket {
+            capacity: rate_limit.capacity,
+            refill_per_tick: rate_limit.refill_per_tick,
+            tokens: rate_limit.capacity,
+            debt: 0,
+            last_tick: cur_tick,
+        }
+    }
+
+    /// Add tokens to the bucket for every tick that passed since the last refill,
+    /// never exceeding the capacity of the bucket.
+    /// Debt is paid first.
+    pub fn refill(&mut self, cur_tick: u64) {
+        let elapsed = cur_tick.wrapping_sub(self.last_tick);
+        self.last_tick = cur_tick;
+
+        let elapsed = usize::try_from(elapsed).unwrap_or(usize::max_value());
+        let refill = self.refill_per_tick.saturating_mul(elapsed);
+
+        let paid = refill.min(self.debt);
+        self.debt -= paid;
+        let refill = refill - paid;
+
+        self.tokens = self.tokens.saturating_add(refill).min(self.capacity);
+    }
+
+    /// Take `amount` tokens from the bucket.
+    /// If there are not enough tokens in the bucket, the bucket is emptied and the rest is
+    /// recorded as debt.
+    pub fn take(&mut self, amount: usize) {
+        match self.tokens.checked_sub(amount) {
+            Some(new_tokens) => self.tokens = new_tokens,
+            None => {
+                self.debt = self.debt.saturating_add(amount - self.tokens);
+                self.tokens = 0;
+            }
+        }
+    }
+
+    /// Is the bucket empty? Nothing should be taken from an empty bucket until it is refilled.
+    pub fn is_empty(&self) -> bool {
+        self.tokens == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let rate_limit = RateLimitConfig {
+            capacity: 10,
+            refill_per_tick: 4,
+        };
+        let mut token_bucket = TokenBucket::new(&rate_limit, 0);
+        assert!(!token_bucket.is_empty());
+        token_bucket.take(7);
+        assert!(!token_bucket.is_empty());
+        token_bucket.take(3);
+        assert!(token_bucket.is_empty());
+
+        // No time has passed:
+        token_bucket.refill(0);
+        assert!(token_bucket.is_empty());
+
+        token_bucket.refill(1);
+        assert!(!token_bucket.is_empty());
+        token_bucket.take(4);
+        assert!(token_bucket.is_empty());
+
+        // The bucket never holds more than its capacity:
+        token_bucket.refill(100);
+        token_bucket.take(10);
+        assert!(token_bucket.is_empty());
+    }
+
+    #[test]
+    fn test_token_bucket_debt() {
+        let rate_limit = RateLimitConfig {
+            capacity: 10,
+            refill_per_tick: 4,
+        };
+        let mut token_bucket = TokenBucket::new(&rate_limit, 0);
+        // Larger than the capacity: 10 tokens are taken, 15 are recorded as debt.
+        token_bucket.take(25);
+        assert!(token_bucket.is_empty());
+
+        // 16 tokens were added, 15 of them pay the debt:
+        token_bucket.refill(4);
+        assert!(!token_bucket.is_empty());
+        token_bucket.take(1);
+        assert!(token_bucket.is_empty());
+
+        token_bucket.refill(5);
+        token_bucket.take(3);
+        assert!(!token_bucket.is_empty());
+    }
+}
@@ -1,19 +1,27 @@
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
+use futures::future::Either;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
-use std::collections::{HashMap, HashSet};
+use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::marker::Unpin;
 
 use common::futures_compat::send_to_sink;
+use common::int_convert::usize_to_u64;
 use common::select_streams::{select_streams, BoxStream};
 use crypto::identity::PublicKey;
 use timer::TimerClient;
 
-use proto::relay::messages::{IncomingConnection, RejectConnection};
+use proto::relay::messages::{
+    ConnectionStats, IncomingConnection, RejectConnection, RelayListenIn, RelayListenOut,
+};
 
+use super::rate_limit::{RateLimitConfig, TokenBucket};
 use super::types::{IncomingAccept, IncomingConn, IncomingConnInner};
 
+/// Amount of closed connections we keep statistics for.
+const MAX_RECENT_CONNECTION_STATS: usize = 100;
+
 struct ConnPair<M, K> {
     receiver: M,
     sender: K,
@@ -28,11 +36,27 @@ impl<M, K> ConnPair<M, K> {
 struct HalfTunnel<MT, KT> {
     conn_pair: ConnPair<MT, KT>,
     ticks_to_close: usize,
+    connected_at_tick: u64,
+}
+
+/// An open tunnel. Allows to notify both directions of the tunnel that the relay server is
+/// closing.
+struct Tunnel {
+    closing_senders: Vec<oneshot::Sender<()>>,
+}
+
+impl Tunnel {
+    /// Ask the tunnel to stop forwarding messages and close.
+    fn close(&mut self) {
+        for closing_sender in self.closing_senders.drain(..) {
+            let _ = closing_sender.send(());
+        }
+    }
 }
 
 struct Listener<MT, KT> {
     half_tunnels: HashMap<PublicKey, HalfTunnel<MT, KT>>,
-    tunnels: HashSet<PublicKey>,
+    tunnels: HashMap<PublicKey, Tunnel>,
     opt_sender: Option<mpsc::Sender<IncomingConnection>>,
 }
 
@@ -40,23 +64,85 @@ impl<MT, KT> Listener<MT, KT> {
     fn new(sender: mpsc::Sender<IncomingConnection>) -> Self {
         Listener {
             half_tunnels: HashMap::new(),
-            tunnels: HashSet::new(),
+            tunnels: HashMap::new(),
             opt_sender: Some(sender),
         }
     }
 }
 
+/// One direction of a tunnel has finished. The tunnel is closing.
+struct TunnelClosing {
+    init_public_key: PublicKey,
+    listen_public_key: PublicKey,
+}
+
+/// Both directions of a tunnel have finished.
 struct TunnelClosed {
     init_public_key: PublicKey,
     listen_public_key: PublicKey,
+    init_stats: ConnectionStats,
+    listen_stats: ConnectionStats,
+}
+
+pub enum RelayServerRequest {
+    /// Statistics of the last closed connections, oldest first.
+    RecentConnectionStats(oneshot::Sender<Vec<(PublicKey, ConnectionStats)>>),
+}
+
+#[derive(Debug)]
+pub struct RelayServerHandleError;
+
+/// A handle for gracefully shutting down a running relay server.
+pub struct ShutdownHandle {
+    shutdown_sender: oneshot::Sender<()>,
+}
+
+impl ShutdownHandle {
+    pub fn new(shutdown_sender: oneshot::Sender<()>) -> Self {
+        ShutdownHandle { shutdown_sender }
+    }
+
+    /// Stop accepting new connections and close all existing tunnels.
+    /// The relay server completes once all the tunnels were closed.
+    pub fn shutdown(self) {
+        let _ = self.shutdown_sender.send(());
+    }
+}
+
+/// A handle for querying a running relay server.
+#[derive(Clone)]
+pub struct RelayServerHandle {
+    requests_sender: mpsc::Sender<RelayServerRequest>,
+}
+
+impl RelayServerHandle {
+    pub fn new(requests_sender: mpsc::Sender<RelayServerRequest>) -> Self {
+        RelayServerHandle { requests_sender }
+    }
+
+    /// Get the statistics of the last (up to `MAX_RECENT_CONNECTION_STATS`) closed connections,
+    /// oldest first. Statistics of a connection are only available after it was closed.
+    pub async fn recent_connection_stats(
+        &mut self,
+    ) -> Result<Vec<(PublicKey, ConnectionStats)>, RelayServerHandleError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        await!(self
+            .requests_sender
+            .send(RelayServerRequest::RecentConnectionStats(response_sender)))
+        .map_err(|_| RelayServerHandleError)?;
+        await!(response_receiver).map_err(|_| RelayServerHandleError)
+    }
 }
 
 enum RelayServerEvent<ML, KL, MA, KA, MC, KC> {
     IncomingConn(IncomingConn<ML, KL, MA, KA, MC, KC>),
     IncomingConnsClosed,
+    TunnelClosing(TunnelClosing),
     TunnelClosed(TunnelClosed),
     ListenerMessage((PublicKey, RejectConnection)),
     ListenerClosed(PublicKey),
+    Request(RelayServerRequest),
+    Shutdown,
     TimerTick,
     TimerClosed,
 }
@@ -68,9 +154,12 @@ impl<ML, KL, MA, KA, MC, KC> fmt::Debug for RelayServerEvent<ML, KL, MA, KA, MC,
             RelayServerEvent::IncomingConnsClosed => {
                 write!(f, "RelayServerEvent::IncomingConnsClosed")
             }
+            RelayServerEvent::TunnelClosing(_) => write!(f, "RelayServerEvent::TunnelClosing"),
             RelayServerEvent::TunnelClosed(_) => write!(f, "RelayServerEvent::TunnelClosed"),
             RelayServerEvent::ListenerMessage(_) => write!(f, "RelayServerEvent::ListenerMessage"),
             RelayServerEvent::ListenerClosed(_) => write!(f, "RelayServerEvent::ListenerClosed"),
+            RelayServerEvent::Request(_) => write!(f, "RelayServerEvent::Request"),
+            RelayServerEvent::Shutdown => write!(f, "RelayServerEvent::Shutdown"),
             RelayServerEvent::TimerTick => write!(f, "RelayServerEvent::TimerTick"),
             RelayServerEvent::TimerClosed => write!(f, "RelayServerEvent::TimerClosed"),
         }
@@ -87,14 +176,114 @@ pub enum RelayServerError {
     NoPendingHalfTunnel,
     AlreadyListening,
     EventReceiverError,
+    DrainTimeout,
+}
+
+/// Statistics of one direction of a tunnel.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ForwardStats {
+    /// Amount of messages forwarded
+    num_messages: u64,
+    /// Amount of bytes forwarded
+    num_bytes: u64,
+}
+
+enum ForwardEvent {
+    Message(Vec<u8>),
+    ReceiverClosed,
+    Closing,
+    TimerTick,
+    TimerClosed,
+}
+
+/// Forward all messages from `receiver` to `sender`, until `receiver` is closed or a closing
+/// marker is received through `closing_receiver`.
+///
+/// Messages are never dropped: A tunnel may carry a SecureChannel, which can not recover from a
+/// lost message. Instead, once the rate limit is exceeded we stop reading from `receiver` until
+/// the token bucket is refilled. This applies backpressure to this tunnel only.
+///
+/// Returns statistics about the forwarded messages.
+async fn forward_messages<M, K>(
+    receiver: M,
+    mut sender: K,
+    closing_receiver: oneshot::Receiver<()>,
+    mut timer_client: TimerClient,
+    rate_limit: RateLimitConfig,
+) -> ForwardStats
+where
+    M: Stream<Item = Vec<u8>> + Unpin + Send,
+    K: Sink<Vec<u8>, SinkError = ()> + Unpin,
+{
+    let mut forward_stats = ForwardStats::default();
+
+    let timer_stream = match await!(timer_client.request_timer_stream()) {
+        Ok(timer_stream) => timer_stream,
+        Err(_) => {
+            error!("forward_messages(): Failed to obtain a timer stream");
+            return forward_stats;
+        }
+    };
+    let timer_stream = timer_stream
+        .map(|_| ForwardEvent::TimerTick)
+        .chain(stream::once(future::ready(ForwardEvent::TimerClosed)));
+
+    let mut incoming = receiver
+        .map(ForwardEvent::Message)
+        .chain(stream::once(future::ready(ForwardEvent::ReceiverClosed)));
+    // A canceled closing_receiver is not a closing marker:
+    let closing_receiver = stream::once(closing_receiver)
+        .filter_map(|res| future::ready(res.ok().map(|()| ForwardEvent::Closing)));
+    let mut other_events = stream::select(closing_receiver, timer_stream);
+
+    let mut cur_tick: u64 = 0;
+    let mut token_bucket = TokenBucket::new(&rate_limit, cur_tick);
+
+    loop {
+        // While the token bucket is empty we do not read incoming messages, so that the pressure
+        // is propagated back to the sender:
+        let opt_event = if token_bucket.is_empty() {
+            await!(other_events.next())
+        } else {
+            await!(stream::select(&mut incoming, &mut other_events).next())
+        };
+        let event = match opt_event {
+            Some(event) => event,
+            None => break,
+        };
+        let message = match event {
+            ForwardEvent::Message(message) => message,
+            ForwardEvent::TimerTick => {
+                cur_tick = cur_tick.wrapping_add(1);
+                token_bucket.refill(cur_tick);
+                continue;
+            }
+            ForwardEvent::ReceiverClosed | ForwardEvent::Closing | ForwardEvent::TimerClosed => {
+                break
+            }
+        };
+        let message_len = usize_to_u64(message.len()).unwrap();
+        token_bucket.take(message.len());
+        if await!(sender.send(message)).is_err() {
+            error!("forward_messages(): Send error");
+            break;
+        }
+        forward_stats.num_messages = forward_stats.num_messages.saturating_add(1);
+        forward_stats.num_bytes = forward_stats.num_bytes.saturating_add(message_len);
+    }
+    forward_stats
 }
 
-fn handle_accept<MT, KT, MA, KA, TCL>(
+fn handle_accept<MT, KT, MA, KA, TCG, TCL>(
     listeners: &mut HashMap<PublicKey, Listener<MT, KT>>,
     acceptor_public_key: PublicKey,
     incoming_accept: IncomingAccept<MA, KA>,
-    // TODO: This should be a oneshot:
+    cur_tick: u64,
+    // TODO: These should be oneshots:
+    tunnel_closing_sender: TCG,
     tunnel_closed_sender: TCL,
+    timer_client: TimerClient,
+    rate_limit: RateLimitConfig,
     mut spawner: impl Spawn,
 ) -> Result<(), RelayServerError>
 where
@@ -102,6 +291,7 @@ where
     KT: Sink<Vec<u8>, SinkError = ()> + Unpin + Send + 'static,
     MA: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
     KA: Sink<Vec<u8>, SinkError = ()> + Unpin + Send + 'static,
+    TCG: Sink<TunnelClosing, SinkError = ()> + Unpin + Send + 'static,
     TCL: Sink<TunnelClosed, SinkError = ()> + Unpin + Send + 'static,
 {
     let listener = match listeners.get_mut(&acceptor_public_key) {
@@ -109,55 +299,110 @@ where
         None => return Err(RelayServerError::ListeningNotInProgress),
     };
     let IncomingAccept {
-        mut receiver,
-        mut sender,
+        receiver,
+        sender,
         accept_public_key,
     } = incoming_accept;
-    let conn_pair = match listener.half_tunnels.remove(&accept_public_key) {
-        Some(HalfTunnel { conn_pair, .. }) => conn_pair,
+    let (conn_pair, init_connected_at_tick) = match listener.half_tunnels.remove(&accept_public_key)
+    {
+        Some(HalfTunnel {
+            conn_pair,
+            connected_at_tick,
+            ..
+        }) => (conn_pair, connected_at_tick),
         None => return Err(RelayServerError::NoPendingHalfTunnel),
     };
-    let c_accept_public_key = accept_public_key.clone();
 
     let ConnPair {
-        sender: mut remote_sender,
-        receiver: mut remote_receiver,
+        sender: remote_sender,
+        receiver: remote_receiver,
     } = conn_pair;
 
-    let send_fut1 = async move {
-        await!(remote_sender
-            .send_all(&mut receiver)
-            .map_err(|e| error!("send_fut1 error: {:?}", e))
-            .then(|_| future::ready(())))
+    let (listen_closing_sender, listen_closing_receiver) = oneshot::channel();
+    let (init_closing_sender, init_closing_receiver) = oneshot::channel();
+    let tunnel = Tunnel {
+        closing_senders: vec![listen_closing_sender, init_closing_sender],
     };
-    let send_fut2 = async move {
-        await!(sender
-            .send_all(&mut remote_receiver)
-            .map_err(|e| error!("send_fut2 error: {:?}", e))
-            .then(move |_| {
-                let tunnel_closed = TunnelClosed {
-                    init_public_key: c_accept_public_key,
-                    listen_public_key: acceptor_public_key,
-                };
-                send_to_sink(tunnel_closed_sender, tunnel_closed).then(|_| future::ready(()))
-            }))
+    listener.tunnels.insert(accept_public_key.clone(), tunnel);
+
+    let tunnel_fut = async move {
+        // listener --> initiator and initiator --> listener.
+        // Every direction of the tunnel is rate limited separately:
+        let listen_fut = Box::pin(forward_messages(
+            receiver,
+            remote_sender,
+            listen_closing_receiver,
+            timer_client.clone(),
+            rate_limit.clone(),
+        ));
+        let init_fut = Box::pin(forward_messages(
+            remote_receiver,
+            sender,
+            init_closing_receiver,
+            timer_client,
+            rate_limit,
+        ));
+
+        // Once one direction has finished, the tunnel is closing. The relay server removes the
+        // tunnel right away (And stops the other direction), so that the same pair of nodes
+        // can open a new tunnel without waiting for the old one to finish.
+        let tunnel_closing = TunnelClosing {
+            init_public_key: accept_public_key.clone(),
+            listen_public_key: acceptor_public_key.clone(),
+        };
+        let (listen_stats, init_stats) = match await!(future::select(listen_fut, init_fut)) {
+            Either::Left((listen_stats, init_fut)) => {
+                let _ = await!(send_to_sink(tunnel_closing_sender, tunnel_closing));
+                (listen_stats, await!(init_fut))
+            }
+            Either::Right((init_stats, listen_fut)) => {
+                let _ = await!(send_to_sink(tunnel_closing_sender, tunnel_closing));
+                (await!(listen_fut), init_stats)
+            }
+        };
+
+        let tunnel_closed = TunnelClosed {
+            init_public_key: accept_public_key,
+            listen_public_key: acceptor_public_key,
+            init_stats: ConnectionStats {
+                connected_at_tick: init_connected_at_tick,
+                bytes_sent: init_stats.num_bytes,
+                bytes_received: listen_stats.num_bytes,
+                messages_sent: init_stats.num_messages,
+            },
+            listen_stats: ConnectionStats {
+                connected_at_tick: cur_tick,
+                bytes_sent: listen_stats.num_bytes,
+                bytes_received: init_stats.num_bytes,
+                messages_sent: listen_stats.num_messages,
+            },
+        };
+        let _ = await!(send_to_sink(tunnel_closed_sender, tunnel_closed));
     };
 
-    spawner.spawn(send_fut1).unwrap();
-    spawner.spawn(send_fut2).unwrap();
+    spawner.spawn(tunnel_fut).unwrap();
 
     Ok(())
 }
 
+/// Main loop of the relay server.
+///
+/// Once a shutdown is requested through `shutdown_receiver`, new connections are discarded and
+/// all open tunnels are asked to close. The loop then waits up to `drain_timeout_ticks` for the
+/// tunnels to close.
 pub async fn relay_server_loop<ML, KL, MA, KA, MC, KC, S>(
     mut timer_client: TimerClient,
     incoming_conns: S,
+    incoming_requests: mpsc::Receiver<RelayServerRequest>,
+    shutdown_receiver: oneshot::Receiver<()>,
     half_tunnel_ticks: usize,
+    drain_timeout_ticks: usize,
+    rate_limit: RateLimitConfig,
     mut spawner: impl Spawn + Clone,
 ) -> Result<(), RelayServerError>
 where
-    ML: Stream<Item = RejectConnection> + Unpin + Send + 'static,
-    KL: Sink<IncomingConnection, SinkError = ()> + Unpin + Send + 'static,
+    ML: Stream<Item = RelayListenIn> + Unpin + Send + 'static,
+    KL: Sink<RelayListenOut, SinkError = ()> + Unpin + Send + 'static,
     MA: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
     KA: Sink<Vec<u8>, SinkError = ()> + Unpin + Send + 'static,
     MC: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
@@ -176,17 +421,37 @@ where
             RelayServerEvent::IncomingConnsClosed,
         )));
 
+    let incoming_requests = incoming_requests.map(RelayServerEvent::Request);
+
+    // A dropped ShutdownHandle does not cause a shutdown:
+    let shutdown_receiver = stream::once(shutdown_receiver)
+        .filter_map(|res| future::ready(res.ok().map(|()| RelayServerEvent::Shutdown)));
+
     let (event_sender, event_receiver) = mpsc::channel::<RelayServerEvent<_, _, _, _, _, _>>(0);
 
-    let mut relay_server_events = select_streams![timer_stream, incoming_conns, event_receiver];
+    let mut relay_server_events = select_streams![
+        timer_stream,
+        incoming_conns,
+        incoming_requests,
+        shutdown_receiver,
+        event_receiver
+    ];
 
     let mut incoming_conns_closed = false;
+    // Amount of ticks left until we give up on draining the open tunnels.
+    // Some(_) means that we are shutting down:
+    let mut opt_drain_ticks: Option<usize> = None;
     let mut listeners: HashMap<PublicKey, Listener<_, _>> = HashMap::new();
+    let mut cur_tick: u64 = 0;
+    let mut recent_connection_stats: VecDeque<(PublicKey, ConnectionStats)> = VecDeque::new();
 
     while let Some(relay_server_event) = await!(relay_server_events.next()) {
         let c_event_sender = event_sender.clone().sink_map_err(|_| ());
         match relay_server_event {
             RelayServerEvent::IncomingConn(incoming_conn) => {
+                if opt_drain_ticks.is_some() {
+                    continue; // Shutting down, discard connection
+                }
                 let IncomingConn { public_key, inner } = incoming_conn;
                 match inner {
                     IncomingConnInner::Listen(incoming_listen) => {
@@ -195,43 +460,64 @@ where
                         }
 
                         let sender = incoming_listen.sender;
-                        let receiver = incoming_listen.receiver;
+                        let mut receiver = incoming_listen.receiver;
+
+                        // Pings from the listener are answered directly, without going through
+                        // the main loop:
+                        let (pong_sender, pong_receiver) = mpsc::channel::<()>(0);
 
                         // Change the sender to be an mpsc::Sender, so that we can use the
                         // try_send() function.
-                        let (mpsc_sender, mut mpsc_receiver) =
-                            mpsc::channel::<IncomingConnection>(0);
+                        let (mpsc_sender, mpsc_receiver) = mpsc::channel::<IncomingConnection>(0);
+                        // The listen connection is closed once mpsc_sender is dropped:
+                        let incoming_connections = mpsc_receiver
+                            .map(|incoming_connection| {
+                                Some(RelayListenOut::IncomingConnection(incoming_connection))
+                            })
+                            .chain(stream::once(future::ready(None)));
+                        let pongs = pong_receiver.map(|()| Some(RelayListenOut::Pong));
+                        let mut outgoing = select_streams![incoming_connections, pongs]
+                            .take_while(|opt_relay_listen_out| {
+                                future::ready(opt_relay_listen_out.is_some())
+                            })
+                            .map(Option::unwrap);
                         spawner
                             .spawn(async move {
                                 let mut sender = sender.sink_map_err(|_| ());
-                                await!(sender
-                                    .send_all(&mut mpsc_receiver)
-                                    .then(|_| future::ready(())))
+                                await!(sender.send_all(&mut outgoing).then(|_| future::ready(())))
                             })
                             .unwrap();
                         let listener = Listener::new(mpsc_sender);
                         listeners.insert(public_key.clone(), listener);
-                        let c_public_key = public_key.clone();
-                        let mut receiver = receiver
-                            .map(move |reject_connection| {
-                                RelayServerEvent::ListenerMessage((
-                                    c_public_key.clone(),
-                                    reject_connection,
-                                ))
-                            })
-                            .chain(stream::once(future::ready(
-                                RelayServerEvent::ListenerClosed(public_key.clone()),
-                            )));
                         spawner
                             .spawn(async move {
                                 let mut c_event_sender = c_event_sender.sink_map_err(|_| ());
-                                await!(c_event_sender
-                                    .send_all(&mut receiver)
-                                    .then(|_| future::ready(())))
+                                let mut pong_sender = pong_sender.sink_map_err(|_| ());
+                                while let Some(relay_listen_in) = await!(receiver.next()) {
+                                    let res = match relay_listen_in {
+                                        RelayListenIn::RejectConnection(reject_connection) => {
+                                            await!(c_event_sender.send(
+                                                RelayServerEvent::ListenerMessage((
+                                                    public_key.clone(),
+                                                    reject_connection
+                                                ))
+                                            ))
+                                        }
+                                        RelayListenIn::Ping => await!(pong_sender.send(())),
+                                    };
+                                    if res.is_err() {
+                                        return;
+                                    }
+                                }
+                                let _ = await!(c_event_sender
+                                    .send(RelayServerEvent::ListenerClosed(public_key)));
                             })
                             .unwrap();
                     }
                     IncomingConnInner::Accept(incoming_accept) => {
+                        let tunnel_closing_sender = c_event_sender.clone().with(|tunnel_closing| {
+                            future::ready(Ok(RelayServerEvent::TunnelClosing(tunnel_closing)))
+                        });
                         let tunnel_closed_sender = c_event_sender.with(|tunnel_closed| {
                             future::ready(Ok(RelayServerEvent::TunnelClosed(tunnel_closed)))
                         });
@@ -239,7 +525,11 @@ where
                             &mut listeners,
                             public_key.clone(),
                             incoming_accept,
+                            cur_tick,
+                            tunnel_closing_sender,
                             tunnel_closed_sender,
+                            timer_client.clone(),
+                            rate_limit.clone(),
                             spawner.clone(),
                         )
                         .map_err(|e| warn!("handle_accept() error: {:?}", e));
@@ -251,7 +541,7 @@ where
                             None => continue, // Discard Connect connection
                         };
                         if listener.half_tunnels.contains_key(&public_key)
-                            || listener.tunnels.contains(&public_key)
+                            || listener.tunnels.contains_key(&public_key)
                         {
                             continue;
                         }
@@ -262,6 +552,7 @@ where
                                 incoming_connect.sender,
                             ),
                             ticks_to_close: half_tunnel_ticks,
+                            connected_at_tick: cur_tick,
                         };
                         if let Some(sender) = &mut listener.opt_sender {
                             // Try to send a message to listener about new pending connection:
@@ -277,14 +568,30 @@ where
                 }
             }
             RelayServerEvent::IncomingConnsClosed => incoming_conns_closed = true,
-            RelayServerEvent::TunnelClosed(tunnel_closed) => {
-                let listener = match listeners.get_mut(&tunnel_closed.listen_public_key) {
+            RelayServerEvent::TunnelClosing(tunnel_closing) => {
+                let listener = match listeners.get_mut(&tunnel_closing.listen_public_key) {
                     Some(listener) => listener,
                     None => continue,
                 };
-                listener.tunnels.remove(&tunnel_closed.init_public_key);
+                if let Some(mut tunnel) = listener.tunnels.remove(&tunnel_closing.init_public_key) {
+                    // Stop the direction that is still open:
+                    tunnel.close();
+                }
                 if listener.opt_sender.is_none() && listener.tunnels.is_empty() {
-                    listeners.remove(&tunnel_closed.listen_public_key);
+                    listeners.remove(&tunnel_closing.listen_public_key);
+                }
+            }
+            RelayServerEvent::TunnelClosed(tunnel_closed) => {
+                recent_connection_stats.push_back((
+                    tunnel_closed.init_public_key.clone(),
+                    tunnel_closed.init_stats,
+                ));
+                recent_connection_stats.push_back((
+                    tunnel_closed.listen_public_key.clone(),
+                    tunnel_closed.listen_stats,
+                ));
+                while recent_connection_stats.len() > MAX_RECENT_CONNECTION_STATS {
+                    let _ = recent_connection_stats.pop_front();
                 }
             }
             RelayServerEvent::ListenerMessage((
@@ -310,7 +617,33 @@ where
                     listeners.remove(&public_key);
                 }
             }
+            RelayServerEvent::Request(RelayServerRequest::RecentConnectionStats(
+                response_sender,
+            )) => {
+                let _ = response_sender.send(recent_connection_stats.iter().cloned().collect());
+            }
+            RelayServerEvent::Shutdown => {
+                info!("relay_server_loop(): Shutting down");
+                opt_drain_ticks = Some(drain_timeout_ticks);
+                for listener in listeners.values_mut() {
+                    // Close the listen connection and all pending half tunnels:
+                    listener.opt_sender = None;
+                    listener.half_tunnels = HashMap::new();
+                    for tunnel in listener.tunnels.values_mut() {
+                        tunnel.close();
+                    }
+                }
+                // Listeners are kept until all their tunnels are closed:
+                listeners.retain(|_public_key, listener| !listener.tunnels.is_empty());
+            }
             RelayServerEvent::TimerTick => {
+                if let Some(drain_ticks) = &mut opt_drain_ticks {
+                    *drain_ticks = drain_ticks.saturating_sub(1);
+                    if *drain_ticks == 0 {
+                        return Err(RelayServerError::DrainTimeout);
+                    }
+                }
+                cur_tick = cur_tick.wrapping_add(1);
                 // Remove old half tunnels:
                 for listener in listeners.values_mut() {
                     listener
@@ -324,7 +657,7 @@ where
             }
             RelayServerEvent::TimerClosed => break,
         }
-        if incoming_conns_closed && listeners.is_empty() {
+        if (incoming_conns_closed || opt_drain_ticks.is_some()) && listeners.is_empty() {
             break;
         }
     }
@@ -337,11 +670,22 @@ mod tests {
     use futures::channel::mpsc;
     use futures::executor::ThreadPool;
     use futures::task::{Spawn, SpawnExt};
+    use futures::TryFutureExt;
 
     use super::super::types::{IncomingAccept, IncomingConnect, IncomingListen};
     use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
     use timer::create_timer_incoming;
 
+    const DRAIN_TIMEOUT_TICKS: usize = 8;
+
+    /// A rate limit that is never reached in the tests below.
+    fn rate_limit() -> RateLimitConfig {
+        RateLimitConfig {
+            capacity: 0x1000,
+            refill_per_tick: 0x1000,
+        }
+    }
+
     async fn task_relay_server_connect(
         mut spawner: impl Spawn + Clone + Send + 'static,
     ) -> Result<(), ()> {
@@ -350,13 +694,19 @@ mod tests {
         let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
 
         let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (_requests_sender, incoming_requests) = mpsc::channel(0);
+        let (_shutdown_sender, shutdown_receiver) = oneshot::channel();
 
         let half_tunnel_ticks: usize = 16;
 
         let fut_relay_server = relay_server_loop(
             timer_client,
             incoming_conns,
+            incoming_requests,
+            shutdown_receiver,
             half_tunnel_ticks,
+            DRAIN_TIMEOUT_TICKS,
+            rate_limit(),
             spawner.clone(),
         );
 
@@ -377,8 +727,8 @@ mod tests {
          * a_ac | --> c_ac | c_bc <-- | b_bc
          */
 
-        let (a_ac, c_ac) = mpsc::channel::<RejectConnection>(0);
-        let (c_ca, mut a_ca) = mpsc::channel::<IncomingConnection>(0);
+        let (a_ac, c_ac) = mpsc::channel::<RelayListenIn>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<RelayListenOut>(0);
         let (mut b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
         let (c_cb, mut b_cb) = mpsc::channel::<Vec<u8>>(0);
 
@@ -411,9 +761,9 @@ mod tests {
         let msg = await!(a_ca.next()).unwrap();
         assert_eq!(
             msg,
-            IncomingConnection {
+            RelayListenOut::IncomingConnection(IncomingConnection {
                 public_key: b_public_key.clone()
-            }
+            })
         );
 
         // Open a new connection to Accept:
@@ -467,13 +817,19 @@ mod tests {
         let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
 
         let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (_requests_sender, incoming_requests) = mpsc::channel(0);
+        let (_shutdown_sender, shutdown_receiver) = oneshot::channel();
 
         let half_tunnel_ticks: usize = 16;
 
         let fut_relay_server = relay_server_loop(
             timer_client,
             incoming_conns,
+            incoming_requests,
+            shutdown_receiver,
             half_tunnel_ticks,
+            DRAIN_TIMEOUT_TICKS,
+            rate_limit(),
             spawner.clone(),
         );
 
@@ -494,8 +850,8 @@ mod tests {
          * a_ac | --> c_ac | c_bc <-- | b_bc
          */
 
-        let (mut a_ac, c_ac) = mpsc::channel::<RejectConnection>(0);
-        let (c_ca, mut a_ca) = mpsc::channel::<IncomingConnection>(0);
+        let (mut a_ac, c_ac) = mpsc::channel::<RelayListenIn>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<RelayListenOut>(0);
         let (b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
         let (c_cb, mut b_cb) = mpsc::channel::<Vec<u8>>(0);
 
@@ -528,9 +884,9 @@ mod tests {
         let msg = await!(a_ca.next()).unwrap();
         assert_eq!(
             msg,
-            IncomingConnection {
+            RelayListenOut::IncomingConnection(IncomingConnection {
                 public_key: b_public_key.clone()
-            }
+            })
         );
 
         // This is done to help the compiler deduce the types for
@@ -556,7 +912,7 @@ mod tests {
         let reject_connection = RejectConnection {
             public_key: b_public_key,
         };
-        await!(a_ac.send(reject_connection)).unwrap();
+        await!(a_ac.send(RelayListenIn::RejectConnection(reject_connection))).unwrap();
 
         // B should be notified that the connection is closed:
         assert!(await!(b_cb.next()).is_none());
@@ -578,6 +934,505 @@ mod tests {
             .unwrap();
     }
 
+    async fn task_relay_server_connection_stats(
+        mut spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let (_shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let mut relay_server_handle = RelayServerHandle::new(requests_sender);
+
+        let half_tunnel_ticks: usize = 16;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            incoming_requests,
+            shutdown_receiver,
+            half_tunnel_ticks,
+            DRAIN_TIMEOUT_TICKS,
+            rate_limit(),
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let (a_ac, c_ac) = mpsc::channel::<RelayListenIn>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<RelayListenOut>(0);
+        let (mut b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, mut b_cb) = mpsc::channel::<Vec<u8>>(0);
+
+        let a_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let b_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let incoming_conn_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                receiver: c_ac,
+                sender: c_ca.sink_map_err(|_| ()),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_a)).unwrap();
+
+        let incoming_conn_b = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                receiver: c_bc,
+                sender: c_cb.sink_map_err(|_| ()),
+                connect_public_key: a_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_b)).unwrap();
+        let _ = await!(a_ca.next()).unwrap();
+
+        let (mut a_ac1, c_ac1) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca1, mut a_ca1) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn_accept_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Accept(IncomingAccept {
+                receiver: c_ac1,
+                sender: c_ca1.sink_map_err(|_| ()),
+                accept_public_key: b_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_accept_a)).unwrap();
+
+        await!(a_ac1.send(vec![1, 2, 3])).unwrap();
+        assert_eq!(await!(b_cb.next()).unwrap(), vec![1, 2, 3]);
+        await!(b_bc.send(vec![4, 3, 2, 1])).unwrap();
+        assert_eq!(await!(a_ca1.next()).unwrap(), vec![4, 3, 2, 1]);
+        await!(b_bc.send(vec![5])).unwrap();
+        assert_eq!(await!(a_ca1.next()).unwrap(), vec![5]);
+
+        // Statistics are only available for closed connections:
+        assert!(await!(relay_server_handle.recent_connection_stats())
+            .unwrap()
+            .is_empty());
+
+        // Close the tunnel from both sides:
+        drop(b_bc);
+        drop(a_ac1);
+        assert!(await!(a_ca1.next()).is_none());
+        assert!(await!(b_cb.next()).is_none());
+
+        let connection_stats = loop {
+            let connection_stats = await!(relay_server_handle.recent_connection_stats()).unwrap();
+            if !connection_stats.is_empty() {
+                break connection_stats;
+            }
+        };
+        assert_eq!(
+            connection_stats,
+            vec![
+                (
+                    b_public_key,
+                    ConnectionStats {
+                        connected_at_tick: 0,
+                        bytes_sent: 5,
+                        bytes_received: 3,
+                        messages_sent: 2,
+                    }
+                ),
+                (
+                    a_public_key,
+                    ConnectionStats {
+                        connected_at_tick: 0,
+                        bytes_sent: 3,
+                        bytes_received: 5,
+                        messages_sent: 1,
+                    }
+                ),
+            ]
+        );
+
+        drop(a_ac);
+        drop(outgoing_conns);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_connection_stats() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool
+            .run(task_relay_server_connection_stats(thread_pool.clone()))
+            .unwrap();
+    }
+
+    async fn task_relay_server_reconnect_half_closed(
+        mut spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (_requests_sender, incoming_requests) = mpsc::channel(0);
+        let (_shutdown_sender, shutdown_receiver) = oneshot::channel();
+
+        let half_tunnel_ticks: usize = 16;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            incoming_requests,
+            shutdown_receiver,
+            half_tunnel_ticks,
+            DRAIN_TIMEOUT_TICKS,
+            rate_limit(),
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let (_a_ac, c_ac) = mpsc::channel::<RelayListenIn>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<RelayListenOut>(0);
+        let (_b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, _b_cb) = mpsc::channel::<Vec<u8>>(0);
+
+        let a_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let b_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let incoming_conn_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                receiver: c_ac,
+                sender: c_ca.sink_map_err(|_| ()),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_a)).unwrap();
+
+        let incoming_conn_b = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                receiver: c_bc,
+                sender: c_cb.sink_map_err(|_| ()),
+                connect_public_key: a_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_b)).unwrap();
+        let _ = await!(a_ca.next()).unwrap();
+
+        let (a_ac1, c_ac1) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca1, mut a_ca1) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn_accept_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Accept(IncomingAccept {
+                receiver: c_ac1,
+                sender: c_ca1.sink_map_err(|_| ()),
+                accept_public_key: b_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_accept_a)).unwrap();
+
+        // a closes its side of the tunnel, while b keeps its side open.
+        // The relay server stops the direction b --> a once the tunnel is closing:
+        drop(a_ac1);
+        assert!(await!(a_ca1.next()).is_none());
+
+        // b can connect to a again, although its old connection is still open:
+        let (_b_bc2, c_bc2) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb2, _b_cb2) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn_b2 = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                receiver: c_bc2,
+                sender: c_cb2.sink_map_err(|_| ()),
+                connect_public_key: a_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_b2)).unwrap();
+        match await!(a_ca.next()).unwrap() {
+            RelayListenOut::IncomingConnection(incoming_connection) => {
+                assert_eq!(incoming_connection.public_key, b_public_key)
+            }
+            _ => unreachable!(),
+        };
+
+        drop(outgoing_conns);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_reconnect_half_closed() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool
+            .run(task_relay_server_reconnect_half_closed(thread_pool.clone()))
+            .unwrap();
+    }
+
+    async fn task_relay_server_shutdown(
+        mut spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (_requests_sender, incoming_requests) = mpsc::channel(0);
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let shutdown_handle = ShutdownHandle::new(shutdown_sender);
+
+        let half_tunnel_ticks: usize = 16;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            incoming_requests,
+            shutdown_receiver,
+            half_tunnel_ticks,
+            DRAIN_TIMEOUT_TICKS,
+            rate_limit(),
+            spawner.clone(),
+        );
+        let relay_server_handle = spawner.spawn_with_handle(fut_relay_server).unwrap();
+
+        let (_a_ac, c_ac) = mpsc::channel::<RelayListenIn>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<RelayListenOut>(0);
+        let (mut b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, mut b_cb) = mpsc::channel::<Vec<u8>>(0);
+
+        let a_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let b_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let incoming_conn_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                receiver: c_ac,
+                sender: c_ca.sink_map_err(|_| ()),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_a)).unwrap();
+
+        let incoming_conn_b = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                receiver: c_bc,
+                sender: c_cb.sink_map_err(|_| ()),
+                connect_public_key: a_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_b)).unwrap();
+        let _ = await!(a_ca.next()).unwrap();
+
+        let (mut a_ac1, c_ac1) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca1, mut a_ca1) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn_accept_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Accept(IncomingAccept {
+                receiver: c_ac1,
+                sender: c_ca1.sink_map_err(|_| ()),
+                accept_public_key: b_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_accept_a)).unwrap();
+
+        await!(a_ac1.send(vec![1, 2, 3])).unwrap();
+        assert_eq!(await!(b_cb.next()).unwrap(), vec![1, 2, 3]);
+        await!(b_bc.send(vec![4, 3, 2, 1])).unwrap();
+        assert_eq!(await!(a_ca1.next()).unwrap(), vec![4, 3, 2, 1]);
+
+        shutdown_handle.shutdown();
+
+        // The tunnel and the listen connection are closed, although both sides are still open:
+        assert!(await!(a_ca1.next()).is_none());
+        assert!(await!(b_cb.next()).is_none());
+        assert!(await!(a_ca.next()).is_none());
+
+        // The relay server completes after a clean drain:
+        assert!(await!(relay_server_handle).is_ok());
+
+        drop(a_ac1);
+        drop(b_bc);
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_shutdown() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool
+            .run(task_relay_server_shutdown(thread_pool.clone()))
+            .unwrap();
+    }
+
+    async fn task_relay_server_shutdown_drain_timeout(
+        mut spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (_requests_sender, incoming_requests) = mpsc::channel(0);
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+        let shutdown_handle = ShutdownHandle::new(shutdown_sender);
+
+        let half_tunnel_ticks: usize = 16;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            incoming_requests,
+            shutdown_receiver,
+            half_tunnel_ticks,
+            DRAIN_TIMEOUT_TICKS,
+            rate_limit(),
+            spawner.clone(),
+        );
+        let (result_sender, mut result_receiver) = oneshot::channel();
+        spawner
+            .spawn(fut_relay_server.map(move |res| {
+                let _ = result_sender.send(res);
+            }))
+            .unwrap();
+
+        let (_a_ac, c_ac) = mpsc::channel::<RelayListenIn>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<RelayListenOut>(0);
+        let (_b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, _b_cb) = mpsc::channel::<Vec<u8>>(0);
+
+        let a_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let b_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let incoming_conn_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(IncomingListen {
+                receiver: c_ac,
+                sender: c_ca.sink_map_err(|_| ()),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_a)).unwrap();
+
+        let incoming_conn_b = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                receiver: c_bc,
+                sender: c_cb.sink_map_err(|_| ()),
+                connect_public_key: a_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_b)).unwrap();
+        let _ = await!(a_ca.next()).unwrap();
+
+        let (mut a_ac1, c_ac1) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca1, _a_ca1) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_conn_accept_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Accept(IncomingAccept {
+                receiver: c_ac1,
+                sender: c_ca1.sink_map_err(|_| ()),
+                accept_public_key: b_public_key.clone(),
+            }),
+        };
+        await!(outgoing_conns.send(incoming_conn_accept_a)).unwrap();
+
+        // b never reads its messages. The first message is buffered, and the tunnel gets stuck
+        // trying to send the second message:
+        await!(a_ac1.send(vec![1, 2, 3])).unwrap();
+        await!(a_ac1.send(vec![4, 5, 6])).unwrap();
+        await!(a_ac1.send(vec![7, 8, 9])).unwrap();
+
+        shutdown_handle.shutdown();
+
+        // The tunnel can not be closed, so the relay server gives up after the drain timeout:
+        let res = loop {
+            if let Some(res) = result_receiver.try_recv().unwrap() {
+                break res;
+            }
+            // The timer service may close once the relay server is done:
+            let _ = await!(tick_sender.send(()));
+        };
+        match res {
+            Err(RelayServerError::DrainTimeout) => {}
+            _ => unreachable!(),
+        };
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_shutdown_drain_timeout() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool
+            .run(task_relay_server_shutdown_drain_timeout(
+                thread_pool.clone(),
+            ))
+            .unwrap();
+    }
+
+    async fn task_forward_messages_rate_limit(mut spawner: impl Spawn + Clone + Send + 'static) {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut input_sender, input_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (output_sender, mut output_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let rate_limit = RateLimitConfig {
+            capacity: 10,
+            refill_per_tick: 5,
+        };
+        let (_closing_sender, closing_receiver) = oneshot::channel();
+        let forward_fut = forward_messages(
+            input_receiver,
+            output_sender.sink_map_err(|_| ()),
+            closing_receiver,
+            timer_client,
+            rate_limit,
+        );
+        let forward_stats_receiver = spawner.spawn_with_handle(forward_fut).unwrap();
+
+        // Bucket: 10 -> 2
+        await!(input_sender.send(vec![0; 8])).unwrap();
+        assert_eq!(await!(output_receiver.next()).unwrap(), vec![0; 8]);
+
+        // Not enough tokens, but the message is not dropped.
+        // Bucket: 2 -> 0 (debt: 2)
+        await!(input_sender.send(vec![1; 4])).unwrap();
+        assert_eq!(await!(output_receiver.next()).unwrap(), vec![1; 4]);
+
+        // The bucket is empty, this message waits for a refill.
+        await!(input_sender.send(vec![2; 1])).unwrap();
+        // Bucket: 0 -> 3 (debt paid) -> 2
+        await!(tick_sender.send(())).unwrap();
+        assert_eq!(await!(output_receiver.next()).unwrap(), vec![2; 1]);
+
+        // A message larger than the capacity of the bucket passes.
+        // Bucket: 2 -> 0 (debt: 23)
+        await!(input_sender.send(vec![3; 25])).unwrap();
+        assert_eq!(await!(output_receiver.next()).unwrap(), vec![3; 25]);
+
+        // Bucket: 0 -> 2 (debt paid) -> 1
+        await!(input_sender.send(vec![4; 1])).unwrap();
+        for _ in 0..5 {
+            await!(tick_sender.send(())).unwrap();
+        }
+        assert_eq!(await!(output_receiver.next()).unwrap(), vec![4; 1]);
+
+        drop(input_sender);
+        assert!(await!(output_receiver.next()).is_none());
+
+        assert_eq!(
+            await!(forward_stats_receiver),
+            ForwardStats {
+                num_messages: 5,
+                num_bytes: 39,
+            }
+        );
+    }
+
+    #[test]
+    fn test_forward_messages_rate_limit() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_forward_messages_rate_limit(thread_pool.clone()));
+    }
+
     // TODO: Add tests:
     // - Timeout of half tunnels
     //      (Do some action first, to make sure timer_stream was already obtained).
@@ -9,6 +9,7 @@ use common::select_streams::{select_streams, BoxStream};
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
+use crypto::sym_encrypt::SymEncryptAlgorithm;
 use identity::IdentityClient;
 use timer::TimerClient;
 
@@ -31,6 +32,7 @@ enum SecureChannelError {
     UnexpectedRemotePublicKey,
     RequestTimerStreamError,
     HandleIncomingError,
+    RekeyTooFrequent,
     SpawnError,
 }
 
@@ -39,6 +41,7 @@ async fn initial_exchange<EK, M: 'static, K: 'static, R: CryptoRandom + 'static>
     mut reader: M,
     identity_client: IdentityClient,
     opt_expected_remote: Option<PublicKey>,
+    sym_encrypt_algorithm: SymEncryptAlgorithm,
     rng: R,
 ) -> Result<(ScState, K, M), SecureChannelError>
 where
@@ -49,7 +52,8 @@ where
     let local_public_key = await!(identity_client.request_public_key())
         .map_err(|_| SecureChannelError::IdentityFailure)?;
 
-    let (dh_state_initial, exchange_rand_nonce) = ScStateInitial::new(&local_public_key, &rng);
+    let (dh_state_initial, exchange_rand_nonce) =
+        ScStateInitial::new(&local_public_key, sym_encrypt_algorithm, &rng);
     let ser_exchange_rand_nonce = serialize_exchange_rand_nonce(&exchange_rand_nonce);
     await!(writer.send(ser_exchange_rand_nonce)).map_err(|_| SecureChannelError::WriterError)?;
 
@@ -99,6 +103,7 @@ async fn secure_channel_loop<EK, M: 'static, K: 'static, R: CryptoRandom + 'stat
     mut to_user: mpsc::Sender<Vec<u8>>,
     rng: R,
     ticks_to_rekey: usize,
+    rekey_min_ticks: usize,
     mut timer_client: TimerClient,
 ) -> Result<(), SecureChannelError>
 where
@@ -129,16 +134,30 @@ where
         )));
 
     let mut cur_ticks_to_rekey = ticks_to_rekey;
+    // Amount of ticks elapsed since the last completed rekey
+    // (Or since the channel was created):
+    let mut ticks_since_rekey: usize = 0;
     let mut events = select_streams![reader, from_user, timer_stream];
 
     while let Some(event) = await!(events.next()) {
         match event {
             SecureChannelEvent::Reader(data) => {
+                let rekey_pending = dh_state.is_rekey_pending();
                 let hi_output = dh_state
                     .handle_incoming(&EncryptedData(data), &rng)
                     .map_err(|_| SecureChannelError::HandleIncomingError)?;
                 if hi_output.rekey_occurred {
+                    // A rekey initiated by the remote side is only allowed once every
+                    // rekey_min_ticks. We close the channel without responding otherwise:
+                    if !rekey_pending && ticks_since_rekey < rekey_min_ticks {
+                        error!(
+                            "secure_channel_loop(): Remote rekey after {} ticks (min: {})",
+                            ticks_since_rekey, rekey_min_ticks
+                        );
+                        return Err(SecureChannelError::RekeyTooFrequent);
+                    }
                     cur_ticks_to_rekey = ticks_to_rekey;
+                    ticks_since_rekey = 0;
                 }
                 if let Some(send_message) = hi_output.opt_send_message {
                     await!(writer.send(send_message.0))
@@ -154,6 +173,7 @@ where
                 await!(writer.send(enc_data.0)).map_err(|_| SecureChannelError::WriterError)?;
             }
             SecureChannelEvent::TimerTick => {
+                ticks_since_rekey = ticks_since_rekey.saturating_add(1);
                 if let Some(new_cur_ticks_to_rekey) = cur_ticks_to_rekey.checked_sub(1) {
                     cur_ticks_to_rekey = new_cur_ticks_to_rekey;
                     continue;
@@ -184,6 +204,13 @@ where
 ///
 /// `ticks_to_rekey` is the amount of time ticks it takes to issue a rekey, changing the symmetric
 /// key used for the encryption.
+///
+/// `rekey_min_ticks` is the minimal amount of time ticks that must pass between two completed
+/// rekeys before the remote side may initiate a new rekey. If the remote side initiates a rekey
+/// earlier, the channel is closed.
+///
+/// `sym_encrypt_algorithm` is the symmetric encryption algorithm we prefer. It is used only if the
+/// remote side prefers it too, otherwise the default algorithm is used.
 async fn create_secure_channel<EK, M, K, R, S>(
     writer: K,
     reader: M,
@@ -192,6 +219,8 @@ async fn create_secure_channel<EK, M, K, R, S>(
     rng: R,
     timer_client: TimerClient,
     ticks_to_rekey: usize,
+    rekey_min_ticks: usize,
+    sym_encrypt_algorithm: SymEncryptAlgorithm,
     mut spawner: S,
 ) -> Result<(PublicKey, ConnPairVec), SecureChannelError>
 where
@@ -206,6 +235,7 @@ where
         reader,
         identity_client,
         opt_expected_remote,
+        sym_encrypt_algorithm,
         rng.clone()
     ))?;
 
@@ -222,6 +252,7 @@ where
         to_user,
         rng.clone(),
         ticks_to_rekey,
+        rekey_min_ticks,
         timer_client,
     );
 
@@ -243,6 +274,8 @@ pub struct SecureChannel<R, S> {
     rng: R,
     timer_client: TimerClient,
     ticks_to_rekey: usize,
+    rekey_min_ticks: usize,
+    sym_encrypt_algorithm: SymEncryptAlgorithm,
     spawner: S,
 }
 
@@ -252,6 +285,8 @@ impl<R, S> SecureChannel<R, S> {
         rng: R,
         timer_client: TimerClient,
         ticks_to_rekey: usize,
+        rekey_min_ticks: usize,
+        sym_encrypt_algorithm: SymEncryptAlgorithm,
         spawner: S,
     ) -> SecureChannel<R, S> {
         SecureChannel {
@@ -259,6 +294,8 @@ impl<R, S> SecureChannel<R, S> {
             rng,
             timer_client,
             ticks_to_rekey,
+            rekey_min_ticks,
+            sym_encrypt_algorithm,
             spawner,
         }
     }
@@ -295,6 +332,8 @@ where
                 self.rng.clone(),
                 self.timer_client.clone(),
                 self.ticks_to_rekey,
+                self.rekey_min_ticks,
+                self.sym_encrypt_algorithm,
                 self.spawner.clone()
             ))
             .ok()
@@ -307,7 +346,7 @@ mod tests {
     use super::*;
     use futures::channel::oneshot;
     use futures::Future;
-    use timer::create_timer_incoming;
+    use timer::{create_timer_incoming, dummy_timer_multi_sender, TimerTick};
 
     use futures::executor::ThreadPool;
     use futures::task::SpawnExt;
@@ -394,6 +433,8 @@ mod tests {
             rng1.clone(),
             timer_client.clone(),
             ticks_to_rekey,
+            0,
+            SymEncryptAlgorithm::default(),
             thread_pool.clone(),
         );
 
@@ -405,6 +446,8 @@ mod tests {
             rng2.clone(),
             timer_client.clone(),
             ticks_to_rekey,
+            0,
+            SymEncryptAlgorithm::default(),
             thread_pool.clone(),
         );
 
@@ -429,4 +472,195 @@ mod tests {
         assert_eq!(true, thread_pool.run(output_receiver1).unwrap());
         assert_eq!(true, thread_pool.run(output_receiver2).unwrap());
     }
+
+    async fn task_secure_channel_remote_public_key(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        spawner: impl Spawn + Clone + Send + Sync + 'static,
+    ) {
+        let mut secure_channel1 = SecureChannel::new(
+            identity_client1,
+            DummyRandom::new(&[1u8]),
+            timer_client.clone(),
+            16,
+            0,
+            SymEncryptAlgorithm::Aes128Gcm,
+            spawner.clone(),
+        );
+        let mut secure_channel2 = SecureChannel::new(
+            identity_client2,
+            DummyRandom::new(&[2u8]),
+            timer_client,
+            16,
+            0,
+            SymEncryptAlgorithm::Aes128Gcm,
+            spawner,
+        );
+
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        let (output1, output2) = await!(future::join(
+            secure_channel1.transform((Some(public_key2.clone()), (sender1, receiver1))),
+            secure_channel2.transform((None, (sender2, receiver2)))
+        ));
+        let (remote_public_key1, _conn_pair1) = output1.unwrap();
+        let (remote_public_key2, _conn_pair2) = output2.unwrap();
+        assert_eq!(remote_public_key1, public_key2);
+        assert_eq!(remote_public_key2, public_key1);
+    }
+
+    #[test]
+    fn test_secure_channel_remote_public_key() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
+
+        let rng1 = DummyRandom::new(&[1u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng1);
+        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+        let public_key1 = identity1.get_public_key();
+        let (requests_sender1, identity_server1) = create_identity(identity1);
+        let identity_client1 = IdentityClient::new(requests_sender1);
+
+        let rng2 = DummyRandom::new(&[2u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng2);
+        let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+        let public_key2 = identity2.get_public_key();
+        let (requests_sender2, identity_server2) = create_identity(identity2);
+        let identity_client2 = IdentityClient::new(requests_sender2);
+
+        thread_pool
+            .spawn(identity_server1.then(|_| future::ready(())))
+            .unwrap();
+        thread_pool
+            .spawn(identity_server2.then(|_| future::ready(())))
+            .unwrap();
+
+        thread_pool.run(task_secure_channel_remote_public_key(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_secure_channel_rekey_min_ticks(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        spawner: impl Spawn + Clone + Send + Sync + 'static,
+    ) {
+        let ticks_to_rekey: usize = 4;
+        let rekey_min_ticks: usize = 8;
+
+        let (mut tick_sender_receiver1, timer_client1) = dummy_timer_multi_sender(spawner.clone());
+        let (mut tick_sender_receiver2, timer_client2) = dummy_timer_multi_sender(spawner.clone());
+
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        // The first side rekeys often, but doesn't limit the rekeys of the remote side:
+        let fut_sc1 = create_secure_channel(
+            sender1.sink_map_err(|_| ()),
+            receiver1,
+            identity_client1,
+            Some(public_key2),
+            DummyRandom::new(&[1u8]),
+            timer_client1,
+            ticks_to_rekey,
+            0,
+            SymEncryptAlgorithm::default(),
+            spawner.clone(),
+        );
+
+        // The second side never initiates a rekey by itself, but limits the rekeys of the remote
+        // side:
+        let fut_sc2 = create_secure_channel(
+            sender2.sink_map_err(|_| ()),
+            receiver2,
+            identity_client2,
+            Some(public_key1),
+            DummyRandom::new(&[2u8]),
+            timer_client2,
+            usize::max_value(),
+            rekey_min_ticks,
+            SymEncryptAlgorithm::default(),
+            spawner.clone(),
+        );
+
+        let (output1, output2) = await!(future::join(fut_sc1, fut_sc2));
+        let (_public_key, (mut sender1, mut receiver1)) = output1.unwrap();
+        let (_public_key, (mut sender2, mut receiver2)) = output2.unwrap();
+
+        let mut tick_sender1 = await!(tick_sender_receiver1.next()).unwrap();
+        let mut tick_sender2 = await!(tick_sender_receiver2.next()).unwrap();
+
+        // Let enough time pass on the second side.
+        // (The extra tick makes sure all the previous ticks were processed):
+        for _ in 0..rekey_min_ticks + 1 {
+            await!(tick_sender2.send(TimerTick)).unwrap();
+        }
+
+        // Cause the first side to rekey:
+        for _ in 0..ticks_to_rekey + 2 {
+            await!(tick_sender1.send(TimerTick)).unwrap();
+        }
+
+        // The rekey is honored by the second side:
+        await!(sender1.send(vec![0, 1, 2])).unwrap();
+        assert_eq!(await!(receiver2.next()).unwrap(), vec![0, 1, 2]);
+        await!(sender2.send(vec![3, 4, 5])).unwrap();
+        assert_eq!(await!(receiver1.next()).unwrap(), vec![3, 4, 5]);
+
+        // Cause the first side to rekey again, too early for the second side:
+        for _ in 0..ticks_to_rekey + 1 {
+            await!(tick_sender1.send(TimerTick)).unwrap();
+        }
+
+        // The second side closes the channel:
+        assert!(await!(receiver2.next()).is_none());
+    }
+
+    #[test]
+    fn test_secure_channel_rekey_min_ticks() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        let rng1 = DummyRandom::new(&[1u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng1);
+        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+        let public_key1 = identity1.get_public_key();
+        let (requests_sender1, identity_server1) = create_identity(identity1);
+        let identity_client1 = IdentityClient::new(requests_sender1);
+
+        let rng2 = DummyRandom::new(&[2u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng2);
+        let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+        let public_key2 = identity2.get_public_key();
+        let (requests_sender2, identity_server2) = create_identity(identity2);
+        let identity_client2 = IdentityClient::new(requests_sender2);
+
+        thread_pool
+            .spawn(identity_server1.then(|_| future::ready(())))
+            .unwrap();
+        thread_pool
+            .spawn(identity_server2.then(|_| future::ready(())))
+            .unwrap();
+
+        thread_pool.run(task_secure_channel_rekey_min_ticks(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            thread_pool.clone(),
+        ));
+    }
 }
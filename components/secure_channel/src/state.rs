@@ -4,7 +4,7 @@ use std::mem;
 use crypto::crypto_rand::{CryptoRandom, RandValue};
 use crypto::dh::{DhPrivateKey, Salt};
 use crypto::identity::{verify_signature, PublicKey, Signature};
-use crypto::sym_encrypt::{Decryptor, Encryptor};
+use crypto::sym_encrypt::{Decryptor, Encryptor, SymEncryptAlgorithm, SymKey};
 use identity::IdentityClient;
 use proto::secure_channel::messages::{
     ChannelContent, ChannelMessage, EncryptedData, ExchangeDh, ExchangeRandNonce, PlainData, Rekey,
@@ -21,8 +21,6 @@ pub enum ScStateError {
     IncorrectRandNonce,
     InvalidSignature,
     KeyDerivationFailure,
-    CreateEncryptorFailure,
-    CreateDecryptorFailure,
     DecryptionFailure,
     DeserializeError,
     RekeyInProgress,
@@ -31,6 +29,7 @@ pub enum ScStateError {
 pub struct ScStateInitial {
     local_public_key: PublicKey,
     local_rand_nonce: RandValue,
+    local_sym_encrypt_algorithm: SymEncryptAlgorithm,
 }
 
 pub struct ScStateHalf {
@@ -39,6 +38,7 @@ pub struct ScStateHalf {
     local_rand_nonce: RandValue,
     dh_private_key: DhPrivateKey,
     local_salt: Salt,
+    local_sym_encrypt_algorithm: SymEncryptAlgorithm,
 }
 
 struct PendingRekey {
@@ -50,6 +50,8 @@ pub struct ScState {
     #[allow(unused)]
     local_public_key: PublicKey,
     remote_public_key: PublicKey,
+    /// Algorithm agreed during the initial exchange. Also used for the keys created by rekeying.
+    sym_encrypt_algorithm: SymEncryptAlgorithm,
     sender: Encryptor,
     receiver: Decryptor,
     /// We might have an old receiver from the last rekeying.
@@ -60,8 +62,11 @@ pub struct ScState {
 }
 
 impl ScStateInitial {
+    /// `local_sym_encrypt_algorithm` is the symmetric encryption algorithm we prefer.
+    /// If the remote side prefers another algorithm, both sides use the default algorithm.
     pub fn new<R: CryptoRandom>(
         local_public_key: &PublicKey,
+        local_sym_encrypt_algorithm: SymEncryptAlgorithm,
         rng: &R,
     ) -> (ScStateInitial, ExchangeRandNonce) {
         let local_rand_nonce = RandValue::new(rng);
@@ -69,6 +74,7 @@ impl ScStateInitial {
         let sc_state_initial = ScStateInitial {
             local_public_key: local_public_key.clone(),
             local_rand_nonce: local_rand_nonce.clone(),
+            local_sym_encrypt_algorithm,
         };
         let exchange_rand_nonce = ExchangeRandNonce {
             rand_nonce: local_rand_nonce,
@@ -96,6 +102,7 @@ impl ScStateInitial {
             local_rand_nonce: self.local_rand_nonce,
             dh_private_key,
             local_salt: local_salt.clone(),
+            local_sym_encrypt_algorithm: self.local_sym_encrypt_algorithm,
         };
 
         let mut exchange_dh = ExchangeDh {
@@ -103,6 +110,7 @@ impl ScStateInitial {
             rand_nonce: exchange_rand_nonce.rand_nonce,
             key_salt: local_salt,
             signature: Signature::zero(),
+            sym_encrypt_algorithm: self.local_sym_encrypt_algorithm,
         };
         exchange_dh.signature =
             await!(identity_client.request_signature(exchange_dh.signature_buffer())).unwrap();
@@ -129,6 +137,14 @@ impl ScStateHalf {
     pub fn handle_exchange_dh(self, exchange_dh: ExchangeDh) -> Result<ScState, ScStateError> {
         self.verify_exchange_dh(&exchange_dh)?;
 
+        // Both sides reach the same decision:
+        let sym_encrypt_algorithm =
+            if self.local_sym_encrypt_algorithm == exchange_dh.sym_encrypt_algorithm {
+                self.local_sym_encrypt_algorithm
+            } else {
+                SymEncryptAlgorithm::default()
+            };
+
         let (send_key, recv_key) = self
             .dh_private_key
             .derive_symmetric_key(
@@ -141,9 +157,9 @@ impl ScStateHalf {
         Ok(ScState {
             local_public_key: self.local_public_key,
             remote_public_key: self.remote_public_key,
-            sender: Encryptor::new(&send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?,
-            receiver: Decryptor::new(&recv_key)
-                .map_err(|_| ScStateError::CreateDecryptorFailure)?,
+            sym_encrypt_algorithm,
+            sender: Encryptor::new(SymKey::new(send_key, sym_encrypt_algorithm)),
+            receiver: Decryptor::new(SymKey::new(recv_key, sym_encrypt_algorithm)),
             opt_old_receiver: None,
             opt_pending_rekey: None,
         })
@@ -267,10 +283,9 @@ impl ScState {
                     .derive_symmetric_key(rekey.dh_public_key, local_salt.clone(), rekey.key_salt)
                     .map_err(|_| ScStateError::KeyDerivationFailure)?;
 
-                let new_sender =
-                    Encryptor::new(&send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?;
+                let new_sender = Encryptor::new(SymKey::new(send_key, self.sym_encrypt_algorithm));
                 let new_receiver =
-                    Decryptor::new(&recv_key).map_err(|_| ScStateError::CreateDecryptorFailure)?;
+                    Decryptor::new(SymKey::new(recv_key, self.sym_encrypt_algorithm));
 
                 self.opt_old_receiver = Some(mem::replace(&mut self.receiver, new_receiver));
 
@@ -297,10 +312,9 @@ impl ScState {
                         rekey.key_salt,
                     )
                     .map_err(|_| ScStateError::KeyDerivationFailure)?;
-                self.sender =
-                    Encryptor::new(&send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?;
+                self.sender = Encryptor::new(SymKey::new(send_key, self.sym_encrypt_algorithm));
                 let new_receiver =
-                    Decryptor::new(&recv_key).map_err(|_| ScStateError::CreateDecryptorFailure)?;
+                    Decryptor::new(SymKey::new(recv_key, self.sym_encrypt_algorithm));
                 self.opt_old_receiver = Some(mem::replace(&mut self.receiver, new_receiver));
                 Ok(HandleIncomingOutput {
                     rekey_occurred: true,
@@ -327,6 +341,11 @@ impl ScState {
         }
     }
 
+    /// Did we initiate a rekey that was not yet answered by the remote side?
+    pub fn is_rekey_pending(&self) -> bool {
+        self.opt_pending_rekey.is_some()
+    }
+
     /// Get the public key of the remote side
     pub fn get_remote_public_key(&self) -> &PublicKey {
         &self.remote_public_key
@@ -348,15 +367,17 @@ mod tests {
     async fn run_basic_sc_state(
         identity_client1: IdentityClient,
         identity_client2: IdentityClient,
+        sym_encrypt_algorithm1: SymEncryptAlgorithm,
+        sym_encrypt_algorithm2: SymEncryptAlgorithm,
     ) -> Result<(ScState, ScState), ()> {
         let rng1 = DummyRandom::new(&[1u8]);
         let rng2 = DummyRandom::new(&[2u8]);
         let local_public_key1 = await!(identity_client1.request_public_key()).unwrap();
         let local_public_key2 = await!(identity_client2.request_public_key()).unwrap();
         let (sc_state_initial1, exchange_rand_nonce1) =
-            ScStateInitial::new(&local_public_key1, &rng1);
+            ScStateInitial::new(&local_public_key1, sym_encrypt_algorithm1, &rng1);
         let (sc_state_initial2, exchange_rand_nonce2) =
-            ScStateInitial::new(&local_public_key2, &rng2);
+            ScStateInitial::new(&local_public_key2, sym_encrypt_algorithm2, &rng2);
 
         let (sc_state_half1, exchange_dh1) = await!(sc_state_initial1.handle_exchange_rand_nonce(
             exchange_rand_nonce2,
@@ -442,7 +463,10 @@ mod tests {
         assert_eq!(incoming_output2.opt_incoming_message, None);
     }
 
-    fn prepare_dh_test() -> (ScState, ScState, DummyRandom, DummyRandom) {
+    fn prepare_dh_test(
+        sym_encrypt_algorithm1: SymEncryptAlgorithm,
+        sym_encrypt_algorithm2: SymEncryptAlgorithm,
+    ) -> (ScState, ScState, DummyRandom, DummyRandom) {
         let rng1 = DummyRandom::new(&[1u8]);
         let pkcs8 = generate_pkcs8_key_pair(&rng1);
         let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
@@ -465,7 +489,12 @@ mod tests {
             .unwrap();
 
         let (sc_state1, sc_state2) = thread_pool
-            .run(run_basic_sc_state(identity_client1, identity_client2))
+            .run(run_basic_sc_state(
+                identity_client1,
+                identity_client2,
+                sym_encrypt_algorithm1,
+                sym_encrypt_algorithm2,
+            ))
             .unwrap();
 
         (sc_state1, sc_state2, rng1, rng2)
@@ -473,13 +502,51 @@ mod tests {
 
     #[test]
     fn test_basic_sc_state() {
-        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test();
+        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test(
+            SymEncryptAlgorithm::default(),
+            SymEncryptAlgorithm::default(),
+        );
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         rekey_sequential(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         rekey_simultaneous(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
     }
+
+    #[test]
+    fn test_sc_state_sym_encrypt_algorithm() {
+        // Both sides prefer AES-128-GCM:
+        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test(
+            SymEncryptAlgorithm::Aes128Gcm,
+            SymEncryptAlgorithm::Aes128Gcm,
+        );
+        assert_eq!(
+            sc_state1.sym_encrypt_algorithm,
+            SymEncryptAlgorithm::Aes128Gcm
+        );
+        assert_eq!(
+            sc_state2.sym_encrypt_algorithm,
+            SymEncryptAlgorithm::Aes128Gcm
+        );
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        rekey_sequential(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+
+        // Different preferences. Both sides use the default algorithm:
+        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test(
+            SymEncryptAlgorithm::Aes128Gcm,
+            SymEncryptAlgorithm::ChaCha20Poly1305,
+        );
+        assert_eq!(
+            sc_state1.sym_encrypt_algorithm,
+            SymEncryptAlgorithm::default()
+        );
+        assert_eq!(
+            sc_state2.sym_encrypt_algorithm,
+            SymEncryptAlgorithm::default()
+        );
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+    }
     // TODO: Add tests:
     // - Test the usage of old receiver
     // - Test error cases
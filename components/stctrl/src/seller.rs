@@ -15,6 +15,9 @@ pub struct CreateInvoiceCmd {
     /// Amount of credits to pay (A non negative integer)
     #[structopt(short = "a", long = "amount")]
     pub amount: u128,
+    /// Amount of time ticks until the invoice expires (A tick is one second)
+    #[structopt(long = "validity-ticks", default_value = "86400")]
+    pub validity_ticks: u64,
     /// Path of output invoice file
     #[structopt(parse(from_os_str), short = "o", long = "output")]
     pub output: PathBuf,
@@ -76,6 +79,7 @@ async fn seller_create_invoice(
 ) -> Result<(), SellerError> {
     let CreateInvoiceCmd {
         amount,
+        validity_ticks,
         output,
     } = create_invoice_cmd;
 
@@ -94,7 +98,7 @@ async fn seller_create_invoice(
         dest_payment: amount,
     };
 
-    await!(app_seller.add_invoice(invoice_id.clone(), amount))
+    await!(app_seller.add_invoice(invoice_id.clone(), amount, validity_ticks))
         .map_err(|_| SellerError::AddInvoiceError)?;
 
 
@@ -209,6 +209,8 @@ pub fn create_stctrl_setup(temp_dir_path: &Path) -> StCtrlSetup {
         proutes: true,
         pfunds: true,
         pconfig: true,
+        max_requests_per_second: None,
+        expires_in: None,
     };
     stmgr(StMgrCmd::AppTicket(app_ticket_cmd)).unwrap();
 
@@ -221,6 +223,8 @@ pub fn create_stctrl_setup(temp_dir_path: &Path) -> StCtrlSetup {
         proutes: true,
         pfunds: true,
         pconfig: true,
+        max_requests_per_second: None,
+        expires_in: None,
     };
     stmgr(StMgrCmd::AppTicket(app_ticket_cmd)).unwrap();
 
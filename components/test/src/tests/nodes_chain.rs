@@ -48,6 +48,8 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
                 routes: true,
                 send_funds: true,
                 config: true,
+                max_requests_per_second: None,
+                friend_permissions: None,
             },
         );
 
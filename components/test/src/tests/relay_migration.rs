@@ -62,6 +62,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
 
@@ -93,6 +95,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
     let node1_handle = await!(create_node(
@@ -221,6 +225,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
     let _node1_handle = await!(create_node(
@@ -44,6 +44,8 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
 
@@ -76,6 +78,8 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
     await!(create_node(
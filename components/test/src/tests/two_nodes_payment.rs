@@ -46,6 +46,8 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
 
@@ -88,6 +90,8 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
             routes: true,
             send_funds: true,
             config: true,
+            max_requests_per_second: None,
+            friend_permissions: None,
         },
     );
     await!(create_node(
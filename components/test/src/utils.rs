@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::future::RemoteHandle;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, FutureExt, SinkExt, TryFutureExt};
@@ -9,24 +10,31 @@ use futures::{future, FutureExt, SinkExt, TryFutureExt};
 use crypto::identity::{generate_pkcs8_key_pair, Identity, PublicKey, SoftwareEd25519Identity};
 
 use crypto::crypto_rand::CryptoRandom;
+use crypto::sym_encrypt::SymEncryptAlgorithm;
 use crypto::test_utils::DummyRandom;
 
 use common::test_executor::TestExecutor;
 
 use proto::app_server::messages::{AppPermissions, NamedRelayAddress, RelayAddress};
-use proto::consts::{KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY};
+use proto::consts::{
+    INCOMING_CONN_WAIT_TICKS, KEEPALIVE_TICKS, MAX_FRIENDS, MAX_NODE_RELAYS,
+    MAX_OPERATIONS_IN_BATCH, MAX_PENDING_TICKS, NODE_DRAIN_TIMEOUT_TICKS, REKEY_MIN_TICKS,
+    RELAY_DRAIN_TIMEOUT_TICKS, RELAY_RATE_LIMIT_CAPACITY, RELAY_RATE_LIMIT_REFILL_PER_TICK,
+    TICKS_TO_REKEY,
+};
+use proto::file::app::TrustedApp;
 use proto::index_server::messages::NamedIndexServerAddress;
 use proto::net::messages::NetAddress;
 
 use identity::{create_identity, IdentityClient};
 
 use node::connect::{node_connect, NodeConnection};
-use node::{net_node, NodeConfig, NodeState};
+use node::{net_node, NodeConfig, NodeMetrics, NodeState};
 
 use database::file_db::FileDb;
 
 use index_server::net_index_server;
-use relay::net_relay_server;
+use relay::{net_relay_server, RateLimitConfig};
 
 use timer::TimerClient;
 
@@ -41,6 +49,12 @@ const BACKOFF_TICKS: usize = 0x8;
 const MAX_CONCURRENT_ENCRYPT: usize = 0x8;
 /// The size we allocate for the user send funds requests queue.
 const MAX_PENDING_USER_REQUESTS: usize = 0x20;
+/// Amount of ticks between two saved snapshots of the node report (App side)
+const REPORT_SNAPSHOT_INTERVAL_TICKS: usize = 0x10;
+/// Total amount of frozen credits (local or remote) above which the funder emits a warning.
+const MAX_FROZEN_CREDITS_THRESHOLD: u128 = 1 << 64;
+/// Amount of payment events a completed payment is kept in the payment history.
+const HISTORY_RETENTION_EVENTS: u64 = 1 << 20;
 /// Maximum amount of concurrent index client requests:
 const MAX_OPEN_INDEX_CLIENT_REQUESTS: usize = 0x8;
 /// The amount of ticks we are willing to wait until a connection is established (Through
@@ -128,6 +142,9 @@ fn default_node_config() -> NodeConfig {
         keepalive_ticks: KEEPALIVE_TICKS,
         /// Amount of ticks to wait until the next rekeying (Channel encryption)
         ticks_to_rekey: TICKS_TO_REKEY,
+        rekey_min_ticks: REKEY_MIN_TICKS,
+        /// Preferred symmetric encryption algorithm (Channel encryption)
+        sym_encrypt_algorithm: SymEncryptAlgorithm::default(),
         /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
         /// time.
         max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
@@ -138,12 +155,23 @@ fn default_node_config() -> NodeConfig {
         max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
         /// The size we allocate for the user send funds requests queue.
         max_pending_user_requests: MAX_PENDING_USER_REQUESTS,
+        /// The amount of ticks we wait for a pending transaction to be collected.
+        max_pending_ticks: MAX_PENDING_TICKS,
+        /// Total amount of frozen credits (local or remote) above which we emit a warning.
+        max_frozen_credits_threshold: MAX_FROZEN_CREDITS_THRESHOLD,
+        /// Amount of payment events a completed payment is kept in the payment history.
+        history_retention_events: HISTORY_RETENTION_EVENTS,
         /// Maximum amount of concurrent index client requests:
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        max_friends: MAX_FRIENDS,
         /// Maximum amount of incoming app connections we set up at the same time
         max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
+        incoming_conn_wait_ticks: INCOMING_CONN_WAIT_TICKS,
+        drain_timeout_ticks: NODE_DRAIN_TIMEOUT_TICKS,
+        opt_socks5_proxy: None,
+        opt_access_control_path: None,
     }
 }
 
@@ -247,6 +275,8 @@ where
         listen_node_address(node_index),
         timer_client,
         app_identity_client,
+        None,
+        REPORT_SNAPSHOT_INTERVAL_TICKS,
         rng,
         spawner.clone()
     ))
@@ -272,11 +302,22 @@ where
     // Translate application index to application public key:
     let trusted_apps = trusted_apps
         .into_iter()
-        .map(|(index, app_permissions)| (get_app_identity(index).get_public_key(), app_permissions))
+        .map(|(index, permissions)| {
+            let public_key = get_app_identity(index).get_public_key();
+            let trusted_app = TrustedApp {
+                public_key: public_key.clone(),
+                permissions,
+                expires_at: None,
+            };
+            (public_key, trusted_app)
+        })
         .collect::<HashMap<_, _>>();
     let get_trusted_apps = move || Some(trusted_apps.clone());
 
     let rng = DummyRandom::new(&[0xff, 0x13, 0x37, index]);
+    // Graceful shutdown is not used in tests. Dropping the sender means a shutdown is never
+    // requested:
+    let (_shutdown_sender, shutdown_receiver) = oneshot::channel();
     // Note: we use the same spawner for testing purposes.
     // Simulating the passage of time becomes more difficult if our code uses a few different executors.
     let net_node_fut = net_node(
@@ -288,6 +329,8 @@ where
         default_node_config(),
         get_trusted_apps,
         sim_db.load_db(index),
+        shutdown_receiver,
+        Arc::new(NodeMetrics::new()),
         spawner.clone(), // trusted_apps_spawner
         spawner.clone(), // database_spawner
         spawner.clone(),
@@ -365,16 +408,25 @@ pub async fn create_relay<S>(
     let incoming_raw_conns = await!(sim_network_client.listen(listen_address)).unwrap();
 
     let rng = DummyRandom::new(&[0xff, 0x13, 0x39, index]);
-    let net_relay_server_fut = net_relay_server(
+    let (_requests_sender, incoming_requests) = mpsc::channel(0);
+    let rate_limit = RateLimitConfig {
+        capacity: RELAY_RATE_LIMIT_CAPACITY,
+        refill_per_tick: RELAY_RATE_LIMIT_REFILL_PER_TICK,
+    };
+    let (_shutdown_handle, net_relay_server_fut) = net_relay_server(
         incoming_raw_conns,
+        incoming_requests,
         identity_client,
         timer_client,
         rng,
         MAX_CONCURRENT_ENCRYPT,
+        RELAY_DRAIN_TIMEOUT_TICKS,
+        rate_limit,
         spawner.clone(),
-    )
-    .map_err(|e| error!("net_relay_server() error: {:?}", e))
-    .map(|_| ());
+    );
+    let net_relay_server_fut = net_relay_server_fut
+        .map_err(|e| error!("net_relay_server() error: {:?}", e))
+        .map(|_| ());
 
     spawner.spawn(net_relay_server_fut).unwrap();
 }
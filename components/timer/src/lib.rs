@@ -17,9 +17,11 @@ extern crate log;
 #[macro_use]
 extern crate common;
 
+pub mod test_utils;
 mod timer;
 pub mod utils;
 
 pub use self::timer::{
-    create_timer, create_timer_incoming, dummy_timer_multi_sender, TimerClient, TimerTick,
+    create_timer, create_timer_incoming, dummy_timer_multi_sender, TimerClient, TimerClientError,
+    TimerTick,
 };
@@ -0,0 +1,108 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use crate::timer::{TimerClient, TimerRequest, TimerTick};
+
+/// A timer service for tests. Time only moves forward when `advance()` is called.
+pub struct VirtualTimerService {
+    /// Senders of all the timer streams that are still open:
+    tick_senders: Arc<Mutex<Vec<mpsc::Sender<TimerTick>>>>,
+    /// Amount of ticks passed since the service was created:
+    cur_tick: usize,
+}
+
+impl VirtualTimerService {
+    pub fn cur_tick(&self) -> usize {
+        self.cur_tick
+    }
+
+    /// Advance time by `n` ticks. Every tick is sent to all the timer streams that were
+    /// requested before it. Timer streams that were dropped are removed.
+    ///
+    /// Note that, like with the real timer, sending a tick waits until the previous tick was
+    /// consumed. Therefore the timer streams should be consumed while time is advanced.
+    pub async fn advance(&mut self, n: usize) {
+        for _ in 0..n {
+            let tick_senders = mem::replace(&mut *self.tick_senders.lock().unwrap(), Vec::new());
+            let mut open_tick_senders = Vec::new();
+            for mut tick_sender in tick_senders {
+                if await!(tick_sender.send(TimerTick)).is_ok() {
+                    open_tick_senders.push(tick_sender);
+                }
+            }
+            // Timer streams might have been requested while we were sending:
+            self.tick_senders.lock().unwrap().extend(open_tick_senders);
+            self.cur_tick += 1;
+        }
+    }
+}
+
+/// Create a virtual timer service, and a `TimerClient` connected to it.
+pub fn virtual_timer(mut spawner: impl Spawn) -> (VirtualTimerService, TimerClient) {
+    let (request_sender, mut request_receiver) = mpsc::channel::<TimerRequest>(0);
+    let tick_senders = Arc::new(Mutex::new(Vec::new()));
+
+    let c_tick_senders = tick_senders.clone();
+    spawner
+        .spawn(async move {
+            while let Some(timer_request) = await!(request_receiver.next()) {
+                let (tick_sender, tick_receiver) = mpsc::channel::<TimerTick>(0);
+                c_tick_senders.lock().unwrap().push(tick_sender);
+                let _ = timer_request.response_sender.send(tick_receiver);
+            }
+        })
+        .unwrap();
+
+    let virtual_timer_service = VirtualTimerService {
+        tick_senders,
+        cur_tick: 0,
+    };
+    (virtual_timer_service, TimerClient::new(request_sender))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::ThreadPool;
+    use futures::future::join;
+
+    async fn task_virtual_timer_advance(spawner: impl Spawn + Clone) {
+        let (mut virtual_timer_service, mut timer_client) = virtual_timer(spawner.clone());
+
+        let mut timer_stream0 = await!(timer_client.request_timer_stream()).unwrap();
+        let timer_stream1 = await!(timer_client.request_timer_stream()).unwrap();
+
+        // A dropped timer stream does not block the advance of time:
+        drop(timer_stream1);
+
+        let (_, ticks0) = await!(join(
+            virtual_timer_service.advance(4),
+            (&mut timer_stream0).take(4).collect::<Vec<TimerTick>>()
+        ));
+        assert_eq!(ticks0.len(), 4);
+        assert_eq!(virtual_timer_service.cur_tick(), 4);
+
+        // A timer stream requested later gets the following ticks:
+        let timer_stream2 = await!(timer_client.request_timer_stream()).unwrap();
+        let (_, (ticks0, ticks2)) = await!(join(
+            virtual_timer_service.advance(2),
+            join(
+                timer_stream0.take(2).collect::<Vec<TimerTick>>(),
+                timer_stream2.take(2).collect::<Vec<TimerTick>>()
+            )
+        ));
+        assert_eq!(ticks0.len(), 2);
+        assert_eq!(ticks2.len(), 2);
+        assert_eq!(virtual_timer_service.cur_tick(), 6);
+    }
+
+    #[test]
+    fn test_virtual_timer_advance() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_virtual_timer_advance(thread_pool.clone()));
+    }
+}
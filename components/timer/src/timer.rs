@@ -25,7 +25,9 @@
 #![allow(clippy::too_many_arguments, clippy::implicit_hasher, clippy::module_inception)]
 // TODO: disallow clippy::too_many_arguments
 
+use common::conn::BoxFuture;
 use common::futures_compat::create_interval;
+use common::int_convert::usize_to_u64;
 use common::select_streams::{select_streams, BoxStream};
 use futures::channel::{mpsc, oneshot};
 use futures::future::FutureExt;
@@ -51,8 +53,8 @@ pub enum TimerClientError {
     ResponseCanceled,
 }
 
-struct TimerRequest {
-    response_sender: oneshot::Sender<mpsc::Receiver<TimerTick>>,
+pub(crate) struct TimerRequest {
+    pub(crate) response_sender: oneshot::Sender<mpsc::Receiver<TimerTick>>,
 }
 
 impl std::fmt::Debug for TimerRequest {
@@ -67,7 +69,7 @@ pub struct TimerClient {
 }
 
 impl TimerClient {
-    fn new(sender: mpsc::Sender<TimerRequest>) -> TimerClient {
+    pub(crate) fn new(sender: mpsc::Sender<TimerRequest>) -> TimerClient {
         TimerClient { sender }
     }
 
@@ -83,6 +85,50 @@ impl TimerClient {
             Err(_) => Err(TimerClientError::ResponseCanceled),
         }
     }
+
+    /// Resolve after `ticks` time ticks have passed.
+    /// Also resolves if the timer service is closed before that.
+    pub fn request_timeout(
+        &self,
+        ticks: usize,
+    ) -> BoxFuture<'static, Result<(), TimerClientError>> {
+        let mut timer_client = self.clone();
+        Box::pin(async move {
+            let timer_stream = await!(timer_client.request_timer_stream())?;
+            await!(timer_stream
+                .take(usize_to_u64(ticks).unwrap())
+                .for_each(|_| future::ready(())));
+            Ok(())
+        })
+    }
+
+    /// A stream that yields once every `ticks` time ticks.
+    /// The stream ends when the timer service is closed, or if a timer stream could not be
+    /// obtained.
+    pub fn request_interval(&self, ticks: usize) -> impl Stream<Item = ()> + Unpin + Send {
+        let mut timer_client = self.clone();
+        let fut_timer_stream = Box::pin(async move {
+            await!(timer_client.request_timer_stream())
+                .map_err(|e| warn!("request_interval(): request_timer_stream() error: {:?}", e))
+                .ok()
+        });
+
+        // An interval of 0 ticks is treated as an interval of 1 tick:
+        let ticks = if ticks == 0 { 1 } else { ticks };
+        let mut ticks_left = ticks;
+        stream::once(fut_timer_stream)
+            .filter_map(future::ready)
+            .flatten()
+            .filter_map(move |_| {
+                ticks_left -= 1;
+                if ticks_left == 0 {
+                    ticks_left = ticks;
+                    future::ready(Some(()))
+                } else {
+                    future::ready(None)
+                }
+            })
+    }
 }
 
 #[derive(Debug)]
@@ -93,6 +139,10 @@ enum TimerEvent {
     RequestsDone,
 }
 
+/// Broadcast every incoming tick to all the subscribers.
+/// A single task serves all the subscribers: Every `TimerClient::request_timer_stream()` adds a
+/// sender to `tick_senders`, and every tick is sent to all of them in one pass.
+/// Subscribers that dropped their timer stream are removed on the next tick.
 async fn timer_loop<M>(
     incoming: M,
     from_client: mpsc::Receiver<TimerRequest>,
@@ -230,6 +280,44 @@ mod tests {
         thread_pool.run(wait_fut);
     }
 
+    async fn task_timer_broadcast(mut spawner: impl Spawn + Clone) {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let mut timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let mut timer_streams = Vec::new();
+        for _ in 0..16usize {
+            timer_streams.push(await!(timer_client.request_timer_stream()).unwrap());
+        }
+        // A dropped subscriber does not stop the broadcast:
+        drop(timer_streams.pop());
+
+        let mut handles = Vec::new();
+        for timer_stream in timer_streams {
+            let handle = spawner
+                .spawn_with_handle(timer_stream.collect::<Vec<TimerTick>>())
+                .unwrap();
+            handles.push(handle);
+        }
+
+        for _ in 0..3usize {
+            await!(tick_sender.send(())).unwrap();
+        }
+        // Closing the incoming ticks closes all the timer streams:
+        drop(tick_sender);
+
+        assert_eq!(handles.len(), 15);
+        for handle in handles {
+            assert_eq!(await!(handle).len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_timer_broadcast() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_timer_broadcast(thread_pool.clone()));
+    }
+
     #[test]
     fn test_timer_create_multiple_streams() {
         let mut thread_pool = ThreadPool::new().unwrap();
@@ -378,6 +466,49 @@ mod tests {
         let _ = await!(join(timer_stream_fut, tick_sender_fut));
     }
 
+    async fn task_request_timeout(mut spawner: impl Spawn + Clone) {
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let timeout_fut = spawner
+            .spawn_with_handle(timer_client.request_timeout(4))
+            .unwrap();
+
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+        for _ in 0..4usize {
+            await!(tick_sender.send(TimerTick)).unwrap();
+        }
+        assert!(await!(timeout_fut).is_ok());
+    }
+
+    #[test]
+    fn test_request_timeout() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_request_timeout(thread_pool.clone()));
+    }
+
+    async fn task_request_interval(mut spawner: impl Spawn + Clone) {
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let interval_fut = spawner
+            .spawn_with_handle(timer_client.request_interval(3).collect::<Vec<()>>())
+            .unwrap();
+
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+        for _ in 0..7usize {
+            await!(tick_sender.send(TimerTick)).unwrap();
+        }
+        drop(tick_sender);
+
+        // 7 ticks contain 2 whole intervals of 3 ticks:
+        assert_eq!(await!(interval_fut).len(), 2);
+    }
+
+    #[test]
+    fn test_request_interval() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_request_interval(thread_pool.clone()));
+    }
+
     #[test]
     fn test_dummy_timer_multi_sender() {
         let mut thread_pool = ThreadPool::new().unwrap();
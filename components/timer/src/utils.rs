@@ -11,16 +11,9 @@ pub enum SleepTicksError {
 }
 
 /// Sleep for a certain amount of time ticks
-pub async fn sleep_ticks(
-    ticks: usize,
-    mut timer_client: TimerClient,
-) -> Result<(), SleepTicksError> {
-    let timer_stream = await!(timer_client.request_timer_stream())
-        .map_err(|_| SleepTicksError::RequestTimerStreamError)?;
-    let ticks_u64 = usize_to_u64(ticks).unwrap();
-    let fut = timer_stream.take(ticks_u64).for_each(|_| future::ready(()));
-    await!(fut);
-    Ok(())
+pub async fn sleep_ticks(ticks: usize, timer_client: TimerClient) -> Result<(), SleepTicksError> {
+    await!(timer_client.request_timeout(ticks))
+        .map_err(|_| SleepTicksError::RequestTimerStreamError)
 }
 
 /// Wraps a future with a timeout.
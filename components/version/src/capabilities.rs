@@ -0,0 +1,81 @@
+/// An optional protocol feature that may be supported by one side of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    JsonSerde,
+    PingPong,
+    BatchVerify,
+}
+
+impl Capability {
+    fn bit(self) -> u64 {
+        match self {
+            Capability::JsonSerde => 1 << 0,
+            Capability::PingPong => 1 << 1,
+            Capability::BatchVerify => 1 << 2,
+        }
+    }
+}
+
+/// A set of capabilities, sent as a bitmap during the version prefix exchange.
+/// Unknown bits received from the remote side are kept, and are removed when the intersection
+/// with the local capabilities is computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub fn empty() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn from_bits(bits: u64) -> Self {
+        Capabilities(bits)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn with(self, capability: Capability) -> Self {
+        Capabilities(self.0 | capability.bit())
+    }
+
+    pub fn has(self, capability: Capability) -> bool {
+        self.0 & capability.bit() != 0
+    }
+
+    /// Capabilities supported by both sides
+    pub fn intersection(self, other: Capabilities) -> Self {
+        Capabilities(self.0 & other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities_intersection() {
+        let local = Capabilities::empty()
+            .with(Capability::JsonSerde)
+            .with(Capability::PingPong);
+        let remote = Capabilities::empty()
+            .with(Capability::PingPong)
+            .with(Capability::BatchVerify);
+
+        let common = local.intersection(remote);
+        assert!(common.has(Capability::PingPong));
+        assert!(!common.has(Capability::JsonSerde));
+        assert!(!common.has(Capability::BatchVerify));
+
+        assert!(local.intersection(Capabilities::empty()).is_empty());
+        // Unknown bits are dropped:
+        assert_eq!(
+            Capabilities::from_bits(0xff00).intersection(local),
+            Capabilities::empty()
+        );
+    }
+}
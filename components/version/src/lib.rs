@@ -16,6 +16,8 @@
 #[macro_use]
 extern crate log;
 
+mod capabilities;
 mod version_prefix;
 
-pub use self::version_prefix::VersionPrefix;
+pub use self::capabilities::{Capabilities, Capability};
+pub use self::version_prefix::{VersionPrefix, VersionedConn};
@@ -1,15 +1,77 @@
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, SinkExt, StreamExt};
 
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
 
+use crate::capabilities::Capabilities;
+
+/// Length of the version message
+const VERSION_LEN: usize = 4;
+/// Length of the capabilities message
+const CAPABILITIES_LEN: usize = 8;
+
+fn encode_version(version: u32) -> Vec<u8> {
+    let mut version_data = Vec::new();
+    version_data.write_u32::<BigEndian>(version).unwrap();
+    version_data
+}
+
+fn decode_version(version_data: &[u8]) -> Option<u32> {
+    if version_data.len() != VERSION_LEN {
+        return None;
+    }
+    Some(BigEndian::read_u32(version_data))
+}
+
+fn encode_capabilities(capabilities: Capabilities) -> Vec<u8> {
+    let mut capabilities_data = Vec::new();
+    capabilities_data
+        .write_u64::<BigEndian>(capabilities.bits())
+        .unwrap();
+    capabilities_data
+}
+
+fn decode_capabilities(capabilities_data: &[u8]) -> Option<Capabilities> {
+    if capabilities_data.len() != CAPABILITIES_LEN {
+        return None;
+    }
+    Some(Capabilities::from_bits(BigEndian::read_u64(
+        capabilities_data,
+    )))
+}
+
+/// A connection after the version prefix exchange, together with the capabilities supported by
+/// both sides.
+pub struct VersionedConn {
+    conn_pair: ConnPairVec,
+    capabilities: Capabilities,
+}
+
+impl VersionedConn {
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    pub fn into_conn_pair(self) -> ConnPairVec {
+        self.conn_pair
+    }
+}
+
 /// Prefix a communication session (Of Vec<u8>) with each side declaring his version.
 /// If the local version does not match the stated remote version, the connection is closed.
+///
+/// Optionally, the capabilities of each side are exchanged in a separate message, sent only after
+/// the remote version was received and found to be equal to the local version. A protocol version
+/// either always uses the capabilities message or never does, so a matching version means that
+/// the remote side expects it. A remote side with a different (For example, older) version never
+/// receives the capabilities message, and sees exactly the version message it expects.
 #[derive(Clone)]
 pub struct VersionPrefix<S> {
     local_version: u32,
+    /// Local capabilities. None if this protocol version does not exchange capabilities.
+    opt_local_capabilities: Option<Capabilities>,
     spawner: S,
 }
 
@@ -20,25 +82,128 @@ where
     pub fn new(local_version: u32, spawner: S) -> Self {
         VersionPrefix {
             local_version,
+            opt_local_capabilities: None,
             spawner,
         }
     }
 
-    pub fn spawn_prefix(&mut self, conn_pair: ConnPairVec) -> ConnPairVec {
+    /// Create a VersionPrefix for a protocol version that exchanges capabilities.
+    pub fn with_capabilities(
+        local_version: u32,
+        local_capabilities: Capabilities,
+        spawner: S,
+    ) -> Self {
+        VersionPrefix {
+            local_version,
+            opt_local_capabilities: Some(local_capabilities),
+            spawner,
+        }
+    }
+
+    /// Exchange versions (and capabilities, if used) with the remote side, waiting for the
+    /// remote side's messages.
+    /// Returns None if the remote version does not match the local version.
+    pub async fn negotiate(&self, conn_pair: ConnPairVec) -> Option<VersionedConn> {
+        let (mut sender, mut receiver) = conn_pair;
+
+        if await!(sender.send(encode_version(self.local_version))).is_err() {
+            warn!("Failed to send version information");
+            return None;
+        }
+
+        let version_data = match await!(receiver.next()) {
+            Some(version_data) => version_data,
+            None => {
+                warn!("Failed to receive version information");
+                return None;
+            }
+        };
+
+        let remote_version = match decode_version(&version_data) {
+            Some(remote_version) => remote_version,
+            None => {
+                warn!("Invalid version_data length");
+                return None;
+            }
+        };
+
+        if remote_version != self.local_version {
+            warn!("Invalid remote version: {}", remote_version);
+            return None;
+        }
+
+        let local_capabilities = match self.opt_local_capabilities {
+            Some(local_capabilities) => local_capabilities,
+            None => {
+                return Some(VersionedConn {
+                    conn_pair: (sender, receiver),
+                    capabilities: Capabilities::empty(),
+                })
+            }
+        };
+
+        // The remote side has our version, and therefore expects our capabilities:
+        if await!(sender.send(encode_capabilities(local_capabilities))).is_err() {
+            warn!("Failed to send capabilities");
+            return None;
+        }
+
+        let capabilities_data = match await!(receiver.next()) {
+            Some(capabilities_data) => capabilities_data,
+            None => {
+                warn!("Failed to receive capabilities");
+                return None;
+            }
+        };
+
+        let remote_capabilities = match decode_capabilities(&capabilities_data) {
+            Some(remote_capabilities) => remote_capabilities,
+            None => {
+                warn!("Invalid capabilities_data length");
+                return None;
+            }
+        };
+
+        Some(VersionedConn {
+            conn_pair: (sender, receiver),
+            capabilities: local_capabilities.intersection(remote_capabilities),
+        })
+    }
+
+    /// Like `spawn_prefix()`, but also returns the capabilities supported by both sides, once
+    /// they are known. The returned receiver is closed if the exchange fails.
+    pub fn spawn_prefix_capabilities(
+        &mut self,
+        conn_pair: ConnPairVec,
+    ) -> (ConnPairVec, oneshot::Receiver<Capabilities>) {
         let (mut sender, mut receiver) = conn_pair;
 
         let (user_sender, mut from_user_sender) = mpsc::channel(0);
         let (mut to_user_receiver, user_receiver) = mpsc::channel(0);
 
+        // Used by the receiver to let the sender know that the remote version matches:
+        let (version_match_sender, version_match_receiver) = oneshot::channel::<()>();
+        let (capabilities_sender, capabilities_receiver) = oneshot::channel();
+
         let local_version = self.local_version;
+        let opt_local_capabilities = self.opt_local_capabilities;
         let sender_fut = async move {
             // First send our protocol version to the remote side:
-            let mut version_data = Vec::new();
-            version_data.write_u32::<BigEndian>(local_version).unwrap();
-            if await!(sender.send(version_data)).is_err() {
+            if await!(sender.send(encode_version(local_version))).is_err() {
                 warn!("Failed to send version information");
                 return;
             }
+            if let Some(local_capabilities) = opt_local_capabilities {
+                // Send our capabilities only after the remote side has proved to have our
+                // version:
+                if await!(version_match_receiver).is_err() {
+                    return;
+                }
+                if await!(sender.send(encode_capabilities(local_capabilities))).is_err() {
+                    warn!("Failed to send capabilities");
+                    return;
+                }
+            }
             // Next send any other message from the user:
             let _ = await!(sender.send_all(&mut from_user_sender));
         };
@@ -56,17 +221,44 @@ where
                 }
             };
 
-            if version_data.len() != 4 {
-                warn!("Invalid version_data length");
-                return;
-            }
+            let remote_version = match decode_version(&version_data) {
+                Some(remote_version) => remote_version,
+                None => {
+                    warn!("Invalid version_data length");
+                    return;
+                }
+            };
 
-            let remote_version = BigEndian::read_u32(&version_data);
             if remote_version != local_version {
                 warn!("Invalid remote version: {}", remote_version);
                 return;
             }
 
+            let capabilities = match opt_local_capabilities {
+                Some(local_capabilities) => {
+                    let _ = version_match_sender.send(());
+                    let capabilities_data = match await!(receiver.next()) {
+                        Some(capabilities_data) => capabilities_data,
+                        None => {
+                            warn!("Failed to receive capabilities");
+                            return;
+                        }
+                    };
+                    match decode_capabilities(&capabilities_data) {
+                        Some(remote_capabilities) => {
+                            local_capabilities.intersection(remote_capabilities)
+                        }
+                        None => {
+                            warn!("Invalid capabilities_data length");
+                            return;
+                        }
+                    }
+                }
+                None => Capabilities::empty(),
+            };
+            // The user might not be interested in the capabilities:
+            let _ = capabilities_sender.send(capabilities);
+
             let _ = await!(to_user_receiver.send_all(&mut receiver));
         };
         // If spawning fails, the user will find out when he tries to read
@@ -75,7 +267,15 @@ where
             error!("VersionPrefix::spawn_prefix(): spawn() failed: {:?}", e);
         }
 
-        (user_sender, user_receiver)
+        ((user_sender, user_receiver), capabilities_receiver)
+    }
+
+    /// Prefix the connection with the version exchange, without waiting for the remote side.
+    /// Use `spawn_prefix_capabilities()` or `negotiate()` to find out the capabilities supported
+    /// by both sides.
+    pub fn spawn_prefix(&mut self, conn_pair: ConnPairVec) -> ConnPairVec {
+        let (conn_pair, _capabilities_receiver) = self.spawn_prefix_capabilities(conn_pair);
+        conn_pair
     }
 }
 
@@ -96,6 +296,8 @@ mod tests {
     use super::*;
     use futures::executor::ThreadPool;
 
+    use crate::capabilities::Capability;
+
     async fn task_version_prefix_match<S>(spawner: S)
     where
         S: Spawn,
@@ -150,4 +352,131 @@ mod tests {
         let mut thread_pool = ThreadPool::new().unwrap();
         thread_pool.run(task_version_prefix_mismatch(thread_pool.clone()));
     }
+
+    async fn task_version_prefix_negotiate_capabilities<S>(spawner: S)
+    where
+        S: Spawn + Clone,
+    {
+        let (a_sender, b_receiver) = mpsc::channel(0);
+        let (b_sender, a_receiver) = mpsc::channel(0);
+
+        let a_capabilities = Capabilities::empty()
+            .with(Capability::JsonSerde)
+            .with(Capability::PingPong);
+        let b_capabilities = Capabilities::empty()
+            .with(Capability::PingPong)
+            .with(Capability::BatchVerify);
+
+        let version_prefix_a =
+            VersionPrefix::with_capabilities(3u32, a_capabilities, spawner.clone());
+        let version_prefix_b = VersionPrefix::with_capabilities(3u32, b_capabilities, spawner);
+
+        let (opt_a_conn, opt_b_conn) = await!(future::join(
+            version_prefix_a.negotiate((a_sender, a_receiver)),
+            version_prefix_b.negotiate((b_sender, b_receiver))
+        ));
+        let a_conn = opt_a_conn.unwrap();
+        let b_conn = opt_b_conn.unwrap();
+
+        // Only the capabilities supported by both sides are enabled:
+        for conn in &[&a_conn, &b_conn] {
+            assert!(conn.capabilities().has(Capability::PingPong));
+            assert!(!conn.capabilities().has(Capability::JsonSerde));
+            assert!(!conn.capabilities().has(Capability::BatchVerify));
+        }
+
+        let (mut a_sender, _a_receiver) = a_conn.into_conn_pair();
+        let (_b_sender, mut b_receiver) = b_conn.into_conn_pair();
+        await!(a_sender.send(vec![1, 2, 3])).unwrap();
+        assert_eq!(await!(b_receiver.next()).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_version_prefix_negotiate_capabilities() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_version_prefix_negotiate_capabilities(
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_version_prefix_spawn_capabilities<S>(spawner: S)
+    where
+        S: Spawn + Clone,
+    {
+        let (a_sender, b_receiver) = mpsc::channel(0);
+        let (b_sender, a_receiver) = mpsc::channel(0);
+
+        let mut version_prefix_a = VersionPrefix::with_capabilities(
+            3u32,
+            Capabilities::empty()
+                .with(Capability::JsonSerde)
+                .with(Capability::PingPong),
+            spawner.clone(),
+        );
+        let version_prefix_b = VersionPrefix::with_capabilities(
+            3u32,
+            Capabilities::empty().with(Capability::PingPong),
+            spawner,
+        );
+
+        let ((mut a_sender, _a_receiver), a_capabilities_receiver) =
+            version_prefix_a.spawn_prefix_capabilities((a_sender, a_receiver));
+        // User messages are sent only after the capabilities:
+        await!(a_sender.send(vec![1, 2, 3])).unwrap();
+
+        let b_conn = await!(version_prefix_b.negotiate((b_sender, b_receiver))).unwrap();
+        assert_eq!(
+            b_conn.capabilities(),
+            Capabilities::empty().with(Capability::PingPong)
+        );
+        assert_eq!(
+            await!(a_capabilities_receiver).unwrap(),
+            Capabilities::empty().with(Capability::PingPong)
+        );
+
+        let (_b_sender, mut b_receiver) = b_conn.into_conn_pair();
+        assert_eq!(await!(b_receiver.next()).unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_version_prefix_spawn_capabilities() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_version_prefix_spawn_capabilities(thread_pool.clone()));
+    }
+
+    async fn task_version_prefix_no_capabilities<S>(spawner: S)
+    where
+        S: Spawn + Clone,
+    {
+        let (a_sender, mut b_receiver) = mpsc::channel(0);
+        let (_b_sender, a_receiver) = mpsc::channel(0);
+
+        // A side without capabilities sends only its version, exactly like older versions:
+        let mut version_prefix_3 = VersionPrefix::new(3u32, spawner.clone());
+        let (_a_sender, _a_receiver) = version_prefix_3.spawn_prefix((a_sender, a_receiver));
+        assert_eq!(await!(b_receiver.next()).unwrap(), vec![0, 0, 0, 3]);
+
+        // A side with capabilities sends only its version to an older side. The capabilities
+        // are not sent, because the versions do not match:
+        let (c_sender, mut d_receiver) = mpsc::channel(0);
+        let (mut d_sender, c_receiver) = mpsc::channel(0);
+        let mut version_prefix_caps = VersionPrefix::with_capabilities(
+            3u32,
+            Capabilities::empty().with(Capability::PingPong),
+            spawner,
+        );
+        let ((_c_sender, mut c_receiver), capabilities_receiver) =
+            version_prefix_caps.spawn_prefix_capabilities((c_sender, c_receiver));
+        assert_eq!(await!(d_receiver.next()).unwrap(), vec![0, 0, 0, 3]);
+        await!(d_sender.send(vec![0, 0, 0, 2])).unwrap();
+        assert!(await!(c_receiver.next()).is_none());
+        assert!(await!(capabilities_receiver).is_err());
+        assert!(await!(d_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_version_prefix_no_capabilities() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_version_prefix_no_capabilities(thread_pool.clone()));
+    }
 }